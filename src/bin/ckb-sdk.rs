@@ -0,0 +1,530 @@
+//! A command line companion of the SDK, exercise the public APIs end-to-end.
+//!
+//! Build with the `bin` feature:
+//!     cargo build --features bin --bin ckb-sdk
+//!
+//! # Example:
+//!     ./target/debug/ckb-sdk balance --address <address>
+//!     ./target/debug/ckb-sdk transfer \
+//!       --sender-key <key-hex> \
+//!       --receiver <address> \
+//!       --capacity 61.0 \
+//!       --tx-file tx.json
+//!     ./target/debug/ckb-sdk inspect --tx-file tx.json
+use std::collections::HashMap;
+use std::error::Error as StdErr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use ckb_hash::blake2b_256;
+use ckb_jsonrpc_types as json_types;
+use ckb_sdk::{
+    constants::SIGHASH_TYPE_HASH,
+    rpc::CkbRpcClient,
+    traits::{
+        CellQueryOptions, DefaultCellCollector, DefaultCellDepResolver, DefaultHeaderDepResolver,
+        DefaultTransactionDependencyProvider, SecpCkbRawKeySigner,
+    },
+    tx_builder::{
+        dao::{
+            DaoBatchPrepareBuilder, DaoBatchWithdrawBuilder, DaoDepositBuilder, DaoDepositReceiver,
+            DaoWithdrawReceiver,
+        },
+        transfer::CapacityTransferBuilder,
+        udt::{UdtTargetReceiver, UdtTransferBuilder},
+        unlock_tx,
+        utilization::{CapacityUtilizationReport, UtilizationOptions},
+        CapacityBalancer, TransferAction, TxBuilder,
+    },
+    unlock::{ScriptUnlocker, SecpSighashUnlocker},
+    Address, HumanCapacity, ScriptId, SECP256K1,
+};
+use ckb_types::{
+    bytes::Bytes,
+    core::{BlockView, DepType, EpochNumberWithFraction, FeeRate, ScriptHashType, TransactionView},
+    packed::{CellDep, CellOutput, OutPoint, Script, Transaction, WitnessArgs},
+    prelude::*,
+    H256,
+};
+use clap::{Parser, Subcommand};
+
+const FEE_RATE: u64 = 1000;
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Cli {
+    /// CKB rpc url
+    #[clap(long, value_name = "URL", default_value = "http://127.0.0.1:8114")]
+    ckb_rpc: String,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+/// The arguments of the commands build a transaction
+#[derive(clap::Args, Debug)]
+struct TxArgs {
+    /// The sender private key (hex string)
+    #[clap(long, value_name = "KEY")]
+    sender_key: H256,
+
+    /// Save the signed transaction to this file (json format)
+    #[clap(long, value_name = "PATH")]
+    tx_file: Option<PathBuf>,
+
+    /// Send the signed transaction
+    #[clap(long)]
+    send: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Show the capacity of an address
+    Balance {
+        /// The address
+        #[clap(long, value_name = "ADDRESS")]
+        address: Address,
+    },
+    /// Transfer capacity from a sighash address
+    Transfer {
+        #[clap(flatten)]
+        tx_args: TxArgs,
+
+        /// The receiver address
+        #[clap(long, value_name = "ADDRESS")]
+        receiver: Address,
+
+        /// The capacity to transfer (unit: CKB, example: 102.43)
+        #[clap(long, value_name = "CKB")]
+        capacity: HumanCapacity,
+    },
+    /// Deposit capacity into Nervos DAO
+    DaoDeposit {
+        #[clap(flatten)]
+        tx_args: TxArgs,
+
+        /// The capacity to deposit (unit: CKB, example: 102.43)
+        #[clap(long, value_name = "CKB")]
+        capacity: HumanCapacity,
+    },
+    /// Prepare all the deposited cells of the sender (withdraw phase 1)
+    DaoPrepare {
+        #[clap(flatten)]
+        tx_args: TxArgs,
+    },
+    /// Withdraw all the matured prepared cells of the sender (withdraw phase 2)
+    DaoWithdraw {
+        #[clap(flatten)]
+        tx_args: TxArgs,
+    },
+    /// Transfer sUDT/xUDT from a sighash address
+    UdtTransfer {
+        #[clap(flatten)]
+        tx_args: TxArgs,
+
+        /// The code hash of the udt type script (hash type is `type`)
+        #[clap(long, value_name = "H256")]
+        udt_code_hash: H256,
+
+        /// The args of the udt type script (hex string)
+        #[clap(long, value_name = "HEX")]
+        udt_args: String,
+
+        /// The out point of the udt script cell, format: <tx-hash>-<index>
+        #[clap(long, value_name = "OUT_POINT")]
+        udt_cell_dep: String,
+
+        /// The receiver address, the receiver must have an udt cell
+        #[clap(long, value_name = "ADDRESS")]
+        receiver: Address,
+
+        /// The udt amount to transfer
+        #[clap(long, value_name = "AMOUNT")]
+        amount: u128,
+    },
+    /// Show the summary of a transaction file
+    Inspect {
+        /// The transaction file (json format)
+        #[clap(long, value_name = "PATH")]
+        tx_file: PathBuf,
+    },
+    /// Sign the sighash inputs of a transaction file
+    SignFile {
+        /// The signer private key (hex string)
+        #[clap(long, value_name = "KEY")]
+        sender_key: H256,
+
+        /// The transaction file (json format), the signed transaction is
+        /// saved back to it
+        #[clap(long, value_name = "PATH")]
+        tx_file: PathBuf,
+    },
+}
+
+/// The RPC backed resolvers and providers
+struct Providers {
+    ckb_client: CkbRpcClient,
+    cell_dep_resolver: DefaultCellDepResolver,
+    header_dep_resolver: DefaultHeaderDepResolver,
+    cell_collector: DefaultCellCollector,
+    tx_dep_provider: DefaultTransactionDependencyProvider,
+}
+
+impl Providers {
+    fn new(ckb_rpc: &str) -> Result<Providers, Box<dyn StdErr>> {
+        let ckb_client = CkbRpcClient::new(ckb_rpc);
+        let cell_dep_resolver = {
+            let genesis_block = ckb_client
+                .get_block_by_number(0.into())?
+                .ok_or("genesis block not found")?;
+            DefaultCellDepResolver::from_genesis(&BlockView::from(genesis_block))?
+        };
+        Ok(Providers {
+            ckb_client,
+            cell_dep_resolver,
+            header_dep_resolver: DefaultHeaderDepResolver::new(ckb_rpc),
+            cell_collector: DefaultCellCollector::new(ckb_rpc),
+            tx_dep_provider: DefaultTransactionDependencyProvider::new(ckb_rpc, 10),
+        })
+    }
+
+    fn build(
+        &mut self,
+        builder: &dyn TxBuilder,
+        key: &SighashKey,
+    ) -> Result<TransactionView, Box<dyn StdErr>> {
+        let placeholder_witness = WitnessArgs::new_builder()
+            .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+            .build();
+        let balancer =
+            CapacityBalancer::new_simple(key.lock_script.clone(), placeholder_witness, FEE_RATE);
+        let (tx, still_locked_groups) = builder.build_unlocked(
+            &mut self.cell_collector,
+            &self.cell_dep_resolver,
+            &self.header_dep_resolver,
+            &self.tx_dep_provider,
+            &balancer,
+            &key.unlockers(),
+        )?;
+        if !still_locked_groups.is_empty() {
+            return Err(format!("{} script groups not unlocked", still_locked_groups.len()).into());
+        }
+        Ok(tx)
+    }
+}
+
+/// A secp256k1 key and its sighash lock script
+struct SighashKey {
+    secret_key: secp256k1::SecretKey,
+    lock_script: Script,
+}
+
+impl SighashKey {
+    fn new(key: &H256) -> Result<SighashKey, Box<dyn StdErr>> {
+        let secret_key = secp256k1::SecretKey::from_slice(key.as_bytes())
+            .map_err(|err| format!("invalid secret key: {}", err))?;
+        let pubkey = secp256k1::PublicKey::from_secret_key(&SECP256K1, &secret_key);
+        let hash160 = blake2b_256(&pubkey.serialize()[..])[0..20].to_vec();
+        let lock_script = Script::new_builder()
+            .code_hash(SIGHASH_TYPE_HASH.pack())
+            .hash_type(ScriptHashType::Type.into())
+            .args(Bytes::from(hash160).pack())
+            .build();
+        Ok(SighashKey {
+            secret_key,
+            lock_script,
+        })
+    }
+
+    fn unlockers(&self) -> HashMap<ScriptId, Box<dyn ScriptUnlocker>> {
+        let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![self.secret_key]);
+        let sighash_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+        let mut unlockers = HashMap::default();
+        unlockers.insert(
+            ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+            Box::new(sighash_unlocker) as Box<dyn ScriptUnlocker>,
+        );
+        unlockers
+    }
+}
+
+fn parse_out_point(value: &str) -> Result<OutPoint, Box<dyn StdErr>> {
+    let (tx_hash, index) = value
+        .split_once('-')
+        .ok_or_else(|| format!("invalid out point: {}", value))?;
+    let tx_hash: H256 = tx_hash.trim_start_matches("0x").parse()?;
+    let index: u32 = index.parse()?;
+    Ok(OutPoint::new(tx_hash.pack(), index))
+}
+
+fn read_tx(path: &Path) -> Result<TransactionView, Box<dyn StdErr>> {
+    let content = fs::read_to_string(path)?;
+    let tx: json_types::TransactionView = serde_json::from_str(&content)?;
+    Ok(Transaction::from(tx.inner).into_view())
+}
+
+fn write_tx(path: &Path, tx: TransactionView) -> Result<(), Box<dyn StdErr>> {
+    let json_tx = json_types::TransactionView::from(tx);
+    fs::write(path, serde_json::to_string_pretty(&json_tx)?)?;
+    Ok(())
+}
+
+/// Save or send the built transaction
+fn output_tx(
+    tx_args: &TxArgs,
+    ckb_client: &CkbRpcClient,
+    tx: TransactionView,
+) -> Result<(), Box<dyn StdErr>> {
+    println!("tx hash: {:#x}", tx.hash());
+    if let Some(path) = tx_args.tx_file.as_ref() {
+        write_tx(path, tx.clone())?;
+        println!("tx saved to {}", path.display());
+    }
+    if tx_args.send {
+        let outputs_validator = Some(json_types::OutputsValidator::Passthrough);
+        let tx_hash = ckb_client.send_transaction(tx.data().into(), outputs_validator)?;
+        println!(">>> tx {:#x} sent! <<<", tx_hash);
+    }
+    Ok(())
+}
+
+fn inspect(tx: &TransactionView) {
+    println!("tx hash: {:#x}", tx.hash());
+    println!("cell deps: {}", tx.cell_deps().len());
+    println!("header deps: {}", tx.header_deps().len());
+    println!("inputs: {}", tx.inputs().len());
+    for (index, input) in tx.inputs().into_iter().enumerate() {
+        let out_point = input.previous_output();
+        let tx_hash: H256 = out_point.tx_hash().unpack();
+        let out_index: u32 = out_point.index().unpack();
+        println!("  input[{}]: {:#x}-{}", index, tx_hash, out_index);
+    }
+    println!("outputs: {}", tx.outputs().len());
+    let report = CapacityUtilizationReport::new(tx, &UtilizationOptions::default());
+    for line in report.summary().lines() {
+        println!("  {}", line);
+    }
+    println!(
+        "total output capacity: {}",
+        HumanCapacity(report.total_capacity())
+    );
+    let witnesses_len = tx
+        .witnesses()
+        .into_iter()
+        .map(|witness| witness.raw_data().len())
+        .collect::<Vec<_>>();
+    println!("witnesses length: {:?}", witnesses_len);
+}
+
+fn main() -> Result<(), Box<dyn StdErr>> {
+    let cli = Cli::parse();
+    match cli.command {
+        Command::Balance { address } => {
+            let ckb_client = CkbRpcClient::new(cli.ckb_rpc.as_str());
+            let query = CellQueryOptions::new_lock(Script::from(&address));
+            let capacity = ckb_client
+                .get_cells_capacity(query.into())?
+                .map(|cells_capacity| cells_capacity.capacity.value())
+                .unwrap_or_default();
+            println!("{} CKB", HumanCapacity(capacity));
+        }
+        Command::Transfer {
+            tx_args,
+            receiver,
+            capacity,
+        } => {
+            let key = SighashKey::new(&tx_args.sender_key)?;
+            let mut providers = Providers::new(cli.ckb_rpc.as_str())?;
+            let output = CellOutput::new_builder()
+                .lock(Script::from(&receiver))
+                .capacity(capacity.0.pack())
+                .build();
+            let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+            let tx = providers.build(&builder, &key)?;
+            output_tx(&tx_args, &providers.ckb_client, tx)?;
+        }
+        Command::DaoDeposit { tx_args, capacity } => {
+            let key = SighashKey::new(&tx_args.sender_key)?;
+            let mut providers = Providers::new(cli.ckb_rpc.as_str())?;
+            let receiver = DaoDepositReceiver::new(key.lock_script.clone(), capacity.0);
+            let builder = DaoDepositBuilder::new(vec![receiver]);
+            let tx = providers.build(&builder, &key)?;
+            output_tx(&tx_args, &providers.ckb_client, tx)?;
+        }
+        Command::DaoPrepare { tx_args } => {
+            let key = SighashKey::new(&tx_args.sender_key)?;
+            let mut providers = Providers::new(cli.ckb_rpc.as_str())?;
+            let builder = DaoBatchPrepareBuilder::new(key.lock_script.clone());
+            let tx = providers.build(&builder, &key)?;
+            output_tx(&tx_args, &providers.ckb_client, tx)?;
+        }
+        Command::DaoWithdraw { tx_args } => {
+            let key = SighashKey::new(&tx_args.sender_key)?;
+            let mut providers = Providers::new(cli.ckb_rpc.as_str())?;
+            let tip_header = providers.ckb_client.get_tip_header()?;
+            let current_epoch =
+                EpochNumberWithFraction::from_full_value(tip_header.inner.epoch.value());
+            let receiver = DaoWithdrawReceiver::LockScript {
+                script: key.lock_script.clone(),
+                fee_rate: Some(FeeRate::from_u64(FEE_RATE)),
+            };
+            let builder =
+                DaoBatchWithdrawBuilder::new(key.lock_script.clone(), current_epoch, receiver);
+            let tx = providers.build(&builder, &key)?;
+            output_tx(&tx_args, &providers.ckb_client, tx)?;
+        }
+        Command::UdtTransfer {
+            tx_args,
+            udt_code_hash,
+            udt_args,
+            udt_cell_dep,
+            receiver,
+            amount,
+        } => {
+            let key = SighashKey::new(&tx_args.sender_key)?;
+            let mut providers = Providers::new(cli.ckb_rpc.as_str())?;
+            let udt_args = hex_decode(&udt_args)?;
+            let type_script = Script::new_builder()
+                .code_hash(udt_code_hash.pack())
+                .hash_type(ScriptHashType::Type.into())
+                .args(Bytes::from(udt_args).pack())
+                .build();
+            let cell_dep = CellDep::new_builder()
+                .out_point(parse_out_point(&udt_cell_dep)?)
+                .dep_type(DepType::Code.into())
+                .build();
+            providers.cell_dep_resolver.insert(
+                ScriptId::from(&type_script),
+                cell_dep,
+                "udt".to_string(),
+            );
+            let builder = UdtTransferBuilder {
+                type_script,
+                sender: key.lock_script.clone(),
+                receivers: vec![UdtTargetReceiver::new(
+                    TransferAction::Update,
+                    Script::from(&receiver),
+                    amount,
+                )],
+            };
+            let tx = providers.build(&builder, &key)?;
+            output_tx(&tx_args, &providers.ckb_client, tx)?;
+        }
+        Command::Inspect { tx_file } => {
+            inspect(&read_tx(&tx_file)?);
+        }
+        Command::SignFile {
+            sender_key,
+            tx_file,
+        } => {
+            let key = SighashKey::new(&sender_key)?;
+            let tx = read_tx(&tx_file)?;
+            let tx_dep_provider =
+                DefaultTransactionDependencyProvider::new(cli.ckb_rpc.as_str(), 10);
+            let (tx, still_locked_groups) = unlock_tx(tx, &tx_dep_provider, &key.unlockers())?;
+            write_tx(&tx_file, tx)?;
+            println!(
+                "tx signed, {} script groups not unlocked",
+                still_locked_groups.len()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn hex_decode(value: &str) -> Result<Vec<u8>, Box<dyn StdErr>> {
+    let value = value.trim_start_matches("0x");
+    if value.len() % 2 != 0 {
+        return Err(format!("invalid hex string: {}", value).into());
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|index| {
+            u8::from_str_radix(&value[index..index + 2], 16)
+                .map_err(|err| format!("invalid hex string: {}", err).into())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::{core::TransactionBuilder, packed::CellInput};
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli_args() {
+        Cli::command().debug_assert();
+        let key = format!("{:x}", H256([1u8; 32]));
+        let cli = Cli::try_parse_from([
+            "ckb-sdk",
+            "dao-deposit",
+            "--sender-key",
+            key.as_str(),
+            "--capacity",
+            "102.43",
+            "--send",
+        ])
+        .unwrap();
+        assert_eq!(cli.ckb_rpc, "http://127.0.0.1:8114");
+        match cli.command {
+            Command::DaoDeposit { tx_args, capacity } => {
+                assert_eq!(tx_args.sender_key, H256([1u8; 32]));
+                assert!(tx_args.send);
+                assert!(tx_args.tx_file.is_none());
+                assert_eq!(capacity.0, 10_243_000_000);
+            }
+            command => panic!("unexpected command: {:?}", command),
+        }
+        // The sender key is required
+        assert!(Cli::try_parse_from(["ckb-sdk", "dao-prepare"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_out_point() {
+        let tx_hash = H256([2u8; 32]);
+        let out_point = parse_out_point(&format!("{:#x}-3", tx_hash)).unwrap();
+        assert_eq!(out_point, OutPoint::new(tx_hash.pack(), 3));
+        assert!(parse_out_point(&format!("{:#x}", tx_hash)).is_err());
+        assert!(parse_out_point(&format!("{:#x}-x", tx_hash)).is_err());
+    }
+
+    #[test]
+    fn test_hex_decode() {
+        assert_eq!(hex_decode("0x01ff").unwrap(), vec![0x01, 0xff]);
+        assert_eq!(hex_decode("").unwrap(), Vec::<u8>::new());
+        assert!(hex_decode("0x012").is_err());
+        assert!(hex_decode("zz").is_err());
+    }
+
+    #[test]
+    fn test_sighash_key() {
+        let key = SighashKey::new(&H256([1u8; 32])).unwrap();
+        let pubkey = secp256k1::PublicKey::from_secret_key(&SECP256K1, &key.secret_key);
+        assert_eq!(
+            key.lock_script.args().raw_data(),
+            Bytes::from(blake2b_256(&pubkey.serialize()[..])[0..20].to_vec())
+        );
+        assert!(key
+            .unlockers()
+            .contains_key(&ScriptId::new_type(SIGHASH_TYPE_HASH.clone())));
+        assert!(SighashKey::new(&H256::default()).is_err());
+    }
+
+    #[test]
+    fn test_tx_file_roundtrip() {
+        let tx = TransactionBuilder::default()
+            .input(CellInput::new(OutPoint::new(H256([3u8; 32]).pack(), 0), 0))
+            .output(CellOutput::new_builder().capacity(100u64.pack()).build())
+            .output_data(Bytes::new().pack())
+            .witness(Bytes::from(vec![4u8; 65]).pack())
+            .build();
+        let path = std::env::temp_dir().join(format!("ckb-sdk-cli-tx-{}.json", std::process::id()));
+        write_tx(&path, tx.clone()).unwrap();
+        let loaded = read_tx(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.hash(), tx.hash());
+        assert_eq!(loaded.witnesses(), tx.witnesses());
+        assert!(read_tx(&path).is_err());
+    }
+}
@@ -1,5 +1,8 @@
 use ckb_types::{core::EpochNumberWithFraction, h256, H256};
 
+mod spec;
+pub use spec::{clear_system_script_constants, set_system_script_constants, SystemScriptConstants};
+
 pub const PREFIX_MAINNET: &str = "ckb";
 pub const PREFIX_TESTNET: &str = "ckt";
 
@@ -48,6 +51,14 @@ pub const ACP_TYPE_HASH_LINA: H256 =
 pub const ACP_TYPE_HASH_AGGRON: H256 =
     h256!("0x3419a1c09eb2567f6552ee7a8ecffd64155cffe0f1796e6e61ec088d740c1356");
 
+/// PW-lock mainnet code hash (hash type: type), see:
+/// <https://github.com/lay2dev/pw-lock>
+pub const PW_LOCK_TYPE_HASH_LINA: H256 =
+    h256!("0xbf43c3602455798c1a61a596e0d95278864c552fafe231c063b3fabf97a8febc");
+/// PW-lock testnet code hash (hash type: type)
+pub const PW_LOCK_TYPE_HASH_AGGRON: H256 =
+    h256!("0x58c5f491aba6d61678b7cf7edf4910b1f5e00ec0cde2f42e0abb4fd9aff25a63");
+
 /// cheque withdraw since value
 pub const CHEQUE_CELL_SINCE: u64 = 0xA000000000000006;
 
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use ckb_chain_spec::ChainSpec;
+use ckb_resource::Resource;
+use ckb_types::{
+    core::{BlockView, DepType},
+    packed::{CellDep, OutPoint},
+    prelude::*,
+    H256,
+};
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+
+use super::{
+    DAO_OUTPUT_LOC, MULTISIG_GROUP_OUTPUT_LOC, MULTISIG_OUTPUT_LOC, SIGHASH_GROUP_OUTPUT_LOC,
+    SIGHASH_OUTPUT_LOC,
+};
+use crate::NetworkType;
+
+lazy_static! {
+    static ref OVERRIDES: RwLock<HashMap<NetworkType, SystemScriptConstants>> =
+        RwLock::new(HashMap::new());
+    /// Building the genesis block is expensive, the bundled constants are
+    /// loaded once per network.
+    static ref BUNDLED: RwLock<HashMap<NetworkType, SystemScriptConstants>> =
+        RwLock::new(HashMap::new());
+}
+
+/// System script code hashes and cell deps of a network, derived from the
+/// genesis block built by the chain spec.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SystemScriptConstants {
+    pub sighash_type_hash: H256,
+    pub multisig_type_hash: H256,
+    pub dao_type_hash: H256,
+    pub sighash_dep: CellDep,
+    pub multisig_dep: CellDep,
+    pub dao_dep: CellDep,
+}
+
+impl SystemScriptConstants {
+    /// Extract the system scripts from a genesis block.
+    pub fn from_genesis(genesis_block: &BlockView) -> Result<Self, anyhow::Error> {
+        let transactions = genesis_block.transactions();
+        let type_hash = |loc: (usize, usize), name: &str| -> Result<H256, anyhow::Error> {
+            transactions
+                .get(loc.0)
+                .and_then(|tx| tx.output(loc.1))
+                .and_then(|output| output.type_().to_opt())
+                .map(|script| script.calc_script_hash().unpack())
+                .ok_or_else(|| anyhow!("No type hash({}) found in txs[{}][{}]", name, loc.0, loc.1))
+        };
+        let out_point = |loc: (usize, usize)| -> Result<OutPoint, anyhow::Error> {
+            transactions
+                .get(loc.0)
+                .filter(|tx| tx.outputs().len() > loc.1)
+                .map(|tx| OutPoint::new(tx.hash(), loc.1 as u32))
+                .ok_or_else(|| anyhow!("No output found in txs[{}][{}]", loc.0, loc.1))
+        };
+        Ok(SystemScriptConstants {
+            sighash_type_hash: type_hash(SIGHASH_OUTPUT_LOC, "sighash")?,
+            multisig_type_hash: type_hash(MULTISIG_OUTPUT_LOC, "multisig")?,
+            dao_type_hash: type_hash(DAO_OUTPUT_LOC, "dao")?,
+            sighash_dep: CellDep::new_builder()
+                .out_point(out_point(SIGHASH_GROUP_OUTPUT_LOC)?)
+                .dep_type(DepType::DepGroup.into())
+                .build(),
+            multisig_dep: CellDep::new_builder()
+                .out_point(out_point(MULTISIG_GROUP_OUTPUT_LOC)?)
+                .dep_type(DepType::DepGroup.into())
+                .build(),
+            dao_dep: CellDep::new_builder()
+                .out_point(out_point(DAO_OUTPUT_LOC)?)
+                .build(),
+        })
+    }
+
+    /// Build the genesis block from a chain spec and extract the system scripts.
+    pub fn from_chain_spec(spec: &ChainSpec) -> Result<Self, anyhow::Error> {
+        let genesis_block = spec
+            .build_genesis()
+            .map_err(|err| anyhow!(err.to_string()))?;
+        Self::from_genesis(&genesis_block)
+    }
+
+    /// Load from the canonical chain spec bundled in `ckb-resource`, only
+    /// mainnet and testnet chain specs are bundled.
+    pub fn bundled(network: NetworkType) -> Result<Self, anyhow::Error> {
+        if let Some(constants) = BUNDLED.read().get(&network) {
+            return Ok(constants.clone());
+        }
+        let constants = Self::load_bundled(network)?;
+        BUNDLED.write().insert(network, constants.clone());
+        Ok(constants)
+    }
+
+    fn load_bundled(network: NetworkType) -> Result<Self, anyhow::Error> {
+        let file = match network {
+            NetworkType::Mainnet => "specs/mainnet.toml",
+            NetworkType::Testnet => "specs/testnet.toml",
+            _ => return Err(anyhow!("no bundled chain spec for network: {}", network)),
+        };
+        let spec = ChainSpec::load_from(&Resource::bundled(file.to_string()))
+            .map_err(|err| anyhow!(err.to_string()))?;
+        Self::from_chain_spec(&spec)
+    }
+
+    /// Get the constants of a network, the runtime override (see
+    /// [`set_system_script_constants`]) takes priority over the bundled chain
+    /// spec. Used by
+    /// [`DefaultCellDepResolver::from_network`](crate::traits::DefaultCellDepResolver::from_network).
+    pub fn get(network: NetworkType) -> Result<Self, anyhow::Error> {
+        if let Some(constants) = OVERRIDES.read().get(&network) {
+            return Ok(constants.clone());
+        }
+        Self::bundled(network)
+    }
+}
+
+/// Override the system script constants of a network at runtime, for custom
+/// dev chains or a network upgrade not bundled yet. Return the previous value.
+pub fn set_system_script_constants(
+    network: NetworkType,
+    constants: SystemScriptConstants,
+) -> Option<SystemScriptConstants> {
+    OVERRIDES.write().insert(network, constants)
+}
+
+/// Remove the runtime override of a network.
+pub fn clear_system_script_constants(network: NetworkType) -> Option<SystemScriptConstants> {
+    OVERRIDES.write().remove(&network)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{DAO_TYPE_HASH, MULTISIG_TYPE_HASH, SIGHASH_TYPE_HASH};
+    use crate::traits::DefaultCellDepResolver;
+
+    #[test]
+    fn test_hard_coded_constants_match_chain_spec() {
+        for network in [NetworkType::Mainnet, NetworkType::Testnet].iter() {
+            let constants = SystemScriptConstants::bundled(*network).unwrap();
+            assert_eq!(constants.sighash_type_hash, SIGHASH_TYPE_HASH);
+            assert_eq!(constants.multisig_type_hash, MULTISIG_TYPE_HASH);
+            assert_eq!(constants.dao_type_hash, DAO_TYPE_HASH);
+        }
+    }
+
+    #[test]
+    fn test_runtime_override() {
+        let mut constants = SystemScriptConstants::bundled(NetworkType::Testnet).unwrap();
+        constants.dao_type_hash = H256::default();
+        assert!(set_system_script_constants(NetworkType::Dev, constants.clone()).is_none());
+        assert_eq!(
+            SystemScriptConstants::get(NetworkType::Dev).unwrap(),
+            constants
+        );
+        assert!(clear_system_script_constants(NetworkType::Dev).is_some());
+        assert!(SystemScriptConstants::get(NetworkType::Dev).is_err());
+    }
+
+    #[test]
+    fn test_cell_dep_resolver_from_network() {
+        let bundled = SystemScriptConstants::bundled(NetworkType::Mainnet).unwrap();
+        let resolver = DefaultCellDepResolver::from_network(NetworkType::Mainnet).unwrap();
+        assert_eq!(resolver.sighash_dep().unwrap().0, bundled.sighash_dep);
+        assert_eq!(resolver.multisig_dep().unwrap().0, bundled.multisig_dep);
+        assert_eq!(resolver.dao_dep().unwrap().0, bundled.dao_dep);
+
+        // The resolver follows the runtime override
+        let mut constants = bundled;
+        constants.dao_dep = CellDep::default();
+        set_system_script_constants(NetworkType::Staging, constants);
+        let resolver = DefaultCellDepResolver::from_network(NetworkType::Staging).unwrap();
+        assert_eq!(resolver.dao_dep().unwrap().0, CellDep::default());
+        clear_system_script_constants(NetworkType::Staging);
+        assert!(DefaultCellDepResolver::from_network(NetworkType::Staging).is_err());
+    }
+}
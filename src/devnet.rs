@@ -0,0 +1,270 @@
+//! A hermetic integration test harness backed by a containerized CKB devnet.
+//!
+//! The node runs the built-in indexer (`ckb run --indexer`), so a single
+//! container serves both the CKB and the indexer RPC. Blocks are generated on
+//! demand by the `IntegrationTest` RPC module, and the devnet genesis issued
+//! cells are used to fund the test keys.
+//!
+//! ```ignore
+//! let docker = testcontainers::clients::Cli::default();
+//! let devnet = Devnet::start(&docker)?;
+//! let tx_hash = devnet.fund(lock_script, 1000 * ONE_CKB)?;
+//! let mut cell_collector = devnet.cell_collector();
+//! ```
+use std::collections::HashMap;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use thiserror::Error;
+
+use ckb_jsonrpc_types::{OutputsValidator, Status};
+use ckb_types::{
+    bytes::Bytes,
+    core::{BlockView, ScriptHashType, TransactionView},
+    h160, h256,
+    packed::{CellOutput, Script, WitnessArgs},
+    prelude::*,
+    H160, H256,
+};
+use testcontainers::{clients::Cli, core::WaitFor, Container, GenericImage, RunnableImage};
+
+use crate::constants::SIGHASH_TYPE_HASH;
+use crate::rpc::{CkbRpcClient, IndexerRpcClient};
+use crate::traits::{
+    DefaultCellCollector, DefaultCellDepResolver, DefaultHeaderDepResolver,
+    DefaultTransactionDependencyProvider, SecpCkbRawKeySigner,
+};
+use crate::tx_builder::{transfer::CapacityTransferBuilder, CapacityBalancer, TxBuilder};
+use crate::unlock::{ScriptUnlocker, SecpSighashUnlocker};
+use crate::{RpcError, ScriptId};
+
+/// The docker image of the node
+pub const DEVNET_IMAGE: &str = "nervos/ckb";
+/// The docker image tag, matches the ckb crates version
+pub const DEVNET_IMAGE_TAG: &str = "v0.118.0";
+/// The rpc port inside the container
+pub const DEVNET_RPC_PORT: u16 = 8114;
+
+/// The private key of the first issued cell in devnet genesis
+pub const GENESIS_KEY: H256 =
+    h256!("0xd00c06bfd800d27397002dca6fb0993d5ba6399b4238b2f29ee9deb97593d2bc");
+/// The sighash lock args of [`GENESIS_KEY`]
+pub const GENESIS_ARG: H160 = h160!("0xc8328aabcd9b9e8e64fbc566c4385c3bdeb219d7");
+
+const FEE_RATE: u64 = 1000;
+/// The max blocks to generate while waiting a transaction to be committed
+const MAX_COMMIT_BLOCKS: usize = 20;
+
+#[derive(Error, Debug)]
+pub enum DevnetError {
+    #[error("rpc error: `{0}`")]
+    Rpc(#[from] RpcError),
+
+    #[error("build transaction error: `{0}`")]
+    Build(#[from] crate::tx_builder::TxBuilderError),
+
+    #[error("other error: `{0}`")]
+    Other(anyhow::Error),
+}
+
+/// The shell script to init and run a devnet node with rpc exposed
+fn run_script() -> String {
+    format!(
+        "ckb init --chain dev --force --ba-arg {:#x} \
+         && sed -i 's/127.0.0.1:{port}/0.0.0.0:{port}/' ckb.toml \
+         && sed -i 's/^modules = \\[/modules = [\"IntegrationTest\", /' ckb.toml \
+         && exec ckb run --indexer",
+        GENESIS_ARG,
+        port = DEVNET_RPC_PORT
+    )
+}
+
+/// A running devnet node, the container is removed when dropped
+pub struct Devnet<'d> {
+    container: Container<'d, GenericImage>,
+    url: String,
+}
+
+impl<'d> Devnet<'d> {
+    /// Start a devnet node with the default image
+    pub fn start(docker: &'d Cli) -> Result<Devnet<'d>, DevnetError> {
+        Devnet::start_with_image(docker, DEVNET_IMAGE, DEVNET_IMAGE_TAG)
+    }
+
+    pub fn start_with_image(
+        docker: &'d Cli,
+        image: &str,
+        tag: &str,
+    ) -> Result<Devnet<'d>, DevnetError> {
+        let image = GenericImage::new(image, tag)
+            .with_entrypoint("/bin/sh")
+            .with_exposed_port(DEVNET_RPC_PORT)
+            .with_wait_for(WaitFor::message_on_stdout("Listen HTTP RPCServer"));
+        let args = vec!["-c".to_string(), run_script()];
+        let container = docker.run(RunnableImage::from((image, args)));
+        let port = container.get_host_port_ipv4(DEVNET_RPC_PORT);
+        let devnet = Devnet {
+            container,
+            url: format!("http://127.0.0.1:{}", port),
+        };
+        devnet.wait_ready(Duration::from_secs(30))?;
+        Ok(devnet)
+    }
+
+    fn wait_ready(&self, timeout: Duration) -> Result<(), DevnetError> {
+        let start = Instant::now();
+        loop {
+            match self.ckb_client().get_tip_block_number() {
+                Ok(_) => return Ok(()),
+                Err(err) if start.elapsed() >= timeout => return Err(err.into()),
+                Err(_) => thread::sleep(Duration::from_millis(200)),
+            }
+        }
+    }
+
+    /// The container of the node
+    pub fn container(&self) -> &Container<'d, GenericImage> {
+        &self.container
+    }
+
+    /// The rpc url of the node (also the indexer rpc url)
+    pub fn url(&self) -> &str {
+        self.url.as_str()
+    }
+
+    pub fn ckb_client(&self) -> CkbRpcClient {
+        CkbRpcClient::new(self.url.as_str())
+    }
+
+    pub fn indexer_client(&self) -> IndexerRpcClient {
+        IndexerRpcClient::new(self.url.as_str())
+    }
+
+    pub fn cell_dep_resolver(&self) -> Result<DefaultCellDepResolver, DevnetError> {
+        let genesis_block = self
+            .ckb_client()
+            .get_block_by_number(0.into())?
+            .ok_or_else(|| DevnetError::Other(anyhow!("genesis block not found")))?;
+        DefaultCellDepResolver::from_genesis(&BlockView::from(genesis_block))
+            .map_err(|err| DevnetError::Other(anyhow!(err)))
+    }
+
+    pub fn header_dep_resolver(&self) -> DefaultHeaderDepResolver {
+        DefaultHeaderDepResolver::new(self.url.as_str())
+    }
+
+    pub fn cell_collector(&self) -> DefaultCellCollector {
+        DefaultCellCollector::new(self.url.as_str())
+    }
+
+    pub fn tx_dep_provider(&self) -> DefaultTransactionDependencyProvider {
+        DefaultTransactionDependencyProvider::new(self.url.as_str(), 10)
+    }
+
+    /// The sighash lock script of the genesis key
+    pub fn genesis_lock_script(&self) -> Script {
+        Script::new_builder()
+            .code_hash(SIGHASH_TYPE_HASH.pack())
+            .hash_type(ScriptHashType::Type.into())
+            .args(Bytes::from(GENESIS_ARG.as_bytes().to_vec()).pack())
+            .build()
+    }
+
+    /// Generate `count` blocks, return the last block hash
+    pub fn generate_blocks(&self, count: usize) -> Result<H256, DevnetError> {
+        let ckb_client = self.ckb_client();
+        let mut block_hash = H256::default();
+        for _ in 0..count {
+            block_hash = ckb_client.generate_block()?;
+        }
+        Ok(block_hash)
+    }
+
+    /// Send the transaction and generate blocks until it is committed
+    pub fn send_and_commit(&self, tx: TransactionView) -> Result<H256, DevnetError> {
+        let ckb_client = self.ckb_client();
+        let tx_hash =
+            ckb_client.send_transaction(tx.data().into(), Some(OutputsValidator::Passthrough))?;
+        for _ in 0..MAX_COMMIT_BLOCKS {
+            self.generate_blocks(1)?;
+            let status = ckb_client
+                .get_transaction(tx_hash.clone())?
+                .map(|tx_with_status| tx_with_status.tx_status.status);
+            if status == Some(Status::Committed) {
+                return Ok(tx_hash);
+            }
+        }
+        Err(DevnetError::Other(anyhow!(
+            "transaction {:#x} not committed after {} blocks",
+            tx_hash,
+            MAX_COMMIT_BLOCKS
+        )))
+    }
+
+    /// Transfer `capacity` from the genesis issued cells to the lock script,
+    /// return the committed transaction hash.
+    pub fn fund(&self, lock_script: Script, capacity: u64) -> Result<H256, DevnetError> {
+        let genesis_key = secp256k1::SecretKey::from_slice(GENESIS_KEY.as_bytes())
+            .map_err(|err| DevnetError::Other(anyhow!(err)))?;
+        let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![genesis_key]);
+        let sighash_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+        let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+        unlockers.insert(
+            ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+            Box::new(sighash_unlocker),
+        );
+
+        let placeholder_witness = WitnessArgs::new_builder()
+            .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+            .build();
+        let balancer =
+            CapacityBalancer::new_simple(self.genesis_lock_script(), placeholder_witness, FEE_RATE);
+        let output = CellOutput::new_builder()
+            .capacity(capacity.pack())
+            .lock(lock_script)
+            .build();
+        let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+        let (tx, still_locked_groups) = builder.build_unlocked(
+            &mut self.cell_collector(),
+            &self.cell_dep_resolver()?,
+            &self.header_dep_resolver(),
+            &self.tx_dep_provider(),
+            &balancer,
+            &unlockers,
+        )?;
+        if !still_locked_groups.is_empty() {
+            return Err(DevnetError::Other(anyhow!(
+                "genesis cells not unlocked: {:?}",
+                still_locked_groups
+            )));
+        }
+        self.send_and_commit(tx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::ONE_CKB;
+    use crate::traits::CellQueryOptions;
+
+    #[test]
+    fn test_devnet_fund() {
+        let docker = Cli::default();
+        let devnet = Devnet::start(&docker).unwrap();
+        let lock_script = Script::new_builder()
+            .code_hash(SIGHASH_TYPE_HASH.pack())
+            .hash_type(ScriptHashType::Type.into())
+            .args(Bytes::from(vec![7u8; 20]).pack())
+            .build();
+        devnet.fund(lock_script.clone(), 1000 * ONE_CKB).unwrap();
+        let capacity = devnet
+            .indexer_client()
+            .get_cells_capacity(CellQueryOptions::new_lock(lock_script).into())
+            .unwrap()
+            .map(|cells_capacity| cells_capacity.capacity.value())
+            .unwrap_or_default();
+        assert_eq!(capacity, 1000 * ONE_CKB);
+    }
+}
@@ -0,0 +1,188 @@
+//! A small client of the public CKB testnet faucet, for integration tests and
+//! examples to provision funds without manual steps.
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use ckb_types::packed::Script;
+
+use crate::constants::ONE_CKB;
+use crate::rpc::IndexerRpcClient;
+use crate::traits::CellQueryOptions;
+use crate::{Address, NetworkType, RpcError};
+
+/// The public testnet faucet api
+pub const DEFAULT_FAUCET_URL: &str = "https://faucet-api.nervos.org";
+/// The default amount (in CKB) to claim
+pub const DEFAULT_CLAIM_AMOUNT: u64 = 10_000;
+
+#[derive(Error, Debug)]
+pub enum FaucetError {
+    #[error("only testnet address is supported, got: {0}")]
+    InvalidNetwork(NetworkType),
+
+    #[error("http error: `{0}`")]
+    Http(#[from] reqwest::Error),
+
+    #[error("faucet rejected the claim: `{0}`")]
+    Rejected(String),
+
+    #[error("rpc error: `{0}`")]
+    Rpc(#[from] RpcError),
+
+    #[error("funds not credited after {0:?}")]
+    Timeout(Duration),
+}
+
+#[derive(Debug, Serialize)]
+struct ClaimEvent {
+    address_hash: String,
+    amount: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ClaimRequest {
+    claim_event: ClaimEvent,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClaimErrorResponse {
+    #[serde(default)]
+    errors: Vec<serde_json::Value>,
+}
+
+/// The testnet faucet client
+#[derive(Debug, Clone)]
+pub struct FaucetClient {
+    url: String,
+    client: reqwest::blocking::Client,
+    /// The interval between two balance checks when waiting for funds
+    pub poll_interval: Duration,
+}
+
+impl Default for FaucetClient {
+    fn default() -> FaucetClient {
+        FaucetClient::new(DEFAULT_FAUCET_URL)
+    }
+}
+
+impl FaucetClient {
+    pub fn new(url: &str) -> FaucetClient {
+        FaucetClient {
+            url: url.trim_end_matches('/').to_string(),
+            client: reqwest::blocking::Client::new(),
+            poll_interval: Duration::from_secs(5),
+        }
+    }
+
+    /// Request `amount` CKB for a testnet address.
+    pub fn request_funds(&self, address: &Address, amount: u64) -> Result<(), FaucetError> {
+        if address.network() != NetworkType::Testnet {
+            return Err(FaucetError::InvalidNetwork(address.network()));
+        }
+        let request = ClaimRequest {
+            claim_event: ClaimEvent {
+                address_hash: address.to_string(),
+                amount: amount.to_string(),
+            },
+        };
+        let response = self
+            .client
+            .post(format!("{}/claim_events", self.url))
+            .json(&request)
+            .send()?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let message = response
+                .json::<ClaimErrorResponse>()
+                .ok()
+                .filter(|resp| !resp.errors.is_empty())
+                .map(|resp| serde_json::Value::from(resp.errors).to_string())
+                .unwrap_or_else(|| status.to_string());
+            return Err(FaucetError::Rejected(message));
+        }
+        Ok(())
+    }
+
+    /// Poll the indexer until the capacity of the address reaches
+    /// `min_capacity` (in shannons), return the capacity.
+    pub fn wait_for_capacity(
+        &self,
+        indexer_client: &IndexerRpcClient,
+        address: &Address,
+        min_capacity: u64,
+        timeout: Duration,
+    ) -> Result<u64, FaucetError> {
+        let query = CellQueryOptions::new_lock(Script::from(address));
+        let start = Instant::now();
+        loop {
+            let capacity = indexer_client
+                .get_cells_capacity(query.clone().into())?
+                .map(|cells_capacity| cells_capacity.capacity.value())
+                .unwrap_or_default();
+            if capacity >= min_capacity {
+                return Ok(capacity);
+            }
+            if start.elapsed() >= timeout {
+                return Err(FaucetError::Timeout(timeout));
+            }
+            thread::sleep(self.poll_interval);
+        }
+    }
+
+    /// Request `amount` CKB for the address and wait until it is credited.
+    pub fn request_and_wait(
+        &self,
+        indexer_client: &IndexerRpcClient,
+        address: &Address,
+        amount: u64,
+        timeout: Duration,
+    ) -> Result<u64, FaucetError> {
+        let query = CellQueryOptions::new_lock(Script::from(address));
+        let before = indexer_client
+            .get_cells_capacity(query.into())?
+            .map(|cells_capacity| cells_capacity.capacity.value())
+            .unwrap_or_default();
+        self.request_funds(address, amount)?;
+        self.wait_for_capacity(
+            indexer_client,
+            address,
+            before.saturating_add(amount.saturating_mul(ONE_CKB)),
+            timeout,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AddressPayload;
+    use ckb_types::h160;
+    use httpmock::prelude::*;
+
+    #[test]
+    fn test_request_funds() {
+        let payload =
+            AddressPayload::from_pubkey_hash(h160!("0xb39bbc0b3673c7d36450bc14cfcdad2d559c6c64"));
+        let testnet_address = Address::new(NetworkType::Testnet, payload.clone(), true);
+        let mainnet_address = Address::new(NetworkType::Mainnet, payload, true);
+
+        let server = MockServer::start();
+        let claim_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/claim_events")
+                .body_contains(testnet_address.to_string().as_str());
+            then.status(201).body("{}");
+        });
+        let client = FaucetClient::new(server.base_url().as_str());
+        client.request_funds(&testnet_address, 10_000).unwrap();
+        claim_mock.assert();
+
+        assert!(matches!(
+            client.request_funds(&mainnet_address, 10_000),
+            Err(FaucetError::InvalidNetwork(NetworkType::Mainnet))
+        ));
+    }
+}
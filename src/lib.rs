@@ -1,7 +1,17 @@
 pub mod constants;
 pub mod core;
+#[cfg(feature = "integration-tests")]
+pub mod devnet;
+#[cfg(feature = "test-utils")]
+pub mod faucet;
+#[cfg(feature = "test-utils")]
+pub mod lumos_compat;
+pub mod plugin;
 pub mod pubsub;
 pub mod rpc;
+#[cfg(feature = "schema")]
+pub mod schema;
+pub mod spv;
 pub mod traits;
 pub mod transaction;
 pub mod tx_builder;
@@ -18,9 +28,9 @@ mod tests;
 
 pub use rpc::{CkbRpcClient, IndexerRpcClient, RpcError};
 pub use types::{
-    Address, AddressPayload, AddressType, CodeHashIndex, HumanCapacity, NetworkInfo, NetworkType,
-    OldAddress, OldAddressFormat, ScriptGroup, ScriptGroupType, ScriptId, Since, SinceType,
-    TransactionWithScriptGroups,
+    Address, AddressPayload, AddressType, CodeHashIndex, HumanCapacity, HumanFeeRate, NetworkInfo,
+    NetworkType, OldAddress, OldAddressFormat, ScriptGroup, ScriptGroupType, ScriptId, Since,
+    SinceType, TransactionWithScriptGroups,
 };
 
 pub use ckb_crypto::secp::SECP256K1;
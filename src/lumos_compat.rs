@@ -0,0 +1,449 @@
+//! Compare the transactions built by Lumos (the TypeScript SDK) and this SDK.
+//!
+//! Teams migrating from Lumos can build the same transaction with both stacks
+//! and check the semantic differences: fees, cell deps, header deps, inputs,
+//! outputs and witness layouts. The input/output order and the cell dep
+//! order are not compared, only the sets.
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use anyhow::anyhow;
+use serde_json::Value;
+use thiserror::Error;
+
+use ckb_jsonrpc_types as json_types;
+use ckb_types::{
+    core::TransactionView,
+    packed::{Byte32, CellDep, OutPoint, Transaction, WitnessArgs},
+    prelude::*,
+};
+
+use crate::traits::{
+    CellCollector, CellDepResolver, HeaderDepResolver, TransactionDependencyProvider,
+};
+use crate::tx_builder::{tx_fee, CapacityBalancer, TxBuilder, TxBuilderError};
+use crate::unlock::ScriptUnlocker;
+use crate::ScriptId;
+
+#[derive(Error, Debug)]
+pub enum LumosCompatError {
+    #[error("invalid lumos transaction json: `{0}`")]
+    InvalidJson(anyhow::Error),
+
+    #[error("calculate transaction fee error: `{0}`")]
+    TxFee(anyhow::Error),
+
+    #[error("build transaction error: `{0}`")]
+    TxBuilder(#[from] TxBuilderError),
+}
+
+/// Parse a transaction object produced by Lumos (e.g.
+/// `helpers.createTransactionFromSkeleton`). Both the camelCase (Lumos) and
+/// the snake_case (CKB RPC) field names are accepted.
+pub fn parse_lumos_transaction(json: &str) -> Result<TransactionView, LumosCompatError> {
+    let value: Value =
+        serde_json::from_str(json).map_err(|err| LumosCompatError::InvalidJson(anyhow!(err)))?;
+    let tx: json_types::Transaction = serde_json::from_value(normalize_keys(value))
+        .map_err(|err| LumosCompatError::InvalidJson(anyhow!(err)))?;
+    Ok(Transaction::from(tx).into_view())
+}
+
+fn to_snake_case(key: &str) -> String {
+    let mut result = String::with_capacity(key.len() + 4);
+    for ch in key.chars() {
+        if ch.is_ascii_uppercase() {
+            result.push('_');
+            result.push(ch.to_ascii_lowercase());
+        } else {
+            result.push(ch);
+        }
+    }
+    result
+}
+
+fn normalize_keys(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| {
+                    let key = to_snake_case(&key);
+                    let value = match (key.as_str(), value) {
+                        ("dep_type", Value::String(dep_type)) => {
+                            Value::String(to_snake_case(&dep_type))
+                        }
+                        (_, value) => normalize_keys(value),
+                    };
+                    (key, value)
+                })
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(normalize_keys).collect()),
+        value => value,
+    }
+}
+
+/// The layout of a witness, the content of the signatures is not compared.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum WitnessLayout {
+    Empty,
+    /// The witness is a `WitnessArgs`, the values are the field lengths
+    WitnessArgs {
+        lock: Option<usize>,
+        input_type: Option<usize>,
+        output_type: Option<usize>,
+    },
+    /// The witness is not a `WitnessArgs`, the value is the length
+    Raw(usize),
+}
+
+impl WitnessLayout {
+    pub fn from_witness(witness: &[u8]) -> WitnessLayout {
+        if witness.is_empty() {
+            return WitnessLayout::Empty;
+        }
+        match WitnessArgs::from_slice(witness) {
+            Ok(witness_args) => WitnessLayout::WitnessArgs {
+                lock: witness_args
+                    .lock()
+                    .to_opt()
+                    .map(|data| data.raw_data().len()),
+                input_type: witness_args
+                    .input_type()
+                    .to_opt()
+                    .map(|data| data.raw_data().len()),
+                output_type: witness_args
+                    .output_type()
+                    .to_opt()
+                    .map(|data| data.raw_data().len()),
+            },
+            Err(_) => WitnessLayout::Raw(witness.len()),
+        }
+    }
+}
+
+/// A semantic difference between the Lumos transaction and the SDK one
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum TxDifference {
+    Fee {
+        lumos: u64,
+        sdk: u64,
+    },
+    /// The cell deps only in the Lumos transaction or only in the SDK one
+    CellDeps {
+        lumos_only: Vec<CellDep>,
+        sdk_only: Vec<CellDep>,
+    },
+    HeaderDeps {
+        lumos_only: Vec<Byte32>,
+        sdk_only: Vec<Byte32>,
+    },
+    Inputs {
+        lumos_only: Vec<OutPoint>,
+        sdk_only: Vec<OutPoint>,
+    },
+    /// The count of the outputs with the same lock script, type script and
+    /// data (the capacity is not compared, e.g. the change)
+    Outputs {
+        lumos_count: usize,
+        sdk_count: usize,
+        description: String,
+    },
+    WitnessLayout {
+        index: usize,
+        lumos: Option<WitnessLayout>,
+        sdk: Option<WitnessLayout>,
+    },
+}
+
+impl fmt::Display for TxDifference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxDifference::Fee { lumos, sdk } => write!(f, "fee: lumos={}, sdk={}", lumos, sdk),
+            TxDifference::CellDeps {
+                lumos_only,
+                sdk_only,
+            } => write!(
+                f,
+                "cell deps: lumos only={:?}, sdk only={:?}",
+                lumos_only, sdk_only
+            ),
+            TxDifference::HeaderDeps {
+                lumos_only,
+                sdk_only,
+            } => write!(
+                f,
+                "header deps: lumos only={:?}, sdk only={:?}",
+                lumos_only, sdk_only
+            ),
+            TxDifference::Inputs {
+                lumos_only,
+                sdk_only,
+            } => write!(
+                f,
+                "inputs: lumos only={:?}, sdk only={:?}",
+                lumos_only, sdk_only
+            ),
+            TxDifference::Outputs {
+                lumos_count,
+                sdk_count,
+                description,
+            } => write!(
+                f,
+                "outputs {}: lumos count={}, sdk count={}",
+                description, lumos_count, sdk_count
+            ),
+            TxDifference::WitnessLayout { index, lumos, sdk } => write!(
+                f,
+                "witness[{}] layout: lumos={:?}, sdk={:?}",
+                index, lumos, sdk
+            ),
+        }
+    }
+}
+
+/// The comparison report
+#[derive(Debug, Clone, Default)]
+pub struct ComparisonReport {
+    pub differences: Vec<TxDifference>,
+}
+
+impl ComparisonReport {
+    pub fn is_equivalent(&self) -> bool {
+        self.differences.is_empty()
+    }
+}
+
+impl fmt::Display for ComparisonReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.differences.is_empty() {
+            return write!(f, "equivalent");
+        }
+        for difference in &self.differences {
+            writeln!(f, "{}", difference)?;
+        }
+        Ok(())
+    }
+}
+
+fn set_diff<T: Clone + Eq + std::hash::Hash>(lumos: Vec<T>, sdk: Vec<T>) -> (Vec<T>, Vec<T>) {
+    let lumos_set: HashSet<T> = lumos.iter().cloned().collect();
+    let sdk_set: HashSet<T> = sdk.iter().cloned().collect();
+    let lumos_only = lumos
+        .into_iter()
+        .filter(|item| !sdk_set.contains(item))
+        .collect();
+    let sdk_only = sdk
+        .into_iter()
+        .filter(|item| !lumos_set.contains(item))
+        .collect();
+    (lumos_only, sdk_only)
+}
+
+fn output_kinds(tx: &TransactionView) -> HashMap<String, usize> {
+    let mut kinds = HashMap::new();
+    for (output, data) in tx.outputs_with_data_iter() {
+        let description = format!(
+            "lock={}, type={}, data=0x{:x}",
+            output.lock(),
+            output.type_(),
+            data
+        );
+        *kinds.entry(description).or_insert(0) += 1;
+    }
+    kinds
+}
+
+/// Compare the structure of the transactions (everything except the fee)
+pub fn diff_transactions(
+    lumos_tx: &TransactionView,
+    sdk_tx: &TransactionView,
+) -> Vec<TxDifference> {
+    let mut differences = Vec::new();
+    let (lumos_only, sdk_only) = set_diff(
+        lumos_tx.cell_deps().into_iter().collect(),
+        sdk_tx.cell_deps().into_iter().collect(),
+    );
+    if !lumos_only.is_empty() || !sdk_only.is_empty() {
+        differences.push(TxDifference::CellDeps {
+            lumos_only,
+            sdk_only,
+        });
+    }
+    let (lumos_only, sdk_only) = set_diff(
+        lumos_tx.header_deps_iter().collect(),
+        sdk_tx.header_deps_iter().collect(),
+    );
+    if !lumos_only.is_empty() || !sdk_only.is_empty() {
+        differences.push(TxDifference::HeaderDeps {
+            lumos_only,
+            sdk_only,
+        });
+    }
+    let (lumos_only, sdk_only) = set_diff(
+        lumos_tx.input_pts_iter().collect(),
+        sdk_tx.input_pts_iter().collect(),
+    );
+    if !lumos_only.is_empty() || !sdk_only.is_empty() {
+        differences.push(TxDifference::Inputs {
+            lumos_only,
+            sdk_only,
+        });
+    }
+
+    let lumos_kinds = output_kinds(lumos_tx);
+    let sdk_kinds = output_kinds(sdk_tx);
+    let mut descriptions: Vec<_> = lumos_kinds.keys().chain(sdk_kinds.keys()).collect();
+    descriptions.sort();
+    descriptions.dedup();
+    for description in descriptions {
+        let lumos_count = lumos_kinds.get(description).cloned().unwrap_or_default();
+        let sdk_count = sdk_kinds.get(description).cloned().unwrap_or_default();
+        if lumos_count != sdk_count {
+            differences.push(TxDifference::Outputs {
+                lumos_count,
+                sdk_count,
+                description: description.clone(),
+            });
+        }
+    }
+
+    let lumos_witnesses = lumos_tx.witnesses();
+    let sdk_witnesses = sdk_tx.witnesses();
+    let layout = |witnesses: &ckb_types::packed::BytesVec, index: usize| {
+        witnesses
+            .get(index)
+            .map(|witness| WitnessLayout::from_witness(witness.raw_data().as_ref()))
+    };
+    for index in 0..lumos_witnesses.len().max(sdk_witnesses.len()) {
+        let lumos = layout(&lumos_witnesses, index);
+        let sdk = layout(&sdk_witnesses, index);
+        if lumos != sdk {
+            differences.push(TxDifference::WitnessLayout { index, lumos, sdk });
+        }
+    }
+    differences
+}
+
+/// Compare the transactions, include the fee
+pub fn compare_transactions(
+    lumos_tx: &TransactionView,
+    sdk_tx: &TransactionView,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+    header_dep_resolver: &dyn HeaderDepResolver,
+) -> Result<ComparisonReport, LumosCompatError> {
+    let fee = |tx: &TransactionView| {
+        tx_fee(tx.clone(), tx_dep_provider, header_dep_resolver)
+            .map_err(|err| LumosCompatError::TxFee(anyhow!(err)))
+    };
+    let lumos_fee = fee(lumos_tx)?;
+    let sdk_fee = fee(sdk_tx)?;
+    let mut differences = Vec::new();
+    if lumos_fee != sdk_fee {
+        differences.push(TxDifference::Fee {
+            lumos: lumos_fee,
+            sdk: sdk_fee,
+        });
+    }
+    differences.extend(diff_transactions(lumos_tx, sdk_tx));
+    Ok(ComparisonReport { differences })
+}
+
+/// Build the equivalent transaction (balanced, not unlocked) with the SDK
+/// builder, then compare it with the Lumos transaction json.
+#[allow(clippy::too_many_arguments)]
+pub fn compare_with_builder(
+    lumos_json: &str,
+    builder: &dyn TxBuilder,
+    cell_collector: &mut dyn CellCollector,
+    cell_dep_resolver: &dyn CellDepResolver,
+    header_dep_resolver: &dyn HeaderDepResolver,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+    balancer: &CapacityBalancer,
+    unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+) -> Result<ComparisonReport, LumosCompatError> {
+    let lumos_tx = parse_lumos_transaction(lumos_json)?;
+    let sdk_tx = builder.build_balanced(
+        cell_collector,
+        cell_dep_resolver,
+        header_dep_resolver,
+        tx_dep_provider,
+        balancer,
+        unlockers,
+    )?;
+    compare_transactions(&lumos_tx, &sdk_tx, tx_dep_provider, header_dep_resolver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::bytes::Bytes;
+
+    const LUMOS_TX: &str = r#"{
+        "version": "0x0",
+        "cellDeps": [{
+            "outPoint": {
+                "txHash": "0xf8de3bb47d055cdf460d93a2a6e1b05f7432f9777c8c474abf4eec1d4aee5d37",
+                "index": "0x0"
+            },
+            "depType": "depGroup"
+        }],
+        "headerDeps": [],
+        "inputs": [{
+            "since": "0x0",
+            "previousOutput": {
+                "txHash": "0x8f8c79eb6671709633fe6a46de93c0fedc9c1b8a6527a18d3983879542635c9f",
+                "index": "0x1"
+            }
+        }],
+        "outputs": [{
+            "capacity": "0x2540be400",
+            "lock": {
+                "codeHash": "0x9bd7e06f3ecf4be0f2fcd2188b23f1b9fcc88e5d4b65a8637b17723bbda3cce8",
+                "hashType": "type",
+                "args": "0x36c329ed630d6ce750712a477543672adab57f4c"
+            },
+            "type": null
+        }],
+        "outputsData": ["0x"],
+        "witnesses": ["0x55000000100000005500000055000000410000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000"]
+    }"#;
+
+    #[test]
+    fn test_compare_lumos_transaction() {
+        let lumos_tx = parse_lumos_transaction(LUMOS_TX).unwrap();
+        assert_eq!(lumos_tx.cell_deps().len(), 1);
+        assert_eq!(
+            lumos_tx.cell_deps().get(0).unwrap().dep_type(),
+            ckb_types::core::DepType::DepGroup.into()
+        );
+        assert_eq!(
+            WitnessLayout::from_witness(lumos_tx.witnesses().get(0).unwrap().raw_data().as_ref()),
+            WitnessLayout::WitnessArgs {
+                lock: Some(65),
+                input_type: None,
+                output_type: None,
+            }
+        );
+        assert!(diff_transactions(&lumos_tx, &lumos_tx).is_empty());
+
+        let sdk_tx = lumos_tx
+            .as_advanced_builder()
+            .set_cell_deps(Vec::new())
+            .set_witnesses(vec![WitnessArgs::new_builder()
+                .lock(Some(Bytes::from(vec![0u8; 85])).pack())
+                .build()
+                .as_bytes()
+                .pack()])
+            .build();
+        let differences = diff_transactions(&lumos_tx, &sdk_tx);
+        assert_eq!(differences.len(), 2);
+        assert!(matches!(
+            &differences[0],
+            TxDifference::CellDeps { lumos_only, sdk_only } if lumos_only.len() == 1 && sdk_only.is_empty()
+        ));
+        assert!(matches!(
+            &differences[1],
+            TxDifference::WitnessLayout { index: 0, .. }
+        ));
+    }
+}
@@ -0,0 +1,266 @@
+//! Plugins of the third-party lock and type scripts.
+//!
+//! A [`ScriptPlugin`] bundles everything the SDK needs to work with a
+//! script: the script ids it matches, a decoder of the args, the cell deps,
+//! a factory of the unlocker and the script handlers of the
+//! [`TransactionBuilderConfiguration`]. An out-of-tree crate (e.g. for
+//! JoyID, Spore or an enterprise lock) registers its plugin once with
+//! [`register_script_plugin`], then the plugins are applied to the SDK
+//! registries by [`apply_cell_deps`], [`build_unlockers`] and
+//! [`register_script_handlers`].
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+use thiserror::Error;
+
+use ckb_types::packed::{CellDep, Script};
+
+use crate::traits::{DefaultCellDepResolver, Signer};
+use crate::transaction::{handler::ScriptHandler, TransactionBuilderConfiguration};
+use crate::tx_builder::TxBuilderError;
+use crate::unlock::ScriptUnlocker;
+use crate::{NetworkInfo, ScriptId};
+
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+pub enum PluginError {
+    #[error("script plugin already registered: `{0}`")]
+    DuplicatedName(String),
+
+    #[error("invalid script args: `{0}`")]
+    InvalidArgs(String),
+}
+
+/// The integration of a lock or type script
+pub trait ScriptPlugin: Send + Sync {
+    /// The unique name of the plugin
+    fn name(&self) -> &str;
+
+    /// The script ids of the script on the network
+    fn script_ids(&self, network: &NetworkInfo) -> Vec<ScriptId>;
+
+    /// Decode the script args to a readable JSON value
+    fn decode_args(&self, args: &[u8]) -> Result<serde_json::Value, PluginError> {
+        Ok(serde_json::Value::String(format!(
+            "0x{}",
+            args.iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>()
+        )))
+    }
+
+    /// The cell deps of the script ids: `(script id, cell dep, name)`
+    fn cell_deps(&self, network: &NetworkInfo) -> Vec<(ScriptId, CellDep, String)>;
+
+    /// Create the unlocker of the lock script with the signer, `None` for
+    /// a type script.
+    fn unlocker(
+        &self,
+        _signer: Box<dyn Signer>,
+        _network: &NetworkInfo,
+    ) -> Option<Box<dyn ScriptUnlocker>> {
+        None
+    }
+
+    /// The script handlers extending the transaction builder
+    fn script_handlers(
+        &self,
+        _network: &NetworkInfo,
+    ) -> Result<Vec<Box<dyn ScriptHandler>>, TxBuilderError> {
+        Ok(Vec::new())
+    }
+}
+
+lazy_static! {
+    static ref SCRIPT_PLUGINS: RwLock<Vec<Arc<dyn ScriptPlugin>>> = RwLock::new(Vec::new());
+}
+
+/// Register the plugin, the name must be unique
+pub fn register_script_plugin(plugin: Arc<dyn ScriptPlugin>) -> Result<(), PluginError> {
+    let mut plugins = SCRIPT_PLUGINS.write();
+    if plugins.iter().any(|item| item.name() == plugin.name()) {
+        return Err(PluginError::DuplicatedName(plugin.name().to_string()));
+    }
+    plugins.push(plugin);
+    Ok(())
+}
+
+/// Unregister the plugin by name, return false if it is not registered
+pub fn unregister_script_plugin(name: &str) -> bool {
+    let mut plugins = SCRIPT_PLUGINS.write();
+    let len = plugins.len();
+    plugins.retain(|plugin| plugin.name() != name);
+    plugins.len() != len
+}
+
+/// The registered plugins in registration order
+pub fn script_plugins() -> Vec<Arc<dyn ScriptPlugin>> {
+    SCRIPT_PLUGINS.read().clone()
+}
+
+/// The first registered plugin matching the script on the network
+pub fn find_script_plugin(script: &Script, network: &NetworkInfo) -> Option<Arc<dyn ScriptPlugin>> {
+    let script_id = ScriptId::from(script);
+    SCRIPT_PLUGINS
+        .read()
+        .iter()
+        .find(|plugin| plugin.script_ids(network).contains(&script_id))
+        .cloned()
+}
+
+/// Decode the args of the script by its plugin, `None` if no plugin matches
+pub fn decode_script_args(
+    script: &Script,
+    network: &NetworkInfo,
+) -> Result<Option<serde_json::Value>, PluginError> {
+    find_script_plugin(script, network)
+        .map(|plugin| plugin.decode_args(&script.args().raw_data()))
+        .transpose()
+}
+
+/// Insert the cell deps of all the plugins into the resolver
+pub fn apply_cell_deps(resolver: &mut DefaultCellDepResolver, network: &NetworkInfo) {
+    for plugin in script_plugins() {
+        for (script_id, cell_dep, name) in plugin.cell_deps(network) {
+            resolver.insert(script_id, cell_dep, name);
+        }
+    }
+}
+
+/// Create the unlockers of all the plugins, `new_signer` creates the signer
+/// of each unlocker.
+pub fn build_unlockers(
+    network: &NetworkInfo,
+    new_signer: &dyn Fn() -> Box<dyn Signer>,
+) -> HashMap<ScriptId, Box<dyn ScriptUnlocker>> {
+    let mut unlockers = HashMap::new();
+    for plugin in script_plugins() {
+        for script_id in plugin.script_ids(network) {
+            if let Some(unlocker) = plugin.unlocker(new_signer(), network) {
+                unlockers.insert(script_id, unlocker);
+            }
+        }
+    }
+    unlockers
+}
+
+/// Register the script handlers of all the plugins into the configuration
+pub fn register_script_handlers(
+    configuration: &mut TransactionBuilderConfiguration,
+) -> Result<(), TxBuilderError> {
+    let network = configuration.network_info().clone();
+    for plugin in script_plugins() {
+        for handler in plugin.script_handlers(&network)? {
+            configuration.register_script_handler(handler);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::{
+        bytes::Bytes,
+        core::{BlockView, DepType},
+        packed::OutPoint,
+        prelude::*,
+        H160, H256,
+    };
+
+    use crate::traits::{CellDepResolver, SecpCkbRawKeySigner};
+    use crate::unlock::SecpSighashUnlocker;
+
+    struct TestPlugin;
+
+    const TEST_CODE_HASH: H256 = H256([0x5c; 32]);
+
+    impl ScriptPlugin for TestPlugin {
+        fn name(&self) -> &str {
+            "test-lock"
+        }
+
+        fn script_ids(&self, _network: &NetworkInfo) -> Vec<ScriptId> {
+            vec![ScriptId::new_data1(TEST_CODE_HASH)]
+        }
+
+        fn decode_args(&self, args: &[u8]) -> Result<serde_json::Value, PluginError> {
+            let pubkey_hash = H160::from_slice(args)
+                .map_err(|_| PluginError::InvalidArgs(format!("length: {}", args.len())))?;
+            Ok(serde_json::json!({ "pubkey_hash": pubkey_hash }))
+        }
+
+        fn cell_deps(&self, _network: &NetworkInfo) -> Vec<(ScriptId, CellDep, String)> {
+            let cell_dep = CellDep::new_builder()
+                .out_point(OutPoint::new(H256([0x5d; 32]).pack(), 0))
+                .dep_type(DepType::Code.into())
+                .build();
+            vec![(
+                ScriptId::new_data1(TEST_CODE_HASH),
+                cell_dep,
+                "test lock".to_string(),
+            )]
+        }
+
+        fn unlocker(
+            &self,
+            signer: Box<dyn Signer>,
+            _network: &NetworkInfo,
+        ) -> Option<Box<dyn ScriptUnlocker>> {
+            Some(Box::new(SecpSighashUnlocker::from(signer)))
+        }
+    }
+
+    #[test]
+    fn test_script_plugin() {
+        let network = NetworkInfo::testnet();
+        register_script_plugin(Arc::new(TestPlugin)).unwrap();
+        assert_eq!(
+            register_script_plugin(Arc::new(TestPlugin)),
+            Err(PluginError::DuplicatedName("test-lock".to_string()))
+        );
+
+        let script = Script::new_builder()
+            .code_hash(TEST_CODE_HASH.pack())
+            .hash_type(ScriptId::new_data1(TEST_CODE_HASH).hash_type.into())
+            .args(Bytes::from(vec![0x01; 20]).pack())
+            .build();
+        assert_eq!(
+            find_script_plugin(&script, &network).unwrap().name(),
+            "test-lock"
+        );
+        assert_eq!(
+            decode_script_args(&script, &network).unwrap(),
+            Some(serde_json::json!({ "pubkey_hash": H160([0x01; 20]) }))
+        );
+        let other_script = script
+            .clone()
+            .as_builder()
+            .code_hash(H256([0x5e; 32]).pack())
+            .build();
+        assert!(find_script_plugin(&other_script, &network).is_none());
+        let bad_args = script
+            .clone()
+            .as_builder()
+            .args(Bytes::from(vec![0x01; 3]).pack())
+            .build();
+        assert!(decode_script_args(&bad_args, &network).is_err());
+
+        let genesis_block: ckb_jsonrpc_types::BlockView =
+            serde_json::from_str(include_str!("test-data/genesis_block.json")).unwrap();
+        let genesis_block: BlockView = genesis_block.into();
+        let mut resolver = DefaultCellDepResolver::from_genesis(&genesis_block).unwrap();
+        apply_cell_deps(&mut resolver, &network);
+        assert!(resolver.resolve(&script).is_some());
+
+        let unlockers = build_unlockers(&network, &|| {
+            Box::new(SecpCkbRawKeySigner::default()) as Box<dyn Signer>
+        });
+        assert!(unlockers.contains_key(&ScriptId::new_data1(TEST_CODE_HASH)));
+
+        assert!(unregister_script_plugin("test-lock"));
+        assert!(!unregister_script_plugin("test-lock"));
+        assert!(find_script_plugin(&script, &network).is_none());
+    }
+}
@@ -110,6 +110,23 @@ crate::jsonrpc!(pub struct CkbRpcClient {
     pub fn calculate_dao_maximum_withdraw(&self, out_point: OutPoint, kind: DaoWithdrawingCalculationKind) -> Capacity;
 });
 
+crate::jsonrpc_async!(
+/// The async ckb rpc client, only the methods required by the async transaction
+/// building pipeline and transaction submission are included.
+pub struct AsyncCkbRpcClient {
+    pub fn get_block(&self, hash: H256) -> Option<BlockView>;
+    pub fn get_block_by_number(&self, number: BlockNumber) -> Option<BlockView>;
+    pub fn get_consensus(&self) -> Consensus;
+    pub fn get_epoch_by_number(&self, number: EpochNumber) -> Option<EpochView>;
+    pub fn get_header(&self, hash: H256) -> Option<HeaderView>;
+    pub fn get_header_by_number(&self, number: BlockNumber) -> Option<HeaderView>;
+    pub fn get_live_cell(&self, out_point: OutPoint, with_data: bool) -> CellWithStatus;
+    pub fn get_tip_block_number(&self) -> BlockNumber;
+    pub fn get_tip_header(&self) -> HeaderView;
+    pub fn get_transaction(&self, hash: H256) -> Option<TransactionWithStatusResponse>;
+    pub fn send_transaction(&self, tx: Transaction, outputs_validator: Option<OutputsValidator>) -> H256;
+});
+
 fn transform_cycles(cycles: Option<Vec<ckb_jsonrpc_types::Cycle>>) -> Vec<Cycle> {
     cycles
         .map(|c| c.into_iter().map(Into::into).collect())
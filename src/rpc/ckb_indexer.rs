@@ -78,6 +78,26 @@ impl From<CellQueryOptions> for SearchKey {
     }
 }
 
+/// Convert the [`CellQueryOptions`] to the indexer [`SearchKey`].
+///
+/// The cell collectors use this to build the `get_cells` request, a custom
+/// implementation can target an indexer fork with extended filters (for
+/// example setting `group_by_transaction` or a different `script_len_range`)
+/// without reimplementing the collector.
+pub trait SearchKeyConverter: Send + Sync {
+    fn to_search_key(&self, query: &CellQueryOptions) -> SearchKey;
+}
+
+/// The default conversion, same as `SearchKey::from(CellQueryOptions)`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultSearchKeyConverter;
+
+impl SearchKeyConverter for DefaultSearchKeyConverter {
+    fn to_search_key(&self, query: &CellQueryOptions) -> SearchKey {
+        SearchKey::from(query.clone())
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "snake_case")]
 pub enum ScriptType {
@@ -171,16 +191,24 @@ pub struct TxWithCells {
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "snake_case")]
+#[non_exhaustive]
 pub enum CellType {
     Input,
     Output,
+    /// A variant added by a newer node
+    #[serde(other)]
+    Unknown,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "snake_case")]
+#[non_exhaustive]
 pub enum IOType {
     Input,
     Output,
+    /// A variant added by a newer node
+    #[serde(other)]
+    Unknown,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -195,3 +223,10 @@ crate::jsonrpc!(pub struct IndexerRpcClient {
     pub fn get_transactions(&self, search_key: SearchKey, order: Order, limit: Uint32, after: Option<JsonBytes>) -> Pagination<Tx>;
     pub fn get_cells_capacity(&self, search_key: SearchKey) -> Option<CellsCapacity>;
 });
+
+crate::jsonrpc_async!(pub struct AsyncIndexerRpcClient {
+    pub fn get_indexer_tip(&self) -> Option<Tip>;
+    pub fn get_cells(&self, search_key: SearchKey, order: Order, limit: Uint32, after: Option<JsonBytes>) -> Pagination<Cell>;
+    pub fn get_transactions(&self, search_key: SearchKey, order: Order, limit: Uint32, after: Option<JsonBytes>) -> Pagination<Tx>;
+    pub fn get_cells_capacity(&self, search_key: SearchKey) -> Option<CellsCapacity>;
+});
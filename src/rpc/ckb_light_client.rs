@@ -31,11 +31,20 @@ pub enum SetScriptsCommand {
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
 #[serde(tag = "status")]
 #[serde(rename_all = "snake_case")]
+#[non_exhaustive]
 pub enum FetchStatus<T> {
-    Added { timestamp: Uint64 },
-    Fetching { first_sent: Uint64 },
-    Fetched { data: T },
+    Added {
+        timestamp: Uint64,
+    },
+    Fetching {
+        first_sent: Uint64,
+    },
+    Fetched {
+        data: T,
+    },
     NotFound,
+    #[serde(other)]
+    Unknown,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
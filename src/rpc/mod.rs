@@ -1,10 +1,11 @@
 mod ckb;
 pub mod ckb_indexer;
 pub mod ckb_light_client;
+mod tolerant;
 
 use anyhow::anyhow;
-pub use ckb::CkbRpcClient;
-pub use ckb_indexer::IndexerRpcClient;
+pub use ckb::{AsyncCkbRpcClient, CkbRpcClient};
+pub use ckb_indexer::{AsyncIndexerRpcClient, IndexerRpcClient};
 use ckb_jsonrpc_types::{JsonBytes, ResponseFormat};
 pub use ckb_light_client::LightClientRpcClient;
 
@@ -18,10 +19,57 @@ pub enum RpcError {
     Http(#[from] reqwest::Error),
     #[error("jsonrpc error: `{0}`")]
     Rpc(#[from] jsonrpc_core::Error),
+    #[error("unknown fields in response: `{0}`")]
+    Schema(String),
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
 
+/// How to parse the RPC responses.
+///
+/// The response types (most are from `ckb-jsonrpc-types`) reject unknown
+/// fields, so a node upgrade adding a field breaks the deserialization. The
+/// raw response is always available by `post::<_, serde_json::Value>`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub enum ParseMode {
+    /// Skip the unknown fields rejected by the response types and parse
+    /// again. Only the fields not in the struct being parsed are skipped, it
+    /// is the best effort for forward compatibility.
+    #[default]
+    Tolerant,
+    /// Reject the responses with any field not in the response types, even
+    /// if the type ignores unknown fields. Useful in tests to detect the
+    /// schema changes of the node early.
+    Strict,
+}
+
+/// Parse the result of a RPC response by the parse mode
+pub fn decode_response<RET>(
+    value: serde_json::Value,
+    parse_mode: ParseMode,
+) -> Result<RET, RpcError>
+where
+    RET: serde::de::DeserializeOwned,
+{
+    match parse_mode {
+        ParseMode::Strict => {
+            let mut ignored = Vec::new();
+            let ret = serde_ignored::deserialize(value, |path| ignored.push(path.to_string()))?;
+            if ignored.is_empty() {
+                Ok(ret)
+            } else {
+                Err(RpcError::Schema(ignored.join(", ")))
+            }
+        }
+        ParseMode::Tolerant => match serde::Deserialize::deserialize(&value) {
+            Ok(ret) => Ok(ret),
+            Err(_) => Ok(serde::Deserialize::deserialize(
+                tolerant::TolerantDeserializer(&value),
+            )?),
+        },
+    }
+}
+
 #[macro_export]
 macro_rules! jsonrpc {
     (
@@ -37,18 +85,36 @@ macro_rules! jsonrpc {
             pub client: reqwest::blocking::Client,
             pub url: reqwest::Url,
             pub id: std::sync::atomic::AtomicU64,
+            parse_mode: $crate::rpc::ParseMode,
         }
 
         impl Clone for $struct_name {
             fn clone(&self) -> Self {
-                Self::new(&self.url.to_string())
+                let mut client = Self::new(&self.url.to_string());
+                client.parse_mode = self.parse_mode;
+                client
             }
         }
 
         impl $struct_name {
             pub fn new(uri: &str) -> Self {
                 let url = reqwest::Url::parse(uri).expect("ckb uri, e.g. \"http://127.0.0.1:8114\"");
-                $struct_name { url, id: 0.into(), client: reqwest::blocking::Client::new(), }
+                $struct_name {
+                    url,
+                    id: 0.into(),
+                    client: reqwest::blocking::Client::new(),
+                    parse_mode: Default::default(),
+                }
+            }
+
+            /// Set the response parse mode (tolerant by default)
+            pub fn set_parse_mode(&mut self, parse_mode: $crate::rpc::ParseMode) {
+                self.parse_mode = parse_mode;
+            }
+
+            /// The response parse mode
+            pub fn parse_mode(&self) -> $crate::rpc::ParseMode {
+                self.parse_mode
             }
 
             pub fn post<PARAM, RET>(&self, method:&str, params: PARAM)->Result<RET, $crate::rpc::RpcError>
@@ -69,7 +135,7 @@ macro_rules! jsonrpc {
                 let output = resp.json::<jsonrpc_core::response::Output>()?;
                 match output {
                     jsonrpc_core::response::Output::Success(success) => {
-                        serde_json::from_value(success.result).map_err(Into::into)
+                        $crate::rpc::decode_response(success.result, self.parse_mode)
                     },
                     jsonrpc_core::response::Output::Failure(failure) => {
                         Err(failure.error.into())
@@ -95,7 +161,7 @@ macro_rules! jsonrpc {
                     let output = resp.json::<jsonrpc_core::response::Output>()?;
                     match output {
                         jsonrpc_core::response::Output::Success(success) => {
-                            serde_json::from_value(success.result).map_err(Into::into)
+                            $crate::rpc::decode_response(success.result, $selff.parse_mode)
                         },
                         jsonrpc_core::response::Output::Failure(failure) => {
                             Err(failure.error.into())
@@ -107,6 +173,96 @@ macro_rules! jsonrpc {
     )
 }
 
+/// Same as [`jsonrpc!`](crate::jsonrpc) but generate an async client backed by
+/// `reqwest::Client`.
+#[macro_export]
+macro_rules! jsonrpc_async {
+    (
+        $(#[$struct_attr:meta])*
+        pub struct $struct_name:ident {$(
+            $(#[$attr:meta])*
+            pub fn $method:ident(& $selff:ident $(, $arg_name:ident: $arg_ty:ty)*)
+                -> $return_ty:ty;
+        )*}
+    ) => (
+        $(#[$struct_attr])*
+        pub struct $struct_name {
+            pub client: reqwest::Client,
+            pub url: reqwest::Url,
+            pub id: std::sync::atomic::AtomicU64,
+            parse_mode: $crate::rpc::ParseMode,
+        }
+
+        impl Clone for $struct_name {
+            fn clone(&self) -> Self {
+                $struct_name {
+                    client: self.client.clone(),
+                    url: self.url.clone(),
+                    id: 0.into(),
+                    parse_mode: self.parse_mode,
+                }
+            }
+        }
+
+        impl $struct_name {
+            pub fn new(uri: &str) -> Self {
+                let url = reqwest::Url::parse(uri).expect("ckb uri, e.g. \"http://127.0.0.1:8114\"");
+                $struct_name {
+                    url,
+                    id: 0.into(),
+                    client: reqwest::Client::new(),
+                    parse_mode: Default::default(),
+                }
+            }
+
+            /// Set the response parse mode (tolerant by default)
+            pub fn set_parse_mode(&mut self, parse_mode: $crate::rpc::ParseMode) {
+                self.parse_mode = parse_mode;
+            }
+
+            /// The response parse mode
+            pub fn parse_mode(&self) -> $crate::rpc::ParseMode {
+                self.parse_mode
+            }
+
+            pub async fn post<PARAM, RET>(&self, method:&str, params: PARAM)->Result<RET, $crate::rpc::RpcError>
+            where
+                PARAM:serde::ser::Serialize,
+                RET: serde::de::DeserializeOwned,
+            {
+                let params = serde_json::to_value(params)?;
+                let id = self.id.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
+                let mut req_json = serde_json::Map::new();
+                req_json.insert("id".to_owned(), serde_json::json!(id));
+                req_json.insert("jsonrpc".to_owned(), serde_json::json!("2.0"));
+                req_json.insert("method".to_owned(), serde_json::json!(method));
+                req_json.insert("params".to_owned(), params);
+
+                let resp = self.client.post(self.url.clone()).json(&req_json).send().await?;
+                let output = resp.json::<jsonrpc_core::response::Output>().await?;
+                match output {
+                    jsonrpc_core::response::Output::Success(success) => {
+                        $crate::rpc::decode_response(success.result, self.parse_mode)
+                    },
+                    jsonrpc_core::response::Output::Failure(failure) => {
+                        Err(failure.error.into())
+                    }
+                }
+            }
+
+            $(
+                $(#[$attr])*
+                pub async fn $method(&$selff $(, $arg_name: $arg_ty)*) -> Result<$return_ty, $crate::rpc::RpcError> {
+                    let method = String::from(stringify!($method));
+                    let params = $crate::serialize_parameters!($($arg_name,)*);
+                    $selff.post(&method, params).await
+                }
+            )*
+        }
+    )
+}
+
 #[macro_export]
 macro_rules! serialize_parameters {
     () => ( serde_json::Value::Null );
@@ -153,3 +309,103 @@ mod anyhow_tests {
         println!("{}", error)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_jsonrpc_types as json_types;
+    use serde_json::json;
+
+    #[test]
+    fn test_decode_response() {
+        let value = json!({
+            "code_hash": "0x9bd7e06f3ecf4be0f2fcd2188b23f1b9fcc88e5d4b65a8637b17723bbda3cce8",
+            "hash_type": "type",
+            "args": "0x",
+            "new_field": 1,
+        });
+        let script: json_types::Script =
+            decode_response(value.clone(), ParseMode::Tolerant).unwrap();
+        assert_eq!(script.args, json_types::JsonBytes::default());
+        assert!(matches!(
+            decode_response::<json_types::Script>(value, ParseMode::Strict),
+            Err(RpcError::Json(_))
+        ));
+
+        // the unknown fields ignored by the type are rejected in strict mode
+        let value = json!({ "objects": [], "last_cursor": "0x", "new_field": 1 });
+        assert!(decode_response::<ckb_indexer::Pagination<u64>>(
+            value.clone(),
+            ParseMode::Tolerant
+        )
+        .is_ok());
+        assert!(matches!(
+            decode_response::<ckb_indexer::Pagination<u64>>(value, ParseMode::Strict),
+            Err(RpcError::Schema(_))
+        ));
+
+        let cell_type: ckb_indexer::CellType =
+            decode_response(json!("new_type"), ParseMode::Tolerant).unwrap();
+        assert!(matches!(cell_type, ckb_indexer::CellType::Unknown));
+    }
+
+    #[test]
+    fn test_decode_response_keeps_known_fields() {
+        #[derive(serde::Deserialize, Debug, PartialEq)]
+        #[serde(deny_unknown_fields)]
+        struct Inner {
+            extra: Option<u64>,
+            value: u64,
+        }
+        #[derive(serde::Deserialize, Debug, PartialEq)]
+        enum Kind {
+            Wrapped(Inner),
+        }
+        #[derive(serde::Deserialize, Debug, PartialEq)]
+        #[serde(deny_unknown_fields)]
+        struct Outer {
+            inner: Inner,
+            items: Vec<Inner>,
+            kind: Kind,
+        }
+
+        // `extra` is unknown to the outer struct only
+        let value = json!({
+            "extra": 1,
+            "inner": { "extra": 2, "value": 3 },
+            "items": [{ "value": 4, "new_field": 5 }, { "extra": 6, "value": 7 }],
+            "kind": { "Wrapped": { "extra": 8, "value": 9, "new_field": 10 } },
+        });
+        let outer: Outer = decode_response(value, ParseMode::Tolerant).unwrap();
+        assert_eq!(
+            outer,
+            Outer {
+                inner: Inner {
+                    extra: Some(2),
+                    value: 3
+                },
+                items: vec![
+                    Inner {
+                        extra: None,
+                        value: 4
+                    },
+                    Inner {
+                        extra: Some(6),
+                        value: 7
+                    },
+                ],
+                kind: Kind::Wrapped(Inner {
+                    extra: Some(8),
+                    value: 9
+                }),
+            }
+        );
+
+        // Other errors are still reported
+        let value = json!({ "inner": { "value": "0x1" }, "items": [], "kind": "Wrapped" });
+        assert!(matches!(
+            decode_response::<Outer>(value, ParseMode::Tolerant),
+            Err(RpcError::Json(_))
+        ));
+    }
+}
@@ -0,0 +1,183 @@
+//! The deserializer of the tolerant parse mode.
+//!
+//! It wraps a `serde_json::Value` and skips the keys of an object which are
+//! not in the fields of the struct being deserialized, so the response types
+//! rejecting unknown fields can be parsed in a single pass. Only the structs
+//! know their fields, the unknown fields in the untagged or internally tagged
+//! enums are still rejected.
+use serde::de::{
+    self, value::BorrowedStrDeserializer, DeserializeSeed, Deserializer, EnumAccess, MapAccess,
+    SeqAccess, VariantAccess, Visitor,
+};
+use serde::forward_to_deserialize_any;
+use serde_json::{map, Error, Value};
+
+pub(crate) struct TolerantDeserializer<'de>(pub(crate) &'de Value);
+
+impl<'de> Deserializer<'de> for TolerantDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Value::Array(items) => visitor.visit_seq(TolerantSeq(items.iter())),
+            Value::Object(map) => visitor.visit_map(TolerantMap::new(map, None)),
+            value => value.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.0 {
+            Value::Object(map) => visitor.visit_map(TolerantMap::new(map, Some(fields))),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.0 {
+            Value::Object(map) if map.len() == 1 => {
+                let (variant, value) = map.iter().next().expect("one entry");
+                visitor.visit_enum(TolerantEnum { variant, value })
+            }
+            value => value.deserialize_enum(name, variants, visitor),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map identifier ignored_any
+    }
+}
+
+struct TolerantSeq<'de>(std::slice::Iter<'de, Value>);
+
+impl<'de> SeqAccess<'de> for TolerantSeq<'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        self.0
+            .next()
+            .map(|item| seed.deserialize(TolerantDeserializer(item)))
+            .transpose()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+struct TolerantMap<'de> {
+    iter: map::Iter<'de>,
+    /// The fields of the struct, skip the other keys
+    fields: Option<&'static [&'static str]>,
+    value: Option<&'de Value>,
+}
+
+impl<'de> TolerantMap<'de> {
+    fn new(map: &'de map::Map<String, Value>, fields: Option<&'static [&'static str]>) -> Self {
+        TolerantMap {
+            iter: map.iter(),
+            fields,
+            value: None,
+        }
+    }
+}
+
+impl<'de> MapAccess<'de> for TolerantMap<'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        for (key, value) in &mut self.iter {
+            if self
+                .fields
+                .map_or(true, |fields| fields.contains(&key.as_str()))
+            {
+                self.value = Some(value);
+                return seed
+                    .deserialize(BorrowedStrDeserializer::new(key))
+                    .map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let value = self
+            .value
+            .take()
+            .ok_or_else(|| de::Error::custom("value is missing"))?;
+        seed.deserialize(TolerantDeserializer(value))
+    }
+}
+
+struct TolerantEnum<'de> {
+    variant: &'de String,
+    value: &'de Value,
+}
+
+impl<'de> EnumAccess<'de> for TolerantEnum<'de> {
+    type Error = Error;
+    type Variant = TolerantDeserializer<'de>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self::Variant), Error> {
+        let variant = seed.deserialize(BorrowedStrDeserializer::new(self.variant))?;
+        Ok((variant, TolerantDeserializer(self.value)))
+    }
+}
+
+impl<'de> VariantAccess<'de> for TolerantDeserializer<'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        de::Deserialize::deserialize(self)
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_struct("", fields, visitor)
+    }
+}
@@ -0,0 +1,57 @@
+//! JSON Schema of the serializable builder inputs, enabled by feature `schema`.
+//!
+//! Services not written in Rust can use the exported schemas to validate the
+//! requests they send to a transaction building service based on this SDK.
+use schemars::{schema::RootSchema, schema_for};
+
+use crate::types::{AddressType, CodeHashIndex, NetworkType, ScriptGroupType};
+use crate::unlock::{
+    omni_lock::{AdminConfig, Identity, OmniLockFlags},
+    IdentityFlag, MultisigConfig, OmniLockAcpConfig, OmniLockConfig,
+};
+
+/// Export the JSON Schema of all serializable builder inputs, keyed by type name.
+pub fn builder_input_schemas() -> Vec<(&'static str, RootSchema)> {
+    vec![
+        ("NetworkType", schema_for!(NetworkType)),
+        ("AddressType", schema_for!(AddressType)),
+        ("CodeHashIndex", schema_for!(CodeHashIndex)),
+        ("ScriptGroupType", schema_for!(ScriptGroupType)),
+        ("MultisigConfig", schema_for!(MultisigConfig)),
+        ("IdentityFlag", schema_for!(IdentityFlag)),
+        ("Identity", schema_for!(Identity)),
+        ("OmniLockFlags", schema_for!(OmniLockFlags)),
+        ("AdminConfig", schema_for!(AdminConfig)),
+        ("OmniLockAcpConfig", schema_for!(OmniLockAcpConfig)),
+        ("OmniLockConfig", schema_for!(OmniLockConfig)),
+    ]
+}
+
+/// Export the JSON Schema of all serializable builder inputs as one JSON
+/// object, keyed by type name.
+pub fn builder_input_schemas_json() -> serde_json::Value {
+    let schemas = builder_input_schemas()
+        .into_iter()
+        .map(|(name, schema)| {
+            let value = serde_json::to_value(schema).expect("serialize json schema");
+            (name.to_string(), value)
+        })
+        .collect();
+    serde_json::Value::Object(schemas)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder_input_schemas() {
+        let schemas = builder_input_schemas_json();
+        let multisig = &schemas["MultisigConfig"];
+        assert_eq!(
+            multisig["properties"]["sighash_addresses"]["items"]["type"],
+            "string"
+        );
+        assert_eq!(schemas["NetworkType"]["enum"][0], "Mainnet");
+    }
+}
@@ -0,0 +1,331 @@
+//! Simple payment verification (SPV) proofs of the committed transactions.
+//!
+//! A [`CkbSpvProof`] proves a transaction is committed in a block and the
+//! block is followed by a chain of headers (the confirmations), it is what
+//! the bridges and the cross-chain verifiers (e.g. the ckb-spv contracts)
+//! check against the headers they trust:
+//!
+//!   * [`CkbTxProof`] is the transaction merkle proof, the molecule encoding
+//!     ([`CkbTxProof::to_bytes`]) is the `CkbTxProof` table of the ckb-spv
+//!     contracts.
+//!   * The headers start from the block of the transaction, every header is
+//!     the parent of the next one, encoded as a `HeaderVec`.
+//!
+//! Build the proof from a node with [`build_spv_proof`], or from a block
+//! with [`CkbTxProof::from_block`].
+use std::convert::TryFrom;
+
+use ckb_jsonrpc_types::BlockNumber;
+use ckb_types::{
+    core::{BlockView, HeaderView},
+    packed::{self, Byte32},
+    prelude::*,
+    utilities::{merkle_root, MerkleProof, CBMT},
+    H256,
+};
+use thiserror::Error;
+
+use crate::rpc::{CkbRpcClient, RpcError};
+
+#[derive(Error, Debug)]
+pub enum SpvError {
+    #[error("rpc error: `{0}`")]
+    Rpc(#[from] RpcError),
+
+    #[error("transaction not committed: `{0:#x}`")]
+    TxNotCommitted(H256),
+
+    #[error("header not found: `{0}`")]
+    HeaderNotFound(String),
+
+    #[error("not enough confirmations, required: `{0}`, actual: `{1}`")]
+    NotEnoughConfirmations(u64, u64),
+
+    #[error("invalid proof: `{0}`")]
+    InvalidProof(String),
+}
+
+/// The merkle proof of a transaction in its block
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct CkbTxProof {
+    /// The index of the transaction in the merkle tree (not the index in the
+    /// block)
+    pub tx_merkle_index: u16,
+    pub block_number: u64,
+    pub block_hash: H256,
+    pub tx_hash: H256,
+    pub witnesses_root: H256,
+    pub lemmas: Vec<H256>,
+}
+
+impl CkbTxProof {
+    /// Build the proof of the transaction in the block, return `None` if the
+    /// transaction is not in the block.
+    pub fn from_block(block: &BlockView, tx_hash: &H256) -> Option<CkbTxProof> {
+        let tx_hash: Byte32 = tx_hash.pack();
+        let tx_index = block.tx_hashes().iter().position(|hash| hash == &tx_hash)?;
+        let proof = CBMT::build_merkle_proof(block.tx_hashes(), &[tx_index as u32])?;
+        CkbTxProof::new(
+            &block.header(),
+            tx_hash.unpack(),
+            block.calc_witnesses_root().unpack(),
+            proof,
+        )
+    }
+
+    fn new(
+        header: &HeaderView,
+        tx_hash: H256,
+        witnesses_root: H256,
+        proof: MerkleProof,
+    ) -> Option<CkbTxProof> {
+        let tx_merkle_index = u16::try_from(*proof.indices().first()?).ok()?;
+        Some(CkbTxProof {
+            tx_merkle_index,
+            block_number: header.number(),
+            block_hash: header.hash().unpack(),
+            tx_hash,
+            witnesses_root,
+            lemmas: proof.lemmas().iter().map(|lemma| lemma.unpack()).collect(),
+        })
+    }
+
+    /// The transactions root of the block computed from the proof
+    pub fn transactions_root(&self) -> Option<H256> {
+        let proof = MerkleProof::new(
+            vec![self.tx_merkle_index as u32],
+            self.lemmas.iter().map(|lemma| lemma.pack()).collect(),
+        );
+        let raw_transactions_root = proof.root(&[self.tx_hash.pack()])?;
+        Some(merkle_root(&[raw_transactions_root, self.witnesses_root.pack()]).unpack())
+    }
+
+    /// Verify the proof against the header of the block
+    pub fn verify(&self, header: &HeaderView) -> Result<(), SpvError> {
+        let block_hash: H256 = header.hash().unpack();
+        if block_hash != self.block_hash || header.number() != self.block_number {
+            return Err(SpvError::InvalidProof(format!(
+                "header {:#x} is not the block of the proof",
+                header.hash()
+            )));
+        }
+        let transactions_root: H256 = header.transactions_root().unpack();
+        if self.transactions_root() != Some(transactions_root) {
+            return Err(SpvError::InvalidProof(format!(
+                "transactions root mismatch, transaction: {:#x}",
+                self.tx_hash
+            )));
+        }
+        Ok(())
+    }
+
+    /// The molecule encoding (`CkbTxProof` table):
+    ///
+    /// ```text
+    /// table CkbTxProof {
+    ///     tx_merkle_index: Uint16,
+    ///     block_number:    Uint64,
+    ///     block_hash:      Byte32,
+    ///     tx_hash:         Byte32,
+    ///     witnesses_root:  Byte32,
+    ///     lemmas:          Byte32Vec,
+    /// }
+    /// ```
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let lemmas = packed::Byte32Vec::new_builder()
+            .set(self.lemmas.iter().map(|lemma| lemma.pack()).collect())
+            .build();
+        molecule_table(&[
+            &self.tx_merkle_index.to_le_bytes(),
+            &self.block_number.to_le_bytes(),
+            self.block_hash.as_bytes(),
+            self.tx_hash.as_bytes(),
+            self.witnesses_root.as_bytes(),
+            lemmas.as_slice(),
+        ])
+    }
+}
+
+/// The SPV proof of a transaction, see the [module](self) document.
+#[derive(Debug, Clone)]
+pub struct CkbSpvProof {
+    /// The headers from the block of the transaction, the number of
+    /// confirmations is `headers.len() - 1`
+    pub headers: Vec<HeaderView>,
+    pub tx_proof: CkbTxProof,
+}
+
+impl CkbSpvProof {
+    /// The header chain encoded as a `HeaderVec`
+    pub fn header_chain(&self) -> packed::HeaderVec {
+        packed::HeaderVec::new_builder()
+            .set(self.headers.iter().map(|header| header.data()).collect())
+            .build()
+    }
+
+    pub fn confirmations(&self) -> u64 {
+        self.headers.len().saturating_sub(1) as u64
+    }
+
+    /// Verify the transaction proof and the header chain, the caller must
+    /// check the headers against the ones it trusts.
+    pub fn verify(&self) -> Result<(), SpvError> {
+        let header = self
+            .headers
+            .first()
+            .ok_or_else(|| SpvError::InvalidProof("empty header chain".to_string()))?;
+        self.tx_proof.verify(header)?;
+        for pair in self.headers.windows(2) {
+            if pair[1].parent_hash() != pair[0].hash() || pair[1].number() != pair[0].number() + 1 {
+                return Err(SpvError::InvalidProof(format!(
+                    "header {:#x} is not the child of header {:#x}",
+                    pair[1].hash(),
+                    pair[0].hash()
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Build the SPV proof of a committed transaction with `confirmations`
+/// headers after its block.
+pub fn build_spv_proof(
+    ckb_client: &CkbRpcClient,
+    tx_hash: &H256,
+    confirmations: u64,
+) -> Result<CkbSpvProof, SpvError> {
+    let block_hash = ckb_client
+        .get_transaction(tx_hash.clone())?
+        .and_then(|tx_with_status| tx_with_status.tx_status.block_hash)
+        .ok_or_else(|| SpvError::TxNotCommitted(tx_hash.clone()))?;
+    let proof = ckb_client.get_transaction_proof(vec![tx_hash.clone()], Some(block_hash))?;
+    let header: HeaderView = ckb_client
+        .get_header(proof.block_hash.clone())?
+        .ok_or_else(|| SpvError::HeaderNotFound(format!("{:#x}", proof.block_hash)))?
+        .into();
+    let tip_number = ckb_client.get_tip_block_number()?.value();
+    let actual_confirmations = tip_number.saturating_sub(header.number());
+    if actual_confirmations < confirmations {
+        return Err(SpvError::NotEnoughConfirmations(
+            confirmations,
+            actual_confirmations,
+        ));
+    }
+    let merkle_proof = MerkleProof::new(
+        proof
+            .proof
+            .indices
+            .iter()
+            .map(|index| index.value())
+            .collect(),
+        proof
+            .proof
+            .lemmas
+            .iter()
+            .map(|lemma| lemma.pack())
+            .collect(),
+    );
+    let tx_proof = CkbTxProof::new(&header, tx_hash.clone(), proof.witnesses_root, merkle_proof)
+        .ok_or_else(|| SpvError::InvalidProof(format!("transaction {:#x}", tx_hash)))?;
+
+    let mut headers = vec![header];
+    for number in headers[0].number() + 1..=headers[0].number() + confirmations {
+        let header = ckb_client
+            .get_header_by_number(BlockNumber::from(number))?
+            .ok_or_else(|| SpvError::HeaderNotFound(number.to_string()))?;
+        headers.push(header.into());
+    }
+    let spv_proof = CkbSpvProof { headers, tx_proof };
+    // The chain may be reorganized during the queries
+    spv_proof.verify()?;
+    Ok(spv_proof)
+}
+
+/// Encode the fields as a molecule table
+fn molecule_table(fields: &[&[u8]]) -> Vec<u8> {
+    let header_size = 4 * (fields.len() + 1);
+    let total_size = header_size + fields.iter().map(|field| field.len()).sum::<usize>();
+    let mut data = Vec::with_capacity(total_size);
+    data.extend_from_slice(&(total_size as u32).to_le_bytes());
+    let mut offset = header_size;
+    for field in fields {
+        data.extend_from_slice(&(offset as u32).to_le_bytes());
+        offset += field.len();
+    }
+    for field in fields {
+        data.extend_from_slice(field);
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    use ckb_types::{
+        bytes::Bytes,
+        core::{BlockBuilder, HeaderBuilder, TransactionBuilder},
+        packed::{CellOutput, Header},
+    };
+
+    fn build_block() -> BlockView {
+        let txs = (0..5u64).map(|n| {
+            TransactionBuilder::default()
+                .output(CellOutput::new_builder().capacity(n.pack()).build())
+                .output_data(Bytes::default().pack())
+                .witness(Bytes::from(vec![n as u8]).pack())
+                .build()
+        });
+        BlockBuilder::default()
+            .number(10u64.pack())
+            .transactions(txs)
+            .build()
+    }
+
+    #[test]
+    fn test_tx_proof() {
+        let block = build_block();
+        for tx in block.transactions() {
+            let proof = CkbTxProof::from_block(&block, &tx.hash().unpack()).unwrap();
+            assert_eq!(proof.block_number, 10);
+            proof.verify(&block.header()).unwrap();
+
+            let bytes = proof.to_bytes();
+            let total_size = u32::from_le_bytes(bytes[0..4].try_into().unwrap());
+            assert_eq!(total_size as usize, bytes.len());
+            assert_eq!(
+                &bytes[bytes.len() - 32..],
+                proof.lemmas.last().unwrap().as_bytes()
+            );
+        }
+        assert!(CkbTxProof::from_block(&block, &H256::default()).is_none());
+
+        let tx_hash = block.transactions()[1].hash().unpack();
+        let mut proof = CkbTxProof::from_block(&block, &tx_hash).unwrap();
+        proof.tx_hash = block.transactions()[2].hash().unpack();
+        assert!(proof.verify(&block.header()).is_err());
+    }
+
+    #[test]
+    fn test_spv_proof_header_chain() {
+        let block = build_block();
+        let tx_hash = block.transactions()[3].hash().unpack();
+        let tx_proof = CkbTxProof::from_block(&block, &tx_hash).unwrap();
+        let child = HeaderBuilder::default()
+            .number(11u64.pack())
+            .parent_hash(block.hash())
+            .build();
+        let mut spv_proof = CkbSpvProof {
+            headers: vec![block.header(), child],
+            tx_proof,
+        };
+        spv_proof.verify().unwrap();
+        assert_eq!(spv_proof.confirmations(), 1);
+        assert_eq!(spv_proof.header_chain().len(), 2);
+
+        spv_proof.headers[1] = Header::default().into_view();
+        assert!(spv_proof.verify().is_err());
+    }
+}
@@ -6,6 +6,7 @@ use std::sync::Arc;
 
 use ckb_jsonrpc_types::Serialize;
 use ckb_types::core::{HeaderBuilder, TransactionBuilder};
+use futures::future::BoxFuture;
 use rand::{thread_rng, Rng};
 use thiserror::Error;
 
@@ -15,6 +16,7 @@ use crate::{
         SIGHASH_TYPE_HASH,
     },
     traits::{
+        AsyncCellCollector, AsyncHeaderDepResolver, AsyncTransactionDependencyProvider,
         CellCollector, CellCollectorError, CellDepResolver, CellQueryOptions,
         DefaultCellDepResolver, HeaderDepResolver, LiveCell, TransactionDependencyError,
         TransactionDependencyProvider,
@@ -547,23 +549,128 @@ impl CellCollector for LiveCellsContext {
 
     fn lock_cell(
         &mut self,
-        _out_point: OutPoint,
+        out_point: OutPoint,
         _tip_block_number: u64,
     ) -> Result<(), CellCollectorError> {
-        unimplemented!()
+        if let Some(idx) = self
+            .inputs
+            .iter()
+            .position(|item| item.input.previous_output() == out_point)
+        {
+            self.used_inputs.insert(idx);
+        }
+        Ok(())
     }
     fn apply_tx(
         &mut self,
-        _tx: Transaction,
+        tx: Transaction,
         _tip_block_number: u64,
     ) -> Result<(), CellCollectorError> {
-        unimplemented!()
+        let tx_view = tx.into_view();
+        for input in tx_view.inputs() {
+            let previous_output = input.previous_output();
+            if let Some(idx) = self
+                .inputs
+                .iter()
+                .position(|item| item.input.previous_output() == previous_output)
+            {
+                self.used_inputs.insert(idx);
+            }
+        }
+        for (idx, (output, data)) in tx_view.outputs_with_data_iter().enumerate() {
+            let out_point = OutPoint::new(tx_view.hash(), idx as u32);
+            self.inputs.push(MockInput {
+                input: CellInput::new(out_point, 0),
+                output,
+                data,
+                header: None,
+            });
+        }
+        Ok(())
     }
     fn reset(&mut self) {
         self.used_inputs.clear();
     }
 }
 
+// The async providers resolve immediately from the in-memory context
+impl AsyncTransactionDependencyProvider for Context {
+    fn get_transaction<'a>(
+        &'a self,
+        tx_hash: &'a Byte32,
+    ) -> BoxFuture<'a, Result<TransactionView, TransactionDependencyError>> {
+        Box::pin(async move { TransactionDependencyProvider::get_transaction(self, tx_hash) })
+    }
+    fn get_cell<'a>(
+        &'a self,
+        out_point: &'a OutPoint,
+    ) -> BoxFuture<'a, Result<CellOutput, TransactionDependencyError>> {
+        Box::pin(async move { TransactionDependencyProvider::get_cell(self, out_point) })
+    }
+    fn get_cell_data<'a>(
+        &'a self,
+        out_point: &'a OutPoint,
+    ) -> BoxFuture<'a, Result<Bytes, TransactionDependencyError>> {
+        Box::pin(async move { TransactionDependencyProvider::get_cell_data(self, out_point) })
+    }
+    fn get_header<'a>(
+        &'a self,
+        block_hash: &'a Byte32,
+    ) -> BoxFuture<'a, Result<HeaderView, TransactionDependencyError>> {
+        Box::pin(async move { TransactionDependencyProvider::get_header(self, block_hash) })
+    }
+    fn get_block_extension<'a>(
+        &'a self,
+        block_hash: &'a Byte32,
+    ) -> BoxFuture<'a, Result<Option<ckb_types::packed::Bytes>, TransactionDependencyError>> {
+        Box::pin(
+            async move { TransactionDependencyProvider::get_block_extension(self, block_hash) },
+        )
+    }
+}
+
+impl AsyncHeaderDepResolver for Context {
+    fn resolve_by_tx<'a>(
+        &'a self,
+        tx_hash: &'a Byte32,
+    ) -> BoxFuture<'a, Result<Option<HeaderView>, anyhow::Error>> {
+        Box::pin(async move { HeaderDepResolver::resolve_by_tx(self, tx_hash) })
+    }
+    fn resolve_by_number(
+        &self,
+        number: u64,
+    ) -> BoxFuture<'_, Result<Option<HeaderView>, anyhow::Error>> {
+        Box::pin(async move { HeaderDepResolver::resolve_by_number(self, number) })
+    }
+}
+
+impl AsyncCellCollector for LiveCellsContext {
+    fn collect_live_cells<'a>(
+        &'a mut self,
+        query: &'a CellQueryOptions,
+        apply_changes: bool,
+    ) -> BoxFuture<'a, Result<(Vec<LiveCell>, u64), CellCollectorError>> {
+        Box::pin(async move { CellCollector::collect_live_cells(self, query, apply_changes) })
+    }
+    fn lock_cell(
+        &mut self,
+        out_point: OutPoint,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        CellCollector::lock_cell(self, out_point, tip_block_number)
+    }
+    fn apply_tx(
+        &mut self,
+        tx: Transaction,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        CellCollector::apply_tx(self, tx, tip_block_number)
+    }
+    fn reset(&mut self) {
+        CellCollector::reset(self)
+    }
+}
+
 struct DummyLoader;
 impl MockResourceLoader for DummyLoader {
     fn get_header(&mut self, hash: H256) -> Result<Option<HeaderView>, String> {
@@ -1,40 +1,85 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 use ckb_dao_utils::pack_dao_data;
 use ckb_hash::blake2b_256;
 use ckb_jsonrpc_types as json_types;
 use ckb_types::{
     bytes::Bytes,
-    core::{BlockView, Capacity, EpochNumberWithFraction, HeaderBuilder, ScriptHashType},
+    core::{
+        BlockView, Capacity, EpochNumberWithFraction, FeeRate, HeaderBuilder, ScriptHashType,
+        TransactionBuilder, TransactionView,
+    },
     h160, h256,
-    packed::{CellInput, CellOutput, Script, ScriptOpt, WitnessArgs},
+    packed::{self, CellInput, CellOutput, OutPoint, Script, ScriptOpt, Uint64, WitnessArgs},
     prelude::*,
     H160, H256,
 };
 
 use crate::constants::{
     CHEQUE_CELL_SINCE, DAO_TYPE_HASH, MULTISIG_TYPE_HASH, ONE_CKB, SIGHASH_TYPE_HASH,
+    TYPE_ID_CODE_HASH,
+};
+#[cfg(feature = "async-tx-builder")]
+use crate::traits::AsyncCellCollector;
+use crate::traits::{
+    CellCollector, CellCollectorError, CellDepResolver, CellQueryOptions, HeaderDepResolver,
+    InputSinceMap, LiveCell, SecpCkbRawKeySigner, SnapshotRecorder, SnapshotReplayer,
+    TransactionDependencyError, TransactionDependencyProvider,
 };
-use crate::traits::SecpCkbRawKeySigner;
 use crate::tx_builder::{
-    acp::{AcpTransferBuilder, AcpTransferReceiver},
+    acp::{build_acp_script, AcpCreateBuilder, AcpTransferBuilder, AcpTransferReceiver},
+    allowance::Allowance,
+    amend::TxAmendBuilder,
+    balance_tx_capacity, balance_tx_capacity_partial, balance_tx_capacity_with_report,
+    chain::TxChainBuilder,
+    channel::{ChannelFundingBuilder, ChannelRefund, PendingTxDependencyProvider},
     cheque::{ChequeClaimBuilder, ChequeWithdrawBuilder},
+    coin_select::{balance_tx_capacity_exact, BranchAndBoundSelector},
     dao::{
-        DaoDepositBuilder, DaoDepositReceiver, DaoPrepareBuilder, DaoWithdrawBuilder,
-        DaoWithdrawItem, DaoWithdrawReceiver,
+        DaoBatchDepositBuilder, DaoBatchPrepareBuilder, DaoCapacityProvider, DaoDepositBuilder,
+        DaoDepositReceiver, DaoPrepareBuilder, DaoWithdrawBuilder, DaoWithdrawItem,
+        DaoWithdrawReceiver,
     },
+    escrow::{EscrowConfig, EscrowCreateBuilder, EscrowSettleBuilder, EscrowSettlement},
+    estimate_required_capacity, fill_placeholder_witnesses, fill_placeholder_witnesses_with_skip,
+    freeze::{freeze_lock_script, frozen_reason, unfreeze_lock_script, FreezeRegistry},
+    htlc::{build_htlc_script, HtlcCreateBuilder, HtlcSpendBuilder},
+    is_info_output,
+    metrics::SelectionMetricsAggregator,
+    migration::MigrationBuilder,
+    ordering::InputOrdering,
+    plan::{BalancePlan, BalanceRecorder, ChangeDecision, InputReason},
+    rbf::ReplaceByFeeBuilder,
+    reclaim::ReclaimBuilder,
+    recovery::{load_multisig_configs, RecoveryScanner},
+    shuffle::ShuffleMode,
+    state_cell::{encode_state_data, StateCell, StateCellCreateBuilder},
+    sweep::SweepBuilder,
     transfer::CapacityTransferBuilder,
-    udt::{UdtIssueBuilder, UdtTargetReceiver, UdtTransferBuilder, UdtType},
-    unlock_tx, CapacityBalancer, TransferAction, TxBuilder,
+    tx_fee,
+    type_id::{TypeIdDeployBuilder, TypeIdDeployment, TypeIdUpgradeBuilder},
+    udt::{
+        UdtAutoTransferBuilder, UdtBurnBuilder, UdtIssueBuilder, UdtReceiverDetector,
+        UdtReceiverMode, UdtTargetReceiver, UdtTransferBuilder, UdtType,
+    },
+    unlock_tx, unlock_tx_with_freeze_registry, unlock_tx_with_skip,
+    vesting::{VestingBuilder, VestingClaimBuilder, VestingLock},
+    BalanceTxCapacityError, CapacityBalancer, CapacityProvider, ChangeSplit, ChangeTemplate,
+    DustPolicy, SinceSource, StrictConfig, TransactionFeeError, TransferAction, TxBuilder,
+    TxBuilderError, UdtChange, MAX_CHANGE_LOCK_ARGS_SIZE,
 };
 use crate::unlock::{
-    AcpUnlocker, ChequeAction, ChequeUnlocker, MultisigConfig, ScriptUnlocker,
-    SecpMultisigUnlocker, SecpSighashUnlocker,
+    dedup_multisig_witnesses, find_duplicated_multisig_configs, verify_signatures, AcpUnlocker,
+    ChequeAction, ChequeUnlocker, HtlcAction, HtlcArgs, HtlcUnlocker, MultisigConfig,
+    MultisigProposal, ProposalError, ScriptUnlocker, SecpMultisigUnlocker, SecpSighashUnlocker,
+    SignatureStatus, UnlockError,
 };
-use crate::util::{calculate_dao_maximum_withdraw4, minimal_unlock_point};
-use crate::{ScriptId, Since, SinceType};
+use crate::util::{blake160, calculate_dao_maximum_withdraw4, minimal_unlock_point};
+use crate::{Address, AddressPayload, NetworkType, ScriptGroup, ScriptId, Since, SinceType};
 
-use crate::test_util::{random_out_point, Context};
+use crate::test_util::{random_out_point, Context, LiveCellsContext};
 
 // ckt1qyq86vaa6e8tsruv5ngcd5tp7lcvcewxy7cquuksvj
 const ACCOUNT0_KEY: H256 =
@@ -183,87 +228,280 @@ fn test_transfer_from_sighash() {
 }
 
 #[test]
-fn test_transfer_from_multisig() {
-    let lock_args = vec![
-        ACCOUNT0_ARG.clone(),
-        ACCOUNT1_ARG.clone(),
-        ACCOUNT2_ARG.clone(),
-    ];
-    let cfg = MultisigConfig::new_with(lock_args, 0, 2).unwrap();
-
-    let sender = build_multisig_script(&cfg);
+fn test_capacity_balancer_builder() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
     let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(Vec::new(), vec![(sender.clone(), Some(300 * ONE_CKB))]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+
+    let err = CapacityBalancer::builder()
+        .fee_rate(FEE_RATE)
+        .build()
+        .unwrap_err();
+    assert!(matches!(err, BalanceTxCapacityError::EmptyCapacityProvider));
+    let err = CapacityBalancer::builder()
+        .capacity_provider(sender.clone(), placeholder_witness.clone())
+        .capacity_provider(sender.clone(), placeholder_witness.clone())
+        .build()
+        .unwrap_err();
+    assert!(matches!(err, BalanceTxCapacityError::InvalidConfig(_)));
+    let oversized_lock = sender
+        .clone()
+        .as_builder()
+        .args(Bytes::from(vec![0u8; MAX_CHANGE_LOCK_ARGS_SIZE + 1]).pack())
+        .build();
+    let err = CapacityBalancer::builder()
+        .capacity_provider(sender.clone(), placeholder_witness.clone())
+        .change_lock_script(oversized_lock)
+        .build()
+        .unwrap_err();
+    assert!(matches!(err, BalanceTxCapacityError::InvalidConfig(_)));
+    let err = CapacityBalancer::builder()
+        .capacity_provider(sender.clone(), placeholder_witness.clone())
+        .fee_payer(CapacityProvider::new_simple(vec![(
+            receiver.clone(),
+            placeholder_witness.clone(),
+        )]))
+        .exact_fee(ONE_CKB)
+        .build()
+        .unwrap_err();
+    assert!(matches!(err, BalanceTxCapacityError::InvalidConfig(_)));
+
+    let balancer = CapacityBalancer::builder()
+        .fee_rate(FEE_RATE)
+        .capacity_provider(sender.clone(), placeholder_witness)
+        .change_lock_script(receiver.clone())
+        .force_small_change_as_fee(ONE_CKB)
+        .dust_policy(DustPolicy::new(ONE_CKB, None))
+        .build()
+        .unwrap();
+    assert_eq!(balancer.force_small_change_as_fee, Some(ONE_CKB));
+
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver.clone())
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    let mut cell_collector = ctx.to_live_cells_context();
+    let tx = builder
+        .build_balanced(
+            &mut cell_collector,
+            &ctx,
+            &ctx,
+            &ctx,
+            &balancer,
+            &HashMap::new(),
+        )
+        .unwrap();
+    assert_eq!(tx.inputs().len(), 1);
+    assert_eq!(tx.outputs().len(), 2);
+    assert_eq!(tx.output(1).unwrap().lock(), receiver);
+}
 
+#[test]
+fn test_strict_mode_balancing() {
+    let sender1 = build_sighash_script(ACCOUNT1_ARG);
+    let sender2 = build_sighash_script(ACCOUNT2_ARG);
+    let receiver = build_sighash_script(ACCOUNT3_ARG);
     let ctx = init_context(
         Vec::new(),
         vec![
-            (sender.clone(), Some(100 * ONE_CKB)),
-            (sender.clone(), Some(200 * ONE_CKB)),
-            (sender.clone(), Some(300 * ONE_CKB)),
+            (sender1.clone(), Some(100 * ONE_CKB)),
+            (sender2.clone(), Some(200 * ONE_CKB)),
         ],
     );
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let build = |output_capacity: u64, balancer: &CapacityBalancer| {
+        let output = CellOutput::new_builder()
+            .capacity(output_capacity.pack())
+            .lock(receiver.clone())
+            .build();
+        let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+        let mut cell_collector = ctx.to_live_cells_context();
+        builder.build_balanced(
+            &mut cell_collector,
+            &ctx,
+            &ctx,
+            &ctx,
+            balancer,
+            &HashMap::new(),
+        )
+    };
+
+    // The first provider can not cover the output, switching to the second
+    // provider is forbidden
+    let strict_balancer = |strict: StrictConfig| {
+        CapacityBalancer::builder()
+            .fee_rate(FEE_RATE)
+            .capacity_provider(sender1.clone(), placeholder_witness.clone())
+            .capacity_provider(sender2.clone(), placeholder_witness.clone())
+            .force_small_change_as_fee(50 * ONE_CKB)
+            .strict(strict)
+            .build()
+            .unwrap()
+    };
+    let err = build(150 * ONE_CKB, &strict_balancer(StrictConfig::default())).unwrap_err();
+    assert!(matches!(
+        err,
+        TxBuilderError::BalanceCapacity(BalanceTxCapacityError::StrictModeViolation(ref behavior))
+            if behavior == "switch to next capacity provider"
+    ));
+    let tx = build(
+        150 * ONE_CKB,
+        &strict_balancer(StrictConfig {
+            allow_switch_provider: true,
+            ..Default::default()
+        }),
+    )
+    .unwrap();
+    assert_eq!(tx.inputs().len(), 2);
+    assert_eq!(tx.outputs().len(), 2);
+
+    // The 20 CKB left can not hold a change cell, absorbing it into the fee
+    // is forbidden
+    let err = build(80 * ONE_CKB, &strict_balancer(StrictConfig::default())).unwrap_err();
+    assert!(matches!(
+        err,
+        TxBuilderError::BalanceCapacity(BalanceTxCapacityError::StrictModeViolation(ref behavior))
+            if behavior == "absorb small change into fee"
+    ));
+    let tx = build(
+        80 * ONE_CKB,
+        &strict_balancer(StrictConfig {
+            allow_small_change_as_fee: true,
+            ..Default::default()
+        }),
+    )
+    .unwrap();
+    assert_eq!(tx.inputs().len(), 1);
+    assert_eq!(tx.outputs().len(), 1);
+    assert_eq!(tx_fee(tx, &ctx, &ctx).unwrap(), 20 * ONE_CKB);
+}
+
+#[test]
+fn test_transfer_with_input_since_map() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let mut ctx = init_context(Vec::new(), Vec::new());
+    let out_point1 = random_out_point();
+    let out_point2 = random_out_point();
+    ctx.add_simple_live_cell(out_point1.clone(), sender.clone(), Some(100 * ONE_CKB));
+    ctx.add_simple_live_cell(out_point2.clone(), sender.clone(), Some(200 * ONE_CKB));
 
     let output = CellOutput::new_builder()
-        .capacity((120 * ONE_CKB).pack())
+        .capacity((250 * ONE_CKB).pack())
         .lock(receiver)
         .build();
-    let builder = CapacityTransferBuilder::new(vec![(output.clone(), Bytes::default())]);
-    let placeholder_witness = cfg.placeholder_witness();
-    let balancer =
-        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let since = Since::new(SinceType::BlockNumber, 100, false).value();
+    let mut since_map = InputSinceMap::default();
+    since_map.values.insert(out_point2.clone(), since);
+    let balancer = CapacityBalancer::new_simple_with_since(
+        sender,
+        placeholder_witness,
+        SinceSource::Provider(Arc::new(since_map)),
+        FEE_RATE,
+    );
 
     let mut cell_collector = ctx.to_live_cells_context();
-    let account0_key = secp256k1::SecretKey::from_slice(ACCOUNT0_KEY.as_bytes()).unwrap();
-    let account2_key = secp256k1::SecretKey::from_slice(ACCOUNT2_KEY.as_bytes()).unwrap();
-    let unlockers = build_multisig_unlockers(account0_key, cfg.clone());
-    let mut tx = builder
-        .build_balanced(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+    let tx = builder
+        .build_balanced(
+            &mut cell_collector,
+            &ctx,
+            &ctx,
+            &ctx,
+            &balancer,
+            &HashMap::new(),
+        )
         .unwrap();
-
-    let mut locked_groups = None;
-    for key in [account0_key, account2_key] {
-        let unlockers = build_multisig_unlockers(key, cfg.clone());
-        let (new_tx, new_locked_groups) = unlock_tx(tx.clone(), &ctx, &unlockers).unwrap();
-        tx = new_tx;
-        locked_groups = Some(new_locked_groups);
-    }
-
-    assert_eq!(locked_groups, Some(Vec::new()));
-    assert_eq!(tx.header_deps().len(), 0);
-    assert_eq!(tx.cell_deps().len(), 1);
-    assert_eq!(tx.inputs().len(), 2);
-    for out_point in tx.input_pts_iter() {
-        assert_eq!(ctx.get_input(&out_point).unwrap().0.lock(), sender);
-    }
-    assert_eq!(tx.outputs().len(), 2);
-    assert_eq!(tx.output(0).unwrap(), output);
-    assert_eq!(tx.output(1).unwrap().lock(), sender);
-    let witnesses = tx
-        .witnesses()
+    #[allow(clippy::mutable_key_type)]
+    let inputs_since: HashMap<_, _> = tx
+        .inputs()
         .into_iter()
-        .map(|w| w.raw_data())
-        .collect::<Vec<_>>();
-    assert_eq!(witnesses.len(), 2);
-    assert_eq!(witnesses[0].len(), placeholder_witness.as_slice().len());
-    assert_eq!(witnesses[1].len(), 0);
-    ctx.verify(tx, FEE_RATE).unwrap();
+        .map(|input| {
+            (
+                input.previous_output(),
+                Unpack::<u64>::unpack(&input.since()),
+            )
+        })
+        .collect();
+    assert_eq!(inputs_since.len(), 2);
+    assert_eq!(inputs_since.get(&out_point1), Some(&0));
+    assert_eq!(inputs_since.get(&out_point2), Some(&since));
 }
 
 #[test]
-fn test_transfer_from_acp() {
-    let data_hash = H256::from(blake2b_256(ACP_BIN));
-    let sender = Script::new_builder()
-        .code_hash(data_hash.pack())
-        .hash_type(ScriptHashType::Data1.into())
-        .args(Bytes::from(ACCOUNT1_ARG.0.to_vec()).pack())
+fn test_balance_with_metrics_sink() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let aggregator = SelectionMetricsAggregator::new();
+    let mut balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
+    balancer.set_max_fee(Some(ONE_CKB));
+    balancer.set_metrics_sink(Some(Arc::new(aggregator.clone())));
+
+    // With change
+    let ctx = init_context(Vec::new(), vec![(sender.clone(), Some(300 * ONE_CKB))]);
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver.clone())
+        .build();
+    let base_tx = TransactionBuilder::default()
+        .output(output)
+        .output_data(Bytes::default().pack())
+        .build();
+    let mut cell_collector = ctx.to_live_cells_context();
+    let tx =
+        balance_tx_capacity(&base_tx, &balancer, &mut cell_collector, &ctx, &ctx, &ctx).unwrap();
+    let change_capacity: u64 = tx.output(1).unwrap().capacity().unpack();
+    let metrics = aggregator.snapshot();
+    assert_eq!(metrics.builds, 1);
+    assert_eq!(metrics.selected_inputs, 1);
+    assert_eq!(metrics.changes, 1);
+    assert_eq!(metrics.change_capacity, change_capacity);
+    assert_eq!(metrics.dust_builds, 0);
+
+    // The small change is paid as fee
+    let ctx = init_context(Vec::new(), vec![(sender, Some(200 * ONE_CKB))]);
+    let output = CellOutput::new_builder()
+        .capacity((200 * ONE_CKB - ONE_CKB / 10).pack())
+        .lock(receiver)
         .build();
+    let base_tx = TransactionBuilder::default()
+        .output(output)
+        .output_data(Bytes::default().pack())
+        .build();
+    let mut cell_collector = ctx.to_live_cells_context();
+    let tx =
+        balance_tx_capacity(&base_tx, &balancer, &mut cell_collector, &ctx, &ctx, &ctx).unwrap();
+    assert_eq!(tx.outputs().len(), 1);
+    let metrics = aggregator.reset();
+    assert_eq!(metrics.builds, 2);
+    assert_eq!(metrics.changes, 1);
+    assert_eq!(metrics.dust_builds, 1);
+    assert!(metrics.dust_as_fee > 0);
+    assert_eq!(metrics.fee_overpayment, metrics.dust_as_fee);
+    assert_eq!(aggregator.snapshot().builds, 0);
+}
+
+#[test]
+fn test_estimate_required_capacity() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
     let receiver = build_sighash_script(ACCOUNT2_ARG);
     let ctx = init_context(
-        vec![(ACP_BIN, true)],
+        Vec::new(),
         vec![
             (sender.clone(), Some(100 * ONE_CKB)),
             (sender.clone(), Some(200 * ONE_CKB)),
-            (sender.clone(), Some(300 * ONE_CKB)),
         ],
     );
 
@@ -271,195 +509,4641 @@ fn test_transfer_from_acp() {
         .capacity((120 * ONE_CKB).pack())
         .lock(receiver)
         .build();
-    let builder = CapacityTransferBuilder::new(vec![(output.clone(), Bytes::default())]);
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
     let placeholder_witness = WitnessArgs::new_builder()
         .lock(Some(Bytes::from(vec![0u8; 65])).pack())
         .build();
-    let balancer =
-        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
-
-    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
-    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
-    let script_unlocker = AcpUnlocker::from(Box::new(signer) as Box<_>);
-    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
-    unlockers.insert(ScriptId::new_data1(data_hash), Box::new(script_unlocker));
+    let balancer = CapacityBalancer::new_simple(sender, placeholder_witness, FEE_RATE);
 
     let mut cell_collector = ctx.to_live_cells_context();
-    let (tx, locked_groups) = builder
-        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
-        .unwrap();
+    let estimate = estimate_required_capacity(
+        &builder,
+        &balancer,
+        &cell_collector,
+        &ctx,
+        &ctx,
+        &ctx,
+        &HashMap::new(),
+    )
+    .unwrap();
+    assert_eq!(estimate.outputs_capacity, 120 * ONE_CKB);
+    assert_eq!(estimate.required_capacity, 120 * ONE_CKB + estimate.fee);
+    assert_eq!(estimate.change_capacity, 61 * ONE_CKB);
+    // No cell is collected
+    assert!(cell_collector.used_inputs.is_empty());
 
-    assert!(locked_groups.is_empty());
-    assert_eq!(tx.header_deps().len(), 0);
-    assert_eq!(tx.cell_deps().len(), 1);
+    let tx = builder
+        .build_balanced(
+            &mut cell_collector,
+            &ctx,
+            &ctx,
+            &ctx,
+            &balancer,
+            &HashMap::new(),
+        )
+        .unwrap();
     assert_eq!(tx.inputs().len(), 2);
-    for out_point in tx.input_pts_iter() {
-        assert_eq!(ctx.get_input(&out_point).unwrap().0.lock(), sender);
-    }
-    assert_eq!(tx.outputs().len(), 2);
-    assert_eq!(tx.output(0).unwrap(), output);
-    assert_eq!(tx.output(1).unwrap().lock(), sender);
-    let witnesses = tx
-        .witnesses()
-        .into_iter()
-        .map(|w| w.raw_data())
-        .collect::<Vec<_>>();
-    assert_eq!(witnesses.len(), 2);
-    assert_eq!(witnesses[0].len(), placeholder_witness.as_slice().len());
-    assert_eq!(witnesses[1].len(), 0);
-    ctx.verify(tx, FEE_RATE).unwrap();
+    let fee = tx_fee(tx, &ctx, &ctx).unwrap();
+    // One more input than the estimate
+    let max_fee = estimate.fee + estimate.fee_per_input + estimate.change_fee + 2;
+    assert!(
+        (estimate.fee..=max_fee).contains(&fee),
+        "{} {:?}",
+        fee,
+        estimate
+    );
 }
 
 #[test]
-fn test_transfer_to_acp() {
-    let data_hash = H256::from(blake2b_256(ACP_BIN));
-    let sender = build_sighash_script(ACCOUNT1_ARG);
-    let receiver = Script::new_builder()
-        .code_hash(data_hash.pack())
-        .hash_type(ScriptHashType::Data1.into())
-        .args(Bytes::from(ACCOUNT2_ARG.0.to_vec()).pack())
-        .build();
+fn test_balance_partial() {
+    let sender1 = build_sighash_script(ACCOUNT1_ARG);
+    let sender2 = build_sighash_script(ACCOUNT2_ARG);
+    let receiver = build_sighash_script(ACCOUNT3_ARG);
     let ctx = init_context(
-        vec![(ACP_BIN, true)],
+        Vec::new(),
         vec![
-            (sender.clone(), Some(100 * ONE_CKB)),
-            (sender.clone(), Some(200 * ONE_CKB)),
-            (sender.clone(), Some(300 * ONE_CKB)),
-            (receiver.clone(), Some(99 * ONE_CKB)),
+            (sender1.clone(), Some(100 * ONE_CKB)),
+            (sender2.clone(), Some(200 * ONE_CKB)),
         ],
     );
 
-    let acp_receiver = AcpTransferReceiver::new(receiver.clone(), 150 * ONE_CKB);
-    let builder = AcpTransferBuilder::new(vec![acp_receiver]);
+    let output = CellOutput::new_builder()
+        .capacity((150 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let base_tx = TransactionBuilder::default()
+        .output(output)
+        .output_data(Bytes::default().pack())
+        .build();
     let placeholder_witness = WitnessArgs::new_builder()
         .lock(Some(Bytes::from(vec![0u8; 65])).pack())
         .build();
-    let balancer =
-        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
-
-    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
-    let signer1 = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
-    let sighash_unlocker = AcpUnlocker::from(Box::new(signer1) as Box<_>);
-    let acp_unlocker = AcpUnlocker::from(Box::<SecpCkbRawKeySigner>::default() as Box<_>);
-    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
-    unlockers.insert(
-        ScriptId::new_type(SIGHASH_TYPE_HASH),
-        Box::new(sighash_unlocker),
-    );
-    unlockers.insert(ScriptId::new_data1(data_hash), Box::new(acp_unlocker));
+    let balancer1 = CapacityBalancer::new_simple(sender1, placeholder_witness.clone(), FEE_RATE);
+    let balancer2 = CapacityBalancer::new_simple(sender2.clone(), placeholder_witness, FEE_RATE);
 
     let mut cell_collector = ctx.to_live_cells_context();
-    let (tx, locked_groups) = builder
-        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
-        .unwrap();
+    let err = balance_tx_capacity(
+        &base_tx,
+        &balancer1,
+        &mut cell_collector.clone(),
+        &ctx,
+        &ctx,
+        &ctx,
+    )
+    .unwrap_err();
+    assert!(matches!(err, BalanceTxCapacityError::CapacityNotEnough(_)));
 
-    assert!(locked_groups.is_empty());
-    assert_eq!(tx.header_deps().len(), 0);
-    assert_eq!(tx.cell_deps().len(), 2);
-    assert_eq!(tx.inputs().len(), 3);
-    let input_cells = vec![
-        CellOutput::new_builder()
-            .capacity((99 * ONE_CKB).pack())
-            .lock(receiver.clone())
-            .build(),
-        CellOutput::new_builder()
-            .capacity((100 * ONE_CKB).pack())
-            .lock(sender.clone())
-            .build(),
-        CellOutput::new_builder()
-            .capacity((200 * ONE_CKB).pack())
-            .lock(sender.clone())
-            .build(),
-    ];
-    for (idx, out_point) in tx.input_pts_iter().enumerate() {
-        assert_eq!(ctx.get_input(&out_point).unwrap().0, input_cells[idx]);
+    // The first participant funds what it can
+    let (partial_tx, missing) =
+        balance_tx_capacity_partial(&base_tx, &balancer1, &mut cell_collector, &ctx, &ctx, &ctx)
+            .unwrap();
+    assert_eq!(partial_tx.inputs().len(), 1);
+    assert_eq!(partial_tx.outputs().len(), 1);
+    assert!(missing > 50 * ONE_CKB && missing < 51 * ONE_CKB);
+    match tx_fee(partial_tx.clone(), &ctx, &ctx) {
+        Err(TransactionFeeError::CapacityOverflow(delta)) => assert!(delta < missing),
+        other => panic!("unexpected fee: {:?}", other),
     }
+
+    // The second participant supplies the rest
+    let (tx, missing) = balance_tx_capacity_partial(
+        &partial_tx,
+        &balancer2,
+        &mut cell_collector,
+        &ctx,
+        &ctx,
+        &ctx,
+    )
+    .unwrap();
+    assert_eq!(missing, 0);
+    assert_eq!(tx.inputs().len(), 2);
     assert_eq!(tx.outputs().len(), 2);
-    let acp_output = CellOutput::new_builder()
-        .capacity(((99 + 150) * ONE_CKB).pack())
-        .lock(receiver)
-        .build();
-    assert_eq!(tx.output(0).unwrap(), acp_output);
-    assert_eq!(tx.output(1).unwrap().lock(), sender);
-    let witnesses = tx
-        .witnesses()
-        .into_iter()
-        .map(|w| w.raw_data())
-        .collect::<Vec<_>>();
-    assert_eq!(witnesses.len(), 3);
-    assert_eq!(witnesses[0].len(), 0);
-    assert_eq!(witnesses[1].len(), placeholder_witness.as_slice().len());
-    assert_eq!(witnesses[2].len(), 0);
-    ctx.verify(tx, FEE_RATE).unwrap();
+    assert_eq!(tx.output(1).unwrap().lock(), sender2);
+    assert!(tx_fee(tx, &ctx, &ctx).is_ok());
 }
 
 #[test]
-fn test_cheque_claim() {
-    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
-    let cheque_data_hash = H256::from(blake2b_256(CHEQUE_BIN));
-    let sender = build_sighash_script(ACCOUNT1_ARG);
+fn test_transfer_from_frozen_lock_script() {
+    // The process wide registry is shared by the tests, use a key not used by
+    // the other tests.
+    let sender_key = secp256k1::SecretKey::from_slice(&[0x5a; 32]).unwrap();
+    let pubkey = secp256k1::PublicKey::from_secret_key(&crate::SECP256K1, &sender_key);
+    let sender = build_sighash_script(blake160(&pubkey.serialize()));
     let receiver = build_sighash_script(ACCOUNT2_ARG);
-    let cheque_script = build_cheque_script(&sender, &receiver, cheque_data_hash.clone());
-    let type_script = Script::new_builder()
-        .code_hash(sudt_data_hash.pack())
-        .hash_type(ScriptHashType::Data1.into())
-        .args(Bytes::from(vec![9u8; 32]).pack())
-        .build();
-    let mut ctx = init_context(
-        vec![(CHEQUE_BIN, true), (SUDT_BIN, false)],
-        vec![
-            (receiver.clone(), Some(100 * ONE_CKB)),
-            (receiver.clone(), Some(200 * ONE_CKB)),
-        ],
-    );
+    let ctx = init_context(Vec::new(), vec![(sender.clone(), Some(300 * ONE_CKB))]);
 
-    let receiver_input = CellInput::new(random_out_point(), 0);
-    let receiver_output = CellOutput::new_builder()
-        .capacity((200 * ONE_CKB).pack())
-        .lock(receiver.clone())
-        .type_(Some(type_script.clone()).pack())
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
         .build();
-    let receiver_data = Bytes::from(1000u128.to_le_bytes().to_vec());
-    ctx.add_live_cell(
-        receiver_input.clone(),
-        receiver_output.clone(),
-        receiver_data,
-        None,
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let mut balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
+    let freeze_registry = Arc::new(FreezeRegistry::default());
+    balancer.set_freeze_registry(Some(freeze_registry.clone()));
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![sender_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
     );
 
-    let cheque_input = CellInput::new(random_out_point(), 0);
-    let cheque_output = CellOutput::new_builder()
-        .capacity((220 * ONE_CKB).pack())
-        .lock(cheque_script)
+    // Balanced before the freeze
+    let mut cell_collector = ctx.to_live_cells_context();
+    let balanced_tx = builder
+        .build_balanced(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+
+    // Frozen in the registry of the balancer only
+    freeze_registry.freeze_lock_script(&sender, "key compromised");
+    let lock_hash: H256 = sender.calc_script_hash().unpack();
+    assert_eq!(
+        freeze_registry.frozen_reason(&lock_hash),
+        Some("key compromised".to_string())
+    );
+    assert_eq!(frozen_reason(&lock_hash), None);
+    let mut cell_collector = ctx.to_live_cells_context();
+    let err = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        TxBuilderError::BalanceCapacity(BalanceTxCapacityError::FrozenLockScript(ref hash, _))
+            if hash == &lock_hash
+    ));
+    let err =
+        unlock_tx_with_freeze_registry(balanced_tx.clone(), &ctx, &unlockers, &freeze_registry)
+            .unwrap_err();
+    assert!(matches!(err, UnlockError::FrozenLockScript(ref hash, _) if hash == &lock_hash));
+    assert!(unlock_tx(balanced_tx.clone(), &ctx, &unlockers).is_ok());
+
+    // The base transaction spending from the frozen lock script is refused
+    // before balancing
+    struct BaseTxBuilder(TransactionView);
+    impl TxBuilder for BaseTxBuilder {
+        fn build_base(
+            &self,
+            _cell_collector: &mut dyn CellCollector,
+            _cell_dep_resolver: &dyn CellDepResolver,
+            _header_dep_resolver: &dyn HeaderDepResolver,
+            _tx_dep_provider: &dyn TransactionDependencyProvider,
+        ) -> Result<TransactionView, TxBuilderError> {
+            Ok(self.0.clone())
+        }
+    }
+    let base_builder = BaseTxBuilder(
+        TransactionBuilder::default()
+            .input(ctx.inputs[0].input.clone())
+            .output(balanced_tx.output(0).unwrap())
+            .output_data(Bytes::default().pack())
+            .build(),
+    );
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let mut other_balancer = CapacityBalancer::new_simple(
+        build_sighash_script(ACCOUNT1_ARG),
+        placeholder_witness,
+        FEE_RATE,
+    );
+    other_balancer.set_freeze_registry(Some(freeze_registry.clone()));
+    let mut cell_collector = ctx.to_live_cells_context();
+    let err = base_builder
+        .build_unlocked(
+            &mut cell_collector,
+            &ctx,
+            &ctx,
+            &ctx,
+            &other_balancer,
+            &unlockers,
+        )
+        .unwrap_err();
+    assert!(matches!(err, TxBuilderError::FrozenLockScript(ref hash, _) if hash == &lock_hash));
+
+    assert!(freeze_registry.unfreeze_lock_script(&sender));
+    assert!(!freeze_registry.unfreeze_lock_script(&sender));
+    let (tx, locked_groups) =
+        unlock_tx_with_freeze_registry(balanced_tx.clone(), &ctx, &unlockers, &freeze_registry)
+            .unwrap();
+    assert!(locked_groups.is_empty());
+    ctx.verify(tx, FEE_RATE).unwrap();
+
+    // The process wide registry
+    freeze_lock_script(&sender, "maintenance");
+    assert_eq!(frozen_reason(&lock_hash), Some("maintenance".to_string()));
+    let err = unlock_tx(balanced_tx.clone(), &ctx, &unlockers).unwrap_err();
+    assert!(matches!(err, UnlockError::FrozenLockScript(ref hash, _) if hash == &lock_hash));
+    assert!(unfreeze_lock_script(&sender));
+    assert!(unlock_tx(balanced_tx, &ctx, &unlockers).is_ok());
+}
+
+#[test]
+fn test_recovery_plan() {
+    let acp_data_hash = H256::from(blake2b_256(ACP_BIN));
+    let acp_script_id = ScriptId::new_data1(acp_data_hash);
+    let sighash_lock = build_sighash_script(ACCOUNT1_ARG);
+    let acp_lock = build_acp_script(&acp_script_id, &ACCOUNT1_ARG, None, None).unwrap();
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(
+        vec![(ACP_BIN, true)],
+        vec![
+            (sighash_lock.clone(), Some(100 * ONE_CKB)),
+            (sighash_lock.clone(), Some(150 * ONE_CKB)),
+            (acp_lock.clone(), Some(200 * ONE_CKB)),
+            (receiver.clone(), Some(300 * ONE_CKB)),
+        ],
+    );
+
+    let multisig_config = MultisigConfig::new_with(vec![ACCOUNT1_ARG, ACCOUNT2_ARG], 0, 1).unwrap();
+    let configs_path = std::env::temp_dir().join(format!(
+        "ckb-sdk-recovery-multisig-{}.json",
+        std::process::id()
+    ));
+    std::fs::write(
+        &configs_path,
+        serde_json::to_string(&vec![multisig_config.clone()]).unwrap(),
+    )
+    .unwrap();
+    let multisig_configs = load_multisig_configs(&configs_path).unwrap();
+    std::fs::remove_file(&configs_path).unwrap();
+    assert_eq!(multisig_configs, vec![multisig_config]);
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let mut scanner = RecoveryScanner::new(vec![account1_key]);
+    scanner.multisig_configs = multisig_configs;
+    scanner.acp_script_id = Some(acp_script_id);
+    let names: Vec<_> = scanner.locks().iter().map(|lock| lock.name()).collect();
+    assert_eq!(names, vec!["sighash", "acp", "multisig"]);
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let plan = scanner
+        .build_plan(receiver.clone(), FEE_RATE, &mut cell_collector, &ctx, &ctx)
+        .unwrap();
+    assert_eq!(plan.found.len(), 2);
+    assert_eq!(plan.found[0].lock_script, sighash_lock);
+    assert_eq!(plan.found[0].cells.len(), 2);
+    assert_eq!(plan.found[1].lock_script, acp_lock);
+    assert_eq!(plan.total_capacity(), 450 * ONE_CKB);
+    assert_eq!(plan.txs.len(), 2);
+    for (tx, locked_groups) in plan.txs {
+        assert!(locked_groups.is_empty());
+        assert_eq!(tx.outputs().len(), 1);
+        assert_eq!(tx.output(0).unwrap().lock(), receiver);
+        ctx.verify(tx, FEE_RATE).unwrap();
+    }
+}
+
+#[cfg(feature = "async-tx-builder")]
+async fn build_async_transfer(
+    ctx: Context,
+) -> Result<(TransactionView, TransactionView), TxBuilderError> {
+    use crate::tx_builder::asynchronous::AsyncTxBuilder;
+
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(sender, placeholder_witness, FEE_RATE);
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let unlockers = move || {
+        let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+        let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+        let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+        unlockers.insert(
+            ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+            Box::new(script_unlocker),
+        );
+        unlockers
+    };
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, _) = builder.build_unlocked(
+        &mut cell_collector,
+        &ctx,
+        &ctx,
+        &ctx,
+        &balancer,
+        &unlockers(),
+    )?;
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (async_tx, locked_groups) = builder
+        .build_unlocked_async(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .await?;
+    assert!(locked_groups.is_empty());
+    Ok((tx, async_tx))
+}
+
+#[cfg(feature = "async-tx-builder")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_async_tx_builder() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+        ],
+    );
+    // The futures are `Send`, so the building can be spawned
+    let (tx, async_tx) = tokio::spawn(build_async_transfer(ctx.clone()))
+        .await
+        .unwrap()
+        .unwrap();
+    // The async pipeline shares the balancing and unlocking of the blocking one
+    assert_eq!(async_tx, tx);
+    ctx.verify(async_tx, FEE_RATE).unwrap();
+}
+
+#[cfg(feature = "async-tx-builder")]
+#[tokio::test(flavor = "current_thread")]
+async fn test_async_tx_builder_current_thread() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let ctx = init_context(Vec::new(), vec![(sender, Some(300 * ONE_CKB))]);
+    // An error instead of the `block_in_place` panic
+    assert!(matches!(
+        build_async_transfer(ctx).await,
+        Err(TxBuilderError::Other(_))
+    ));
+}
+
+#[cfg(feature = "async-tx-builder")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_async_balance_fail_fast_with_max_capacity() {
+    use crate::tx_builder::asynchronous::balance_tx_capacity_async;
+
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+        ],
+    );
+    let output = CellOutput::new_builder()
+        .capacity((1000 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let base_tx = TransactionBuilder::default()
+        .output(output)
+        .output_data(Bytes::default().pack())
+        .build();
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(sender, placeholder_witness, FEE_RATE);
+
+    // The max total capacity of the async collector is used by the balancer
+    let mut cell_collector = IndexedLiveCellsContext::new(ctx.to_live_cells_context());
+    let err = balance_tx_capacity_async(&base_tx, &balancer, &mut cell_collector, &ctx, &ctx, &ctx)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        TxBuilderError::BalanceCapacity(BalanceTxCapacityError::CapacityNotEnough(_))
+    ));
+    assert_eq!(cell_collector.max_capacity_queries(), 1);
+    assert!(cell_collector.0.used_inputs.is_empty());
+}
+
+#[test]
+fn test_channel_funding_with_refund() {
+    let cfg = MultisigConfig::new_with(vec![ACCOUNT1_ARG, ACCOUNT2_ARG], 0, 2).unwrap();
+    let funding_lock = build_multisig_script(&cfg);
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let mut ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+        ],
+    );
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let account2_key = secp256k1::SecretKey::from_slice(ACCOUNT2_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut funding_unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    funding_unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+    let refund_unlockers = build_multisig_unlockers(account1_key, cfg.clone());
+
+    let refund_since = Since::new(SinceType::BlockNumber, 100, true).value();
+    let refund_output = CellOutput::new_builder()
+        .capacity((149 * ONE_CKB).pack())
+        .lock(sender.clone())
+        .build();
+    let refund = ChannelRefund {
+        since: refund_since,
+        outputs: vec![(refund_output.clone(), Bytes::default())],
+    };
+    let builder = ChannelFundingBuilder::new(funding_lock.clone(), 150 * ONE_CKB);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let mut balancer = CapacityBalancer::new_simple(sender, placeholder_witness, FEE_RATE);
+
+    // The funding output is found wherever the shuffle pass moves it
+    let mut funding_indices = HashSet::new();
+    for seed in 0..8 {
+        balancer.set_shuffle(Some(ShuffleMode::Seeded(seed)));
+        let mut cell_collector = ctx.to_live_cells_context();
+        let pair = builder
+            .build_with_refund(
+                &refund,
+                &mut cell_collector,
+                &ctx,
+                &ctx,
+                &ctx,
+                &balancer,
+                &funding_unlockers,
+                &refund_unlockers,
+            )
+            .unwrap();
+        let funding_index: u32 = pair.funding_out_point.index().unpack();
+        assert_eq!(pair.funding_out_point.tx_hash(), pair.funding_tx.hash());
+        assert_eq!(
+            builder.funding_index(&pair.funding_tx).unwrap(),
+            funding_index as usize
+        );
+        let funding_output = pair.funding_tx.output(funding_index as usize).unwrap();
+        assert_eq!(funding_output.lock(), funding_lock);
+        assert_eq!(
+            Unpack::<u64>::unpack(&funding_output.capacity()),
+            150 * ONE_CKB
+        );
+        funding_indices.insert(funding_index);
+
+        // The refund transaction spends the funding cell after the timelock,
+        // the counterparty's signature is still missing
+        assert_eq!(pair.refund_tx.inputs().len(), 1);
+        let refund_input = pair.refund_tx.inputs().get(0).unwrap();
+        assert_eq!(refund_input.previous_output(), pair.funding_out_point);
+        assert_eq!(Unpack::<u64>::unpack(&refund_input.since()), refund_since);
+        assert_eq!(pair.refund_tx.output(0).unwrap(), refund_output);
+        assert!(pair.refund_not_unlocked.is_empty());
+
+        if seed > 0 {
+            continue;
+        }
+        let (funding_tx, locked_groups) =
+            unlock_tx(pair.funding_tx.clone(), &ctx, &funding_unlockers).unwrap();
+        assert!(locked_groups.is_empty());
+        ctx.verify(funding_tx, FEE_RATE).unwrap();
+
+        ctx.add_live_cell(
+            CellInput::new(pair.funding_out_point.clone(), 0),
+            funding_output,
+            Bytes::default(),
+            None,
+        );
+        assert!(ctx.verify(pair.refund_tx.clone(), FEE_RATE).is_err());
+        let counterparty_unlockers = build_multisig_unlockers(account2_key, cfg.clone());
+        let (refund_tx, locked_groups) =
+            unlock_tx(pair.refund_tx, &ctx, &counterparty_unlockers).unwrap();
+        assert!(locked_groups.is_empty());
+        ctx.verify(refund_tx, FEE_RATE).unwrap();
+    }
+    assert!(funding_indices.len() > 1);
+
+    // The refund outputs can not spend more than the funding cell
+    let refund = ChannelRefund {
+        since: refund_since,
+        outputs: vec![(
+            refund_output
+                .as_builder()
+                .capacity((151 * ONE_CKB).pack())
+                .build(),
+            Bytes::default(),
+        )],
+    };
+    let mut cell_collector = ctx.to_live_cells_context();
+    assert!(matches!(
+        builder.build_with_refund(
+            &refund,
+            &mut cell_collector,
+            &ctx,
+            &ctx,
+            &ctx,
+            &balancer,
+            &funding_unlockers,
+            &refund_unlockers,
+        ),
+        Err(TxBuilderError::InvalidParameter(_))
+    ));
+
+    // The refund transaction must pay the fee
+    let refund = ChannelRefund {
+        since: refund_since,
+        outputs: vec![(
+            refund_output
+                .as_builder()
+                .capacity((150 * ONE_CKB).pack())
+                .build(),
+            Bytes::default(),
+        )],
+    };
+    let mut cell_collector = ctx.to_live_cells_context();
+    assert!(matches!(
+        builder.build_with_refund(
+            &refund,
+            &mut cell_collector,
+            &ctx,
+            &ctx,
+            &ctx,
+            &balancer,
+            &funding_unlockers,
+            &refund_unlockers,
+        ),
+        Err(TxBuilderError::InvalidParameter(_))
+    ));
+
+    // The funding output is ambiguous
+    let funding_output = CellOutput::new_builder()
+        .capacity((150 * ONE_CKB).pack())
+        .lock(funding_lock)
+        .build();
+    let funding_tx = TransactionBuilder::default()
+        .outputs(vec![funding_output.clone(), funding_output])
+        .outputs_data(vec![Bytes::default().pack(), Bytes::default().pack()])
+        .build();
+    assert!(matches!(
+        builder.funding_index(&funding_tx),
+        Err(TxBuilderError::Other(_))
+    ));
+}
+
+#[test]
+fn test_pending_tx_dependency_provider() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let ctx = init_context(Vec::new(), vec![(sender.clone(), Some(100 * ONE_CKB))]);
+    let live_out_point = ctx.inputs[0].input.previous_output();
+
+    let output = CellOutput::new_builder()
+        .capacity((80 * ONE_CKB).pack())
+        .lock(sender)
+        .build();
+    let pending_tx = TransactionBuilder::default()
+        .input(CellInput::new(live_out_point.clone(), 0))
+        .output(output.clone())
+        .output_data(Bytes::from("pending").pack())
+        .build();
+    let provider = PendingTxDependencyProvider {
+        inner: &ctx,
+        tx: &pending_tx,
+    };
+
+    let pending_out_point = OutPoint::new(pending_tx.hash(), 0);
+    assert_eq!(
+        provider.get_transaction(&pending_tx.hash()).unwrap().hash(),
+        pending_tx.hash()
+    );
+    assert_eq!(provider.get_cell(&pending_out_point).unwrap(), output);
+    assert_eq!(
+        provider.get_cell_data(&pending_out_point).unwrap(),
+        Bytes::from("pending")
+    );
+    let missing_out_point = OutPoint::new(pending_tx.hash(), 1);
+    assert!(matches!(
+        provider.get_cell(&missing_out_point),
+        Err(TransactionDependencyError::NotFound(_))
+    ));
+    assert!(matches!(
+        provider.get_cell_data(&missing_out_point),
+        Err(TransactionDependencyError::NotFound(_))
+    ));
+
+    // Other cells are resolved by the inner provider
+    assert_eq!(
+        provider.get_cell(&live_out_point).unwrap(),
+        ctx.get_cell(&live_out_point).unwrap()
+    );
+    assert!(provider.get_cell(&random_out_point()).is_err());
+}
+
+fn build_htlc_owner_cell(ctx: &mut Context, lock: &Script) -> CellInput {
+    // The type script keeps the owner cell from being collected again by the
+    // balancer
+    let type_script = Script::new_builder()
+        .code_hash(H256::from(blake2b_256(ALWAYS_SUCCESS_BIN)).pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .build();
+    let owner_input = CellInput::new(random_out_point(), 0);
+    let owner_output = CellOutput::new_builder()
+        .capacity((100 * ONE_CKB).pack())
+        .lock(lock.clone())
         .type_(Some(type_script).pack())
         .build();
-    let cheque_data = Bytes::from(500u128.to_le_bytes().to_vec());
+    ctx.add_live_cell(owner_input.clone(), owner_output, Bytes::default(), None);
+    owner_input
+}
+
+#[test]
+fn test_htlc_claim_and_refund() {
+    // The always success script stands in for the HTLC lock, the transactions
+    // are verified for the owner signatures and the fee
+    let htlc_script_id = ScriptId::new_data1(H256::from(blake2b_256(ALWAYS_SUCCESS_BIN)));
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let mut ctx = init_context(
+        vec![(ALWAYS_SUCCESS_BIN, false)],
+        vec![
+            (sender.clone(), Some(300 * ONE_CKB)),
+            (receiver.clone(), Some(100 * ONE_CKB)),
+        ],
+    );
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let account2_key = secp256k1::SecretKey::from_slice(ACCOUNT2_KEY.as_bytes()).unwrap();
+    let build_unlockers = |key: secp256k1::SecretKey, action: Option<HtlcAction>| {
+        let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![key]);
+        let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+        let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+        unlockers.insert(
+            ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+            Box::new(script_unlocker),
+        );
+        if let Some(action) = action {
+            unlockers.insert(htlc_script_id.clone(), Box::new(HtlcUnlocker::new(action)));
+        }
+        unlockers
+    };
+
+    let preimage = Bytes::from("htlc preimage");
+    let refund_since = Since::new(SinceType::BlockNumber, 100, true).value();
+    let lock_hash_prefix =
+        |lock: &Script| H160::from_slice(&lock.calc_script_hash().as_slice()[0..20]).unwrap();
+    let args = HtlcArgs::new(
+        lock_hash_prefix(&receiver),
+        lock_hash_prefix(&sender),
+        HtlcArgs::hash_preimage(preimage.as_ref()),
+        refund_since,
+    );
+    let htlc_lock = build_htlc_script(&htlc_script_id, &args);
+
+    // Create the hashlocked cell
+    let builder = HtlcCreateBuilder::new(htlc_script_id.clone(), args.clone(), 150 * ONE_CKB);
+    let balancer =
+        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (create_tx, locked_groups) = builder
+        .build_unlocked(
+            &mut cell_collector,
+            &ctx,
+            &ctx,
+            &ctx,
+            &balancer,
+            &build_unlockers(account1_key, None),
+        )
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    let htlc_output = create_tx.output(0).unwrap();
+    assert_eq!(htlc_output.lock(), htlc_lock);
+    assert_eq!(
+        Unpack::<u64>::unpack(&htlc_output.capacity()),
+        150 * ONE_CKB
+    );
+    ctx.verify(create_tx.clone(), FEE_RATE).unwrap();
+    let htlc_input = CellInput::new(OutPoint::new(create_tx.hash(), 0), 0);
+    ctx.add_live_cell(htlc_input.clone(), htlc_output, Bytes::default(), None);
+    let htlc_out_point = htlc_input.previous_output();
+
+    // Claim by the receiver with the preimage
+    let receiver_input = build_htlc_owner_cell(&mut ctx, &receiver);
+    let wrong_claim = HtlcSpendBuilder::new(
+        vec![htlc_out_point.clone()],
+        receiver_input.clone(),
+        HtlcAction::Claim(Bytes::from("wrong preimage")),
+    );
+    let balancer =
+        CapacityBalancer::new_simple(receiver.clone(), placeholder_witness.clone(), FEE_RATE);
+    let claim_unlockers = build_unlockers(account2_key, Some(HtlcAction::Claim(preimage.clone())));
+    let mut cell_collector = ctx.to_live_cells_context();
+    assert!(matches!(
+        wrong_claim.build_unlocked(
+            &mut cell_collector,
+            &ctx,
+            &ctx,
+            &ctx,
+            &balancer,
+            &claim_unlockers
+        ),
+        Err(TxBuilderError::InvalidParameter(_))
+    ));
+    let claim = HtlcSpendBuilder::new(
+        vec![htlc_out_point.clone()],
+        receiver_input.clone(),
+        HtlcAction::Claim(preimage.clone()),
+    );
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (claim_tx, locked_groups) = claim
+        .build_unlocked(
+            &mut cell_collector,
+            &ctx,
+            &ctx,
+            &ctx,
+            &balancer,
+            &claim_unlockers,
+        )
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    let inputs: Vec<_> = claim_tx.inputs().into_iter().collect();
+    assert_eq!(inputs[0], htlc_input);
+    assert_eq!(inputs[1], receiver_input);
+    let witness =
+        WitnessArgs::from_slice(&claim_tx.witnesses().get(0).unwrap().raw_data()).unwrap();
+    assert_eq!(witness.lock().to_opt().unwrap().raw_data(), preimage);
+    let owner_output = claim_tx.output(0).unwrap();
+    assert_eq!(owner_output.lock(), receiver);
+    assert_eq!(
+        Unpack::<u64>::unpack(&owner_output.capacity()),
+        250 * ONE_CKB
+    );
+    ctx.verify(claim_tx, FEE_RATE).unwrap();
+
+    // Refund by the sender after the timelock
+    let sender_input = build_htlc_owner_cell(&mut ctx, &sender);
+    let balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
+    let refund_unlockers = build_unlockers(account1_key, Some(HtlcAction::Refund));
+    // The receiver can not refund
+    let mut cell_collector = ctx.to_live_cells_context();
+    assert!(matches!(
+        HtlcSpendBuilder::new(
+            vec![htlc_out_point.clone()],
+            receiver_input,
+            HtlcAction::Refund
+        )
+        .build_unlocked(
+            &mut cell_collector,
+            &ctx,
+            &ctx,
+            &ctx,
+            &balancer,
+            &refund_unlockers
+        ),
+        Err(TxBuilderError::InvalidParameter(_))
+    ));
+    let refund = HtlcSpendBuilder::new(
+        vec![htlc_out_point],
+        sender_input.clone(),
+        HtlcAction::Refund,
+    );
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (refund_tx, locked_groups) = refund
+        .build_unlocked(
+            &mut cell_collector,
+            &ctx,
+            &ctx,
+            &ctx,
+            &balancer,
+            &refund_unlockers,
+        )
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    let inputs: Vec<_> = refund_tx.inputs().into_iter().collect();
+    assert_eq!(inputs[0].previous_output(), htlc_input.previous_output());
+    assert_eq!(Unpack::<u64>::unpack(&inputs[0].since()), refund_since);
+    assert_eq!(inputs[1], sender_input);
+    assert_eq!(refund_tx.output(0).unwrap().lock(), sender);
+    ctx.verify(refund_tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_htlc_spend_capacity_overflow() {
+    let htlc_script_id = ScriptId::new_data1(H256::from(blake2b_256(ALWAYS_SUCCESS_BIN)));
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let mut ctx = init_context(vec![(ALWAYS_SUCCESS_BIN, false)], Vec::new());
+    let lock_hash_prefix =
+        |lock: &Script| H160::from_slice(&lock.calc_script_hash().as_slice()[0..20]).unwrap();
+    let preimage = Bytes::from("htlc preimage");
+    let args = HtlcArgs::new(
+        lock_hash_prefix(&receiver),
+        lock_hash_prefix(&sender),
+        HtlcArgs::hash_preimage(preimage.as_ref()),
+        Since::new(SinceType::BlockNumber, 100, true).value(),
+    );
+    let htlc_lock = build_htlc_script(&htlc_script_id, &args);
+    let htlc_out_points = (0..2)
+        .map(|_| {
+            let htlc_input = CellInput::new(random_out_point(), 0);
+            let htlc_output = CellOutput::new_builder()
+                .capacity((u64::MAX / 2 + 1).pack())
+                .lock(htlc_lock.clone())
+                .build();
+            ctx.add_live_cell(htlc_input.clone(), htlc_output, Bytes::default(), None);
+            htlc_input.previous_output()
+        })
+        .collect::<Vec<_>>();
+    let receiver_input = build_htlc_owner_cell(&mut ctx, &receiver);
+
+    let claim = HtlcSpendBuilder::new(
+        htlc_out_points[0..1].to_vec(),
+        receiver_input.clone(),
+        HtlcAction::Claim(preimage.clone()),
+    );
+    assert!(claim
+        .build_base(&mut ctx.to_live_cells_context(), &ctx, &ctx, &ctx)
+        .is_ok());
+    let claim = HtlcSpendBuilder::new(htlc_out_points, receiver_input, HtlcAction::Claim(preimage));
+    assert!(matches!(
+        claim.build_base(&mut ctx.to_live_cells_context(), &ctx, &ctx, &ctx),
+        Err(TxBuilderError::Other(_))
+    ));
+}
+
+#[test]
+fn test_htlc_unlocker() {
+    let htlc_script_id = ScriptId::new_data1(H256::from(blake2b_256(ALWAYS_SUCCESS_BIN)));
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let mut ctx = init_context(vec![(ALWAYS_SUCCESS_BIN, false)], Vec::new());
+    let preimage = Bytes::from("htlc preimage");
+    let refund_since = Since::new(SinceType::BlockNumber, 100, true).value();
+    let args = HtlcArgs::new(
+        H160::from_slice(&receiver.calc_script_hash().as_slice()[0..20]).unwrap(),
+        H160::from_slice(&[3u8; 20]).unwrap(),
+        HtlcArgs::hash_preimage(preimage.as_ref()),
+        refund_since,
+    );
+    let htlc_lock = build_htlc_script(&htlc_script_id, &args);
+    let htlc_input = CellInput::new(random_out_point(), 0);
     ctx.add_live_cell(
-        cheque_input.clone(),
-        cheque_output.clone(),
-        cheque_data,
+        htlc_input.clone(),
+        CellOutput::new_builder()
+            .capacity((150 * ONE_CKB).pack())
+            .lock(htlc_lock.clone())
+            .build(),
+        Bytes::default(),
         None,
     );
+    let receiver_input = CellInput::new(random_out_point(), 0);
+    ctx.add_live_cell(
+        receiver_input.clone(),
+        CellOutput::new_builder()
+            .capacity((100 * ONE_CKB).pack())
+            .lock(receiver)
+            .build(),
+        Bytes::default(),
+        None,
+    );
+    let tx = TransactionBuilder::default()
+        .input(htlc_input.clone())
+        .input(receiver_input.clone())
+        .build();
+    let mut script_group = ScriptGroup::from_lock_script(&htlc_lock);
+    script_group.input_indices = vec![0];
+
+    // The placeholder is the preimage itself, the refund path needs no witness
+    let claim_unlocker = HtlcUnlocker::new(HtlcAction::Claim(preimage.clone()));
+    assert!(claim_unlocker.match_args(&args.to_bytes()));
+    assert!(!claim_unlocker
+        .is_unlocked(&tx, &script_group, &ctx)
+        .unwrap());
+    let placeholder_tx = claim_unlocker
+        .fill_placeholder_witness(&tx, &script_group, &ctx)
+        .unwrap();
+    let witness =
+        WitnessArgs::from_slice(&placeholder_tx.witnesses().get(0).unwrap().raw_data()).unwrap();
+    assert_eq!(witness.lock().to_opt().unwrap().raw_data(), preimage);
+    let refund_unlocker = HtlcUnlocker::new(HtlcAction::Refund);
+    assert!(refund_unlocker.match_args(&args.to_bytes()));
+    assert_eq!(
+        refund_unlocker
+            .fill_placeholder_witness(&tx, &script_group, &ctx)
+            .unwrap()
+            .hash(),
+        tx.hash()
+    );
+
+    // Unlock replaces a wrong lock field
+    let wrong_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from("wrong preimage")).pack())
+        .build();
+    let wrong_tx = tx
+        .as_advanced_builder()
+        .witness(wrong_witness.as_bytes().pack())
+        .build();
+    assert!(!claim_unlocker
+        .is_unlocked(&wrong_tx, &script_group, &ctx)
+        .unwrap());
+    let unlocked_tx = claim_unlocker
+        .unlock(&wrong_tx, &script_group, &ctx)
+        .unwrap();
+    assert!(claim_unlocker
+        .is_unlocked(&unlocked_tx, &script_group, &ctx)
+        .unwrap());
+    assert_eq!(
+        unlocked_tx.witnesses().get(0).unwrap(),
+        placeholder_tx.witnesses().get(0).unwrap()
+    );
+
+    // The refund path requires the refund lock input and the since value
+    assert!(refund_unlocker
+        .is_unlocked(&tx, &script_group, &ctx)
+        .is_err());
+    let refund_tx = tx
+        .as_advanced_builder()
+        .set_inputs(vec![
+            CellInput::new(htlc_input.previous_output(), refund_since),
+            receiver_input,
+        ])
+        .build();
+    assert!(!refund_unlocker
+        .is_unlocked(&refund_tx, &script_group, &ctx)
+        .unwrap());
+    // The claim path requires a zero since
+    assert!(claim_unlocker
+        .is_unlocked(&refund_tx, &script_group, &ctx)
+        .is_err());
+}
+
+#[test]
+fn test_tx_chain_builder() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let mut ctx = init_context(Vec::new(), vec![(sender.clone(), Some(300 * ONE_CKB))]);
+
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let mut chain = TxChainBuilder::new(0);
+    let mut txs = Vec::new();
+    // The second transaction can only be balanced by the change of the first one
+    for capacity in [100, 120] {
+        let output = CellOutput::new_builder()
+            .capacity((capacity * ONE_CKB).pack())
+            .lock(receiver.clone())
+            .build();
+        let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+        let (tx, locked_groups) = chain
+            .build_next(
+                &builder,
+                &mut cell_collector,
+                &ctx,
+                &ctx,
+                &ctx,
+                &balancer,
+                &unlockers,
+            )
+            .unwrap();
+        assert!(locked_groups.is_empty());
+        txs.push(tx);
+    }
+    assert_eq!(chain.txs(), txs.as_slice());
+    assert_eq!(txs[1].inputs().len(), 1);
+    assert_eq!(
+        txs[1].inputs().get(0).unwrap().previous_output(),
+        chain.out_point(0, 1).unwrap()
+    );
+    assert!(chain.out_point(0, 2).is_none());
+
+    for tx in txs {
+        ctx.verify(tx.clone(), FEE_RATE).unwrap();
+        for (idx, (output, data)) in tx.outputs_with_data_iter().enumerate() {
+            let input = CellInput::new(OutPoint::new(tx.hash(), idx as u32), 0);
+            ctx.add_live_cell(input, output, data, None);
+        }
+    }
+}
+
+#[test]
+fn test_transfer_info_output() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(Vec::new(), vec![(sender.clone(), Some(200 * ONE_CKB))]);
+
+    let commitment = Bytes::from(vec![7u8; 32]);
+    // 8 + 53 + 32 bytes
+    let output = CellOutput::new_builder()
+        .capacity((93 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    assert!(is_info_output(&output, commitment.as_ref()));
+    assert!(!is_info_output(&output, &[]));
+    let mut builder = CapacityTransferBuilder::new(vec![(output.clone(), commitment.clone())]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.output(0).unwrap(), output);
+    assert_eq!(tx.outputs_data().get(0).unwrap().raw_data(), commitment);
+    assert_eq!(tx.output(1).unwrap().lock(), sender);
+    ctx.verify(tx, FEE_RATE).unwrap();
+
+    builder.set_allow_info_outputs(false);
+    let mut cell_collector = ctx.to_live_cells_context();
+    assert!(builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .is_err());
+}
+
+#[test]
+fn test_tx_amend_builder() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(200 * ONE_CKB)),
+            (sender.clone(), Some(300 * ONE_CKB)),
+        ],
+    );
+
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver.clone())
+        .build();
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let builder = CapacityTransferBuilder::new(vec![(output.clone(), Bytes::default())]);
+    let (signed_tx, _) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+
+    let extra_output = CellOutput::new_builder()
+        .capacity((100 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let mut builder = TxAmendBuilder::new(signed_tx.clone());
+    builder.add_output(extra_output.clone(), Bytes::default());
+    let base_tx = builder
+        .build_base(&mut cell_collector, &ctx, &ctx, &ctx)
+        .unwrap();
+    assert_eq!(base_tx.inputs(), signed_tx.inputs());
+    assert_eq!(base_tx.cell_deps().len(), 1);
+    assert_eq!(base_tx.outputs().len(), signed_tx.outputs().len() + 1);
+    assert!(base_tx.witnesses().into_iter().all(|witness| {
+        witness.raw_data().is_empty()
+            || WitnessArgs::from_slice(witness.raw_data().as_ref())
+                .unwrap()
+                .lock()
+                .is_none()
+    }));
+
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.inputs().len(), 2);
+    assert_eq!(tx.output(0).unwrap(), output);
+    assert_eq!(tx.output(2).unwrap(), extra_output);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_replace_by_fee() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(Vec::new(), vec![(sender.clone(), Some(300 * ONE_CKB))]);
+
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let builder = CapacityTransferBuilder::new(vec![(output.clone(), Bytes::default())]);
+    let (pending_tx, _) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    let original_fee = tx_fee(pending_tx.clone(), &ctx, &ctx).unwrap();
+
+    let builder = ReplaceByFeeBuilder::new(pending_tx.clone(), Some(1));
+    let (tx, locked_groups) = builder
+        .build_replacement(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.inputs(), pending_tx.inputs());
+    assert_eq!(tx.outputs().len(), 2);
+    assert_eq!(tx.output(0).unwrap(), output);
+    assert_eq!(tx.output(1).unwrap().lock(), sender);
+    let tx_size = tx.data().as_reader().serialized_size_in_block();
+    let fee = tx_fee(tx.clone(), &ctx, &ctx).unwrap();
+    assert!(fee >= builder.min_replace_fee(original_fee, tx_size));
+    assert_ne!(tx.hash(), pending_tx.hash());
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_balance_tx_capacity_exact() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(50 * ONE_CKB)),
+            (sender.clone(), Some(31 * ONE_CKB)),
+        ],
+    );
+
+    let output = CellOutput::new_builder()
+        .capacity((80 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output.clone(), Bytes::default())]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let base_tx = builder
+        .build_base(&mut cell_collector, &ctx, &ctx, &ctx)
+        .unwrap();
+    let selector = BranchAndBoundSelector::new(ONE_CKB);
+    let tx = balance_tx_capacity_exact(
+        &base_tx,
+        &balancer,
+        &selector,
+        &mut cell_collector,
+        &ctx,
+        &ctx,
+        &ctx,
+        0,
+    )
+    .unwrap()
+    .unwrap();
+    let (tx, locked_groups) = unlock_tx(tx, &ctx, &unlockers).unwrap();
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.inputs().len(), 2);
+    assert_eq!(tx.outputs().len(), 1);
+    assert_eq!(tx.output(0).unwrap(), output);
+    let fee = tx_fee(tx.clone(), &ctx, &ctx).unwrap();
+    assert_eq!(fee, ONE_CKB);
+    ctx.verify(tx, FEE_RATE).unwrap();
+
+    // no exact match without tolerance
+    let mut cell_collector = ctx.to_live_cells_context();
+    let selector = BranchAndBoundSelector::default();
+    assert!(balance_tx_capacity_exact(
+        &base_tx,
+        &balancer,
+        &selector,
+        &mut cell_collector,
+        &ctx,
+        &ctx,
+        &ctx,
+        0,
+    )
+    .unwrap()
+    .is_none());
+}
+
+#[test]
+fn test_unlock_tx_with_skip() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(Vec::new(), vec![(sender.clone(), Some(200 * ONE_CKB))]);
+
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let tx = builder
+        .build_balanced(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    #[allow(clippy::mutable_key_type)]
+    let skip_lock_hashes = vec![sender.calc_script_hash()].into_iter().collect();
+    let (unlocked_tx, not_unlocked) =
+        unlock_tx_with_skip(tx.clone(), &ctx, &unlockers, &skip_lock_hashes).unwrap();
+    assert!(not_unlocked.is_empty());
+    // the skipped group is left to the external signer
+    assert_eq!(unlocked_tx.witnesses(), tx.witnesses());
+
+    let (_, not_unlocked) = unlock_tx(tx.clone(), &ctx, &HashMap::default()).unwrap();
+    assert_eq!(not_unlocked.len(), 1);
+    let (_, not_matched) =
+        fill_placeholder_witnesses_with_skip(tx, &ctx, &HashMap::default(), &skip_lock_hashes)
+            .unwrap();
+    assert!(not_matched.is_empty());
+}
+
+#[test]
+fn test_change_split() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(Vec::new(), vec![(sender.clone(), Some(500 * ONE_CKB))]);
+
+    let output = CellOutput::new_builder()
+        .capacity((100 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output.clone(), Bytes::default())]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let mut balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
+    balancer.set_change_split(Some(ChangeSplit::new(3, 100 * ONE_CKB)));
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.outputs().len(), 4);
+    assert_eq!(tx.output(0).unwrap(), output);
+    for change in tx.outputs().into_iter().skip(1) {
+        assert_eq!(change.lock(), sender);
+        assert!(Unpack::<u64>::unpack(&change.capacity()) >= 100 * ONE_CKB);
+    }
+    ctx.verify(tx, FEE_RATE).unwrap();
+
+    // the change is not enough to split
+    balancer.set_change_split(Some(ChangeSplit::new(3, 200 * ONE_CKB)));
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, _) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert_eq!(tx.outputs().len(), 2);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_balance_with_udt_change() {
+    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let owner = build_sighash_script(H160::default());
+    let type_script = Script::new_builder()
+        .code_hash(sudt_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(owner.calc_script_hash().as_bytes().pack())
+        .build();
+    let mut ctx = init_context(
+        vec![(SUDT_BIN, false)],
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+        ],
+    );
+    let udt_input = CellInput::new(random_out_point(), 0);
+    let udt_output = CellOutput::new_builder()
+        .capacity((200 * ONE_CKB).pack())
+        .lock(sender.clone())
+        .type_(Some(type_script.clone()).pack())
+        .build();
+    ctx.add_live_cell(
+        udt_input.clone(),
+        udt_output,
+        Bytes::from(500u128.to_le_bytes().to_vec()),
+        None,
+    );
+
+    let receiver_output = CellOutput::new_builder()
+        .capacity((150 * ONE_CKB).pack())
+        .lock(receiver)
+        .type_(Some(type_script.clone()).pack())
+        .build();
+    let mut builder = TxAmendBuilder::new(TransactionBuilder::default().build());
+    builder.add_input(udt_input).add_output(
+        receiver_output.clone(),
+        Bytes::from(300u128.to_le_bytes().to_vec()),
+    );
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let mut balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
+    balancer.add_udt_change(UdtChange::new(type_script.clone(), None));
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.output(0).unwrap(), receiver_output);
+    let change_udt_output = tx.output(1).unwrap();
+    assert_eq!(change_udt_output.lock(), sender);
+    assert_eq!(
+        change_udt_output.type_().to_opt(),
+        Some(type_script.clone())
+    );
+    assert_eq!(
+        tx.outputs_data().get(1).unwrap().raw_data(),
+        Bytes::from(200u128.to_le_bytes().to_vec())
+    );
+    ctx.verify(tx, FEE_RATE).unwrap();
+
+    // the outputs can not take more UDT than the inputs
+    let mut builder = TxAmendBuilder::new(TransactionBuilder::default().build());
+    builder.add_output(receiver_output, Bytes::from(300u128.to_le_bytes().to_vec()));
+    let err = builder
+        .build_balanced(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        TxBuilderError::BalanceCapacity(BalanceTxCapacityError::UdtAmountNotEnough(_, 0, 300))
+    ));
+}
+
+#[test]
+fn test_transfer_with_fee_rate_provider() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(Vec::new(), vec![(sender.clone(), Some(100 * ONE_CKB))]);
+
+    let output = CellOutput::new_builder()
+        .capacity((80 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let mut balancer = CapacityBalancer::new_simple(sender, placeholder_witness, FEE_RATE);
+    let high_fee_rate = FEE_RATE * 5;
+    balancer.set_fee_rate_provider(Some(Arc::new(FeeRate::from_u64(high_fee_rate))));
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    let tx_size = tx.data().as_reader().serialized_size_in_block() as u64;
+    let fee = tx_fee(tx.clone(), &ctx, &ctx).unwrap();
+    assert!(fee >= FeeRate::from_u64(high_fee_rate).fee(tx_size).as_u64());
+    ctx.verify(tx, high_fee_rate).unwrap();
+}
+
+#[test]
+fn test_transfer_send_max() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+        ],
+    );
+
+    let builder = CapacityTransferBuilder::new_send_max(receiver.clone());
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let mut balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
+    balancer.set_send_max(Some(0));
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.inputs().len(), 2);
+    for out_point in tx.input_pts_iter() {
+        assert_eq!(ctx.get_input(&out_point).unwrap().0.lock(), sender);
+    }
+    assert_eq!(tx.outputs().len(), 1);
+    let output = tx.output(0).unwrap();
+    assert_eq!(output.lock(), receiver);
+    let tx_size = tx.data().as_reader().serialized_size_in_block() as u64;
+    let fee = FeeRate::from_u64(FEE_RATE).fee(tx_size).as_u64();
+    assert_eq!(tx_fee(tx.clone(), &ctx, &ctx).unwrap(), fee);
+    assert_eq!(
+        Unpack::<u64>::unpack(&output.capacity()),
+        300 * ONE_CKB - fee
+    );
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_transfer_exact_fee() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+        ],
+    );
+
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output.clone(), Bytes::default())]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let mut balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
+    let exact_fee = ONE_CKB / 100;
+    balancer.set_exact_fee(Some(exact_fee));
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.output(0).unwrap(), output);
+    assert_eq!(tx.outputs().len(), 2);
+    assert_eq!(tx.output(1).unwrap().lock(), sender);
+    assert_eq!(tx_fee(tx.clone(), &ctx, &ctx).unwrap(), exact_fee);
+    ctx.verify(tx, FEE_RATE).unwrap();
+
+    // the exact fee must meet the fee rate
+    balancer.set_exact_fee(Some(100));
+    let mut cell_collector = ctx.to_live_cells_context();
+    let err = builder
+        .build_balanced(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        TxBuilderError::BalanceCapacity(BalanceTxCapacityError::ExactFeeTooLow(100, _))
+    ));
+}
+
+#[test]
+fn test_exact_fee_and_send_max_with_undersized_placeholder() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+        ],
+    );
+    // The signature is bigger than the placeholder
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 20])).pack())
+        .build();
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver.clone())
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    let mut balancer =
+        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+    balancer.set_exact_fee(Some(ONE_CKB));
+    let mut cell_collector = ctx.to_live_cells_context();
+    let placeholder_tx = builder
+        .build_balanced(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    let placeholder_tx_size = placeholder_tx.data().as_reader().serialized_size_in_block();
+
+    // Enough for the placeholder transaction only, the unlocked transaction
+    // is not rebalanced
+    let exact_fee = FeeRate::from_u64(FEE_RATE)
+        .fee(placeholder_tx_size as u64)
+        .as_u64();
+    balancer.set_exact_fee(Some(exact_fee));
+    let mut cell_collector = ctx.to_live_cells_context();
+    let err = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        TxBuilderError::BalanceCapacity(BalanceTxCapacityError::ExactFeeTooLow(fee, _)) if fee == exact_fee
+    ));
+
+    // A generous exact fee is kept after unlock
+    balancer.set_exact_fee(Some(ONE_CKB / 100));
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, _) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert_eq!(tx_fee(tx.clone(), &ctx, &ctx).unwrap(), ONE_CKB / 100);
+    ctx.verify(tx, FEE_RATE).unwrap();
+
+    // The send max output pays the extra fee, no change output is added
+    let builder = CapacityTransferBuilder::new_send_max(receiver.clone());
+    let mut balancer = CapacityBalancer::new_simple(sender, placeholder_witness, FEE_RATE);
+    balancer.set_send_max(Some(0));
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, _) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert_eq!(tx.inputs().len(), 2);
+    assert_eq!(tx.outputs().len(), 1);
+    assert_eq!(tx.output(0).unwrap().lock(), receiver);
+    let fee = tx_fee(tx.clone(), &ctx, &ctx).unwrap();
+    assert_eq!(
+        Unpack::<u64>::unpack(&tx.output(0).unwrap().capacity()),
+        300 * ONE_CKB - fee
+    );
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_transfer_with_fee_limits() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+        ],
+    );
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let mut balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
+    assert!(balancer.check_fee_limits(u64::MAX, 1).is_ok());
+    let mut cell_collector = ctx.to_live_cells_context();
+    let tx = builder
+        .build_balanced(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    let fee = tx_fee(tx.clone(), &ctx, &ctx).unwrap();
+    let tx_size = tx.data().as_reader().serialized_size_in_block() as u64;
+    let fee_rate = fee * 1000 / tx_size;
+
+    // The limits are inclusive
+    balancer.set_fee_limits(Some(fee), Some(fee_rate));
+    let mut cell_collector = ctx.to_live_cells_context();
+    let limited_tx = builder
+        .build_balanced(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert_eq!(limited_tx.hash(), tx.hash());
+
+    balancer.set_fee_limits(Some(fee - 1), None);
+    let mut cell_collector = ctx.to_live_cells_context();
+    let err = builder
+        .build_balanced(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        TxBuilderError::BalanceCapacity(BalanceTxCapacityError::FeeExceedLimit(actual, limit))
+            if actual == fee && limit == fee - 1
+    ));
+
+    balancer.set_fee_limits(None, Some(fee_rate - 1));
+    let mut cell_collector = ctx.to_live_cells_context();
+    let err = builder
+        .build_balanced(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        TxBuilderError::BalanceCapacity(BalanceTxCapacityError::FeeRateExceedLimit(actual, limit))
+            if actual == fee_rate && limit == fee_rate - 1
+    ));
+
+    // The signature is bigger than the placeholder, the change output pays
+    // the extra fee when the transaction is rebalanced after unlock, which
+    // must not exceed the limit either.
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 20])).pack())
+        .build();
+    let mut balancer = CapacityBalancer::new_simple(sender, placeholder_witness, FEE_RATE);
+    let mut cell_collector = ctx.to_live_cells_context();
+    let placeholder_tx = builder
+        .build_balanced(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    let placeholder_fee = tx_fee(placeholder_tx, &ctx, &ctx).unwrap();
+    balancer.set_fee_limits(Some(placeholder_fee), None);
+    let mut cell_collector = ctx.to_live_cells_context();
+    let err = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        TxBuilderError::BalanceCapacity(BalanceTxCapacityError::FeeExceedLimit(actual, limit))
+            if actual > placeholder_fee && limit == placeholder_fee
+    ));
+
+    balancer.set_fee_limits(Some(ONE_CKB), None);
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    assert!(tx_fee(tx.clone(), &ctx, &ctx).unwrap() > placeholder_fee);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_transfer_with_dust_policy() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+        ],
+    );
+
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output.clone(), Bytes::default())]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let mut balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
+    balancer.set_dust_policy(Some(DustPolicy::new(100 * ONE_CKB, Some(100 * ONE_CKB))));
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.inputs().len(), 2);
+    assert_eq!(tx.outputs().len(), 2);
+    let change_capacity: u64 = tx.output(1).unwrap().capacity().unpack();
+    assert!(change_capacity >= (61 + 100) * ONE_CKB);
+    ctx.verify(tx, FEE_RATE).unwrap();
+
+    // the change left is below the threshold
+    balancer.set_dust_policy(Some(DustPolicy::new(200 * ONE_CKB, None)));
+    let mut cell_collector = ctx.to_live_cells_context();
+    let err = builder
+        .build_balanced(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        TxBuilderError::BalanceCapacity(BalanceTxCapacityError::CapacityNotEnough(_))
+    ));
+
+    // the recipient output is below the min output capacity
+    balancer.set_dust_policy(Some(DustPolicy::new(0, Some(150 * ONE_CKB))));
+    let mut cell_collector = ctx.to_live_cells_context();
+    let err = builder
+        .build_balanced(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        TxBuilderError::BalanceCapacity(BalanceTxCapacityError::DustOutput(0, _, _))
+    ));
+}
+
+fn dust_policy_context() -> (Context, Script, TransactionView, CapacityBalancer) {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(Vec::new(), vec![(sender.clone(), Some(200 * ONE_CKB))]);
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let base_tx = TransactionBuilder::default()
+        .output(output)
+        .output_data(Bytes::default().pack())
+        .build();
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
+    (ctx, sender, base_tx, balancer)
+}
+
+#[test]
+fn test_balance_with_default_dust_policy() {
+    let (ctx, _, base_tx, mut balancer) = dust_policy_context();
+    let tx = balance_tx_capacity(
+        &base_tx,
+        &balancer,
+        &mut ctx.to_live_cells_context(),
+        &ctx,
+        &ctx,
+        &ctx,
+    )
+    .unwrap();
+    // The default policy changes nothing
+    balancer.set_dust_policy(Some(DustPolicy::default()));
+    let dust_tx = balance_tx_capacity(
+        &base_tx,
+        &balancer,
+        &mut ctx.to_live_cells_context(),
+        &ctx,
+        &ctx,
+        &ctx,
+    )
+    .unwrap();
+    assert_eq!(dust_tx.hash(), tx.hash());
+    assert_eq!(tx.outputs().len(), 2);
+}
+
+#[test]
+fn test_balance_with_dust_change_threshold() {
+    let (ctx, sender, base_tx, mut balancer) = dust_policy_context();
+    // About 80 CKB is left, enough for the 61 CKB change cell plus 10 CKB
+    balancer.set_dust_policy(Some(DustPolicy::new(10 * ONE_CKB, None)));
+    let tx = balance_tx_capacity(
+        &base_tx,
+        &balancer,
+        &mut ctx.to_live_cells_context(),
+        &ctx,
+        &ctx,
+        &ctx,
+    )
+    .unwrap();
+    assert_eq!(tx.outputs().len(), 2);
+    let change_output = tx.output(1).unwrap();
+    assert_eq!(change_output.lock(), sender);
+    assert!(Unpack::<u64>::unpack(&change_output.capacity()) >= (61 + 10) * ONE_CKB);
+
+    // The change would be below the threshold and no more cells to collect
+    balancer.set_dust_policy(Some(DustPolicy::new(30 * ONE_CKB, None)));
+    let err = balance_tx_capacity(
+        &base_tx,
+        &balancer,
+        &mut ctx.to_live_cells_context(),
+        &ctx,
+        &ctx,
+        &ctx,
+    )
+    .unwrap_err();
+    assert!(matches!(err, BalanceTxCapacityError::CapacityNotEnough(_)));
+
+    // The small change is absorbed into the fee when it is allowed
+    balancer.set_max_fee(Some(100 * ONE_CKB));
+    let tx = balance_tx_capacity(
+        &base_tx,
+        &balancer,
+        &mut ctx.to_live_cells_context(),
+        &ctx,
+        &ctx,
+        &ctx,
+    )
+    .unwrap();
+    assert_eq!(tx.outputs().len(), 1);
+    assert_eq!(tx_fee(tx, &ctx, &ctx).unwrap(), 80 * ONE_CKB);
+}
+
+#[test]
+fn test_balance_with_dust_min_output_capacity() {
+    let (ctx, _, base_tx, mut balancer) = dust_policy_context();
+    // The check is inclusive and the change output is not checked
+    balancer.set_dust_policy(Some(DustPolicy::new(0, Some(120 * ONE_CKB))));
+    let tx = balance_tx_capacity(
+        &base_tx,
+        &balancer,
+        &mut ctx.to_live_cells_context(),
+        &ctx,
+        &ctx,
+        &ctx,
+    )
+    .unwrap();
+    assert_eq!(tx.outputs().len(), 2);
+    assert!(Unpack::<u64>::unpack(&tx.output(1).unwrap().capacity()) < 120 * ONE_CKB);
+
+    let dust_output = CellOutput::new_builder()
+        .capacity((100 * ONE_CKB).pack())
+        .lock(build_sighash_script(ACCOUNT3_ARG))
+        .build();
+    let dust_tx = base_tx
+        .as_advanced_builder()
+        .output(dust_output)
+        .output_data(Bytes::default().pack())
+        .build();
+    let err = balance_tx_capacity(
+        &dust_tx,
+        &balancer,
+        &mut ctx.to_live_cells_context(),
+        &ctx,
+        &ctx,
+        &ctx,
+    )
+    .unwrap_err();
+    assert!(matches!(
+        err,
+        BalanceTxCapacityError::DustOutput(1, capacity, limit)
+            if capacity == 100 * ONE_CKB && limit == 120 * ONE_CKB
+    ));
+}
+
+#[test]
+fn test_capacity_provider_report() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+            (receiver.clone(), Some(300 * ONE_CKB)),
+        ],
+    );
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let provider = CapacityProvider::new_simple(vec![
+        (sender.clone(), placeholder_witness.clone()),
+        (sender.clone(), placeholder_witness),
+    ]);
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let reports = provider.report(&mut cell_collector).unwrap();
+    assert_eq!(reports.len(), 1);
+    let report = &reports[0];
+    assert_eq!(report.lock_script, sender);
+    assert_eq!(report.cell_count, 2);
+    assert_eq!(report.total_capacity, 300 * ONE_CKB);
+    assert_eq!(report.free_capacity, (300 - 61 * 2) * ONE_CKB);
+    assert_eq!(report.largest_cell, Some(200 * ONE_CKB));
+    assert_eq!(report.smallest_cell, Some(100 * ONE_CKB));
+    assert_eq!(report.immature_count, 0);
+}
+
+#[test]
+fn test_transfer_with_fee_payer() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let sponsor = build_sighash_script(ACCOUNT2_ARG);
+    let receiver = build_sighash_script(ACCOUNT3_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(200 * ONE_CKB)),
+            (sponsor.clone(), Some(300 * ONE_CKB)),
+        ],
+    );
+
+    let output = CellOutput::new_builder()
+        .capacity((70 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output.clone(), Bytes::default())]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let mut balancer =
+        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+    balancer.set_fee_payer(Some(CapacityProvider::new_simple(vec![(
+        sponsor.clone(),
+        placeholder_witness,
+    )])));
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let account2_key = secp256k1::SecretKey::from_slice(ACCOUNT2_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key, account2_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.inputs().len(), 2);
+    assert_eq!(tx.outputs().len(), 3);
+    assert_eq!(tx.output(0).unwrap(), output);
+    // the sender pays no fee
+    let sender_change = tx.output(1).unwrap();
+    assert_eq!(sender_change.lock(), sender);
+    let sender_capacity: u64 = sender_change.capacity().unpack();
+    assert_eq!(sender_capacity, 130 * ONE_CKB);
+    let sponsor_change = tx.output(2).unwrap();
+    assert_eq!(sponsor_change.lock(), sponsor);
+    let sponsor_capacity: u64 = sponsor_change.capacity().unpack();
+    assert_eq!(
+        tx_fee(tx.clone(), &ctx, &ctx).unwrap(),
+        300 * ONE_CKB - sponsor_capacity
+    );
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_transfer_with_shuffle() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let sponsor = build_sighash_script(ACCOUNT2_ARG);
+    let receiver = build_sighash_script(ACCOUNT3_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sponsor.clone(), Some(300 * ONE_CKB)),
+        ],
+    );
+
+    let output = CellOutput::new_builder()
+        .capacity((200 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output.clone(), Bytes::default())]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let mut balancer =
+        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+    balancer.set_fee_payer(Some(CapacityProvider::new_simple(vec![(
+        sponsor,
+        placeholder_witness,
+    )])));
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let account2_key = secp256k1::SecretKey::from_slice(ACCOUNT2_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key, account2_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    #[allow(clippy::mutable_key_type)]
+    let mut input_orders = HashSet::new();
+    for seed in 0..8 {
+        balancer.set_shuffle(Some(ShuffleMode::Seeded(seed)));
+        let mut cell_collector = ctx.to_live_cells_context();
+        let (tx, locked_groups) = builder
+            .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+            .unwrap();
+        assert!(locked_groups.is_empty());
+        assert_eq!(tx.inputs().len(), 4);
+        assert_eq!(tx.outputs().len(), 3);
+        assert!(tx.outputs().into_iter().any(|item| item == output));
+        let mut cell_collector = ctx.to_live_cells_context();
+        let (same_tx, _) = builder
+            .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+            .unwrap();
+        assert_eq!(same_tx.hash(), tx.hash());
+        ctx.verify(tx.clone(), FEE_RATE).unwrap();
+
+        input_orders.insert(tx.input_pts_iter().collect::<Vec<_>>());
+    }
+    // the inputs are shuffled differently by the seeds
+    assert!(input_orders.len() > 1);
+}
+
+#[test]
+fn test_shuffle_keeps_position_dependent_cells() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let mut ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(400 * ONE_CKB)),
+            (sender.clone(), Some(400 * ONE_CKB)),
+            (sender.clone(), Some(400 * ONE_CKB)),
+        ],
+    );
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let mut balancer =
+        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    // The type id args are computed from the first input and the output index
+    let mut builder = TypeIdDeployBuilder::new(sender.clone(), Bytes::from(ALWAYS_SUCCESS_BIN));
+    builder.capacity = Some(1000 * ONE_CKB);
+    for seed in 0..8 {
+        balancer.set_shuffle(Some(ShuffleMode::Seeded(seed)));
+        let mut cell_collector = ctx.to_live_cells_context();
+        let base_tx = builder
+            .build_base(&mut cell_collector, &ctx, &ctx, &ctx)
+            .unwrap();
+        let mut cell_collector = ctx.to_live_cells_context();
+        let (tx, locked_groups) = builder
+            .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+            .unwrap();
+        assert!(locked_groups.is_empty());
+        assert_eq!(tx.inputs().len(), 3);
+        assert_eq!(tx.inputs().get(0), base_tx.inputs().get(0));
+        assert_eq!(tx.output(0), base_tx.output(0));
+        TypeIdDeployment::from_tx(&tx, 0).unwrap();
+        ctx.verify(tx, FEE_RATE).unwrap();
+    }
+
+    // The DAO prepare input and output must have the same index
+    let deposit_point = EpochNumberWithFraction::new(5, 5, 1000);
+    let deposit_input = CellInput::new(random_out_point(), 0);
+    let deposit_output = CellOutput::new_builder()
+        .capacity((220 * ONE_CKB).pack())
+        .lock(sender.clone())
+        .type_(Some(build_dao_script()).pack())
+        .build();
+    let deposit_header = HeaderBuilder::default()
+        .epoch(deposit_point.full_value().pack())
+        .number(5005u64.pack())
+        .build();
+    ctx.add_live_cell(
+        deposit_input.clone(),
+        deposit_output.clone(),
+        Bytes::from(vec![0u8; 8]),
+        Some(deposit_header.hash()),
+    );
+    ctx.add_header(deposit_header);
+    let builder = DaoPrepareBuilder::from(vec![deposit_input.clone()]);
+    let output = CellOutput::new_builder()
+        .capacity((500 * ONE_CKB).pack())
+        .lock(sender)
+        .build();
+    #[allow(clippy::mutable_key_type)]
+    let mut input_orders = HashSet::new();
+    for seed in 0..8 {
+        balancer.set_shuffle(Some(ShuffleMode::Seeded(seed)));
+        let base_tx = {
+            let mut cell_collector = ctx.to_live_cells_context();
+            builder
+                .build_base(&mut cell_collector, &ctx, &ctx, &ctx)
+                .unwrap()
+        };
+        // Spend more than one capacity cell, so there is something to shuffle
+        let base_tx = base_tx
+            .as_advanced_builder()
+            .output(output.clone())
+            .output_data(Bytes::default().pack())
+            .build();
+        let (base_tx, _) = fill_placeholder_witnesses(base_tx, &ctx, &unlockers).unwrap();
+        let mut cell_collector = ctx.to_live_cells_context();
+        let tx = balance_tx_capacity(&base_tx, &balancer, &mut cell_collector, &ctx, &ctx, &ctx)
+            .unwrap();
+        let (tx, locked_groups) = unlock_tx(tx, &ctx, &unlockers).unwrap();
+        assert!(locked_groups.is_empty());
+        assert_eq!(tx.inputs().get(0).unwrap(), deposit_input);
+        assert_eq!(tx.output(0).unwrap(), deposit_output);
+        input_orders.insert(tx.input_pts_iter().collect::<Vec<_>>());
+        ctx.verify(tx, FEE_RATE).unwrap();
+    }
+    // The other inputs are still shuffled
+    assert!(input_orders.len() > 1);
+}
+
+#[test]
+fn test_build_unlocked_rebalance_with_balancer_modes() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+        ],
+    );
+    // The signature is bigger than the placeholder, so the transaction is
+    // rebalanced after unlock
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 20])).pack())
+        .build();
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output.clone(), Bytes::default())]);
+    let mut balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
+
+    // The change output is tracked through the shuffle, the extra fee is
+    // taken from it wherever it is moved to
+    let mut change_positions = HashSet::new();
+    for seed in 0..8 {
+        balancer.set_shuffle(Some(ShuffleMode::Seeded(seed)));
+        let mut cell_collector = ctx.to_live_cells_context();
+        let (tx, locked_groups) = builder
+            .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+            .unwrap();
+        assert!(locked_groups.is_empty());
+        assert_eq!(tx.outputs().len(), 2);
+        assert!(tx.outputs().into_iter().any(|item| item == output));
+        let change_idx = tx
+            .outputs()
+            .into_iter()
+            .position(|item| item.lock() == sender)
+            .unwrap();
+        change_positions.insert(change_idx);
+        ctx.verify(tx, FEE_RATE).unwrap();
+    }
+    assert_eq!(change_positions.len(), 2);
+
+    // The rebalancing after unlock uses the fee rate from the provider
+    balancer.set_shuffle(None);
+    let high_fee_rate = FEE_RATE * 5;
+    balancer.set_fee_rate_provider(Some(Arc::new(FeeRate::from_u64(high_fee_rate))));
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, _) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    let tx_size = tx.data().as_reader().serialized_size_in_block() as u64;
+    let fee = tx_fee(tx.clone(), &ctx, &ctx).unwrap();
+    assert!(fee >= FeeRate::from_u64(high_fee_rate).fee(tx_size).as_u64());
+    ctx.verify(tx, high_fee_rate).unwrap();
+}
+
+#[test]
+fn test_transfer_with_plan() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+        ],
+    );
+
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
+
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(SecpSighashUnlocker::from(Box::new(signer) as Box<_>)),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, plan) = builder
+        .build_balanced_with_plan(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    let tx_hash: H256 = tx.hash().unpack();
+    assert_eq!(plan.tx_hash, tx_hash);
+    assert_eq!(plan.inputs.len(), tx.inputs().len());
+    for input in &plan.inputs {
+        assert_eq!(Script::from(input.lock.clone()), sender);
+        assert_ne!(input.reason, InputReason::Builder);
+    }
+    assert_eq!(plan.fee.value(), tx_fee(tx.clone(), &ctx, &ctx).unwrap());
+    let change_capacity: u64 = tx.output(1).unwrap().capacity().unpack();
+    assert_eq!(
+        plan.change,
+        ChangeDecision::Output {
+            index: 1u32.into(),
+            capacity: change_capacity.into(),
+        }
+    );
+    assert!(plan.provider_switches.is_empty());
+    let json = plan.to_json();
+    assert!(json.contains("\"decision\": \"output\""));
+    assert_eq!(serde_json::from_str::<BalancePlan>(&json).unwrap(), plan);
+}
+
+#[test]
+fn test_balance_with_report() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+        ],
+    );
+
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let base_tx = TransactionBuilder::default()
+        .output(output.clone())
+        .output_data(Bytes::default().pack())
+        .build();
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let mut balancer = CapacityBalancer::new_simple(sender, placeholder_witness, FEE_RATE);
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, report) =
+        balance_tx_capacity_with_report(&base_tx, &balancer, &mut cell_collector, &ctx, &ctx, &ctx)
+            .unwrap();
+    assert_eq!(
+        report.selected_cells,
+        tx.input_pts_iter().collect::<Vec<_>>()
+    );
+    assert_eq!(report.inputs_capacity, 300 * ONE_CKB);
+    assert_eq!(report.inputs_capacity - report.outputs_capacity, report.fee);
+    assert_eq!(report.fee, tx_fee(tx.clone(), &ctx, &ctx).unwrap());
+    assert_eq!(report.change_index, Some(1));
+    assert!(report.collector_queries > 0);
+
+    // inspect the failed balancing with a recorder
+    let output = output
+        .as_builder()
+        .capacity((1000 * ONE_CKB).pack())
+        .build();
+    let base_tx = base_tx
+        .as_advanced_builder()
+        .set_outputs(vec![output])
+        .build();
+    let recorder = BalanceRecorder::new();
+    balancer.recorder = Some(recorder.clone());
+    let mut cell_collector = ctx.to_live_cells_context();
+    let err = balance_tx_capacity(&base_tx, &balancer, &mut cell_collector, &ctx, &ctx, &ctx)
+        .unwrap_err();
+    assert!(matches!(err, BalanceTxCapacityError::CapacityNotEnough(_)));
+    assert_eq!(recorder.selected_cells().len(), 2);
+    assert!(recorder.collector_queries() > 0);
+}
+
+#[test]
+fn test_balance_with_single_query() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        (0..20)
+            .map(|_| (sender.clone(), Some(100 * ONE_CKB)))
+            .collect(),
+    );
+
+    let base_tx = TransactionBuilder::default()
+        .output(
+            CellOutput::new_builder()
+                .capacity((1500 * ONE_CKB).pack())
+                .lock(receiver)
+                .build(),
+        )
+        .output_data(Bytes::default().pack())
+        .build();
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(sender, placeholder_witness, FEE_RATE);
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, report) =
+        balance_tx_capacity_with_report(&base_tx, &balancer, &mut cell_collector, &ctx, &ctx, &ctx)
+            .unwrap();
+    // The shortfall, including the fee of the new inputs, is collected at once
+    assert_eq!(report.collector_queries, 1);
+    assert_eq!(tx.inputs().len(), 16);
+    assert_eq!(report.change_index, Some(1));
+    assert_eq!(report.fee, tx_fee(tx.clone(), &ctx, &ctx).unwrap());
+    assert_eq!(
+        report.fee,
+        FeeRate::from_u64(FEE_RATE)
+            .fee(tx.data().as_reader().serialized_size_in_block() as u64)
+            .as_u64()
+    );
+}
+
+/// A cell collector which also tells the max total capacity, like the
+/// indexer based one, and counts the max total capacity queries
+#[derive(Clone)]
+struct IndexedLiveCellsContext(LiveCellsContext, Arc<AtomicUsize>);
+
+impl IndexedLiveCellsContext {
+    fn new(live_cells: LiveCellsContext) -> IndexedLiveCellsContext {
+        IndexedLiveCellsContext(live_cells, Arc::new(AtomicUsize::new(0)))
+    }
+
+    fn max_capacity_queries(&self) -> usize {
+        self.1.load(Ordering::SeqCst)
+    }
+}
+
+impl CellCollector for IndexedLiveCellsContext {
+    fn collect_live_cells(
+        &mut self,
+        query: &CellQueryOptions,
+        apply_changes: bool,
+    ) -> Result<(Vec<LiveCell>, u64), CellCollectorError> {
+        self.0.collect_live_cells(query, apply_changes)
+    }
+
+    fn max_total_capacity(
+        &mut self,
+        query: &CellQueryOptions,
+    ) -> Result<Option<u64>, CellCollectorError> {
+        self.1.fetch_add(1, Ordering::SeqCst);
+        let mut query = query.clone();
+        query.min_total_capacity = u64::MAX;
+        let (_, capacity) = self.0.collect_live_cells(&query, false)?;
+        Ok(Some(capacity))
+    }
+
+    fn lock_cell(
+        &mut self,
+        out_point: OutPoint,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        self.0.lock_cell(out_point, tip_block_number)
+    }
+
+    fn apply_tx(
+        &mut self,
+        tx: packed::Transaction,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        self.0.apply_tx(tx, tip_block_number)
+    }
+
+    fn reset(&mut self) {
+        self.0.reset()
+    }
+}
+
+#[cfg(feature = "async-tx-builder")]
+impl AsyncCellCollector for IndexedLiveCellsContext {
+    fn collect_live_cells<'a>(
+        &'a mut self,
+        query: &'a CellQueryOptions,
+        apply_changes: bool,
+    ) -> futures::future::BoxFuture<'a, Result<(Vec<LiveCell>, u64), CellCollectorError>> {
+        Box::pin(async move { CellCollector::collect_live_cells(self, query, apply_changes) })
+    }
+
+    fn max_total_capacity<'a>(
+        &'a mut self,
+        query: &'a CellQueryOptions,
+    ) -> futures::future::BoxFuture<'a, Result<Option<u64>, CellCollectorError>> {
+        Box::pin(async move { CellCollector::max_total_capacity(self, query) })
+    }
+
+    fn lock_cell(
+        &mut self,
+        out_point: OutPoint,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        CellCollector::lock_cell(self, out_point, tip_block_number)
+    }
+
+    fn apply_tx(
+        &mut self,
+        tx: packed::Transaction,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        CellCollector::apply_tx(self, tx, tip_block_number)
+    }
+
+    fn reset(&mut self) {
+        CellCollector::reset(self)
+    }
+}
+
+#[test]
+fn test_balance_fail_fast_with_max_capacity() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+        ],
+    );
+
+    let output = CellOutput::new_builder()
+        .capacity((1000 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let base_tx = TransactionBuilder::default()
+        .output(output.clone())
+        .output_data(Bytes::default().pack())
+        .build();
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let mut balancer = CapacityBalancer::new_simple(sender, placeholder_witness, FEE_RATE);
+    let recorder = BalanceRecorder::new();
+    balancer.recorder = Some(recorder.clone());
+
+    let mut cell_collector = IndexedLiveCellsContext::new(ctx.to_live_cells_context());
+    let err = balance_tx_capacity(&base_tx, &balancer, &mut cell_collector, &ctx, &ctx, &ctx)
+        .unwrap_err();
+    assert!(matches!(err, BalanceTxCapacityError::CapacityNotEnough(_)));
+    // The cells are not collected (and locked)
+    assert!(recorder.selected_cells().is_empty());
+    assert_eq!(recorder.collector_queries(), 0);
+    assert!(cell_collector.0.used_inputs.is_empty());
+
+    let output = output.as_builder().capacity((120 * ONE_CKB).pack()).build();
+    let base_tx = base_tx
+        .as_advanced_builder()
+        .set_outputs(vec![output])
+        .build();
+    let queries = cell_collector.max_capacity_queries();
+    let tx =
+        balance_tx_capacity(&base_tx, &balancer, &mut cell_collector, &ctx, &ctx, &ctx).unwrap();
+    assert_eq!(tx.inputs().len(), 2);
+    // The max capacity is queried once, not again for every collecting
+    assert_eq!(cell_collector.max_capacity_queries(), queries + 1);
+}
+
+#[test]
+fn test_balance_fail_fast_with_all_providers() {
+    let sender1 = build_sighash_script(ACCOUNT1_ARG);
+    let sender2 = build_sighash_script(ACCOUNT2_ARG);
+    let receiver = build_sighash_script(ACCOUNT3_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender1.clone(), Some(300 * ONE_CKB)),
+            (sender2.clone(), Some(300 * ONE_CKB)),
+        ],
+    );
+
+    let output = CellOutput::new_builder()
+        .capacity((1000 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let base_tx = TransactionBuilder::default()
+        .output(output)
+        .output_data(Bytes::default().pack())
+        .build();
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let mut balancer = CapacityBalancer::new_with_provider(
+        FEE_RATE,
+        CapacityProvider::new_simple(vec![
+            (sender1, placeholder_witness.clone()),
+            (sender2, placeholder_witness),
+        ]),
+    );
+    let recorder = BalanceRecorder::new();
+    balancer.recorder = Some(recorder.clone());
+
+    let mut cell_collector = IndexedLiveCellsContext::new(ctx.to_live_cells_context());
+    let err = balance_tx_capacity(&base_tx, &balancer, &mut cell_collector, &ctx, &ctx, &ctx)
+        .unwrap_err();
+    match err {
+        BalanceTxCapacityError::CapacityNotEnough(msg) => {
+            assert!(msg.contains("max available=600.0"), "{}", msg);
+        }
+        err => panic!("unexpected error: {}", err),
+    }
+    // Failed before collecting any cell of the first provider
+    assert_eq!(recorder.collector_queries(), 0);
+    assert!(recorder.selected_cells().is_empty());
+}
+
+#[test]
+fn test_transfer_with_dao_provider() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let mut ctx = init_context(Vec::new(), vec![(sender.clone(), Some(100 * ONE_CKB))]);
+
+    let (deposit_point, prepare_point) = ((5, 5, 1000), (184, 4, 1000));
+    let deposit_number = deposit_point.0 * deposit_point.2 + deposit_point.1;
+    let prepare_number = prepare_point.0 * prepare_point.2 + prepare_point.1;
+    let deposit_point =
+        EpochNumberWithFraction::new(deposit_point.0, deposit_point.1, deposit_point.2);
+    let prepare_point =
+        EpochNumberWithFraction::new(prepare_point.0, prepare_point.1, prepare_point.2);
+    let deposit_header = HeaderBuilder::default()
+        .epoch(deposit_point.full_value().pack())
+        .number(deposit_number.pack())
+        .dao(pack_dao_data(
+            10_000_000_000_123_456,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        ))
+        .build();
+    let prepare_header = HeaderBuilder::default()
+        .epoch(prepare_point.full_value().pack())
+        .number(prepare_number.pack())
+        .dao(pack_dao_data(
+            10_000_000_001_123_456,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        ))
+        .build();
+    let prepare_out_point = random_out_point();
+    let prepare_output = CellOutput::new_builder()
+        .capacity((220 * ONE_CKB).pack())
+        .lock(sender.clone())
+        .type_(Some(build_dao_script()).pack())
+        .build();
+    ctx.add_live_cell(
+        CellInput::new(prepare_out_point.clone(), 0),
+        prepare_output,
+        Bytes::from(deposit_number.to_le_bytes().to_vec()),
+        Some(prepare_header.hash()),
+    );
+    ctx.add_header(deposit_header.clone());
+    ctx.add_header(prepare_header.clone());
+    let unlock_point = minimal_unlock_point(&deposit_header, &prepare_header);
+
+    let output = CellOutput::new_builder()
+        .capacity((250 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let mut balancer =
+        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    // The prepared cell is not matured yet
+    let immature_epoch = EpochNumberWithFraction::new(unlock_point.number() - 1, 0, 1);
+    balancer.set_dao_provider(Some(DaoCapacityProvider::new(immature_epoch, 0)));
+    let mut cell_collector = ctx.to_live_cells_context();
+    let err = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap_err();
+    assert!(err.to_string().contains("capacity not enough"));
+
+    let matured_epoch = EpochNumberWithFraction::new(unlock_point.number() + 1, 0, 1);
+    balancer.set_dao_provider(Some(DaoCapacityProvider::new(matured_epoch, 0)));
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.inputs().len(), 2);
+    let dao_input = tx.inputs().get(0).unwrap();
+    assert_eq!(dao_input.previous_output(), prepare_out_point);
+    let since: u64 = dao_input.since().unpack();
+    assert_eq!(
+        since,
+        Since::new(
+            SinceType::EpochNumberWithFraction,
+            unlock_point.full_value(),
+            false
+        )
+        .value()
+    );
+    assert_eq!(
+        tx.header_deps().into_iter().collect::<Vec<_>>(),
+        vec![deposit_header.hash(), prepare_header.hash()]
+    );
+    let witness = WitnessArgs::from_slice(&tx.witnesses().get(0).unwrap().raw_data()).unwrap();
+    assert_eq!(
+        witness.input_type().to_opt().unwrap().raw_data(),
+        Bytes::from(0u64.to_le_bytes().to_vec())
+    );
+    assert_eq!(tx.outputs().len(), 2);
+    assert_eq!(tx.output(1).unwrap().lock(), sender);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_udt_allowance() {
+    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
+    let cheque_data_hash = H256::from(blake2b_256(CHEQUE_BIN));
+    let holder = build_sighash_script(ACCOUNT1_ARG);
+    let spender = build_sighash_script(ACCOUNT2_ARG);
+    let type_script = Script::new_builder()
+        .code_hash(sudt_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(Bytes::from(vec![9u8; 32]).pack())
+        .build();
+    let mut ctx = init_context(
+        vec![(CHEQUE_BIN, true), (SUDT_BIN, false)],
+        vec![
+            (holder.clone(), Some(500 * ONE_CKB)),
+            (spender.clone(), Some(100 * ONE_CKB)),
+        ],
+    );
+    let allowance = Allowance::new(
+        ScriptId::new_data1(cheque_data_hash.clone()),
+        type_script.clone(),
+        holder.clone(),
+        spender.clone(),
+    );
+    assert_eq!(
+        allowance.cheque_lock_script(),
+        build_cheque_script(&holder, &spender, cheque_data_hash.clone())
+    );
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+
+    // grant
+    let holder_output = CellOutput::new_builder()
+        .capacity((200 * ONE_CKB).pack())
+        .lock(holder.clone())
+        .type_(Some(type_script.clone()).pack())
+        .build();
+    ctx.add_live_cell(
+        CellInput::new(random_out_point(), 0),
+        holder_output,
+        Bytes::from(500u128.to_le_bytes().to_vec()),
+        None,
+    );
+    assert!(allowance.grant(&[]).is_err());
+    assert!(allowance.grant(&[100, 0]).is_err());
+    let builder = allowance.grant(&[100, 200]).unwrap();
+    let balancer =
+        CapacityBalancer::new_simple(holder.clone(), placeholder_witness.clone(), FEE_RATE);
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    let installments: Vec<u128> = tx
+        .outputs_with_data_iter()
+        .filter(|(output, _)| output.lock() == allowance.cheque_lock_script())
+        .map(|(_, data)| {
+            let mut amount_bytes = [0u8; 16];
+            amount_bytes.copy_from_slice(&data);
+            u128::from_le_bytes(amount_bytes)
+        })
+        .collect();
+    assert_eq!(installments, vec![100, 200]);
+    ctx.verify(tx, FEE_RATE).unwrap();
+
+    // claim
+    let epoch = EpochNumberWithFraction::new(10, 0, 1);
+    let header = HeaderBuilder::default()
+        .epoch(epoch.full_value().pack())
+        .number(10_000u64.pack())
+        .build();
+    ctx.add_header(header.clone());
+    for amount in [100u128, 200, 300].iter() {
+        let cheque_output = CellOutput::new_builder()
+            .capacity((162 * ONE_CKB).pack())
+            .lock(allowance.cheque_lock_script())
+            .type_(Some(type_script.clone()).pack())
+            .build();
+        ctx.add_live_cell(
+            CellInput::new(random_out_point(), 0),
+            cheque_output,
+            Bytes::from(amount.to_le_bytes().to_vec()),
+            Some(header.hash()),
+        );
+    }
+    let spender_input = CellInput::new(random_out_point(), 0);
+    let spender_output = CellOutput::new_builder()
+        .capacity((200 * ONE_CKB).pack())
+        .lock(spender.clone())
+        .type_(Some(type_script.clone()).pack())
+        .build();
+    ctx.add_live_cell(
+        spender_input.clone(),
+        spender_output,
+        Bytes::from(0u128.to_le_bytes().to_vec()),
+        None,
+    );
+    let mut cell_collector = ctx.to_live_cells_context();
+    assert_eq!(allowance.remaining(&mut cell_collector).unwrap(), 600);
+    assert!(allowance
+        .claim(&mut cell_collector, spender_input.clone(), 50)
+        .is_err());
+    let builder = allowance
+        .claim(&mut cell_collector, spender_input, 350)
+        .unwrap();
+    let claimed: u128 = allowance
+        .installments(&mut cell_collector)
+        .unwrap()
+        .into_iter()
+        .filter(|installment| {
+            builder
+                .inputs
+                .iter()
+                .any(|input| input.previous_output() == installment.out_point)
+        })
+        .map(|installment| installment.amount)
+        .sum();
+    assert!(claimed > 0 && claimed <= 350);
+
+    let balancer = CapacityBalancer::new_simple(spender, placeholder_witness, FEE_RATE);
+    let account2_key = secp256k1::SecretKey::from_slice(ACCOUNT2_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account2_key]);
+    let sighash_unlocker = SecpSighashUnlocker::from(Box::new(signer.clone()) as Box<_>);
+    let cheque_unlocker = ChequeUnlocker::from((Box::new(signer) as Box<_>, ChequeAction::Claim));
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH),
+        Box::new(sighash_unlocker),
+    );
+    unlockers.insert(
+        ScriptId::new_data1(cheque_data_hash),
+        Box::new(cheque_unlocker),
+    );
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    assert_eq!(
+        tx.outputs_data().get(0).unwrap().raw_data(),
+        Bytes::from(claimed.to_le_bytes().to_vec())
+    );
+    ctx.verify(tx, FEE_RATE).unwrap();
+
+    // refund
+    let mut cell_collector = ctx.to_live_cells_context();
+    let refundable = allowance
+        .refundable(
+            &mut cell_collector,
+            &ctx,
+            EpochNumberWithFraction::new(15, 0, 1),
+        )
+        .unwrap();
+    assert!(refundable.is_empty());
+    let refundable = allowance
+        .refundable(
+            &mut cell_collector,
+            &ctx,
+            EpochNumberWithFraction::new(16, 0, 1),
+        )
+        .unwrap();
+    assert_eq!(refundable.len(), 3);
+    assert_eq!(allowance.refund(refundable).sender_lock_script, holder);
+}
+
+struct TrailingInputBuilder {
+    input: CellInput,
+    output: CellOutput,
+}
+
+impl TxBuilder for TrailingInputBuilder {
+    fn build_base(
+        &self,
+        _cell_collector: &mut dyn CellCollector,
+        _cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        Ok(TransactionBuilder::default()
+            .input(self.input.clone())
+            .output(self.output.clone())
+            .output_data(Bytes::default().pack())
+            .build())
+    }
+
+    fn input_ordering(&self, _base_tx: &TransactionView) -> Option<InputOrdering> {
+        Some(InputOrdering::new().trailing(self.input.previous_output()))
+    }
+}
+
+#[test]
+fn test_transfer_with_input_ordering() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let owner = build_sighash_script(ACCOUNT2_ARG);
+    let receiver = build_sighash_script(ACCOUNT0_ARG);
+    let mut ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(100 * ONE_CKB)),
+        ],
+    );
+    let trailing_out_point = random_out_point();
+    ctx.add_simple_live_cell(trailing_out_point.clone(), owner, Some(100 * ONE_CKB));
+
+    let builder = TrailingInputBuilder {
+        input: CellInput::new(trailing_out_point.clone(), 0),
+        output: CellOutput::new_builder()
+            .capacity((250 * ONE_CKB).pack())
+            .lock(receiver)
+            .build(),
+    };
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let mut balancer = CapacityBalancer::new_simple(sender, placeholder_witness, FEE_RATE);
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let account2_key = secp256k1::SecretKey::from_slice(ACCOUNT2_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key, account2_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    for shuffle in [
+        None,
+        Some(ShuffleMode::Seeded(1)),
+        Some(ShuffleMode::Seeded(2)),
+    ] {
+        balancer.set_shuffle(shuffle);
+        let mut cell_collector = ctx.to_live_cells_context();
+        let (tx, _) = builder
+            .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+            .unwrap();
+        assert_eq!(tx.inputs().len(), 3);
+        assert_eq!(
+            tx.inputs().get(2).unwrap().previous_output(),
+            trailing_out_point
+        );
+        assert!(InputOrdering::new()
+            .trailing(trailing_out_point.clone())
+            .is_satisfied(&tx)
+            .unwrap());
+        ctx.verify(tx, FEE_RATE).unwrap();
+    }
+}
+
+#[test]
+fn test_transfer_with_witness_size_estimator() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(Vec::new(), vec![(sender.clone(), Some(200 * ONE_CKB))]);
+
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    // The placeholder is smaller than the final signature
+    let small_placeholder = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 20])).pack())
+        .build();
+    let signed_witness_size = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build()
+        .as_slice()
+        .len();
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let mut balancer = CapacityBalancer::new_simple(sender.clone(), small_placeholder, FEE_RATE);
+    let mut cell_collector = ctx.to_live_cells_context();
+    let balanced_tx = builder
+        .build_balanced(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    let (tx, _) = unlock_tx(balanced_tx.clone(), &ctx, &unlockers).unwrap();
+    assert!(balancer
+        .check_unlocked_fee(&balanced_tx, &tx, &ctx, &ctx)
+        .unwrap()
+        .is_some());
+
+    balancer.set_witness_size_estimator(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Arc::new(signed_witness_size),
+    );
+    let mut cell_collector = ctx.to_live_cells_context();
+    let balanced_tx = builder
+        .build_balanced(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert_eq!(
+        balancer.estimated_tx_size(&balanced_tx, &ctx).unwrap(),
+        balanced_tx.data().as_reader().serialized_size_in_block() + 45
+    );
+    let (tx, _) = unlock_tx(balanced_tx.clone(), &ctx, &unlockers).unwrap();
+    assert!(balancer
+        .check_unlocked_fee(&balanced_tx, &tx, &ctx, &ctx)
+        .unwrap()
+        .is_none());
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_transfer_with_type_and_data() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(
+        vec![(ALWAYS_SUCCESS_BIN, false)],
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+        ],
+    );
+
+    let type_script = Script::new_builder()
+        .code_hash(H256::from(blake2b_256(ALWAYS_SUCCESS_BIN)).pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .build();
+    let output_data = Bytes::from(vec![1u8; 100]);
+    let output = CellOutput::new_builder()
+        .capacity((200 * ONE_CKB).pack())
+        .lock(receiver.clone())
+        .type_(Some(type_script.clone()).pack())
+        .build();
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer =
+        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    // capacity not enough for the type script and output data
+    let small_output = output
+        .clone()
+        .as_builder()
+        .capacity((100 * ONE_CKB).pack())
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(small_output, output_data.clone())]);
+    let mut cell_collector = ctx.to_live_cells_context();
+    assert!(builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .is_err());
+
+    let mut builder = CapacityTransferBuilder::new(Vec::new());
+    builder.add_output(output.clone(), output_data.clone());
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.cell_deps().len(), 2);
+    assert_eq!(tx.inputs().len(), 2);
+    assert_eq!(tx.outputs().len(), 2);
+    assert_eq!(tx.output(0).unwrap(), output);
+    assert_eq!(tx.outputs_data().get(0).unwrap().raw_data(), output_data);
+    assert_eq!(tx.output(1).unwrap().lock(), sender);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_snapshot_replay() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+        ],
+    );
+
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(sender, placeholder_witness, FEE_RATE);
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let recorder = SnapshotRecorder::new();
+    let mut cell_collector = recorder.cell_collector(Box::new(ctx.to_live_cells_context()));
+    let (tx, _) = builder
+        .build_unlocked(
+            &mut cell_collector,
+            &recorder.cell_dep_resolver(&ctx),
+            &recorder.header_dep_resolver(&ctx),
+            &recorder.tx_dep_provider(&ctx),
+            &balancer,
+            &unlockers,
+        )
+        .unwrap();
+    let path = std::env::temp_dir().join(format!("ckb-sdk-snapshot-{}.json", std::process::id()));
+    recorder.snapshot().save(&path).unwrap();
+
+    let mut replayer = SnapshotReplayer::load(&path).unwrap();
+    let replayer_provider = replayer.clone();
+    let (replayed_tx, _) = builder
+        .build_unlocked(
+            &mut replayer,
+            &replayer_provider,
+            &replayer_provider,
+            &replayer_provider,
+            &balancer,
+            &unlockers,
+        )
+        .unwrap();
+    assert_eq!(replayed_tx.data(), tx.data());
+    std::fs::remove_file(&path).unwrap();
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_transfer_from_multisig() {
+    let lock_args = vec![
+        ACCOUNT0_ARG.clone(),
+        ACCOUNT1_ARG.clone(),
+        ACCOUNT2_ARG.clone(),
+    ];
+    let cfg = MultisigConfig::new_with(lock_args, 0, 2).unwrap();
+
+    let sender = build_multisig_script(&cfg);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+            (sender.clone(), Some(300 * ONE_CKB)),
+        ],
+    );
+
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output.clone(), Bytes::default())]);
+    let placeholder_witness = cfg.placeholder_witness();
+    let balancer =
+        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let account0_key = secp256k1::SecretKey::from_slice(ACCOUNT0_KEY.as_bytes()).unwrap();
+    let account2_key = secp256k1::SecretKey::from_slice(ACCOUNT2_KEY.as_bytes()).unwrap();
+    let unlockers = build_multisig_unlockers(account0_key, cfg.clone());
+    let mut tx = builder
+        .build_balanced(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+
+    let mut locked_groups = None;
+    for key in [account0_key, account2_key] {
+        let unlockers = build_multisig_unlockers(key, cfg.clone());
+        let (new_tx, new_locked_groups) = unlock_tx(tx.clone(), &ctx, &unlockers).unwrap();
+        tx = new_tx;
+        locked_groups = Some(new_locked_groups);
+    }
+
+    assert_eq!(locked_groups, Some(Vec::new()));
+    assert_eq!(tx.header_deps().len(), 0);
+    assert_eq!(tx.cell_deps().len(), 1);
+    assert_eq!(tx.inputs().len(), 2);
+    for out_point in tx.input_pts_iter() {
+        assert_eq!(ctx.get_input(&out_point).unwrap().0.lock(), sender);
+    }
+    assert_eq!(tx.outputs().len(), 2);
+    assert_eq!(tx.output(0).unwrap(), output);
+    assert_eq!(tx.output(1).unwrap().lock(), sender);
+    let witnesses = tx
+        .witnesses()
+        .into_iter()
+        .map(|w| w.raw_data())
+        .collect::<Vec<_>>();
+    assert_eq!(witnesses.len(), 2);
+    assert_eq!(witnesses[0].len(), placeholder_witness.as_slice().len());
+    assert_eq!(witnesses[1].len(), 0);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_verify_signatures() {
+    let cfg =
+        MultisigConfig::new_with(vec![ACCOUNT0_ARG, ACCOUNT1_ARG, ACCOUNT2_ARG], 0, 2).unwrap();
+    let sender = build_multisig_script(&cfg);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(Vec::new(), vec![(sender.clone(), Some(300 * ONE_CKB))]);
+
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    let balancer = CapacityBalancer::new_simple(sender, cfg.placeholder_witness(), FEE_RATE);
+    let mut cell_collector = ctx.to_live_cells_context();
+    let account0_key = secp256k1::SecretKey::from_slice(ACCOUNT0_KEY.as_bytes()).unwrap();
+    let account2_key = secp256k1::SecretKey::from_slice(ACCOUNT2_KEY.as_bytes()).unwrap();
+    let unlockers = build_multisig_unlockers(account0_key, cfg.clone());
+    let mut tx = builder
+        .build_balanced(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    let reports = verify_signatures(&tx, &ctx).unwrap();
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].status, SignatureStatus::Missing);
+
+    let (new_tx, _) = unlock_tx(tx, &ctx, &unlockers).unwrap();
+    tx = new_tx;
+    let reports = verify_signatures(&tx, &ctx).unwrap();
+    assert_eq!(
+        reports[0].status,
+        SignatureStatus::Partial {
+            signed: 1,
+            threshold: 2
+        }
+    );
+
+    let unlockers = build_multisig_unlockers(account2_key, cfg);
+    let (new_tx, _) = unlock_tx(tx, &ctx, &unlockers).unwrap();
+    tx = new_tx;
+    let reports = verify_signatures(&tx, &ctx).unwrap();
+    assert!(reports[0].status.is_valid());
+
+    // the signatures not match the modified transaction
+    let tampered_tx = tx
+        .as_advanced_builder()
+        .set_outputs_data(vec![Bytes::from(vec![1u8]).pack(), Bytes::new().pack()])
+        .build();
+    let reports = verify_signatures(&tampered_tx, &ctx).unwrap();
+    assert!(matches!(reports[0].status, SignatureStatus::Invalid(_)));
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_multisig_witness_dedup() {
+    let cfg =
+        MultisigConfig::new_with(vec![ACCOUNT0_ARG, ACCOUNT1_ARG, ACCOUNT2_ARG], 0, 2).unwrap();
+    let sender = build_multisig_script(&cfg);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(100 * ONE_CKB)),
+        ],
+    );
+
+    let output = CellOutput::new_builder()
+        .capacity((150 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    let placeholder_witness = cfg.placeholder_witness();
+    let balancer =
+        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+    let mut cell_collector = ctx.to_live_cells_context();
+    let account0_key = secp256k1::SecretKey::from_slice(ACCOUNT0_KEY.as_bytes()).unwrap();
+    let account2_key = secp256k1::SecretKey::from_slice(ACCOUNT2_KEY.as_bytes()).unwrap();
+    let unlockers = build_multisig_unlockers(account0_key, cfg.clone());
+    let tx = builder
+        .build_balanced(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert_eq!(tx.inputs().len(), 2);
+    assert!(find_duplicated_multisig_configs(&tx, &ctx)
+        .unwrap()
+        .is_empty());
+
+    // the config is repeated in the second witness of the group
+    let mut witnesses: Vec<_> = tx.witnesses().into_iter().collect();
+    witnesses[1] = placeholder_witness.as_bytes().pack();
+    let bloated_tx = tx.as_advanced_builder().set_witnesses(witnesses).build();
+    let duplicated = find_duplicated_multisig_configs(&bloated_tx, &ctx).unwrap();
+    assert_eq!(duplicated.len(), 1);
+    assert_eq!(duplicated[0].script, sender);
+    assert_eq!(duplicated[0].input_index, 1);
+    assert_eq!(
+        duplicated[0].wasted_size,
+        placeholder_witness
+            .lock()
+            .to_opt()
+            .unwrap()
+            .raw_data()
+            .len()
+    );
+
+    let new_tx = dedup_multisig_witnesses(&bloated_tx, &ctx).unwrap();
+    assert!(find_duplicated_multisig_configs(&new_tx, &ctx)
+        .unwrap()
+        .is_empty());
+    assert_eq!(new_tx.witnesses().get(1).unwrap().raw_data().len(), 0);
+    assert_eq!(new_tx, tx);
+
+    // the multisig unlocker drops the duplicated config when filling the
+    // placeholder witness
+    let (filled_tx, _) = fill_placeholder_witnesses(bloated_tx, &ctx, &unlockers).unwrap();
+    assert_eq!(filled_tx, tx);
+
+    let (tx, _) = unlock_tx(filled_tx, &ctx, &unlockers).unwrap();
+    let unlockers = build_multisig_unlockers(account2_key, cfg);
+    let (tx, _) = unlock_tx(tx, &ctx, &unlockers).unwrap();
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_multisig_proposal() {
+    let cfg =
+        MultisigConfig::new_with(vec![ACCOUNT0_ARG, ACCOUNT1_ARG, ACCOUNT2_ARG], 1, 2).unwrap();
+    let sender = build_multisig_script(&cfg);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+        ],
+    );
+
+    let output = CellOutput::new_builder()
+        .capacity((250 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    let balancer = CapacityBalancer::new_simple(sender, cfg.placeholder_witness(), FEE_RATE);
+    let mut cell_collector = ctx.to_live_cells_context();
+    let tx = builder
+        .build_balanced(
+            &mut cell_collector,
+            &ctx,
+            &ctx,
+            &ctx,
+            &balancer,
+            &HashMap::default(),
+        )
+        .unwrap();
+    let mut proposal =
+        MultisigProposal::new(tx, cfg.clone(), "pay 250 CKB".to_string(), &ctx).unwrap();
+    let tx_hash: H256 = proposal.tx().hash().unpack();
+    assert_eq!(proposal.id(), &tx_hash);
+
+    let sign = |proposal: &MultisigProposal, key: &H256| {
+        let key = secp256k1::SecretKey::from_slice(key.as_bytes()).unwrap();
+        let unlockers = build_multisig_unlockers(key, cfg.clone());
+        unlock_tx(proposal.tx(), &ctx, &unlockers).unwrap().0
+    };
+    let signed_tx = sign(&proposal, &ACCOUNT2_KEY);
+    // the signatures of other members are not accepted
+    assert!(matches!(
+        proposal.approve(&ACCOUNT1_ARG, &signed_tx),
+        Err(ProposalError::InvalidApproval(_))
+    ));
+    proposal.approve(&ACCOUNT2_ARG, &signed_tx).unwrap();
+    let signed_tx = sign(&proposal, &ACCOUNT1_KEY);
+    proposal.approve(&ACCOUNT1_ARG, &signed_tx).unwrap();
+    // the first signer is required
+    assert!(!proposal.is_executable());
+    assert!(matches!(
+        proposal.execute(),
+        Err(ProposalError::MissingRequiredApproval(_))
+    ));
+
+    let path = std::env::temp_dir().join(format!(
+        "ckb-sdk-multisig-proposal-{}.json",
+        std::process::id()
+    ));
+    proposal.save(&path).unwrap();
+    let mut proposal = MultisigProposal::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(proposal.summary(), "pay 250 CKB");
+    assert_eq!(proposal.approvals().len(), 2);
+
+    let signed_tx = sign(&proposal, &ACCOUNT0_KEY);
+    proposal.approve(&ACCOUNT0_ARG, &signed_tx).unwrap();
+    assert!(proposal.is_executable());
+    let tx = proposal.execute().unwrap();
+    let reports = verify_signatures(&tx, &ctx).unwrap();
+    assert!(reports[0].status.is_valid());
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_transfer_from_acp() {
+    let data_hash = H256::from(blake2b_256(ACP_BIN));
+    let sender = Script::new_builder()
+        .code_hash(data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(Bytes::from(ACCOUNT1_ARG.0.to_vec()).pack())
+        .build();
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(
+        vec![(ACP_BIN, true)],
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+            (sender.clone(), Some(300 * ONE_CKB)),
+        ],
+    );
+
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output.clone(), Bytes::default())]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer =
+        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = AcpUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(ScriptId::new_data1(data_hash), Box::new(script_unlocker));
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.header_deps().len(), 0);
+    assert_eq!(tx.cell_deps().len(), 1);
+    assert_eq!(tx.inputs().len(), 2);
+    for out_point in tx.input_pts_iter() {
+        assert_eq!(ctx.get_input(&out_point).unwrap().0.lock(), sender);
+    }
+    assert_eq!(tx.outputs().len(), 2);
+    assert_eq!(tx.output(0).unwrap(), output);
+    assert_eq!(tx.output(1).unwrap().lock(), sender);
+    let witnesses = tx
+        .witnesses()
+        .into_iter()
+        .map(|w| w.raw_data())
+        .collect::<Vec<_>>();
+    assert_eq!(witnesses.len(), 2);
+    assert_eq!(witnesses[0].len(), placeholder_witness.as_slice().len());
+    assert_eq!(witnesses[1].len(), 0);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_transfer_to_acp() {
+    let data_hash = H256::from(blake2b_256(ACP_BIN));
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = Script::new_builder()
+        .code_hash(data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(Bytes::from(ACCOUNT2_ARG.0.to_vec()).pack())
+        .build();
+    let ctx = init_context(
+        vec![(ACP_BIN, true)],
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+            (sender.clone(), Some(300 * ONE_CKB)),
+            (receiver.clone(), Some(99 * ONE_CKB)),
+        ],
+    );
+
+    let acp_receiver = AcpTransferReceiver::new(receiver.clone(), 150 * ONE_CKB);
+    let builder = AcpTransferBuilder::new(vec![acp_receiver]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer =
+        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer1 = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let sighash_unlocker = AcpUnlocker::from(Box::new(signer1) as Box<_>);
+    let acp_unlocker = AcpUnlocker::from(Box::<SecpCkbRawKeySigner>::default() as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH),
+        Box::new(sighash_unlocker),
+    );
+    unlockers.insert(ScriptId::new_data1(data_hash), Box::new(acp_unlocker));
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.header_deps().len(), 0);
+    assert_eq!(tx.cell_deps().len(), 2);
+    assert_eq!(tx.inputs().len(), 3);
+    let input_cells = vec![
+        CellOutput::new_builder()
+            .capacity((99 * ONE_CKB).pack())
+            .lock(receiver.clone())
+            .build(),
+        CellOutput::new_builder()
+            .capacity((100 * ONE_CKB).pack())
+            .lock(sender.clone())
+            .build(),
+        CellOutput::new_builder()
+            .capacity((200 * ONE_CKB).pack())
+            .lock(sender.clone())
+            .build(),
+    ];
+    for (idx, out_point) in tx.input_pts_iter().enumerate() {
+        assert_eq!(ctx.get_input(&out_point).unwrap().0, input_cells[idx]);
+    }
+    assert_eq!(tx.outputs().len(), 2);
+    let acp_output = CellOutput::new_builder()
+        .capacity(((99 + 150) * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    assert_eq!(tx.output(0).unwrap(), acp_output);
+    assert_eq!(tx.output(1).unwrap().lock(), sender);
+    let witnesses = tx
+        .witnesses()
+        .into_iter()
+        .map(|w| w.raw_data())
+        .collect::<Vec<_>>();
+    assert_eq!(witnesses.len(), 3);
+    assert_eq!(witnesses[0].len(), 0);
+    assert_eq!(witnesses[1].len(), placeholder_witness.as_slice().len());
+    assert_eq!(witnesses[2].len(), 0);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_create_acp_cell() {
+    let acp_data_hash = H256::from(blake2b_256(ACP_BIN));
+    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let ctx = init_context(
+        vec![(ACP_BIN, true), (SUDT_BIN, false)],
+        vec![(sender.clone(), Some(300 * ONE_CKB))],
+    );
+    let acp_script_id = ScriptId::new_data1(acp_data_hash);
+    assert!(build_acp_script(&acp_script_id, &ACCOUNT2_ARG, None, Some(2)).is_err());
+    let receiver = build_acp_script(&acp_script_id, &ACCOUNT2_ARG, Some(8), Some(2)).unwrap();
+    assert_eq!(receiver.args().raw_data().len(), 22);
+    let type_script = Script::new_builder()
+        .code_hash(sudt_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(Bytes::from(vec![9u8; 32]).pack())
+        .build();
+
+    let builder = AcpCreateBuilder::new(receiver.clone(), Some(type_script.clone()));
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH),
+        Box::new(script_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.outputs().len(), 2);
+    let output = tx.output(0).unwrap();
+    assert_eq!(output.lock(), receiver);
+    assert_eq!(output.type_().to_opt(), Some(type_script));
+    // 8 + (32 + 1 + 22) + (32 + 1 + 32) + 16
+    assert_eq!(Unpack::<u64>::unpack(&output.capacity()), 144 * ONE_CKB);
+    assert_eq!(
+        tx.outputs_data().get(0).unwrap().raw_data(),
+        Bytes::from(0u128.to_le_bytes().to_vec())
+    );
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_cheque_claim() {
+    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
+    let cheque_data_hash = H256::from(blake2b_256(CHEQUE_BIN));
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let cheque_script = build_cheque_script(&sender, &receiver, cheque_data_hash.clone());
+    let type_script = Script::new_builder()
+        .code_hash(sudt_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(Bytes::from(vec![9u8; 32]).pack())
+        .build();
+    let mut ctx = init_context(
+        vec![(CHEQUE_BIN, true), (SUDT_BIN, false)],
+        vec![
+            (receiver.clone(), Some(100 * ONE_CKB)),
+            (receiver.clone(), Some(200 * ONE_CKB)),
+        ],
+    );
+
+    let receiver_input = CellInput::new(random_out_point(), 0);
+    let receiver_output = CellOutput::new_builder()
+        .capacity((200 * ONE_CKB).pack())
+        .lock(receiver.clone())
+        .type_(Some(type_script.clone()).pack())
+        .build();
+    let receiver_data = Bytes::from(1000u128.to_le_bytes().to_vec());
+    ctx.add_live_cell(
+        receiver_input.clone(),
+        receiver_output.clone(),
+        receiver_data,
+        None,
+    );
+
+    let cheque_input = CellInput::new(random_out_point(), 0);
+    let cheque_output = CellOutput::new_builder()
+        .capacity((220 * ONE_CKB).pack())
+        .lock(cheque_script)
+        .type_(Some(type_script).pack())
+        .build();
+    let cheque_data = Bytes::from(500u128.to_le_bytes().to_vec());
+    ctx.add_live_cell(
+        cheque_input.clone(),
+        cheque_output.clone(),
+        cheque_data,
+        None,
+    );
+
+    let builder = ChequeClaimBuilder::new(vec![cheque_input], receiver_input, sender.clone());
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer =
+        CapacityBalancer::new_simple(receiver.clone(), placeholder_witness.clone(), FEE_RATE);
+
+    let account2_key = secp256k1::SecretKey::from_slice(ACCOUNT2_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account2_key]);
+    let sighash_unlocker = SecpSighashUnlocker::from(Box::new(signer.clone()) as Box<_>);
+    let cheque_unlocker = ChequeUnlocker::from((Box::new(signer) as Box<_>, ChequeAction::Claim));
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH),
+        Box::new(sighash_unlocker),
+    );
+    unlockers.insert(
+        ScriptId::new_data1(cheque_data_hash),
+        Box::new(cheque_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.header_deps().len(), 0);
+    assert_eq!(tx.cell_deps().len(), 3);
+    assert_eq!(tx.inputs().len(), 3);
+    let input_cells = vec![
+        cheque_output,
+        receiver_output.clone(),
+        CellOutput::new_builder()
+            .capacity((100 * ONE_CKB).pack())
+            .lock(receiver.clone())
+            .build(),
+    ];
+    for (idx, out_point) in tx.input_pts_iter().enumerate() {
+        assert_eq!(ctx.get_input(&out_point).unwrap().0, input_cells[idx]);
+    }
+    assert_eq!(tx.outputs().len(), 3);
+    assert_eq!(tx.output(0).unwrap(), receiver_output);
+    let sender_output = CellOutput::new_builder()
+        .capacity((220 * ONE_CKB).pack())
+        .lock(sender)
+        .build();
+    assert_eq!(tx.output(1).unwrap(), sender_output);
+    assert_eq!(tx.output(2).unwrap().lock(), receiver);
+    let expected_outputs_data = vec![
+        Bytes::from((1000u128 + 500u128).to_le_bytes().to_vec()),
+        Bytes::default(),
+        Bytes::default(),
+    ];
+    let outputs_data = tx
+        .outputs_data()
+        .into_iter()
+        .map(|d| d.raw_data())
+        .collect::<Vec<_>>();
+    assert_eq!(outputs_data, expected_outputs_data);
+    let witnesses = tx
+        .witnesses()
+        .into_iter()
+        .map(|w| w.raw_data())
+        .collect::<Vec<_>>();
+    assert_eq!(witnesses.len(), 3);
+    assert_eq!(witnesses[0].len(), 0);
+    assert_eq!(witnesses[1].len(), placeholder_witness.as_slice().len());
+    assert_eq!(witnesses[2].len(), 0);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_cheque_claim_with_discovery() {
+    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
+    let cheque_data_hash = H256::from(blake2b_256(CHEQUE_BIN));
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let cheque_script = build_cheque_script(&sender, &receiver, cheque_data_hash.clone());
+    let type_script = Script::new_builder()
+        .code_hash(sudt_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(Bytes::from(vec![9u8; 32]).pack())
+        .build();
+    let mut ctx = init_context(
+        vec![(CHEQUE_BIN, true), (SUDT_BIN, false)],
+        vec![(receiver.clone(), Some(100 * ONE_CKB))],
+    );
+
+    let receiver_input = CellInput::new(random_out_point(), 0);
+    let receiver_output = CellOutput::new_builder()
+        .capacity((200 * ONE_CKB).pack())
+        .lock(receiver.clone())
+        .type_(Some(type_script.clone()).pack())
+        .build();
+    ctx.add_live_cell(
+        receiver_input.clone(),
+        receiver_output.clone(),
+        Bytes::from(1000u128.to_le_bytes().to_vec()),
+        None,
+    );
+    for amount in [300u128, 500].iter() {
+        let cheque_output = CellOutput::new_builder()
+            .capacity((162 * ONE_CKB).pack())
+            .lock(cheque_script.clone())
+            .type_(Some(type_script.clone()).pack())
+            .build();
+        ctx.add_live_cell(
+            CellInput::new(random_out_point(), 0),
+            cheque_output,
+            Bytes::from(amount.to_le_bytes().to_vec()),
+            None,
+        );
+    }
+
+    let builder = ChequeClaimBuilder::new_with_discovery(
+        receiver_input,
+        sender.clone(),
+        ScriptId::new_data1(cheque_data_hash.clone()),
+    );
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(receiver, placeholder_witness, FEE_RATE);
+
+    let account2_key = secp256k1::SecretKey::from_slice(ACCOUNT2_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account2_key]);
+    let sighash_unlocker = SecpSighashUnlocker::from(Box::new(signer.clone()) as Box<_>);
+    let cheque_unlocker = ChequeUnlocker::from((Box::new(signer) as Box<_>, ChequeAction::Claim));
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH),
+        Box::new(sighash_unlocker),
+    );
+    unlockers.insert(
+        ScriptId::new_data1(cheque_data_hash),
+        Box::new(cheque_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+
+    assert!(locked_groups.is_empty());
+    // two cheque cells, the receiver input and the capacity provider
+    assert_eq!(tx.inputs().len(), 4);
+    assert_eq!(tx.output(0).unwrap(), receiver_output);
+    assert_eq!(
+        tx.outputs_data().get(0).unwrap().raw_data(),
+        Bytes::from((1000u128 + 300 + 500).to_le_bytes().to_vec())
+    );
+    assert_eq!(
+        tx.output(1).unwrap(),
+        CellOutput::new_builder()
+            .capacity((2 * 162 * ONE_CKB).pack())
+            .lock(sender)
+            .build()
+    );
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_cheque_withdraw() {
+    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
+    let cheque_data_hash = H256::from(blake2b_256(CHEQUE_BIN));
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let cheque_script = build_cheque_script(&sender, &receiver, cheque_data_hash.clone());
+    let type_script = Script::new_builder()
+        .code_hash(sudt_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(Bytes::from(vec![9u8; 32]).pack())
+        .build();
+    let mut ctx = init_context(
+        vec![(CHEQUE_BIN, true), (SUDT_BIN, false)],
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+        ],
+    );
+
+    let cheque_out_point = random_out_point();
+    let cheque_input = CellInput::new(cheque_out_point.clone(), CHEQUE_CELL_SINCE);
+    let cheque_output = CellOutput::new_builder()
+        .capacity((220 * ONE_CKB).pack())
+        .lock(cheque_script)
+        .type_(Some(type_script).pack())
+        .build();
+    let cheque_data = Bytes::from(500u128.to_le_bytes().to_vec());
+    ctx.add_live_cell(cheque_input, cheque_output.clone(), cheque_data, None);
+
+    let builder = ChequeWithdrawBuilder::new(vec![cheque_out_point], sender.clone(), None);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer =
+        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let sighash_unlocker = SecpSighashUnlocker::from(Box::new(signer.clone()) as Box<_>);
+    let cheque_unlocker =
+        ChequeUnlocker::from((Box::new(signer) as Box<_>, ChequeAction::Withdraw));
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH),
+        Box::new(sighash_unlocker),
+    );
+    unlockers.insert(
+        ScriptId::new_data1(cheque_data_hash),
+        Box::new(cheque_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.header_deps().len(), 0);
+    assert_eq!(tx.cell_deps().len(), 3);
+    assert_eq!(tx.inputs().len(), 2);
+    let input_cells = vec![
+        cheque_output.clone(),
+        CellOutput::new_builder()
+            .capacity((100 * ONE_CKB).pack())
+            .lock(sender.clone())
+            .build(),
+    ];
+    for (idx, out_point) in tx.input_pts_iter().enumerate() {
+        assert_eq!(ctx.get_input(&out_point).unwrap().0, input_cells[idx]);
+    }
+    assert_eq!(tx.outputs().len(), 2);
+    let sender_output = cheque_output.as_builder().lock(sender.clone()).build();
+    assert_eq!(tx.output(0).unwrap(), sender_output);
+    assert_eq!(tx.output(1).unwrap().lock(), sender);
+    let expected_outputs_data = vec![
+        Bytes::from(500u128.to_le_bytes().to_vec()),
+        Bytes::default(),
+    ];
+    let outputs_data = tx
+        .outputs_data()
+        .into_iter()
+        .map(|d| d.raw_data())
+        .collect::<Vec<_>>();
+    assert_eq!(outputs_data, expected_outputs_data);
+    let witnesses_len = tx
+        .witnesses()
+        .into_iter()
+        .map(|w| w.raw_data().len())
+        .collect::<Vec<_>>();
+    assert_eq!(witnesses_len, vec![0, placeholder_witness.as_slice().len()]);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_sweep() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        (1..=5)
+            .map(|idx| (sender.clone(), Some(idx * 100 * ONE_CKB)))
+            .collect(),
+    );
+
+    let mut builder = SweepBuilder::new(vec![sender.clone()], receiver.clone(), FEE_RATE);
+    builder.max_inputs = 3;
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let account2_key = secp256k1::SecretKey::from_slice(ACCOUNT2_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key, account2_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let txs = builder
+        .build_sweep_txs(&mut cell_collector, &ctx, &ctx, &unlockers)
+        .unwrap();
+    assert_eq!(txs.len(), 2);
+    let (first_tx, locked_groups) = txs[0].clone();
+    assert!(locked_groups.is_empty());
+    assert_eq!(first_tx.inputs().len(), 3);
+    assert_eq!(first_tx.outputs().len(), 1);
+    assert_eq!(first_tx.output(0).unwrap().lock(), receiver);
+
+    let (last_tx, locked_groups) = txs[1].clone();
+    assert!(locked_groups.is_empty());
+    assert_eq!(last_tx.inputs().len(), 3);
+    assert_eq!(
+        last_tx.inputs().get(0).unwrap().previous_output(),
+        OutPoint::new(first_tx.hash(), 0)
+    );
+    assert_eq!(last_tx.outputs().len(), 1);
+    let last_capacity: u64 = last_tx.output(0).unwrap().capacity().unpack();
+    assert!(last_capacity < 1500 * ONE_CKB);
+    assert!(last_capacity > 1499 * ONE_CKB);
+    ctx.verify(first_tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_reclaim() {
+    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let owner = build_sighash_script(H160::default());
+    let type_script = Script::new_builder()
+        .code_hash(sudt_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(owner.calc_script_hash().as_bytes().pack())
+        .build();
+    let mut ctx = init_context(vec![(SUDT_BIN, false)], Vec::new());
+    let mut cells = Vec::new();
+    for amount in [0u128, 500] {
+        let input = CellInput::new(random_out_point(), 0);
+        let output = CellOutput::new_builder()
+            .capacity((200 * ONE_CKB).pack())
+            .lock(sender.clone())
+            .type_(Some(type_script.clone()).pack())
+            .build();
+        let data = Bytes::from(amount.to_le_bytes().to_vec());
+        cells.push(input.previous_output());
+        ctx.add_live_cell(input, output, data, None);
+    }
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    // the second cell carries UDT amount
+    let mut builder = ReclaimBuilder::new(cells.clone(), receiver.clone(), FEE_RATE);
+    assert!(builder.build_reclaim_tx(&ctx, &ctx, &unlockers).is_err());
+    builder.allow_udt = true;
+    assert!(builder.build_reclaim_tx(&ctx, &ctx, &unlockers).is_ok());
+
+    let builder = ReclaimBuilder::new(vec![cells[0].clone()], receiver.clone(), FEE_RATE);
+    let (tx, locked_groups) = builder.build_reclaim_tx(&ctx, &ctx, &unlockers).unwrap();
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.cell_deps().len(), 2);
+    assert_eq!(tx.inputs().len(), 1);
+    assert_eq!(tx.outputs().len(), 1);
+    let output = tx.output(0).unwrap();
+    assert_eq!(output.lock(), receiver);
+    assert!(output.type_().is_none());
+    let capacity: u64 = output.capacity().unpack();
+    assert!(capacity < 200 * ONE_CKB);
+    assert!(capacity > 199 * ONE_CKB);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_migration() {
+    let deprecated = build_sighash_script(ACCOUNT1_ARG);
+    let target = build_sighash_script(ACCOUNT2_ARG);
+    let mut ctx = init_context(
+        vec![(ALWAYS_SUCCESS_BIN, false)],
+        (1..=4)
+            .map(|idx| (deprecated.clone(), Some(idx * 100 * ONE_CKB)))
+            .collect(),
+    );
+    let type_script = Script::new_builder()
+        .code_hash(H256::from(blake2b_256(ALWAYS_SUCCESS_BIN)).pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .build();
+    let typed_output = CellOutput::new_builder()
+        .capacity((200 * ONE_CKB).pack())
+        .lock(deprecated.clone())
+        .type_(Some(type_script).pack())
+        .build();
+    let typed_data = Bytes::from(vec![1u8; 10]);
+    ctx.add_live_cell(
+        CellInput::new(random_out_point(), 0),
+        typed_output.clone(),
+        typed_data.clone(),
+        None,
+    );
+
+    let mut builder = MigrationBuilder::new(vec![deprecated.clone()], target.clone(), FEE_RATE);
+    builder.max_inputs = 3;
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (txs, report) = builder
+        .build_migration_txs(&mut cell_collector, &ctx, &ctx, &unlockers)
+        .unwrap();
+    assert_eq!(txs.len(), 2);
+    assert_eq!(report.txs.len(), 2);
+    assert_eq!(report.cells_count(), 5);
+    assert_eq!(report.total_capacity(), 1200 * ONE_CKB);
+
+    let (first_tx, locked_groups) = txs[0].clone();
+    assert!(locked_groups.is_empty());
+    assert_eq!(first_tx.inputs().len(), 3);
+    assert_eq!(first_tx.outputs().len(), 2);
+    assert_eq!(first_tx.output(0).unwrap().lock(), target);
+    assert_eq!(
+        first_tx.output(1).unwrap(),
+        typed_output.as_builder().lock(target.clone()).build()
+    );
+    assert_eq!(
+        first_tx.outputs_data().get(1).unwrap().raw_data(),
+        typed_data
+    );
+    let (second_tx, _) = txs[1].clone();
+    assert_eq!(second_tx.inputs().len(), 2);
+    assert_eq!(second_tx.outputs().len(), 1);
+    for (tx, tx_report) in txs.iter().zip(report.txs.iter()) {
+        assert_eq!(tx.0.hash(), tx_report.tx_hash.pack());
+        let output_capacity: u64 = tx.0.outputs_capacity().unwrap().as_u64();
+        assert_eq!(output_capacity + tx_report.fee, tx_report.capacity);
+        ctx.verify(tx.0.clone(), FEE_RATE).unwrap();
+    }
+}
+
+#[test]
+fn test_type_id_deploy() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(500 * ONE_CKB)),
+            (sender.clone(), Some(600 * ONE_CKB)),
+        ],
+    );
+
+    let builder = TypeIdDeployBuilder::new(sender.clone(), Bytes::from(ALWAYS_SUCCESS_BIN));
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer =
+        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.inputs().len(), 2);
+    assert_eq!(tx.outputs().len(), 2);
+    assert_eq!(tx.output(1).unwrap().lock(), sender);
+
+    let deployment = TypeIdDeployment::from_tx(&tx, 0).unwrap();
+    assert_eq!(deployment.out_point, OutPoint::new(tx.hash(), 0));
+    assert_eq!(
+        deployment.data_hash,
+        H256::from(blake2b_256(ALWAYS_SUCCESS_BIN))
+    );
+    assert_eq!(
+        deployment.script_id(),
+        ScriptId::new_type(deployment.type_id_script.calc_script_hash().unpack())
+    );
+    assert!(TypeIdDeployment::from_tx(&tx, 1).is_err());
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_type_id_upgrade() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let mut ctx = init_context(Vec::new(), vec![(sender.clone(), Some(2000 * ONE_CKB))]);
+
+    let type_id_script = Script::new_builder()
+        .code_hash(TYPE_ID_CODE_HASH.pack())
+        .hash_type(ScriptHashType::Type.into())
+        .args(Bytes::from(vec![3u8; 32]).pack())
+        .build();
+    let old_data = Bytes::from(ALWAYS_SUCCESS_BIN);
+    let old_output = CellOutput::new_builder()
+        .lock(sender.clone())
+        .type_(Some(type_id_script.clone()).pack())
+        .build_exact_capacity(Capacity::bytes(old_data.len()).unwrap())
+        .unwrap();
+    let old_capacity: u64 = old_output.capacity().unpack();
+    ctx.add_live_cell(
+        CellInput::new(random_out_point(), 0),
+        old_output,
+        old_data,
+        None,
+    );
+
+    let new_data = Bytes::from(SUDT_BIN);
+    let builder = TypeIdUpgradeBuilder::new(type_id_script.clone(), new_data.clone());
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer =
+        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.inputs().len(), 2);
+    assert_eq!(tx.outputs().len(), 2);
+    let deployment = TypeIdDeployment::from_tx(&tx, 0).unwrap();
+    assert_eq!(deployment.type_id_script, type_id_script);
+    assert_eq!(deployment.code_hash(), builder.code_hash());
+    assert_eq!(deployment.data_hash, H256::from(blake2b_256(SUDT_BIN)));
+    let new_capacity: u64 = tx.output(0).unwrap().capacity().unpack();
+    assert!(new_capacity > old_capacity);
+    assert_eq!(tx.outputs_data().get(0).unwrap().raw_data(), new_data);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_state_cell() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let mut ctx = init_context(Vec::new(), vec![(sender.clone(), Some(2000 * ONE_CKB))]);
+
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer =
+        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    // create
+    let builder = StateCellCreateBuilder::<Uint64>::new(sender.clone(), 1u64.pack());
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    let created = StateCell::<Uint64>::from_tx(&tx, 0).unwrap();
+    assert_eq!(created.schema_version, 0);
+    assert_eq!(created.revision, 0);
+    let state: u64 = created.state.unpack();
+    assert_eq!(state, 1);
+    ctx.verify(tx, FEE_RATE).unwrap();
+
+    // update
+    let type_script = Script::new_builder()
+        .code_hash(TYPE_ID_CODE_HASH.pack())
+        .hash_type(ScriptHashType::Type.into())
+        .args(Bytes::from(vec![5u8; 32]).pack())
+        .build();
+    let data = encode_state_data(0, 3, &Pack::<Uint64>::pack(&7u64));
+    let output = CellOutput::new_builder()
+        .lock(sender.clone())
+        .type_(Some(type_script.clone()).pack())
+        .build_exact_capacity(Capacity::bytes(data.len()).unwrap())
+        .unwrap();
+    ctx.add_live_cell(CellInput::new(random_out_point(), 0), output, data, None);
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let cell = StateCell::<Uint64>::load(&mut cell_collector, &type_script).unwrap();
+    assert_eq!(cell.revision, 3);
+    let (tx, _) = cell
+        .update(8u64.pack())
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    let updated = StateCell::<Uint64>::from_tx(&tx, 0).unwrap();
+    assert_eq!(updated.type_script, type_script);
+    assert_eq!(updated.revision, 4);
+    let state: u64 = updated.state.unpack();
+    assert_eq!(state, 8);
+    ctx.verify(tx, FEE_RATE).unwrap();
+
+    // migrate to a new schema
+    let mut cell_collector = ctx.to_live_cells_context();
+    let new_state: packed::Bytes = Bytes::from(vec![1u8; 20]).pack();
+    let (tx, _) = cell
+        .migrate(1, new_state.clone())
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    let migrated = StateCell::<packed::Bytes>::from_tx(&tx, 0).unwrap();
+    assert_eq!(migrated.schema_version, 1);
+    assert_eq!(migrated.revision, 4);
+    assert_eq!(migrated.state, new_state);
+    ctx.verify(tx, FEE_RATE).unwrap();
+
+    // consume
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, _) = cell
+        .consume()
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert!(tx
+        .outputs()
+        .into_iter()
+        .all(|output| output.type_().is_none()));
+    ctx.verify(tx, FEE_RATE).unwrap();
+
+    // the cell changed since read
+    let mut stale = cell.clone();
+    stale.out_point = random_out_point();
+    let mut cell_collector = ctx.to_live_cells_context();
+    let err = stale
+        .update(9u64.pack())
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap_err();
+    assert!(err.to_string().contains("state cell changed since read"));
+}
+
+#[test]
+fn test_dao_deposit() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+            (sender.clone(), Some(300 * ONE_CKB)),
+        ],
+    );
+
+    let deposit_receiver = DaoDepositReceiver::new(sender.clone(), 120 * ONE_CKB);
+    let builder = DaoDepositBuilder::new(vec![deposit_receiver]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer =
+        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.header_deps().len(), 0);
+    assert_eq!(tx.cell_deps().len(), 2);
+    assert_eq!(tx.inputs().len(), 2);
+    for out_point in tx.input_pts_iter() {
+        assert_eq!(ctx.get_input(&out_point).unwrap().0.lock(), sender);
+    }
+    assert_eq!(tx.outputs().len(), 2);
+    let deposit_output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(sender.clone())
+        .type_(Some(build_dao_script()).pack())
+        .build();
+    assert_eq!(tx.output(0).unwrap(), deposit_output);
+    assert_eq!(tx.output(1).unwrap().lock(), sender);
+    let expected_outputs_data = vec![Bytes::from(vec![0u8; 8]), Bytes::default()];
+    let outputs_data = tx
+        .outputs_data()
+        .into_iter()
+        .map(|d| d.raw_data())
+        .collect::<Vec<_>>();
+    assert_eq!(outputs_data, expected_outputs_data);
+    let witnesses_len = tx
+        .witnesses()
+        .into_iter()
+        .map(|w| w.raw_data().len())
+        .collect::<Vec<_>>();
+    assert_eq!(witnesses_len, vec![placeholder_witness.as_slice().len(), 0]);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_dao_deposit_with_change_template() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+            (sender.clone(), Some(300 * ONE_CKB)),
+        ],
+    );
+
+    let deposit_receiver = DaoDepositReceiver::new(sender.clone(), 200 * ONE_CKB);
+    let builder = DaoDepositBuilder::new(vec![deposit_receiver]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let mut balancer =
+        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+    // The change is deposited too
+    balancer.set_change_template(Some(ChangeTemplate::new(
+        Some(build_dao_script()),
+        Bytes::from(vec![0u8; 8]),
+    )));
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    // The 100 CKB left by the first two cells can not hold a deposit cell
+    // (102 CKB occupied capacity)
+    assert_eq!(tx.inputs().len(), 3);
+    assert_eq!(tx.outputs().len(), 2);
+    let change_output = tx.output(1).unwrap();
+    assert_eq!(change_output.lock(), sender);
+    assert_eq!(change_output.type_().to_opt(), Some(build_dao_script()));
+    assert_eq!(
+        tx.outputs_data().get(1).unwrap().raw_data(),
+        Bytes::from(vec![0u8; 8])
+    );
+    let change_capacity: u64 = change_output.capacity().unpack();
+    let occupied_capacity = change_output
+        .occupied_capacity(Capacity::bytes(8).unwrap())
+        .unwrap()
+        .as_u64();
+    assert_eq!(occupied_capacity, 102 * ONE_CKB);
+    assert!(change_capacity >= occupied_capacity);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_dao_batch_deposit() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+            (sender.clone(), Some(300 * ONE_CKB)),
+        ],
+    );
+
+    let builder =
+        DaoBatchDepositBuilder::new_split(sender.clone(), 350 * ONE_CKB, 120 * ONE_CKB).unwrap();
+    assert_eq!(builder.receivers().len(), 3);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.outputs().len(), 4);
+    let capacities = tx
+        .outputs()
+        .into_iter()
+        .take(3)
+        .map(|output| {
+            assert_eq!(output.type_().to_opt(), Some(build_dao_script()));
+            output.capacity().unpack()
+        })
+        .collect::<Vec<u64>>();
+    assert_eq!(
+        capacities,
+        vec![120 * ONE_CKB, 120 * ONE_CKB, 110 * ONE_CKB]
+    );
+    assert_eq!(tx.output(3).unwrap().lock(), sender);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_dao_batch_prepare() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let mut ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+        ],
+    );
+
+    let deposit_point = EpochNumberWithFraction::new(5, 5, 1000);
+    let deposit_number: u64 = 5 * 1000 + 5;
+    let deposit_header = HeaderBuilder::default()
+        .epoch(deposit_point.full_value().pack())
+        .number(deposit_number.pack())
+        .build();
+    let deposit_block_hash = deposit_header.hash();
+    let deposit_output = CellOutput::new_builder()
+        .capacity((220 * ONE_CKB).pack())
+        .lock(sender.clone())
+        .type_(Some(build_dao_script()).pack())
+        .build();
+    for _ in 0..3 {
+        ctx.add_live_cell(
+            CellInput::new(random_out_point(), 0),
+            deposit_output.clone(),
+            Bytes::from(vec![0u8; 8]),
+            Some(deposit_block_hash.clone()),
+        );
+    }
+    ctx.add_header(deposit_header);
+
+    let builder = DaoBatchPrepareBuilder::new(sender.clone());
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+
+    assert!(locked_groups.is_empty());
+    assert_eq!(
+        tx.header_deps().into_iter().collect::<Vec<_>>(),
+        vec![deposit_block_hash]
+    );
+    assert_eq!(tx.inputs().len(), 4);
+    for idx in 0..3 {
+        assert_eq!(tx.output(idx).unwrap(), deposit_output);
+        assert_eq!(
+            tx.outputs_data().get(idx).unwrap().raw_data(),
+            Bytes::from(deposit_number.to_le_bytes().to_vec())
+        );
+    }
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_dao_prepare() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let mut ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+            (sender.clone(), Some(300 * ONE_CKB)),
+        ],
+    );
+
+    let deposit_point = (5, 5, 1000);
+    let deposit_number = deposit_point.0 * deposit_point.2 + deposit_point.1;
+    let deposit_point =
+        EpochNumberWithFraction::new(deposit_point.0, deposit_point.1, deposit_point.2);
+
+    let deposit_input = CellInput::new(random_out_point(), 0);
+    let deposit_output = CellOutput::new_builder()
+        .capacity((220 * ONE_CKB).pack())
+        .lock(sender.clone())
+        .type_(Some(build_dao_script()).pack())
+        .build();
+    let deposit_header = HeaderBuilder::default()
+        .epoch(deposit_point.full_value().pack())
+        .number(deposit_number.pack())
+        .build();
+    let deposit_block_hash = deposit_header.hash();
+    ctx.add_live_cell(
+        deposit_input.clone(),
+        deposit_output.clone(),
+        Bytes::from(vec![0u8; 8]),
+        Some(deposit_block_hash.clone()),
+    );
+    ctx.add_header(deposit_header);
 
-    let builder = ChequeClaimBuilder::new(vec![cheque_input], receiver_input, sender.clone());
+    let builder = DaoPrepareBuilder::from(vec![deposit_input]);
     let placeholder_witness = WitnessArgs::new_builder()
         .lock(Some(Bytes::from(vec![0u8; 65])).pack())
         .build();
     let balancer =
-        CapacityBalancer::new_simple(receiver.clone(), placeholder_witness.clone(), FEE_RATE);
+        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
 
-    let account2_key = secp256k1::SecretKey::from_slice(ACCOUNT2_KEY.as_bytes()).unwrap();
-    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account2_key]);
-    let sighash_unlocker = SecpSighashUnlocker::from(Box::new(signer.clone()) as Box<_>);
-    let cheque_unlocker = ChequeUnlocker::from((Box::new(signer) as Box<_>, ChequeAction::Claim));
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
     let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
     unlockers.insert(
-        ScriptId::new_type(SIGHASH_TYPE_HASH),
-        Box::new(sighash_unlocker),
-    );
-    unlockers.insert(
-        ScriptId::new_data1(cheque_data_hash),
-        Box::new(cheque_unlocker),
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
     );
 
     let mut cell_collector = ctx.to_live_cells_context();
@@ -468,31 +5152,20 @@ fn test_cheque_claim() {
         .unwrap();
 
     assert!(locked_groups.is_empty());
-    assert_eq!(tx.header_deps().len(), 0);
-    assert_eq!(tx.cell_deps().len(), 3);
-    assert_eq!(tx.inputs().len(), 3);
-    let input_cells = vec![
-        cheque_output,
-        receiver_output.clone(),
-        CellOutput::new_builder()
-            .capacity((100 * ONE_CKB).pack())
-            .lock(receiver.clone())
-            .build(),
-    ];
-    for (idx, out_point) in tx.input_pts_iter().enumerate() {
-        assert_eq!(ctx.get_input(&out_point).unwrap().0, input_cells[idx]);
+    assert_eq!(
+        tx.header_deps().into_iter().collect::<Vec<_>>(),
+        vec![deposit_block_hash]
+    );
+    assert_eq!(tx.cell_deps().len(), 2);
+    assert_eq!(tx.inputs().len(), 2);
+    for out_point in tx.input_pts_iter() {
+        assert_eq!(ctx.get_input(&out_point).unwrap().0.lock(), sender);
     }
-    assert_eq!(tx.outputs().len(), 3);
-    assert_eq!(tx.output(0).unwrap(), receiver_output);
-    let sender_output = CellOutput::new_builder()
-        .capacity((220 * ONE_CKB).pack())
-        .lock(sender)
-        .build();
-    assert_eq!(tx.output(1).unwrap(), sender_output);
-    assert_eq!(tx.output(2).unwrap().lock(), receiver);
+    assert_eq!(tx.outputs().len(), 2);
+    assert_eq!(tx.output(0).unwrap(), deposit_output);
+    assert_eq!(tx.output(1).unwrap().lock(), sender);
     let expected_outputs_data = vec![
-        Bytes::from((1000u128 + 500u128).to_le_bytes().to_vec()),
-        Bytes::default(),
+        Bytes::from(deposit_number.to_le_bytes().to_vec()),
         Bytes::default(),
     ];
     let outputs_data = tx
@@ -501,68 +5174,98 @@ fn test_cheque_claim() {
         .map(|d| d.raw_data())
         .collect::<Vec<_>>();
     assert_eq!(outputs_data, expected_outputs_data);
-    let witnesses = tx
+    let witnesses_len = tx
         .witnesses()
         .into_iter()
-        .map(|w| w.raw_data())
+        .map(|w| w.raw_data().len())
         .collect::<Vec<_>>();
-    assert_eq!(witnesses.len(), 3);
-    assert_eq!(witnesses[0].len(), 0);
-    assert_eq!(witnesses[1].len(), placeholder_witness.as_slice().len());
-    assert_eq!(witnesses[2].len(), 0);
+    assert_eq!(witnesses_len, vec![placeholder_witness.as_slice().len(), 0]);
     ctx.verify(tx, FEE_RATE).unwrap();
 }
 
 #[test]
-fn test_cheque_withdraw() {
-    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
-    let cheque_data_hash = H256::from(blake2b_256(CHEQUE_BIN));
+fn test_dao_withdraw() {
     let sender = build_sighash_script(ACCOUNT1_ARG);
-    let receiver = build_sighash_script(ACCOUNT2_ARG);
-    let cheque_script = build_cheque_script(&sender, &receiver, cheque_data_hash.clone());
-    let type_script = Script::new_builder()
-        .code_hash(sudt_data_hash.pack())
-        .hash_type(ScriptHashType::Data1.into())
-        .args(Bytes::from(vec![9u8; 32]).pack())
-        .build();
     let mut ctx = init_context(
-        vec![(CHEQUE_BIN, true), (SUDT_BIN, false)],
+        Vec::new(),
         vec![
             (sender.clone(), Some(100 * ONE_CKB)),
             (sender.clone(), Some(200 * ONE_CKB)),
+            (sender.clone(), Some(300 * ONE_CKB)),
         ],
     );
 
-    let cheque_out_point = random_out_point();
-    let cheque_input = CellInput::new(cheque_out_point.clone(), CHEQUE_CELL_SINCE);
-    let cheque_output = CellOutput::new_builder()
+    let (deposit_point, prepare_point) = ((5, 5, 1000), (184, 4, 1000));
+    let deposit_number = deposit_point.0 * deposit_point.2 + deposit_point.1;
+    let prepare_number = prepare_point.0 * prepare_point.2 + prepare_point.1;
+    let deposit_point =
+        EpochNumberWithFraction::new(deposit_point.0, deposit_point.1, deposit_point.2);
+    let prepare_point =
+        EpochNumberWithFraction::new(prepare_point.0, prepare_point.1, prepare_point.2);
+    let deposit_header = HeaderBuilder::default()
+        .epoch(deposit_point.full_value().pack())
+        .number(deposit_number.pack())
+        .dao(pack_dao_data(
+            10_000_000_000_123_456,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        ))
+        .build();
+    let prepare_header = HeaderBuilder::default()
+        .epoch(prepare_point.full_value().pack())
+        .number(prepare_number.pack())
+        .dao(pack_dao_data(
+            10_000_000_001_123_456,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        ))
+        .build();
+    let deposit_block_hash = deposit_header.hash();
+    let prepare_block_hash = prepare_header.hash();
+
+    let unlock_point = minimal_unlock_point(&deposit_header, &prepare_header);
+    let since = Since::new(
+        SinceType::EpochNumberWithFraction,
+        unlock_point.full_value(),
+        false,
+    );
+    let prepare_out_point = random_out_point();
+    let prepare_input = CellInput::new(prepare_out_point.clone(), since.value());
+    let prepare_output = CellOutput::new_builder()
         .capacity((220 * ONE_CKB).pack())
-        .lock(cheque_script)
-        .type_(Some(type_script).pack())
+        .lock(sender.clone())
+        .type_(Some(build_dao_script()).pack())
         .build();
-    let cheque_data = Bytes::from(500u128.to_le_bytes().to_vec());
-    ctx.add_live_cell(cheque_input, cheque_output.clone(), cheque_data, None);
+    ctx.add_live_cell(
+        prepare_input,
+        prepare_output.clone(),
+        Bytes::from(deposit_number.to_le_bytes().to_vec()),
+        Some(prepare_block_hash.clone()),
+    );
+    ctx.add_header(deposit_header.clone());
+    ctx.add_header(prepare_header.clone());
 
-    let builder = ChequeWithdrawBuilder::new(vec![cheque_out_point], sender.clone(), None);
     let placeholder_witness = WitnessArgs::new_builder()
         .lock(Some(Bytes::from(vec![0u8; 65])).pack())
         .build();
+    let withdraw_item = DaoWithdrawItem::new(prepare_out_point, Some(placeholder_witness.clone()));
+    let withdraw_receiver = DaoWithdrawReceiver::LockScript {
+        script: sender.clone(),
+        fee_rate: None,
+    };
+    let builder = DaoWithdrawBuilder::new(vec![withdraw_item], withdraw_receiver);
     let balancer =
         CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
 
     let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
     let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
-    let sighash_unlocker = SecpSighashUnlocker::from(Box::new(signer.clone()) as Box<_>);
-    let cheque_unlocker =
-        ChequeUnlocker::from((Box::new(signer) as Box<_>, ChequeAction::Withdraw));
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
     let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
     unlockers.insert(
-        ScriptId::new_type(SIGHASH_TYPE_HASH),
-        Box::new(sighash_unlocker),
-    );
-    unlockers.insert(
-        ScriptId::new_data1(cheque_data_hash),
-        Box::new(cheque_unlocker),
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
     );
 
     let mut cell_collector = ctx.to_live_cells_context();
@@ -571,27 +5274,34 @@ fn test_cheque_withdraw() {
         .unwrap();
 
     assert!(locked_groups.is_empty());
-    assert_eq!(tx.header_deps().len(), 0);
-    assert_eq!(tx.cell_deps().len(), 3);
+    assert_eq!(
+        tx.header_deps().into_iter().collect::<Vec<_>>(),
+        vec![deposit_block_hash, prepare_block_hash]
+    );
+    assert_eq!(tx.cell_deps().len(), 2);
     assert_eq!(tx.inputs().len(), 2);
-    let input_cells = vec![
-        cheque_output.clone(),
-        CellOutput::new_builder()
-            .capacity((100 * ONE_CKB).pack())
-            .lock(sender.clone())
-            .build(),
-    ];
-    for (idx, out_point) in tx.input_pts_iter().enumerate() {
-        assert_eq!(ctx.get_input(&out_point).unwrap().0, input_cells[idx]);
+    for out_point in tx.input_pts_iter() {
+        assert_eq!(ctx.get_input(&out_point).unwrap().0.lock(), sender);
     }
     assert_eq!(tx.outputs().len(), 2);
-    let sender_output = cheque_output.as_builder().lock(sender.clone()).build();
-    assert_eq!(tx.output(0).unwrap(), sender_output);
+    let occupied_capacity = prepare_output
+        .occupied_capacity(Capacity::bytes(8).unwrap())
+        .unwrap()
+        .as_u64();
+    let expected_capacity = calculate_dao_maximum_withdraw4(
+        &deposit_header,
+        &prepare_header,
+        &prepare_output,
+        occupied_capacity,
+    );
+    let expected_output = prepare_output
+        .as_builder()
+        .capacity(expected_capacity.pack())
+        .type_(ScriptOpt::default())
+        .build();
+    assert_eq!(tx.output(0).unwrap(), expected_output);
     assert_eq!(tx.output(1).unwrap().lock(), sender);
-    let expected_outputs_data = vec![
-        Bytes::from(500u128.to_le_bytes().to_vec()),
-        Bytes::default(),
-    ];
+    let expected_outputs_data = vec![Bytes::default(), Bytes::default()];
     let outputs_data = tx
         .outputs_data()
         .into_iter()
@@ -603,29 +5313,41 @@ fn test_cheque_withdraw() {
         .into_iter()
         .map(|w| w.raw_data().len())
         .collect::<Vec<_>>();
-    assert_eq!(witnesses_len, vec![0, placeholder_witness.as_slice().len()]);
+    let witness = placeholder_witness
+        .as_builder()
+        .input_type(Some(Bytes::from(vec![0u8; 8])).pack())
+        .build();
+    assert_eq!(witnesses_len, vec![witness.as_slice().len(), 0]);
     ctx.verify(tx, FEE_RATE).unwrap();
 }
 
 #[test]
-fn test_dao_deposit() {
-    let sender = build_sighash_script(ACCOUNT1_ARG);
+fn test_udt_issue() {
+    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
+    let owner = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
     let ctx = init_context(
-        Vec::new(),
+        vec![(SUDT_BIN, false)],
         vec![
-            (sender.clone(), Some(100 * ONE_CKB)),
-            (sender.clone(), Some(200 * ONE_CKB)),
-            (sender.clone(), Some(300 * ONE_CKB)),
+            (owner.clone(), Some(100 * ONE_CKB)),
+            (owner.clone(), Some(200 * ONE_CKB)),
+            (owner.clone(), Some(300 * ONE_CKB)),
         ],
     );
 
-    let deposit_receiver = DaoDepositReceiver::new(sender.clone(), 120 * ONE_CKB);
-    let builder = DaoDepositBuilder::new(vec![deposit_receiver]);
+    let sudt_script_id = ScriptId::new_data1(sudt_data_hash.clone());
+    let udt_receiver = UdtTargetReceiver::new(TransferAction::Create, receiver.clone(), 500);
+    let builder = UdtIssueBuilder {
+        udt_type: UdtType::Sudt,
+        script_id: sudt_script_id,
+        owner: owner.clone(),
+        receivers: vec![udt_receiver],
+    };
     let placeholder_witness = WitnessArgs::new_builder()
         .lock(Some(Bytes::from(vec![0u8; 65])).pack())
         .build();
     let balancer =
-        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+        CapacityBalancer::new_simple(owner.clone(), placeholder_witness.clone(), FEE_RATE);
 
     let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
     let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
@@ -646,17 +5368,32 @@ fn test_dao_deposit() {
     assert_eq!(tx.cell_deps().len(), 2);
     assert_eq!(tx.inputs().len(), 2);
     for out_point in tx.input_pts_iter() {
-        assert_eq!(ctx.get_input(&out_point).unwrap().0.lock(), sender);
+        assert_eq!(ctx.get_input(&out_point).unwrap().0.lock(), owner);
     }
     assert_eq!(tx.outputs().len(), 2);
-    let deposit_output = CellOutput::new_builder()
-        .capacity((120 * ONE_CKB).pack())
-        .lock(sender.clone())
-        .type_(Some(build_dao_script()).pack())
+    let type_script = Script::new_builder()
+        .code_hash(sudt_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(owner.calc_script_hash().as_bytes().pack())
         .build();
-    assert_eq!(tx.output(0).unwrap(), deposit_output);
-    assert_eq!(tx.output(1).unwrap().lock(), sender);
-    let expected_outputs_data = vec![Bytes::from(vec![0u8; 8]), Bytes::default()];
+    let output = CellOutput::new_builder()
+        .lock(receiver)
+        .type_(Some(type_script).pack())
+        .build();
+    let occupied_capacity = output
+        .occupied_capacity(Capacity::bytes(16).unwrap())
+        .unwrap()
+        .as_u64();
+    let output = output
+        .as_builder()
+        .capacity(occupied_capacity.pack())
+        .build();
+    assert_eq!(tx.output(0).unwrap(), output);
+    assert_eq!(tx.output(1).unwrap().lock(), owner);
+    let expected_outputs_data = vec![
+        Bytes::from(500u128.to_le_bytes().to_vec()),
+        Bytes::default(),
+    ];
     let outputs_data = tx
         .outputs_data()
         .into_iter()
@@ -673,47 +5410,39 @@ fn test_dao_deposit() {
 }
 
 #[test]
-fn test_dao_prepare() {
-    let sender = build_sighash_script(ACCOUNT1_ARG);
-    let mut ctx = init_context(
-        Vec::new(),
+fn test_udt_issue_multiple_receivers() {
+    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
+    let owner = build_sighash_script(ACCOUNT1_ARG);
+    let receiver1 = build_sighash_script(ACCOUNT2_ARG);
+    let receiver2 = build_sighash_script(ACCOUNT3_ARG);
+    let ctx = init_context(
+        vec![(SUDT_BIN, false)],
         vec![
-            (sender.clone(), Some(100 * ONE_CKB)),
-            (sender.clone(), Some(200 * ONE_CKB)),
-            (sender.clone(), Some(300 * ONE_CKB)),
+            (owner.clone(), Some(100 * ONE_CKB)),
+            (owner.clone(), Some(200 * ONE_CKB)),
+            (owner.clone(), Some(300 * ONE_CKB)),
         ],
     );
 
-    let deposit_point = (5, 5, 1000);
-    let deposit_number = deposit_point.0 * deposit_point.2 + deposit_point.1;
-    let deposit_point =
-        EpochNumberWithFraction::new(deposit_point.0, deposit_point.1, deposit_point.2);
-
-    let deposit_input = CellInput::new(random_out_point(), 0);
-    let deposit_output = CellOutput::new_builder()
-        .capacity((220 * ONE_CKB).pack())
-        .lock(sender.clone())
-        .type_(Some(build_dao_script()).pack())
-        .build();
-    let deposit_header = HeaderBuilder::default()
-        .epoch(deposit_point.full_value().pack())
-        .number(deposit_number.pack())
-        .build();
-    let deposit_block_hash = deposit_header.hash();
-    ctx.add_live_cell(
-        deposit_input.clone(),
-        deposit_output.clone(),
-        Bytes::from(vec![0u8; 8]),
-        Some(deposit_block_hash.clone()),
+    let builder = UdtIssueBuilder::new(
+        UdtType::Sudt,
+        ScriptId::new_data1(sudt_data_hash),
+        owner.clone(),
+        vec![
+            UdtTargetReceiver::new(TransferAction::Create, receiver1.clone(), 500),
+            UdtTargetReceiver::new(TransferAction::Create, receiver2.clone(), 300),
+        ],
+    );
+    let type_script = builder.type_script();
+    assert_eq!(
+        type_script.args().raw_data(),
+        owner.calc_script_hash().as_bytes()
     );
-    ctx.add_header(deposit_header);
-
-    let builder = DaoPrepareBuilder::from(vec![deposit_input]);
     let placeholder_witness = WitnessArgs::new_builder()
         .lock(Some(Bytes::from(vec![0u8; 65])).pack())
         .build();
     let balancer =
-        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+        CapacityBalancer::new_simple(owner.clone(), placeholder_witness.clone(), FEE_RATE);
 
     let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
     let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
@@ -730,112 +5459,69 @@ fn test_dao_prepare() {
         .unwrap();
 
     assert!(locked_groups.is_empty());
-    assert_eq!(
-        tx.header_deps().into_iter().collect::<Vec<_>>(),
-        vec![deposit_block_hash]
-    );
-    assert_eq!(tx.cell_deps().len(), 2);
-    assert_eq!(tx.inputs().len(), 2);
     for out_point in tx.input_pts_iter() {
-        assert_eq!(ctx.get_input(&out_point).unwrap().0.lock(), sender);
+        assert_eq!(ctx.get_input(&out_point).unwrap().0.lock(), owner);
     }
-    assert_eq!(tx.outputs().len(), 2);
-    assert_eq!(tx.output(0).unwrap(), deposit_output);
-    assert_eq!(tx.output(1).unwrap().lock(), sender);
-    let expected_outputs_data = vec![
-        Bytes::from(deposit_number.to_le_bytes().to_vec()),
-        Bytes::default(),
-    ];
+    assert_eq!(tx.outputs().len(), 3);
+    assert_eq!(tx.output(0).unwrap().lock(), receiver1);
+    assert_eq!(tx.output(1).unwrap().lock(), receiver2);
+    assert_eq!(
+        tx.output(0).unwrap().type_().to_opt(),
+        Some(type_script.clone())
+    );
+    assert_eq!(tx.output(1).unwrap().type_().to_opt(), Some(type_script));
+    assert_eq!(tx.output(2).unwrap().lock(), owner);
     let outputs_data = tx
         .outputs_data()
         .into_iter()
         .map(|d| d.raw_data())
         .collect::<Vec<_>>();
-    assert_eq!(outputs_data, expected_outputs_data);
-    let witnesses_len = tx
-        .witnesses()
-        .into_iter()
-        .map(|w| w.raw_data().len())
-        .collect::<Vec<_>>();
-    assert_eq!(witnesses_len, vec![placeholder_witness.as_slice().len(), 0]);
+    assert_eq!(
+        outputs_data,
+        vec![
+            Bytes::from(500u128.to_le_bytes().to_vec()),
+            Bytes::from(300u128.to_le_bytes().to_vec()),
+            Bytes::default(),
+        ]
+    );
     ctx.verify(tx, FEE_RATE).unwrap();
 }
 
 #[test]
-fn test_dao_withdraw() {
+fn test_udt_burn() {
+    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
     let sender = build_sighash_script(ACCOUNT1_ARG);
+    let reclaim_lock = build_sighash_script(ACCOUNT2_ARG);
+    let owner = build_sighash_script(H160::default());
+    let type_script = Script::new_builder()
+        .code_hash(sudt_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(owner.calc_script_hash().as_bytes().pack())
+        .build();
     let mut ctx = init_context(
-        Vec::new(),
+        vec![(SUDT_BIN, false)],
         vec![
             (sender.clone(), Some(100 * ONE_CKB)),
             (sender.clone(), Some(200 * ONE_CKB)),
-            (sender.clone(), Some(300 * ONE_CKB)),
         ],
     );
 
-    let (deposit_point, prepare_point) = ((5, 5, 1000), (184, 4, 1000));
-    let deposit_number = deposit_point.0 * deposit_point.2 + deposit_point.1;
-    let prepare_number = prepare_point.0 * prepare_point.2 + prepare_point.1;
-    let deposit_point =
-        EpochNumberWithFraction::new(deposit_point.0, deposit_point.1, deposit_point.2);
-    let prepare_point =
-        EpochNumberWithFraction::new(prepare_point.0, prepare_point.1, prepare_point.2);
-    let deposit_header = HeaderBuilder::default()
-        .epoch(deposit_point.full_value().pack())
-        .number(deposit_number.pack())
-        .dao(pack_dao_data(
-            10_000_000_000_123_456,
-            Default::default(),
-            Default::default(),
-            Default::default(),
-        ))
-        .build();
-    let prepare_header = HeaderBuilder::default()
-        .epoch(prepare_point.full_value().pack())
-        .number(prepare_number.pack())
-        .dao(pack_dao_data(
-            10_000_000_001_123_456,
-            Default::default(),
-            Default::default(),
-            Default::default(),
-        ))
-        .build();
-    let deposit_block_hash = deposit_header.hash();
-    let prepare_block_hash = prepare_header.hash();
-
-    let unlock_point = minimal_unlock_point(&deposit_header, &prepare_header);
-    let since = Since::new(
-        SinceType::EpochNumberWithFraction,
-        unlock_point.full_value(),
-        false,
-    );
-    let prepare_out_point = random_out_point();
-    let prepare_input = CellInput::new(prepare_out_point.clone(), since.value());
-    let prepare_output = CellOutput::new_builder()
-        .capacity((220 * ONE_CKB).pack())
+    let sender_input = CellInput::new(random_out_point(), 0);
+    let sender_output = CellOutput::new_builder()
+        .capacity((200 * ONE_CKB).pack())
         .lock(sender.clone())
-        .type_(Some(build_dao_script()).pack())
+        .type_(Some(type_script.clone()).pack())
         .build();
-    ctx.add_live_cell(
-        prepare_input,
-        prepare_output.clone(),
-        Bytes::from(deposit_number.to_le_bytes().to_vec()),
-        Some(prepare_block_hash.clone()),
-    );
-    ctx.add_header(deposit_header.clone());
-    ctx.add_header(prepare_header.clone());
+    let sender_data = Bytes::from(500u128.to_le_bytes().to_vec());
+    ctx.add_live_cell(sender_input, sender_output.clone(), sender_data, None);
 
+    let mut builder = UdtBurnBuilder::new(type_script, sender.clone(), Some(300));
+    builder.reclaim_lock = Some(reclaim_lock.clone());
+    assert!(!builder.is_owner_mode().unwrap());
     let placeholder_witness = WitnessArgs::new_builder()
         .lock(Some(Bytes::from(vec![0u8; 65])).pack())
         .build();
-    let withdraw_item = DaoWithdrawItem::new(prepare_out_point, Some(placeholder_witness.clone()));
-    let withdraw_receiver = DaoWithdrawReceiver::LockScript {
-        script: sender.clone(),
-        fee_rate: None,
-    };
-    let builder = DaoWithdrawBuilder::new(vec![withdraw_item], withdraw_receiver);
-    let balancer =
-        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+    let balancer = CapacityBalancer::new_simple(sender, placeholder_witness, FEE_RATE);
 
     let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
     let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
@@ -852,138 +5538,286 @@ fn test_dao_withdraw() {
         .unwrap();
 
     assert!(locked_groups.is_empty());
-    assert_eq!(
-        tx.header_deps().into_iter().collect::<Vec<_>>(),
-        vec![deposit_block_hash, prepare_block_hash]
-    );
-    assert_eq!(tx.cell_deps().len(), 2);
-    assert_eq!(tx.inputs().len(), 2);
-    for out_point in tx.input_pts_iter() {
-        assert_eq!(ctx.get_input(&out_point).unwrap().0.lock(), sender);
-    }
-    assert_eq!(tx.outputs().len(), 2);
-    let occupied_capacity = prepare_output
-        .occupied_capacity(Capacity::bytes(8).unwrap())
+    let change_udt_output = tx.output(0).unwrap();
+    let change_capacity = change_udt_output
+        .occupied_capacity(Capacity::bytes(16).unwrap())
         .unwrap()
         .as_u64();
-    let expected_capacity = calculate_dao_maximum_withdraw4(
-        &deposit_header,
-        &prepare_header,
-        &prepare_output,
-        occupied_capacity,
+    assert_eq!(
+        change_udt_output,
+        sender_output
+            .as_builder()
+            .capacity(change_capacity.pack())
+            .build()
     );
-    let expected_output = prepare_output
-        .as_builder()
-        .capacity(expected_capacity.pack())
-        .type_(ScriptOpt::default())
+    let reclaim_output = tx.output(1).unwrap();
+    assert_eq!(reclaim_output.lock(), reclaim_lock);
+    let reclaim_capacity: u64 = reclaim_output.capacity().unpack();
+    assert_eq!(reclaim_capacity, 200 * ONE_CKB - change_capacity);
+    assert_eq!(
+        tx.outputs_data().get(0).unwrap().raw_data(),
+        Bytes::from(200u128.to_le_bytes().to_vec())
+    );
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+fn build_escrow_cell(
+    ctx: &mut Context,
+    config: &EscrowConfig,
+    buyer_key: secp256k1::SecretKey,
+    type_script: &Script,
+) -> OutPoint {
+    let buyer = build_sighash_script(config.buyer.clone());
+    let builder = EscrowCreateBuilder::new(config.clone(), buyer.clone(), type_script.clone(), 300);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
         .build();
-    assert_eq!(tx.output(0).unwrap(), expected_output);
-    assert_eq!(tx.output(1).unwrap().lock(), sender);
-    let expected_outputs_data = vec![Bytes::default(), Bytes::default()];
-    let outputs_data = tx
-        .outputs_data()
-        .into_iter()
-        .map(|d| d.raw_data())
-        .collect::<Vec<_>>();
-    assert_eq!(outputs_data, expected_outputs_data);
-    let witnesses_len = tx
-        .witnesses()
-        .into_iter()
-        .map(|w| w.raw_data().len())
-        .collect::<Vec<_>>();
-    let witness = placeholder_witness
+    let balancer = CapacityBalancer::new_simple(buyer, placeholder_witness, FEE_RATE);
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![buyer_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(
+            &mut cell_collector,
+            &*ctx,
+            &*ctx,
+            &*ctx,
+            &balancer,
+            &unlockers,
+        )
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    ctx.verify(tx.clone(), FEE_RATE).unwrap();
+
+    let escrow_lock = config.lock_script().unwrap();
+    let (idx, (output, data)) = tx
+        .outputs_with_data_iter()
+        .enumerate()
+        .find(|(_, (output, _))| output.lock() == escrow_lock)
+        .unwrap();
+    assert_eq!(output.type_().to_opt().as_ref(), Some(type_script));
+    assert_eq!(data, Bytes::from(300u128.to_le_bytes().to_vec()));
+    let out_point = OutPoint::new(tx.hash(), idx as u32);
+    ctx.add_live_cell(CellInput::new(out_point.clone(), 0), output, data, None);
+    out_point
+}
+
+fn build_escrow_settle_unlockers(
+    key: secp256k1::SecretKey,
+    config: &EscrowConfig,
+) -> HashMap<ScriptId, Box<dyn ScriptUnlocker>> {
+    let mut unlockers = build_multisig_unlockers(key, config.multisig_config().unwrap());
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+    unlockers
+}
+
+#[test]
+fn test_escrow_create_and_release() {
+    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
+    let buyer = build_sighash_script(ACCOUNT1_ARG);
+    let seller = build_sighash_script(ACCOUNT2_ARG);
+    let owner = build_sighash_script(H160::default());
+    let type_script = Script::new_builder()
+        .code_hash(sudt_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(owner.calc_script_hash().as_bytes().pack())
+        .build();
+    let mut ctx = init_context(
+        vec![(SUDT_BIN, false)],
+        vec![
+            (buyer.clone(), Some(200 * ONE_CKB)),
+            (seller.clone(), Some(100 * ONE_CKB)),
+        ],
+    );
+    let buyer_output = CellOutput::new_builder()
+        .capacity((200 * ONE_CKB).pack())
+        .lock(buyer.clone())
+        .type_(Some(type_script.clone()).pack())
+        .build();
+    ctx.add_live_cell(
+        CellInput::new(random_out_point(), 0),
+        buyer_output,
+        Bytes::from(500u128.to_le_bytes().to_vec()),
+        None,
+    );
+
+    assert!(matches!(
+        EscrowConfig::new(ACCOUNT1_ARG, ACCOUNT1_ARG, None),
+        Err(TxBuilderError::InvalidParameter(_))
+    ));
+    let config = EscrowConfig::new(ACCOUNT1_ARG, ACCOUNT2_ARG, None).unwrap();
+    let buyer_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let seller_key = secp256k1::SecretKey::from_slice(ACCOUNT2_KEY.as_bytes()).unwrap();
+    let escrow_out_point = build_escrow_cell(&mut ctx, &config, buyer_key, &type_script);
+
+    // The seller pays the fee, the buyer co-signs the release
+    let builder = EscrowSettleBuilder::new(
+        config.clone(),
+        escrow_out_point.clone(),
+        EscrowSettlement::Release,
+        type_script.clone(),
+    );
+    // The receiver is the sighash lock of the participant in the config
+    assert_eq!(builder.receiver_lock(), seller);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(seller.clone(), placeholder_witness, FEE_RATE);
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(
+            &mut cell_collector,
+            &ctx,
+            &ctx,
+            &ctx,
+            &balancer,
+            &build_escrow_settle_unlockers(seller_key, &config),
+        )
+        .unwrap();
+    // Signed by the seller only
+    assert!(locked_groups.is_empty());
+    assert!(ctx.verify(tx.clone(), FEE_RATE).is_err());
+    let (tx, locked_groups) = unlock_tx(
+        tx,
+        &ctx,
+        &build_multisig_unlockers(buyer_key, config.multisig_config().unwrap()),
+    )
+    .unwrap();
+    assert!(locked_groups.is_empty());
+
+    assert_eq!(
+        tx.inputs().get(0).unwrap().previous_output(),
+        escrow_out_point
+    );
+    let output = tx.output(0).unwrap();
+    assert_eq!(output.lock(), seller);
+    assert_eq!(output.type_().to_opt(), Some(type_script.clone()));
+    assert_eq!(
+        tx.outputs_data().get(0).unwrap().raw_data(),
+        Bytes::from(300u128.to_le_bytes().to_vec())
+    );
+    ctx.verify(tx, FEE_RATE).unwrap();
+
+    // The escrow cell is not guarded by another config
+    let other_config = EscrowConfig::new(ACCOUNT1_ARG, ACCOUNT3_ARG, None).unwrap();
+    let builder = EscrowSettleBuilder::new(
+        other_config,
+        escrow_out_point.clone(),
+        EscrowSettlement::Release,
+        type_script.clone(),
+    );
+    let mut cell_collector = ctx.to_live_cells_context();
+    assert!(matches!(
+        builder.build_base(&mut cell_collector, &ctx, &ctx, &ctx),
+        Err(TxBuilderError::InvalidParameter(_))
+    ));
+
+    // The escrow cell does not hold the expected UDT
+    let other_type_script = type_script
         .as_builder()
-        .input_type(Some(Bytes::from(vec![0u8; 8])).pack())
+        .args(buyer.calc_script_hash().as_bytes().pack())
         .build();
-    assert_eq!(witnesses_len, vec![witness.as_slice().len(), 0]);
-    ctx.verify(tx, FEE_RATE).unwrap();
+    let builder = EscrowSettleBuilder::new(
+        config,
+        escrow_out_point,
+        EscrowSettlement::Release,
+        other_type_script,
+    );
+    let mut cell_collector = ctx.to_live_cells_context();
+    assert!(matches!(
+        builder.build_base(&mut cell_collector, &ctx, &ctx, &ctx),
+        Err(TxBuilderError::InvalidParameter(_))
+    ));
 }
 
 #[test]
-fn test_udt_issue() {
+fn test_escrow_dispute() {
     let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
-    let owner = build_sighash_script(ACCOUNT1_ARG);
-    let receiver = build_sighash_script(ACCOUNT2_ARG);
-    let ctx = init_context(
+    let buyer = build_sighash_script(ACCOUNT1_ARG);
+    let owner = build_sighash_script(H160::default());
+    let type_script = Script::new_builder()
+        .code_hash(sudt_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(owner.calc_script_hash().as_bytes().pack())
+        .build();
+    let mut ctx = init_context(
         vec![(SUDT_BIN, false)],
-        vec![
-            (owner.clone(), Some(100 * ONE_CKB)),
-            (owner.clone(), Some(200 * ONE_CKB)),
-            (owner.clone(), Some(300 * ONE_CKB)),
-        ],
+        vec![(buyer.clone(), Some(200 * ONE_CKB))],
+    );
+    let buyer_output = CellOutput::new_builder()
+        .capacity((200 * ONE_CKB).pack())
+        .lock(buyer.clone())
+        .type_(Some(type_script.clone()).pack())
+        .build();
+    ctx.add_live_cell(
+        CellInput::new(random_out_point(), 0),
+        buyer_output,
+        Bytes::from(500u128.to_le_bytes().to_vec()),
+        None,
     );
 
-    let sudt_script_id = ScriptId::new_data1(sudt_data_hash.clone());
-    let udt_receiver = UdtTargetReceiver::new(TransferAction::Create, receiver.clone(), 500);
-    let builder = UdtIssueBuilder {
-        udt_type: UdtType::Sudt,
-        script_id: sudt_script_id,
-        owner: owner.clone(),
-        receivers: vec![udt_receiver],
+    assert!(matches!(
+        EscrowConfig::new(ACCOUNT1_ARG, ACCOUNT2_ARG, Some(ACCOUNT2_ARG)),
+        Err(TxBuilderError::InvalidParameter(_))
+    ));
+    // The fields are public, the duplicate is also rejected when the lock
+    // script is derived
+    let invalid_config = EscrowConfig {
+        buyer: ACCOUNT1_ARG,
+        seller: ACCOUNT2_ARG,
+        arbiter: Some(ACCOUNT1_ARG),
     };
+    assert!(invalid_config.lock_script().is_err());
+
+    let config = EscrowConfig::new(ACCOUNT1_ARG, ACCOUNT2_ARG, Some(ACCOUNT3_ARG)).unwrap();
+    assert_eq!(config.multisig_config().unwrap().threshold(), 2);
+    let buyer_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let arbiter_key = secp256k1::SecretKey::from_slice(ACCOUNT3_KEY.as_bytes()).unwrap();
+    let escrow_out_point = build_escrow_cell(&mut ctx, &config, buyer_key, &type_script);
+
+    // The seller does not respond, the arbiter refunds with the buyer
+    let builder = EscrowSettleBuilder::new(
+        config.clone(),
+        escrow_out_point,
+        EscrowSettlement::Refund,
+        type_script.clone(),
+    );
+    assert_eq!(builder.receiver_lock(), buyer);
     let placeholder_witness = WitnessArgs::new_builder()
         .lock(Some(Bytes::from(vec![0u8; 65])).pack())
         .build();
-    let balancer =
-        CapacityBalancer::new_simple(owner.clone(), placeholder_witness.clone(), FEE_RATE);
-
-    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
-    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
-    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
-    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
-    unlockers.insert(
-        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
-        Box::new(script_unlocker),
-    );
-
+    let balancer = CapacityBalancer::new_simple(buyer.clone(), placeholder_witness, FEE_RATE);
     let mut cell_collector = ctx.to_live_cells_context();
     let (tx, locked_groups) = builder
-        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .build_unlocked(
+            &mut cell_collector,
+            &ctx,
+            &ctx,
+            &ctx,
+            &balancer,
+            &build_escrow_settle_unlockers(buyer_key, &config),
+        )
         .unwrap();
-
     assert!(locked_groups.is_empty());
-    assert_eq!(tx.header_deps().len(), 0);
-    assert_eq!(tx.cell_deps().len(), 2);
-    assert_eq!(tx.inputs().len(), 2);
-    for out_point in tx.input_pts_iter() {
-        assert_eq!(ctx.get_input(&out_point).unwrap().0.lock(), owner);
-    }
-    assert_eq!(tx.outputs().len(), 2);
-    let type_script = Script::new_builder()
-        .code_hash(sudt_data_hash.pack())
-        .hash_type(ScriptHashType::Data1.into())
-        .args(owner.calc_script_hash().as_bytes().pack())
-        .build();
-    let output = CellOutput::new_builder()
-        .lock(receiver)
-        .type_(Some(type_script).pack())
-        .build();
-    let occupied_capacity = output
-        .occupied_capacity(Capacity::bytes(16).unwrap())
-        .unwrap()
-        .as_u64();
-    let output = output
-        .as_builder()
-        .capacity(occupied_capacity.pack())
-        .build();
-    assert_eq!(tx.output(0).unwrap(), output);
-    assert_eq!(tx.output(1).unwrap().lock(), owner);
-    let expected_outputs_data = vec![
-        Bytes::from(500u128.to_le_bytes().to_vec()),
-        Bytes::default(),
-    ];
-    let outputs_data = tx
-        .outputs_data()
-        .into_iter()
-        .map(|d| d.raw_data())
-        .collect::<Vec<_>>();
-    assert_eq!(outputs_data, expected_outputs_data);
-    let witnesses_len = tx
-        .witnesses()
-        .into_iter()
-        .map(|w| w.raw_data().len())
-        .collect::<Vec<_>>();
-    assert_eq!(witnesses_len, vec![placeholder_witness.as_slice().len(), 0]);
+    assert!(ctx.verify(tx.clone(), FEE_RATE).is_err());
+    let arbiter_unlockers =
+        build_multisig_unlockers(arbiter_key, config.multisig_config().unwrap());
+    let (tx, locked_groups) = unlock_tx(tx, &ctx, &arbiter_unlockers).unwrap();
+    assert!(locked_groups.is_empty());
+
+    let output = tx.output(0).unwrap();
+    assert_eq!(output.lock(), buyer);
+    assert_eq!(output.type_().to_opt(), Some(type_script));
     ctx.verify(tx, FEE_RATE).unwrap();
 }
 
@@ -1086,6 +5920,297 @@ fn test_udt_transfer() {
     ctx.verify(tx, FEE_RATE).unwrap();
 }
 
+#[test]
+fn test_udt_auto_transfer() {
+    let acp_data_hash = H256::from(blake2b_256(ACP_BIN));
+    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
+    let cheque_data_hash = H256::from(blake2b_256(CHEQUE_BIN));
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let owner = build_sighash_script(H160::default());
+    let type_script = Script::new_builder()
+        .code_hash(sudt_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(owner.calc_script_hash().as_bytes().pack())
+        .build();
+    let mut ctx = init_context(
+        vec![(ACP_BIN, true), (SUDT_BIN, false), (CHEQUE_BIN, true)],
+        vec![(sender.clone(), Some(400 * ONE_CKB))],
+    );
+    let sender_input = CellInput::new(random_out_point(), 0);
+    let sender_output = CellOutput::new_builder()
+        .capacity((200 * ONE_CKB).pack())
+        .lock(sender.clone())
+        .type_(Some(type_script.clone()).pack())
+        .build();
+    let sender_data = Bytes::from(500u128.to_le_bytes().to_vec());
+    ctx.add_live_cell(sender_input, sender_output, sender_data, None);
+
+    let receiver_acp_lock = Script::new_builder()
+        .code_hash(acp_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(Bytes::from(ACCOUNT2_ARG.0.to_vec()).pack())
+        .build();
+    let receiver_input = CellInput::new(random_out_point(), 0);
+    let receiver_output = CellOutput::new_builder()
+        .capacity((200 * ONE_CKB).pack())
+        .lock(receiver_acp_lock.clone())
+        .type_(Some(type_script.clone()).pack())
+        .build();
+    let receiver_data = Bytes::from(100u128.to_le_bytes().to_vec());
+    ctx.add_live_cell(receiver_input, receiver_output.clone(), receiver_data, None);
+    let receiver_secp_lock = build_sighash_script(ACCOUNT3_ARG);
+
+    let receivers = vec![
+        (receiver_acp_lock.clone(), 100),
+        (receiver_secp_lock.clone(), 200),
+    ]
+    .into_iter()
+    .map(|(lock, amount)| {
+        let payload = AddressPayload::from(lock);
+        (Address::new(NetworkType::Testnet, payload, true), amount)
+    })
+    .collect::<Vec<_>>();
+    let acp_script_ids = vec![ScriptId::new_data1(acp_data_hash.clone())];
+    let plain_builder = UdtAutoTransferBuilder::new(
+        type_script.clone(),
+        sender.clone(),
+        receivers.clone(),
+        UdtReceiverDetector::new(acp_script_ids.clone(), None),
+    );
+    let builder = UdtAutoTransferBuilder::new(
+        type_script.clone(),
+        sender.clone(),
+        receivers,
+        UdtReceiverDetector::new(acp_script_ids, Some(ScriptId::new_data1(cheque_data_hash))),
+    );
+    let mut cell_collector = ctx.to_live_cells_context();
+    assert_eq!(
+        plain_builder.receiver_modes(&mut cell_collector).unwrap(),
+        vec![UdtReceiverMode::Acp, UdtReceiverMode::Plain]
+    );
+    assert_eq!(
+        builder.receiver_modes(&mut cell_collector).unwrap(),
+        vec![UdtReceiverMode::Acp, UdtReceiverMode::Cheque]
+    );
+
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let acp_unlocker = AcpUnlocker::from(Box::<SecpCkbRawKeySigner>::default() as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+    unlockers.insert(ScriptId::new_data1(acp_data_hash), Box::new(acp_unlocker));
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.output(1).unwrap(), receiver_output);
+    assert_eq!(
+        tx.outputs_data().get(1).unwrap().raw_data(),
+        Bytes::from(200u128.to_le_bytes().to_vec())
+    );
+    let cheque_lock = tx.output(2).unwrap().lock();
+    assert_eq!(
+        &cheque_lock.args().raw_data()[0..20],
+        &receiver_secp_lock.calc_script_hash().as_slice()[0..20]
+    );
+    assert_eq!(
+        &cheque_lock.args().raw_data()[20..40],
+        &sender.calc_script_hash().as_slice()[0..20]
+    );
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_vesting_create() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+            (sender.clone(), Some(300 * ONE_CKB)),
+        ],
+    );
+    let lock_args = vec![ACCOUNT0_ARG.clone(), ACCOUNT2_ARG.clone()];
+    let cfg = MultisigConfig::new_with(lock_args, 0, 1).unwrap();
+    let builder =
+        VestingBuilder::new_linear(VestingLock::from(cfg.clone()), 301 * ONE_CKB, 10, 5, 3)
+            .unwrap();
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer =
+        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.outputs().len(), 4);
+    let tranche_capacities = tx
+        .outputs()
+        .into_iter()
+        .take(3)
+        .map(|output| Unpack::<u64>::unpack(&output.capacity()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        tranche_capacities,
+        vec![100 * ONE_CKB, 100 * ONE_CKB, 101 * ONE_CKB]
+    );
+    for (idx, epoch_number) in [10u64, 15, 20].iter().enumerate() {
+        let lock = tx.output(idx).unwrap().lock();
+        assert_eq!(lock.code_hash(), MULTISIG_TYPE_HASH.pack());
+        let args = lock.args().raw_data();
+        assert_eq!(args.len(), 28);
+        assert_eq!(&args[0..20], cfg.hash160().as_bytes());
+        assert_eq!(
+            &args[20..28],
+            &Since::new_absolute_epoch(*epoch_number)
+                .value()
+                .to_le_bytes()[..]
+        );
+    }
+    assert_eq!(tx.output(3).unwrap().lock(), sender);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_vesting_linear_overflow() {
+    let lock_args = vec![ACCOUNT0_ARG.clone(), ACCOUNT2_ARG.clone()];
+    let cfg = MultisigConfig::new_with(lock_args, 0, 1).unwrap();
+    assert!(matches!(
+        VestingBuilder::new_linear(
+            VestingLock::from(cfg.clone()),
+            300 * ONE_CKB,
+            10,
+            u64::MAX,
+            3
+        ),
+        Err(TxBuilderError::InvalidParameter(_))
+    ));
+    assert!(matches!(
+        VestingBuilder::new_linear(VestingLock::from(cfg), 300 * ONE_CKB, u64::MAX, 1, 2),
+        Err(TxBuilderError::InvalidParameter(_))
+    ));
+}
+
+#[test]
+fn test_vesting_claim() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let lock_args = vec![ACCOUNT0_ARG.clone(), ACCOUNT2_ARG.clone()];
+    let cfg = MultisigConfig::new_with(lock_args, 0, 1).unwrap();
+    let vesting_lock = VestingLock::from(cfg.clone());
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (vesting_lock.lock_script(10), Some(100 * ONE_CKB)),
+            (vesting_lock.lock_script(15), Some(100 * ONE_CKB)),
+            (vesting_lock.lock_script(20), Some(101 * ONE_CKB)),
+        ],
+    );
+    let epoch_numbers = vec![10, 15, 20];
+
+    // Before the first tranche unlocks
+    let builder = VestingClaimBuilder::new(
+        vesting_lock.clone(),
+        epoch_numbers.clone(),
+        EpochNumberWithFraction::new(9, 0, 1),
+        receiver.clone(),
+    );
+    assert!(builder.matured_epochs().is_empty());
+    let mut cell_collector = ctx.to_live_cells_context();
+    assert!(matches!(
+        builder.build_base(&mut cell_collector, &ctx, &ctx, &ctx),
+        Err(TxBuilderError::InvalidParameter(_))
+    ));
+
+    // Partial claim, only the matured tranches are spent
+    let builder = VestingClaimBuilder::new(
+        vesting_lock.clone(),
+        epoch_numbers.clone(),
+        EpochNumberWithFraction::new(16, 0, 1),
+        receiver.clone(),
+    );
+    assert_eq!(builder.matured_epochs(), vec![10, 15]);
+    let mut cell_collector = ctx.to_live_cells_context();
+    let base_tx = builder
+        .build_base(&mut cell_collector, &ctx, &ctx, &ctx)
+        .unwrap();
+    let mut input_sinces = base_tx
+        .inputs()
+        .into_iter()
+        .map(|input| Unpack::<u64>::unpack(&input.since()))
+        .collect::<Vec<_>>();
+    input_sinces.sort_unstable();
+    assert_eq!(
+        input_sinces,
+        vec![
+            Since::new_absolute_epoch(10).value(),
+            Since::new_absolute_epoch(15).value()
+        ]
+    );
+    assert_eq!(base_tx.outputs().len(), 1);
+    assert_eq!(
+        Unpack::<u64>::unpack(&base_tx.output(0).unwrap().capacity()),
+        200 * ONE_CKB
+    );
+
+    // After all tranches unlock
+    let builder = VestingClaimBuilder::new(
+        vesting_lock,
+        epoch_numbers,
+        EpochNumberWithFraction::new(20, 0, 1),
+        receiver.clone(),
+    );
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(sender, placeholder_witness, FEE_RATE);
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let account2_key = secp256k1::SecretKey::from_slice(ACCOUNT2_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers = build_multisig_unlockers(account2_key, cfg);
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.inputs().len(), 4);
+    assert_eq!(tx.output(0).unwrap().lock(), receiver);
+    assert_eq!(
+        Unpack::<u64>::unpack(&tx.output(0).unwrap().capacity()),
+        301 * ONE_CKB
+    );
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
 pub mod ckb_indexer_rpc;
 pub mod ckb_rpc;
 pub mod cycle;
@@ -2,7 +2,7 @@ use std::collections::HashMap;
 
 use crate::{
     constants::{ONE_CKB, SIGHASH_TYPE_HASH},
-    test_util::random_out_point,
+    test_util::{random_out_point, Context},
     tests::{
         build_sighash_script, init_context, omni_lock_util::generate_rc, ACCOUNT0_ARG,
         ACCOUNT0_KEY, ACCOUNT1_ARG, ACCOUNT1_KEY, ACCOUNT2_ARG, ACCOUNT2_KEY, ACCOUNT3_ARG,
@@ -12,7 +12,7 @@ use crate::{
     tx_builder::{
         acp::{AcpTransferBuilder, AcpTransferReceiver},
         balance_tx_capacity, fill_placeholder_witnesses,
-        omni_lock::OmniLockTransferBuilder,
+        omni_lock::{resolve_rce_cells, OmniLockAdminTransferBuilder, OmniLockTransferBuilder},
         udt::{UdtTargetReceiver, UdtTransferBuilder},
         CapacityProvider, TransferAction,
     },
@@ -32,7 +32,7 @@ use ckb_crypto::secp::{Pubkey, SECP256K1};
 use ckb_hash::blake2b_256;
 use ckb_types::{
     bytes::Bytes,
-    core::{FeeRate, ScriptHashType},
+    core::{ScriptHashType, TransactionBuilder},
     packed::{Byte32, CellInput, CellOutput, Script, WitnessArgs},
     prelude::*,
     H160, H256,
@@ -55,8 +55,10 @@ fn build_omnilock_unlockers(
     config: OmniLockConfig,
     unlock_mode: OmniUnlockMode,
 ) -> HashMap<ScriptId, Box<dyn ScriptUnlocker>> {
-    let signer = if config.is_ethereum() {
+    let signer = if config.is_ethereum() || config.is_tron() {
         SecpCkbRawKeySigner::new_with_ethereum_secret_keys(vec![key])
+    } else if config.is_bitcoin() {
+        bitcoin_signer(key)
     } else {
         SecpCkbRawKeySigner::new_with_secret_keys(vec![key])
     };
@@ -91,6 +93,44 @@ fn test_omnilock_transfer_from_ethereum() {
     test_omnilock_simple_hash(cfg);
 }
 
+#[test]
+fn test_omnilock_transfer_from_tron() {
+    let account0_key = secp256k1::SecretKey::from_slice(ACCOUNT0_KEY.as_bytes()).unwrap();
+    let pubkey = secp256k1::PublicKey::from_secret_key(&SECP256K1, &account0_key);
+    let cfg = OmniLockConfig::new_tron(keccak160(Pubkey::from(pubkey).as_ref()));
+    test_omnilock_simple_hash(cfg);
+}
+
+#[test]
+fn test_omnilock_transfer_from_eos() {
+    let account0_key = secp256k1::SecretKey::from_slice(ACCOUNT0_KEY.as_bytes()).unwrap();
+    let pubkey = secp256k1::PublicKey::from_secret_key(&SECP256K1, &account0_key);
+    let cfg = OmniLockConfig::new_eos(blake160(&pubkey.serialize()));
+    test_omnilock_simple_hash(cfg);
+}
+
+#[cfg(feature = "bitcoin")]
+fn bitcoin_signer(key: secp256k1::SecretKey) -> SecpCkbRawKeySigner {
+    SecpCkbRawKeySigner::new_with_bitcoin_secret_keys(vec![key])
+}
+#[cfg(not(feature = "bitcoin"))]
+fn bitcoin_signer(_key: secp256k1::SecretKey) -> SecpCkbRawKeySigner {
+    unreachable!("the bitcoin identity requires the `bitcoin` feature")
+}
+
+#[cfg(feature = "bitcoin")]
+#[test]
+fn test_omnilock_transfer_from_bitcoin() {
+    use crate::util::hash160;
+
+    let account0_key = secp256k1::SecretKey::from_slice(ACCOUNT0_KEY.as_bytes()).unwrap();
+    let pubkey = secp256k1::PublicKey::from_secret_key(&SECP256K1, &account0_key);
+    let cfg = OmniLockConfig::new_bitcoin(hash160(&pubkey.serialize()));
+    test_omnilock_simple_hash(cfg);
+    let cfg = OmniLockConfig::new_bitcoin(hash160(&pubkey.serialize_uncompressed()));
+    test_omnilock_simple_hash(cfg);
+}
+
 fn test_omnilock_simple_hash(cfg: OmniLockConfig) {
     let unlock_mode = OmniUnlockMode::Normal;
     let sender = build_omnilock_script(&cfg);
@@ -476,6 +516,62 @@ fn test_omnilock_simple_hash_rc(mut cfg: OmniLockConfig, unlock_mode: OmniUnlock
     ctx.verify(tx, FEE_RATE).unwrap();
 }
 
+#[test]
+fn test_omnilock_admin_transfer_builder() {
+    let sender_key = secp256k1::SecretKey::from_slice(ACCOUNT0_KEY.as_bytes()).unwrap();
+    let pubkey = secp256k1::PublicKey::from_secret_key(&SECP256K1, &sender_key);
+    let mut cfg = OmniLockConfig::new_pubkey_hash(blake160(&pubkey.serialize()));
+    let admin_key = secp256k1::SecretKey::from_slice(ACCOUNT3_KEY.as_bytes()).unwrap();
+    let pubkey = secp256k1::PublicKey::from_secret_key(&SECP256K1, &admin_key);
+    let id = Identity::new_pubkey_hash(blake160(&pubkey.serialize()));
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+
+    let mut ctx = init_context(vec![(OMNILOCK_BIN, true)], vec![]);
+    let (proof_vec, rc_type_id, rce_cells) =
+        generate_rc(&mut ctx, id.to_smt_key().into(), false, ACCOUNT3_ARG);
+    let rc_type_id = H256::from_slice(rc_type_id.as_ref()).unwrap();
+    let (resolved_cells, rules_count) = resolve_rce_cells(&rc_type_id, &ctx, &ctx).unwrap();
+    assert_eq!(resolved_cells, rce_cells);
+    assert_eq!(rules_count, 2);
+
+    // proofs not match the rules
+    cfg.set_admin_config(AdminConfig::new(
+        rc_type_id.clone(),
+        SmtProofEntryVec::default(),
+        id.clone(),
+        None,
+        false,
+    ));
+    let sender = build_omnilock_script(&cfg);
+    ctx.add_simple_live_cell(random_out_point(), sender.clone(), Some(300 * ONE_CKB));
+    let output = CellOutput::new_builder()
+        .capacity((110 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let mut cell_collector = ctx.to_live_cells_context();
+    let builder =
+        OmniLockAdminTransferBuilder::new(vec![(output.clone(), Bytes::default())], cfg.clone());
+    assert!(builder
+        .build_base(&mut cell_collector, &ctx, &ctx, &ctx)
+        .is_err());
+
+    cfg.set_admin_config(AdminConfig::new(rc_type_id, proof_vec, id, None, false));
+    let builder =
+        OmniLockAdminTransferBuilder::new(vec![(output.clone(), Bytes::default())], cfg.clone());
+    let placeholder_witness = cfg.placeholder_witness(OmniUnlockMode::Admin).unwrap();
+    let balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
+    let unlockers = build_omnilock_unlockers(admin_key, cfg, OmniUnlockMode::Admin);
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.cell_deps().len(), 1 + rce_cells.len());
+    assert_eq!(tx.inputs().len(), 1);
+    assert_eq!(tx.output(0).unwrap(), output);
+    assert_eq!(tx.output(1).unwrap().lock(), sender);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
 #[test]
 fn test_omnilock_transfer_from_sighash2_wl() {
     let sender_key = secp256k1::SecretKey::from_slice(ACCOUNT0_KEY.as_bytes())
@@ -792,15 +888,14 @@ fn test_omnilock_transfer_from_ownerlock() {
         .lock(Some(Bytes::from(vec![0u8; 65])).pack())
         .build();
 
-    let balancer = CapacityBalancer {
-        fee_rate: FeeRate::from_u64(FEE_RATE),
-        capacity_provider: CapacityProvider::new_simple(vec![
+    let mut balancer = CapacityBalancer::new_with_provider(
+        FEE_RATE,
+        CapacityProvider::new_simple(vec![
             (sender0.clone(), placeholder_witness0.clone()),
             (sender1.clone(), placeholder_witness1.clone()),
         ]),
-        change_lock_script: None,
-        force_small_change_as_fee: Some(ONE_CKB),
-    };
+    );
+    balancer.set_max_fee(Some(ONE_CKB));
 
     let mut cell_collector = ctx.to_live_cells_context();
     let account0_key = secp256k1::SecretKey::from_slice(ACCOUNT0_KEY.as_bytes()).unwrap();
@@ -892,15 +987,14 @@ fn test_omnilock_transfer_from_ownerlock_wl_admin() {
         .lock(Some(Bytes::from(vec![0u8; 65])).pack())
         .build();
 
-    let balancer = CapacityBalancer {
-        fee_rate: FeeRate::from_u64(FEE_RATE),
-        capacity_provider: CapacityProvider::new_simple(vec![
+    let mut balancer = CapacityBalancer::new_with_provider(
+        FEE_RATE,
+        CapacityProvider::new_simple(vec![
             (sender0.clone(), placeholder_witness0.clone()),
             (owner_sender.clone(), placeholder_witness1.clone()),
         ]),
-        change_lock_script: None,
-        force_small_change_as_fee: Some(ONE_CKB),
-    };
+    );
+    balancer.set_max_fee(Some(ONE_CKB));
 
     let mut cell_collector = ctx.to_live_cells_context();
     let account0_key = secp256k1::SecretKey::from_slice(ACCOUNT0_KEY.as_bytes()).unwrap();
@@ -1314,6 +1408,49 @@ fn test_omnilock_simple_hash_timelock(mut cfg: OmniLockConfig) {
     ctx.verify(tx, FEE_RATE).unwrap();
 }
 
+#[test]
+fn test_omnilock_timelock_since_not_satisfied() {
+    let unlock_mode = OmniUnlockMode::Normal;
+    let sender_key = secp256k1::SecretKey::from_slice(ACCOUNT0_KEY.as_bytes()).unwrap();
+    let pubkey = secp256k1::PublicKey::from_secret_key(&SECP256K1, &sender_key);
+    let mut cfg = OmniLockConfig::new_pubkey_hash(blake160(&pubkey.serialize()));
+    cfg.set_time_lock_config(Since::new_absolute_epoch(200).value());
+    let sender = build_omnilock_script(&cfg);
+    let mut ctx = init_context(vec![(OMNILOCK_BIN, true)], vec![]);
+    let unlockers = build_omnilock_unlockers(sender_key, cfg.clone(), unlock_mode);
+    let placeholder_witness = cfg.placeholder_witness(unlock_mode).unwrap();
+
+    let build_tx = |ctx: &mut Context, epoch_number: u64| {
+        let input = CellInput::new(
+            random_out_point(),
+            Since::new_absolute_epoch(epoch_number).value(),
+        );
+        let output = CellOutput::new_builder()
+            .capacity((300 * ONE_CKB).pack())
+            .lock(sender.clone())
+            .build();
+        ctx.add_live_cell(input.clone(), output, Bytes::default(), None);
+        TransactionBuilder::default()
+            .input(input)
+            .output(
+                CellOutput::new_builder()
+                    .capacity((200 * ONE_CKB).pack())
+                    .lock(build_sighash_script(ACCOUNT2_ARG))
+                    .build(),
+            )
+            .output_data(Bytes::default().pack())
+            .witness(placeholder_witness.as_bytes().pack())
+            .build()
+    };
+
+    let tx = build_tx(&mut ctx, 100);
+    assert!(unlock_tx(tx, &ctx, &unlockers).is_err());
+
+    let tx = build_tx(&mut ctx, 200);
+    let (_, locked_groups) = unlock_tx(tx, &ctx, &unlockers).unwrap();
+    assert!(locked_groups.is_empty());
+}
+
 fn build_sudt_script(omnilock_hash: Byte32) -> Script {
     let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
     Script::new_builder()
@@ -0,0 +1,360 @@
+//! Threshold (MPC) signing of the sighash digests.
+//!
+//! An [`AggregateSigner`] backend coordinates the parties of a threshold
+//! key: a session is started with the digest of a script group, the partial
+//! shares of the parties arrive asynchronously, and once enough shares are
+//! collected they are aggregated into an ordinary recoverable secp256k1
+//! signature. [`AggregateSignerAdapter`] drives the sessions and implements
+//! [`Signer`], so the result is inserted by the sighash unlocker as if it
+//! was signed by a single key.
+use std::collections::HashSet;
+
+use anyhow::anyhow;
+use futures::future::BoxFuture;
+
+use ckb_types::{bytes::Bytes, core::TransactionView, H160};
+
+use super::{Signer, SignerError};
+use crate::util::blake160;
+use crate::SECP256K1;
+
+/// A signing session of one digest
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct AggregateSession {
+    /// The session id assigned by the backend
+    pub session_id: Bytes,
+    /// The id of the threshold key (the blake160 of the aggregated pubkey)
+    pub id: Bytes,
+    /// The 32 bytes sighash digest
+    pub message: Bytes,
+    /// The number of shares required to aggregate the signature
+    pub threshold: usize,
+}
+
+/// The partial signature share of a party
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct PartialShare {
+    /// The index of the party in the threshold key
+    pub party: u32,
+    pub data: Bytes,
+}
+
+/// A threshold signing backend
+pub trait AggregateSigner: Send + Sync {
+    /// Same as [`Signer::match_id`], the id of a threshold key
+    fn match_id(&self, id: &[u8]) -> bool;
+
+    /// Start a session, the parties are notified to sign the digest
+    fn start_session<'a>(
+        &'a self,
+        id: &'a [u8],
+        message: &'a [u8],
+        tx: &'a TransactionView,
+    ) -> BoxFuture<'a, Result<AggregateSession, SignerError>>;
+
+    /// Wait for the next partial share of the session, a party may send its
+    /// share more than once.
+    fn next_share<'a>(
+        &'a self,
+        session: &'a AggregateSession,
+    ) -> BoxFuture<'a, Result<PartialShare, SignerError>>;
+
+    /// Aggregate the shares (at least `session.threshold` from different
+    /// parties) into the 65 bytes recoverable signature.
+    fn aggregate(
+        &self,
+        session: &AggregateSession,
+        shares: &[PartialShare],
+    ) -> Result<Bytes, SignerError>;
+
+    /// Abort the session, e.g. after an error
+    fn abort_session<'a>(
+        &'a self,
+        _session: &'a AggregateSession,
+    ) -> BoxFuture<'a, Result<(), SignerError>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Run a session to the final recoverable signature. The signature is
+/// checked against the id before it is returned.
+pub async fn sign_aggregated(
+    signer: &dyn AggregateSigner,
+    id: &[u8],
+    message: &[u8],
+    tx: &TransactionView,
+) -> Result<Bytes, SignerError> {
+    if !signer.match_id(id) {
+        return Err(SignerError::IdNotFound);
+    }
+    if message.len() != 32 {
+        return Err(SignerError::InvalidMessage(format!(
+            "expected length: 32, got: {}",
+            message.len()
+        )));
+    }
+    let session = signer.start_session(id, message, tx).await?;
+    let result = collect_and_aggregate(signer, &session).await;
+    if result.is_err() {
+        // The error of the session is more helpful than the abort error
+        let _ = signer.abort_session(&session).await;
+    }
+    result
+}
+
+async fn collect_and_aggregate(
+    signer: &dyn AggregateSigner,
+    session: &AggregateSession,
+) -> Result<Bytes, SignerError> {
+    if session.threshold == 0 {
+        return Err(SignerError::Other(anyhow!("invalid session threshold: 0")));
+    }
+    let mut parties = HashSet::new();
+    let mut shares = Vec::new();
+    while shares.len() < session.threshold {
+        let share = signer.next_share(session).await?;
+        if parties.insert(share.party) {
+            shares.push(share);
+        }
+    }
+    let signature = signer.aggregate(session, &shares)?;
+    let pubkey_hash = recover_pubkey_hash(&session.message, &signature)?;
+    if pubkey_hash.as_bytes() != session.id.as_ref() {
+        return Err(SignerError::Other(anyhow!(
+            "aggregated signature mismatch, expected pubkey hash: {:#x}, got: {:#x}",
+            H160::from_slice(&session.id).unwrap_or_default(),
+            pubkey_hash
+        )));
+    }
+    Ok(signature)
+}
+
+fn recover_pubkey_hash(message: &[u8], signature: &[u8]) -> Result<H160, SignerError> {
+    if signature.len() != 65 {
+        return Err(SignerError::Other(anyhow!(
+            "invalid aggregated signature length: {}",
+            signature.len()
+        )));
+    }
+    let recid = secp256k1::ecdsa::RecoveryId::from_i32(i32::from(signature[64]))
+        .map_err(|err| anyhow!("invalid recovery id: {}", err))?;
+    let signature = secp256k1::ecdsa::RecoverableSignature::from_compact(&signature[0..64], recid)
+        .map_err(|err| anyhow!("invalid aggregated signature: {}", err))?;
+    let message = secp256k1::Message::from_digest_slice(message)
+        .map_err(|err| SignerError::InvalidMessage(err.to_string()))?;
+    let pubkey = SECP256K1
+        .recover_ecdsa(&message, &signature)
+        .map_err(|err| anyhow!("recover public key failed: {}", err))?;
+    Ok(blake160(&pubkey.serialize()))
+}
+
+/// A [`Signer`] blocking on the sessions of the backend
+pub struct AggregateSignerAdapter<T> {
+    inner: T,
+}
+
+impl<T: AggregateSigner> AggregateSignerAdapter<T> {
+    pub fn new(inner: T) -> AggregateSignerAdapter<T> {
+        AggregateSignerAdapter { inner }
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: AggregateSigner> From<T> for AggregateSignerAdapter<T> {
+    fn from(inner: T) -> AggregateSignerAdapter<T> {
+        AggregateSignerAdapter::new(inner)
+    }
+}
+
+impl<T: AggregateSigner> Signer for AggregateSignerAdapter<T> {
+    fn match_id(&self, id: &[u8]) -> bool {
+        self.inner.match_id(id)
+    }
+
+    fn sign(
+        &self,
+        id: &[u8],
+        message: &[u8],
+        recoverable: bool,
+        tx: &TransactionView,
+    ) -> Result<Bytes, SignerError> {
+        if !recoverable {
+            return Err(SignerError::Other(anyhow!(
+                "aggregate signer only produces recoverable signatures"
+            )));
+        }
+        futures::executor::block_on(sign_aggregated(&self.inner, id, message, tx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::thread;
+
+    use ckb_types::core::TransactionBuilder;
+    use futures::channel::mpsc;
+    use futures::StreamExt;
+    use parking_lot::Mutex;
+
+    use crate::traits::SecpCkbRawKeySigner;
+
+    type ShareReceiver = Arc<futures::lock::Mutex<mpsc::Receiver<PartialShare>>>;
+
+    /// A mock backend: every party sends a share from its own thread, the
+    /// shares are only party tags and the aggregation signs with the full key.
+    struct MockBackend {
+        key: secp256k1::SecretKey,
+        parties: u32,
+        threshold: usize,
+        sessions: Mutex<HashMap<Bytes, ShareReceiver>>,
+        aborted: Mutex<Vec<Bytes>>,
+    }
+
+    impl MockBackend {
+        fn lock_arg(&self) -> H160 {
+            let pubkey = secp256k1::PublicKey::from_secret_key(&SECP256K1, &self.key);
+            blake160(&pubkey.serialize())
+        }
+    }
+
+    impl AggregateSigner for MockBackend {
+        fn match_id(&self, id: &[u8]) -> bool {
+            id == self.lock_arg().as_bytes()
+        }
+
+        fn start_session<'a>(
+            &'a self,
+            id: &'a [u8],
+            message: &'a [u8],
+            _tx: &'a TransactionView,
+        ) -> BoxFuture<'a, Result<AggregateSession, SignerError>> {
+            Box::pin(async move {
+                let mut sessions = self.sessions.lock();
+                let session_id = Bytes::from((sessions.len() as u32).to_le_bytes().to_vec());
+                let (sender, receiver) = mpsc::channel(self.parties as usize * 2);
+                for party in 0..self.parties {
+                    let mut sender = sender.clone();
+                    thread::spawn(move || {
+                        // The first party sends its share twice
+                        for _ in 0..(2 - party.min(1)) {
+                            let share = PartialShare {
+                                party,
+                                data: Bytes::from(vec![party as u8]),
+                            };
+                            let _ = futures::executor::block_on(futures::SinkExt::send(
+                                &mut sender,
+                                share,
+                            ));
+                        }
+                    });
+                }
+                sessions.insert(
+                    session_id.clone(),
+                    Arc::new(futures::lock::Mutex::new(receiver)),
+                );
+                Ok(AggregateSession {
+                    session_id,
+                    id: Bytes::from(id.to_vec()),
+                    message: Bytes::from(message.to_vec()),
+                    threshold: self.threshold,
+                })
+            })
+        }
+
+        fn next_share<'a>(
+            &'a self,
+            session: &'a AggregateSession,
+        ) -> BoxFuture<'a, Result<PartialShare, SignerError>> {
+            Box::pin(async move {
+                let receiver = self
+                    .sessions
+                    .lock()
+                    .get(&session.session_id)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("session not found"))?;
+                let share = receiver.lock().await.next().await;
+                share.ok_or_else(|| SignerError::Other(anyhow!("not enough shares")))
+            })
+        }
+
+        fn aggregate(
+            &self,
+            session: &AggregateSession,
+            shares: &[PartialShare],
+        ) -> Result<Bytes, SignerError> {
+            assert!(shares.len() >= session.threshold);
+            let message = secp256k1::Message::from_digest_slice(&session.message).unwrap();
+            let signature = SECP256K1.sign_ecdsa_recoverable(&message, &self.key);
+            Ok(Bytes::from(
+                crate::util::serialize_signature(&signature).to_vec(),
+            ))
+        }
+
+        fn abort_session<'a>(
+            &'a self,
+            session: &'a AggregateSession,
+        ) -> BoxFuture<'a, Result<(), SignerError>> {
+            Box::pin(async move {
+                self.aborted.lock().push(session.session_id.clone());
+                Ok(())
+            })
+        }
+    }
+
+    fn new_backend(parties: u32, threshold: usize) -> MockBackend {
+        MockBackend {
+            key: secp256k1::SecretKey::from_slice(&[3u8; 32]).unwrap(),
+            parties,
+            threshold,
+            sessions: Mutex::new(HashMap::new()),
+            aborted: Mutex::new(Vec::new()),
+        }
+    }
+
+    #[test]
+    fn test_aggregate_signer() {
+        let tx = TransactionBuilder::default().build();
+        let message = [8u8; 32];
+
+        let signer = AggregateSignerAdapter::new(new_backend(3, 2));
+        let lock_arg = signer.inner().lock_arg();
+        assert!(signer.match_id(lock_arg.as_bytes()));
+        let signature = signer
+            .sign(lock_arg.as_bytes(), &message, true, &tx)
+            .unwrap();
+        // The same signature as the single key signer
+        let raw_signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![signer.inner().key]);
+        assert_eq!(
+            signature,
+            raw_signer
+                .sign(lock_arg.as_bytes(), &message, true, &tx)
+                .unwrap()
+        );
+        assert!(signer.inner().aborted.lock().is_empty());
+
+        assert!(signer
+            .sign(lock_arg.as_bytes(), &message, false, &tx)
+            .is_err());
+        assert!(matches!(
+            signer.sign(&[0u8; 20], &message, true, &tx),
+            Err(SignerError::IdNotFound)
+        ));
+        assert!(matches!(
+            signer.sign(lock_arg.as_bytes(), &message[..20], true, &tx),
+            Err(SignerError::InvalidMessage(_))
+        ));
+
+        // Not enough parties, the session is aborted
+        let signer = AggregateSignerAdapter::new(new_backend(2, 3));
+        assert!(signer
+            .sign(lock_arg.as_bytes(), &message, true, &tx)
+            .is_err());
+        assert_eq!(signer.inner().aborted.lock().len(), 1);
+    }
+}
@@ -0,0 +1,505 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use futures::future::BoxFuture;
+use lru::LruCache;
+use parking_lot::Mutex;
+
+use ckb_jsonrpc_types::{self as json_types, Either};
+use ckb_types::{
+    bytes::Bytes,
+    core::{HeaderView, TransactionView},
+    packed::{Byte32, CellOutput, OutPoint, Transaction, TransactionReader},
+    prelude::*,
+};
+
+use super::{offchain_impls::CollectResult, OffchainCellCollector};
+use super::{OffchainTransactionDependencyProvider, TransactionDependencyProvider};
+use crate::rpc::ckb_indexer::{DefaultSearchKeyConverter, Order, SearchKeyConverter, Tip};
+use crate::rpc::{AsyncCkbRpcClient, AsyncIndexerRpcClient};
+use crate::traits::{
+    AsyncCellCollector, AsyncHeaderDepResolver, AsyncTransactionDependencyProvider,
+    CellCollectorError, CellQueryOptions, LiveCell, MaturityOption, QueryOrder,
+    TransactionDependencyError,
+};
+use crate::util::get_max_mature_number_async;
+
+/// A header_dep resolver use async ckb jsonrpc client as backend
+#[derive(Clone)]
+pub struct DefaultAsyncHeaderDepResolver {
+    ckb_client: AsyncCkbRpcClient,
+}
+impl DefaultAsyncHeaderDepResolver {
+    pub fn new(ckb_client: &str) -> DefaultAsyncHeaderDepResolver {
+        let ckb_client = AsyncCkbRpcClient::new(ckb_client);
+        DefaultAsyncHeaderDepResolver { ckb_client }
+    }
+}
+impl AsyncHeaderDepResolver for DefaultAsyncHeaderDepResolver {
+    fn resolve_by_tx<'a>(
+        &'a self,
+        tx_hash: &'a Byte32,
+    ) -> BoxFuture<'a, Result<Option<HeaderView>, anyhow::Error>> {
+        Box::pin(async move {
+            if let Some(block_hash) = self
+                .ckb_client
+                .get_transaction(tx_hash.unpack())
+                .await
+                .map_err(|e| anyhow!(e))?
+                .and_then(|tx_with_status| tx_with_status.tx_status.block_hash)
+            {
+                Ok(self
+                    .ckb_client
+                    .get_header(block_hash)
+                    .await
+                    .map_err(Box::new)?
+                    .map(Into::into))
+            } else {
+                Ok(None)
+            }
+        })
+    }
+    fn resolve_by_number(
+        &self,
+        number: u64,
+    ) -> BoxFuture<'_, Result<Option<HeaderView>, anyhow::Error>> {
+        Box::pin(async move {
+            Ok(self
+                .ckb_client
+                .get_header_by_number(number.into())
+                .await
+                .map_err(|e| anyhow!(e))?
+                .map(Into::into))
+        })
+    }
+}
+
+/// A cell collector use ckb-indexer as backend, async version of
+/// [`DefaultCellCollector`](super::DefaultCellCollector)
+#[derive(Clone)]
+pub struct DefaultAsyncCellCollector {
+    indexer_client: AsyncIndexerRpcClient,
+    ckb_client: AsyncCkbRpcClient,
+    offchain: OffchainCellCollector,
+    acceptable_indexer_leftbehind: u64,
+    search_key_converter: Arc<dyn SearchKeyConverter>,
+}
+
+impl DefaultAsyncCellCollector {
+    pub fn new(ckb_client: &str) -> DefaultAsyncCellCollector {
+        let indexer_client = AsyncIndexerRpcClient::new(ckb_client);
+        let ckb_client = AsyncCkbRpcClient::new(ckb_client);
+        DefaultAsyncCellCollector {
+            indexer_client,
+            ckb_client,
+            offchain: OffchainCellCollector::default(),
+            acceptable_indexer_leftbehind: 1,
+            search_key_converter: Arc::new(DefaultSearchKeyConverter),
+        }
+    }
+
+    /// Set the conversion from the query options to the indexer search key,
+    /// the default is [`DefaultSearchKeyConverter`]
+    pub fn set_search_key_converter(&mut self, converter: Arc<dyn SearchKeyConverter>) {
+        self.search_key_converter = converter;
+    }
+
+    /// THe acceptable ckb-indexer leftbehind block number (default = 1)
+    pub fn acceptable_indexer_leftbehind(&self) -> u64 {
+        self.acceptable_indexer_leftbehind
+    }
+    /// Set the acceptable ckb-indexer leftbehind block number
+    pub fn set_acceptable_indexer_leftbehind(&mut self, value: u64) {
+        self.acceptable_indexer_leftbehind = value;
+    }
+
+    /// Check if ckb-indexer synced with ckb node. This will check every 50ms for 100 times (more than 5s in total, since ckb-indexer's poll interval is 2.0s).
+    pub async fn check_ckb_chain(&mut self) -> Result<(), CellCollectorError> {
+        let tip_number = self
+            .ckb_client
+            .get_tip_block_number()
+            .await
+            .map_err(|err| CellCollectorError::Internal(err.into()))?;
+
+        for _ in 0..100 {
+            match self
+                .indexer_client
+                .get_indexer_tip()
+                .await
+                .map_err(|err| CellCollectorError::Internal(err.into()))?
+            {
+                Some(Tip { block_number, .. }) => {
+                    if tip_number.value()
+                        > block_number.value() + self.acceptable_indexer_leftbehind
+                    {
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                    } else {
+                        return Ok(());
+                    }
+                }
+                None => {
+                    return Err(CellCollectorError::Other(anyhow!(
+                        "ckb-indexer server not synced"
+                    )));
+                }
+            }
+        }
+        Err(CellCollectorError::Other(anyhow!(
+            "ckb-indexer server inconsistent with currently connected ckb node or not synced!"
+        )))
+    }
+
+    async fn collect_live_cells_inner(
+        &mut self,
+        query: &CellQueryOptions,
+        apply_changes: bool,
+    ) -> Result<(Vec<LiveCell>, u64), CellCollectorError> {
+        let max_mature_number = get_max_mature_number_async(&self.ckb_client)
+            .await
+            .map_err(|err| CellCollectorError::Internal(anyhow!(err)))?;
+
+        self.offchain.max_mature_number = max_mature_number;
+        let tip_num = self
+            .ckb_client
+            .get_tip_block_number()
+            .await
+            .map_err(|err| CellCollectorError::Internal(anyhow!(err)))?
+            .value();
+        let CollectResult {
+            cells,
+            rest_cells,
+            mut total_capacity,
+        } = self.offchain.collect(query, tip_num);
+        let mut cells: Vec<_> = cells.into_iter().map(|c| c.0).collect();
+
+        if total_capacity < query.min_total_capacity {
+            self.check_ckb_chain().await?;
+            let order = match query.order {
+                QueryOrder::Asc => Order::Asc,
+                QueryOrder::Desc => Order::Desc,
+            };
+            let mut ret_cells: HashMap<_, _> = cells
+                .into_iter()
+                .map(|c| (c.out_point.clone(), c))
+                .collect();
+            let locked_cells = self.offchain.locked_cells.clone();
+            let search_key = self.search_key_converter.to_search_key(query);
+            const MAX_LIMIT: u32 = 4096;
+            let mut limit: u32 = query.limit.unwrap_or(16);
+            let mut last_cursor: Option<json_types::JsonBytes> = None;
+            while total_capacity < query.min_total_capacity {
+                let page = self
+                    .indexer_client
+                    .get_cells(search_key.clone(), order.clone(), limit.into(), last_cursor)
+                    .await
+                    .map_err(|err| CellCollectorError::Internal(err.into()))?;
+                if page.objects.is_empty() {
+                    break;
+                }
+                for cell in page.objects {
+                    let live_cell = LiveCell::from(cell);
+                    if !query.match_cell(&live_cell, max_mature_number)
+                        || locked_cells.contains_key(&(
+                            live_cell.out_point.tx_hash().unpack(),
+                            live_cell.out_point.index().unpack(),
+                        ))
+                    {
+                        continue;
+                    }
+                    let capacity: u64 = live_cell.output.capacity().unpack();
+                    // use cell from indexer to replace offchain cell
+                    if ret_cells
+                        .insert(live_cell.out_point.clone(), live_cell)
+                        .is_none()
+                    {
+                        total_capacity += capacity;
+                    }
+                    if total_capacity >= query.min_total_capacity {
+                        break;
+                    }
+                }
+                last_cursor = Some(page.last_cursor);
+                if limit < MAX_LIMIT {
+                    limit *= 2;
+                }
+            }
+            cells = ret_cells.into_values().collect();
+        }
+        if apply_changes {
+            self.offchain.live_cells = rest_cells;
+            for cell in &cells {
+                self.lock_cell(cell.out_point.clone(), tip_num)?;
+            }
+        }
+        Ok((cells, total_capacity))
+    }
+
+    async fn max_total_capacity_inner(
+        &mut self,
+        query: &CellQueryOptions,
+    ) -> Result<Option<u64>, CellCollectorError> {
+        self.check_ckb_chain().await?;
+        let search_key = self.search_key_converter.to_search_key(query);
+        let indexed_capacity = self
+            .indexer_client
+            .get_cells_capacity(search_key)
+            .await
+            .map_err(|err| CellCollectorError::Internal(err.into()))?
+            .map(|cells_capacity| cells_capacity.capacity.value())
+            .unwrap_or_default();
+        // The outputs of the applied transactions may not be indexed yet, the
+        // maturity is ignored so they are never under counted.
+        let mut offchain_query = query.clone();
+        offchain_query.maturity = MaturityOption::Both;
+        let offchain_capacity: u64 = self
+            .offchain
+            .live_cells
+            .iter()
+            .filter(|(cell, _)| offchain_query.match_cell(cell, 0))
+            .map(|(cell, _)| Unpack::<u64>::unpack(&cell.output.capacity()))
+            .sum();
+        Ok(Some(indexed_capacity.saturating_add(offchain_capacity)))
+    }
+}
+
+impl AsyncCellCollector for DefaultAsyncCellCollector {
+    fn collect_live_cells<'a>(
+        &'a mut self,
+        query: &'a CellQueryOptions,
+        apply_changes: bool,
+    ) -> BoxFuture<'a, Result<(Vec<LiveCell>, u64), CellCollectorError>> {
+        Box::pin(self.collect_live_cells_inner(query, apply_changes))
+    }
+
+    fn max_total_capacity<'a>(
+        &'a mut self,
+        query: &'a CellQueryOptions,
+    ) -> BoxFuture<'a, Result<Option<u64>, CellCollectorError>> {
+        Box::pin(self.max_total_capacity_inner(query))
+    }
+
+    fn lock_cell(
+        &mut self,
+        out_point: OutPoint,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        self.offchain.lock_cell(out_point, tip_block_number)
+    }
+    fn apply_tx(
+        &mut self,
+        tx: Transaction,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        self.offchain.apply_tx(tx, tip_block_number)
+    }
+    fn reset(&mut self) {
+        self.offchain.reset();
+    }
+}
+
+struct DefaultAsyncTxDepProviderCache {
+    tx_cache: LruCache<Byte32, TransactionView>,
+    cell_cache: LruCache<OutPoint, (CellOutput, Bytes)>,
+    header_cache: LruCache<Byte32, HeaderView>,
+    extension_cache: LruCache<Byte32, Option<ckb_types::packed::Bytes>>,
+    offchain_cache: OffchainTransactionDependencyProvider,
+}
+
+/// A transaction dependency provider use async ckb rpc client as backend, and
+/// with LRU cache supported. The cache is never locked across an rpc call.
+#[derive(Clone)]
+pub struct DefaultAsyncTransactionDependencyProvider {
+    rpc_client: AsyncCkbRpcClient,
+    cache: Arc<Mutex<DefaultAsyncTxDepProviderCache>>,
+}
+
+impl DefaultAsyncTransactionDependencyProvider {
+    /// Arguments:
+    ///   * `url` is the ckb http jsonrpc server url
+    ///   * When `cache_capacity` is 0 for not using cache.
+    pub fn new(url: &str, cache_capacity: usize) -> DefaultAsyncTransactionDependencyProvider {
+        let rpc_client = AsyncCkbRpcClient::new(url);
+        let cache = DefaultAsyncTxDepProviderCache {
+            tx_cache: LruCache::new(cache_capacity),
+            cell_cache: LruCache::new(cache_capacity),
+            header_cache: LruCache::new(cache_capacity),
+            extension_cache: LruCache::new(cache_capacity),
+            offchain_cache: OffchainTransactionDependencyProvider::new(),
+        };
+        DefaultAsyncTransactionDependencyProvider {
+            rpc_client,
+            cache: Arc::new(Mutex::new(cache)),
+        }
+    }
+
+    pub fn apply_tx(
+        &mut self,
+        tx: Transaction,
+        tip_block_number: u64,
+    ) -> Result<(), TransactionDependencyError> {
+        let mut cache = self.cache.lock();
+        cache.offchain_cache.apply_tx(tx, tip_block_number)?;
+        Ok(())
+    }
+
+    pub async fn get_cell_with_data(
+        &self,
+        out_point: &OutPoint,
+    ) -> Result<(CellOutput, Bytes), TransactionDependencyError> {
+        {
+            let mut cache = self.cache.lock();
+            if let Some(pair) = cache.cell_cache.get(out_point) {
+                return Ok(pair.clone());
+            }
+        }
+
+        let cell_with_status = self
+            .rpc_client
+            .get_live_cell(out_point.clone().into(), true)
+            .await
+            .map_err(|err| TransactionDependencyError::Other(err.into()))?;
+        if cell_with_status.status != "live" {
+            return Err(TransactionDependencyError::Other(anyhow!(
+                "invalid cell status: {:?}",
+                cell_with_status.status
+            )));
+        }
+        let cell = cell_with_status.cell.unwrap();
+        let output = CellOutput::from(cell.output);
+        let output_data = cell.data.unwrap().content.into_bytes();
+        self.cache
+            .lock()
+            .cell_cache
+            .put(out_point.clone(), (output.clone(), output_data.clone()));
+        Ok((output, output_data))
+    }
+}
+
+impl AsyncTransactionDependencyProvider for DefaultAsyncTransactionDependencyProvider {
+    fn get_transaction<'a>(
+        &'a self,
+        tx_hash: &'a Byte32,
+    ) -> BoxFuture<'a, Result<TransactionView, TransactionDependencyError>> {
+        Box::pin(async move {
+            {
+                let mut cache = self.cache.lock();
+                if let Some(tx) = cache.tx_cache.get(tx_hash) {
+                    return Ok(tx.clone());
+                }
+                let ret = cache.offchain_cache.get_transaction(tx_hash);
+                if ret.is_ok() {
+                    return ret;
+                }
+            }
+            let tx_with_status = self
+                .rpc_client
+                .get_transaction(tx_hash.unpack())
+                .await
+                .map_err(|err| TransactionDependencyError::Other(err.into()))?
+                .ok_or_else(|| TransactionDependencyError::NotFound("transaction".to_string()))?;
+            if tx_with_status.tx_status.status != json_types::Status::Committed {
+                return Err(TransactionDependencyError::Other(anyhow!(
+                    "invalid transaction status: {:?}",
+                    tx_with_status.tx_status
+                )));
+            }
+            let tx = match tx_with_status.transaction.unwrap().inner {
+                Either::Left(t) => Transaction::from(t.inner).into_view(),
+                Either::Right(bytes) => TransactionReader::from_slice(bytes.as_bytes())
+                    .map(|reader| reader.to_entity().into_view())
+                    .map_err(|err| anyhow!("invalid molecule encoded TransactionView: {}", err))?,
+            };
+            self.cache.lock().tx_cache.put(tx_hash.clone(), tx.clone());
+            Ok(tx)
+        })
+    }
+    fn get_cell<'a>(
+        &'a self,
+        out_point: &'a OutPoint,
+    ) -> BoxFuture<'a, Result<CellOutput, TransactionDependencyError>> {
+        Box::pin(async move {
+            {
+                let ret = self.cache.lock().offchain_cache.get_cell(out_point);
+                if ret.is_ok() {
+                    return ret;
+                }
+            }
+            self.get_cell_with_data(out_point)
+                .await
+                .map(|(output, _)| output)
+        })
+    }
+    fn get_cell_data<'a>(
+        &'a self,
+        out_point: &'a OutPoint,
+    ) -> BoxFuture<'a, Result<Bytes, TransactionDependencyError>> {
+        Box::pin(async move {
+            {
+                let ret = self.cache.lock().offchain_cache.get_cell_data(out_point);
+                if ret.is_ok() {
+                    return ret;
+                }
+            }
+            self.get_cell_with_data(out_point)
+                .await
+                .map(|(_, output_data)| output_data)
+        })
+    }
+    fn get_header<'a>(
+        &'a self,
+        block_hash: &'a Byte32,
+    ) -> BoxFuture<'a, Result<HeaderView, TransactionDependencyError>> {
+        Box::pin(async move {
+            {
+                let mut cache = self.cache.lock();
+                if let Some(header) = cache.header_cache.get(block_hash) {
+                    return Ok(header.clone());
+                }
+            }
+            let header = self
+                .rpc_client
+                .get_header(block_hash.unpack())
+                .await
+                .map_err(|err| TransactionDependencyError::Other(err.into()))?
+                .map(HeaderView::from)
+                .ok_or_else(|| TransactionDependencyError::NotFound("header".to_string()))?;
+            self.cache
+                .lock()
+                .header_cache
+                .put(block_hash.clone(), header.clone());
+            Ok(header)
+        })
+    }
+
+    fn get_block_extension<'a>(
+        &'a self,
+        block_hash: &'a Byte32,
+    ) -> BoxFuture<'a, Result<Option<ckb_types::packed::Bytes>, TransactionDependencyError>> {
+        Box::pin(async move {
+            {
+                let mut cache = self.cache.lock();
+                if let Some(extension) = cache.extension_cache.get(block_hash) {
+                    return Ok(extension.clone());
+                }
+            }
+            let block = self
+                .rpc_client
+                .get_block(block_hash.unpack())
+                .await
+                .map_err(|err| TransactionDependencyError::Other(err.into()))?;
+            match block {
+                Some(block) => {
+                    let extension = block.extension.map(ckb_types::packed::Bytes::from);
+                    self.cache
+                        .lock()
+                        .extension_cache
+                        .put(block_hash.clone(), extension.clone());
+                    Ok(extension)
+                }
+                None => Ok(None),
+            }
+        })
+    }
+}
@@ -0,0 +1,643 @@
+//! A cell collector maintains live cells by scanning blocks from the ckb node
+//! directly, for environments where running ckb-indexer is not an option.
+//!
+//! Only the cells locked by the configured lock scripts are tracked, the scan
+//! state can be persisted to a file so that the collector can catch up from
+//! the last scanned block after restart.
+//!
+//! The scanned cells can also be consumed as a stream of [`ScanEvent`]s, see
+//! [`BlockScanCellCollector::into_event_stream`].
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use futures::{channel::mpsc, executor::block_on, sink::SinkExt, stream::Stream};
+use serde::{Deserialize, Serialize};
+
+use ckb_jsonrpc_types as json_types;
+use ckb_types::{
+    core::{BlockNumber, BlockView},
+    packed::{Byte32, CellOutput, OutPoint, Script, Transaction},
+    prelude::*,
+    H256,
+};
+
+use super::{offchain_impls::CollectResult, OffchainCellCollector};
+use crate::rpc::CkbRpcClient;
+use crate::traits::{
+    CellCollector, CellCollectorError, CellQueryOptions, LiveCell, PrimaryScriptType, QueryOrder,
+};
+use crate::util::get_max_mature_number;
+
+/// The default number of confirmations, blocks newer than `tip - confirmations`
+/// are not scanned to avoid chain reorganization.
+pub const DEFAULT_SCAN_CONFIRMATIONS: u64 = 24;
+/// Save the scan state to the store file every this many blocks.
+const SAVE_INTERVAL: u64 = 1000;
+
+/// The catch-up progress of the block scanner
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct ScanProgress {
+    /// The last scanned block number
+    pub current: BlockNumber,
+    /// The target block number of current round (`tip - confirmations`)
+    pub target: BlockNumber,
+}
+
+impl ScanProgress {
+    pub fn is_synced(&self) -> bool {
+        self.current >= self.target
+    }
+}
+
+pub type ScanProgressCallback = Arc<dyn Fn(ScanProgress) + Send + Sync>;
+
+/// The events emitted by the block scanner
+#[derive(Debug, Clone)]
+pub enum ScanEvent {
+    /// A new cell locked by the tracked lock scripts (e.g. a payment received)
+    Received(LiveCell),
+    /// A tracked cell is spent
+    Spent(OutPoint),
+    /// A block is scanned
+    Progress(ScanProgress),
+}
+
+/// The stream of the block scanner events, created by
+/// [`BlockScanCellCollector::into_event_stream`].
+///
+/// The scanning runs in a background thread and pauses when `buffer` events
+/// are not consumed yet, it stops after an error is yielded or the stream is
+/// dropped.
+pub struct ScanEventStream {
+    receiver: mpsc::Receiver<Result<ScanEvent, CellCollectorError>>,
+}
+
+impl Stream for ScanEventStream {
+    type Item = Result<ScanEvent, CellCollectorError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.receiver).poll_next(cx)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredCell {
+    out_point: json_types::OutPoint,
+    output: json_types::CellOutput,
+    output_data: json_types::JsonBytes,
+    block_number: json_types::BlockNumber,
+    tx_index: json_types::Uint32,
+}
+
+impl From<&LiveCell> for StoredCell {
+    fn from(cell: &LiveCell) -> StoredCell {
+        StoredCell {
+            out_point: cell.out_point.clone().into(),
+            output: cell.output.clone().into(),
+            output_data: json_types::JsonBytes::from_bytes(cell.output_data.clone()),
+            block_number: cell.block_number.into(),
+            tx_index: cell.tx_index.into(),
+        }
+    }
+}
+
+impl From<StoredCell> for LiveCell {
+    fn from(cell: StoredCell) -> LiveCell {
+        LiveCell {
+            output: cell.output.into(),
+            output_data: cell.output_data.into_bytes(),
+            out_point: cell.out_point.into(),
+            block_number: cell.block_number.value(),
+            tx_index: cell.tx_index.value(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct StoredState {
+    lock_scripts: Vec<json_types::Script>,
+    next_block_number: json_types::BlockNumber,
+    last_block_hash: Option<H256>,
+    cells: Vec<StoredCell>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct ScanState {
+    next_block_number: BlockNumber,
+    last_block_hash: Option<Byte32>,
+    // (tx_hash, index) => LiveCell
+    cells: HashMap<(H256, u32), LiveCell>,
+}
+
+impl ScanState {
+    fn load(path: &Path, lock_scripts: &[Script]) -> Result<ScanState, CellCollectorError> {
+        if !path.exists() {
+            return Ok(ScanState::default());
+        }
+        let content = fs::read(path).map_err(|err| CellCollectorError::Other(anyhow!(err)))?;
+        let stored: StoredState = serde_json::from_slice(&content)
+            .map_err(|err| CellCollectorError::Other(anyhow!(err)))?;
+        let stored_locks: Vec<Script> = stored.lock_scripts.into_iter().map(Into::into).collect();
+        // New lock scripts require a full rescan
+        if lock_scripts
+            .iter()
+            .any(|script| !stored_locks.contains(script))
+        {
+            return Ok(ScanState::default());
+        }
+        let cells = stored
+            .cells
+            .into_iter()
+            .map(LiveCell::from)
+            .filter(|cell| lock_scripts.contains(&cell.output.lock()))
+            .map(|cell| (out_point_key(&cell.out_point), cell))
+            .collect();
+        Ok(ScanState {
+            next_block_number: stored.next_block_number.value(),
+            last_block_hash: stored.last_block_hash.map(|hash| hash.pack()),
+            cells,
+        })
+    }
+
+    fn save(&self, path: &Path, lock_scripts: &[Script]) -> Result<(), CellCollectorError> {
+        let stored = StoredState {
+            lock_scripts: lock_scripts.iter().cloned().map(Into::into).collect(),
+            next_block_number: self.next_block_number.into(),
+            last_block_hash: self.last_block_hash.as_ref().map(|hash| hash.unpack()),
+            cells: self.cells.values().map(StoredCell::from).collect(),
+        };
+        let content =
+            serde_json::to_vec(&stored).map_err(|err| CellCollectorError::Other(anyhow!(err)))?;
+        // Write to a temporary file first, so that the store is never half written
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, content).map_err(|err| CellCollectorError::Other(anyhow!(err)))?;
+        fs::rename(&tmp_path, path).map_err(|err| CellCollectorError::Other(anyhow!(err)))?;
+        Ok(())
+    }
+
+    fn apply_block(&mut self, block: &BlockView, lock_scripts: &[Script]) -> Vec<ScanEvent> {
+        let block_number = block.number();
+        let mut events = Vec::new();
+        for (tx_index, tx) in block.transactions().iter().enumerate() {
+            for input in tx.inputs().into_iter() {
+                if let Some(cell) = self.cells.remove(&out_point_key(&input.previous_output())) {
+                    events.push(ScanEvent::Spent(cell.out_point));
+                }
+            }
+            let tx_hash = tx.hash();
+            for (index, (output, output_data)) in tx.outputs_with_data_iter().enumerate() {
+                if !lock_scripts.contains(&output.lock()) {
+                    continue;
+                }
+                let out_point = OutPoint::new(tx_hash.clone(), index as u32);
+                let cell = LiveCell {
+                    output,
+                    output_data,
+                    out_point: out_point.clone(),
+                    block_number,
+                    tx_index: tx_index as u32,
+                };
+                events.push(ScanEvent::Received(cell.clone()));
+                self.cells.insert(out_point_key(&out_point), cell);
+            }
+        }
+        self.next_block_number = block_number + 1;
+        self.last_block_hash = Some(block.hash());
+        events
+    }
+}
+
+fn out_point_key(out_point: &OutPoint) -> (H256, u32) {
+    (out_point.tx_hash().unpack(), out_point.index().unpack())
+}
+
+/// A cell collector only requires a ckb full node, it scans every block to
+/// maintain the live cells locked by the configured lock scripts.
+///
+/// The first collect will scan from genesis block (or the block set by
+/// [`BlockScanCellCollector::set_start_block_number`]), which can take a long
+/// time, use [`BlockScanCellCollector::set_progress_callback`] to report the
+/// catch-up progress.
+#[derive(Clone)]
+pub struct BlockScanCellCollector {
+    ckb_client: CkbRpcClient,
+    lock_scripts: Vec<Script>,
+    store_path: Option<PathBuf>,
+    confirmations: u64,
+    state: ScanState,
+    offchain: OffchainCellCollector,
+    progress_callback: Option<ScanProgressCallback>,
+}
+
+impl BlockScanCellCollector {
+    /// Arguments:
+    ///   * `ckb_client` is the ckb http jsonrpc server url
+    ///   * `lock_scripts` are the lock scripts to track
+    ///   * `store_path` is the file to persist scan state, `None` for not persisting
+    pub fn new(
+        ckb_client: &str,
+        lock_scripts: Vec<Script>,
+        store_path: Option<PathBuf>,
+    ) -> Result<BlockScanCellCollector, CellCollectorError> {
+        let state = match store_path.as_ref() {
+            Some(path) => ScanState::load(path, &lock_scripts)?,
+            None => ScanState::default(),
+        };
+        Ok(BlockScanCellCollector {
+            ckb_client: CkbRpcClient::new(ckb_client),
+            lock_scripts,
+            store_path,
+            confirmations: DEFAULT_SCAN_CONFIRMATIONS,
+            state,
+            offchain: OffchainCellCollector::default(),
+            progress_callback: None,
+        })
+    }
+
+    /// Blocks newer than `tip - confirmations` are not scanned
+    pub fn set_confirmations(&mut self, value: u64) {
+        self.confirmations = value;
+    }
+
+    /// Skip the blocks before `number`, only take effect before the first
+    /// scan. Cells created before the start block will not be tracked.
+    pub fn set_start_block_number(&mut self, number: BlockNumber) {
+        if self.state.next_block_number == 0 && self.state.last_block_hash.is_none() {
+            self.state.next_block_number = number;
+        }
+    }
+
+    pub fn set_progress_callback(&mut self, callback: ScanProgressCallback) {
+        self.progress_callback = Some(callback);
+    }
+
+    /// The number of the next block to scan
+    pub fn next_block_number(&self) -> BlockNumber {
+        self.state.next_block_number
+    }
+
+    /// Scan blocks until `tip - confirmations`, return the final progress.
+    pub fn sync(&mut self) -> Result<ScanProgress, CellCollectorError> {
+        let callback = self.progress_callback.clone();
+        self.sync_with(|event| {
+            if let (ScanEvent::Progress(progress), Some(callback)) = (&event, callback.as_ref()) {
+                callback(*progress);
+            }
+            true
+        })
+    }
+
+    /// Scan blocks and pass the events to `on_event`, stop scanning when it
+    /// returns `false`.
+    fn sync_with<F>(&mut self, mut on_event: F) -> Result<ScanProgress, CellCollectorError>
+    where
+        F: FnMut(ScanEvent) -> bool,
+    {
+        let tip_num = self
+            .ckb_client
+            .get_tip_block_number()
+            .map_err(|err| CellCollectorError::Internal(anyhow!(err)))?
+            .value();
+        let target = tip_num.saturating_sub(self.confirmations);
+        let mut scanned = 0;
+        while self.state.next_block_number <= target {
+            let number = self.state.next_block_number;
+            let block: BlockView = self
+                .ckb_client
+                .get_block_by_number(number.into())
+                .map_err(|err| CellCollectorError::Internal(anyhow!(err)))?
+                .ok_or_else(|| {
+                    CellCollectorError::Internal(anyhow!("block not found: {}", number))
+                })?
+                .into();
+            if let Some(last_hash) = self.state.last_block_hash.as_ref() {
+                if &block.parent_hash() != last_hash {
+                    return Err(CellCollectorError::Other(anyhow!(
+                        "chain reorganized at block {}, deeper than confirmations: {}, rescan required",
+                        number,
+                        self.confirmations
+                    )));
+                }
+            }
+            let events = self.state.apply_block(&block, &self.lock_scripts);
+            scanned += 1;
+            let progress = ScanProgress {
+                current: number,
+                target,
+            };
+            let stopped = !events
+                .into_iter()
+                .chain(Some(ScanEvent::Progress(progress)))
+                .all(&mut on_event);
+            if stopped {
+                break;
+            }
+            if scanned % SAVE_INTERVAL == 0 {
+                self.save()?;
+            }
+        }
+        if scanned > 0 {
+            self.save()?;
+        }
+        Ok(ScanProgress {
+            current: self.state.next_block_number.saturating_sub(1),
+            target,
+        })
+    }
+
+    /// Turn the collector into a stream of scan events, the blocks are
+    /// scanned in a background thread, the tip is polled every `interval`
+    /// after catching up. At most `buffer` events are buffered, the scanning
+    /// waits for the consumer when the buffer is full.
+    pub fn into_event_stream(mut self, interval: Duration, buffer: usize) -> ScanEventStream {
+        let (mut sender, receiver) = mpsc::channel(buffer);
+        thread::spawn(move || loop {
+            let result = self.sync_with(|event| block_on(sender.send(Ok(event))).is_ok());
+            if let Err(err) = result {
+                let _ = block_on(sender.send(Err(err)));
+                return;
+            }
+            if sender.is_closed() {
+                return;
+            }
+            thread::sleep(interval);
+        });
+        ScanEventStream { receiver }
+    }
+
+    /// Persist the scan state to the store file (if configured)
+    pub fn save(&self) -> Result<(), CellCollectorError> {
+        if let Some(path) = self.store_path.as_ref() {
+            self.state.save(path, &self.lock_scripts)?;
+        }
+        Ok(())
+    }
+}
+
+impl CellCollector for BlockScanCellCollector {
+    fn collect_live_cells(
+        &mut self,
+        query: &CellQueryOptions,
+        apply_changes: bool,
+    ) -> Result<(Vec<LiveCell>, u64), CellCollectorError> {
+        if query.primary_type == PrimaryScriptType::Lock
+            && !self.lock_scripts.contains(&query.primary_script)
+        {
+            return Err(CellCollectorError::Other(anyhow!(
+                "lock script not tracked by block scanner: {}",
+                query.primary_script
+            )));
+        }
+        self.sync()?;
+        let max_mature_number = get_max_mature_number(&self.ckb_client)
+            .map_err(|err| CellCollectorError::Internal(anyhow!(err)))?;
+
+        self.offchain.max_mature_number = max_mature_number;
+        let tip_num = self
+            .ckb_client
+            .get_tip_block_number()
+            .map_err(|err| CellCollectorError::Internal(anyhow!(err)))?
+            .value();
+        let CollectResult {
+            cells,
+            rest_cells,
+            mut total_capacity,
+        } = self.offchain.collect(query, tip_num);
+        let mut cells: Vec<_> = cells.into_iter().map(|c| c.0).collect();
+
+        if total_capacity < query.min_total_capacity {
+            let mut scanned_cells: Vec<_> = self
+                .state
+                .cells
+                .iter()
+                .filter(|(key, cell)| {
+                    !self.offchain.locked_cells.contains_key(key)
+                        && query.match_cell(cell, max_mature_number)
+                        && !cells.iter().any(|c| c.out_point == cell.out_point)
+                })
+                .map(|(_, cell)| cell.clone())
+                .collect();
+            scanned_cells.sort_by_key(|cell| (cell.block_number, cell.tx_index));
+            if query.order == QueryOrder::Desc {
+                scanned_cells.reverse();
+            }
+            for cell in scanned_cells {
+                if total_capacity >= query.min_total_capacity
+                    || query.limit.map(|limit| cells.len() >= limit as usize) == Some(true)
+                {
+                    break;
+                }
+                let capacity: u64 = cell.output.capacity().unpack();
+                total_capacity += capacity;
+                cells.push(cell);
+            }
+        }
+        if apply_changes {
+            self.offchain.live_cells = rest_cells;
+            for cell in &cells {
+                self.lock_cell(cell.out_point.clone(), tip_num)?;
+            }
+        }
+        Ok((cells, total_capacity))
+    }
+
+    fn lock_cell(
+        &mut self,
+        out_point: OutPoint,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        self.offchain.lock_cell(out_point, tip_block_number)
+    }
+    fn apply_tx(
+        &mut self,
+        tx: Transaction,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        self.offchain.apply_tx(tx, tip_block_number)
+    }
+    fn reset(&mut self) {
+        self.offchain.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::MockRpcResult;
+    use ckb_types::{
+        bytes::Bytes,
+        core::{BlockBuilder, TransactionBuilder},
+        packed::CellInput,
+    };
+    use futures::StreamExt;
+    use httpmock::prelude::*;
+
+    #[test]
+    fn test_scan_state_apply_block_and_store() {
+        let lock = Script::new_builder()
+            .args(Bytes::from(vec![1u8; 20]).pack())
+            .build();
+        let other_lock = Script::new_builder()
+            .args(Bytes::from(vec![2u8; 20]).pack())
+            .build();
+        let output = |lock: &Script| {
+            CellOutput::new_builder()
+                .capacity(100u64.pack())
+                .lock(lock.clone())
+                .build()
+        };
+        let tx1 = TransactionBuilder::default()
+            .output(output(&lock))
+            .output(output(&other_lock))
+            .output_data(Bytes::new().pack())
+            .output_data(Bytes::new().pack())
+            .build();
+        let block1 = BlockBuilder::default()
+            .number(1u64.pack())
+            .transaction(tx1.clone())
+            .build();
+        let tx2 = TransactionBuilder::default()
+            .input(CellInput::new(OutPoint::new(tx1.hash(), 0), 0))
+            .output(output(&lock))
+            .output_data(Bytes::from(vec![3u8]).pack())
+            .build();
+        let block2 = BlockBuilder::default()
+            .number(2u64.pack())
+            .parent_hash(block1.hash())
+            .transaction(tx2.clone())
+            .build();
+
+        let locks = vec![lock.clone()];
+        let mut state = ScanState::default();
+        let events = state.apply_block(&block1, &locks);
+        assert!(matches!(
+            events.as_slice(),
+            [ScanEvent::Received(cell)] if cell.out_point == OutPoint::new(tx1.hash(), 0)
+        ));
+        assert_eq!(state.cells.len(), 1);
+        let events = state.apply_block(&block2, &locks);
+        assert!(matches!(
+            events.as_slice(),
+            [ScanEvent::Spent(out_point), ScanEvent::Received(cell)]
+                if *out_point == OutPoint::new(tx1.hash(), 0)
+                    && cell.out_point == OutPoint::new(tx2.hash(), 0)
+        ));
+        assert_eq!(state.cells.len(), 1);
+        assert_eq!(state.next_block_number, 3);
+        let cell = state.cells.values().next().unwrap();
+        assert_eq!(cell.out_point, OutPoint::new(tx2.hash(), 0));
+        assert_eq!(cell.output_data, Bytes::from(vec![3u8]));
+
+        let path = std::env::temp_dir().join(format!("ckb-sdk-block-scan-{}.json", tx2.hash()));
+        state.save(&path, &locks).unwrap();
+        let loaded = ScanState::load(&path, &locks).unwrap();
+        assert_eq!(loaded.next_block_number, 3);
+        assert_eq!(loaded.last_block_hash, Some(block2.hash()));
+        assert_eq!(loaded.cells.len(), 1);
+        // New lock script requires a full rescan
+        let loaded = ScanState::load(&path, &[lock, other_lock]).unwrap();
+        assert_eq!(loaded.next_block_number, 0);
+        assert!(loaded.cells.is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_scan_event_stream() {
+        let lock = Script::new_builder()
+            .args(Bytes::from(vec![1u8; 20]).pack())
+            .build();
+        let output = CellOutput::new_builder()
+            .capacity(100u64.pack())
+            .lock(lock.clone())
+            .build();
+        let tx1 = TransactionBuilder::default()
+            .output(output.clone())
+            .output_data(Bytes::new().pack())
+            .build();
+        let block1 = BlockBuilder::default()
+            .number(1u64.pack())
+            .transaction(tx1.clone())
+            .build();
+        let tx2 = TransactionBuilder::default()
+            .input(CellInput::new(OutPoint::new(tx1.hash(), 0), 0))
+            .output(output)
+            .output_data(Bytes::new().pack())
+            .build();
+        let block2 = BlockBuilder::default()
+            .number(2u64.pack())
+            .parent_hash(block1.hash())
+            .transaction(tx2.clone())
+            .build();
+
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(POST)
+                .path("/")
+                .body_contains("get_tip_block_number");
+            then.status(200)
+                .body(MockRpcResult::new(json_types::BlockNumber::from(2)).to_json());
+        });
+        for block in [&block1, &block2] {
+            let number = format!(r#"["{:#x}"]"#, block.number());
+            let json_block = json_types::BlockView::from(block.clone());
+            server.mock(|when, then| {
+                when.method(POST)
+                    .path("/")
+                    .body_contains("get_block_by_number")
+                    .body_contains(number.as_str());
+                then.status(200)
+                    .body(MockRpcResult::new(Some(json_block)).to_json());
+            });
+        }
+
+        let mut collector =
+            BlockScanCellCollector::new(server.base_url().as_str(), vec![lock], None).unwrap();
+        collector.set_confirmations(0);
+        collector.set_start_block_number(1);
+        let mut stream = collector.into_event_stream(Duration::from_millis(10), 1);
+        let mut next_event = || block_on(stream.next()).unwrap().unwrap();
+        assert!(matches!(
+            next_event(),
+            ScanEvent::Received(cell) if cell.out_point == OutPoint::new(tx1.hash(), 0)
+        ));
+        assert!(matches!(
+            next_event(),
+            ScanEvent::Progress(ScanProgress {
+                current: 1,
+                target: 2
+            })
+        ));
+        assert!(matches!(
+            next_event(),
+            ScanEvent::Spent(out_point) if out_point == OutPoint::new(tx1.hash(), 0)
+        ));
+        assert!(matches!(
+            next_event(),
+            ScanEvent::Received(cell) if cell.out_point == OutPoint::new(tx2.hash(), 0)
+        ));
+        assert!(matches!(
+            next_event(),
+            ScanEvent::Progress(ScanProgress {
+                current: 2,
+                target: 2
+            })
+        ));
+        drop(stream);
+
+        // The stream ends after the error
+        let mut collector =
+            BlockScanCellCollector::new(server.base_url().as_str(), vec![Script::default()], None)
+                .unwrap();
+        collector.set_confirmations(0);
+        let mut stream = collector.into_event_stream(Duration::from_millis(10), 1);
+        assert!(block_on(stream.next()).unwrap().is_err());
+        assert!(block_on(stream.next()).is_none());
+    }
+}
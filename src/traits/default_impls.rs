@@ -13,7 +13,7 @@ use ckb_hash::blake2b_256;
 use ckb_jsonrpc_types::{self as json_types, Either};
 use ckb_types::{
     bytes::Bytes,
-    core::{BlockView, DepType, HeaderView, TransactionView},
+    core::{BlockView, DepType, FeeRate, HeaderView, TransactionView},
     packed::{Byte32, CellDep, CellOutput, OutPoint, Script, Transaction, TransactionReader},
     prelude::*,
     H160,
@@ -23,22 +23,24 @@ use super::{
     offchain_impls::CollectResult, OffchainCellCollector, OffchainCellDepResolver,
     OffchainTransactionDependencyProvider,
 };
-use crate::rpc::ckb_indexer::{Order, SearchKey, Tip};
+use crate::rpc::ckb_indexer::{DefaultSearchKeyConverter, Order, SearchKeyConverter, Tip};
 use crate::rpc::{CkbRpcClient, IndexerRpcClient};
 use crate::traits::{
-    CellCollector, CellCollectorError, CellDepResolver, CellQueryOptions, HeaderDepResolver,
-    LiveCell, QueryOrder, Signer, SignerError, TransactionDependencyError,
-    TransactionDependencyProvider,
+    CellCollector, CellCollectorError, CellDepResolver, CellQueryOptions, FeePriority,
+    FeeRateProvider, HeaderDepResolver, LiveCell, MaturityOption, QueryOrder, Signer, SignerError,
+    TipTracker, TransactionDependencyError, TransactionDependencyProvider,
 };
 use crate::types::ScriptId;
 use crate::util::{get_max_mature_number, serialize_signature, zeroize_privkey};
 use crate::SECP256K1;
 use crate::{
+    constants::SystemScriptConstants,
     constants::{
         DAO_OUTPUT_LOC, DAO_TYPE_HASH, MULTISIG_GROUP_OUTPUT_LOC, MULTISIG_OUTPUT_LOC,
         MULTISIG_TYPE_HASH, SIGHASH_GROUP_OUTPUT_LOC, SIGHASH_OUTPUT_LOC, SIGHASH_TYPE_HASH,
     },
     util::keccak160,
+    NetworkType,
 };
 use ckb_resource::{
     CODE_HASH_DAO, CODE_HASH_SECP256K1_BLAKE160_MULTISIG_ALL,
@@ -168,6 +170,36 @@ impl DefaultCellDepResolver {
         let offchain = OffchainCellDepResolver { items };
         Ok(DefaultCellDepResolver { offchain })
     }
+    /// Resolve the system scripts by the constants of the network, see
+    /// [`SystemScriptConstants::get`], no RPC is required.
+    pub fn from_network(network: NetworkType) -> Result<DefaultCellDepResolver, anyhow::Error> {
+        Ok(Self::from_constants(&SystemScriptConstants::get(network)?))
+    }
+
+    pub fn from_constants(constants: &SystemScriptConstants) -> DefaultCellDepResolver {
+        let mut items = HashMap::default();
+        items.insert(
+            ScriptId::new_type(constants.sighash_type_hash.clone()),
+            (
+                constants.sighash_dep.clone(),
+                "Secp256k1 blake160 sighash all".to_string(),
+            ),
+        );
+        items.insert(
+            ScriptId::new_type(constants.multisig_type_hash.clone()),
+            (
+                constants.multisig_dep.clone(),
+                "Secp256k1 blake160 multisig all".to_string(),
+            ),
+        );
+        items.insert(
+            ScriptId::new_type(constants.dao_type_hash.clone()),
+            (constants.dao_dep.clone(), "Nervos DAO".to_string()),
+        );
+        let offchain = OffchainCellDepResolver { items };
+        DefaultCellDepResolver { offchain }
+    }
+
     pub fn insert(
         &mut self,
         script_id: ScriptId,
@@ -202,6 +234,68 @@ impl CellDepResolver for DefaultCellDepResolver {
     }
 }
 
+/// A fee rate provider use the fee rate statistics of the ckb node.
+///
+/// The fee rate of each priority:
+///   * `Low`: the median fee rate of the recent 101 blocks
+///   * `Medium`: the median fee rate of the recent 21 blocks
+///   * `High`: the max of the mean and the median fee rate of the recent 21
+///     blocks
+///
+/// The fee rate is never lower than `min_fee_rate`, which is also used when
+/// the node has no statistics (e.g. no transaction in the recent blocks).
+pub struct DefaultFeeRateProvider {
+    ckb_client: CkbRpcClient,
+    priority: FeePriority,
+    min_fee_rate: u64,
+}
+impl DefaultFeeRateProvider {
+    pub fn new(ckb_client: &str, priority: FeePriority) -> DefaultFeeRateProvider {
+        let ckb_client = CkbRpcClient::new(ckb_client);
+        DefaultFeeRateProvider {
+            ckb_client,
+            priority,
+            min_fee_rate: 1000,
+        }
+    }
+
+    pub fn set_min_fee_rate(&mut self, min_fee_rate: u64) {
+        self.min_fee_rate = min_fee_rate;
+    }
+}
+impl std::fmt::Debug for DefaultFeeRateProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("DefaultFeeRateProvider")
+            .field("priority", &self.priority)
+            .field("min_fee_rate", &self.min_fee_rate)
+            .finish()
+    }
+}
+impl FeeRateProvider for DefaultFeeRateProvider {
+    fn fee_rate(&self) -> Result<FeeRate, anyhow::Error> {
+        let target = match self.priority {
+            FeePriority::Low => 101,
+            FeePriority::Medium | FeePriority::High => 21,
+        };
+        let statistics = self
+            .ckb_client
+            .get_fee_rate_statistics(Some(target.into()))
+            .map_err(|e| anyhow!(e))?;
+        let fee_rate = match statistics {
+            Some(statistics) => {
+                let mean: u64 = statistics.mean.into();
+                let median: u64 = statistics.median.into();
+                match self.priority {
+                    FeePriority::Low | FeePriority::Medium => median,
+                    FeePriority::High => mean.max(median),
+                }
+            }
+            None => self.min_fee_rate,
+        };
+        Ok(FeeRate::from_u64(fee_rate.max(self.min_fee_rate)))
+    }
+}
+
 /// A header_dep resolver use ckb jsonrpc client as backend
 pub struct DefaultHeaderDepResolver {
     ckb_client: CkbRpcClient,
@@ -245,6 +339,8 @@ pub struct DefaultCellCollector {
     ckb_client: CkbRpcClient,
     offchain: OffchainCellCollector,
     acceptable_indexer_leftbehind: u64,
+    search_key_converter: Arc<dyn SearchKeyConverter>,
+    tip_tracker: Option<TipTracker>,
 }
 
 impl DefaultCellCollector {
@@ -256,9 +352,39 @@ impl DefaultCellCollector {
             ckb_client,
             offchain: OffchainCellCollector::default(),
             acceptable_indexer_leftbehind: 1,
+            search_key_converter: Arc::new(DefaultSearchKeyConverter),
+            tip_tracker: None,
         }
     }
 
+    /// Use the tip cached by the tracker for the maturity check and the
+    /// offchain cells instead of querying the tip on every collection
+    pub fn set_tip_tracker(&mut self, tip_tracker: TipTracker) {
+        self.tip_tracker = Some(tip_tracker);
+    }
+
+    /// Set the conversion from the query options to the indexer search key,
+    /// the default is [`DefaultSearchKeyConverter`]
+    pub fn set_search_key_converter(&mut self, converter: Arc<dyn SearchKeyConverter>) {
+        self.search_key_converter = converter;
+    }
+
+    /// The total capacity of all the live cells of the lock script (include
+    /// the cells with data or type script and the immature cells), by the
+    /// indexer `get_cells_capacity` instead of paginating the cells.
+    pub fn get_balance(&mut self, lock_script: &Script) -> Result<u64, CellCollectorError> {
+        self.check_ckb_chain()?;
+        let search_key = self
+            .search_key_converter
+            .to_search_key(&CellQueryOptions::new_lock(lock_script.clone()));
+        Ok(self
+            .indexer_client
+            .get_cells_capacity(search_key)
+            .map_err(|err| CellCollectorError::Internal(err.into()))?
+            .map(|cells_capacity| cells_capacity.capacity.value())
+            .unwrap_or_default())
+    }
+
     /// THe acceptable ckb-indexer leftbehind block number (default = 1)
     pub fn acceptable_indexer_leftbehind(&self) -> u64 {
         self.acceptable_indexer_leftbehind
@@ -309,15 +435,22 @@ impl CellCollector for DefaultCellCollector {
         query: &CellQueryOptions,
         apply_changes: bool,
     ) -> Result<(Vec<LiveCell>, u64), CellCollectorError> {
-        let max_mature_number = get_max_mature_number(&self.ckb_client)
-            .map_err(|err| CellCollectorError::Internal(anyhow!(err)))?;
-
+        let (max_mature_number, tip_num) = if let Some(tip_tracker) = self.tip_tracker.as_ref() {
+            let tip = tip_tracker
+                .tip()
+                .map_err(|err| CellCollectorError::Internal(anyhow!(err)))?;
+            (tip.max_mature_number, tip.number())
+        } else {
+            let max_mature_number = get_max_mature_number(&self.ckb_client)
+                .map_err(|err| CellCollectorError::Internal(anyhow!(err)))?;
+            let tip_num = self
+                .ckb_client
+                .get_tip_block_number()
+                .map_err(|err| CellCollectorError::Internal(anyhow!(err)))?
+                .value();
+            (max_mature_number, tip_num)
+        };
         self.offchain.max_mature_number = max_mature_number;
-        let tip_num = self
-            .ckb_client
-            .get_tip_block_number()
-            .map_err(|err| CellCollectorError::Internal(anyhow!(err)))?
-            .value();
         let CollectResult {
             cells,
             rest_cells,
@@ -336,7 +469,7 @@ impl CellCollector for DefaultCellCollector {
                 .map(|c| (c.out_point.clone(), c))
                 .collect();
             let locked_cells = self.offchain.locked_cells.clone();
-            let search_key = SearchKey::from(query.clone());
+            let search_key = self.search_key_converter.to_search_key(query);
             const MAX_LIMIT: u32 = 4096;
             let mut limit: u32 = query.limit.unwrap_or(16);
             let mut last_cursor: Option<json_types::JsonBytes> = None;
@@ -386,6 +519,32 @@ impl CellCollector for DefaultCellCollector {
         Ok((cells, total_capacity))
     }
 
+    fn max_total_capacity(
+        &mut self,
+        query: &CellQueryOptions,
+    ) -> Result<Option<u64>, CellCollectorError> {
+        self.check_ckb_chain()?;
+        let search_key = self.search_key_converter.to_search_key(query);
+        let indexed_capacity = self
+            .indexer_client
+            .get_cells_capacity(search_key)
+            .map_err(|err| CellCollectorError::Internal(err.into()))?
+            .map(|cells_capacity| cells_capacity.capacity.value())
+            .unwrap_or_default();
+        // The outputs of the applied transactions may not be indexed yet, the
+        // maturity is ignored so they are never under counted.
+        let mut offchain_query = query.clone();
+        offchain_query.maturity = MaturityOption::Both;
+        let offchain_capacity: u64 = self
+            .offchain
+            .live_cells
+            .iter()
+            .filter(|(cell, _)| offchain_query.match_cell(cell, 0))
+            .map(|(cell, _)| Unpack::<u64>::unpack(&cell.output.capacity()))
+            .sum();
+        Ok(Some(indexed_capacity.saturating_add(offchain_capacity)))
+    }
+
     fn lock_cell(
         &mut self,
         out_point: OutPoint,
@@ -410,6 +569,7 @@ struct DefaultTxDepProviderInner {
     tx_cache: LruCache<Byte32, TransactionView>,
     cell_cache: LruCache<OutPoint, (CellOutput, Bytes)>,
     header_cache: LruCache<Byte32, HeaderView>,
+    extension_cache: LruCache<Byte32, Option<ckb_types::packed::Bytes>>,
     offchain_cache: OffchainTransactionDependencyProvider,
 }
 
@@ -437,6 +597,7 @@ impl DefaultTransactionDependencyProvider {
             tx_cache: LruCache::new(cache_capacity),
             cell_cache: LruCache::new(cache_capacity),
             header_cache: LruCache::new(cache_capacity),
+            extension_cache: LruCache::new(cache_capacity),
             offchain_cache: OffchainTransactionDependencyProvider::new(),
         };
         DefaultTransactionDependencyProvider {
@@ -556,14 +717,23 @@ impl TransactionDependencyProvider for DefaultTransactionDependencyProvider {
         &self,
         block_hash: &Byte32,
     ) -> Result<Option<ckb_types::packed::Bytes>, TransactionDependencyError> {
-        let inner = self.inner.lock();
+        let mut inner = self.inner.lock();
+        if let Some(extension) = inner.extension_cache.get(block_hash) {
+            return Ok(extension.clone());
+        }
 
         let block = inner
             .rpc_client
             .get_block(block_hash.unpack())
             .map_err(|err| TransactionDependencyError::Other(err.into()))?;
         match block {
-            Some(block) => Ok(block.extension.map(ckb_types::packed::Bytes::from)),
+            Some(block) => {
+                let extension = block.extension.map(ckb_types::packed::Bytes::from);
+                inner
+                    .extension_cache
+                    .put(block_hash.clone(), extension.clone());
+                Ok(extension)
+            }
             None => Ok(None),
         }
     }
@@ -607,6 +777,27 @@ impl SecpCkbRawKeySigner {
         let hash160 = keccak160(Pubkey::from(pubkey).as_ref());
         self.keys.insert(hash160, key);
     }
+
+    /// Create SecpkRawKeySigner from secret keys for bitcoin algorithm.
+    #[cfg(feature = "bitcoin")]
+    pub fn new_with_bitcoin_secret_keys(keys: Vec<secp256k1::SecretKey>) -> SecpCkbRawKeySigner {
+        let mut signer = SecpCkbRawKeySigner::default();
+        for key in keys {
+            signer.add_bitcoin_secret_key(key);
+        }
+        signer
+    }
+    /// Add a bitcoin secret key, the id is `hash160(pubkey)` of both the
+    /// compressed and the uncompressed public key.
+    #[cfg(feature = "bitcoin")]
+    pub fn add_bitcoin_secret_key(&mut self, key: secp256k1::SecretKey) {
+        use crate::util::hash160;
+
+        let pubkey = secp256k1::PublicKey::from_secret_key(&SECP256K1, &key);
+        self.keys.insert(hash160(&pubkey.serialize()), key);
+        self.keys
+            .insert(hash160(&pubkey.serialize_uncompressed()), key);
+    }
 }
 
 impl Signer for SecpCkbRawKeySigner {
@@ -660,3 +851,77 @@ mod anyhow_tests {
         assert_eq!("data not found: `DataHashNotFound`", error.to_string());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::ckb_indexer::{CellsCapacity, SearchKey};
+    use crate::test_util::MockRpcResult;
+    use ckb_types::{core::ScriptHashType, H256};
+    use httpmock::prelude::*;
+
+    /// Target an indexer fork grouping the cells by transaction
+    struct GroupByTransaction;
+
+    impl SearchKeyConverter for GroupByTransaction {
+        fn to_search_key(&self, query: &CellQueryOptions) -> SearchKey {
+            let mut search_key = DefaultSearchKeyConverter.to_search_key(query);
+            search_key.group_by_transaction = Some(true);
+            search_key
+        }
+    }
+
+    #[test]
+    fn test_search_key_converter() {
+        let lock_script = Script::new_builder()
+            .code_hash(SIGHASH_TYPE_HASH.pack())
+            .hash_type(ScriptHashType::Type.into())
+            .args(Bytes::from(vec![1u8; 20]).pack())
+            .build();
+        let query = CellQueryOptions::new_lock(lock_script.clone());
+        assert_eq!(
+            serde_json::to_string(&DefaultSearchKeyConverter.to_search_key(&query)).unwrap(),
+            serde_json::to_string(&SearchKey::from(query)).unwrap()
+        );
+
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(POST)
+                .path("/")
+                .body_contains("get_tip_block_number");
+            then.status(200)
+                .body(MockRpcResult::new(json_types::BlockNumber::from(100)).to_json());
+        });
+        server.mock(|when, then| {
+            when.method(POST).path("/").body_contains("get_indexer_tip");
+            then.status(200).body(
+                MockRpcResult::new(Tip {
+                    block_hash: H256::default(),
+                    block_number: 100.into(),
+                })
+                .to_json(),
+            );
+        });
+        let capacity_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/")
+                .body_contains("get_cells_capacity")
+                .body_contains(r#""group_by_transaction":true"#);
+            then.status(200).body(
+                MockRpcResult::new(CellsCapacity {
+                    capacity: 500.into(),
+                    block_hash: H256::default(),
+                    block_number: 100.into(),
+                })
+                .to_json(),
+            );
+        });
+
+        let mut collector = DefaultCellCollector::new(server.base_url().as_str());
+        // The request of the default converter does not match the mock
+        assert!(collector.get_balance(&lock_script).is_err());
+        collector.set_search_key_converter(Arc::new(GroupByTransaction));
+        assert_eq!(collector.get_balance(&lock_script).unwrap(), 500);
+        capacity_mock.assert_hits(1);
+    }
+}
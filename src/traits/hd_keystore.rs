@@ -0,0 +1,273 @@
+//! A [`Signer`] of an HD wallet account.
+//!
+//! The keys are derived from the account key `m/44'/309'/{account}'` on the
+//! receiving chain `{account}/0/{index}` and the change chain
+//! `{account}/1/{index}`. The signer finds the child key of a lock arg by
+//! deriving the keys of each chain until `gap_limit` consecutive indices
+//! after the last used one do not match, the found indices are cached.
+use std::collections::HashMap;
+
+use parking_lot::Mutex;
+
+use ckb_types::{bytes::Bytes, core::TransactionView, H160};
+
+use super::{Signer, SignerError};
+use crate::types::bip32::{
+    account_path, Bip32Error, ExtendedPrivKey, ExtendedPubKey, CHANGE_CHAIN, EXTERNAL_CHAIN,
+};
+#[cfg(feature = "keystore")]
+use crate::types::mnemonic::{Mnemonic, MnemonicError};
+use crate::util::{blake160, serialize_signature, zeroize_privkey};
+use crate::SECP256K1;
+
+/// The gap limit suggested by BIP-44
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+
+#[derive(Default)]
+struct KeyIndex {
+    /// lock arg => (chain, index)
+    paths: HashMap<H160, (u32, u32)>,
+    /// The derived indices of the receiving and change chains
+    derived: [u32; 2],
+    /// The next index after the last used one of the receiving and change chains
+    used: [u32; 2],
+}
+
+/// Sign with the child keys of an HD wallet account
+pub struct HdKeystoreSigner {
+    account_key: ExtendedPrivKey,
+    chain_keys: [ExtendedPubKey; 2],
+    gap_limit: u32,
+    index: Mutex<KeyIndex>,
+}
+
+impl HdKeystoreSigner {
+    /// Use the account key `m/44'/309'/{account}'`
+    pub fn new(account_key: ExtendedPrivKey) -> Result<HdKeystoreSigner, Bip32Error> {
+        let chain_keys = [
+            account_key
+                .derive_child(EXTERNAL_CHAIN)?
+                .to_extended_pubkey(),
+            account_key.derive_child(CHANGE_CHAIN)?.to_extended_pubkey(),
+        ];
+        Ok(HdKeystoreSigner {
+            account_key,
+            chain_keys,
+            gap_limit: DEFAULT_GAP_LIMIT,
+            index: Mutex::new(KeyIndex::default()),
+        })
+    }
+
+    /// Derive the account key from the master key
+    pub fn from_master(
+        master_key: &ExtendedPrivKey,
+        account: u32,
+    ) -> Result<HdKeystoreSigner, Bip32Error> {
+        HdKeystoreSigner::new(master_key.derive_path(&account_path(account))?)
+    }
+
+    /// Derive the master key and the account key from the seed
+    pub fn from_seed(seed: &[u8], account: u32) -> Result<HdKeystoreSigner, Bip32Error> {
+        HdKeystoreSigner::from_master(&ExtendedPrivKey::new_master(seed)?, account)
+    }
+
+    /// Derive the account key from the mnemonic and the passphrase
+    #[cfg(feature = "keystore")]
+    pub fn from_mnemonic(
+        mnemonic: &Mnemonic,
+        passphrase: &str,
+        account: u32,
+    ) -> Result<HdKeystoreSigner, MnemonicError> {
+        Ok(HdKeystoreSigner::from_master(
+            &mnemonic.to_master_key(passphrase)?,
+            account,
+        )?)
+    }
+
+    pub fn gap_limit(mut self, gap_limit: u32) -> Self {
+        self.gap_limit = gap_limit;
+        self
+    }
+
+    /// The account extended public key, to derive the addresses without the
+    /// private key
+    pub fn account_pubkey(&self) -> ExtendedPubKey {
+        self.account_key.to_extended_pubkey()
+    }
+
+    /// The sighash lock arg of `{account}/{chain}/{index}`
+    pub fn derive_lock_arg(&self, chain: u32, index: u32) -> Result<H160, Bip32Error> {
+        let chain_key = self.chain_key(chain)?;
+        Ok(blake160(
+            &chain_key.derive_child(index)?.public_key.serialize(),
+        ))
+    }
+
+    /// The sighash lock arg of the receiving address `{account}/0/{index}`
+    pub fn receive_lock_arg(&self, index: u32) -> Result<H160, Bip32Error> {
+        self.derive_lock_arg(EXTERNAL_CHAIN, index)
+    }
+
+    /// The sighash lock arg of the change address `{account}/1/{index}`
+    pub fn change_lock_arg(&self, index: u32) -> Result<H160, Bip32Error> {
+        self.derive_lock_arg(CHANGE_CHAIN, index)
+    }
+
+    /// Mark the index as used, the search extends to `gap_limit` indices
+    /// after it
+    pub fn mark_used(&self, chain: u32, index: u32) -> Result<(), Bip32Error> {
+        self.chain_key(chain)?;
+        let mut key_index = self.index.lock();
+        let used = &mut key_index.used[chain as usize];
+        *used = (*used).max(index.saturating_add(1));
+        Ok(())
+    }
+
+    /// The `(chain, index)` of the lock arg within the gap limit
+    pub fn find_path(&self, lock_arg: &H160) -> Option<(u32, u32)> {
+        let mut key_index = self.index.lock();
+        if let Some(path) = key_index.paths.get(lock_arg) {
+            return Some(*path);
+        }
+        for chain in [EXTERNAL_CHAIN, CHANGE_CHAIN] {
+            let slot = chain as usize;
+            while key_index.derived[slot] < key_index.used[slot].saturating_add(self.gap_limit) {
+                let index = key_index.derived[slot];
+                key_index.derived[slot] += 1;
+                // An invalid child key is skipped as BIP-32 suggests
+                let derived = match self.derive_lock_arg(chain, index) {
+                    Ok(derived) => derived,
+                    Err(_) => continue,
+                };
+                key_index.paths.insert(derived.clone(), (chain, index));
+                if &derived == lock_arg {
+                    let used = &mut key_index.used[slot];
+                    *used = (*used).max(index + 1);
+                    return Some((chain, index));
+                }
+            }
+        }
+        None
+    }
+
+    fn chain_key(&self, chain: u32) -> Result<&ExtendedPubKey, Bip32Error> {
+        self.chain_keys
+            .get(chain as usize)
+            .ok_or_else(|| Bip32Error::InvalidPath(format!("unknown chain: {}", chain)))
+    }
+}
+
+impl Signer for HdKeystoreSigner {
+    fn match_id(&self, id: &[u8]) -> bool {
+        id.len() == 20 && self.find_path(&H160::from_slice(id).unwrap()).is_some()
+    }
+
+    fn sign(
+        &self,
+        id: &[u8],
+        message: &[u8],
+        recoverable: bool,
+        _tx: &TransactionView,
+    ) -> Result<Bytes, SignerError> {
+        if id.len() != 20 {
+            return Err(SignerError::IdNotFound);
+        }
+        let (chain, index) = self
+            .find_path(&H160::from_slice(id).unwrap())
+            .ok_or(SignerError::IdNotFound)?;
+        if message.len() != 32 {
+            return Err(SignerError::InvalidMessage(format!(
+                "expected length: 32, got: {}",
+                message.len()
+            )));
+        }
+        let mut key = self
+            .account_key
+            .derive_path(&[chain, index])
+            .map_err(|err| SignerError::Other(err.into()))?
+            .private_key;
+        let msg =
+            secp256k1::Message::from_digest_slice(message).expect("Convert to message failed");
+        let signature = if recoverable {
+            let sig = SECP256K1.sign_ecdsa_recoverable(&msg, &key);
+            Bytes::from(serialize_signature(&sig).to_vec())
+        } else {
+            let sig = SECP256K1.sign_ecdsa(&msg, &key);
+            Bytes::from(sig.serialize_compact().to_vec())
+        };
+        zeroize_privkey(&mut key);
+        Ok(signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::core::TransactionBuilder;
+
+    #[test]
+    fn test_hd_keystore_signer() {
+        let seed = [1u8; 32];
+        let signer = HdKeystoreSigner::from_seed(&seed, 0).unwrap().gap_limit(5);
+        let master = ExtendedPrivKey::new_master(&seed).unwrap();
+        let mut path = account_path(0);
+        path.extend_from_slice(&[CHANGE_CHAIN, 3]);
+        let change_key = master.derive_path(&path).unwrap();
+        let change_arg = blake160(&change_key.public_key().serialize());
+        assert_eq!(signer.change_lock_arg(3).unwrap(), change_arg);
+        assert_eq!(
+            signer
+                .account_pubkey()
+                .derive_path(&[CHANGE_CHAIN, 3])
+                .unwrap(),
+            change_key.to_extended_pubkey()
+        );
+
+        assert!(signer.match_id(change_arg.as_bytes()));
+        assert_eq!(signer.find_path(&change_arg), Some((CHANGE_CHAIN, 3)));
+        let tx = TransactionBuilder::default().build();
+        let message = [2u8; 32];
+        let signature = signer
+            .sign(change_arg.as_bytes(), &message, true, &tx)
+            .unwrap();
+        let recoverable = secp256k1::ecdsa::RecoverableSignature::from_compact(
+            &signature[0..64],
+            secp256k1::ecdsa::RecoveryId::from_i32(signature[64] as i32).unwrap(),
+        )
+        .unwrap();
+        let msg = secp256k1::Message::from_digest_slice(&message).unwrap();
+        assert_eq!(
+            SECP256K1.recover_ecdsa(&msg, &recoverable).unwrap(),
+            change_key.public_key()
+        );
+
+        // Index 8 is beyond the gap limit until index 3 is used, the window
+        // moves to 5 indices after each found index
+        let receive_arg = signer.receive_lock_arg(8).unwrap();
+        let signer = HdKeystoreSigner::from_seed(&seed, 0).unwrap().gap_limit(5);
+        assert!(!signer.match_id(receive_arg.as_bytes()));
+        signer.mark_used(EXTERNAL_CHAIN, 3).unwrap();
+        assert_eq!(signer.find_path(&receive_arg), Some((EXTERNAL_CHAIN, 8)));
+        assert!(signer.match_id(signer.receive_lock_arg(12).unwrap().as_bytes()));
+        assert!(!signer.match_id(signer.receive_lock_arg(18).unwrap().as_bytes()));
+        assert!(matches!(
+            signer.sign(&[0u8; 20], &message, true, &tx),
+            Err(SignerError::IdNotFound)
+        ));
+        assert!(signer.mark_used(2, 0).is_err());
+    }
+
+    #[cfg(feature = "keystore")]
+    #[test]
+    fn test_hd_keystore_from_mnemonic() {
+        use crate::types::mnemonic::Language;
+
+        let mnemonic = Mnemonic::from_entropy(Language::English, &[0u8; 16]).unwrap();
+        let signer = HdKeystoreSigner::from_mnemonic(&mnemonic, "TREZOR", 0).unwrap();
+        let expected = HdKeystoreSigner::from_seed(&mnemonic.to_seed("TREZOR"), 0).unwrap();
+        assert_eq!(
+            signer.receive_lock_arg(0).unwrap(),
+            expected.receive_lock_arg(0).unwrap()
+        );
+    }
+}
@@ -0,0 +1,589 @@
+//! The encrypted JSON keystore of ckb-cli.
+//!
+//! ckb-cli stores each master key (the secret key and the chain code, 64
+//! bytes) in a file named `UTC--{time}--{lock arg}` under its keystore
+//! directory. The key is encrypted by AES-128-CTR with the first half of the
+//! scrypt derived key, and authenticated by the keccak256 MAC of the second
+//! half and the ciphertext, the same as the Ethereum V3 keystore.
+//!
+//! [`KeystoreSigner`] loads a key directory and signs with the sighash key
+//! (the master key) of each file. The keys are unlocked on demand with a
+//! passphrase and relocked after a timeout.
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use aes::Aes128;
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Keccak256};
+use subtle::ConstantTimeEq;
+use thiserror::Error;
+
+use ckb_types::{bytes::Bytes, core::TransactionView, H160};
+
+use super::{Signer, SignerError};
+use crate::types::bip32::ExtendedPrivKey;
+use crate::util::{blake160, serialize_signature, zeroize_privkey, zeroize_slice};
+use crate::SECP256K1;
+
+type Aes128Ctr = ctr::Ctr128BE<Aes128>;
+
+/// The cipher of the keystore
+pub const KEYSTORE_CIPHER: &str = "aes-128-ctr";
+/// The key derivation function of the keystore
+pub const KEYSTORE_KDF: &str = "scrypt";
+/// The time a key stays unlocked by default
+pub const DEFAULT_UNLOCK_TIMEOUT: Duration = Duration::from_secs(300);
+
+const KEYSTORE_VERSION: u32 = 3;
+const KEYSTORE_ORIGIN: &str = "ckb-cli";
+
+#[derive(Error, Debug)]
+pub enum KeystoreError {
+    #[error("io error: `{0}`")]
+    Io(#[from] std::io::Error),
+
+    #[error("json error: `{0}`")]
+    Json(#[from] serde_json::Error),
+
+    #[error("unsupported keystore: `{0}`")]
+    Unsupported(String),
+
+    #[error("invalid keystore: `{0}`")]
+    Invalid(String),
+
+    #[error("wrong password")]
+    WrongPassword,
+
+    #[error("key not found: `{0:#x}`")]
+    KeyNotFound(H160),
+
+    #[error("key is locked: `{0:#x}`")]
+    Locked(H160),
+}
+
+/// The scrypt parameters, the default is the ckb-cli one (N = 2^18, r = 8,
+/// p = 1)
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct ScryptParams {
+    pub log_n: u8,
+    pub r: u32,
+    pub p: u32,
+}
+
+impl Default for ScryptParams {
+    fn default() -> ScryptParams {
+        ScryptParams {
+            log_n: 18,
+            r: 8,
+            p: 1,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Hash)]
+pub struct CipherParams {
+    pub iv: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Hash)]
+pub struct KdfParams {
+    pub dklen: usize,
+    pub n: u64,
+    pub r: u32,
+    pub p: u32,
+    pub salt: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Hash)]
+pub struct KeyCrypto {
+    pub cipher: String,
+    pub cipherparams: CipherParams,
+    pub ciphertext: String,
+    pub kdf: String,
+    pub kdfparams: KdfParams,
+    pub mac: String,
+}
+
+/// A key file of the keystore, the unknown fields are ignored
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Hash)]
+pub struct KeyFile {
+    pub id: String,
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    pub crypto: KeyCrypto,
+}
+
+impl KeyFile {
+    /// Encrypt the master key with the password
+    pub fn encrypt(
+        master_key: &ExtendedPrivKey,
+        password: &[u8],
+        params: ScryptParams,
+    ) -> Result<KeyFile, KeystoreError> {
+        let mut salt = [0u8; 32];
+        let mut iv = [0u8; 16];
+        let mut id = [0u8; 16];
+        for buf in [&mut salt[..], &mut iv[..], &mut id[..]] {
+            getrandom::getrandom(buf).map_err(|err| KeystoreError::Invalid(err.to_string()))?;
+        }
+        let kdfparams = KdfParams {
+            dklen: 32,
+            n: 1 << params.log_n,
+            r: params.r,
+            p: params.p,
+            salt: hex::encode(salt),
+        };
+        let mut derived_key = derive_key(password, &kdfparams)?;
+        let mut ciphertext =
+            [master_key.private_key.secret_bytes(), master_key.chain_code].concat();
+        Aes128Ctr::new(derived_key[0..16].into(), iv[..].into()).apply_keystream(&mut ciphertext);
+        let mac = calculate_mac(&derived_key, &ciphertext);
+        zeroize_slice(&mut derived_key);
+        // uuid v4
+        id[6] = (id[6] & 0x0f) | 0x40;
+        id[8] = (id[8] & 0x3f) | 0x80;
+        let id = hex::encode(id);
+        Ok(KeyFile {
+            id: format!(
+                "{}-{}-{}-{}-{}",
+                &id[0..8],
+                &id[8..12],
+                &id[12..16],
+                &id[16..20],
+                &id[20..32]
+            ),
+            version: KEYSTORE_VERSION,
+            origin: Some(KEYSTORE_ORIGIN.to_string()),
+            crypto: KeyCrypto {
+                cipher: KEYSTORE_CIPHER.to_string(),
+                cipherparams: CipherParams {
+                    iv: hex::encode(iv),
+                },
+                ciphertext: hex::encode(ciphertext),
+                kdf: KEYSTORE_KDF.to_string(),
+                kdfparams,
+                mac: hex::encode(mac),
+            },
+        })
+    }
+
+    /// Decrypt the master key with the password
+    pub fn decrypt(&self, password: &[u8]) -> Result<ExtendedPrivKey, KeystoreError> {
+        let crypto = &self.crypto;
+        if crypto.cipher != KEYSTORE_CIPHER {
+            return Err(KeystoreError::Unsupported(format!(
+                "cipher: {}",
+                crypto.cipher
+            )));
+        }
+        if crypto.kdf != KEYSTORE_KDF {
+            return Err(KeystoreError::Unsupported(format!("kdf: {}", crypto.kdf)));
+        }
+        let mut ciphertext = decode_hex("ciphertext", &crypto.ciphertext)?;
+        let iv = decode_hex("iv", &crypto.cipherparams.iv)?;
+        let mac = decode_hex("mac", &crypto.mac)?;
+        if ciphertext.len() != 64 || iv.len() != 16 {
+            return Err(KeystoreError::Invalid(format!(
+                "ciphertext length: {}, iv length: {}",
+                ciphertext.len(),
+                iv.len()
+            )));
+        }
+        let mut derived_key = derive_key(password, &crypto.kdfparams)?;
+        // Compare in constant time, the timing must not leak how many bytes
+        // of the MAC match
+        let expected_mac = calculate_mac(&derived_key, &ciphertext);
+        if !bool::from(expected_mac[..].ct_eq(&mac[..])) {
+            zeroize_slice(&mut derived_key);
+            return Err(KeystoreError::WrongPassword);
+        }
+        Aes128Ctr::new(derived_key[0..16].into(), iv[..].into()).apply_keystream(&mut ciphertext);
+        zeroize_slice(&mut derived_key);
+        let private_key = secp256k1::SecretKey::from_slice(&ciphertext[0..32])
+            .map_err(|err| KeystoreError::Invalid(err.to_string()));
+        let mut chain_code = [0u8; 32];
+        chain_code.copy_from_slice(&ciphertext[32..64]);
+        zeroize_slice(&mut ciphertext);
+        Ok(ExtendedPrivKey {
+            private_key: private_key?,
+            chain_code,
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<KeyFile, KeystoreError> {
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    /// Save the key file of the lock arg into the keystore directory with
+    /// the ckb-cli file name
+    pub fn save<P: AsRef<Path>>(&self, dir: P, lock_arg: &H160) -> Result<PathBuf, KeystoreError> {
+        fs::create_dir_all(dir.as_ref())?;
+        let path = dir.as_ref().join(format!(
+            "UTC--{}--{:x}",
+            utc_timestamp(SystemTime::now()),
+            lock_arg
+        ));
+        fs::write(&path, serde_json::to_string(self)?)?;
+        Ok(path)
+    }
+}
+
+fn decode_hex(name: &str, value: &str) -> Result<Vec<u8>, KeystoreError> {
+    hex::decode(value.trim_start_matches("0x"))
+        .map_err(|err| KeystoreError::Invalid(format!("{}: {}", name, err)))
+}
+
+fn derive_key(password: &[u8], kdfparams: &KdfParams) -> Result<[u8; 32], KeystoreError> {
+    if kdfparams.dklen != 32 || !kdfparams.n.is_power_of_two() {
+        return Err(KeystoreError::Unsupported(format!(
+            "scrypt dklen: {}, n: {}",
+            kdfparams.dklen, kdfparams.n
+        )));
+    }
+    let params = scrypt::Params::new(
+        kdfparams.n.trailing_zeros() as u8,
+        kdfparams.r,
+        kdfparams.p,
+        kdfparams.dklen,
+    )
+    .map_err(|err| KeystoreError::Unsupported(err.to_string()))?;
+    let salt = decode_hex("salt", &kdfparams.salt)?;
+    let mut derived_key = [0u8; 32];
+    scrypt::scrypt(password, &salt, &params, &mut derived_key)
+        .map_err(|err| KeystoreError::Invalid(err.to_string()))?;
+    Ok(derived_key)
+}
+
+fn calculate_mac(derived_key: &[u8; 32], ciphertext: &[u8]) -> [u8; 32] {
+    let mut hasher = Keccak256::new();
+    hasher.update(&derived_key[16..32]);
+    hasher.update(ciphertext);
+    let mut mac = [0u8; 32];
+    mac.copy_from_slice(&hasher.finalize());
+    mac
+}
+
+/// Format like `2019-05-24T09-35-41.151870000Z`
+fn utc_timestamp(time: SystemTime) -> String {
+    let duration = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = duration.as_secs();
+    let (days, secs_of_day) = ((secs / 86400) as i64, secs % 86400);
+    // The civil date of the days since 1970-01-01
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400;
+    let year = if month <= 2 { year + 1 } else { year };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}-{:02}-{:02}.{:09}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60,
+        duration.subsec_nanos()
+    )
+}
+
+/// Provide the passphrase of the lock arg to unlock on demand, e.g. by
+/// prompting the user
+pub type PassphraseProvider = Box<dyn Fn(&H160) -> Option<String> + Send + Sync>;
+
+/// Sign with the keys of a ckb-cli keystore
+pub struct KeystoreSigner {
+    key_files: HashMap<H160, KeyFile>,
+    unlock_timeout: Duration,
+    passphrase_provider: Option<PassphraseProvider>,
+    unlocked: Mutex<HashMap<H160, (secp256k1::SecretKey, Instant)>>,
+}
+
+impl KeystoreSigner {
+    pub fn new(key_files: HashMap<H160, KeyFile>) -> KeystoreSigner {
+        KeystoreSigner {
+            key_files,
+            unlock_timeout: DEFAULT_UNLOCK_TIMEOUT,
+            passphrase_provider: None,
+            unlocked: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Load the key files of a ckb-cli keystore directory, the lock arg of
+    /// each file is the suffix of its name
+    pub fn from_dir<P: AsRef<Path>>(dir: P) -> Result<KeystoreSigner, KeystoreError> {
+        let mut key_files = HashMap::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let lock_arg = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .filter(|name| name.starts_with("UTC--"))
+                .and_then(|name| name.rsplit("--").next())
+                .and_then(|suffix| hex::decode(suffix).ok())
+                .and_then(|bytes| H160::from_slice(&bytes).ok());
+            if let Some(lock_arg) = lock_arg {
+                key_files.insert(lock_arg, KeyFile::load(&path)?);
+            }
+        }
+        Ok(KeystoreSigner::new(key_files))
+    }
+
+    /// The time a key stays unlocked
+    pub fn unlock_timeout(mut self, timeout: Duration) -> Self {
+        self.unlock_timeout = timeout;
+        self
+    }
+
+    /// Unlock the locked keys on demand with the passphrase provider
+    pub fn passphrase_provider(mut self, provider: PassphraseProvider) -> Self {
+        self.passphrase_provider = Some(provider);
+        self
+    }
+
+    pub fn lock_args(&self) -> Vec<H160> {
+        self.key_files.keys().cloned().collect()
+    }
+
+    /// Unlock the key for the unlock timeout
+    pub fn unlock(&self, lock_arg: &H160, password: &[u8]) -> Result<(), KeystoreError> {
+        let mut key = self.unlock_key(lock_arg, password)?;
+        zeroize_privkey(&mut key);
+        Ok(())
+    }
+
+    fn unlock_key(
+        &self,
+        lock_arg: &H160,
+        password: &[u8],
+    ) -> Result<secp256k1::SecretKey, KeystoreError> {
+        let key_file = self
+            .key_files
+            .get(lock_arg)
+            .ok_or_else(|| KeystoreError::KeyNotFound(lock_arg.clone()))?;
+        let master_key = key_file.decrypt(password)?;
+        if &blake160(&master_key.public_key().serialize()) != lock_arg {
+            return Err(KeystoreError::Invalid(format!(
+                "key file of {:#x} holds another key",
+                lock_arg
+            )));
+        }
+        let mut unlocked = self.unlocked.lock();
+        if let Some((mut old_key, _)) = unlocked.insert(
+            lock_arg.clone(),
+            (master_key.private_key, Instant::now() + self.unlock_timeout),
+        ) {
+            zeroize_privkey(&mut old_key);
+        }
+        Ok(master_key.private_key)
+    }
+
+    /// Lock the key, return false if it is not unlocked
+    pub fn lock(&self, lock_arg: &H160) -> bool {
+        match self.unlocked.lock().remove(lock_arg) {
+            Some((mut key, _)) => {
+                zeroize_privkey(&mut key);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn lock_all(&self) {
+        for (_, (mut key, _)) in self.unlocked.lock().drain() {
+            zeroize_privkey(&mut key);
+        }
+    }
+
+    pub fn is_unlocked(&self, lock_arg: &H160) -> bool {
+        self.unlocked_key(lock_arg).is_some()
+    }
+
+    /// The unlocked key, the expired key is relocked
+    fn unlocked_key(&self, lock_arg: &H160) -> Option<secp256k1::SecretKey> {
+        let mut unlocked = self.unlocked.lock();
+        let (key, expires_at) = unlocked.get(lock_arg)?;
+        if Instant::now() < *expires_at {
+            return Some(*key);
+        }
+        if let Some((mut key, _)) = unlocked.remove(lock_arg) {
+            zeroize_privkey(&mut key);
+        }
+        None
+    }
+
+    fn secret_key(&self, lock_arg: &H160) -> Result<secp256k1::SecretKey, KeystoreError> {
+        if let Some(key) = self.unlocked_key(lock_arg) {
+            return Ok(key);
+        }
+        let passphrase = self
+            .passphrase_provider
+            .as_ref()
+            .and_then(|provider| provider(lock_arg))
+            .ok_or_else(|| KeystoreError::Locked(lock_arg.clone()))?;
+        self.unlock_key(lock_arg, passphrase.as_bytes())
+    }
+}
+
+impl Signer for KeystoreSigner {
+    fn match_id(&self, id: &[u8]) -> bool {
+        id.len() == 20 && self.key_files.contains_key(&H160::from_slice(id).unwrap())
+    }
+
+    fn sign(
+        &self,
+        id: &[u8],
+        message: &[u8],
+        recoverable: bool,
+        _tx: &TransactionView,
+    ) -> Result<Bytes, SignerError> {
+        if !self.match_id(id) {
+            return Err(SignerError::IdNotFound);
+        }
+        if message.len() != 32 {
+            return Err(SignerError::InvalidMessage(format!(
+                "expected length: 32, got: {}",
+                message.len()
+            )));
+        }
+        let mut key = self
+            .secret_key(&H160::from_slice(id).unwrap())
+            .map_err(|err| SignerError::Other(err.into()))?;
+        let msg =
+            secp256k1::Message::from_digest_slice(message).expect("Convert to message failed");
+        let signature = if recoverable {
+            let sig = SECP256K1.sign_ecdsa_recoverable(&msg, &key);
+            Bytes::from(serialize_signature(&sig).to_vec())
+        } else {
+            let sig = SECP256K1.sign_ecdsa(&msg, &key);
+            Bytes::from(sig.serialize_compact().to_vec())
+        };
+        zeroize_privkey(&mut key);
+        Ok(signature)
+    }
+}
+
+impl Drop for KeystoreSigner {
+    fn drop(&mut self) {
+        self.lock_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::core::TransactionBuilder;
+
+    // Light parameters to keep the tests fast
+    const TEST_PARAMS: ScryptParams = ScryptParams {
+        log_n: 10,
+        r: 8,
+        p: 1,
+    };
+
+    #[test]
+    fn test_key_file() {
+        let master_key = ExtendedPrivKey::new_master(&[3u8; 32]).unwrap();
+        let key_file = KeyFile::encrypt(&master_key, b"123456", TEST_PARAMS).unwrap();
+        assert_eq!(key_file.crypto.kdfparams.n, 1024);
+        assert_eq!(key_file.id.len(), 36);
+        assert_eq!(key_file.decrypt(b"123456").unwrap(), master_key);
+        assert!(matches!(
+            key_file.decrypt(b"654321"),
+            Err(KeystoreError::WrongPassword)
+        ));
+
+        let json = serde_json::to_string(&key_file).unwrap();
+        let mut value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["crypto"]["cipher"], "aes-128-ctr");
+        assert_eq!(value["crypto"]["kdf"], "scrypt");
+        // A truncated MAC never matches
+        let mut truncated = value.clone();
+        let mac = key_file.crypto.mac[..62].to_string();
+        truncated["crypto"]["mac"] = mac.into();
+        let truncated: KeyFile = serde_json::from_value(truncated).unwrap();
+        assert!(matches!(
+            truncated.decrypt(b"123456"),
+            Err(KeystoreError::WrongPassword)
+        ));
+        value["crypto"]["kdf"] = "pbkdf2".into();
+        let key_file: KeyFile = serde_json::from_value(value).unwrap();
+        assert!(matches!(
+            key_file.decrypt(b"123456"),
+            Err(KeystoreError::Unsupported(_))
+        ));
+
+        assert_eq!(
+            utc_timestamp(UNIX_EPOCH + Duration::new(1_558_690_541, 151_870_000)),
+            "2019-05-24T09-35-41.151870000Z"
+        );
+    }
+
+    #[test]
+    fn test_keystore_signer() {
+        let dir = std::env::temp_dir().join(format!("ckb-sdk-keystore-{}", std::process::id()));
+        let master_key = ExtendedPrivKey::new_master(&[4u8; 32]).unwrap();
+        let lock_arg = blake160(&master_key.public_key().serialize());
+        let path = KeyFile::encrypt(&master_key, b"123456", TEST_PARAMS)
+            .unwrap()
+            .save(&dir, &lock_arg)
+            .unwrap();
+        assert!(path
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .ends_with(&format!("--{:x}", lock_arg)));
+
+        let signer = KeystoreSigner::from_dir(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(signer.lock_args(), vec![lock_arg.clone()]);
+        assert!(signer.match_id(lock_arg.as_bytes()));
+        let tx = TransactionBuilder::default().build();
+        let message = [6u8; 32];
+        assert!(signer
+            .sign(lock_arg.as_bytes(), &message, true, &tx)
+            .is_err());
+
+        assert!(matches!(
+            signer.unlock(&lock_arg, b"000000"),
+            Err(KeystoreError::WrongPassword)
+        ));
+        signer.unlock(&lock_arg, b"123456").unwrap();
+        assert!(signer.is_unlocked(&lock_arg));
+        let signature = signer
+            .sign(lock_arg.as_bytes(), &message, true, &tx)
+            .unwrap();
+        let recoverable = secp256k1::ecdsa::RecoverableSignature::from_compact(
+            &signature[0..64],
+            secp256k1::ecdsa::RecoveryId::from_i32(signature[64] as i32).unwrap(),
+        )
+        .unwrap();
+        let msg = secp256k1::Message::from_digest_slice(&message).unwrap();
+        assert_eq!(
+            SECP256K1.recover_ecdsa(&msg, &recoverable).unwrap(),
+            master_key.public_key()
+        );
+        assert!(signer.lock(&lock_arg));
+        assert!(!signer.is_unlocked(&lock_arg));
+
+        // Relocked right after unlocked on demand
+        let signer = signer
+            .unlock_timeout(Duration::from_secs(0))
+            .passphrase_provider(Box::new(|_| Some("123456".to_string())));
+        assert!(signer
+            .sign(lock_arg.as_bytes(), &message, false, &tx)
+            .is_ok());
+        assert!(!signer.is_unlocked(&lock_arg));
+    }
+}
@@ -0,0 +1,474 @@
+//! A [`Signer`] backed by the CKB app of a Ledger hardware wallet.
+//!
+//! The app talks APDU over the Ledger HID framing:
+//!
+//!   * [`LedgerSigner::new`] reads the public key of the derivation path with
+//!     `INS_GET_PUBLIC_KEY`, the id of the signer is its blake160 hash.
+//!   * [`Signer::sign`] sends the derivation path in the first `INS_SIGN`
+//!     chunk, then the molecule encoded transaction (for the device to
+//!     display) and the signing message in the following chunks, the last
+//!     chunk is marked with [`P1_LAST_MARKER`]. The device returns a
+//!     recoverable signature after the user confirms.
+//!
+//! The transport is abstracted by [`LedgerTransport`], [`HidLedgerTransport`]
+//! connects to the first Ledger device found via hidapi.
+use std::convert::TryFrom;
+use std::time::Duration;
+
+use hidapi::{HidApi, HidDevice};
+use parking_lot::Mutex;
+use thiserror::Error;
+
+use ckb_types::{bytes::Bytes, core::TransactionView, prelude::*, H160};
+
+use super::{Signer, SignerError};
+use crate::types::bip32::{account_path, parse_derivation_path, Bip32Error, EXTERNAL_CHAIN};
+use crate::util::blake160;
+
+/// The USB vendor id of Ledger devices
+pub const LEDGER_VENDOR_ID: u16 = 0x2c97;
+/// The HID usage page of the Ledger APDU interface
+pub const LEDGER_USAGE_PAGE: u16 = 0xffa0;
+
+pub const CLA: u8 = 0x80;
+pub const INS_GET_APP_VERSION: u8 = 0x00;
+pub const INS_GET_PUBLIC_KEY: u8 = 0x02;
+pub const INS_SIGN: u8 = 0x03;
+pub const P1_FIRST: u8 = 0x00;
+pub const P1_NEXT: u8 = 0x01;
+pub const P1_LAST_MARKER: u8 = 0x80;
+/// The max data size of an APDU
+pub const MAX_APDU_DATA_SIZE: usize = 230;
+/// The status word of success
+pub const SW_OK: u16 = 0x9000;
+/// The status word when the user rejects on the device
+pub const SW_USER_REJECTED: u16 = 0x6985;
+
+const HID_PACKET_SIZE: usize = 64;
+const HID_CHANNEL: u16 = 0x0101;
+const HID_TAG_APDU: u8 = 0x05;
+const HID_READ_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Error, Debug)]
+pub enum LedgerError {
+    #[error("hid error: `{0}`")]
+    Hid(#[from] hidapi::HidError),
+
+    #[error("ledger device not found")]
+    DeviceNotFound,
+
+    #[error("rejected by the user on the device")]
+    UserRejected,
+
+    #[error("ledger app returned status: `{0:#06x}`")]
+    Status(u16),
+
+    #[error("invalid response: `{0}`")]
+    InvalidResponse(String),
+
+    #[error(transparent)]
+    Bip32(#[from] Bip32Error),
+}
+
+/// An APDU command
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct LedgerApdu {
+    pub cla: u8,
+    pub ins: u8,
+    pub p1: u8,
+    pub p2: u8,
+    pub data: Vec<u8>,
+}
+
+impl LedgerApdu {
+    pub fn new(ins: u8, p1: u8, data: Vec<u8>) -> LedgerApdu {
+        LedgerApdu {
+            cla: CLA,
+            ins,
+            p1,
+            p2: 0,
+            data,
+        }
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = vec![self.cla, self.ins, self.p1, self.p2, self.data.len() as u8];
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+}
+
+/// Exchange the APDUs with the device
+pub trait LedgerTransport: Send {
+    /// Send the APDU and return the response data without the status word,
+    /// an error if the status word is not [`SW_OK`].
+    fn exchange(&mut self, apdu: &LedgerApdu) -> Result<Vec<u8>, LedgerError>;
+}
+
+/// The transport over the Ledger HID framing
+pub struct HidLedgerTransport {
+    device: HidDevice,
+}
+
+impl HidLedgerTransport {
+    /// Connect to the first Ledger device found
+    pub fn open() -> Result<HidLedgerTransport, LedgerError> {
+        let api = HidApi::new()?;
+        let info = api
+            .device_list()
+            .find(|info| {
+                info.vendor_id() == LEDGER_VENDOR_ID
+                    && (info.usage_page() == LEDGER_USAGE_PAGE || info.interface_number() == 0)
+            })
+            .ok_or(LedgerError::DeviceNotFound)?;
+        let device = info.open_device(&api)?;
+        Ok(HidLedgerTransport { device })
+    }
+
+    fn write_apdu(&self, apdu: &[u8]) -> Result<(), LedgerError> {
+        for (sequence, packet) in hid_packets(apdu).into_iter().enumerate() {
+            // The first byte is the report id
+            let mut report = vec![0u8];
+            report.extend_from_slice(&packet);
+            let written = self.device.write(&report)?;
+            if written < report.len() {
+                return Err(LedgerError::InvalidResponse(format!(
+                    "packet {} partially written",
+                    sequence
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn read_response(&self) -> Result<Vec<u8>, LedgerError> {
+        let mut response = Vec::new();
+        let mut expected_len = None;
+        let mut sequence: u16 = 0;
+        loop {
+            let mut packet = [0u8; HID_PACKET_SIZE];
+            let read = self
+                .device
+                .read_timeout(&mut packet, HID_READ_TIMEOUT.as_millis() as i32)?;
+            if read < 5
+                || u16::from_be_bytes([packet[0], packet[1]]) != HID_CHANNEL
+                || packet[2] != HID_TAG_APDU
+                || u16::from_be_bytes([packet[3], packet[4]]) != sequence
+            {
+                return Err(LedgerError::InvalidResponse(format!(
+                    "invalid hid packet, sequence: {}",
+                    sequence
+                )));
+            }
+            let payload = if sequence == 0 {
+                expected_len = Some(u16::from_be_bytes([packet[5], packet[6]]) as usize);
+                &packet[7..read]
+            } else {
+                &packet[5..read]
+            };
+            response.extend_from_slice(payload);
+            let expected_len = expected_len.unwrap_or_default();
+            if response.len() >= expected_len {
+                response.truncate(expected_len);
+                return Ok(response);
+            }
+            sequence += 1;
+        }
+    }
+}
+
+impl LedgerTransport for HidLedgerTransport {
+    fn exchange(&mut self, apdu: &LedgerApdu) -> Result<Vec<u8>, LedgerError> {
+        self.write_apdu(&apdu.serialize())?;
+        parse_status(self.read_response()?)
+    }
+}
+
+/// Split the APDU into HID packets (without the report id)
+fn hid_packets(apdu: &[u8]) -> Vec<Vec<u8>> {
+    let mut data = (apdu.len() as u16).to_be_bytes().to_vec();
+    data.extend_from_slice(apdu);
+    data.chunks(HID_PACKET_SIZE - 5)
+        .enumerate()
+        .map(|(sequence, chunk)| {
+            let mut packet = Vec::with_capacity(HID_PACKET_SIZE);
+            packet.extend_from_slice(&HID_CHANNEL.to_be_bytes());
+            packet.push(HID_TAG_APDU);
+            packet.extend_from_slice(&(sequence as u16).to_be_bytes());
+            packet.extend_from_slice(chunk);
+            packet.resize(HID_PACKET_SIZE, 0);
+            packet
+        })
+        .collect()
+}
+
+/// Split the status word from the response
+fn parse_status(mut response: Vec<u8>) -> Result<Vec<u8>, LedgerError> {
+    if response.len() < 2 {
+        return Err(LedgerError::InvalidResponse(
+            "response without status word".to_string(),
+        ));
+    }
+    let status_word = response.split_off(response.len() - 2);
+    match u16::from_be_bytes([status_word[0], status_word[1]]) {
+        SW_OK => Ok(response),
+        SW_USER_REJECTED => Err(LedgerError::UserRejected),
+        status => Err(LedgerError::Status(status)),
+    }
+}
+
+fn serialize_path(path: &[u32]) -> Vec<u8> {
+    let mut data = vec![path.len() as u8];
+    for index in path {
+        data.extend_from_slice(&index.to_be_bytes());
+    }
+    data
+}
+
+/// A signer of one derivation path of the Ledger CKB app
+pub struct LedgerSigner {
+    transport: Mutex<Box<dyn LedgerTransport>>,
+    path: Vec<u32>,
+    pubkey: secp256k1::PublicKey,
+}
+
+impl LedgerSigner {
+    /// Use the derivation path on the device connected by `transport`
+    pub fn new(
+        mut transport: Box<dyn LedgerTransport>,
+        path: Vec<u32>,
+    ) -> Result<LedgerSigner, LedgerError> {
+        let response = transport.exchange(&LedgerApdu::new(
+            INS_GET_PUBLIC_KEY,
+            P1_FIRST,
+            serialize_path(&path),
+        ))?;
+        // pubkey length (1 byte) + pubkey
+        let pubkey_len = *response
+            .first()
+            .ok_or_else(|| LedgerError::InvalidResponse("empty public key".to_string()))?
+            as usize;
+        let pubkey = response
+            .get(1..1 + pubkey_len)
+            .and_then(|data| secp256k1::PublicKey::from_slice(data).ok())
+            .ok_or_else(|| LedgerError::InvalidResponse("invalid public key".to_string()))?;
+        Ok(LedgerSigner {
+            transport: Mutex::new(transport),
+            path,
+            pubkey,
+        })
+    }
+
+    /// Connect to the first Ledger device and use the derivation path like
+    /// `m/44'/309'/0'/0/0`
+    pub fn open(path: &str) -> Result<LedgerSigner, LedgerError> {
+        LedgerSigner::new(
+            Box::new(HidLedgerTransport::open()?),
+            parse_derivation_path(path)?,
+        )
+    }
+
+    /// Connect to the first Ledger device and use the receiving address
+    /// `m/44'/309'/{account}'/0/{index}`
+    pub fn open_account(account: u32, index: u32) -> Result<LedgerSigner, LedgerError> {
+        let mut path = account_path(account);
+        path.extend_from_slice(&[EXTERNAL_CHAIN, index]);
+        LedgerSigner::new(Box::new(HidLedgerTransport::open()?), path)
+    }
+
+    pub fn path(&self) -> &[u32] {
+        &self.path
+    }
+
+    pub fn pubkey(&self) -> &secp256k1::PublicKey {
+        &self.pubkey
+    }
+
+    /// The lock args of the sighash address
+    pub fn lock_arg(&self) -> H160 {
+        blake160(&self.pubkey.serialize())
+    }
+
+    /// The APDUs to sign the message, the payload is split into chunks of
+    /// [`MAX_APDU_DATA_SIZE`] bytes.
+    fn sign_apdus(&self, message: &[u8], tx: &TransactionView) -> Vec<LedgerApdu> {
+        let mut payload = tx.data().as_slice().to_vec();
+        payload.extend_from_slice(message);
+        let mut apdus = vec![LedgerApdu::new(
+            INS_SIGN,
+            P1_FIRST,
+            serialize_path(&self.path),
+        )];
+        let chunks: Vec<_> = payload.chunks(MAX_APDU_DATA_SIZE).collect();
+        for (idx, chunk) in chunks.iter().enumerate() {
+            let p1 = if idx + 1 == chunks.len() {
+                P1_NEXT | P1_LAST_MARKER
+            } else {
+                P1_NEXT
+            };
+            apdus.push(LedgerApdu::new(INS_SIGN, p1, chunk.to_vec()));
+        }
+        apdus
+    }
+}
+
+impl Signer for LedgerSigner {
+    fn match_id(&self, id: &[u8]) -> bool {
+        id.len() == 20 && id == self.lock_arg().as_bytes()
+    }
+
+    fn sign(
+        &self,
+        id: &[u8],
+        message: &[u8],
+        recoverable: bool,
+        tx: &TransactionView,
+    ) -> Result<Bytes, SignerError> {
+        if !self.match_id(id) {
+            return Err(SignerError::IdNotFound);
+        }
+        if message.len() != 32 {
+            return Err(SignerError::InvalidMessage(format!(
+                "expected length: 32, got: {}",
+                message.len()
+            )));
+        }
+        let mut transport = self.transport.lock();
+        let mut response = Vec::new();
+        for apdu in self.sign_apdus(message, tx) {
+            response = transport
+                .exchange(&apdu)
+                .map_err(|err| SignerError::Other(err.into()))?;
+        }
+        let signature = <[u8; 65]>::try_from(response.as_slice()).map_err(|_| {
+            SignerError::Other(anyhow::anyhow!(
+                "invalid signature length from ledger: {}",
+                response.len()
+            ))
+        })?;
+        if recoverable {
+            Ok(Bytes::from(signature.to_vec()))
+        } else {
+            Ok(Bytes::from(signature[0..64].to_vec()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use ckb_types::core::TransactionBuilder;
+
+    use crate::constants::ONE_CKB;
+    use crate::SECP256K1;
+
+    #[derive(Clone)]
+    struct MockTransport {
+        key: secp256k1::SecretKey,
+        apdus: Arc<Mutex<Vec<LedgerApdu>>>,
+    }
+
+    impl LedgerTransport for MockTransport {
+        fn exchange(&mut self, apdu: &LedgerApdu) -> Result<Vec<u8>, LedgerError> {
+            self.apdus.lock().push(apdu.clone());
+            let pubkey = secp256k1::PublicKey::from_secret_key(&SECP256K1, &self.key);
+            let response = match (apdu.ins, apdu.p1) {
+                (INS_GET_PUBLIC_KEY, _) => {
+                    let pubkey = pubkey.serialize_uncompressed();
+                    let mut response = vec![pubkey.len() as u8];
+                    response.extend_from_slice(&pubkey);
+                    response
+                }
+                (INS_SIGN, p1) if p1 & P1_LAST_MARKER != 0 => {
+                    let message = &apdu.data[apdu.data.len() - 32..];
+                    let message = secp256k1::Message::from_digest_slice(message).unwrap();
+                    let signature = SECP256K1.sign_ecdsa_recoverable(&message, &self.key);
+                    crate::util::serialize_signature(&signature).to_vec()
+                }
+                (INS_SIGN, _) => Vec::new(),
+                _ => return Err(LedgerError::Status(0x6d00)),
+            };
+            parse_status([response, SW_OK.to_be_bytes().to_vec()].concat())
+        }
+    }
+
+    #[test]
+    fn test_ledger_signer() {
+        let key = secp256k1::SecretKey::from_slice(&[7u8; 32]).unwrap();
+        let transport = MockTransport {
+            key,
+            apdus: Arc::new(Mutex::new(Vec::new())),
+        };
+        let path = parse_derivation_path("m/44'/309'/0'/0/0").unwrap();
+        let signer = LedgerSigner::new(Box::new(transport.clone()), path.clone()).unwrap();
+        let pubkey = secp256k1::PublicKey::from_secret_key(&SECP256K1, &key);
+        let lock_arg = blake160(&pubkey.serialize());
+        assert!(signer.match_id(lock_arg.as_bytes()));
+        assert_eq!(signer.lock_arg(), lock_arg);
+
+        // A transaction larger than one APDU
+        let tx = TransactionBuilder::default()
+            .outputs((0..10).map(|_| {
+                ckb_types::packed::CellOutput::new_builder()
+                    .capacity((100 * ONE_CKB).pack())
+                    .build()
+            }))
+            .outputs_data((0..10).map(|_| Bytes::default().pack()))
+            .build();
+        let message = [3u8; 32];
+        let signature = signer
+            .sign(lock_arg.as_bytes(), &message, true, &tx)
+            .unwrap();
+        assert_eq!(signature.len(), 65);
+        let recoverable = secp256k1::ecdsa::RecoverableSignature::from_compact(
+            &signature[0..64],
+            secp256k1::ecdsa::RecoveryId::from_i32(signature[64] as i32).unwrap(),
+        )
+        .unwrap();
+        let message = secp256k1::Message::from_digest_slice(&message).unwrap();
+        assert_eq!(
+            SECP256K1.recover_ecdsa(&message, &recoverable).unwrap(),
+            pubkey
+        );
+
+        let apdus = transport.apdus.lock();
+        let sign_apdus: Vec<_> = apdus.iter().filter(|apdu| apdu.ins == INS_SIGN).collect();
+        assert!(sign_apdus.len() > 2);
+        assert_eq!(sign_apdus[0].data, serialize_path(&path));
+        assert!(sign_apdus
+            .iter()
+            .all(|apdu| apdu.data.len() <= MAX_APDU_DATA_SIZE));
+        assert_eq!(
+            sign_apdus
+                .iter()
+                .filter(|apdu| apdu.p1 & P1_LAST_MARKER != 0)
+                .count(),
+            1
+        );
+        drop(apdus);
+
+        assert!(matches!(
+            signer.sign(&[0u8; 20], &[3u8; 32], true, &tx),
+            Err(SignerError::IdNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_hid_packets() {
+        let apdu = LedgerApdu::new(INS_SIGN, P1_FIRST, vec![1u8; 100]).serialize();
+        let packets = hid_packets(&apdu);
+        assert_eq!(packets.len(), 2);
+        assert!(packets.iter().all(|packet| packet.len() == HID_PACKET_SIZE));
+        assert_eq!(&packets[0][0..5], &[0x01, 0x01, 0x05, 0x00, 0x00]);
+        assert_eq!(u16::from_be_bytes([packets[0][5], packets[0][6]]), 105);
+        assert_eq!(&packets[1][3..5], &[0x00, 0x01]);
+
+        assert!(matches!(
+            parse_status(vec![0x69, 0x85]),
+            Err(LedgerError::UserRejected)
+        ));
+        assert_eq!(parse_status(vec![1, 0x90, 0x00]).unwrap(), vec![1]);
+    }
+}
@@ -1,15 +1,41 @@
 //! The traits defined here is intent to describe the requirements of current
 //!  library code and only implemented the trait in upper level code.
 
+pub mod aggregate_signer;
+pub mod async_impls;
+pub mod block_scan_impls;
 pub mod default_impls;
 pub mod dummy_impls;
+pub mod hd_keystore;
+#[cfg(feature = "keystore")]
+pub mod keystore_impls;
+#[cfg(feature = "ledger")]
+pub mod ledger_impls;
 pub mod light_client_impls;
 pub mod offchain_impls;
-
+pub mod remote_impls;
+pub mod snapshot_impls;
+pub mod tip_tracker;
+#[cfg(feature = "trezor")]
+pub mod trezor_impls;
+
+pub use aggregate_signer::{
+    AggregateSession, AggregateSigner, AggregateSignerAdapter, PartialShare,
+};
+pub use async_impls::{
+    DefaultAsyncCellCollector, DefaultAsyncHeaderDepResolver,
+    DefaultAsyncTransactionDependencyProvider,
+};
+pub use block_scan_impls::{BlockScanCellCollector, ScanEvent, ScanEventStream, ScanProgress};
 pub use default_impls::{
-    DefaultCellCollector, DefaultCellDepResolver, DefaultHeaderDepResolver,
+    DefaultCellCollector, DefaultCellDepResolver, DefaultFeeRateProvider, DefaultHeaderDepResolver,
     DefaultTransactionDependencyProvider, SecpCkbRawKeySigner,
 };
+pub use hd_keystore::HdKeystoreSigner;
+#[cfg(feature = "keystore")]
+pub use keystore_impls::{KeyFile, KeystoreSigner};
+#[cfg(feature = "ledger")]
+pub use ledger_impls::{HidLedgerTransport, LedgerSigner, LedgerTransport};
 pub use light_client_impls::{
     LightClientCellCollector, LightClientHeaderDepResolver,
     LightClientTransactionDependencyProvider,
@@ -18,8 +44,17 @@ pub use offchain_impls::{
     OffchainCellCollector, OffchainCellDepResolver, OffchainHeaderDepResolver,
     OffchainTransactionDependencyProvider,
 };
+pub use remote_impls::RemoteSigner;
+pub use snapshot_impls::{Snapshot, SnapshotRecorder, SnapshotReplayer};
+pub use tip_tracker::{TipInfo, TipTracker};
+#[cfg(feature = "trezor")]
+pub use trezor_impls::{HidTrezorTransport, TrezorSigner, TrezorTransport};
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
 
 use dyn_clone::DynClone;
+use futures::future::BoxFuture;
 use thiserror::Error;
 
 use ckb_hash::blake2b_256;
@@ -29,13 +64,13 @@ use ckb_types::{
     core::{
         cell::{CellMetaBuilder, CellProvider, CellStatus, HeaderChecker},
         error::OutPointError,
-        HeaderView, TransactionView,
+        FeeRate, HeaderView, TransactionView,
     },
     packed::{Byte32, CellDep, CellOutput, OutPoint, Script, Transaction},
     prelude::*,
 };
 
-use crate::{rpc::ckb_indexer::SearchMode, util::is_mature};
+use crate::{rpc::ckb_indexer::SearchMode, types::BlockExtension, util::is_mature};
 
 /// Signer errors
 #[derive(Error, Debug)]
@@ -108,6 +143,17 @@ pub trait TransactionDependencyProvider: Sync + Send {
         &self,
         block_hash: &Byte32,
     ) -> Result<Option<ckb_types::packed::Bytes>, TransactionDependencyError>;
+
+    /// Get the block extension and parse the known contents
+    fn get_parsed_block_extension(
+        &self,
+        block_hash: &Byte32,
+    ) -> Result<Option<BlockExtension>, TransactionDependencyError> {
+        self.get_block_extension(block_hash)?
+            .map(BlockExtension::try_from)
+            .transpose()
+            .map_err(|err| TransactionDependencyError::Other(anyhow::anyhow!(err)))
+    }
 }
 
 // Implement CellDataProvider trait is currently for `DaoCalculator`
@@ -393,6 +439,19 @@ pub trait CellCollector: DynClone {
         apply_changes: bool,
     ) -> Result<(Vec<LiveCell>, u64), CellCollectorError>;
 
+    /// The upper bound of the total capacity of the live cells matching the
+    /// query, `None` if it can not be told without collecting the cells.
+    ///
+    /// It's used to fail fast before paginating the cells (e.g. the balancer
+    /// checks if a capacity provider can cover the shortfall), so the
+    /// implementation must be cheap and must not under count.
+    fn max_total_capacity(
+        &mut self,
+        _query: &CellQueryOptions,
+    ) -> Result<Option<u64>, CellCollectorError> {
+        Ok(None)
+    }
+
     /// Mark this cell as dead cell
     fn lock_cell(
         &mut self,
@@ -424,6 +483,152 @@ pub trait HeaderDepResolver {
     fn resolve_by_number(&self, number: u64) -> Result<Option<HeaderView>, anyhow::Error>;
 }
 
+/// The confirmation priority of a transaction
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum FeePriority {
+    Low,
+    Medium,
+    High,
+}
+
+/// Provide the fee rate (shannons/KB) for the transaction builders.
+///
+/// The [`CapacityBalancer`](crate::tx_builder::CapacityBalancer) queries the
+/// provider before every build, so the fee follows the network condition.
+pub trait FeeRateProvider: Send + Sync + std::fmt::Debug {
+    fn fee_rate(&self) -> Result<FeeRate, anyhow::Error>;
+}
+
+/// A fixed fee rate
+impl FeeRateProvider for FeeRate {
+    fn fee_rate(&self) -> Result<FeeRate, anyhow::Error> {
+        Ok(*self)
+    }
+}
+
+/// Estimate the size of the final (unlocked) witness of a lock script group,
+/// used in the fee calculation when the placeholder witness is smaller than
+/// the final one (e.g. a multisig placeholder with less signatures).
+pub trait WitnessSizeEstimator: Send + Sync + std::fmt::Debug {
+    /// The serialized size of the first witness of the lock script group
+    /// after unlocking (the raw bytes, without the length header), `None` if
+    /// unknown.
+    fn estimated_witness_size(&self, lock_script: &Script) -> Option<usize>;
+}
+
+/// A fixed witness size
+impl WitnessSizeEstimator for usize {
+    fn estimated_witness_size(&self, _lock_script: &Script) -> Option<usize> {
+        Some(*self)
+    }
+}
+
+/// Provide the since value of the inputs added by the balancer, for the lock
+/// scripts which do not keep the since value in the args at a fixed offset,
+/// see [`SinceSource`](crate::tx_builder::SinceSource).
+pub trait SinceProvider: Send + Sync + std::fmt::Debug {
+    /// The since value of the input cell
+    fn since_value(&self, cell: &LiveCell) -> Result<u64, anyhow::Error>;
+}
+
+/// The since values supplied per input, the inputs not in the map use
+/// `default_since`.
+#[derive(Debug, Clone, Default)]
+pub struct InputSinceMap {
+    pub default_since: u64,
+    pub values: HashMap<OutPoint, u64>,
+}
+
+impl SinceProvider for InputSinceMap {
+    fn since_value(&self, cell: &LiveCell) -> Result<u64, anyhow::Error> {
+        Ok(self
+            .values
+            .get(&cell.out_point)
+            .copied()
+            .unwrap_or(self.default_since))
+    }
+}
+
+/// Async version of [`TransactionDependencyProvider`]
+pub trait AsyncTransactionDependencyProvider: Sync + Send {
+    /// For verify certain cell belong to certain transaction
+    fn get_transaction<'a>(
+        &'a self,
+        tx_hash: &'a Byte32,
+    ) -> BoxFuture<'a, Result<TransactionView, TransactionDependencyError>>;
+    /// For get the output information of inputs or cell_deps, those cell should be live cell
+    fn get_cell<'a>(
+        &'a self,
+        out_point: &'a OutPoint,
+    ) -> BoxFuture<'a, Result<CellOutput, TransactionDependencyError>>;
+    /// For get the output data information of inputs or cell_deps
+    fn get_cell_data<'a>(
+        &'a self,
+        out_point: &'a OutPoint,
+    ) -> BoxFuture<'a, Result<Bytes, TransactionDependencyError>>;
+    /// For get the header information of header_deps
+    fn get_header<'a>(
+        &'a self,
+        block_hash: &'a Byte32,
+    ) -> BoxFuture<'a, Result<HeaderView, TransactionDependencyError>>;
+
+    /// For get_block_extension
+    fn get_block_extension<'a>(
+        &'a self,
+        block_hash: &'a Byte32,
+    ) -> BoxFuture<'a, Result<Option<ckb_types::packed::Bytes>, TransactionDependencyError>>;
+}
+
+/// Async version of [`CellCollector`]
+pub trait AsyncCellCollector: Send {
+    /// Collect live cells by query options, if `apply_changes` is true will
+    /// mark all collected cells as dead cells.
+    fn collect_live_cells<'a>(
+        &'a mut self,
+        query: &'a CellQueryOptions,
+        apply_changes: bool,
+    ) -> BoxFuture<'a, Result<(Vec<LiveCell>, u64), CellCollectorError>>;
+
+    /// Async version of [`CellCollector::max_total_capacity`]
+    fn max_total_capacity<'a>(
+        &'a mut self,
+        _query: &'a CellQueryOptions,
+    ) -> BoxFuture<'a, Result<Option<u64>, CellCollectorError>> {
+        Box::pin(async { Ok(None) })
+    }
+
+    /// Mark this cell as dead cell
+    fn lock_cell(
+        &mut self,
+        out_point: OutPoint,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError>;
+    /// Mark all inputs as dead cells and outputs as live cells in the transaction.
+    fn apply_tx(
+        &mut self,
+        tx: Transaction,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError>;
+
+    /// Clear cache and locked cells
+    fn reset(&mut self);
+}
+
+/// Async version of [`HeaderDepResolver`]
+pub trait AsyncHeaderDepResolver: Sync + Send {
+    /// Resolve header dep by trancation hash
+    fn resolve_by_tx<'a>(
+        &'a self,
+        tx_hash: &'a Byte32,
+    ) -> BoxFuture<'a, Result<Option<HeaderView>, anyhow::Error>>;
+
+    /// Resolve header dep by block number
+    fn resolve_by_number(
+        &self,
+        number: u64,
+    ) -> BoxFuture<'_, Result<Option<HeaderView>, anyhow::Error>>;
+}
+
 // test cases make sure new added exception won't breadk `anyhow!(e_variable)` usage,
 #[cfg(test)]
 mod anyhow_tests {
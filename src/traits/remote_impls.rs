@@ -0,0 +1,235 @@
+//! A [`Signer`] delegating to a remote signing service over JSON-RPC/HTTP.
+//!
+//! The keys stay on the signing service, the box building the transactions
+//! only knows the ids (e.g. the lock args) of the keys. Each signing request
+//! calls the `sign_message` method with one object parameter:
+//!
+//! ```json
+//! {
+//!   "id": "0x<lock arg>",
+//!   "message": "0x<message digest of the script group>",
+//!   "recoverable": true,
+//!   "tx_hash": "0x<transaction hash>",
+//!   "tx": { <the transaction, if RemoteSigner::include_tx is set> }
+//! }
+//! ```
+//!
+//! and expects the signature in hex as the result. The service can rebuild
+//! the script groups from the transaction to check what it signs.
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use serde::{Deserialize, Serialize};
+
+use ckb_jsonrpc_types::{JsonBytes, Transaction};
+use ckb_types::{bytes::Bytes, core::TransactionView, prelude::*, H256};
+
+use super::{Signer, SignerError};
+use crate::rpc::{decode_response, ParseMode, RpcError};
+
+/// The default timeout of a signing request, the service may wait for a
+/// manual approval
+pub const DEFAULT_REMOTE_SIGNER_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The parameter of the `sign_message` method
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct SignMessageRequest {
+    pub id: JsonBytes,
+    pub message: JsonBytes,
+    pub recoverable: bool,
+    pub tx_hash: H256,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx: Option<Transaction>,
+}
+
+/// Sign by a remote signing service
+pub struct RemoteSigner {
+    client: reqwest::blocking::Client,
+    url: reqwest::Url,
+    request_id: AtomicU64,
+    ids: HashSet<Bytes>,
+    headers: HeaderMap,
+    timeout: Duration,
+    include_tx: bool,
+}
+
+impl RemoteSigner {
+    /// The signing service at `url` holding the keys of `ids`
+    pub fn new(url: &str, ids: Vec<Bytes>) -> Result<RemoteSigner, RpcError> {
+        let url = reqwest::Url::parse(url)
+            .map_err(|err| anyhow!("invalid remote signer url: {}", err))?;
+        Ok(RemoteSigner {
+            client: reqwest::blocking::Client::new(),
+            url,
+            request_id: AtomicU64::new(0),
+            ids: ids.into_iter().collect(),
+            headers: HeaderMap::new(),
+            timeout: DEFAULT_REMOTE_SIGNER_TIMEOUT,
+            include_tx: false,
+        })
+    }
+
+    /// Add a header (e.g. an API key) to every request
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Authenticate every request with the bearer token
+    pub fn bearer_auth(self, token: &str) -> Result<Self, RpcError> {
+        let mut value = HeaderValue::from_str(&format!("Bearer {}", token))
+            .map_err(|err| anyhow!("invalid bearer token: {}", err))?;
+        value.set_sensitive(true);
+        Ok(self.header(AUTHORIZATION, value))
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Send the whole transaction with the digest, for the service to check
+    /// the transaction before signing
+    pub fn include_tx(mut self, include_tx: bool) -> Self {
+        self.include_tx = include_tx;
+        self
+    }
+
+    /// Replace the ids by the `list_ids` method of the service
+    pub fn refresh_ids(&mut self) -> Result<(), RpcError> {
+        let ids: Vec<JsonBytes> = self.post("list_ids", ())?;
+        self.ids = ids.into_iter().map(|id| id.into_bytes()).collect();
+        Ok(())
+    }
+
+    pub fn ids(&self) -> Vec<Bytes> {
+        self.ids.iter().cloned().collect()
+    }
+
+    fn post<PARAM, RET>(&self, method: &str, params: PARAM) -> Result<RET, RpcError>
+    where
+        PARAM: serde::ser::Serialize,
+        RET: serde::de::DeserializeOwned,
+    {
+        let req_json = serde_json::json!({
+            "id": self.request_id.fetch_add(1, Ordering::Relaxed),
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": serde_json::to_value(params)?,
+        });
+        let resp = self
+            .client
+            .post(self.url.clone())
+            .headers(self.headers.clone())
+            .timeout(self.timeout)
+            .json(&req_json)
+            .send()?
+            .error_for_status()?;
+        match resp.json::<jsonrpc_core::response::Output>()? {
+            jsonrpc_core::response::Output::Success(success) => {
+                decode_response(success.result, ParseMode::Tolerant)
+            }
+            jsonrpc_core::response::Output::Failure(failure) => Err(failure.error.into()),
+        }
+    }
+}
+
+impl Signer for RemoteSigner {
+    fn match_id(&self, id: &[u8]) -> bool {
+        self.ids.contains(id)
+    }
+
+    fn sign(
+        &self,
+        id: &[u8],
+        message: &[u8],
+        recoverable: bool,
+        tx: &TransactionView,
+    ) -> Result<Bytes, SignerError> {
+        if !self.match_id(id) {
+            return Err(SignerError::IdNotFound);
+        }
+        let request = SignMessageRequest {
+            id: JsonBytes::from_vec(id.to_vec()),
+            message: JsonBytes::from_vec(message.to_vec()),
+            recoverable,
+            tx_hash: tx.hash().unpack(),
+            tx: self.include_tx.then(|| tx.data().into()),
+        };
+        let signature: JsonBytes = self
+            .post("sign_message", (request,))
+            .map_err(|err| SignerError::Other(err.into()))?;
+        let signature = signature.into_bytes();
+        let expected_len = if recoverable { 65 } else { 64 };
+        if message.len() == 32 && signature.len() != expected_len {
+            return Err(SignerError::Other(anyhow!(
+                "invalid signature length from remote signer, expected: {}, got: {}",
+                expected_len,
+                signature.len()
+            )));
+        }
+        Ok(signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::MockRpcResult;
+    use ckb_types::core::TransactionBuilder;
+    use httpmock::prelude::*;
+
+    #[test]
+    fn test_remote_signer() {
+        let server = MockServer::start();
+        let tx = TransactionBuilder::default().build();
+        let tx_hash: H256 = tx.hash().unpack();
+        let id = Bytes::from(vec![0x11; 20]);
+        let signature = vec![7u8; 65];
+        let sign_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/")
+                .header("authorization", "Bearer secret")
+                .header("x-client", "builder-1")
+                .body_contains("sign_message")
+                .body_contains(format!("{:#x}", tx_hash))
+                .body_contains(format!("0x{}", "22".repeat(32)));
+            then.status(200)
+                .body(MockRpcResult::new(JsonBytes::from_vec(signature.clone())).to_json());
+        });
+        let unauthorized_mock = server.mock(|when, then| {
+            when.method(POST).path("/").header_missing("authorization");
+            then.status(401);
+        });
+
+        let signer = RemoteSigner::new(server.base_url().as_str(), vec![id.clone()])
+            .unwrap()
+            .bearer_auth("secret")
+            .unwrap()
+            .header(
+                HeaderName::from_static("x-client"),
+                HeaderValue::from_static("builder-1"),
+            )
+            .timeout(Duration::from_secs(5));
+        assert!(signer.match_id(&id));
+        assert!(!signer.match_id(&[0x12; 20]));
+        assert_eq!(
+            signer.sign(&id, &[0x22; 32], true, &tx).unwrap(),
+            Bytes::from(signature)
+        );
+        sign_mock.assert_hits(1);
+        // 65 bytes signature is invalid for a non-recoverable request
+        assert!(signer.sign(&id, &[0x22; 32], false, &tx).is_err());
+        assert!(matches!(
+            signer.sign(&[0x12; 20], &[0x22; 32], true, &tx),
+            Err(SignerError::IdNotFound)
+        ));
+
+        let signer = RemoteSigner::new(server.base_url().as_str(), vec![id.clone()]).unwrap();
+        assert!(signer.sign(&id, &[0x22; 32], true, &tx).is_err());
+        unauthorized_mock.assert_hits(1);
+    }
+}
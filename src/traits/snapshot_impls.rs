@@ -0,0 +1,550 @@
+//! Capture the chain state a transaction build touched into a snapshot, and
+//! replay the build against the snapshot deterministically.
+//!
+//! Wrap the providers of a failing build with [`SnapshotRecorder`], save the
+//! recorded [`Snapshot`] to a file and attach it to the bug report. The
+//! [`SnapshotReplayer`] implements all the provider traits from the snapshot
+//! only, so the build can be reproduced without a ckb node.
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use ckb_jsonrpc_types as json_types;
+use ckb_types::{
+    bytes::Bytes,
+    core::{HeaderView, TransactionView},
+    packed::{self, Byte32, CellDep, CellOutput, OutPoint, Script, Transaction},
+    prelude::*,
+    H256,
+};
+
+use crate::traits::{
+    CellCollector, CellCollectorError, CellDepResolver, CellQueryOptions, HeaderDepResolver,
+    LiveCell, TransactionDependencyError, TransactionDependencyProvider,
+};
+
+/// The snapshot format version
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotCell {
+    pub out_point: json_types::OutPoint,
+    pub output: json_types::CellOutput,
+    pub output_data: json_types::JsonBytes,
+    pub block_number: json_types::BlockNumber,
+    pub tx_index: json_types::Uint32,
+}
+
+impl From<&LiveCell> for SnapshotCell {
+    fn from(cell: &LiveCell) -> SnapshotCell {
+        SnapshotCell {
+            out_point: cell.out_point.clone().into(),
+            output: cell.output.clone().into(),
+            output_data: json_types::JsonBytes::from_bytes(cell.output_data.clone()),
+            block_number: cell.block_number.into(),
+            tx_index: cell.tx_index.into(),
+        }
+    }
+}
+
+impl From<SnapshotCell> for LiveCell {
+    fn from(cell: SnapshotCell) -> LiveCell {
+        LiveCell {
+            output: cell.output.into(),
+            output_data: cell.output_data.into_bytes(),
+            out_point: cell.out_point.into(),
+            block_number: cell.block_number.value(),
+            tx_index: cell.tx_index.value(),
+        }
+    }
+}
+
+/// A `collect_live_cells` call, the query is identified by its debug format
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotQuery {
+    pub query: String,
+    pub apply_changes: bool,
+    pub cells: Vec<SnapshotCell>,
+    pub total_capacity: json_types::Capacity,
+}
+
+/// Everything a transaction build touched
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    /// The `collect_live_cells` calls in order
+    pub queries: Vec<SnapshotQuery>,
+    pub cell_deps: Vec<(json_types::Script, Option<json_types::CellDep>)>,
+    pub headers_by_tx: Vec<(H256, Option<json_types::HeaderView>)>,
+    pub headers_by_number: Vec<(json_types::BlockNumber, Option<json_types::HeaderView>)>,
+    pub transactions: Vec<json_types::TransactionView>,
+    pub cells: Vec<(json_types::OutPoint, json_types::CellOutput)>,
+    pub cells_data: Vec<(json_types::OutPoint, json_types::JsonBytes)>,
+    pub headers: Vec<json_types::HeaderView>,
+    pub block_extensions: Vec<(H256, Option<json_types::JsonBytes>)>,
+}
+
+impl Default for Snapshot {
+    fn default() -> Snapshot {
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            queries: Vec::new(),
+            cell_deps: Vec::new(),
+            headers_by_tx: Vec::new(),
+            headers_by_number: Vec::new(),
+            transactions: Vec::new(),
+            cells: Vec::new(),
+            cells_data: Vec::new(),
+            headers: Vec::new(),
+            block_extensions: Vec::new(),
+        }
+    }
+}
+
+impl Snapshot {
+    pub fn new() -> Snapshot {
+        Snapshot::default()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Snapshot, anyhow::Error> {
+        let content = fs::read(path.as_ref())?;
+        let snapshot: Snapshot = serde_json::from_slice(&content)?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(anyhow!(
+                "unsupported snapshot version: {}, expected: {}",
+                snapshot.version,
+                SNAPSHOT_VERSION
+            ));
+        }
+        Ok(snapshot)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), anyhow::Error> {
+        fs::write(path.as_ref(), serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Record the results of the wrapped providers into a shared snapshot
+#[derive(Clone, Default)]
+pub struct SnapshotRecorder {
+    snapshot: Arc<Mutex<Snapshot>>,
+}
+
+impl SnapshotRecorder {
+    pub fn new() -> SnapshotRecorder {
+        SnapshotRecorder::default()
+    }
+
+    /// The snapshot recorded so far
+    pub fn snapshot(&self) -> Snapshot {
+        self.snapshot.lock().clone()
+    }
+
+    pub fn cell_collector(&self, inner: Box<dyn CellCollector>) -> RecordingCellCollector {
+        RecordingCellCollector {
+            inner,
+            recorder: self.clone(),
+        }
+    }
+
+    pub fn cell_dep_resolver<'a>(
+        &self,
+        inner: &'a dyn CellDepResolver,
+    ) -> RecordingCellDepResolver<'a> {
+        RecordingCellDepResolver {
+            inner,
+            recorder: self.clone(),
+        }
+    }
+
+    pub fn header_dep_resolver<'a>(
+        &self,
+        inner: &'a dyn HeaderDepResolver,
+    ) -> RecordingHeaderDepResolver<'a> {
+        RecordingHeaderDepResolver {
+            inner,
+            recorder: self.clone(),
+        }
+    }
+
+    pub fn tx_dep_provider<'a>(
+        &self,
+        inner: &'a dyn TransactionDependencyProvider,
+    ) -> RecordingTransactionDependencyProvider<'a> {
+        RecordingTransactionDependencyProvider {
+            inner,
+            recorder: self.clone(),
+        }
+    }
+}
+
+pub struct RecordingCellCollector {
+    inner: Box<dyn CellCollector>,
+    recorder: SnapshotRecorder,
+}
+
+impl Clone for RecordingCellCollector {
+    fn clone(&self) -> RecordingCellCollector {
+        RecordingCellCollector {
+            inner: dyn_clone::clone_box(&*self.inner),
+            recorder: self.recorder.clone(),
+        }
+    }
+}
+
+impl CellCollector for RecordingCellCollector {
+    fn collect_live_cells(
+        &mut self,
+        query: &CellQueryOptions,
+        apply_changes: bool,
+    ) -> Result<(Vec<LiveCell>, u64), CellCollectorError> {
+        let (cells, total_capacity) = self.inner.collect_live_cells(query, apply_changes)?;
+        self.recorder.snapshot.lock().queries.push(SnapshotQuery {
+            query: format!("{:?}", query),
+            apply_changes,
+            cells: cells.iter().map(SnapshotCell::from).collect(),
+            total_capacity: total_capacity.into(),
+        });
+        Ok((cells, total_capacity))
+    }
+
+    fn lock_cell(
+        &mut self,
+        out_point: OutPoint,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        self.inner.lock_cell(out_point, tip_block_number)
+    }
+
+    fn apply_tx(
+        &mut self,
+        tx: Transaction,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        self.inner.apply_tx(tx, tip_block_number)
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset()
+    }
+}
+
+pub struct RecordingCellDepResolver<'a> {
+    inner: &'a dyn CellDepResolver,
+    recorder: SnapshotRecorder,
+}
+
+impl<'a> CellDepResolver for RecordingCellDepResolver<'a> {
+    fn resolve(&self, script: &Script) -> Option<CellDep> {
+        let cell_dep = self.inner.resolve(script);
+        self.recorder
+            .snapshot
+            .lock()
+            .cell_deps
+            .push((script.clone().into(), cell_dep.clone().map(Into::into)));
+        cell_dep
+    }
+}
+
+pub struct RecordingHeaderDepResolver<'a> {
+    inner: &'a dyn HeaderDepResolver,
+    recorder: SnapshotRecorder,
+}
+
+impl<'a> HeaderDepResolver for RecordingHeaderDepResolver<'a> {
+    fn resolve_by_tx(&self, tx_hash: &Byte32) -> Result<Option<HeaderView>, anyhow::Error> {
+        let header = self.inner.resolve_by_tx(tx_hash)?;
+        self.recorder
+            .snapshot
+            .lock()
+            .headers_by_tx
+            .push((tx_hash.unpack(), header.clone().map(Into::into)));
+        Ok(header)
+    }
+
+    fn resolve_by_number(&self, number: u64) -> Result<Option<HeaderView>, anyhow::Error> {
+        let header = self.inner.resolve_by_number(number)?;
+        self.recorder
+            .snapshot
+            .lock()
+            .headers_by_number
+            .push((number.into(), header.clone().map(Into::into)));
+        Ok(header)
+    }
+}
+
+pub struct RecordingTransactionDependencyProvider<'a> {
+    inner: &'a dyn TransactionDependencyProvider,
+    recorder: SnapshotRecorder,
+}
+
+impl<'a> TransactionDependencyProvider for RecordingTransactionDependencyProvider<'a> {
+    fn get_transaction(
+        &self,
+        tx_hash: &Byte32,
+    ) -> Result<TransactionView, TransactionDependencyError> {
+        let tx = self.inner.get_transaction(tx_hash)?;
+        self.recorder
+            .snapshot
+            .lock()
+            .transactions
+            .push(tx.clone().into());
+        Ok(tx)
+    }
+
+    fn get_cell(&self, out_point: &OutPoint) -> Result<CellOutput, TransactionDependencyError> {
+        let output = self.inner.get_cell(out_point)?;
+        self.recorder
+            .snapshot
+            .lock()
+            .cells
+            .push((out_point.clone().into(), output.clone().into()));
+        Ok(output)
+    }
+
+    fn get_cell_data(&self, out_point: &OutPoint) -> Result<Bytes, TransactionDependencyError> {
+        let data = self.inner.get_cell_data(out_point)?;
+        self.recorder.snapshot.lock().cells_data.push((
+            out_point.clone().into(),
+            json_types::JsonBytes::from_bytes(data.clone()),
+        ));
+        Ok(data)
+    }
+
+    fn get_header(&self, block_hash: &Byte32) -> Result<HeaderView, TransactionDependencyError> {
+        let header = self.inner.get_header(block_hash)?;
+        self.recorder
+            .snapshot
+            .lock()
+            .headers
+            .push(header.clone().into());
+        Ok(header)
+    }
+
+    fn get_block_extension(
+        &self,
+        block_hash: &Byte32,
+    ) -> Result<Option<packed::Bytes>, TransactionDependencyError> {
+        let extension = self.inner.get_block_extension(block_hash)?;
+        self.recorder.snapshot.lock().block_extensions.push((
+            block_hash.unpack(),
+            extension
+                .clone()
+                .map(|bytes| json_types::JsonBytes::from_bytes(bytes.raw_data())),
+        ));
+        Ok(extension)
+    }
+}
+
+/// Replay a transaction build from a snapshot.
+///
+/// The `collect_live_cells` results are returned in the recorded order of the
+/// same query, other resources are looked up by their keys.
+#[derive(Clone)]
+pub struct SnapshotReplayer {
+    queries: HashMap<String, VecDeque<(Vec<LiveCell>, u64)>>,
+    cell_deps: Arc<HashMap<Script, Option<CellDep>>>,
+    headers_by_tx: Arc<HashMap<Byte32, Option<HeaderView>>>,
+    headers_by_number: Arc<HashMap<u64, Option<HeaderView>>>,
+    transactions: Arc<HashMap<Byte32, TransactionView>>,
+    cells: Arc<HashMap<OutPoint, CellOutput>>,
+    cells_data: Arc<HashMap<OutPoint, Bytes>>,
+    headers: Arc<HashMap<Byte32, HeaderView>>,
+    block_extensions: Arc<HashMap<Byte32, Option<packed::Bytes>>>,
+}
+
+impl SnapshotReplayer {
+    pub fn new(snapshot: Snapshot) -> SnapshotReplayer {
+        let mut queries: HashMap<String, VecDeque<(Vec<LiveCell>, u64)>> = HashMap::new();
+        for query in snapshot.queries {
+            let cells = query.cells.into_iter().map(LiveCell::from).collect();
+            queries
+                .entry(query.query)
+                .or_default()
+                .push_back((cells, query.total_capacity.value()));
+        }
+        SnapshotReplayer {
+            queries,
+            cell_deps: Arc::new(
+                snapshot
+                    .cell_deps
+                    .into_iter()
+                    .map(|(script, cell_dep)| (script.into(), cell_dep.map(Into::into)))
+                    .collect(),
+            ),
+            headers_by_tx: Arc::new(
+                snapshot
+                    .headers_by_tx
+                    .into_iter()
+                    .map(|(tx_hash, header)| (tx_hash.pack(), header.map(Into::into)))
+                    .collect(),
+            ),
+            headers_by_number: Arc::new(
+                snapshot
+                    .headers_by_number
+                    .into_iter()
+                    .map(|(number, header)| (number.value(), header.map(Into::into)))
+                    .collect(),
+            ),
+            transactions: Arc::new(
+                snapshot
+                    .transactions
+                    .into_iter()
+                    .map(|tx| {
+                        let tx = Transaction::from(tx.inner).into_view();
+                        (tx.hash(), tx)
+                    })
+                    .collect(),
+            ),
+            cells: Arc::new(
+                snapshot
+                    .cells
+                    .into_iter()
+                    .map(|(out_point, output)| (out_point.into(), output.into()))
+                    .collect(),
+            ),
+            cells_data: Arc::new(
+                snapshot
+                    .cells_data
+                    .into_iter()
+                    .map(|(out_point, data)| (out_point.into(), data.into_bytes()))
+                    .collect(),
+            ),
+            headers: Arc::new(
+                snapshot
+                    .headers
+                    .into_iter()
+                    .map(|header| {
+                        let header = HeaderView::from(header);
+                        (header.hash(), header)
+                    })
+                    .collect(),
+            ),
+            block_extensions: Arc::new(
+                snapshot
+                    .block_extensions
+                    .into_iter()
+                    .map(|(block_hash, extension)| {
+                        (
+                            block_hash.pack(),
+                            extension.map(|bytes| bytes.into_bytes().pack()),
+                        )
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<SnapshotReplayer, anyhow::Error> {
+        Ok(SnapshotReplayer::new(Snapshot::load(path)?))
+    }
+}
+
+fn not_recorded(resource: String) -> TransactionDependencyError {
+    TransactionDependencyError::NotFound(format!("{} (not recorded in snapshot)", resource))
+}
+
+impl CellCollector for SnapshotReplayer {
+    fn collect_live_cells(
+        &mut self,
+        query: &CellQueryOptions,
+        _apply_changes: bool,
+    ) -> Result<(Vec<LiveCell>, u64), CellCollectorError> {
+        let key = format!("{:?}", query);
+        self.queries
+            .get_mut(&key)
+            .and_then(|results| results.pop_front())
+            .ok_or_else(|| {
+                CellCollectorError::Other(anyhow!("query not recorded in snapshot: {}", key))
+            })
+    }
+
+    fn lock_cell(
+        &mut self,
+        _out_point: OutPoint,
+        _tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        Ok(())
+    }
+
+    fn apply_tx(
+        &mut self,
+        _tx: Transaction,
+        _tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        Ok(())
+    }
+
+    fn reset(&mut self) {}
+}
+
+impl CellDepResolver for SnapshotReplayer {
+    fn resolve(&self, script: &Script) -> Option<CellDep> {
+        self.cell_deps.get(script).cloned().flatten()
+    }
+}
+
+impl HeaderDepResolver for SnapshotReplayer {
+    fn resolve_by_tx(&self, tx_hash: &Byte32) -> Result<Option<HeaderView>, anyhow::Error> {
+        self.headers_by_tx
+            .get(tx_hash)
+            .cloned()
+            .ok_or_else(|| anyhow!("header of tx {} not recorded in snapshot", tx_hash))
+    }
+
+    fn resolve_by_number(&self, number: u64) -> Result<Option<HeaderView>, anyhow::Error> {
+        self.headers_by_number
+            .get(&number)
+            .cloned()
+            .ok_or_else(|| anyhow!("header of block {} not recorded in snapshot", number))
+    }
+}
+
+impl TransactionDependencyProvider for SnapshotReplayer {
+    fn get_transaction(
+        &self,
+        tx_hash: &Byte32,
+    ) -> Result<TransactionView, TransactionDependencyError> {
+        self.transactions
+            .get(tx_hash)
+            .cloned()
+            .ok_or_else(|| not_recorded(format!("transaction {}", tx_hash)))
+    }
+
+    fn get_cell(&self, out_point: &OutPoint) -> Result<CellOutput, TransactionDependencyError> {
+        self.cells
+            .get(out_point)
+            .cloned()
+            .ok_or_else(|| not_recorded(format!("cell {}", out_point)))
+    }
+
+    fn get_cell_data(&self, out_point: &OutPoint) -> Result<Bytes, TransactionDependencyError> {
+        self.cells_data
+            .get(out_point)
+            .cloned()
+            .ok_or_else(|| not_recorded(format!("cell data {}", out_point)))
+    }
+
+    fn get_header(&self, block_hash: &Byte32) -> Result<HeaderView, TransactionDependencyError> {
+        self.headers
+            .get(block_hash)
+            .cloned()
+            .ok_or_else(|| not_recorded(format!("header {}", block_hash)))
+    }
+
+    fn get_block_extension(
+        &self,
+        block_hash: &Byte32,
+    ) -> Result<Option<packed::Bytes>, TransactionDependencyError> {
+        self.block_extensions
+            .get(block_hash)
+            .cloned()
+            .ok_or_else(|| not_recorded(format!("block extension {}", block_hash)))
+    }
+}
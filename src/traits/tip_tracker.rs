@@ -0,0 +1,220 @@
+//! A shared cache of the chain tip.
+//!
+//! The maturity and since checks of the collectors and providers depend on
+//! the tip header, epoch and median time. A [`TipTracker`] fetches them once
+//! per refresh interval, the clones of a tracker share the cached tip so all
+//! the components see the same consistent values instead of each calling
+//! `get_tip_header` separately.
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use ckb_types::{
+    core::{EpochNumberWithFraction, HeaderView},
+    prelude::*,
+};
+
+use crate::rpc::CkbRpcClient;
+use crate::util::get_max_mature_number_at;
+
+/// The tip values fetched at the same time
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TipInfo {
+    pub header: HeaderView,
+    /// The median time of the tip block (in milliseconds)
+    pub median_time: u64,
+    /// The max block number a cellbase cell is mature at
+    pub max_mature_number: u64,
+}
+
+impl TipInfo {
+    pub fn number(&self) -> u64 {
+        self.header.number()
+    }
+
+    pub fn epoch(&self) -> EpochNumberWithFraction {
+        self.header.epoch()
+    }
+}
+
+/// Cache the tip of the chain and refresh it on a configurable interval
+#[derive(Clone)]
+pub struct TipTracker {
+    ckb_client: CkbRpcClient,
+    refresh_interval: Duration,
+    cellbase_maturity: Arc<Mutex<Option<EpochNumberWithFraction>>>,
+    state: Arc<Mutex<Option<(Instant, TipInfo)>>>,
+}
+
+impl TipTracker {
+    pub fn new(ckb_client: &str, refresh_interval: Duration) -> TipTracker {
+        TipTracker {
+            ckb_client: CkbRpcClient::new(ckb_client),
+            refresh_interval,
+            cellbase_maturity: Arc::new(Mutex::new(None)),
+            state: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn refresh_interval(&self) -> Duration {
+        self.refresh_interval
+    }
+
+    /// The cached tip, refreshed if it is older than the refresh interval
+    pub fn tip(&self) -> Result<TipInfo, String> {
+        let mut state = self.state.lock();
+        if let Some((fetched_at, tip)) = state.as_ref() {
+            if fetched_at.elapsed() < self.refresh_interval {
+                return Ok(tip.clone());
+            }
+        }
+        let tip = self.fetch_tip()?;
+        *state = Some((Instant::now(), tip.clone()));
+        Ok(tip)
+    }
+
+    /// Fetch the tip from the node regardless of the refresh interval
+    pub fn refresh(&self) -> Result<TipInfo, String> {
+        let mut state = self.state.lock();
+        let tip = self.fetch_tip()?;
+        *state = Some((Instant::now(), tip.clone()));
+        Ok(tip)
+    }
+
+    pub fn tip_block_number(&self) -> Result<u64, String> {
+        self.tip().map(|tip| tip.number())
+    }
+
+    pub fn tip_epoch(&self) -> Result<EpochNumberWithFraction, String> {
+        self.tip().map(|tip| tip.epoch())
+    }
+
+    pub fn median_time(&self) -> Result<u64, String> {
+        self.tip().map(|tip| tip.median_time)
+    }
+
+    pub fn max_mature_number(&self) -> Result<u64, String> {
+        self.tip().map(|tip| tip.max_mature_number)
+    }
+
+    fn fetch_tip(&self) -> Result<TipInfo, String> {
+        let cellbase_maturity = self.cellbase_maturity()?;
+        let header: HeaderView = self
+            .ckb_client
+            .get_tip_header()
+            .map_err(|err| err.to_string())?
+            .into();
+        let median_time = self
+            .ckb_client
+            .get_block_median_time(header.hash().unpack())
+            .map_err(|err| err.to_string())?
+            .ok_or_else(|| format!("median time of block {:#x} not found", header.hash()))?
+            .value();
+        let max_mature_number =
+            get_max_mature_number_at(&self.ckb_client, header.epoch(), cellbase_maturity)?;
+        Ok(TipInfo {
+            header,
+            median_time,
+            max_mature_number,
+        })
+    }
+
+    // The cellbase maturity is a consensus parameter, fetched only once
+    fn cellbase_maturity(&self) -> Result<EpochNumberWithFraction, String> {
+        let mut cellbase_maturity = self.cellbase_maturity.lock();
+        if let Some(value) = *cellbase_maturity {
+            return Ok(value);
+        }
+        let value = EpochNumberWithFraction::from_full_value(
+            self.ckb_client
+                .get_consensus()
+                .map_err(|err| err.to_string())?
+                .cellbase_maturity
+                .value(),
+        );
+        *cellbase_maturity = Some(value);
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::MockRpcResult;
+    use ckb_chain_spec::consensus::ConsensusBuilder;
+    use ckb_jsonrpc_types::{Consensus, EpochView};
+    use ckb_types::{core::HeaderBuilder, prelude::*};
+    use httpmock::prelude::*;
+
+    #[test]
+    fn test_tip_tracker() {
+        // cellbase maturity is 3(1/3), tip epoch is 3(300/600), epoch 3 starts at block 1800
+        // so the max mature block number is 1800 + (600 * 1 / 6) = 1900
+        let server = MockServer::start();
+        let consensus: Consensus = ConsensusBuilder::default()
+            .cellbase_maturity(EpochNumberWithFraction::new(3, 1, 3))
+            .build()
+            .into();
+        let consensus_mock = server.mock(|when, then| {
+            when.method(POST).path("/").body_contains("get_consensus");
+            then.status(200)
+                .body(MockRpcResult::new(consensus).to_json());
+        });
+        let tip_header: ckb_jsonrpc_types::HeaderView = HeaderBuilder::default()
+            .number(2100u64.pack())
+            .epoch(
+                EpochNumberWithFraction::new(3, 300, 600)
+                    .full_value()
+                    .pack(),
+            )
+            .build()
+            .into();
+        let tip_mock = server.mock(|when, then| {
+            when.method(POST).path("/").body_contains("get_tip_header");
+            then.status(200)
+                .body(MockRpcResult::new(tip_header).to_json());
+        });
+        let epoch3 = EpochView {
+            number: 3.into(),
+            start_number: 1800.into(),
+            length: 600.into(),
+            compact_target: 0.into(),
+        };
+        server.mock(|when, then| {
+            when.method(POST)
+                .path("/")
+                .body_contains("get_epoch_by_number");
+            then.status(200).body(MockRpcResult::new(epoch3).to_json());
+        });
+        server.mock(|when, then| {
+            when.method(POST)
+                .path("/")
+                .body_contains("get_block_median_time");
+            then.status(200)
+                .body(MockRpcResult::new(ckb_jsonrpc_types::Uint64::from(1234u64)).to_json());
+        });
+
+        let tracker = TipTracker::new(server.base_url().as_str(), Duration::from_secs(3600));
+        let tip = tracker.tip().unwrap();
+        assert_eq!(tip.number(), 2100);
+        assert_eq!(tip.epoch(), EpochNumberWithFraction::new(3, 300, 600));
+        assert_eq!(tip.median_time, 1234);
+        assert_eq!(tip.max_mature_number, 1900);
+
+        // The clones share the cached tip
+        let cloned = tracker.clone();
+        assert_eq!(cloned.tip_block_number().unwrap(), 2100);
+        assert_eq!(cloned.max_mature_number().unwrap(), 1900);
+        tip_mock.assert_hits(1);
+
+        assert_eq!(tracker.refresh().unwrap(), tip);
+        tip_mock.assert_hits(2);
+        consensus_mock.assert_hits(1);
+
+        let tracker = TipTracker::new(server.base_url().as_str(), Duration::from_secs(0));
+        tracker.tip().unwrap();
+        tracker.tip().unwrap();
+        tip_mock.assert_hits(4);
+    }
+}
@@ -0,0 +1,597 @@
+//! A [`Signer`] backed by a Trezor hardware wallet.
+//!
+//! Trezor talks protobuf messages over its wire framing, the CKB messages
+//! are:
+//!
+//!   * `CkbGetPublicKey { repeated uint32 address_n = 1; bool show_display = 2; }`
+//!     answered by `CkbPublicKey { bytes public_key = 1; }`, the id of the
+//!     signer is the blake160 hash of the compressed public key.
+//!   * `CkbSignMessage { repeated uint32 address_n = 1; bytes tx_hash = 2; bytes message = 3; }`
+//!     answered by `CkbMessageSignature { bytes signature = 1; }` (65 bytes
+//!     recoverable signature).
+//!
+//! Every script group is signed by its own `CkbSignMessage`, so the device
+//! prompts the user to confirm the transaction hash and the message digest
+//! of each script group. The prompts arrive as `ButtonRequest`s which are
+//! answered by `ButtonAck`, the passphrase (if enabled) is entered on the
+//! device. The device must be unlocked (PIN entered) before use.
+//!
+//! The transport is abstracted by [`TrezorTransport`], [`HidTrezorTransport`]
+//! connects to the first Trezor device found via hidapi.
+use std::convert::TryFrom;
+use std::time::Duration;
+
+use hidapi::{HidApi, HidDevice};
+use parking_lot::Mutex;
+use thiserror::Error;
+
+use ckb_types::{bytes::Bytes, core::TransactionView, prelude::*, H160};
+
+use super::{Signer, SignerError};
+use crate::types::bip32::{account_path, parse_derivation_path, Bip32Error, EXTERNAL_CHAIN};
+use crate::util::blake160;
+
+/// The USB vendor and product ids of Trezor devices with the HID interface
+pub const TREZOR_DEVICE_IDS: [(u16, u16); 2] = [(0x534c, 0x0001), (0x1209, 0x53c1)];
+/// The HID usage page of the U2F interface, which is not the wire interface
+const U2F_USAGE_PAGE: u16 = 0xf1d0;
+
+pub const MESSAGE_TYPE_FAILURE: u16 = 3;
+pub const MESSAGE_TYPE_PIN_MATRIX_REQUEST: u16 = 18;
+pub const MESSAGE_TYPE_BUTTON_REQUEST: u16 = 26;
+pub const MESSAGE_TYPE_BUTTON_ACK: u16 = 27;
+pub const MESSAGE_TYPE_PASSPHRASE_REQUEST: u16 = 41;
+pub const MESSAGE_TYPE_PASSPHRASE_ACK: u16 = 42;
+pub const MESSAGE_TYPE_CKB_GET_PUBLIC_KEY: u16 = 1100;
+pub const MESSAGE_TYPE_CKB_PUBLIC_KEY: u16 = 1101;
+pub const MESSAGE_TYPE_CKB_SIGN_MESSAGE: u16 = 1102;
+pub const MESSAGE_TYPE_CKB_MESSAGE_SIGNATURE: u16 = 1103;
+
+/// The failure code when the user cancels on the device
+pub const FAILURE_ACTION_CANCELLED: u64 = 4;
+
+const HID_PACKET_SIZE: usize = 64;
+const HID_READ_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Error, Debug)]
+pub enum TrezorError {
+    #[error("hid error: `{0}`")]
+    Hid(#[from] hidapi::HidError),
+
+    #[error("trezor device not found")]
+    DeviceNotFound,
+
+    #[error("rejected by the user on the device")]
+    UserRejected,
+
+    #[error("the device is locked, enter the PIN on the device first")]
+    PinRequired,
+
+    #[error("trezor failure, code: `{code}`, message: `{message}`")]
+    Failure { code: u64, message: String },
+
+    #[error("unexpected message type: `{0}`")]
+    UnexpectedMessage(u16),
+
+    #[error("invalid response: `{0}`")]
+    InvalidResponse(String),
+
+    #[error(transparent)]
+    Bip32(#[from] Bip32Error),
+}
+
+/// A protobuf encoded message with its type
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct TrezorMessage {
+    pub message_type: u16,
+    pub payload: Vec<u8>,
+}
+
+impl TrezorMessage {
+    pub fn new(message_type: u16, payload: Vec<u8>) -> TrezorMessage {
+        TrezorMessage {
+            message_type,
+            payload,
+        }
+    }
+}
+
+/// Exchange the messages with the device
+pub trait TrezorTransport: Send {
+    /// Send the message and return the response message
+    fn call(&mut self, message: &TrezorMessage) -> Result<TrezorMessage, TrezorError>;
+}
+
+/// The transport over the Trezor HID framing
+pub struct HidTrezorTransport {
+    device: HidDevice,
+}
+
+impl HidTrezorTransport {
+    /// Connect to the first Trezor device found
+    pub fn open() -> Result<HidTrezorTransport, TrezorError> {
+        let api = HidApi::new()?;
+        let info = api
+            .device_list()
+            .find(|info| {
+                TREZOR_DEVICE_IDS.contains(&(info.vendor_id(), info.product_id()))
+                    && info.usage_page() != U2F_USAGE_PAGE
+            })
+            .ok_or(TrezorError::DeviceNotFound)?;
+        let device = info.open_device(&api)?;
+        Ok(HidTrezorTransport { device })
+    }
+
+    fn write_message(&self, message: &TrezorMessage) -> Result<(), TrezorError> {
+        for (sequence, packet) in hid_packets(message).into_iter().enumerate() {
+            // The first byte is the report id
+            let mut report = vec![0u8];
+            report.extend_from_slice(&packet);
+            let written = self.device.write(&report)?;
+            if written < report.len() {
+                return Err(TrezorError::InvalidResponse(format!(
+                    "packet {} partially written",
+                    sequence
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn read_packet(&self) -> Result<[u8; HID_PACKET_SIZE], TrezorError> {
+        let mut packet = [0u8; HID_PACKET_SIZE];
+        let read = self
+            .device
+            .read_timeout(&mut packet, HID_READ_TIMEOUT.as_millis() as i32)?;
+        if read != HID_PACKET_SIZE || packet[0] != b'?' {
+            return Err(TrezorError::InvalidResponse(
+                "invalid hid packet".to_string(),
+            ));
+        }
+        Ok(packet)
+    }
+
+    fn read_message(&self) -> Result<TrezorMessage, TrezorError> {
+        let packet = self.read_packet()?;
+        let (message_type, payload_len, chunk) = parse_first_packet(&packet)?;
+        let mut payload = chunk.to_vec();
+        while payload.len() < payload_len {
+            payload.extend_from_slice(&self.read_packet()?[1..]);
+        }
+        payload.truncate(payload_len);
+        Ok(TrezorMessage::new(message_type, payload))
+    }
+}
+
+impl TrezorTransport for HidTrezorTransport {
+    fn call(&mut self, message: &TrezorMessage) -> Result<TrezorMessage, TrezorError> {
+        self.write_message(message)?;
+        self.read_message()
+    }
+}
+
+/// Split the message into HID packets (without the report id), the first
+/// packet starts with `?##`, the message type and the payload length, the
+/// following packets start with `?`.
+fn hid_packets(message: &TrezorMessage) -> Vec<Vec<u8>> {
+    let mut data = b"##".to_vec();
+    data.extend_from_slice(&message.message_type.to_be_bytes());
+    data.extend_from_slice(&(message.payload.len() as u32).to_be_bytes());
+    data.extend_from_slice(&message.payload);
+    data.chunks(HID_PACKET_SIZE - 1)
+        .map(|chunk| {
+            let mut packet = Vec::with_capacity(HID_PACKET_SIZE);
+            packet.push(b'?');
+            packet.extend_from_slice(chunk);
+            packet.resize(HID_PACKET_SIZE, 0);
+            packet
+        })
+        .collect()
+}
+
+/// Return the message type, the payload length and the payload chunk of the
+/// first packet
+fn parse_first_packet(packet: &[u8]) -> Result<(u16, usize, &[u8]), TrezorError> {
+    if packet.len() < 9 || &packet[0..3] != b"?##" {
+        return Err(TrezorError::InvalidResponse(
+            "invalid message header".to_string(),
+        ));
+    }
+    let message_type = u16::from_be_bytes([packet[3], packet[4]]);
+    let payload_len = u32::from_be_bytes([packet[5], packet[6], packet[7], packet[8]]) as usize;
+    Ok((message_type, payload_len, &packet[9..]))
+}
+
+fn encode_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn encode_varint_field(buf: &mut Vec<u8>, field: u32, value: u64) {
+    encode_varint(buf, (field as u64) << 3);
+    encode_varint(buf, value);
+}
+
+fn encode_bytes_field(buf: &mut Vec<u8>, field: u32, value: &[u8]) {
+    encode_varint(buf, ((field as u64) << 3) | 2);
+    encode_varint(buf, value.len() as u64);
+    buf.extend_from_slice(value);
+}
+
+fn encode_path(buf: &mut Vec<u8>, path: &[u32]) {
+    for index in path {
+        encode_varint_field(buf, 1, *index as u64);
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum ProtoValue {
+    Varint(u64),
+    Bytes(Vec<u8>),
+}
+
+fn decode_varint(data: &[u8], offset: &mut usize) -> Result<u64, TrezorError> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *data
+            .get(*offset)
+            .ok_or_else(|| TrezorError::InvalidResponse("truncated varint".to_string()))?;
+        *offset += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(TrezorError::InvalidResponse("varint overflow".to_string()))
+}
+
+/// Decode the fields of a protobuf message, the fixed size fields are skipped
+fn decode_fields(data: &[u8]) -> Result<Vec<(u32, ProtoValue)>, TrezorError> {
+    let mut fields = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let key = decode_varint(data, &mut offset)?;
+        let field = (key >> 3) as u32;
+        let skip = match key & 0x07 {
+            0 => {
+                fields.push((field, ProtoValue::Varint(decode_varint(data, &mut offset)?)));
+                0
+            }
+            1 => 8,
+            2 => {
+                let len = decode_varint(data, &mut offset)? as usize;
+                let value = offset
+                    .checked_add(len)
+                    .and_then(|end| data.get(offset..end))
+                    .ok_or_else(|| TrezorError::InvalidResponse("truncated field".to_string()))?;
+                fields.push((field, ProtoValue::Bytes(value.to_vec())));
+                len
+            }
+            5 => 4,
+            wire_type => {
+                return Err(TrezorError::InvalidResponse(format!(
+                    "unsupported wire type: {}",
+                    wire_type
+                )))
+            }
+        };
+        offset += skip;
+    }
+    Ok(fields)
+}
+
+fn bytes_field(fields: &[(u32, ProtoValue)], field: u32) -> Option<&[u8]> {
+    fields.iter().find_map(|(number, value)| match value {
+        ProtoValue::Bytes(bytes) if *number == field => Some(bytes.as_slice()),
+        _ => None,
+    })
+}
+
+fn varint_field(fields: &[(u32, ProtoValue)], field: u32) -> Option<u64> {
+    fields.iter().find_map(|(number, value)| match value {
+        ProtoValue::Varint(value) if *number == field => Some(*value),
+        _ => None,
+    })
+}
+
+/// Send the request, answer the confirmation prompts and return the payload
+/// of the `expected` response
+fn call_expect(
+    transport: &mut dyn TrezorTransport,
+    request: TrezorMessage,
+    expected: u16,
+) -> Result<Vec<u8>, TrezorError> {
+    let mut response = transport.call(&request)?;
+    loop {
+        response = match response.message_type {
+            message_type if message_type == expected => return Ok(response.payload),
+            MESSAGE_TYPE_BUTTON_REQUEST => {
+                transport.call(&TrezorMessage::new(MESSAGE_TYPE_BUTTON_ACK, Vec::new()))?
+            }
+            MESSAGE_TYPE_PASSPHRASE_REQUEST => {
+                // PassphraseAck { bool on_device = 3; }
+                let mut payload = Vec::new();
+                encode_varint_field(&mut payload, 3, 1);
+                transport.call(&TrezorMessage::new(MESSAGE_TYPE_PASSPHRASE_ACK, payload))?
+            }
+            MESSAGE_TYPE_PIN_MATRIX_REQUEST => return Err(TrezorError::PinRequired),
+            MESSAGE_TYPE_FAILURE => {
+                let fields = decode_fields(&response.payload)?;
+                let code = varint_field(&fields, 1).unwrap_or_default();
+                if code == FAILURE_ACTION_CANCELLED {
+                    return Err(TrezorError::UserRejected);
+                }
+                let message = bytes_field(&fields, 2)
+                    .map(|message| String::from_utf8_lossy(message).to_string())
+                    .unwrap_or_default();
+                return Err(TrezorError::Failure { code, message });
+            }
+            message_type => return Err(TrezorError::UnexpectedMessage(message_type)),
+        };
+    }
+}
+
+/// A signer of one derivation path of a Trezor device
+pub struct TrezorSigner {
+    transport: Mutex<Box<dyn TrezorTransport>>,
+    path: Vec<u32>,
+    pubkey: secp256k1::PublicKey,
+}
+
+impl TrezorSigner {
+    /// Use the derivation path on the device connected by `transport`
+    pub fn new(
+        mut transport: Box<dyn TrezorTransport>,
+        path: Vec<u32>,
+    ) -> Result<TrezorSigner, TrezorError> {
+        let mut request = Vec::new();
+        encode_path(&mut request, &path);
+        let response = call_expect(
+            transport.as_mut(),
+            TrezorMessage::new(MESSAGE_TYPE_CKB_GET_PUBLIC_KEY, request),
+            MESSAGE_TYPE_CKB_PUBLIC_KEY,
+        )?;
+        let pubkey = bytes_field(&decode_fields(&response)?, 1)
+            .and_then(|data| secp256k1::PublicKey::from_slice(data).ok())
+            .ok_or_else(|| TrezorError::InvalidResponse("invalid public key".to_string()))?;
+        Ok(TrezorSigner {
+            transport: Mutex::new(transport),
+            path,
+            pubkey,
+        })
+    }
+
+    /// Connect to the first Trezor device and use the derivation path like
+    /// `m/44'/309'/0'/0/0`
+    pub fn open(path: &str) -> Result<TrezorSigner, TrezorError> {
+        TrezorSigner::new(
+            Box::new(HidTrezorTransport::open()?),
+            parse_derivation_path(path)?,
+        )
+    }
+
+    /// Connect to the first Trezor device and use the receiving address
+    /// `m/44'/309'/{account}'/0/{index}`
+    pub fn open_account(account: u32, index: u32) -> Result<TrezorSigner, TrezorError> {
+        let mut path = account_path(account);
+        path.extend_from_slice(&[EXTERNAL_CHAIN, index]);
+        TrezorSigner::new(Box::new(HidTrezorTransport::open()?), path)
+    }
+
+    pub fn path(&self) -> &[u32] {
+        &self.path
+    }
+
+    pub fn pubkey(&self) -> &secp256k1::PublicKey {
+        &self.pubkey
+    }
+
+    /// The lock args of the sighash address
+    pub fn lock_arg(&self) -> H160 {
+        blake160(&self.pubkey.serialize())
+    }
+
+    fn sign_message_request(&self, message: &[u8], tx: &TransactionView) -> TrezorMessage {
+        let mut payload = Vec::new();
+        encode_path(&mut payload, &self.path);
+        encode_bytes_field(&mut payload, 2, tx.hash().as_slice());
+        encode_bytes_field(&mut payload, 3, message);
+        TrezorMessage::new(MESSAGE_TYPE_CKB_SIGN_MESSAGE, payload)
+    }
+}
+
+impl Signer for TrezorSigner {
+    fn match_id(&self, id: &[u8]) -> bool {
+        id.len() == 20 && id == self.lock_arg().as_bytes()
+    }
+
+    fn sign(
+        &self,
+        id: &[u8],
+        message: &[u8],
+        recoverable: bool,
+        tx: &TransactionView,
+    ) -> Result<Bytes, SignerError> {
+        if !self.match_id(id) {
+            return Err(SignerError::IdNotFound);
+        }
+        if message.len() != 32 {
+            return Err(SignerError::InvalidMessage(format!(
+                "expected length: 32, got: {}",
+                message.len()
+            )));
+        }
+        let mut transport = self.transport.lock();
+        let signature = call_expect(
+            transport.as_mut(),
+            self.sign_message_request(message, tx),
+            MESSAGE_TYPE_CKB_MESSAGE_SIGNATURE,
+        )
+        .and_then(|response| {
+            bytes_field(&decode_fields(&response)?, 1)
+                .and_then(|signature| <[u8; 65]>::try_from(signature).ok())
+                .ok_or_else(|| TrezorError::InvalidResponse("invalid signature".to_string()))
+        })
+        .map_err(|err| SignerError::Other(err.into()))?;
+        if recoverable {
+            Ok(Bytes::from(signature.to_vec()))
+        } else {
+            Ok(Bytes::from(signature[0..64].to_vec()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use ckb_types::core::TransactionBuilder;
+
+    use crate::SECP256K1;
+
+    #[derive(Clone)]
+    struct MockTransport {
+        key: secp256k1::SecretKey,
+        messages: Arc<Mutex<Vec<TrezorMessage>>>,
+        pending_signature: Arc<Mutex<Option<Vec<u8>>>>,
+    }
+
+    impl TrezorTransport for MockTransport {
+        fn call(&mut self, message: &TrezorMessage) -> Result<TrezorMessage, TrezorError> {
+            self.messages.lock().push(message.clone());
+            let mut payload = Vec::new();
+            let response = match message.message_type {
+                MESSAGE_TYPE_CKB_GET_PUBLIC_KEY => {
+                    let pubkey = secp256k1::PublicKey::from_secret_key(&SECP256K1, &self.key);
+                    encode_bytes_field(&mut payload, 1, &pubkey.serialize());
+                    TrezorMessage::new(MESSAGE_TYPE_CKB_PUBLIC_KEY, payload)
+                }
+                MESSAGE_TYPE_CKB_SIGN_MESSAGE => {
+                    let fields = decode_fields(&message.payload)?;
+                    let digest = bytes_field(&fields, 3).unwrap();
+                    if digest == [0u8; 32] {
+                        encode_varint_field(&mut payload, 1, FAILURE_ACTION_CANCELLED);
+                        return Ok(TrezorMessage::new(MESSAGE_TYPE_FAILURE, payload));
+                    }
+                    let digest = secp256k1::Message::from_digest_slice(digest).unwrap();
+                    let signature = SECP256K1.sign_ecdsa_recoverable(&digest, &self.key);
+                    *self.pending_signature.lock() =
+                        Some(crate::util::serialize_signature(&signature).to_vec());
+                    // ButtonRequest { code = ButtonRequest_SignTx }
+                    encode_varint_field(&mut payload, 1, 8);
+                    TrezorMessage::new(MESSAGE_TYPE_BUTTON_REQUEST, payload)
+                }
+                MESSAGE_TYPE_BUTTON_ACK => {
+                    let signature = self.pending_signature.lock().take().unwrap();
+                    encode_bytes_field(&mut payload, 1, &signature);
+                    TrezorMessage::new(MESSAGE_TYPE_CKB_MESSAGE_SIGNATURE, payload)
+                }
+                message_type => return Err(TrezorError::UnexpectedMessage(message_type)),
+            };
+            Ok(response)
+        }
+    }
+
+    #[test]
+    fn test_trezor_signer() {
+        let key = secp256k1::SecretKey::from_slice(&[9u8; 32]).unwrap();
+        let transport = MockTransport {
+            key,
+            messages: Arc::new(Mutex::new(Vec::new())),
+            pending_signature: Arc::new(Mutex::new(None)),
+        };
+        let path = parse_derivation_path("m/44'/309'/0'/0/1").unwrap();
+        let signer = TrezorSigner::new(Box::new(transport.clone()), path.clone()).unwrap();
+        let pubkey = secp256k1::PublicKey::from_secret_key(&SECP256K1, &key);
+        let lock_arg = blake160(&pubkey.serialize());
+        assert!(signer.match_id(lock_arg.as_bytes()));
+        assert_eq!(signer.lock_arg(), lock_arg);
+
+        let tx = TransactionBuilder::default().build();
+        let message = [5u8; 32];
+        let signature = signer
+            .sign(lock_arg.as_bytes(), &message, true, &tx)
+            .unwrap();
+        assert_eq!(signature.len(), 65);
+        let recoverable = secp256k1::ecdsa::RecoverableSignature::from_compact(
+            &signature[0..64],
+            secp256k1::ecdsa::RecoveryId::from_i32(signature[64] as i32).unwrap(),
+        )
+        .unwrap();
+        let digest = secp256k1::Message::from_digest_slice(&message).unwrap();
+        assert_eq!(
+            SECP256K1.recover_ecdsa(&digest, &recoverable).unwrap(),
+            pubkey
+        );
+        let signature = signer
+            .sign(lock_arg.as_bytes(), &message, false, &tx)
+            .unwrap();
+        assert_eq!(signature.len(), 64);
+
+        // The sign request carries the path, the tx hash and the digest, the
+        // prompt is confirmed by ButtonAck
+        let messages = transport.messages.lock();
+        let message_types: Vec<_> = messages.iter().map(|msg| msg.message_type).collect();
+        assert_eq!(
+            message_types,
+            vec![
+                MESSAGE_TYPE_CKB_GET_PUBLIC_KEY,
+                MESSAGE_TYPE_CKB_SIGN_MESSAGE,
+                MESSAGE_TYPE_BUTTON_ACK,
+                MESSAGE_TYPE_CKB_SIGN_MESSAGE,
+                MESSAGE_TYPE_BUTTON_ACK,
+            ]
+        );
+        let fields = decode_fields(&messages[1].payload).unwrap();
+        let path_fields: Vec<_> = fields
+            .iter()
+            .filter_map(|(number, value)| match value {
+                ProtoValue::Varint(index) if *number == 1 => Some(*index as u32),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(path_fields, path);
+        assert_eq!(bytes_field(&fields, 2), Some(tx.hash().as_slice()));
+        assert_eq!(bytes_field(&fields, 3), Some(&message[..]));
+        drop(messages);
+
+        let err = signer
+            .sign(lock_arg.as_bytes(), &[0u8; 32], true, &tx)
+            .unwrap_err();
+        assert!(err.to_string().contains("rejected by the user"));
+        assert!(matches!(
+            signer.sign(&[0u8; 20], &[5u8; 32], true, &tx),
+            Err(SignerError::IdNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_hid_packets() {
+        let message = TrezorMessage::new(MESSAGE_TYPE_CKB_SIGN_MESSAGE, vec![1u8; 100]);
+        let packets = hid_packets(&message);
+        assert_eq!(packets.len(), 2);
+        assert!(packets.iter().all(|packet| packet.len() == HID_PACKET_SIZE));
+        let (message_type, payload_len, chunk) = parse_first_packet(&packets[0]).unwrap();
+        assert_eq!(message_type, MESSAGE_TYPE_CKB_SIGN_MESSAGE);
+        assert_eq!(payload_len, 100);
+        assert_eq!(chunk.len(), 55);
+        assert_eq!(packets[1][0], b'?');
+        assert!(parse_first_packet(&packets[1]).is_err());
+
+        let mut payload = Vec::new();
+        encode_varint_field(&mut payload, 1, 0x8000_002c);
+        encode_bytes_field(&mut payload, 2, &[7u8; 3]);
+        assert_eq!(
+            decode_fields(&payload).unwrap(),
+            vec![
+                (1, ProtoValue::Varint(0x8000_002c)),
+                (2, ProtoValue::Bytes(vec![7u8; 3])),
+            ]
+        );
+        assert!(decode_fields(&payload[0..payload.len() - 1]).is_err());
+    }
+}
@@ -1,11 +1,8 @@
-use ckb_hash::new_blake2b;
-use ckb_types::{
-    packed::{Bytes, CellInput},
-    prelude::*,
-};
+use ckb_types::{packed::Bytes, prelude::*};
 
 use crate::{
-    core::TransactionBuilder, tx_builder::TxBuilderError, NetworkInfo, ScriptGroup, ScriptId,
+    core::TransactionBuilder, tx_builder::TxBuilderError, util::calculate_type_id, NetworkInfo,
+    ScriptGroup, ScriptId,
 };
 
 use super::{HandlerContext, ScriptHandler};
@@ -55,12 +52,3 @@ impl ScriptHandler for TypeIdHandler {
         Ok(())
     }
 }
-
-fn calculate_type_id(first_cell_input: &CellInput, output_index: u64) -> [u8; 32] {
-    let mut blake2b = new_blake2b();
-    blake2b.update(first_cell_input.as_slice());
-    blake2b.update(&output_index.to_le_bytes());
-    let mut ret = [0u8; 32];
-    blake2b.finalize(&mut ret);
-    ret
-}
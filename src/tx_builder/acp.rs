@@ -2,9 +2,11 @@ use std::collections::HashSet;
 
 use anyhow::anyhow;
 use ckb_types::{
-    core::{TransactionBuilder, TransactionView},
-    packed::{CellInput, Script},
+    bytes::Bytes,
+    core::{Capacity, TransactionBuilder, TransactionView},
+    packed::{CellInput, CellOutput, Script},
     prelude::*,
+    H160,
 };
 
 use super::{TxBuilder, TxBuilderError};
@@ -12,6 +14,7 @@ use crate::traits::{
     CellCollector, CellDepResolver, CellQueryOptions, HeaderDepResolver,
     TransactionDependencyProvider,
 };
+use crate::types::ScriptId;
 
 #[derive(Clone, Debug)]
 pub struct AcpTransferReceiver {
@@ -95,3 +98,110 @@ impl TxBuilder for AcpTransferBuilder {
             .build())
     }
 }
+
+/// Build the anyone-can-pay lock script.
+///
+/// The minimum transfer amounts are encoded as digits: the minimum CKB is
+/// `10^ckb_minimum` shannons and the minimum UDT amount is `10^udt_minimum`,
+/// `udt_minimum` can only be given with `ckb_minimum`.
+pub fn build_acp_script(
+    acp_script_id: &ScriptId,
+    pubkey_hash: &H160,
+    ckb_minimum: Option<u8>,
+    udt_minimum: Option<u8>,
+) -> Result<Script, TxBuilderError> {
+    let mut args = pubkey_hash.as_bytes().to_vec();
+    match (ckb_minimum, udt_minimum) {
+        (Some(ckb_minimum), udt_minimum) => {
+            args.push(ckb_minimum);
+            args.extend(udt_minimum);
+        }
+        (None, Some(_)) => {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "udt minimum requires ckb minimum in acp args"
+            )));
+        }
+        (None, None) => {}
+    }
+    Ok(Script::new_builder()
+        .code_hash(acp_script_id.code_hash.pack())
+        .hash_type(acp_script_id.hash_type.into())
+        .args(Bytes::from(args).pack())
+        .build())
+}
+
+/// Create a new anyone-can-pay cell, with zero UDT amount if `udt_type_script`
+/// is given, otherwise with empty data.
+pub struct AcpCreateBuilder {
+    /// The anyone-can-pay lock script, the args is 20 bytes pubkey hash and
+    /// optional 1 byte minimum CKB and 1 byte minimum UDT digits
+    pub lock_script: Script,
+    pub udt_type_script: Option<Script>,
+    /// The capacity of the new cell, use the occupied capacity if not given
+    pub capacity: Option<u64>,
+}
+
+impl AcpCreateBuilder {
+    pub fn new(lock_script: Script, udt_type_script: Option<Script>) -> AcpCreateBuilder {
+        AcpCreateBuilder {
+            lock_script,
+            udt_type_script,
+            capacity: None,
+        }
+    }
+}
+
+impl TxBuilder for AcpCreateBuilder {
+    fn build_base(
+        &self,
+        _cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        let args_len = self.lock_script.args().raw_data().len();
+        if !(20..=22).contains(&args_len) {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "invalid acp lock args length, expected: 20, 21 or 22, got: {}",
+                args_len
+            )));
+        }
+        #[allow(clippy::mutable_key_type)]
+        let mut cell_deps = HashSet::new();
+        let output_data = match self.udt_type_script.as_ref() {
+            Some(type_script) => {
+                let cell_dep = cell_dep_resolver
+                    .resolve(type_script)
+                    .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(type_script.clone()))?;
+                cell_deps.insert(cell_dep);
+                Bytes::from(0u128.to_le_bytes().to_vec())
+            }
+            None => Bytes::new(),
+        };
+        let output = CellOutput::new_builder()
+            .lock(self.lock_script.clone())
+            .type_(self.udt_type_script.clone().pack())
+            .build();
+        let occupied_capacity = output
+            .occupied_capacity(Capacity::bytes(output_data.len()).unwrap())
+            .unwrap()
+            .as_u64();
+        let capacity = match self.capacity {
+            Some(capacity) if capacity < occupied_capacity => {
+                return Err(TxBuilderError::InvalidParameter(anyhow!(
+                    "acp cell capacity not enough, occupied: {}, actual: {}",
+                    occupied_capacity,
+                    capacity
+                )));
+            }
+            Some(capacity) => capacity,
+            None => occupied_capacity,
+        };
+        let output = output.as_builder().capacity(capacity.pack()).build();
+        Ok(TransactionBuilder::default()
+            .set_cell_deps(cell_deps.into_iter().collect())
+            .set_outputs(vec![output])
+            .set_outputs_data(vec![output_data.pack()])
+            .build())
+    }
+}
@@ -0,0 +1,200 @@
+//! Delegated UDT spending (allowances) built on cheque cells.
+//!
+//! A holder grants a spender an allowance by locking UDT into cheque cells
+//! (receiver: the spender, sender: the holder), one cell per installment, the
+//! amount of a cell caps what the spender can take for that installment. The
+//! typical use is subscription or merchant billing:
+//!
+//!   * The holder grants the installments with [`Allowance::grant`].
+//!   * The merchant (spender) claims the installments due with
+//!     [`Allowance::claim`], the claimed amount is capped by `max_amount`.
+//!   * The holder takes back the installments not claimed in the claim window
+//!     ([`CHEQUE_CLAIM_EPOCHS`] epochs since the cheque cell is committed)
+//!     with [`Allowance::refundable`] and [`Allowance::refund`].
+//!
+//! The expiry is enforced by the cheque lock, it can not be shorter or longer
+//! than the claim window, grant the installments period by period for a
+//! longer subscription.
+use anyhow::anyhow;
+use ckb_types::{
+    bytes::Bytes,
+    core::EpochNumberWithFraction,
+    packed::{CellInput, OutPoint, Script},
+    prelude::*,
+};
+
+use super::{
+    cheque::{ChequeClaimBuilder, ChequeWithdrawBuilder},
+    cheque_watch::{epochs_elapsed, CHEQUE_CLAIM_EPOCHS},
+    udt::{UdtTargetReceiver, UdtTransferBuilder},
+    TransferAction, TxBuilderError,
+};
+use crate::traits::{CellCollector, CellQueryOptions, HeaderDepResolver, ValueRangeOption};
+use crate::types::ScriptId;
+
+/// An unclaimed installment of an allowance
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct AllowanceInstallment {
+    /// The cheque cell
+    pub out_point: OutPoint,
+    pub amount: u128,
+    pub capacity: u64,
+}
+
+/// The allowance granted by `holder` to `spender` of a UDT
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Allowance {
+    /// The cheque lock script id
+    pub cheque_script_id: ScriptId,
+    /// The udt type script
+    pub type_script: Script,
+    /// The lock script of the holder, must be a sighash address to refund
+    pub holder: Script,
+    /// The lock script of the spender
+    pub spender: Script,
+}
+
+impl Allowance {
+    pub fn new(
+        cheque_script_id: ScriptId,
+        type_script: Script,
+        holder: Script,
+        spender: Script,
+    ) -> Allowance {
+        Allowance {
+            cheque_script_id,
+            type_script,
+            holder,
+            spender,
+        }
+    }
+
+    /// The lock script of the installment cells
+    pub fn cheque_lock_script(&self) -> Script {
+        let mut args = self.spender.calc_script_hash().as_slice()[0..20].to_vec();
+        args.extend_from_slice(&self.holder.calc_script_hash().as_slice()[0..20]);
+        Script::new_builder()
+            .code_hash(self.cheque_script_id.code_hash.pack())
+            .hash_type(self.cheque_script_id.hash_type.into())
+            .args(Bytes::from(args).pack())
+            .build()
+    }
+
+    /// Grant the installments, one cheque cell per cap. The UDT is taken from
+    /// the holder's UDT cell.
+    pub fn grant(&self, caps: &[u128]) -> Result<UdtTransferBuilder, TxBuilderError> {
+        if caps.is_empty() || caps.contains(&0) {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "allowance caps must be non-empty and positive"
+            )));
+        }
+        let cheque_lock = self.cheque_lock_script();
+        Ok(UdtTransferBuilder {
+            type_script: self.type_script.clone(),
+            sender: self.holder.clone(),
+            receivers: caps
+                .iter()
+                .map(|cap| {
+                    UdtTargetReceiver::new(TransferAction::Create, cheque_lock.clone(), *cap)
+                })
+                .collect(),
+        })
+    }
+
+    /// The unclaimed installments
+    pub fn installments(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+    ) -> Result<Vec<AllowanceInstallment>, TxBuilderError> {
+        let mut query = CellQueryOptions::new_lock(self.cheque_lock_script());
+        query.secondary_script = Some(self.type_script.clone());
+        query.data_len_range = Some(ValueRangeOption::new_exact(16));
+        query.min_total_capacity = u64::MAX;
+        let (cells, _) = cell_collector.collect_live_cells(&query, false)?;
+        Ok(cells
+            .into_iter()
+            .map(|cell| {
+                let mut amount_bytes = [0u8; 16];
+                amount_bytes.copy_from_slice(cell.output_data.as_ref());
+                AllowanceInstallment {
+                    out_point: cell.out_point,
+                    amount: u128::from_le_bytes(amount_bytes),
+                    capacity: cell.output.capacity().unpack(),
+                }
+            })
+            .collect())
+    }
+
+    /// The total amount of the unclaimed installments
+    pub fn remaining(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+    ) -> Result<u128, TxBuilderError> {
+        Ok(self
+            .installments(cell_collector)?
+            .iter()
+            .map(|installment| installment.amount)
+            .sum())
+    }
+
+    /// Claim the installments (in the cell collector order) into the
+    /// spender's UDT cell `receiver_input`, the total claimed amount does not
+    /// exceed `max_amount`.
+    pub fn claim(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        receiver_input: CellInput,
+        max_amount: u128,
+    ) -> Result<ChequeClaimBuilder, TxBuilderError> {
+        let mut inputs = Vec::new();
+        let mut total: u128 = 0;
+        for installment in self.installments(cell_collector)? {
+            match total.checked_add(installment.amount) {
+                Some(new_total) if new_total <= max_amount => {
+                    total = new_total;
+                    inputs.push(CellInput::new(installment.out_point, 0));
+                }
+                _ => break,
+            }
+        }
+        if inputs.is_empty() {
+            return Err(TxBuilderError::Other(anyhow!(
+                "no installment can be claimed within amount {}",
+                max_amount
+            )));
+        }
+        Ok(ChequeClaimBuilder::new(
+            inputs,
+            receiver_input,
+            self.holder.clone(),
+        ))
+    }
+
+    /// The installments not claimed in the claim window at `tip_epoch`, the
+    /// commit epochs are resolved by `header_dep_resolver`.
+    pub fn refundable(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        tip_epoch: EpochNumberWithFraction,
+    ) -> Result<Vec<OutPoint>, TxBuilderError> {
+        let mut out_points = Vec::new();
+        for installment in self.installments(cell_collector)? {
+            let tx_hash = installment.out_point.tx_hash();
+            let header = header_dep_resolver
+                .resolve_by_tx(&tx_hash)
+                .map_err(TxBuilderError::Other)?
+                .ok_or(TxBuilderError::ResolveHeaderDepByTxHashFailed(tx_hash))?;
+            if epochs_elapsed(header.epoch(), tip_epoch, CHEQUE_CLAIM_EPOCHS) {
+                out_points.push(installment.out_point);
+            }
+        }
+        Ok(out_points)
+    }
+
+    /// Refund the expired installments to the holder, see
+    /// [`Allowance::refundable`].
+    pub fn refund(&self, out_points: Vec<OutPoint>) -> ChequeWithdrawBuilder {
+        ChequeWithdrawBuilder::new(out_points, self.holder.clone(), None)
+    }
+}
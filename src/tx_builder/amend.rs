@@ -0,0 +1,167 @@
+use std::collections::HashSet;
+
+use anyhow::anyhow;
+use ckb_types::{
+    bytes::Bytes,
+    core::TransactionView,
+    packed::{self, Byte32, CellDep, CellInput, CellOutput, WitnessArgs},
+    prelude::*,
+};
+
+use super::{TxBuilder, TxBuilderError};
+use crate::traits::{
+    CellCollector, CellDepResolver, HeaderDepResolver, TransactionDependencyProvider,
+};
+use crate::types::ScriptId;
+
+/// Amend an existing unsigned transaction (e.g. produced by other tools):
+/// append inputs, outputs, cell deps and header deps, then balance and unlock
+/// it through the [`TxBuilder`] methods.
+///
+/// The cell deps of the new inputs' lock/type scripts and the new outputs'
+/// type scripts are resolved by the cell dep resolver. When
+/// `clear_witness_locks` is set (the default), the lock field of every
+/// witness is removed so the placeholders can be filled again, the
+/// `input_type` and `output_type` fields are kept.
+#[derive(Debug, Clone)]
+pub struct TxAmendBuilder {
+    pub tx: TransactionView,
+    pub inputs: Vec<CellInput>,
+    pub outputs: Vec<(CellOutput, Bytes)>,
+    pub cell_deps: Vec<CellDep>,
+    pub header_deps: Vec<Byte32>,
+    pub clear_witness_locks: bool,
+}
+
+impl TxAmendBuilder {
+    pub fn new(tx: TransactionView) -> TxAmendBuilder {
+        TxAmendBuilder {
+            tx,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            cell_deps: Vec::new(),
+            header_deps: Vec::new(),
+            clear_witness_locks: true,
+        }
+    }
+
+    pub fn add_input(&mut self, input: CellInput) -> &mut Self {
+        self.inputs.push(input);
+        self
+    }
+
+    pub fn add_output(&mut self, output: CellOutput, output_data: Bytes) -> &mut Self {
+        self.outputs.push((output, output_data));
+        self
+    }
+
+    pub fn add_cell_dep(&mut self, cell_dep: CellDep) -> &mut Self {
+        self.cell_deps.push(cell_dep);
+        self
+    }
+
+    pub fn add_header_dep(&mut self, header_dep: Byte32) -> &mut Self {
+        self.header_deps.push(header_dep);
+        self
+    }
+
+    fn clear_witness_lock(witness: packed::Bytes) -> Result<packed::Bytes, TxBuilderError> {
+        let witness_data = witness.raw_data();
+        if witness_data.is_empty() {
+            return Ok(witness);
+        }
+        let witness_args = WitnessArgs::from_slice(witness_data.as_ref())
+            .map_err(|err| TxBuilderError::InvalidParameter(anyhow!(err)))?;
+        Ok(witness_args
+            .as_builder()
+            .lock(None::<Bytes>.pack())
+            .build()
+            .as_bytes()
+            .pack())
+    }
+}
+
+impl TxBuilder for TxAmendBuilder {
+    fn build_base(
+        &self,
+        _cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        #[allow(clippy::mutable_key_type)]
+        let mut cell_deps: HashSet<CellDep> = self.tx.cell_deps().into_iter().collect();
+        let mut new_cell_deps = Vec::new();
+        let mut add_cell_dep = |cell_dep: CellDep| {
+            if cell_deps.insert(cell_dep.clone()) {
+                new_cell_deps.push(cell_dep);
+            }
+        };
+        for cell_dep in &self.cell_deps {
+            add_cell_dep(cell_dep.clone());
+        }
+
+        #[allow(clippy::mutable_key_type)]
+        let mut out_points: HashSet<_> = self.tx.input_pts_iter().collect();
+        for input in &self.inputs {
+            let out_point = input.previous_output();
+            if !out_points.insert(out_point.clone()) {
+                return Err(TxBuilderError::InvalidParameter(anyhow!(
+                    "duplicated input: {}",
+                    out_point
+                )));
+            }
+            let output = tx_dep_provider.get_cell(&out_point)?;
+            let scripts = Some(output.lock())
+                .into_iter()
+                .chain(output.type_().to_opt());
+            for script in scripts {
+                if ScriptId::from(&script).is_type_id() {
+                    continue;
+                }
+                let cell_dep = cell_dep_resolver
+                    .resolve(&script)
+                    .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(script.clone()))?;
+                add_cell_dep(cell_dep);
+            }
+        }
+        for (output, _) in &self.outputs {
+            if let Some(type_script) = output.type_().to_opt() {
+                if !ScriptId::from(&type_script).is_type_id() {
+                    let cell_dep = cell_dep_resolver
+                        .resolve(&type_script)
+                        .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(type_script.clone()))?;
+                    add_cell_dep(cell_dep);
+                }
+            }
+        }
+
+        let header_deps: HashSet<Byte32> = self.tx.header_deps_iter().collect();
+        let new_header_deps: Vec<_> = self
+            .header_deps
+            .iter()
+            .filter(|header_dep| !header_deps.contains(header_dep))
+            .cloned()
+            .collect();
+
+        let witnesses = if self.clear_witness_locks {
+            self.tx
+                .witnesses()
+                .into_iter()
+                .map(Self::clear_witness_lock)
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            self.tx.witnesses().into_iter().collect()
+        };
+        Ok(self
+            .tx
+            .as_advanced_builder()
+            .cell_deps(new_cell_deps)
+            .header_deps(new_header_deps)
+            .inputs(self.inputs.clone())
+            .outputs(self.outputs.iter().map(|(output, _)| output.clone()))
+            .outputs_data(self.outputs.iter().map(|(_, data)| data.pack()))
+            .set_witnesses(witnesses)
+            .build())
+    }
+}
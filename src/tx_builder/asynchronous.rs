@@ -0,0 +1,342 @@
+//! Async version of the transaction building pipeline, enabled by the
+//! `async-tx-builder` feature.
+//!
+//! All I/O goes through the async providers ([`AsyncCellCollector`],
+//! [`AsyncTransactionDependencyProvider`], [`AsyncHeaderDepResolver`]), the
+//! balancing and unlocking logic is shared with the blocking pipeline: the
+//! async providers are bridged to the blocking traits and the blocking
+//! pipeline runs on the current worker thread by
+//! `tokio::task::block_in_place`. So these functions must be called from a
+//! tokio multi-thread runtime (the feature enables `tokio/rt-multi-thread`),
+//! an error is returned on a current-thread runtime or outside a runtime.
+//!
+//! Since [`ScriptUnlocker`] is not `Send`, the functions which unlock take an
+//! [`UnlockersFactory`] and build the unlockers on the worker thread, so all
+//! the futures are `Send` and can be spawned (e.g. in a web handler).
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use futures::future::BoxFuture;
+use parking_lot::Mutex;
+use tokio::runtime::{Handle, RuntimeFlavor};
+
+use ckb_types::{
+    bytes::Bytes,
+    core::{HeaderView, TransactionView},
+    packed::{Byte32, CellOutput, OutPoint, Transaction},
+};
+
+use super::{
+    balance_tx_capacity, fill_placeholder_witnesses, unlock_tx, CapacityBalancer, TxBuilder,
+    TxBuilderError,
+};
+use crate::traits::{
+    AsyncCellCollector, AsyncHeaderDepResolver, AsyncTransactionDependencyProvider, CellCollector,
+    CellCollectorError, CellDepResolver, CellQueryOptions, HeaderDepResolver, LiveCell,
+    TransactionDependencyError, TransactionDependencyProvider,
+};
+use crate::types::{ScriptGroup, ScriptId};
+use crate::unlock::{ScriptUnlocker, UnlockError};
+
+/// Build the unlockers used by the async pipeline, it's called on the worker
+/// thread running the blocking pipeline.
+pub type UnlockersFactory<'a> = dyn Fn() -> HashMap<ScriptId, Box<dyn ScriptUnlocker>> + Sync + 'a;
+
+/// Async transaction Builder interface, all [`TxBuilder`] implement it.
+pub trait AsyncTxBuilder: Sync {
+    /// Async version of [`TxBuilder::build_base`]
+    fn build_base_async<'a>(
+        &'a self,
+        cell_collector: &'a mut dyn AsyncCellCollector,
+        cell_dep_resolver: &'a (dyn CellDepResolver + Sync),
+        header_dep_resolver: &'a dyn AsyncHeaderDepResolver,
+        tx_dep_provider: &'a dyn AsyncTransactionDependencyProvider,
+    ) -> BoxFuture<'a, Result<TransactionView, TxBuilderError>>;
+
+    /// Async version of [`TxBuilder::build_balanced`]
+    fn build_balanced_async<'a>(
+        &'a self,
+        cell_collector: &'a mut dyn AsyncCellCollector,
+        cell_dep_resolver: &'a (dyn CellDepResolver + Sync),
+        header_dep_resolver: &'a dyn AsyncHeaderDepResolver,
+        tx_dep_provider: &'a dyn AsyncTransactionDependencyProvider,
+        balancer: &'a CapacityBalancer,
+        unlockers: &'a UnlockersFactory<'a>,
+    ) -> BoxFuture<'a, Result<TransactionView, TxBuilderError>>;
+
+    /// Async version of [`TxBuilder::build_unlocked`], the transaction is
+    /// rebalanced the same way when the unlocked witnesses are bigger than
+    /// the placeholders.
+    fn build_unlocked_async<'a>(
+        &'a self,
+        cell_collector: &'a mut dyn AsyncCellCollector,
+        cell_dep_resolver: &'a (dyn CellDepResolver + Sync),
+        header_dep_resolver: &'a dyn AsyncHeaderDepResolver,
+        tx_dep_provider: &'a dyn AsyncTransactionDependencyProvider,
+        balancer: &'a CapacityBalancer,
+        unlockers: &'a UnlockersFactory<'a>,
+    ) -> BoxFuture<'a, Result<(TransactionView, Vec<ScriptGroup>), TxBuilderError>>;
+}
+
+impl<T: TxBuilder + Sync> AsyncTxBuilder for T {
+    fn build_base_async<'a>(
+        &'a self,
+        cell_collector: &'a mut dyn AsyncCellCollector,
+        cell_dep_resolver: &'a (dyn CellDepResolver + Sync),
+        header_dep_resolver: &'a dyn AsyncHeaderDepResolver,
+        tx_dep_provider: &'a dyn AsyncTransactionDependencyProvider,
+    ) -> BoxFuture<'a, Result<TransactionView, TxBuilderError>> {
+        Box::pin(async move {
+            let handle = runtime_handle().map_err(TxBuilderError::Other)?;
+            with_blocking(&handle, |handle| {
+                let mut cell_collector = BlockingCellCollector::new(cell_collector, handle);
+                let header_dep_resolver =
+                    BlockingHeaderDepResolver::new(header_dep_resolver, handle);
+                let tx_dep_provider = BlockingTxDepProvider::new(tx_dep_provider, handle);
+                self.build_base(
+                    &mut cell_collector,
+                    cell_dep_resolver,
+                    &header_dep_resolver,
+                    &tx_dep_provider,
+                )
+            })
+        })
+    }
+
+    fn build_balanced_async<'a>(
+        &'a self,
+        cell_collector: &'a mut dyn AsyncCellCollector,
+        cell_dep_resolver: &'a (dyn CellDepResolver + Sync),
+        header_dep_resolver: &'a dyn AsyncHeaderDepResolver,
+        tx_dep_provider: &'a dyn AsyncTransactionDependencyProvider,
+        balancer: &'a CapacityBalancer,
+        unlockers: &'a UnlockersFactory<'a>,
+    ) -> BoxFuture<'a, Result<TransactionView, TxBuilderError>> {
+        Box::pin(async move {
+            let handle = runtime_handle().map_err(TxBuilderError::Other)?;
+            with_blocking(&handle, |handle| {
+                let mut cell_collector = BlockingCellCollector::new(cell_collector, handle);
+                let header_dep_resolver =
+                    BlockingHeaderDepResolver::new(header_dep_resolver, handle);
+                let tx_dep_provider = BlockingTxDepProvider::new(tx_dep_provider, handle);
+                self.build_balanced(
+                    &mut cell_collector,
+                    cell_dep_resolver,
+                    &header_dep_resolver,
+                    &tx_dep_provider,
+                    balancer,
+                    &unlockers(),
+                )
+            })
+        })
+    }
+
+    fn build_unlocked_async<'a>(
+        &'a self,
+        cell_collector: &'a mut dyn AsyncCellCollector,
+        cell_dep_resolver: &'a (dyn CellDepResolver + Sync),
+        header_dep_resolver: &'a dyn AsyncHeaderDepResolver,
+        tx_dep_provider: &'a dyn AsyncTransactionDependencyProvider,
+        balancer: &'a CapacityBalancer,
+        unlockers: &'a UnlockersFactory<'a>,
+    ) -> BoxFuture<'a, Result<(TransactionView, Vec<ScriptGroup>), TxBuilderError>> {
+        Box::pin(async move {
+            let handle = runtime_handle().map_err(TxBuilderError::Other)?;
+            with_blocking(&handle, |handle| {
+                let mut cell_collector = BlockingCellCollector::new(cell_collector, handle);
+                let header_dep_resolver =
+                    BlockingHeaderDepResolver::new(header_dep_resolver, handle);
+                let tx_dep_provider = BlockingTxDepProvider::new(tx_dep_provider, handle);
+                self.build_unlocked(
+                    &mut cell_collector,
+                    cell_dep_resolver,
+                    &header_dep_resolver,
+                    &tx_dep_provider,
+                    balancer,
+                    &unlockers(),
+                )
+            })
+        })
+    }
+}
+
+/// Async version of [`balance_tx_capacity`]
+pub async fn balance_tx_capacity_async(
+    tx: &TransactionView,
+    balancer: &CapacityBalancer,
+    cell_collector: &mut dyn AsyncCellCollector,
+    tx_dep_provider: &dyn AsyncTransactionDependencyProvider,
+    cell_dep_resolver: &(dyn CellDepResolver + Sync),
+    header_dep_resolver: &dyn AsyncHeaderDepResolver,
+) -> Result<TransactionView, TxBuilderError> {
+    let handle = runtime_handle().map_err(TxBuilderError::Other)?;
+    with_blocking(&handle, |handle| {
+        let mut cell_collector = BlockingCellCollector::new(cell_collector, handle);
+        let tx_dep_provider = BlockingTxDepProvider::new(tx_dep_provider, handle);
+        let header_dep_resolver = BlockingHeaderDepResolver::new(header_dep_resolver, handle);
+        Ok(balance_tx_capacity(
+            tx,
+            balancer,
+            &mut cell_collector,
+            &tx_dep_provider,
+            cell_dep_resolver,
+            &header_dep_resolver,
+        )?)
+    })
+}
+
+/// Async version of [`fill_placeholder_witnesses`]
+pub async fn fill_placeholder_witnesses_async(
+    balanced_tx: TransactionView,
+    tx_dep_provider: &dyn AsyncTransactionDependencyProvider,
+    unlockers: &UnlockersFactory<'_>,
+) -> Result<(TransactionView, Vec<ScriptGroup>), UnlockError> {
+    let handle = runtime_handle().map_err(UnlockError::Other)?;
+    with_blocking(&handle, |handle| {
+        let tx_dep_provider = BlockingTxDepProvider::new(tx_dep_provider, handle);
+        fill_placeholder_witnesses(balanced_tx, &tx_dep_provider, &unlockers())
+    })
+}
+
+/// Async version of [`unlock_tx`]
+pub async fn unlock_tx_async(
+    balanced_tx: TransactionView,
+    tx_dep_provider: &dyn AsyncTransactionDependencyProvider,
+    unlockers: &UnlockersFactory<'_>,
+) -> Result<(TransactionView, Vec<ScriptGroup>), UnlockError> {
+    let handle = runtime_handle().map_err(UnlockError::Other)?;
+    with_blocking(&handle, |handle| {
+        let tx_dep_provider = BlockingTxDepProvider::new(tx_dep_provider, handle);
+        unlock_tx(balanced_tx, &tx_dep_provider, &unlockers())
+    })
+}
+
+/// The handle of the current runtime, `block_in_place` panics on a
+/// current-thread runtime so it is rejected here.
+fn runtime_handle() -> Result<Handle, anyhow::Error> {
+    let handle = Handle::try_current()
+        .map_err(|err| anyhow!("async tx builder must run in a tokio runtime: {}", err))?;
+    if handle.runtime_flavor() != RuntimeFlavor::MultiThread {
+        return Err(anyhow!(
+            "async tx builder requires a tokio multi-thread runtime, got: {:?}",
+            handle.runtime_flavor()
+        ));
+    }
+    Ok(handle)
+}
+
+fn with_blocking<R, F: FnOnce(&Handle) -> R>(handle: &Handle, f: F) -> R {
+    tokio::task::block_in_place(|| f(handle))
+}
+
+/// Drive an [`AsyncCellCollector`] as a [`CellCollector`], the clones share
+/// the same underlying collector.
+#[derive(Clone)]
+struct BlockingCellCollector<'a> {
+    inner: Arc<Mutex<&'a mut dyn AsyncCellCollector>>,
+    handle: &'a Handle,
+}
+
+impl<'a> BlockingCellCollector<'a> {
+    fn new(inner: &'a mut dyn AsyncCellCollector, handle: &'a Handle) -> Self {
+        BlockingCellCollector {
+            inner: Arc::new(Mutex::new(inner)),
+            handle,
+        }
+    }
+}
+
+impl<'a> CellCollector for BlockingCellCollector<'a> {
+    fn collect_live_cells(
+        &mut self,
+        query: &CellQueryOptions,
+        apply_changes: bool,
+    ) -> Result<(Vec<LiveCell>, u64), CellCollectorError> {
+        let mut inner = self.inner.lock();
+        self.handle
+            .block_on(inner.collect_live_cells(query, apply_changes))
+    }
+    fn max_total_capacity(
+        &mut self,
+        query: &CellQueryOptions,
+    ) -> Result<Option<u64>, CellCollectorError> {
+        let mut inner = self.inner.lock();
+        self.handle.block_on(inner.max_total_capacity(query))
+    }
+    fn lock_cell(
+        &mut self,
+        out_point: OutPoint,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        self.inner.lock().lock_cell(out_point, tip_block_number)
+    }
+    fn apply_tx(
+        &mut self,
+        tx: Transaction,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        self.inner.lock().apply_tx(tx, tip_block_number)
+    }
+    fn reset(&mut self) {
+        self.inner.lock().reset();
+    }
+}
+
+/// Drive an [`AsyncHeaderDepResolver`] as a [`HeaderDepResolver`]
+struct BlockingHeaderDepResolver<'a> {
+    inner: &'a dyn AsyncHeaderDepResolver,
+    handle: &'a Handle,
+}
+
+impl<'a> BlockingHeaderDepResolver<'a> {
+    fn new(inner: &'a dyn AsyncHeaderDepResolver, handle: &'a Handle) -> Self {
+        BlockingHeaderDepResolver { inner, handle }
+    }
+}
+
+impl<'a> HeaderDepResolver for BlockingHeaderDepResolver<'a> {
+    fn resolve_by_tx(&self, tx_hash: &Byte32) -> Result<Option<HeaderView>, anyhow::Error> {
+        self.handle.block_on(self.inner.resolve_by_tx(tx_hash))
+    }
+    fn resolve_by_number(&self, number: u64) -> Result<Option<HeaderView>, anyhow::Error> {
+        self.handle.block_on(self.inner.resolve_by_number(number))
+    }
+}
+
+/// Drive an [`AsyncTransactionDependencyProvider`] as a [`TransactionDependencyProvider`]
+struct BlockingTxDepProvider<'a> {
+    inner: &'a dyn AsyncTransactionDependencyProvider,
+    handle: &'a Handle,
+}
+
+impl<'a> BlockingTxDepProvider<'a> {
+    fn new(inner: &'a dyn AsyncTransactionDependencyProvider, handle: &'a Handle) -> Self {
+        BlockingTxDepProvider { inner, handle }
+    }
+}
+
+impl<'a> TransactionDependencyProvider for BlockingTxDepProvider<'a> {
+    fn get_transaction(
+        &self,
+        tx_hash: &Byte32,
+    ) -> Result<TransactionView, TransactionDependencyError> {
+        self.handle.block_on(self.inner.get_transaction(tx_hash))
+    }
+    fn get_cell(&self, out_point: &OutPoint) -> Result<CellOutput, TransactionDependencyError> {
+        self.handle.block_on(self.inner.get_cell(out_point))
+    }
+    fn get_cell_data(&self, out_point: &OutPoint) -> Result<Bytes, TransactionDependencyError> {
+        self.handle.block_on(self.inner.get_cell_data(out_point))
+    }
+    fn get_header(&self, block_hash: &Byte32) -> Result<HeaderView, TransactionDependencyError> {
+        self.handle.block_on(self.inner.get_header(block_hash))
+    }
+    fn get_block_extension(
+        &self,
+        block_hash: &Byte32,
+    ) -> Result<Option<ckb_types::packed::Bytes>, TransactionDependencyError> {
+        self.handle
+            .block_on(self.inner.get_block_extension(block_hash))
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+
+use ckb_types::{
+    bytes::Bytes,
+    core::{HeaderView, TransactionView},
+    packed::{Byte32, CellOutput, OutPoint},
+    prelude::*,
+};
+
+use super::{CapacityBalancer, TxBuilder, TxBuilderError};
+use crate::traits::{
+    CellCollector, CellDepResolver, HeaderDepResolver, TransactionDependencyError,
+    TransactionDependencyProvider,
+};
+use crate::types::{ScriptGroup, ScriptId};
+use crate::unlock::ScriptUnlocker;
+
+/// Resolve the transactions and cells from the local pending (not committed)
+/// transactions first, then from the inner provider.
+pub struct PendingTxsDependencyProvider<'a> {
+    pub inner: &'a dyn TransactionDependencyProvider,
+    pub txs: &'a [TransactionView],
+}
+
+impl<'a> PendingTxsDependencyProvider<'a> {
+    fn find_tx(&self, tx_hash: &Byte32) -> Option<&TransactionView> {
+        self.txs.iter().find(|tx| &tx.hash() == tx_hash)
+    }
+}
+
+impl<'a> TransactionDependencyProvider for PendingTxsDependencyProvider<'a> {
+    fn get_transaction(
+        &self,
+        tx_hash: &Byte32,
+    ) -> Result<TransactionView, TransactionDependencyError> {
+        if let Some(tx) = self.find_tx(tx_hash) {
+            return Ok(tx.clone());
+        }
+        self.inner.get_transaction(tx_hash)
+    }
+    fn get_cell(&self, out_point: &OutPoint) -> Result<CellOutput, TransactionDependencyError> {
+        if let Some(tx) = self.find_tx(&out_point.tx_hash()) {
+            let index: u32 = out_point.index().unpack();
+            return tx.output(index as usize).ok_or_else(|| {
+                TransactionDependencyError::NotFound(format!("cell: {}", out_point))
+            });
+        }
+        self.inner.get_cell(out_point)
+    }
+    fn get_cell_data(&self, out_point: &OutPoint) -> Result<Bytes, TransactionDependencyError> {
+        if let Some(tx) = self.find_tx(&out_point.tx_hash()) {
+            let index: u32 = out_point.index().unpack();
+            return tx
+                .outputs_data()
+                .get(index as usize)
+                .map(|data| data.raw_data())
+                .ok_or_else(|| {
+                    TransactionDependencyError::NotFound(format!("cell data: {}", out_point))
+                });
+        }
+        self.inner.get_cell_data(out_point)
+    }
+    fn get_header(&self, block_hash: &Byte32) -> Result<HeaderView, TransactionDependencyError> {
+        self.inner.get_header(block_hash)
+    }
+    fn get_block_extension(
+        &self,
+        block_hash: &Byte32,
+    ) -> Result<Option<ckb_types::packed::Bytes>, TransactionDependencyError> {
+        self.inner.get_block_extension(block_hash)
+    }
+}
+
+/// Build a chain of transactions, the later transactions can spend (or
+/// reference as cell deps) the outputs of the earlier ones before they are
+/// committed, e.g. deploy a contract then use it.
+///
+/// After each transaction is built, it is applied to the cell collector (its
+/// inputs are marked as dead and its outputs become live cells), and the
+/// dependency provider of the next transactions resolves its outputs
+/// locally. The cells deployed by a pending transaction can be registered to
+/// the cell dep resolver with [`TxChainBuilder::out_point`]. The transactions
+/// must be sent in order, see [`ChainSubmitter`](super::submit::ChainSubmitter).
+#[derive(Debug, Clone, Default)]
+pub struct TxChainBuilder {
+    /// The tip block number passed to [`CellCollector::apply_tx`]
+    pub tip_block_number: u64,
+    txs: Vec<TransactionView>,
+}
+
+impl TxChainBuilder {
+    pub fn new(tip_block_number: u64) -> TxChainBuilder {
+        TxChainBuilder {
+            tip_block_number,
+            txs: Vec::new(),
+        }
+    }
+
+    /// The built transactions in sending order
+    pub fn txs(&self) -> &[TransactionView] {
+        &self.txs
+    }
+
+    pub fn into_txs(self) -> Vec<TransactionView> {
+        self.txs
+    }
+
+    /// The out point of an output of the `tx_index`th built transaction
+    pub fn out_point(&self, tx_index: usize, output_index: u32) -> Option<OutPoint> {
+        self.txs
+            .get(tx_index)
+            .filter(|tx| (output_index as usize) < tx.outputs().len())
+            .map(|tx| OutPoint::new(tx.hash(), output_index))
+    }
+
+    /// Build (balance and unlock) the next transaction of the chain, the
+    /// transaction is appended to the chain.
+    ///
+    /// Return value:
+    ///   * The built transaction
+    ///   * The script groups not unlocked by given `unlockers`
+    #[allow(clippy::too_many_arguments)]
+    pub fn build_next(
+        &mut self,
+        builder: &dyn TxBuilder,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+        balancer: &CapacityBalancer,
+        unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+    ) -> Result<(TransactionView, Vec<ScriptGroup>), TxBuilderError> {
+        let pending_provider = PendingTxsDependencyProvider {
+            inner: tx_dep_provider,
+            txs: &self.txs,
+        };
+        let (tx, not_unlocked) = builder.build_unlocked(
+            cell_collector,
+            cell_dep_resolver,
+            header_dep_resolver,
+            &pending_provider,
+            balancer,
+            unlockers,
+        )?;
+        cell_collector.apply_tx(tx.data(), self.tip_block_number)?;
+        self.txs.push(tx.clone());
+        Ok((tx, not_unlocked))
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use ckb_types::{
+    bytes::Bytes,
+    core::{HeaderView, TransactionBuilder, TransactionView},
+    packed::{Byte32, CellInput, CellOutput, OutPoint, Script},
+    prelude::*,
+};
+
+use super::{fill_placeholder_witnesses, unlock_tx, CapacityBalancer, TxBuilder, TxBuilderError};
+use crate::traits::{
+    CellCollector, CellDepResolver, HeaderDepResolver, TransactionDependencyError,
+    TransactionDependencyProvider,
+};
+use crate::types::{ScriptGroup, ScriptId};
+use crate::unlock::ScriptUnlocker;
+
+/// The refund transaction template, spend the funding cell after `since`.
+#[derive(Debug, Clone)]
+pub struct ChannelRefund {
+    /// The since value of the funding input (normally a relative timelock)
+    pub since: u64,
+    /// Refund outputs, total capacity must less than funding capacity, the
+    /// difference is the refund transaction fee and must cover the fee
+    /// required by the balancer's fee rate.
+    pub outputs: Vec<(CellOutput, Bytes)>,
+}
+
+/// Funding transaction and its pre-signed refund transaction.
+#[derive(Debug, Clone)]
+pub struct ChannelTxPair {
+    /// The balanced funding transaction, not signed yet. It must only be
+    /// signed and broadcast after the refund transaction is fully signed.
+    pub funding_tx: TransactionView,
+    /// The funding cell, the funding output may be moved from the first
+    /// output by the balancer (e.g. the shuffle pass)
+    pub funding_out_point: OutPoint,
+    /// The refund transaction signed by given refund unlockers, a multisig
+    /// funding lock may still miss the counterparty's signature
+    pub refund_tx: TransactionView,
+    /// The script groups of refund transaction without a matching unlocker
+    pub refund_not_unlocked: Vec<ScriptGroup>,
+}
+
+/// Build a funding cell locked by `funding_lock` (typically a 2-of-2
+/// multisig).
+#[derive(Debug, Clone)]
+pub struct ChannelFundingBuilder {
+    pub funding_lock: Script,
+    pub capacity: u64,
+}
+
+impl ChannelFundingBuilder {
+    pub fn new(funding_lock: Script, capacity: u64) -> ChannelFundingBuilder {
+        ChannelFundingBuilder {
+            funding_lock,
+            capacity,
+        }
+    }
+
+    /// Build the funding transaction and the refund transaction spending the
+    /// funding cell.
+    ///
+    /// Since the transaction hash does not cover witnesses, the funding
+    /// transaction hash is fixed once it is balanced, so the refund
+    /// transaction can be built and signed before the funding transaction is
+    /// signed.
+    #[allow(clippy::too_many_arguments)]
+    pub fn build_with_refund(
+        &self,
+        refund: &ChannelRefund,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+        balancer: &CapacityBalancer,
+        funding_unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+        refund_unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+    ) -> Result<ChannelTxPair, TxBuilderError> {
+        if refund.outputs.is_empty() {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "empty refund outputs"
+            )));
+        }
+        let refund_capacity = refund
+            .outputs
+            .iter()
+            .map(|(output, _)| Unpack::<u64>::unpack(&output.capacity()))
+            .sum::<u64>();
+        if refund_capacity > self.capacity {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "refund outputs capacity ({}) exceed funding capacity ({})",
+                refund_capacity,
+                self.capacity
+            )));
+        }
+
+        let funding_tx = self.build_balanced(
+            cell_collector,
+            cell_dep_resolver,
+            header_dep_resolver,
+            tx_dep_provider,
+            balancer,
+            funding_unlockers,
+        )?;
+        let funding_index = self.funding_index(&funding_tx)?;
+        let funding_out_point = OutPoint::new(funding_tx.hash(), funding_index as u32);
+
+        let funding_cell_dep = cell_dep_resolver
+            .resolve(&self.funding_lock)
+            .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(self.funding_lock.clone()))?;
+        let refund_tx = TransactionBuilder::default()
+            .cell_dep(funding_cell_dep)
+            .input(CellInput::new(funding_out_point.clone(), refund.since))
+            .outputs(refund.outputs.iter().map(|(output, _)| output.clone()))
+            .outputs_data(refund.outputs.iter().map(|(_, data)| data.pack()))
+            .build();
+        let pending_provider = PendingTxDependencyProvider {
+            inner: tx_dep_provider,
+            tx: &funding_tx,
+        };
+        let (refund_tx, _) =
+            fill_placeholder_witnesses(refund_tx, &pending_provider, refund_unlockers)?;
+        // The placeholder witnesses are filled, the size will not change
+        let refund_tx_size = refund_tx.data().as_reader().serialized_size_in_block();
+        let min_refund_fee = balancer.fee_rate.fee(refund_tx_size as u64).as_u64();
+        if self.capacity - refund_capacity < min_refund_fee {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "refund transaction fee ({}) is less than the minimal fee ({})",
+                self.capacity - refund_capacity,
+                min_refund_fee
+            )));
+        }
+        let (refund_tx, refund_not_unlocked) =
+            unlock_tx(refund_tx, &pending_provider, refund_unlockers)?;
+        Ok(ChannelTxPair {
+            funding_tx,
+            funding_out_point,
+            refund_tx,
+            refund_not_unlocked,
+        })
+    }
+
+    /// Find the funding output in the balanced transaction by its lock and
+    /// capacity, fail when more than one output matches.
+    pub fn funding_index(&self, funding_tx: &TransactionView) -> Result<usize, TxBuilderError> {
+        let mut indices = funding_tx
+            .outputs_with_data_iter()
+            .enumerate()
+            .filter(|(_, (output, data))| {
+                output.lock() == self.funding_lock
+                    && Unpack::<u64>::unpack(&output.capacity()) == self.capacity
+                    && output.type_().to_opt().is_none()
+                    && data.is_empty()
+            })
+            .map(|(index, _)| index);
+        match (indices.next(), indices.next()) {
+            (Some(index), None) => Ok(index),
+            (Some(_), Some(_)) => Err(TxBuilderError::Other(anyhow!(
+                "more than one funding output in transaction {}",
+                funding_tx.hash()
+            ))),
+            (None, _) => Err(TxBuilderError::Other(anyhow!(
+                "funding output not found in transaction {}",
+                funding_tx.hash()
+            ))),
+        }
+    }
+}
+
+impl TxBuilder for ChannelFundingBuilder {
+    fn build_base(
+        &self,
+        _cell_collector: &mut dyn CellCollector,
+        _cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        let output = CellOutput::new_builder()
+            .capacity(self.capacity.pack())
+            .lock(self.funding_lock.clone())
+            .build();
+        Ok(TransactionBuilder::default()
+            .output(output)
+            .output_data(Bytes::new().pack())
+            .build())
+    }
+}
+
+/// Resolve cells created by a not yet committed transaction.
+pub(crate) struct PendingTxDependencyProvider<'a> {
+    pub(crate) inner: &'a dyn TransactionDependencyProvider,
+    pub(crate) tx: &'a TransactionView,
+}
+
+impl<'a> TransactionDependencyProvider for PendingTxDependencyProvider<'a> {
+    fn get_transaction(
+        &self,
+        tx_hash: &Byte32,
+    ) -> Result<TransactionView, TransactionDependencyError> {
+        if tx_hash == &self.tx.hash() {
+            return Ok(self.tx.clone());
+        }
+        self.inner.get_transaction(tx_hash)
+    }
+    fn get_cell(&self, out_point: &OutPoint) -> Result<CellOutput, TransactionDependencyError> {
+        if out_point.tx_hash() == self.tx.hash() {
+            let index: u32 = out_point.index().unpack();
+            return self.tx.output(index as usize).ok_or_else(|| {
+                TransactionDependencyError::NotFound(format!("cell: {}", out_point))
+            });
+        }
+        self.inner.get_cell(out_point)
+    }
+    fn get_cell_data(&self, out_point: &OutPoint) -> Result<Bytes, TransactionDependencyError> {
+        if out_point.tx_hash() == self.tx.hash() {
+            let index: u32 = out_point.index().unpack();
+            return self
+                .tx
+                .outputs_data()
+                .get(index as usize)
+                .map(|data| data.raw_data())
+                .ok_or_else(|| {
+                    TransactionDependencyError::NotFound(format!("cell data: {}", out_point))
+                });
+        }
+        self.inner.get_cell_data(out_point)
+    }
+    fn get_header(&self, block_hash: &Byte32) -> Result<HeaderView, TransactionDependencyError> {
+        self.inner.get_header(block_hash)
+    }
+    fn get_block_extension(
+        &self,
+        block_hash: &Byte32,
+    ) -> Result<Option<ckb_types::packed::Bytes>, TransactionDependencyError> {
+        self.inner.get_block_extension(block_hash)
+    }
+}
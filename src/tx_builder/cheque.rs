@@ -19,6 +19,9 @@ use crate::types::ScriptId;
 pub struct ChequeClaimBuilder {
     /// The cheque cells to claim, all cells must have same lock script and same
     /// type script and cell data length is equals to 16.
+    ///
+    /// If empty and `cheque_script_id` is provided, all the claimable cheque
+    /// cells are collected by the cell collector.
     pub inputs: Vec<CellInput>,
 
     /// Add all SUDT amount to this cell, the type script must be the same with
@@ -27,6 +30,10 @@ pub struct ChequeClaimBuilder {
 
     /// Sender's lock script, the script hash must match the cheque cell's lock script args.
     pub sender_lock_script: Script,
+
+    /// The cheque lock script id, used to discover the cheque cells when
+    /// `inputs` is empty.
+    pub cheque_script_id: Option<ScriptId>,
 }
 
 impl ChequeClaimBuilder {
@@ -39,19 +46,67 @@ impl ChequeClaimBuilder {
             inputs,
             receiver_input,
             sender_lock_script,
+            cheque_script_id: None,
+        }
+    }
+
+    /// Claim all the cheque cells sent from `sender_lock_script` to the owner
+    /// of `receiver_input` with the same type script as `receiver_input`.
+    pub fn new_with_discovery(
+        receiver_input: CellInput,
+        sender_lock_script: Script,
+        cheque_script_id: ScriptId,
+    ) -> ChequeClaimBuilder {
+        ChequeClaimBuilder {
+            inputs: Vec::new(),
+            receiver_input,
+            sender_lock_script,
+            cheque_script_id: Some(cheque_script_id),
+        }
+    }
+
+    /// Collect the live cheque cells of the receiver (by lock script hash) and
+    /// the sender with the given type script.
+    fn discover_inputs(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cheque_script_id: &ScriptId,
+        receiver_lock_script: &Script,
+        type_script: &Script,
+    ) -> Result<Vec<CellInput>, TxBuilderError> {
+        let mut args = receiver_lock_script.calc_script_hash().as_slice()[0..20].to_vec();
+        args.extend_from_slice(&self.sender_lock_script.calc_script_hash().as_slice()[0..20]);
+        let cheque_lock_script = Script::new_builder()
+            .code_hash(cheque_script_id.code_hash.pack())
+            .hash_type(cheque_script_id.hash_type.into())
+            .args(Bytes::from(args).pack())
+            .build();
+        let mut query = CellQueryOptions::new_lock(cheque_lock_script);
+        query.secondary_script = Some(type_script.clone());
+        query.data_len_range = Some(ValueRangeOption::new_exact(16));
+        query.min_total_capacity = u64::MAX;
+        let (cells, _) = cell_collector.collect_live_cells(&query, true)?;
+        if cells.is_empty() {
+            return Err(TxBuilderError::Other(anyhow!(
+                "no claimable cheque cell found"
+            )));
         }
+        Ok(cells
+            .into_iter()
+            .map(|cell| CellInput::new(cell.out_point, 0))
+            .collect())
     }
 }
 
 impl TxBuilder for ChequeClaimBuilder {
     fn build_base(
         &self,
-        _cell_collector: &mut dyn CellCollector,
+        cell_collector: &mut dyn CellCollector,
         cell_dep_resolver: &dyn CellDepResolver,
         _header_dep_resolver: &dyn HeaderDepResolver,
         tx_dep_provider: &dyn TransactionDependencyProvider,
     ) -> Result<TransactionView, TxBuilderError> {
-        if self.inputs.is_empty() {
+        if self.inputs.is_empty() && self.cheque_script_id.is_none() {
             return Err(TxBuilderError::InvalidParameter(anyhow!(
                 "empty cheque inputs"
             )));
@@ -59,8 +114,6 @@ impl TxBuilder for ChequeClaimBuilder {
 
         #[allow(clippy::mutable_key_type)]
         let mut cell_deps = HashSet::new();
-        let mut inputs = self.inputs.clone();
-        inputs.push(self.receiver_input.clone());
 
         let receiver_input_cell =
             tx_dep_provider.get_cell(&self.receiver_input.previous_output())?;
@@ -92,14 +145,26 @@ impl TxBuilder for ChequeClaimBuilder {
             .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(receiver_type_script.clone()))?;
         cell_deps.insert(receiver_type_cell_dep);
 
+        let cheque_inputs = match self.cheque_script_id.as_ref() {
+            Some(cheque_script_id) if self.inputs.is_empty() => self.discover_inputs(
+                cell_collector,
+                cheque_script_id,
+                &receiver_input_cell.lock(),
+                &receiver_type_script,
+            )?,
+            _ => self.inputs.clone(),
+        };
+        let mut inputs = cheque_inputs.clone();
+        inputs.push(self.receiver_input.clone());
+
         let mut cheque_total_amount = 0;
         let mut cheque_total_capacity = 0;
         let mut last_lock_script = None;
-        for input in &self.inputs {
+        for input in &cheque_inputs {
             let out_point = input.previous_output();
             let input_cell = tx_dep_provider.get_cell(&out_point)?;
             let input_data = tx_dep_provider.get_cell_data(&out_point)?;
-            let type_script = receiver_input_cell.type_().to_opt().ok_or_else(|| {
+            let type_script = input_cell.type_().to_opt().ok_or_else(|| {
                 TxBuilderError::InvalidParameter(anyhow!(
                     "cheque input missing type script: {}",
                     input
@@ -156,6 +221,12 @@ impl TxBuilder for ChequeClaimBuilder {
                 "sender lock script is not match with cheque lock script args"
             )));
         }
+        let receiver_lock_hash = receiver_input_cell.lock().calc_script_hash();
+        if receiver_lock_hash.as_slice()[0..20] != cheque_lock_args.as_ref()[0..20] {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "receiver input lock script is not match with cheque lock script args"
+            )));
+        }
 
         let receiver_output = receiver_input_cell;
         let receiver_output_data = {
@@ -0,0 +1,379 @@
+//! Reclaim the expired cheque cells for the cheque sender.
+//!
+//! [`ChequeWatcher`] tracks the cheque cells a sender created, when the claim
+//! window (6 epochs since the cheque cell is committed) elapses without a
+//! claim, it builds and sends the withdraw transaction. The tracked cheques
+//! are persisted to a state file (if given) so the watching can be resumed
+//! after restart.
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use ckb_jsonrpc_types::{self as json_types, Status};
+use ckb_types::{
+    core::{EpochNumberWithFraction, HeaderView},
+    packed::{OutPoint, Script},
+    H256,
+};
+
+use super::{cheque::ChequeWithdrawBuilder, CapacityBalancer, TxBuilder, TxBuilderError};
+use crate::rpc::CkbRpcClient;
+use crate::traits::{
+    CellCollector, CellDepResolver, HeaderDepResolver, TransactionDependencyProvider,
+};
+use crate::types::ScriptId;
+use crate::unlock::ScriptUnlocker;
+use crate::RpcError;
+
+/// The claim window of cheque cells in epochs
+pub const CHEQUE_CLAIM_EPOCHS: u64 = 6;
+
+#[derive(Error, Debug)]
+pub enum ChequeWatchError {
+    #[error("state error: `{0}`")]
+    State(anyhow::Error),
+
+    #[error("rpc error: `{0}`")]
+    Rpc(#[from] RpcError),
+
+    #[error("build withdraw transaction error: `{0}`")]
+    TxBuilder(#[from] TxBuilderError),
+}
+
+/// The status of a watched cheque cell
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChequeWatchStatus {
+    /// Not claimed and not withdrawn yet
+    Pending,
+    /// The withdraw transaction is sent
+    Withdrawing { tx_hash: H256 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct WatchedCheque {
+    out_point: json_types::OutPoint,
+    /// The epoch (full value) of the block which contains the cheque cell
+    created_epoch: Option<u64>,
+    status: ChequeWatchStatus,
+}
+
+/// The result of one poll
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ChequePollReport {
+    /// The cheque cells claimed by the receiver, no longer watched
+    pub claimed: Vec<OutPoint>,
+    /// The cheque cells withdrawn by the sender, no longer watched
+    pub withdrawn: Vec<OutPoint>,
+    /// The sent withdraw transaction
+    pub withdraw_tx: Option<H256>,
+}
+
+/// Check whether `epochs` epochs elapsed from `start` to `current`
+pub fn epochs_elapsed(
+    start: EpochNumberWithFraction,
+    current: EpochNumberWithFraction,
+    epochs: u64,
+) -> bool {
+    // compare current >= start + epochs as fractions
+    let lhs = (u128::from(current.number()) * u128::from(current.length())
+        + u128::from(current.index()))
+        * u128::from(start.length());
+    let rhs = (u128::from(start.number() + epochs) * u128::from(start.length())
+        + u128::from(start.index()))
+        * u128::from(current.length());
+    lhs >= rhs
+}
+
+/// Track the cheque cells of a sender and withdraw the expired ones.
+pub struct ChequeWatcher {
+    /// Sender's lock script, must match the cheque cell's lock script args.
+    pub sender_lock_script: Script,
+    /// If `acp_script_id` provided, will withdraw to anyone-can-pay address
+    pub acp_script_id: Option<ScriptId>,
+    state_path: Option<PathBuf>,
+    cheques: Vec<WatchedCheque>,
+}
+
+impl ChequeWatcher {
+    /// Create a watcher with in-memory state
+    pub fn new(sender_lock_script: Script, acp_script_id: Option<ScriptId>) -> ChequeWatcher {
+        ChequeWatcher {
+            sender_lock_script,
+            acp_script_id,
+            state_path: None,
+            cheques: Vec::new(),
+        }
+    }
+
+    /// Create a watcher with state persisted to a file
+    pub fn open<P: AsRef<Path>>(
+        state_path: P,
+        sender_lock_script: Script,
+        acp_script_id: Option<ScriptId>,
+    ) -> Result<ChequeWatcher, ChequeWatchError> {
+        let state_path = state_path.as_ref().to_path_buf();
+        let cheques = if state_path.exists() {
+            let content =
+                fs::read(&state_path).map_err(|err| ChequeWatchError::State(anyhow!(err)))?;
+            serde_json::from_slice(&content).map_err(|err| ChequeWatchError::State(anyhow!(err)))?
+        } else {
+            Vec::new()
+        };
+        Ok(ChequeWatcher {
+            sender_lock_script,
+            acp_script_id,
+            state_path: Some(state_path),
+            cheques,
+        })
+    }
+
+    fn save(&self) -> Result<(), ChequeWatchError> {
+        if let Some(path) = self.state_path.as_ref() {
+            let content = serde_json::to_vec(&self.cheques)
+                .map_err(|err| ChequeWatchError::State(anyhow!(err)))?;
+            fs::write(path, content).map_err(|err| ChequeWatchError::State(anyhow!(err)))?;
+        }
+        Ok(())
+    }
+
+    /// Start watching a cheque cell created by the sender
+    pub fn track(&mut self, out_point: OutPoint) -> Result<(), ChequeWatchError> {
+        let out_point = json_types::OutPoint::from(out_point);
+        if self
+            .cheques
+            .iter()
+            .all(|cheque| cheque.out_point != out_point)
+        {
+            self.cheques.push(WatchedCheque {
+                out_point,
+                created_epoch: None,
+                status: ChequeWatchStatus::Pending,
+            });
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// The watched cheque cells and their status
+    pub fn cheques(&self) -> Vec<(OutPoint, ChequeWatchStatus)> {
+        self.cheques
+            .iter()
+            .map(|cheque| (cheque.out_point.clone().into(), cheque.status.clone()))
+            .collect()
+    }
+
+    /// The pending cheque cells expired at `tip_epoch`, the cheques not
+    /// committed yet (without created epoch) are not expired.
+    pub fn expired(&self, tip_epoch: EpochNumberWithFraction) -> Vec<OutPoint> {
+        self.cheques
+            .iter()
+            .filter(|cheque| cheque.status == ChequeWatchStatus::Pending)
+            .filter(|cheque| {
+                cheque.created_epoch.map_or(false, |epoch| {
+                    epochs_elapsed(
+                        EpochNumberWithFraction::from_full_value(epoch),
+                        tip_epoch,
+                        CHEQUE_CLAIM_EPOCHS,
+                    )
+                })
+            })
+            .map(|cheque| cheque.out_point.clone().into())
+            .collect()
+    }
+
+    fn block_epoch(
+        ckb_client: &CkbRpcClient,
+        tx_hash: H256,
+    ) -> Result<Option<EpochNumberWithFraction>, ChequeWatchError> {
+        let block_hash = match ckb_client.get_transaction(tx_hash)? {
+            Some(tx_with_status) if tx_with_status.tx_status.status == Status::Committed => {
+                tx_with_status.tx_status.block_hash
+            }
+            _ => None,
+        };
+        if let Some(block_hash) = block_hash {
+            Ok(ckb_client
+                .get_header(block_hash)?
+                .map(|header| HeaderView::from(header).epoch()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Refresh the status of the watched cheques, then build, sign and send
+    /// one withdraw transaction for all expired cheques.
+    #[allow(clippy::too_many_arguments)]
+    pub fn poll(
+        &mut self,
+        ckb_client: &CkbRpcClient,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+        balancer: &CapacityBalancer,
+        unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+    ) -> Result<ChequePollReport, ChequeWatchError> {
+        let mut report = ChequePollReport::default();
+        let mut cheques = Vec::with_capacity(self.cheques.len());
+        for mut cheque in self.cheques.drain(..) {
+            let out_point = OutPoint::from(cheque.out_point.clone());
+            match cheque.status.clone() {
+                ChequeWatchStatus::Pending => {
+                    let cell = ckb_client.get_live_cell(cheque.out_point.clone(), false)?;
+                    if cell.status == "dead" {
+                        report.claimed.push(out_point);
+                        continue;
+                    }
+                    if cheque.created_epoch.is_none() {
+                        cheque.created_epoch =
+                            Self::block_epoch(ckb_client, cheque.out_point.tx_hash.clone())?
+                                .map(|epoch| epoch.full_value());
+                    }
+                }
+                ChequeWatchStatus::Withdrawing { tx_hash } => {
+                    let status = ckb_client
+                        .get_transaction(tx_hash)?
+                        .map(|tx_with_status| tx_with_status.tx_status.status);
+                    match status {
+                        Some(Status::Committed) => {
+                            report.withdrawn.push(out_point);
+                            continue;
+                        }
+                        Some(Status::Pending) | Some(Status::Proposed) => {}
+                        // rejected or dropped, try again
+                        _ => cheque.status = ChequeWatchStatus::Pending,
+                    }
+                }
+            }
+            cheques.push(cheque);
+        }
+        self.cheques = cheques;
+        self.save()?;
+
+        let tip_epoch = HeaderView::from(ckb_client.get_tip_header()?).epoch();
+        let expired = self.expired(tip_epoch);
+        if !expired.is_empty() {
+            let builder = ChequeWithdrawBuilder::new(
+                expired.clone(),
+                self.sender_lock_script.clone(),
+                self.acp_script_id.clone(),
+            );
+            let (tx, _) = builder.build_unlocked(
+                cell_collector,
+                cell_dep_resolver,
+                header_dep_resolver,
+                tx_dep_provider,
+                balancer,
+                unlockers,
+            )?;
+            let tx_hash = ckb_client.send_transaction(tx.data().into(), None)?;
+            let expired: Vec<_> = expired
+                .into_iter()
+                .map(json_types::OutPoint::from)
+                .collect();
+            for cheque in &mut self.cheques {
+                if expired.contains(&cheque.out_point) {
+                    cheque.status = ChequeWatchStatus::Withdrawing {
+                        tx_hash: tx_hash.clone(),
+                    };
+                }
+            }
+            self.save()?;
+            report.withdraw_tx = Some(tx_hash);
+        }
+        Ok(report)
+    }
+
+    /// Poll every `interval` until `stop` is set or an error occurs, run it
+    /// in a background thread.
+    #[allow(clippy::too_many_arguments)]
+    pub fn watch(
+        &mut self,
+        ckb_client: &CkbRpcClient,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+        balancer: &CapacityBalancer,
+        unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+        interval: Duration,
+        stop: &AtomicBool,
+    ) -> Result<(), ChequeWatchError> {
+        while !stop.load(Ordering::SeqCst) {
+            self.poll(
+                ckb_client,
+                cell_collector,
+                cell_dep_resolver,
+                header_dep_resolver,
+                tx_dep_provider,
+                balancer,
+                unlockers,
+            )?;
+            thread::sleep(interval);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::{bytes::Bytes, h256, prelude::*};
+
+    #[test]
+    fn test_cheque_watcher_state() {
+        let start = EpochNumberWithFraction::new(10, 500, 1000);
+        assert!(!epochs_elapsed(
+            start,
+            EpochNumberWithFraction::new(16, 499, 1000),
+            CHEQUE_CLAIM_EPOCHS
+        ));
+        assert!(epochs_elapsed(
+            start,
+            EpochNumberWithFraction::new(16, 900, 1800),
+            CHEQUE_CLAIM_EPOCHS
+        ));
+
+        let sender = Script::new_builder()
+            .args(Bytes::from(vec![1u8; 20]).pack())
+            .build();
+        let out_point = OutPoint::new(h256!("0x1234").pack(), 0);
+        let path = std::env::temp_dir().join(format!(
+            "ckb-sdk-cheque-watcher-{}.json",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+
+        let mut watcher = ChequeWatcher::open(&path, sender.clone(), None).unwrap();
+        watcher.track(out_point.clone()).unwrap();
+        watcher.track(out_point.clone()).unwrap();
+        assert_eq!(
+            watcher.cheques(),
+            vec![(out_point.clone(), ChequeWatchStatus::Pending)]
+        );
+        // not committed yet
+        assert!(watcher
+            .expired(EpochNumberWithFraction::new(100, 0, 1))
+            .is_empty());
+        watcher.cheques[0].created_epoch = Some(start.full_value());
+        watcher.save().unwrap();
+
+        let watcher = ChequeWatcher::open(&path, sender, None).unwrap();
+        assert!(watcher
+            .expired(EpochNumberWithFraction::new(15, 0, 1))
+            .is_empty());
+        assert_eq!(
+            watcher.expired(EpochNumberWithFraction::new(17, 0, 1)),
+            vec![out_point]
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
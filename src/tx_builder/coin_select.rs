@@ -0,0 +1,254 @@
+//! Branch-and-bound coin selection.
+//!
+//! Try to find a set of capacity provider cells whose capacity matches the
+//! required capacity (outputs + fee) exactly, within a tolerance. The excess
+//! capacity goes to the fee and no change cell is created, which makes the
+//! transaction smaller and avoids growing the on-chain state.
+use ckb_types::{core::TransactionView, packed::CellInput, prelude::*};
+
+use super::{
+    tx_fee, BalanceTxCapacityError, CapacityBalancer, TransactionFeeError, CELL_DEP_SIZE,
+    CELL_INPUT_SIZE, WITNESS_HEADER_SIZE,
+};
+use crate::traits::{
+    CellCollector, CellDepResolver, CellQueryOptions, HeaderDepResolver, LiveCell,
+    TransactionDependencyProvider, ValueRangeOption,
+};
+
+/// Branch-and-bound selector, search the subsets of the candidates (largest
+/// first) for an exact match.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct BranchAndBoundSelector {
+    /// The max excess capacity (in shannons) allowed to be paid as fee
+    pub tolerance: u64,
+    /// The max search steps, the search fails when exceeded
+    pub max_tries: usize,
+}
+
+impl Default for BranchAndBoundSelector {
+    fn default() -> BranchAndBoundSelector {
+        BranchAndBoundSelector {
+            tolerance: 0,
+            max_tries: 100_000,
+        }
+    }
+}
+
+impl BranchAndBoundSelector {
+    pub fn new(tolerance: u64) -> BranchAndBoundSelector {
+        BranchAndBoundSelector {
+            tolerance,
+            ..Default::default()
+        }
+    }
+
+    /// Select the values whose sum is in `[target, target + tolerance]`,
+    /// return the indices of the selected values.
+    pub fn select(&self, values: &[u64], target: u64) -> Option<Vec<usize>> {
+        let mut order: Vec<usize> = (0..values.len()).collect();
+        order.sort_by(|a, b| values[*b].cmp(&values[*a]));
+        // remaining[i] is the sum of values[order[i..]]
+        let mut remaining = vec![0u64; order.len() + 1];
+        for i in (0..order.len()).rev() {
+            remaining[i] = remaining[i + 1].saturating_add(values[order[i]]);
+        }
+        let mut search = Search {
+            values,
+            order: &order,
+            remaining: &remaining,
+            target,
+            upper: target.saturating_add(self.tolerance),
+            tries: 0,
+            max_tries: self.max_tries,
+            selected: Vec::new(),
+        };
+        if search.search(0, 0) {
+            let mut selected: Vec<usize> = search.selected.iter().map(|i| order[*i]).collect();
+            selected.sort_unstable();
+            Some(selected)
+        } else {
+            None
+        }
+    }
+}
+
+struct Search<'a> {
+    values: &'a [u64],
+    order: &'a [usize],
+    remaining: &'a [u64],
+    target: u64,
+    upper: u64,
+    tries: usize,
+    max_tries: usize,
+    /// The selected positions in `order`
+    selected: Vec<usize>,
+}
+
+impl<'a> Search<'a> {
+    fn search(&mut self, pos: usize, current: u64) -> bool {
+        self.tries += 1;
+        if self.tries > self.max_tries || current > self.upper {
+            return false;
+        }
+        if current >= self.target {
+            return true;
+        }
+        if pos == self.order.len() || current.saturating_add(self.remaining[pos]) < self.target {
+            return false;
+        }
+        let value = self.values[self.order[pos]];
+        self.selected.push(pos);
+        if self.search(pos + 1, current.saturating_add(value)) {
+            return true;
+        }
+        self.selected.pop();
+        // skip the values equal to the excluded one, they lead to the same
+        // subsets
+        let mut next = pos + 1;
+        while next < self.order.len() && self.values[self.order[next]] == value {
+            next += 1;
+        }
+        self.search(next, current)
+    }
+}
+
+/// Try to balance the transaction with the first capacity provider of the
+/// balancer without creating a change cell.
+///
+/// Return `None` if no exact match is found, the caller should fall back to
+/// the normal balancing (e.g. [`CapacityBalancer::balance_tx_capacity`]). The
+/// selected cells are locked in the cell collector at `tip_block_number`.
+/// All the live cells of the provider are loaded as candidates.
+#[allow(clippy::too_many_arguments)]
+pub fn balance_tx_capacity_exact(
+    tx: &TransactionView,
+    balancer: &CapacityBalancer,
+    selector: &BranchAndBoundSelector,
+    cell_collector: &mut dyn CellCollector,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+    cell_dep_resolver: &dyn CellDepResolver,
+    header_dep_resolver: &dyn HeaderDepResolver,
+    tip_block_number: u64,
+) -> Result<Option<TransactionView>, BalanceTxCapacityError> {
+    let (lock_script, placeholder_witness, since_source) =
+        match balancer.capacity_provider.lock_scripts.first() {
+            Some(provider) => provider,
+            None => return Err(BalanceTxCapacityError::EmptyCapacityProvider),
+        };
+    if tx.witnesses().len() > tx.inputs().len() {
+        return Ok(None);
+    }
+    let mut has_provider = false;
+    for input in tx.inputs() {
+        if tx_dep_provider.get_cell(&input.previous_output())?.lock() == *lock_script {
+            has_provider = true;
+        }
+    }
+    let cell_dep = cell_dep_resolver
+        .resolve(lock_script)
+        .ok_or_else(|| BalanceTxCapacityError::ResolveCellDepFailed(lock_script.clone()))?;
+    let has_cell_dep = tx.cell_deps().into_iter().any(|item| item == cell_dep);
+
+    // The size of the transaction with the padded witnesses, the placeholder
+    // witness and the cell dep, but without the new inputs.
+    let padding = (tx.inputs().len() - tx.witnesses().len()) as u64 * WITNESS_HEADER_SIZE;
+    let mut base_size = tx.data().as_reader().serialized_size_in_block() as u64 + padding;
+    if !has_provider {
+        base_size += placeholder_witness.as_slice().len() as u64;
+    }
+    if !has_cell_dep {
+        base_size += CELL_DEP_SIZE;
+    }
+    let input_size = CELL_INPUT_SIZE + WITNESS_HEADER_SIZE;
+    // NOTE: +1 is for `FeeRate::fee` round
+    let input_fee = balancer.fee_rate.fee(input_size).as_u64() + 1;
+    let base_fee = balancer.fee_rate.fee(base_size).as_u64() + 1;
+    let target = match tx_fee(tx.clone(), tx_dep_provider, header_dep_resolver) {
+        Ok(fee) => base_fee.saturating_sub(fee),
+        Err(TransactionFeeError::CapacityOverflow(delta)) => delta + base_fee,
+        Err(err) => return Err(err.into()),
+    };
+    if target == 0 {
+        return Ok(None);
+    }
+
+    let mut query = CellQueryOptions::new_lock(lock_script.clone());
+    query.secondary_script_len_range = Some(ValueRangeOption::new_exact(0));
+    query.data_len_range = Some(ValueRangeOption::new_exact(0));
+    query.min_total_capacity = u64::MAX;
+    let (cells, _) = cell_collector.collect_live_cells(&query, false)?;
+    let cells: Vec<LiveCell> = cells
+        .into_iter()
+        .filter(|cell| {
+            tx.input_pts_iter()
+                .all(|out_point| out_point != cell.out_point)
+        })
+        .collect();
+    let values: Vec<u64> = cells
+        .iter()
+        .map(|cell| Unpack::<u64>::unpack(&cell.output.capacity()).saturating_sub(input_fee))
+        .collect();
+    let selected = match selector.select(&values, target) {
+        Some(selected) => selected,
+        None => return Ok(None),
+    };
+
+    let mut inputs = Vec::with_capacity(selected.len());
+    for idx in &selected {
+        let since = since_source.input_since(&cells[*idx])?;
+        inputs.push(CellInput::new(cells[*idx].out_point.clone(), since));
+    }
+    let mut witnesses: Vec<_> = tx.witnesses().into_iter().collect();
+    witnesses.resize(tx.inputs().len(), Default::default());
+    for i in 0..selected.len() {
+        if i == 0 && !has_provider {
+            witnesses.push(placeholder_witness.as_bytes().pack());
+        } else {
+            witnesses.push(Default::default());
+        }
+    }
+    let mut builder = tx
+        .as_advanced_builder()
+        .inputs(inputs)
+        .set_witnesses(witnesses);
+    if !has_cell_dep {
+        builder = builder.cell_dep(cell_dep);
+    }
+    let new_tx = builder.build();
+
+    // Check the actual fee, the excess includes the `FeeRate::fee` round of
+    // every estimated part.
+    let tx_size = new_tx.data().as_reader().serialized_size_in_block();
+    let min_fee = balancer.fee_rate.fee(tx_size as u64).as_u64();
+    let fee = tx_fee(new_tx.clone(), tx_dep_provider, header_dep_resolver)?;
+    if fee < min_fee || fee > min_fee + selector.tolerance + selected.len() as u64 + 1 {
+        return Ok(None);
+    }
+    balancer.check_fee_limits(fee, tx_size)?;
+    for idx in selected {
+        cell_collector.lock_cell(cells[idx].out_point.clone(), tip_block_number)?;
+    }
+    Ok(Some(new_tx))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_branch_and_bound_select() {
+        let selector = BranchAndBoundSelector::default();
+        assert_eq!(selector.select(&[50, 30, 20, 10], 60), Some(vec![0, 3]));
+        assert_eq!(selector.select(&[50, 30, 20, 10], 35), None);
+        assert_eq!(selector.select(&[50, 30, 20, 10], 200), None);
+        assert_eq!(
+            BranchAndBoundSelector::new(5).select(&[50, 30, 20, 10], 35),
+            Some(vec![1, 3])
+        );
+        let selector = BranchAndBoundSelector {
+            tolerance: 0,
+            max_tries: 1,
+        };
+        assert_eq!(selector.select(&[50, 30, 20, 10], 60), None);
+    }
+}
@@ -3,15 +3,22 @@ use std::collections::HashSet;
 use anyhow::anyhow;
 use ckb_types::{
     bytes::Bytes,
-    core::{Capacity, FeeRate, ScriptHashType, TransactionBuilder, TransactionView},
+    core::{
+        Capacity, EpochNumberWithFraction, FeeRate, HeaderView, ScriptHashType, TransactionBuilder,
+        TransactionView,
+    },
     packed::{CellInput, CellOutput, OutPoint, Script, WitnessArgs},
     prelude::*,
 };
 
-use super::{TxBuilder, TxBuilderError};
+use super::{
+    tx_fee, BalanceTxCapacityError, CapacityBalancer, TransactionFeeError, TxBuilder,
+    TxBuilderError,
+};
 use crate::constants::DAO_TYPE_HASH;
 use crate::traits::{
-    CellCollector, CellDepResolver, HeaderDepResolver, TransactionDependencyProvider,
+    CellCollector, CellDepResolver, CellQueryOptions, HeaderDepResolver, LiveCell,
+    TransactionDependencyProvider, ValueRangeOption,
 };
 use crate::types::{Since, SinceType};
 use crate::util::{calculate_dao_maximum_withdraw4, minimal_unlock_point};
@@ -175,7 +182,9 @@ impl TxBuilder for DaoPrepareBuilder {
             let output_data = Bytes::from(deposit_header.number().to_le_bytes().to_vec());
 
             cell_deps.insert(input_lock_cell_dep);
-            header_deps.push(deposit_header.hash());
+            if !header_deps.contains(&deposit_header.hash()) {
+                header_deps.push(deposit_header.hash());
+            }
             inputs.push(input.clone());
             outputs.push(output);
             outputs_data.push(output_data.pack());
@@ -417,3 +426,476 @@ impl TxBuilder for DaoWithdrawBuilder {
             .build())
     }
 }
+
+fn dao_type_script() -> Script {
+    Script::new_builder()
+        .code_hash(DAO_TYPE_HASH.pack())
+        .hash_type(ScriptHashType::Type.into())
+        .build()
+}
+
+/// Collect all dao cells (deposited and prepared) of a lock script
+fn collect_dao_cells(
+    cell_collector: &mut dyn CellCollector,
+    lock_script: &Script,
+) -> Result<Vec<LiveCell>, TxBuilderError> {
+    let mut query = CellQueryOptions::new_lock(lock_script.clone());
+    query.secondary_script = Some(dao_type_script());
+    query.data_len_range = Some(ValueRangeOption::new_exact(8));
+    query.min_total_capacity = u64::MAX;
+    let (cells, _) = cell_collector.collect_live_cells(&query, true)?;
+    Ok(cells)
+}
+
+/// A group of dao deposit cells with the same capacity
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct DaoDenomination {
+    pub capacity: u64,
+    pub count: usize,
+}
+
+impl DaoDenomination {
+    pub fn new(capacity: u64, count: usize) -> DaoDenomination {
+        DaoDenomination { capacity, count }
+    }
+}
+
+/// Build a Nervos DAO deposit transaction deposit into multiple cells of
+/// configurable denominations.
+#[derive(Debug, Clone)]
+pub struct DaoBatchDepositBuilder {
+    /// The lock script of all deposit cells
+    pub lock_script: Script,
+    pub denominations: Vec<DaoDenomination>,
+}
+
+impl DaoBatchDepositBuilder {
+    pub fn new(lock_script: Script, denominations: Vec<DaoDenomination>) -> DaoBatchDepositBuilder {
+        DaoBatchDepositBuilder {
+            lock_script,
+            denominations,
+        }
+    }
+
+    /// Split `total_capacity` into cells of `denomination` capacity, the
+    /// remainder is deposited into one more cell if it is enough to hold a
+    /// dao cell, otherwise it is not deposited.
+    pub fn new_split(
+        lock_script: Script,
+        total_capacity: u64,
+        denomination: u64,
+    ) -> Result<DaoBatchDepositBuilder, TxBuilderError> {
+        if denomination == 0 {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "dao deposit denomination can not be 0"
+            )));
+        }
+        let mut builder = DaoBatchDepositBuilder::new(lock_script, Vec::new());
+        let count = (total_capacity / denomination) as usize;
+        if count > 0 {
+            builder
+                .denominations
+                .push(DaoDenomination::new(denomination, count));
+        }
+        let remainder = total_capacity % denomination;
+        if remainder > 0 && remainder >= builder.min_deposit_capacity() {
+            builder
+                .denominations
+                .push(DaoDenomination::new(remainder, 1));
+        }
+        Ok(builder)
+    }
+
+    /// The minimal capacity of a dao deposit cell
+    pub fn min_deposit_capacity(&self) -> u64 {
+        CellOutput::new_builder()
+            .lock(self.lock_script.clone())
+            .type_(Some(dao_type_script()).pack())
+            .build()
+            .occupied_capacity(Capacity::bytes(8).unwrap())
+            .unwrap()
+            .as_u64()
+    }
+
+    /// The deposit receivers of all denominations
+    pub fn receivers(&self) -> Vec<DaoDepositReceiver> {
+        self.denominations
+            .iter()
+            .flat_map(|denomination| {
+                (0..denomination.count).map(move |_| {
+                    DaoDepositReceiver::new(self.lock_script.clone(), denomination.capacity)
+                })
+            })
+            .collect()
+    }
+}
+
+impl TxBuilder for DaoBatchDepositBuilder {
+    fn build_base(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        let min_capacity = self.min_deposit_capacity();
+        if let Some(denomination) = self
+            .denominations
+            .iter()
+            .find(|denomination| denomination.capacity < min_capacity)
+        {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "dao deposit capacity too small, min: {}, actual: {}",
+                min_capacity,
+                denomination.capacity
+            )));
+        }
+        DaoDepositBuilder::new(self.receivers()).build_base(
+            cell_collector,
+            cell_dep_resolver,
+            header_dep_resolver,
+            tx_dep_provider,
+        )
+    }
+}
+
+/// Build a Nervos DAO withdraw Phase 1 transaction prepare all deposited cells
+/// of a lock script, the header deps are resolved automatically.
+#[derive(Debug, Clone)]
+pub struct DaoBatchPrepareBuilder {
+    /// The lock script of the deposited cells
+    pub lock_script: Script,
+    /// Replace the lock script of prepared cells if given
+    pub new_lock_script: Option<Script>,
+}
+
+impl DaoBatchPrepareBuilder {
+    pub fn new(lock_script: Script) -> DaoBatchPrepareBuilder {
+        DaoBatchPrepareBuilder {
+            lock_script,
+            new_lock_script: None,
+        }
+    }
+}
+
+impl TxBuilder for DaoBatchPrepareBuilder {
+    fn build_base(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        let items: Vec<_> = collect_dao_cells(cell_collector, &self.lock_script)?
+            .into_iter()
+            .filter(|cell| cell.output_data.as_ref() == [0u8; 8])
+            .map(|cell| DaoPrepareItem {
+                input: CellInput::new(cell.out_point, 0),
+                lock_script: self.new_lock_script.clone(),
+            })
+            .collect();
+        if items.is_empty() {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "no deposited cell found"
+            )));
+        }
+        DaoPrepareBuilder::new(items).build_base(
+            cell_collector,
+            cell_dep_resolver,
+            header_dep_resolver,
+            tx_dep_provider,
+        )
+    }
+}
+
+/// Build a Nervos DAO withdraw Phase 2 transaction withdraw all matured
+/// prepared cells of a lock script, the header deps are resolved automatically.
+#[derive(Debug, Clone)]
+pub struct DaoBatchWithdrawBuilder {
+    /// The lock script of the prepared cells
+    pub lock_script: Script,
+    /// Only the prepared cells can be unlocked at this epoch are withdrawn
+    pub current_epoch: EpochNumberWithFraction,
+    /// The init witness of the first input, see [`DaoWithdrawItem::init_witness`]
+    pub init_witness: Option<WitnessArgs>,
+    pub receiver: DaoWithdrawReceiver,
+}
+
+impl DaoBatchWithdrawBuilder {
+    pub fn new(
+        lock_script: Script,
+        current_epoch: EpochNumberWithFraction,
+        receiver: DaoWithdrawReceiver,
+    ) -> DaoBatchWithdrawBuilder {
+        DaoBatchWithdrawBuilder {
+            lock_script,
+            current_epoch,
+            init_witness: None,
+            receiver,
+        }
+    }
+}
+
+impl TxBuilder for DaoBatchWithdrawBuilder {
+    fn build_base(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        let mut items = Vec::new();
+        for cell in collect_dao_cells(cell_collector, &self.lock_script)? {
+            if cell.output_data.as_ref() == [0u8; 8] {
+                continue;
+            }
+            let tx_hash = cell.out_point.tx_hash();
+            let prepare_header = header_dep_resolver
+                .resolve_by_tx(&tx_hash)
+                .map_err(TxBuilderError::Other)?
+                .ok_or_else(|| TxBuilderError::ResolveHeaderDepByTxHashFailed(tx_hash.clone()))?;
+            let deposit_number = {
+                let mut number_bytes = [0u8; 8];
+                number_bytes.copy_from_slice(cell.output_data.as_ref());
+                u64::from_le_bytes(number_bytes)
+            };
+            let deposit_header = header_dep_resolver
+                .resolve_by_number(deposit_number)
+                .map_err(TxBuilderError::Other)?
+                .ok_or(TxBuilderError::ResolveHeaderDepByNumberFailed(
+                    deposit_number,
+                ))?;
+            let unlock_point = minimal_unlock_point(&deposit_header, &prepare_header);
+            if unlock_point.to_rational() > self.current_epoch.to_rational() {
+                continue;
+            }
+            let init_witness = if items.is_empty() {
+                self.init_witness.clone()
+            } else {
+                None
+            };
+            items.push(DaoWithdrawItem::new(cell.out_point, init_witness));
+        }
+        if items.is_empty() {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "no matured prepared cell found at epoch {}",
+                self.current_epoch
+            )));
+        }
+        DaoWithdrawBuilder::new(items, self.receiver.clone()).build_base(
+            cell_collector,
+            cell_dep_resolver,
+            header_dep_resolver,
+            tx_dep_provider,
+        )
+    }
+}
+
+/// Use the matured prepared DAO cells of the capacity provider lock scripts to
+/// balance the transaction, see [`CapacityBalancer::dao_provider`].
+///
+/// The withdraw value of a cell is credited by
+/// [`calculate_dao_maximum_withdraw4`], the header deps, the DAO cell dep and
+/// the header dep index in the `input_type` of the witness are added
+/// automatically. The plain cells are still used for the capacity left.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct DaoCapacityProvider {
+    /// Only the prepared cells can be unlocked at this epoch are used
+    pub current_epoch: EpochNumberWithFraction,
+    /// The tip block number, the selected cells are locked in the cell
+    /// collector at this number.
+    pub tip_block_number: u64,
+}
+
+impl DaoCapacityProvider {
+    pub fn new(
+        current_epoch: EpochNumberWithFraction,
+        tip_block_number: u64,
+    ) -> DaoCapacityProvider {
+        DaoCapacityProvider {
+            current_epoch,
+            tip_block_number,
+        }
+    }
+
+    /// Add matured prepared DAO cells to the transaction until the withdraw
+    /// capacity covers the outputs and the fee, return the transaction
+    /// unchanged if no capacity is needed.
+    pub(crate) fn add_inputs(
+        &self,
+        tx: &TransactionView,
+        balancer: &CapacityBalancer,
+        cell_collector: &mut dyn CellCollector,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+        cell_dep_resolver: &dyn CellDepResolver,
+        header_dep_resolver: &dyn HeaderDepResolver,
+    ) -> Result<TransactionView, BalanceTxCapacityError> {
+        if !need_more_capacity(tx, balancer, tx_dep_provider, header_dep_resolver)? {
+            return Ok(tx.clone());
+        }
+        if tx.witnesses().len() > tx.inputs().len() {
+            return Err(BalanceTxCapacityError::InvalidWitnessArgs(anyhow!(
+                "the witnesses count exceed the inputs count"
+            )));
+        }
+        #[allow(clippy::mutable_key_type)]
+        let mut input_locks = HashSet::new();
+        for out_point in tx.input_pts_iter() {
+            input_locks.insert(tx_dep_provider.get_cell(&out_point)?.lock());
+        }
+        let mut new_tx = tx.clone();
+        let mut selected = Vec::new();
+        #[allow(clippy::mutable_key_type)]
+        let mut visited_locks = HashSet::new();
+        for (lock_script, placeholder_witness, _) in &balancer.capacity_provider.lock_scripts {
+            if !visited_locks.insert(lock_script.clone()) {
+                continue;
+            }
+            let mut query = CellQueryOptions::new_lock(lock_script.clone());
+            query.secondary_script = Some(dao_type_script());
+            query.data_len_range = Some(ValueRangeOption::new_exact(8));
+            query.min_total_capacity = u64::MAX;
+            let (cells, _) = cell_collector.collect_live_cells(&query, false)?;
+            for cell in cells {
+                if cell.output_data.as_ref() == [0u8; 8]
+                    || new_tx
+                        .input_pts_iter()
+                        .any(|out_point| out_point == cell.out_point)
+                {
+                    continue;
+                }
+                let (deposit_header, prepare_header) =
+                    resolve_dao_headers(&cell, header_dep_resolver)?;
+                let unlock_point = minimal_unlock_point(&deposit_header, &prepare_header);
+                if unlock_point.to_rational() > self.current_epoch.to_rational() {
+                    continue;
+                }
+                let placeholder_witness = if input_locks.insert(lock_script.clone()) {
+                    Some(placeholder_witness)
+                } else {
+                    None
+                };
+                new_tx = add_withdraw_input(
+                    &new_tx,
+                    &cell,
+                    &deposit_header,
+                    &prepare_header,
+                    placeholder_witness,
+                    cell_dep_resolver,
+                )?;
+                selected.push(cell.out_point);
+                if !need_more_capacity(&new_tx, balancer, tx_dep_provider, header_dep_resolver)? {
+                    break;
+                }
+            }
+            if !need_more_capacity(&new_tx, balancer, tx_dep_provider, header_dep_resolver)? {
+                break;
+            }
+        }
+        for out_point in selected {
+            cell_collector.lock_cell(out_point, self.tip_block_number)?;
+        }
+        Ok(new_tx)
+    }
+}
+
+fn need_more_capacity(
+    tx: &TransactionView,
+    balancer: &CapacityBalancer,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+    header_dep_resolver: &dyn HeaderDepResolver,
+) -> Result<bool, BalanceTxCapacityError> {
+    let tx_size = tx.data().as_reader().serialized_size_in_block();
+    let min_fee = balancer.fee_rate.fee(tx_size as u64).as_u64();
+    match tx_fee(tx.clone(), tx_dep_provider, header_dep_resolver) {
+        Ok(fee) => Ok(fee < min_fee),
+        Err(TransactionFeeError::CapacityOverflow(_)) => Ok(true),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Resolve the deposit header and the prepare header of a prepared cell
+fn resolve_dao_headers(
+    cell: &LiveCell,
+    header_dep_resolver: &dyn HeaderDepResolver,
+) -> Result<(HeaderView, HeaderView), BalanceTxCapacityError> {
+    let tx_hash = cell.out_point.tx_hash();
+    let prepare_header = header_dep_resolver
+        .resolve_by_tx(&tx_hash)
+        .map_err(BalanceTxCapacityError::HeaderDep)?
+        .ok_or_else(|| {
+            BalanceTxCapacityError::HeaderDep(anyhow!(
+                "resolve prepare header by transaction hash failed: {}",
+                tx_hash
+            ))
+        })?;
+    let deposit_number = {
+        let mut number_bytes = [0u8; 8];
+        number_bytes.copy_from_slice(cell.output_data.as_ref());
+        u64::from_le_bytes(number_bytes)
+    };
+    let deposit_header = header_dep_resolver
+        .resolve_by_number(deposit_number)
+        .map_err(BalanceTxCapacityError::HeaderDep)?
+        .ok_or_else(|| {
+            BalanceTxCapacityError::HeaderDep(anyhow!(
+                "resolve deposit header by block number failed: {}",
+                deposit_number
+            ))
+        })?;
+    Ok((deposit_header, prepare_header))
+}
+
+/// Append a prepared cell as a withdraw input
+fn add_withdraw_input(
+    tx: &TransactionView,
+    cell: &LiveCell,
+    deposit_header: &HeaderView,
+    prepare_header: &HeaderView,
+    placeholder_witness: Option<&WitnessArgs>,
+    cell_dep_resolver: &dyn CellDepResolver,
+) -> Result<TransactionView, BalanceTxCapacityError> {
+    let mut builder = tx.as_advanced_builder();
+    for script in [dao_type_script(), cell.output.lock()] {
+        let cell_dep = cell_dep_resolver
+            .resolve(&script)
+            .ok_or(BalanceTxCapacityError::ResolveCellDepFailed(script))?;
+        if tx.cell_deps().into_iter().all(|item| item != cell_dep) {
+            builder = builder.cell_dep(cell_dep);
+        }
+    }
+    let mut header_deps: Vec<_> = tx.header_deps().into_iter().collect();
+    let header_idx = match header_deps
+        .iter()
+        .position(|hash| *hash == deposit_header.hash())
+    {
+        Some(idx) => idx,
+        None => {
+            header_deps.push(deposit_header.hash());
+            header_deps.len() - 1
+        }
+    };
+    if !header_deps.contains(&prepare_header.hash()) {
+        header_deps.push(prepare_header.hash());
+    }
+    let unlock_point = minimal_unlock_point(deposit_header, prepare_header);
+    let since = Since::new(
+        SinceType::EpochNumberWithFraction,
+        unlock_point.full_value(),
+        false,
+    );
+    let witness = placeholder_witness
+        .cloned()
+        .map(|witness| witness.as_builder())
+        .unwrap_or_else(WitnessArgs::new_builder)
+        .input_type(Some(Bytes::from((header_idx as u64).to_le_bytes().to_vec())).pack())
+        .build();
+    let mut witnesses: Vec<_> = tx.witnesses().into_iter().collect();
+    witnesses.resize(tx.inputs().len(), Default::default());
+    witnesses.push(witness.as_bytes().pack());
+    Ok(builder
+        .set_header_deps(header_deps)
+        .input(CellInput::new(cell.out_point.clone(), since.value()))
+        .set_witnesses(witnesses)
+        .build())
+}
@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use ckb_types::{
+    bytes::Bytes,
+    core::{Capacity, TransactionBuilder, TransactionView},
+    packed::{CellOutput, Script},
+    prelude::*,
+};
+
+use super::{CapacityBalancer, TxBuilder, TxBuilderError};
+use crate::traits::{
+    CellCollector, CellDepResolver, HeaderDepResolver, TransactionDependencyProvider,
+};
+use crate::types::{ScriptGroup, ScriptId};
+use crate::unlock::ScriptUnlocker;
+
+/// The default max total output data size of a transaction, leave some room
+/// for inputs and witnesses under the block bytes limit.
+pub const DEFAULT_MAX_TX_DATA_SIZE: usize = 500 * 1024;
+
+/// Store arbitrary data on chain, every data cell has exactly the occupied
+/// capacity.
+///
+/// If `chunk_size` is given, the data is split into chunks of that size, one
+/// cell per chunk. The chunks are packed into transactions in order, the
+/// total data size of a transaction is limited by `max_tx_data_size`, use
+/// [`DataCellBuilder::build_txs`] when the data needs more than one
+/// transaction.
+#[derive(Debug, Clone)]
+pub struct DataCellBuilder {
+    /// The lock script of the data cells
+    pub lock_script: Script,
+    /// The data to store
+    pub data: Bytes,
+    /// Split the data into chunks of this size
+    pub chunk_size: Option<usize>,
+    /// The max total output data size of a transaction
+    pub max_tx_data_size: usize,
+}
+
+impl DataCellBuilder {
+    pub fn new(lock_script: Script, data: Bytes) -> DataCellBuilder {
+        DataCellBuilder {
+            lock_script,
+            data,
+            chunk_size: None,
+            max_tx_data_size: DEFAULT_MAX_TX_DATA_SIZE,
+        }
+    }
+
+    /// The data of every cell in order
+    pub fn chunks(&self) -> Vec<Bytes> {
+        match self.chunk_size {
+            Some(chunk_size) if chunk_size > 0 && self.data.len() > chunk_size => {
+                (0..self.data.len())
+                    .step_by(chunk_size)
+                    .map(|start| {
+                        let end = std::cmp::min(start + chunk_size, self.data.len());
+                        self.data.slice(start..end)
+                    })
+                    .collect()
+            }
+            _ => vec![self.data.clone()],
+        }
+    }
+
+    /// The chunks of every transaction in order
+    pub fn batches(&self) -> Result<Vec<Vec<Bytes>>, TxBuilderError> {
+        if self.data.is_empty() {
+            return Err(TxBuilderError::InvalidParameter(anyhow!("empty data")));
+        }
+        if self.chunk_size == Some(0) {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "chunk size must be positive"
+            )));
+        }
+        let mut batches: Vec<Vec<Bytes>> = Vec::new();
+        let mut batch_size = 0;
+        for chunk in self.chunks() {
+            if chunk.len() > self.max_tx_data_size {
+                return Err(TxBuilderError::InvalidParameter(anyhow!(
+                    "chunk size {} exceeds max transaction data size {}",
+                    chunk.len(),
+                    self.max_tx_data_size
+                )));
+            }
+            match batches.last_mut() {
+                Some(batch) if batch_size + chunk.len() <= self.max_tx_data_size => {
+                    batch_size += chunk.len();
+                    batch.push(chunk);
+                }
+                _ => {
+                    batch_size = chunk.len();
+                    batches.push(vec![chunk]);
+                }
+            }
+        }
+        Ok(batches)
+    }
+
+    /// The total capacity occupied by all data cells
+    pub fn occupied_capacity(&self) -> u64 {
+        self.chunks()
+            .iter()
+            .map(|chunk| self.build_output(chunk).1)
+            .sum()
+    }
+
+    fn build_output(&self, chunk: &Bytes) -> (CellOutput, u64) {
+        let output = CellOutput::new_builder()
+            .lock(self.lock_script.clone())
+            .build_exact_capacity(Capacity::bytes(chunk.len()).expect("data capacity"))
+            .expect("occupied capacity");
+        let capacity: u64 = output.capacity().unpack();
+        (output, capacity)
+    }
+
+    fn build_batch(&self, chunks: &[Bytes]) -> TransactionView {
+        let mut outputs = Vec::new();
+        let mut outputs_data = Vec::new();
+        for chunk in chunks {
+            outputs.push(self.build_output(chunk).0);
+            outputs_data.push(chunk.pack());
+        }
+        TransactionBuilder::default()
+            .set_outputs(outputs)
+            .set_outputs_data(outputs_data)
+            .build()
+    }
+
+    /// Build all the transactions to store the data, each one is balanced and
+    /// unlocked separately. The transactions are independent, they can be
+    /// sent in any order.
+    pub fn build_txs(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+        balancer: &CapacityBalancer,
+        unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+    ) -> Result<Vec<(TransactionView, Vec<ScriptGroup>)>, TxBuilderError> {
+        let mut txs = Vec::new();
+        for batch in self.batches()? {
+            let base_tx = self.build_batch(&batch);
+            let builder = BaseTxBuilder(base_tx);
+            txs.push(builder.build_unlocked(
+                cell_collector,
+                cell_dep_resolver,
+                header_dep_resolver,
+                tx_dep_provider,
+                balancer,
+                unlockers,
+            )?);
+        }
+        Ok(txs)
+    }
+}
+
+/// Return an already built base transaction
+struct BaseTxBuilder(TransactionView);
+
+impl TxBuilder for BaseTxBuilder {
+    fn build_base(
+        &self,
+        _cell_collector: &mut dyn CellCollector,
+        _cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        Ok(self.0.clone())
+    }
+}
+
+impl TxBuilder for DataCellBuilder {
+    fn build_base(
+        &self,
+        _cell_collector: &mut dyn CellCollector,
+        _cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        let batches = self.batches()?;
+        if batches.len() > 1 {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "data needs {} transactions, use build_txs instead",
+                batches.len()
+            )));
+        }
+        Ok(self.build_batch(&batches[0]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_data_cell_batches() {
+        let mut builder = DataCellBuilder::new(Script::default(), Bytes::from(vec![1u8; 1000]));
+        assert_eq!(builder.chunks().len(), 1);
+        assert_eq!(builder.batches().unwrap().len(), 1);
+
+        builder.chunk_size = Some(300);
+        builder.max_tx_data_size = 600;
+        let chunks = builder.chunks();
+        assert_eq!(
+            chunks.iter().map(|chunk| chunk.len()).collect::<Vec<_>>(),
+            vec![300, 300, 300, 100]
+        );
+        let batches = builder.batches().unwrap();
+        assert_eq!(
+            batches.iter().map(|batch| batch.len()).collect::<Vec<_>>(),
+            vec![2, 2]
+        );
+        // 8 (capacity) + 32 (code_hash) + 1 (hash_type) + data
+        assert_eq!(builder.occupied_capacity(), (41 * 4 + 1000) * 100_000_000);
+
+        builder.chunk_size = Some(700);
+        assert!(builder.batches().is_err());
+        builder.chunk_size = Some(0);
+        assert!(builder.batches().is_err());
+    }
+}
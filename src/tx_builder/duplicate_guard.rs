@@ -0,0 +1,277 @@
+//! Duplicate payment detection for payout pipelines.
+//!
+//! Before building a new payment, [`DuplicateGuard`] checks the local journal
+//! of recent outgoing payments and the chain, return the prior transaction
+//! hash if a likely duplicate exists.
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use ckb_jsonrpc_types::{self as json_types, Either, Status};
+use ckb_types::{
+    core::{BlockNumber, TransactionView},
+    packed::{Script, Transaction, TransactionReader},
+    prelude::*,
+    H256,
+};
+
+use crate::rpc::ckb_indexer::{Order, SearchKey};
+use crate::rpc::{CkbRpcClient, IndexerRpcClient};
+use crate::traits::{CellQueryOptions, ValueRangeOption};
+use crate::RpcError;
+
+/// The max recent transactions of the recipient to check on chain
+const CHAIN_CHECK_LIMIT: u32 = 100;
+
+#[derive(Error, Debug)]
+pub enum DuplicateGuardError {
+    #[error("journal error: `{0}`")]
+    Journal(anyhow::Error),
+
+    #[error("rpc error: `{0}`")]
+    Rpc(#[from] RpcError),
+}
+
+/// The key to identify a payment
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct PaymentKey {
+    pub recipient: Script,
+    /// The capacity paid to the recipient
+    pub amount: u64,
+    /// The external reference (e.g. withdrawal request id)
+    pub reference: String,
+}
+
+impl PaymentKey {
+    pub fn new(recipient: Script, amount: u64, reference: String) -> PaymentKey {
+        PaymentKey {
+            recipient,
+            amount,
+            reference,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct JournalEntry {
+    recipient: json_types::Script,
+    amount: json_types::Capacity,
+    reference: String,
+    tx_hash: H256,
+    /// Unix timestamp in seconds
+    timestamp: u64,
+}
+
+impl JournalEntry {
+    fn matches(&self, key: &PaymentKey) -> bool {
+        self.reference == key.reference
+            && self.amount.value() == key.amount
+            && Script::from(self.recipient.clone()) == key.recipient
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Detect duplicate payments in a time window.
+///
+/// The journal is persisted to `journal_path` (if given) on every record,
+/// entries older than the window are pruned.
+pub struct DuplicateGuard {
+    window: Duration,
+    journal_path: Option<PathBuf>,
+    entries: Vec<JournalEntry>,
+}
+
+impl DuplicateGuard {
+    /// Create a guard with in-memory journal
+    pub fn new(window: Duration) -> DuplicateGuard {
+        DuplicateGuard {
+            window,
+            journal_path: None,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Create a guard with journal persisted to a file
+    pub fn open<P: AsRef<Path>>(
+        journal_path: P,
+        window: Duration,
+    ) -> Result<DuplicateGuard, DuplicateGuardError> {
+        let journal_path = journal_path.as_ref().to_path_buf();
+        let entries = if journal_path.exists() {
+            let content = fs::read(&journal_path)
+                .map_err(|err| DuplicateGuardError::Journal(anyhow!(err)))?;
+            serde_json::from_slice(&content)
+                .map_err(|err| DuplicateGuardError::Journal(anyhow!(err)))?
+        } else {
+            Vec::new()
+        };
+        let mut guard = DuplicateGuard {
+            window,
+            journal_path: Some(journal_path),
+            entries,
+        };
+        guard.prune();
+        Ok(guard)
+    }
+
+    fn prune(&mut self) {
+        let min_timestamp = now_secs().saturating_sub(self.window.as_secs());
+        self.entries
+            .retain(|entry| entry.timestamp >= min_timestamp);
+    }
+
+    fn save(&self) -> Result<(), DuplicateGuardError> {
+        if let Some(path) = self.journal_path.as_ref() {
+            let content = serde_json::to_vec(&self.entries)
+                .map_err(|err| DuplicateGuardError::Journal(anyhow!(err)))?;
+            fs::write(path, content).map_err(|err| DuplicateGuardError::Journal(anyhow!(err)))?;
+        }
+        Ok(())
+    }
+
+    /// Record a sent payment
+    pub fn record(&mut self, key: &PaymentKey, tx_hash: H256) -> Result<(), DuplicateGuardError> {
+        self.prune();
+        self.entries.push(JournalEntry {
+            recipient: key.recipient.clone().into(),
+            amount: key.amount.into(),
+            reference: key.reference.clone(),
+            tx_hash,
+            timestamp: now_secs(),
+        });
+        self.save()
+    }
+
+    /// The recorded transactions of the payment in the window, newest first
+    pub fn journal_txs(&self, key: &PaymentKey) -> Vec<H256> {
+        let min_timestamp = now_secs().saturating_sub(self.window.as_secs());
+        self.entries
+            .iter()
+            .rev()
+            .filter(|entry| entry.timestamp >= min_timestamp && entry.matches(key))
+            .map(|entry| entry.tx_hash.clone())
+            .collect()
+    }
+
+    /// Check the journal, a recorded transaction is a duplicate unless the
+    /// node reports it as rejected or unknown (e.g. dropped from tx-pool).
+    pub fn check(
+        &self,
+        key: &PaymentKey,
+        ckb_client: &CkbRpcClient,
+    ) -> Result<Option<H256>, DuplicateGuardError> {
+        for tx_hash in self.journal_txs(key) {
+            let status = ckb_client
+                .get_transaction(tx_hash.clone())?
+                .map(|tx_with_status| tx_with_status.tx_status.status);
+            match status {
+                Some(Status::Pending) | Some(Status::Proposed) | Some(Status::Committed) => {
+                    return Ok(Some(tx_hash));
+                }
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// Check the journal, then the recent committed transactions of the
+    /// recipient since `from_block`. The external reference is not on chain,
+    /// so a committed transaction paying the same amount to the recipient is
+    /// treated as a likely duplicate.
+    pub fn check_with_chain(
+        &self,
+        key: &PaymentKey,
+        ckb_client: &CkbRpcClient,
+        indexer_client: &IndexerRpcClient,
+        from_block: BlockNumber,
+    ) -> Result<Option<H256>, DuplicateGuardError> {
+        if let Some(tx_hash) = self.check(key, ckb_client)? {
+            return Ok(Some(tx_hash));
+        }
+        let mut query = CellQueryOptions::new_lock(key.recipient.clone());
+        query.block_range = Some(ValueRangeOption::new(from_block, u64::MAX));
+        let page = indexer_client.get_transactions(
+            SearchKey::from(query),
+            Order::Desc,
+            CHAIN_CHECK_LIMIT.into(),
+            None,
+        )?;
+        let mut checked = HashSet::new();
+        for tx in page.objects {
+            let tx_hash = tx.tx_hash();
+            if !checked.insert(tx_hash.clone()) {
+                continue;
+            }
+            let tx_view = match ckb_client
+                .get_transaction(tx_hash.clone())?
+                .and_then(|tx_with_status| tx_with_status.transaction)
+            {
+                Some(tx) => match tx.inner {
+                    Either::Left(t) => Transaction::from(t.inner).into_view(),
+                    Either::Right(bytes) => TransactionReader::from_slice(bytes.as_bytes())
+                        .map(|reader| reader.to_entity().into_view())
+                        .map_err(|err| {
+                            RpcError::Other(anyhow!(
+                                "invalid molecule encoded TransactionView: {}",
+                                err
+                            ))
+                        })?,
+                },
+                None => continue,
+            };
+            if pays(&tx_view, key) {
+                return Ok(Some(tx_hash));
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn pays(tx: &TransactionView, key: &PaymentKey) -> bool {
+    tx.outputs().into_iter().any(|output| {
+        output.lock() == key.recipient && Unpack::<u64>::unpack(&output.capacity()) == key.amount
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::{bytes::Bytes, h256};
+
+    #[test]
+    fn test_duplicate_guard_journal() {
+        let recipient = Script::new_builder()
+            .args(Bytes::from(vec![1u8; 20]).pack())
+            .build();
+        let key = PaymentKey::new(recipient.clone(), 100, "order-1".to_string());
+        let tx_hash = h256!("0x9b3a1e4c2f5d6a7b8c9d0e1f2a3b4c5d6e7f8091a2b3c4d5e6f708192a3b4c5d");
+        let path = std::env::temp_dir().join(format!(
+            "ckb-sdk-duplicate-guard-{}.json",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+
+        let mut guard = DuplicateGuard::open(&path, Duration::from_secs(3600)).unwrap();
+        assert!(guard.journal_txs(&key).is_empty());
+        guard.record(&key, tx_hash.clone()).unwrap();
+        assert_eq!(guard.journal_txs(&key), vec![tx_hash.clone()]);
+
+        let other_key = PaymentKey::new(recipient, 100, "order-2".to_string());
+        assert!(guard.journal_txs(&other_key).is_empty());
+
+        let guard = DuplicateGuard::open(&path, Duration::from_secs(3600)).unwrap();
+        assert_eq!(guard.journal_txs(&key), vec![tx_hash]);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,267 @@
+//! UDT escrow pattern.
+//!
+//! The buyer locks UDT (and the cell capacity) into an escrow cell guarded by
+//! a multisig lock:
+//!   * Without arbiter: 2-of-2 multisig of buyer and seller.
+//!   * With arbiter: 2-of-3 multisig of buyer, seller and arbiter, so that the
+//!     arbiter can settle together with either side when they disagree.
+//!
+//! The flow is:
+//!   1. Buyer builds the escrow cell with [`EscrowCreateBuilder`].
+//!   2. Any party builds the settlement transaction with [`EscrowSettleBuilder`]:
+//!      `Release` sends the escrow cell to the seller, `Refund` sends it back to
+//!      the buyer.
+//!   3. Two of the participants sign the settlement transaction with
+//!      [`SecpMultisigUnlocker`](crate::unlock::SecpMultisigUnlocker) using the
+//!      config returned by [`EscrowConfig::multisig_config`].
+use std::collections::HashSet;
+
+use anyhow::anyhow;
+use ckb_types::{
+    core::{TransactionBuilder, TransactionView},
+    packed::{CellInput, OutPoint, Script},
+    prelude::*,
+    H160,
+};
+
+use super::{
+    udt::{UdtTargetReceiver, UdtTransferBuilder},
+    TransferAction, TxBuilder, TxBuilderError,
+};
+use crate::traits::{
+    CellCollector, CellDepResolver, HeaderDepResolver, TransactionDependencyProvider,
+};
+use crate::unlock::MultisigConfig;
+use crate::AddressPayload;
+
+/// The participants of an escrow, identified by their sighash lock args.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct EscrowConfig {
+    pub buyer: H160,
+    pub seller: H160,
+    pub arbiter: Option<H160>,
+}
+
+impl EscrowConfig {
+    /// Return an error if a participant appears more than once, a party
+    /// holding two of the keys could settle the escrow alone.
+    pub fn new(
+        buyer: H160,
+        seller: H160,
+        arbiter: Option<H160>,
+    ) -> Result<EscrowConfig, TxBuilderError> {
+        let config = EscrowConfig {
+            buyer,
+            seller,
+            arbiter,
+        };
+        config.check_participants()?;
+        Ok(config)
+    }
+
+    fn check_participants(&self) -> Result<(), TxBuilderError> {
+        let mut participants = vec![("buyer", &self.buyer), ("seller", &self.seller)];
+        if let Some(arbiter) = self.arbiter.as_ref() {
+            participants.push(("arbiter", arbiter));
+        }
+        for (idx, (role, arg)) in participants.iter().enumerate() {
+            if let Some((other_role, _)) = participants[..idx]
+                .iter()
+                .find(|(_, other_arg)| other_arg == arg)
+            {
+                return Err(TxBuilderError::InvalidParameter(anyhow!(
+                    "duplicated escrow participant: {:#x} is both {} and {}",
+                    arg,
+                    other_role,
+                    role
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// The multisig config guards the escrow cell.
+    pub fn multisig_config(&self) -> Result<MultisigConfig, TxBuilderError> {
+        self.check_participants()?;
+        let mut addresses = vec![self.buyer.clone(), self.seller.clone()];
+        if let Some(arbiter) = self.arbiter.as_ref() {
+            addresses.push(arbiter.clone());
+        }
+        MultisigConfig::new_with(addresses, 0, 2)
+            .map_err(|err| TxBuilderError::InvalidParameter(anyhow!(err)))
+    }
+
+    /// The lock script of the escrow cell.
+    pub fn lock_script(&self) -> Result<Script, TxBuilderError> {
+        Ok(Script::from(&self.multisig_config()?))
+    }
+
+    /// The sighash lock script of the buyer.
+    pub fn buyer_lock(&self) -> Script {
+        Script::from(&AddressPayload::from_pubkey_hash(self.buyer.clone()))
+    }
+
+    /// The sighash lock script of the seller.
+    pub fn seller_lock(&self) -> Script {
+        Script::from(&AddressPayload::from_pubkey_hash(self.seller.clone()))
+    }
+}
+
+/// Build a transaction lock buyer's UDT into an escrow cell. The buyer must
+/// own a UDT cell identified by `buyer_lock` and `type_script`.
+#[derive(Debug, Clone)]
+pub struct EscrowCreateBuilder {
+    pub config: EscrowConfig,
+    pub buyer_lock: Script,
+    /// The udt type script
+    pub type_script: Script,
+    pub amount: u128,
+    /// The capacity of the escrow cell, use the occupied capacity if `None`
+    pub capacity: Option<u64>,
+}
+
+impl EscrowCreateBuilder {
+    pub fn new(
+        config: EscrowConfig,
+        buyer_lock: Script,
+        type_script: Script,
+        amount: u128,
+    ) -> EscrowCreateBuilder {
+        EscrowCreateBuilder {
+            config,
+            buyer_lock,
+            type_script,
+            amount,
+            capacity: None,
+        }
+    }
+}
+
+impl TxBuilder for EscrowCreateBuilder {
+    fn build_base(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        if self.amount == 0 {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "escrow amount can not be 0"
+            )));
+        }
+        let mut receiver = UdtTargetReceiver::new(
+            TransferAction::Create,
+            self.config.lock_script()?,
+            self.amount,
+        );
+        receiver.capacity = self.capacity;
+        let builder = UdtTransferBuilder {
+            type_script: self.type_script.clone(),
+            sender: self.buyer_lock.clone(),
+            receivers: vec![receiver],
+        };
+        builder.build_base(
+            cell_collector,
+            cell_dep_resolver,
+            header_dep_resolver,
+            tx_dep_provider,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum EscrowSettlement {
+    /// Send the escrow cell to the seller
+    Release,
+    /// Send the escrow cell back to the buyer
+    Refund,
+}
+
+/// Build a transaction settle an escrow cell, the whole cell (capacity, type
+/// script and data) is moved to the sighash lock of the seller or the buyer
+/// in the config.
+#[derive(Debug, Clone)]
+pub struct EscrowSettleBuilder {
+    pub config: EscrowConfig,
+    pub escrow_out_point: OutPoint,
+    pub settlement: EscrowSettlement,
+    /// The udt type script, the escrow cell must hold this UDT
+    pub type_script: Script,
+}
+
+impl EscrowSettleBuilder {
+    pub fn new(
+        config: EscrowConfig,
+        escrow_out_point: OutPoint,
+        settlement: EscrowSettlement,
+        type_script: Script,
+    ) -> EscrowSettleBuilder {
+        EscrowSettleBuilder {
+            config,
+            escrow_out_point,
+            settlement,
+            type_script,
+        }
+    }
+
+    /// The lock script receives the escrow cell
+    pub fn receiver_lock(&self) -> Script {
+        match self.settlement {
+            EscrowSettlement::Release => self.config.seller_lock(),
+            EscrowSettlement::Refund => self.config.buyer_lock(),
+        }
+    }
+}
+
+impl TxBuilder for EscrowSettleBuilder {
+    fn build_base(
+        &self,
+        _cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        let escrow_lock = self.config.lock_script()?;
+        let escrow_cell = tx_dep_provider.get_cell(&self.escrow_out_point)?;
+        let escrow_data = tx_dep_provider.get_cell_data(&self.escrow_out_point)?;
+        if escrow_cell.lock() != escrow_lock {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "the escrow cell lock script is not match with escrow config"
+            )));
+        }
+        let type_script = escrow_cell.type_().to_opt().ok_or_else(|| {
+            TxBuilderError::InvalidParameter(anyhow!("escrow cell missing type script"))
+        })?;
+        if type_script != self.type_script {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "the escrow cell type script is not match with the expected udt"
+            )));
+        }
+        if escrow_data.len() < 16 {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "invalid escrow cell data length, expected at least: 16, got: {}",
+                escrow_data.len()
+            )));
+        }
+
+        #[allow(clippy::mutable_key_type)]
+        let mut cell_deps = HashSet::new();
+        let lock_cell_dep = cell_dep_resolver
+            .resolve(&escrow_lock)
+            .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(escrow_lock.clone()))?;
+        let type_cell_dep = cell_dep_resolver
+            .resolve(&type_script)
+            .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(type_script.clone()))?;
+        cell_deps.insert(lock_cell_dep);
+        cell_deps.insert(type_cell_dep);
+
+        let output = escrow_cell.as_builder().lock(self.receiver_lock()).build();
+        Ok(TransactionBuilder::default()
+            .set_cell_deps(cell_deps.into_iter().collect())
+            .set_inputs(vec![CellInput::new(self.escrow_out_point.clone(), 0)])
+            .set_outputs(vec![output])
+            .set_outputs_data(vec![escrow_data.pack()])
+            .build())
+    }
+}
@@ -0,0 +1,152 @@
+//! Spending freeze of lock scripts.
+//!
+//! In a compromise response or a maintenance window, freeze the affected
+//! lock scripts with [`freeze_lock_script`]: the transaction builders refuse
+//! to build a transaction spending from a frozen lock script, the capacity
+//! balancer refuses to collect capacity from it, and
+//! [`unlock_tx`](super::unlock_tx) refuses to sign it. The refused attempts
+//! are logged as warnings with the target [`FREEZE_LOG_TARGET`] for auditing.
+//!
+//! The free functions operate on the process wide registry
+//! ([`FreezeRegistry::global`]). A [`FreezeRegistry`] instance can be set to
+//! the balancer by [`CapacityBalancer::set_freeze_registry`] to scope the
+//! freeze (e.g. per wallet or per test).
+//!
+//! [`CapacityBalancer::set_freeze_registry`]: super::CapacityBalancer::set_freeze_registry
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+
+use ckb_types::{core::TransactionView, packed::Script, prelude::*, H256};
+
+use crate::traits::{TransactionDependencyError, TransactionDependencyProvider};
+
+/// The log target of the refused attempts
+pub const FREEZE_LOG_TARGET: &str = "ckb_sdk::freeze";
+
+lazy_static! {
+    static ref GLOBAL_FREEZE_REGISTRY: FreezeRegistry = FreezeRegistry::default();
+}
+
+/// The frozen lock scripts, lock script hash => reason
+#[derive(Debug, Default)]
+pub struct FreezeRegistry {
+    frozen: RwLock<HashMap<H256, String>>,
+}
+
+impl FreezeRegistry {
+    /// The process wide registry
+    pub fn global() -> &'static FreezeRegistry {
+        &GLOBAL_FREEZE_REGISTRY
+    }
+
+    /// Freeze the lock script, the reason is reported in the errors and the
+    /// audit logs.
+    pub fn freeze_lock_script(&self, lock_script: &Script, reason: &str) {
+        let lock_hash: H256 = lock_script.calc_script_hash().unpack();
+        log::warn!(
+            target: FREEZE_LOG_TARGET,
+            "freeze lock script {:#x}: {}",
+            lock_hash,
+            reason
+        );
+        self.frozen.write().insert(lock_hash, reason.to_string());
+    }
+
+    /// Unfreeze the lock script, return false if it is not frozen
+    pub fn unfreeze_lock_script(&self, lock_script: &Script) -> bool {
+        let lock_hash: H256 = lock_script.calc_script_hash().unpack();
+        let removed = self.frozen.write().remove(&lock_hash).is_some();
+        if removed {
+            log::warn!(
+                target: FREEZE_LOG_TARGET,
+                "unfreeze lock script {:#x}",
+                lock_hash
+            );
+        }
+        removed
+    }
+
+    /// The frozen lock script hashes and the reasons
+    pub fn frozen_lock_scripts(&self) -> Vec<(H256, String)> {
+        self.frozen
+            .read()
+            .iter()
+            .map(|(lock_hash, reason)| (lock_hash.clone(), reason.clone()))
+            .collect()
+    }
+
+    /// The reason if the lock script (by hash) is frozen
+    pub fn frozen_reason(&self, lock_hash: &H256) -> Option<String> {
+        self.frozen.read().get(lock_hash).cloned()
+    }
+
+    /// Return the first frozen lock script hash and the reason in
+    /// `lock_hashes`, the refused `action` is logged.
+    pub(crate) fn check_frozen<I: IntoIterator<Item = H256>>(
+        &self,
+        lock_hashes: I,
+        action: &str,
+    ) -> Option<(H256, String)> {
+        let frozen = self.frozen.read();
+        if frozen.is_empty() {
+            return None;
+        }
+        let (lock_hash, reason) = lock_hashes.into_iter().find_map(|lock_hash| {
+            frozen
+                .get(&lock_hash)
+                .map(|reason| (lock_hash, reason.clone()))
+        })?;
+        log::warn!(
+            target: FREEZE_LOG_TARGET,
+            "refused to {} a transaction spending frozen lock script {:#x}: {}",
+            action,
+            lock_hash,
+            reason
+        );
+        Some((lock_hash, reason))
+    }
+
+    /// Same as [`FreezeRegistry::check_frozen`] with the lock scripts of the
+    /// transaction inputs
+    pub(crate) fn check_frozen_inputs(
+        &self,
+        tx: &TransactionView,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+        action: &str,
+    ) -> Result<Option<(H256, String)>, TransactionDependencyError> {
+        if self.frozen.read().is_empty() {
+            return Ok(None);
+        }
+        let mut lock_hashes = Vec::new();
+        for out_point in tx.input_pts_iter() {
+            let output = tx_dep_provider.get_cell(&out_point)?;
+            lock_hashes.push(output.lock().calc_script_hash().unpack());
+        }
+        Ok(self.check_frozen(lock_hashes, action))
+    }
+}
+
+/// Freeze the lock script in the process wide registry, see
+/// [`FreezeRegistry::freeze_lock_script`]
+pub fn freeze_lock_script(lock_script: &Script, reason: &str) {
+    FreezeRegistry::global().freeze_lock_script(lock_script, reason)
+}
+
+/// Unfreeze the lock script in the process wide registry, return false if
+/// it is not frozen
+pub fn unfreeze_lock_script(lock_script: &Script) -> bool {
+    FreezeRegistry::global().unfreeze_lock_script(lock_script)
+}
+
+/// The frozen lock script hashes and the reasons in the process wide registry
+pub fn frozen_lock_scripts() -> Vec<(H256, String)> {
+    FreezeRegistry::global().frozen_lock_scripts()
+}
+
+/// The reason if the lock script (by hash) is frozen in the process wide
+/// registry
+pub fn frozen_reason(lock_hash: &H256) -> Option<String> {
+    FreezeRegistry::global().frozen_reason(lock_hash)
+}
@@ -0,0 +1,238 @@
+use std::collections::HashSet;
+
+use anyhow::anyhow;
+use ckb_types::{
+    bytes::Bytes,
+    core::{TransactionBuilder, TransactionView},
+    packed::{CellInput, CellOutput, OutPoint, Script},
+    prelude::*,
+    H160,
+};
+
+use super::{salt::SaltStore, TxBuilder, TxBuilderError};
+use crate::traits::{
+    CellCollector, CellDepResolver, HeaderDepResolver, TransactionDependencyProvider,
+};
+use crate::types::ScriptId;
+use crate::unlock::{HtlcAction, HtlcArgs};
+
+/// Build the HTLC lock script from the script id and args.
+pub fn build_htlc_script(script_id: &ScriptId, args: &HtlcArgs) -> Script {
+    Script::new_builder()
+        .code_hash(script_id.code_hash.pack())
+        .hash_type(script_id.hash_type.into())
+        .args(args.to_bytes().pack())
+        .build()
+}
+
+/// Build a transaction create a hashlocked cell.
+#[derive(Debug, Clone)]
+pub struct HtlcCreateBuilder {
+    /// The HTLC lock script id
+    pub script_id: ScriptId,
+    pub args: HtlcArgs,
+    pub capacity: u64,
+    /// The optional type script and data of the HTLC cell (for locking UDT)
+    pub type_script: Option<Script>,
+    pub data: Bytes,
+}
+
+impl HtlcCreateBuilder {
+    pub fn new(script_id: ScriptId, args: HtlcArgs, capacity: u64) -> HtlcCreateBuilder {
+        HtlcCreateBuilder {
+            script_id,
+            args,
+            capacity,
+            type_script: None,
+            data: Bytes::new(),
+        }
+    }
+
+    /// Create the builder with the preimage `secret || salt`, the salt is
+    /// issued by `salt_store` for the receiver/refund lock pair so repeated
+    /// payments never share a hash lock.
+    ///
+    /// Return the builder and the preimage.
+    pub fn new_salted(
+        script_id: ScriptId,
+        receiver_lock_hash: H160,
+        refund_lock_hash: H160,
+        refund_since: u64,
+        secret: &[u8],
+        capacity: u64,
+        salt_store: &mut dyn SaltStore,
+    ) -> Result<(HtlcCreateBuilder, Bytes), TxBuilderError> {
+        let salt_key = [receiver_lock_hash.as_bytes(), refund_lock_hash.as_bytes()].concat();
+        let salt = salt_store
+            .next_salt(&salt_key)
+            .map_err(|err| TxBuilderError::Other(anyhow!(err)))?;
+        let preimage = Bytes::from([secret, salt.as_bytes()].concat());
+        let args = HtlcArgs::new(
+            receiver_lock_hash,
+            refund_lock_hash,
+            HtlcArgs::hash_preimage(preimage.as_ref()),
+            refund_since,
+        );
+        Ok((HtlcCreateBuilder::new(script_id, args, capacity), preimage))
+    }
+}
+
+impl TxBuilder for HtlcCreateBuilder {
+    fn build_base(
+        &self,
+        _cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        #[allow(clippy::mutable_key_type)]
+        let mut cell_deps = HashSet::new();
+        if let Some(type_script) = self.type_script.as_ref() {
+            let cell_dep = cell_dep_resolver
+                .resolve(type_script)
+                .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(type_script.clone()))?;
+            cell_deps.insert(cell_dep);
+        }
+        let output = CellOutput::new_builder()
+            .capacity(self.capacity.pack())
+            .lock(build_htlc_script(&self.script_id, &self.args))
+            .type_(self.type_script.clone().pack())
+            .build();
+        Ok(TransactionBuilder::default()
+            .set_cell_deps(cell_deps.into_iter().collect())
+            .set_outputs(vec![output])
+            .set_outputs_data(vec![self.data.pack()])
+            .build())
+    }
+}
+
+/// Build a transaction spend HTLC cells, by receiver with preimage (claim) or
+/// by refund lock after timeout (refund).
+///
+/// The `owner_input` must be locked by the receiver lock (claim) or the refund
+/// lock (refund), all the capacity of HTLC cells will be added to the owner
+/// cell. The HTLC cells must not have type script.
+#[derive(Debug, Clone)]
+pub struct HtlcSpendBuilder {
+    /// The HTLC cells to spend, all cells must have same lock script
+    pub out_points: Vec<OutPoint>,
+    /// The owner cell proves the receiver or the refunder is presented
+    pub owner_input: CellInput,
+    pub action: HtlcAction,
+}
+
+impl HtlcSpendBuilder {
+    pub fn new(
+        out_points: Vec<OutPoint>,
+        owner_input: CellInput,
+        action: HtlcAction,
+    ) -> HtlcSpendBuilder {
+        HtlcSpendBuilder {
+            out_points,
+            owner_input,
+            action,
+        }
+    }
+}
+
+impl TxBuilder for HtlcSpendBuilder {
+    fn build_base(
+        &self,
+        _cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        if self.out_points.is_empty() {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "empty htlc inputs"
+            )));
+        }
+        #[allow(clippy::mutable_key_type)]
+        let mut cell_deps = HashSet::new();
+
+        let mut htlc_lock_script = None;
+        let mut htlc_total_capacity: u64 = 0;
+        for out_point in &self.out_points {
+            let input_cell = tx_dep_provider.get_cell(out_point)?;
+            if input_cell.type_().to_opt().is_some() {
+                return Err(TxBuilderError::InvalidParameter(anyhow!(
+                    "htlc input with type script is not supported: {}",
+                    out_point
+                )));
+            }
+            let lock_script = input_cell.lock();
+            if htlc_lock_script.is_none() {
+                htlc_lock_script = Some(lock_script);
+            } else if htlc_lock_script.as_ref() != Some(&lock_script) {
+                return Err(TxBuilderError::InvalidParameter(anyhow!(
+                    "all htlc input lock script must be the same"
+                )));
+            }
+            let input_capacity: u64 = input_cell.capacity().unpack();
+            htlc_total_capacity = htlc_total_capacity
+                .checked_add(input_capacity)
+                .ok_or_else(|| TxBuilderError::Other(anyhow!("htlc input capacity overflow")))?;
+        }
+        let htlc_lock_script = htlc_lock_script.unwrap();
+        let args = HtlcArgs::from_slice(htlc_lock_script.args().raw_data().as_ref())
+            .map_err(|err| TxBuilderError::InvalidParameter(anyhow!(err)))?;
+        let (owner_lock_hash, since) = match &self.action {
+            HtlcAction::Claim(preimage) => {
+                if HtlcArgs::hash_preimage(preimage.as_ref()) != args.hash_lock {
+                    return Err(TxBuilderError::InvalidParameter(anyhow!(
+                        "the preimage is not match with htlc hash lock"
+                    )));
+                }
+                (args.receiver_lock_hash, 0)
+            }
+            HtlcAction::Refund => (args.refund_lock_hash, args.refund_since),
+        };
+        let htlc_cell_dep = cell_dep_resolver
+            .resolve(&htlc_lock_script)
+            .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(htlc_lock_script.clone()))?;
+        cell_deps.insert(htlc_cell_dep);
+
+        let owner_out_point = self.owner_input.previous_output();
+        let owner_cell = tx_dep_provider.get_cell(&owner_out_point)?;
+        let owner_data = tx_dep_provider.get_cell_data(&owner_out_point)?;
+        let owner_lock = owner_cell.lock();
+        if &owner_lock.calc_script_hash().as_slice()[0..20] != owner_lock_hash.as_bytes() {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "owner input lock script is not match with htlc lock script args"
+            )));
+        }
+        let owner_cell_dep = cell_dep_resolver
+            .resolve(&owner_lock)
+            .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(owner_lock.clone()))?;
+        cell_deps.insert(owner_cell_dep);
+        if let Some(type_script) = owner_cell.type_().to_opt() {
+            let cell_dep = cell_dep_resolver
+                .resolve(&type_script)
+                .ok_or(TxBuilderError::ResolveCellDepFailed(type_script))?;
+            cell_deps.insert(cell_dep);
+        }
+
+        let mut inputs: Vec<_> = self
+            .out_points
+            .iter()
+            .map(|out_point| CellInput::new(out_point.clone(), since))
+            .collect();
+        inputs.push(self.owner_input.clone());
+
+        let owner_capacity: u64 = owner_cell.capacity().unpack();
+        let owner_output_capacity = owner_capacity
+            .checked_add(htlc_total_capacity)
+            .ok_or_else(|| TxBuilderError::Other(anyhow!("owner output capacity overflow")))?;
+        let owner_output = owner_cell
+            .as_builder()
+            .capacity(owner_output_capacity.pack())
+            .build();
+        Ok(TransactionBuilder::default()
+            .set_cell_deps(cell_deps.into_iter().collect())
+            .set_inputs(inputs)
+            .set_outputs(vec![owner_output])
+            .set_outputs_data(vec![owner_data.pack()])
+            .build())
+    }
+}
@@ -0,0 +1,148 @@
+//! Metrics of the cell selection quality.
+//!
+//! Set a [`SelectionMetricsSink`] to
+//! [`CapacityBalancer::metrics_sink`](super::CapacityBalancer::metrics_sink),
+//! the balancer reports a [`SelectionMetrics`] after every successful
+//! balancing. Operators can aggregate them (e.g. with
+//! [`SelectionMetricsAggregator`] or a sink exporting to their monitoring
+//! system) to tune the coin selection strategies over time.
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use ckb_types::{core::TransactionView, prelude::*};
+
+use super::{tx_fee, BalanceTxCapacityError, CapacityBalancer};
+use crate::traits::{HeaderDepResolver, TransactionDependencyProvider};
+
+/// The metrics of a balanced transaction
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct SelectionMetrics {
+    /// The number of all inputs
+    pub inputs: usize,
+    /// The number of the inputs selected by the balancer
+    pub selected_inputs: usize,
+    /// The capacity of the change output, `None` if no change output is
+    /// created
+    pub change_capacity: Option<u64>,
+    /// The capacity left too small for a change cell and paid as fee
+    pub dust_as_fee: u64,
+    pub fee: u64,
+    /// The fee required by the fee rate
+    pub min_fee: u64,
+}
+
+impl SelectionMetrics {
+    pub(crate) fn new(
+        base_tx: &TransactionView,
+        tx: &TransactionView,
+        change_index: Option<usize>,
+        balancer: &CapacityBalancer,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+        header_dep_resolver: &dyn HeaderDepResolver,
+    ) -> Result<SelectionMetrics, BalanceTxCapacityError> {
+        let fee = tx_fee(tx.clone(), tx_dep_provider, header_dep_resolver)?;
+        let tx_size = balancer.estimated_tx_size(tx, tx_dep_provider)?;
+        let min_fee = balancer.fee_rate.fee(tx_size as u64).as_u64();
+        // The output at the change index is the send max output in send max
+        // mode, not a change output.
+        let change_capacity = change_index
+            .filter(|_| balancer.send_max.is_none())
+            .and_then(|idx| tx.output(idx))
+            .map(|output| Unpack::<u64>::unpack(&output.capacity()));
+        let dust_as_fee = if change_capacity.is_none() && balancer.exact_fee.is_none() {
+            fee.saturating_sub(min_fee)
+        } else {
+            0
+        };
+        Ok(SelectionMetrics {
+            inputs: tx.inputs().len(),
+            selected_inputs: tx.inputs().len().saturating_sub(base_tx.inputs().len()),
+            change_capacity,
+            dust_as_fee,
+            fee,
+            min_fee,
+        })
+    }
+
+    /// The fee paid above the fee required by the fee rate
+    pub fn fee_overpayment(&self) -> u64 {
+        self.fee.saturating_sub(self.min_fee)
+    }
+}
+
+/// Receive the metrics of every balanced transaction
+pub trait SelectionMetricsSink: Send + Sync + std::fmt::Debug {
+    fn record(&self, metrics: &SelectionMetrics);
+}
+
+/// The metrics aggregated over the balanced transactions
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct AggregatedSelectionMetrics {
+    /// The number of the balanced transactions
+    pub builds: usize,
+    pub inputs: usize,
+    pub selected_inputs: usize,
+    /// The number of the transactions with a change output
+    pub changes: usize,
+    pub change_capacity: u64,
+    /// The number of the transactions paid the small change as fee
+    pub dust_builds: usize,
+    pub dust_as_fee: u64,
+    pub fee: u64,
+    pub fee_overpayment: u64,
+}
+
+impl AggregatedSelectionMetrics {
+    /// The average number of the inputs selected by the balancer
+    pub fn avg_selected_inputs(&self) -> f64 {
+        self.selected_inputs as f64 / self.builds.max(1) as f64
+    }
+
+    /// The average fee overpayment (in shannons)
+    pub fn avg_fee_overpayment(&self) -> f64 {
+        self.fee_overpayment as f64 / self.builds.max(1) as f64
+    }
+}
+
+/// A sink aggregating the metrics in memory, the clones of an aggregator
+/// share the metrics.
+#[derive(Debug, Clone, Default)]
+pub struct SelectionMetricsAggregator(Arc<Mutex<AggregatedSelectionMetrics>>);
+
+impl SelectionMetricsAggregator {
+    pub fn new() -> SelectionMetricsAggregator {
+        SelectionMetricsAggregator::default()
+    }
+
+    /// The metrics aggregated so far
+    pub fn snapshot(&self) -> AggregatedSelectionMetrics {
+        self.0.lock().clone()
+    }
+
+    /// Take the metrics aggregated so far and start over
+    pub fn reset(&self) -> AggregatedSelectionMetrics {
+        std::mem::take(&mut self.0.lock())
+    }
+}
+
+impl SelectionMetricsSink for SelectionMetricsAggregator {
+    fn record(&self, metrics: &SelectionMetrics) {
+        let mut aggregated = self.0.lock();
+        aggregated.builds += 1;
+        aggregated.inputs += metrics.inputs;
+        aggregated.selected_inputs += metrics.selected_inputs;
+        if let Some(capacity) = metrics.change_capacity {
+            aggregated.changes += 1;
+            aggregated.change_capacity = aggregated.change_capacity.saturating_add(capacity);
+        }
+        if metrics.dust_as_fee > 0 {
+            aggregated.dust_builds += 1;
+            aggregated.dust_as_fee = aggregated.dust_as_fee.saturating_add(metrics.dust_as_fee);
+        }
+        aggregated.fee = aggregated.fee.saturating_add(metrics.fee);
+        aggregated.fee_overpayment = aggregated
+            .fee_overpayment
+            .saturating_add(metrics.fee_overpayment());
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::anyhow;
+use ckb_types::{
+    bytes::Bytes,
+    core::{Capacity, FeeRate, TransactionBuilder, TransactionView},
+    packed::{CellInput, CellOutput, OutPoint, Script},
+    prelude::*,
+    H256,
+};
+
+use super::{fill_placeholder_witnesses, unlock_tx, TxBuilderError};
+use crate::traits::{
+    CellCollector, CellDepResolver, CellQueryOptions, LiveCell, TransactionDependencyProvider,
+};
+use crate::types::{ScriptGroup, ScriptId};
+use crate::unlock::ScriptUnlocker;
+
+/// The default max inputs count of a migration transaction
+pub const DEFAULT_MIGRATION_MAX_INPUTS: usize = 500;
+/// The default max size of a migration transaction (excluding witnesses)
+pub const DEFAULT_MIGRATION_MAX_TX_SIZE: usize = 400 * 1024;
+
+/// Move all live cells under deprecated lock scripts (e.g. the legacy
+/// multisig lock, PW-lock) to a target lock script.
+///
+/// The cells with type script or data are re-created with only the lock
+/// script replaced, the capacity of the plain cells is merged into one output
+/// which also pays the transaction fee. The cells are split into independent
+/// transactions by `max_inputs` and `max_tx_size`, every transaction must
+/// contain some plain capacity to pay the fee.
+#[derive(Debug, Clone)]
+pub struct MigrationBuilder {
+    /// The deprecated lock scripts to migrate from
+    pub from_locks: Vec<Script>,
+    /// The lock script to migrate to
+    pub to_lock: Script,
+    /// The max inputs count of each transaction
+    pub max_inputs: usize,
+    /// The max size of each transaction, the witnesses are not counted
+    pub max_tx_size: usize,
+    /// The fee rate (shannons/KB)
+    pub fee_rate: u64,
+}
+
+/// The migration summary of a transaction
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct MigrationTxReport {
+    pub tx_hash: H256,
+    /// The migrated cells
+    pub out_points: Vec<OutPoint>,
+    /// The total capacity of the migrated cells
+    pub capacity: u64,
+    pub fee: u64,
+}
+
+/// The migration summary of all transactions
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct MigrationReport {
+    pub txs: Vec<MigrationTxReport>,
+}
+
+impl MigrationReport {
+    pub fn cells_count(&self) -> usize {
+        self.txs.iter().map(|tx| tx.out_points.len()).sum()
+    }
+
+    pub fn total_capacity(&self) -> u64 {
+        self.txs.iter().map(|tx| tx.capacity).sum()
+    }
+
+    pub fn total_fee(&self) -> u64 {
+        self.txs.iter().map(|tx| tx.fee).sum()
+    }
+}
+
+impl MigrationBuilder {
+    pub fn new(from_locks: Vec<Script>, to_lock: Script, fee_rate: u64) -> MigrationBuilder {
+        MigrationBuilder {
+            from_locks,
+            to_lock,
+            max_inputs: DEFAULT_MIGRATION_MAX_INPUTS,
+            max_tx_size: DEFAULT_MIGRATION_MAX_TX_SIZE,
+            fee_rate,
+        }
+    }
+
+    fn collect_cells(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+    ) -> Result<Vec<LiveCell>, TxBuilderError> {
+        let mut cells = Vec::new();
+        for lock_script in &self.from_locks {
+            let mut query = CellQueryOptions::new_lock(lock_script.clone());
+            query.min_total_capacity = u64::MAX;
+            let (lock_cells, _) = cell_collector.collect_live_cells(&query, true)?;
+            cells.extend(lock_cells);
+        }
+        Ok(cells)
+    }
+
+    /// The cell is re-created as a separate output
+    fn is_kept(cell: &LiveCell) -> bool {
+        cell.output.type_().is_some() || !cell.output_data.is_empty()
+    }
+
+    /// Estimated size of the cell in the transaction, the witness is not
+    /// counted.
+    fn estimated_size(cell: &LiveCell) -> usize {
+        let input_size = CellInput::TOTAL_SIZE + 4;
+        if Self::is_kept(cell) {
+            input_size + cell.output.as_slice().len() + 4 + 4 + cell.output_data.len() + 4
+        } else {
+            input_size
+        }
+    }
+
+    /// Split the cells into batches by `max_inputs` and `max_tx_size`
+    pub fn batches<'a>(&self, cells: &'a [LiveCell]) -> Vec<&'a [LiveCell]> {
+        // the merged output and the transaction skeleton
+        let base_size = 512;
+        let mut batches = Vec::new();
+        let mut start = 0;
+        let mut size = base_size;
+        for (idx, cell) in cells.iter().enumerate() {
+            let cell_size = Self::estimated_size(cell);
+            if idx > start
+                && (idx - start >= self.max_inputs || size + cell_size > self.max_tx_size)
+            {
+                batches.push(&cells[start..idx]);
+                start = idx;
+                size = base_size;
+            }
+            size += cell_size;
+        }
+        if start < cells.len() {
+            batches.push(&cells[start..]);
+        }
+        batches
+    }
+
+    /// Build the migration transactions, each one is unlocked by `unlockers`.
+    /// The transactions are independent, they can be sent in any order.
+    ///
+    /// Return value:
+    ///   * The transactions with the script groups not unlocked by given
+    ///     `unlockers`
+    ///   * The migration report
+    #[allow(clippy::type_complexity)]
+    pub fn build_migration_txs(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+        unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+    ) -> Result<(Vec<(TransactionView, Vec<ScriptGroup>)>, MigrationReport), TxBuilderError> {
+        if self.from_locks.is_empty() {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "empty migration lock scripts"
+            )));
+        }
+        if self.from_locks.contains(&self.to_lock) {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "the target lock script is one of the migrated lock scripts"
+            )));
+        }
+        if self.max_inputs == 0 {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "migration max inputs must be positive"
+            )));
+        }
+        let mut cells = self.collect_cells(cell_collector)?;
+        if cells.is_empty() {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "no live cell to migrate"
+            )));
+        }
+        // put the kept cells first, so they share the batch with the plain
+        // cells which pay the fee, unless there are too many of them
+        cells.sort_by_key(|cell| !Self::is_kept(cell));
+
+        let mut txs = Vec::new();
+        let mut report = MigrationReport::default();
+        for batch in self.batches(&cells) {
+            let base_tx = self.build_batch(batch, cell_dep_resolver)?;
+            let (tx, _) = fill_placeholder_witnesses(base_tx, tx_dep_provider, unlockers)?;
+            let (tx, fee) = self.deduct_fee(tx)?;
+            let (tx, not_unlocked) = unlock_tx(tx, tx_dep_provider, unlockers)?;
+            report.txs.push(MigrationTxReport {
+                tx_hash: tx.hash().unpack(),
+                out_points: batch.iter().map(|cell| cell.out_point.clone()).collect(),
+                capacity: batch
+                    .iter()
+                    .map(|cell| Unpack::<u64>::unpack(&cell.output.capacity()))
+                    .sum(),
+                fee,
+            });
+            txs.push((tx, not_unlocked));
+        }
+        Ok((txs, report))
+    }
+
+    fn build_batch(
+        &self,
+        cells: &[LiveCell],
+        cell_dep_resolver: &dyn CellDepResolver,
+    ) -> Result<TransactionView, TxBuilderError> {
+        #[allow(clippy::mutable_key_type)]
+        let mut cell_deps = HashSet::new();
+        let mut inputs = Vec::new();
+        let mut outputs = Vec::new();
+        let mut outputs_data = Vec::new();
+        let mut merged_capacity = 0;
+        for cell in cells {
+            let lock_script = cell.output.lock();
+            let cell_dep = cell_dep_resolver
+                .resolve(&lock_script)
+                .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(lock_script.clone()))?;
+            cell_deps.insert(cell_dep);
+            if let Some(type_script) = cell.output.type_().to_opt() {
+                let cell_dep = cell_dep_resolver
+                    .resolve(&type_script)
+                    .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(type_script.clone()))?;
+                cell_deps.insert(cell_dep);
+            }
+            inputs.push(CellInput::new(cell.out_point.clone(), 0));
+            if Self::is_kept(cell) {
+                outputs.push(
+                    cell.output
+                        .clone()
+                        .as_builder()
+                        .lock(self.to_lock.clone())
+                        .build(),
+                );
+                outputs_data.push(cell.output_data.pack());
+            } else {
+                merged_capacity += Unpack::<u64>::unpack(&cell.output.capacity());
+            }
+        }
+        if merged_capacity == 0 {
+            return Err(TxBuilderError::Other(anyhow!(
+                "no plain cell in the migration batch to pay the fee"
+            )));
+        }
+        let merged_output = CellOutput::new_builder()
+            .capacity(merged_capacity.pack())
+            .lock(self.to_lock.clone())
+            .build();
+        outputs.insert(0, merged_output);
+        outputs_data.insert(0, Bytes::new().pack());
+        Ok(TransactionBuilder::default()
+            .set_cell_deps(cell_deps.into_iter().collect())
+            .set_inputs(inputs)
+            .set_outputs(outputs)
+            .set_outputs_data(outputs_data)
+            .build())
+    }
+
+    /// Pay the transaction fee from the merged output (the first output), the
+    /// witnesses must be filled with placeholders.
+    fn deduct_fee(&self, tx: TransactionView) -> Result<(TransactionView, u64), TxBuilderError> {
+        let output = tx.output(0).expect("merged output");
+        let capacity: u64 = output.capacity().unpack();
+        let tx_size = tx.data().as_reader().serialized_size_in_block();
+        let fee = FeeRate::from_u64(self.fee_rate)
+            .fee(tx_size as u64)
+            .as_u64();
+        let occupied_capacity = output
+            .occupied_capacity(Capacity::zero())
+            .expect("occupied capacity")
+            .as_u64();
+        if capacity < occupied_capacity + fee {
+            return Err(TxBuilderError::Other(anyhow!(
+                "migrated plain capacity not enough, required: {}, actual: {}",
+                occupied_capacity + fee,
+                capacity
+            )));
+        }
+        let output = output
+            .as_builder()
+            .capacity((capacity - fee).pack())
+            .build();
+        let mut outputs: Vec<_> = tx.outputs().into_iter().collect();
+        outputs[0] = output;
+        Ok((tx.as_advanced_builder().set_outputs(outputs).build(), fee))
+    }
+}
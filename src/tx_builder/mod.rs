@@ -1,9 +1,40 @@
 pub mod acp;
+pub mod allowance;
+pub mod amend;
+#[cfg(feature = "async-tx-builder")]
+pub mod asynchronous;
+pub mod chain;
+pub mod channel;
 pub mod cheque;
+pub mod cheque_watch;
+pub mod coin_select;
 pub mod dao;
+pub mod data_cell;
+pub mod duplicate_guard;
+pub mod escrow;
+pub mod freeze;
+pub mod htlc;
+pub mod metrics;
+pub mod migration;
+pub mod nft;
 pub mod omni_lock;
+pub mod ordering;
+pub mod plan;
+pub mod rbf;
+pub mod reclaim;
+pub mod recovery;
+pub mod salt;
+pub mod shuffle;
+#[cfg(feature = "spore")]
+pub mod spore;
+pub mod state_cell;
+pub mod submit;
+pub mod sweep;
 pub mod transfer;
+pub mod type_id;
 pub mod udt;
+pub mod utilization;
+pub mod vesting;
 
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
@@ -17,14 +48,22 @@ use thiserror::Error;
 use ckb_types::core::cell::{CellProvider, HeaderChecker};
 use ckb_types::core::HeaderView;
 use ckb_types::{
+    bytes::Bytes,
     core::{
         cell::resolve_transaction, error::OutPointError, Capacity, CapacityError, FeeRate,
         TransactionView,
     },
-    packed::{Byte32, CellInput, CellOutput, Script, WitnessArgs},
+    packed::{Byte32, CellDep, CellInput, CellOutput, OutPoint, Script, WitnessArgs},
     prelude::*,
+    H256,
 };
 
+use self::dao::DaoCapacityProvider;
+use self::freeze::FreezeRegistry;
+use self::metrics::{SelectionMetrics, SelectionMetricsSink};
+use self::ordering::InputOrdering;
+use self::plan::{BalancePlan, BalanceRecord, BalanceRecorder, BalanceReport, InputReason};
+use self::shuffle::ShuffleMode;
 use crate::types::ScriptGroup;
 use crate::types::{HumanCapacity, ScriptId};
 use crate::unlock::{ScriptUnlocker, UnlockError};
@@ -32,8 +71,9 @@ use crate::util::calculate_dao_maximum_withdraw4;
 use crate::{constants::DAO_TYPE_HASH, NetworkType};
 use crate::{
     traits::{
-        CellCollector, CellCollectorError, CellDepResolver, CellQueryOptions, HeaderDepResolver,
-        TransactionDependencyError, TransactionDependencyProvider, ValueRangeOption,
+        CellCollector, CellCollectorError, CellDepResolver, CellQueryOptions, FeeRateProvider,
+        HeaderDepResolver, LiveCell, MaturityOption, SinceProvider, TransactionDependencyError,
+        TransactionDependencyProvider, ValueRangeOption, WitnessSizeEstimator,
     },
     RpcError,
 };
@@ -76,6 +116,17 @@ pub enum TxBuilderError {
     #[error("can not find specifed output to put small change")]
     NoOutputForSmallChange,
 
+    #[error(
+        "transaction fee `{}` is less than required fee `{}` after unlock, witness size regressions: {:?}",
+        .0.fee,
+        .0.required_fee,
+        .0.regressions
+    )]
+    WitnessSizeRegression(WitnessSizeWarning),
+
+    #[error("lock script `{0:#x}` is frozen: `{1}`")]
+    FrozenLockScript(H256, String),
+
     #[error("other error: `{0}`")]
     Other(anyhow::Error),
 }
@@ -91,6 +142,13 @@ pub trait TxBuilder {
         tx_dep_provider: &dyn TransactionDependencyProvider,
     ) -> Result<TransactionView, TxBuilderError>;
 
+    /// The ordering constraints of the inputs of the base transaction, which
+    /// the balancer must keep when adding the capacity inputs, see
+    /// [`ordering`].
+    fn input_ordering(&self, _base_tx: &TransactionView) -> Option<InputOrdering> {
+        None
+    }
+
     /// Build balanced transaction that ready to sign:
     ///  * Build base transaction
     ///  * Fill placeholder witness for lock script
@@ -110,6 +168,8 @@ pub trait TxBuilder {
             header_dep_resolver,
             tx_dep_provider,
         )?;
+        balancer.check_frozen_base_tx(&base_tx, tx_dep_provider)?;
+        let balancer = &balancer.with_input_ordering(self.input_ordering(&base_tx));
         let (tx_filled_witnesses, _) =
             fill_placeholder_witnesses(base_tx, tx_dep_provider, unlockers)?;
         Ok(balance_tx_capacity(
@@ -122,10 +182,53 @@ pub trait TxBuilder {
         )?)
     }
 
+    /// Same as [`TxBuilder::build_balanced`], also return the plan of the
+    /// balancing decisions.
+    fn build_balanced_with_plan(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+        balancer: &CapacityBalancer,
+        unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+    ) -> Result<(TransactionView, BalancePlan), TxBuilderError> {
+        let base_tx = self.build_base(
+            cell_collector,
+            cell_dep_resolver,
+            header_dep_resolver,
+            tx_dep_provider,
+        )?;
+        balancer.check_frozen_base_tx(&base_tx, tx_dep_provider)?;
+        let (tx_filled_witnesses, _) =
+            fill_placeholder_witnesses(base_tx, tx_dep_provider, unlockers)?;
+        let recorder = BalanceRecorder::new();
+        let mut balancer = balancer.with_input_ordering(self.input_ordering(&tx_filled_witnesses));
+        balancer.recorder = Some(recorder.clone());
+        let (tx, change_idx) = balance_tx_capacity_with_change(
+            &tx_filled_witnesses,
+            &balancer,
+            cell_collector,
+            tx_dep_provider,
+            cell_dep_resolver,
+            header_dep_resolver,
+        )?;
+        let plan = BalancePlan::new(
+            &tx,
+            change_idx,
+            recorder.take(),
+            tx_dep_provider,
+            header_dep_resolver,
+        )?;
+        Ok((tx, plan))
+    }
+
     /// Build unlocked transaction that ready to send or for further unlock:
     ///   * build base transaction
     ///   * balance the capacity
     ///   * unlock(sign) the transaction
+    ///   * rebalance and unlock again if the unlocked witnesses are bigger
+    ///     than the placeholders and the fee rate is not met
     ///
     /// Return value:
     ///   * The built transaction
@@ -139,15 +242,60 @@ pub trait TxBuilder {
         balancer: &CapacityBalancer,
         unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
     ) -> Result<(TransactionView, Vec<ScriptGroup>), TxBuilderError> {
-        let balanced_tx = self.build_balanced(
+        let base_tx = self.build_base(
             cell_collector,
             cell_dep_resolver,
             header_dep_resolver,
             tx_dep_provider,
+        )?;
+        balancer.check_frozen_base_tx(&base_tx, tx_dep_provider)?;
+        let (tx_filled_witnesses, _) =
+            fill_placeholder_witnesses(base_tx, tx_dep_provider, unlockers)?;
+        let balancer = &balancer
+            .with_input_ordering(self.input_ordering(&tx_filled_witnesses))
+            .with_refreshed_fee_rate()?;
+        let (mut balanced_tx, mut change_idx) = balance_tx_capacity_with_change(
+            &tx_filled_witnesses,
             balancer,
-            unlockers,
+            cell_collector,
+            tx_dep_provider,
+            cell_dep_resolver,
+            header_dep_resolver,
         )?;
-        Ok(unlock_tx(balanced_tx, tx_dep_provider, unlockers)?)
+        const MAX_LOOP_TIMES: u32 = 4;
+        let mut n = 0;
+        loop {
+            n += 1;
+            let (tx, not_unlocked) = unlock_tx_with_freeze_registry(
+                balanced_tx.clone(),
+                tx_dep_provider,
+                unlockers,
+                balancer.freeze_registry(),
+            )?;
+            let warning = match balancer.check_unlocked_fee(
+                &balanced_tx,
+                &tx,
+                tx_dep_provider,
+                header_dep_resolver,
+            )? {
+                Some(warning) => warning,
+                None => return Ok((tx, not_unlocked)),
+            };
+            if n >= MAX_LOOP_TIMES {
+                return Err(TxBuilderError::WitnessSizeRegression(warning));
+            }
+            let (new_tx, new_change_idx) = balancer.rebalance_tx_capacity(
+                &balanced_tx,
+                cell_collector,
+                tx_dep_provider,
+                cell_dep_resolver,
+                header_dep_resolver,
+                warning.required_fee,
+                change_idx,
+            )?;
+            balanced_tx = new_tx;
+            change_idx = new_change_idx;
+        }
     }
 
     /// Build unlocked transaction that ready to send or for further unlock, it's similar to `build_unlocked`,
@@ -173,19 +321,26 @@ pub trait TxBuilder {
             header_dep_resolver,
             tx_dep_provider,
         )?;
+        balancer.check_frozen_base_tx(&base_tx, tx_dep_provider)?;
         let (tx_filled_witnesses, _) =
             fill_placeholder_witnesses(base_tx, tx_dep_provider, unlockers)?;
-        let (balanced_tx, mut change_idx) = rebalance_tx_capacity(
+        let balancer = &balancer
+            .with_input_ordering(self.input_ordering(&tx_filled_witnesses))
+            .with_refreshed_fee_rate()?;
+        let (balanced_tx, mut change_idx) = balance_tx_capacity_with_change(
             &tx_filled_witnesses,
             balancer,
             cell_collector,
             tx_dep_provider,
             cell_dep_resolver,
             header_dep_resolver,
-            0,
-            None,
         )?;
-        let (mut tx, unlocked_group) = unlock_tx(balanced_tx, tx_dep_provider, unlockers)?;
+        let (mut tx, unlocked_group) = unlock_tx_with_freeze_registry(
+            balanced_tx,
+            tx_dep_provider,
+            unlockers,
+            balancer.freeze_registry(),
+        )?;
         if unlocked_group.is_empty() {
             let mut ready = false;
             const MAX_LOOP_TIMES: u32 = 16;
@@ -205,7 +360,12 @@ pub trait TxBuilder {
                 ready = ok;
                 change_idx = new_change_idx;
                 if !ready {
-                    let (new_tx, _) = unlock_tx(tx, tx_dep_provider, unlockers)?;
+                    let (new_tx, _) = unlock_tx_with_freeze_registry(
+                        tx,
+                        tx_dep_provider,
+                        unlockers,
+                        balancer.freeze_registry(),
+                    )?;
                     tx = new_tx
                 }
             }
@@ -225,6 +385,20 @@ pub enum TransferAction {
     Update,
 }
 
+/// Check if the output is an "info" output: it carries data (e.g. a RGB++
+/// commitment) and holds exactly its occupied capacity, so it can not be used
+/// to pay fee or hold change.
+pub fn is_info_output(output: &CellOutput, output_data: &[u8]) -> bool {
+    if output_data.is_empty() {
+        return false;
+    }
+    let capacity: u64 = output.capacity().unpack();
+    Capacity::bytes(output_data.len())
+        .and_then(|data_capacity| output.occupied_capacity(data_capacity))
+        .map(|occupied_capacity| occupied_capacity.as_u64() == capacity)
+        .unwrap_or(false)
+}
+
 #[derive(Error, Debug)]
 pub enum TransactionFeeError {
     #[error("transaction dependency provider error: `{0}`")]
@@ -320,6 +494,10 @@ pub enum SinceSource {
     LockArgs(usize),
     /// raw since value
     Value(u64),
+    /// The since value of every input is given by the provider, e.g. the
+    /// since is stored in the args in another layout, or supplied per input
+    /// (see [`InputSinceMap`](crate::traits::InputSinceMap)).
+    Provider(Arc<dyn SinceProvider>),
 }
 
 impl Default for SinceSource {
@@ -328,6 +506,40 @@ impl Default for SinceSource {
     }
 }
 
+impl SinceSource {
+    /// The since value of the inputs locked by `lock_script`
+    pub fn since_value(&self, lock_script: &Script) -> Result<u64, BalanceTxCapacityError> {
+        match self {
+            SinceSource::LockArgs(offset) => {
+                let lock_arg = lock_script.args().raw_data();
+                if lock_arg.len() < offset + 8 {
+                    return Err(BalanceTxCapacityError::InvalidSinceValue(
+                        *offset,
+                        lock_arg.len(),
+                    ));
+                }
+                let mut since_bytes = [0u8; 8];
+                since_bytes.copy_from_slice(&lock_arg[*offset..*offset + 8]);
+                Ok(u64::from_le_bytes(since_bytes))
+            }
+            SinceSource::Value(since_value) => Ok(*since_value),
+            SinceSource::Provider(_) => Err(BalanceTxCapacityError::SinceProvider(anyhow!(
+                "the since value depends on the input cell"
+            ))),
+        }
+    }
+
+    /// The since value of the input cell added by the balancer
+    pub fn input_since(&self, cell: &LiveCell) -> Result<u64, BalanceTxCapacityError> {
+        match self {
+            SinceSource::Provider(provider) => provider
+                .since_value(cell)
+                .map_err(BalanceTxCapacityError::SinceProvider),
+            _ => self.since_value(&cell.output.lock()),
+        }
+    }
+}
+
 /// Provide capacity locked by a list of lock scripts.
 ///
 /// The cells collected by `lock_script` will filter out those have type script
@@ -353,6 +565,84 @@ impl CapacityProvider {
             .collect();
         CapacityProvider { lock_scripts }
     }
+
+    /// Summarize the cells usable by the balancer (without type script and
+    /// data) of every lock script.
+    pub fn report(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+    ) -> Result<Vec<CapacityProviderReport>, CellCollectorError> {
+        let mut reports: Vec<CapacityProviderReport> = Vec::new();
+        for (lock_script, _, _) in &self.lock_scripts {
+            if reports
+                .iter()
+                .any(|report| report.lock_script == *lock_script)
+            {
+                continue;
+            }
+            let mut query = CellQueryOptions::new_lock(lock_script.clone());
+            query.secondary_script_len_range = Some(ValueRangeOption::new_exact(0));
+            query.data_len_range = Some(ValueRangeOption::new_exact(0));
+            query.min_total_capacity = u64::MAX;
+            let (cells, _) = cell_collector.collect_live_cells(&query, false)?;
+            let mut report = CapacityProviderReport::new(lock_script.clone());
+            for cell in cells {
+                let capacity: u64 = cell.output.capacity().unpack();
+                let occupied_capacity = cell
+                    .output
+                    .occupied_capacity(Capacity::zero())
+                    .expect("cell occupied capacity")
+                    .as_u64();
+                report.cell_count += 1;
+                report.total_capacity += capacity;
+                report.free_capacity += capacity.saturating_sub(occupied_capacity);
+                report.largest_cell = report.largest_cell.max(Some(capacity));
+                report.smallest_cell = Some(
+                    report
+                        .smallest_cell
+                        .map_or(capacity, |smallest| smallest.min(capacity)),
+                );
+            }
+            query.maturity = MaturityOption::Immature;
+            let (immature_cells, _) = cell_collector.collect_live_cells(&query, false)?;
+            report.immature_count = immature_cells.len();
+            reports.push(report);
+        }
+        Ok(reports)
+    }
+}
+
+/// The health snapshot of a capacity provider lock script, see
+/// [`CapacityProvider::report`].
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct CapacityProviderReport {
+    pub lock_script: Script,
+    /// The number of the mature cells
+    pub cell_count: usize,
+    /// The total capacity of the mature cells
+    pub total_capacity: u64,
+    /// The total capacity of the mature cells above their occupied capacity
+    pub free_capacity: u64,
+    /// The capacity of the largest mature cell
+    pub largest_cell: Option<u64>,
+    /// The capacity of the smallest mature cell
+    pub smallest_cell: Option<u64>,
+    /// The number of the cells not mature yet (e.g. the cellbase cells)
+    pub immature_count: usize,
+}
+
+impl CapacityProviderReport {
+    fn new(lock_script: Script) -> CapacityProviderReport {
+        CapacityProviderReport {
+            lock_script,
+            cell_count: 0,
+            total_capacity: 0,
+            free_capacity: 0,
+            largest_cell: None,
+            smallest_cell: None,
+            immature_count: 0,
+        }
+    }
 }
 
 #[derive(Error, Debug)]
@@ -395,12 +685,275 @@ pub enum BalanceTxCapacityError {
 
     #[error("should not try to rebalance, orignal fee {0}, required fee: {1},")]
     AlreadyBalance(u64, u64),
+
+    #[error("strict mode violation: `{0}`")]
+    StrictModeViolation(String),
+
+    #[error("transaction fee `{0}` exceed the max absolute fee `{1}`")]
+    FeeExceedLimit(u64, u64),
+
+    #[error("transaction fee rate `{0}` exceed the max fee rate `{1}`")]
+    FeeRateExceedLimit(u64, u64),
+
+    #[error("{0}")]
+    DataSizeExceeded(DataSizeExceeded),
+
+    #[error("invalid UDT amount data at input/output `{0}`, data length: `{1}`")]
+    InvalidUdtData(usize, usize),
+
+    #[error("UDT amount not enough, type script: `{0}`, inputs: `{1}`, outputs: `{2}`")]
+    UdtAmountNotEnough(Script, u128, u128),
+
+    #[error("get fee rate failed: `{0}`")]
+    FeeRate(anyhow::Error),
+
+    #[error("the exact fee `{0}` is lower than the fee `{1}` required by the fee rate")]
+    ExactFeeTooLow(u64, u64),
+
+    #[error("output `{0}` capacity `{1}` is below the dust threshold `{2}`")]
+    DustOutput(usize, u64, u64),
+
+    #[error("resolve header dep failed: `{0}`")]
+    HeaderDep(anyhow::Error),
+
+    #[error("input ordering error: `{0}`")]
+    InputOrdering(String),
+
+    #[error("invalid balancer config: `{0}`")]
+    InvalidConfig(String),
+
+    #[error("get since value failed: `{0}`")]
+    SinceProvider(anyhow::Error),
+
+    #[error("lock script `{0:#x}` is frozen: `{1}`")]
+    FrozenLockScript(H256, String),
+}
+
+/// The max serialized transaction size accepted by the node's tx-pool
+pub const MAX_TX_SIZE: usize = 512_000;
+
+/// The serialized size of a `CellInput`
+pub(crate) const CELL_INPUT_SIZE: u64 = 44;
+/// The serialized size of a `CellDep`
+pub(crate) const CELL_DEP_SIZE: u64 = 37;
+/// The extra size of an item in a dynamic vector (the offset) and the length
+/// header of a `Bytes`
+pub(crate) const WITNESS_HEADER_SIZE: u64 = 4 + 4;
+
+/// The output data size limits of a transaction. The default limits are the
+/// tx-pool max transaction size, set smaller ones to leave room for the
+/// inputs and witnesses.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct DataSizeBudget {
+    /// The max total bytes of all outputs data
+    pub max_total: usize,
+    /// The max bytes of every output data
+    pub max_per_output: usize,
+}
+
+impl Default for DataSizeBudget {
+    fn default() -> DataSizeBudget {
+        DataSizeBudget {
+            max_total: MAX_TX_SIZE,
+            max_per_output: MAX_TX_SIZE,
+        }
+    }
+}
+
+/// The outputs data exceed the [`DataSizeBudget`]
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct DataSizeExceeded {
+    /// The indices of the outputs whose data exceed `max_per_output`
+    pub output_indices: Vec<usize>,
+    pub total: usize,
+    pub budget: DataSizeBudget,
+}
+
+impl std::fmt::Display for DataSizeExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "outputs data size exceed the budget, total: {} (max {}), oversized outputs: {:?} (max {} each)",
+            self.total, self.budget.max_total, self.output_indices, self.budget.max_per_output
+        )
+    }
+}
+
+impl DataSizeBudget {
+    pub fn new(max_total: usize, max_per_output: usize) -> DataSizeBudget {
+        DataSizeBudget {
+            max_total,
+            max_per_output,
+        }
+    }
+
+    /// Check the outputs data of the transaction
+    pub fn check(&self, tx: &TransactionView) -> Result<(), DataSizeExceeded> {
+        let mut total = 0;
+        let mut output_indices = Vec::new();
+        for (index, data) in tx.outputs_data().into_iter().enumerate() {
+            let len = data.raw_data().len();
+            total += len;
+            if len > self.max_per_output {
+                output_indices.push(index);
+            }
+        }
+        if total > self.max_total || !output_indices.is_empty() {
+            return Err(DataSizeExceeded {
+                output_indices,
+                total,
+                budget: *self,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Strict mode config of the balancer, every implicit behavior of the balancer
+/// is forbidden unless explicitly allowed here.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct StrictConfig {
+    /// Allow switching to the next capacity provider lock script when current
+    /// one can not provide enough capacity.
+    pub allow_switch_provider: bool,
+    /// Allow absorbing the small change into transaction fee (requires
+    /// `force_small_change_as_fee` also been set).
+    pub allow_small_change_as_fee: bool,
+    /// Allow reusing the cell dep already in the transaction for the capacity
+    /// provider lock script instead of adding it explicitly.
+    pub allow_reuse_cell_deps: bool,
+}
+
+/// Split the change into multiple outputs, so the following transactions of
+/// a high-throughput sender can spend them in parallel.
+///
+/// The change is split into at most `count` outputs, each holds at least
+/// `min_capacity` (and its occupied capacity). Fewer outputs are created when
+/// the change is not enough, the extra fee of the new outputs is paid by the
+/// change.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct ChangeSplit {
+    pub count: usize,
+    pub min_capacity: u64,
+}
+
+impl ChangeSplit {
+    pub fn new(count: usize, min_capacity: u64) -> ChangeSplit {
+        ChangeSplit {
+            count,
+            min_capacity,
+        }
+    }
+}
+
+/// The template of the change output, the change output gets this type
+/// script and data, its occupied capacity counts them. The lock script is
+/// still `CapacityBalancer::change_lock_script` (or the first capacity
+/// provider lock script).
+///
+/// The balancer does not add the cell dep of the type script, the builder
+/// must add it. A change output with type script is never split, see
+/// [`CapacityBalancer::split_change`].
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct ChangeTemplate {
+    pub type_script: Option<Script>,
+    pub data: Bytes,
+}
+
+impl ChangeTemplate {
+    pub fn new(type_script: Option<Script>, data: Bytes) -> ChangeTemplate {
+        ChangeTemplate { type_script, data }
+    }
+
+    /// The change output with the lock script, the capacity is not set
+    pub fn build_output(&self, lock_script: Script) -> (CellOutput, Bytes) {
+        let output = CellOutput::new_builder()
+            .lock(lock_script)
+            .type_(self.type_script.pack())
+            .build();
+        (output, self.data.clone())
+    }
+}
+
+/// Dust policy of the balancer, avoid creating the cells too small to be
+/// worth spending.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub struct DustPolicy {
+    /// A change cell must hold at least its occupied capacity plus this value
+    /// (in shannons), a smaller change is handled as no change can be created.
+    pub change_threshold: u64,
+    /// Refuse the outputs (except the outputs added by the balancer) with
+    /// capacity below this value (in shannons).
+    pub min_output_capacity: Option<u64>,
+}
+
+impl DustPolicy {
+    pub fn new(change_threshold: u64, min_output_capacity: Option<u64>) -> DustPolicy {
+        DustPolicy {
+            change_threshold,
+            min_output_capacity,
+        }
+    }
+
+    /// Check the outputs of the transaction against `min_output_capacity`
+    pub fn check_outputs(&self, tx: &TransactionView) -> Result<(), BalanceTxCapacityError> {
+        if let Some(min_capacity) = self.min_output_capacity {
+            for (index, output) in tx.outputs().into_iter().enumerate() {
+                let capacity: u64 = output.capacity().unpack();
+                if capacity < min_capacity {
+                    return Err(BalanceTxCapacityError::DustOutput(
+                        index,
+                        capacity,
+                        min_capacity,
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Declare the UDT (sUDT/xUDT) carried by the inputs, the UDT amount of the
+/// inputs not consumed by the outputs is put into a new UDT change output
+/// instead of being burned.
+///
+/// The amount is the first 16 bytes (little endian) of the cell data.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct UdtChange {
+    /// The type script of the UDT
+    pub type_script: Script,
+    /// The lock script of the UDT change output, if `None` use the change
+    /// lock script of the balancer
+    pub lock_script: Option<Script>,
+}
+
+impl UdtChange {
+    pub fn new(type_script: Script, lock_script: Option<Script>) -> UdtChange {
+        UdtChange {
+            type_script,
+            lock_script,
+        }
+    }
+}
+
+fn udt_amount(data: &[u8], index: usize) -> Result<u128, BalanceTxCapacityError> {
+    if data.len() < 16 {
+        return Err(BalanceTxCapacityError::InvalidUdtData(index, data.len()));
+    }
+    let mut amount_bytes = [0u8; 16];
+    amount_bytes.copy_from_slice(&data[0..16]);
+    Ok(u128::from_le_bytes(amount_bytes))
 }
 
 /// Transaction capacity balancer config.
 ///
 /// CapacityBalancer will try to balance the transaction capacity by adding inputs from CapacityProvider.
+///
+/// New options are added over time, so the balancer can not be built by a
+/// struct literal outside this crate, use the constructors or
+/// [`CapacityBalancer::builder`] then the setters.
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct CapacityBalancer {
     pub fee_rate: FeeRate,
 
@@ -415,6 +968,87 @@ pub struct CapacityBalancer {
     /// transaction capacity, force the addition capacity as fee, the value is
     /// actual maximum transaction fee.
     pub force_small_change_as_fee: Option<u64>,
+
+    /// When set, the implicit behaviors of the balancer are forbidden unless
+    /// explicitly allowed by the config, an error will be returned instead.
+    pub strict: Option<StrictConfig>,
+
+    /// Abort building if the transaction fee exceed this value (in shannons).
+    pub max_absolute_fee: Option<u64>,
+
+    /// Abort building if the transaction fee rate exceed this value (in
+    /// shannons/KB).
+    pub max_fee_rate: Option<u64>,
+
+    /// Split the change into multiple outputs, see [`ChangeSplit`].
+    pub change_split: Option<ChangeSplit>,
+
+    /// Abort building if the outputs data exceed the budget.
+    pub data_size_budget: Option<DataSizeBudget>,
+
+    /// The UDT carried by the inputs, see [`UdtChange`].
+    pub udt_changes: Vec<UdtChange>,
+
+    /// When set, `fee_rate` is refreshed from the provider before balancing.
+    pub fee_rate_provider: Option<Arc<dyn FeeRateProvider>>,
+
+    /// Send max mode: spend all the cells of the capacity provider, the output
+    /// at this index receives all the capacity left after the fee, no change
+    /// output is created.
+    pub send_max: Option<usize>,
+
+    /// Exact fee mode: the transaction fee equals this value (in shannons)
+    /// exactly, it must not be lower than the fee required by `fee_rate`.
+    pub exact_fee: Option<u64>,
+
+    /// The dust policy of the change and the outputs, see [`DustPolicy`].
+    pub dust_policy: Option<DustPolicy>,
+
+    /// When set, the fee is paid only by this provider (e.g. a sponsor), the
+    /// `capacity_provider` only provides the capacity of the outputs. Each
+    /// provider gets its own change cell, the fee payer's change cell is
+    /// locked by its first lock script. Ignored in send max and exact fee
+    /// mode.
+    pub fee_payer: Option<CapacityProvider>,
+
+    /// Shuffle the inputs and outputs after balancing, see [`shuffle`].
+    pub shuffle: Option<ShuffleMode>,
+
+    /// Record the balancing decisions, see [`plan`].
+    pub recorder: Option<BalanceRecorder>,
+
+    /// Also use the matured prepared DAO cells of the capacity provider, see
+    /// [`DaoCapacityProvider`].
+    pub dao_provider: Option<DaoCapacityProvider>,
+
+    /// The final witness size estimators of the lock scripts, the transaction
+    /// size used in the fee calculation counts the estimated size instead of
+    /// the placeholder witness size when it is bigger.
+    pub witness_size_estimators: HashMap<ScriptId, Arc<dyn WitnessSizeEstimator>>,
+
+    /// The ordering constraints of the inputs which the balancer must keep,
+    /// see [`ordering`].
+    pub input_ordering: Option<InputOrdering>,
+
+    /// The template of the change output (type script and data), see
+    /// [`ChangeTemplate`].
+    pub change_template: Option<ChangeTemplate>,
+
+    /// Report the cell selection metrics of every balanced transaction, see
+    /// [`metrics`].
+    pub metrics_sink: Option<Arc<dyn SelectionMetricsSink>>,
+
+    /// Partial balancing: when the capacity providers can not cover the
+    /// transaction, return the transaction with all the capacity collected
+    /// instead of `CapacityNotEnough`, the missing capacity is supplied by
+    /// others (e.g. in a multi-party funding flow). See
+    /// [`balance_tx_capacity_partial`]. Ignored in send max and fee payer
+    /// mode.
+    pub allow_partial: bool,
+
+    /// The registry of the frozen lock scripts checked when building and
+    /// signing, the process wide registry is used if not set, see [`freeze`].
+    pub freeze_registry: Option<Arc<FreezeRegistry>>,
 }
 
 impl CapacityBalancer {
@@ -432,15 +1066,10 @@ impl CapacityBalancer {
         placeholder_witness: WitnessArgs,
         fee_rate: u64,
     ) -> CapacityBalancer {
-        CapacityBalancer {
-            fee_rate: FeeRate::from_u64(fee_rate),
-            capacity_provider: CapacityProvider::new_simple(vec![(
-                capacity_provider,
-                placeholder_witness,
-            )]),
-            change_lock_script: None,
-            force_small_change_as_fee: None,
-        }
+        Self::new_with_provider(
+            fee_rate,
+            CapacityProvider::new_simple(vec![(capacity_provider, placeholder_witness)]),
+        )
     }
 
     /// Create new simple capacity balancer with since source.
@@ -450,16 +1079,10 @@ impl CapacityBalancer {
         since_source: SinceSource,
         fee_rate: u64,
     ) -> CapacityBalancer {
-        CapacityBalancer {
-            fee_rate: FeeRate::from_u64(fee_rate),
-            capacity_provider: CapacityProvider::new(vec![(
-                capacity_provider,
-                placeholder_witness,
-                since_source,
-            )]),
-            change_lock_script: None,
-            force_small_change_as_fee: None,
-        }
+        Self::new_with_provider(
+            fee_rate,
+            CapacityProvider::new(vec![(capacity_provider, placeholder_witness, since_source)]),
+        )
     }
 
     pub fn new_with_provider(fee_rate: u64, capacity_provider: CapacityProvider) -> Self {
@@ -468,6 +1091,76 @@ impl CapacityBalancer {
             capacity_provider,
             change_lock_script: None,
             force_small_change_as_fee: None,
+            strict: None,
+            max_absolute_fee: None,
+            max_fee_rate: None,
+            change_split: None,
+            data_size_budget: None,
+            udt_changes: Vec::new(),
+            fee_rate_provider: None,
+            send_max: None,
+            exact_fee: None,
+            dust_policy: None,
+            fee_payer: None,
+            shuffle: None,
+            recorder: None,
+            dao_provider: None,
+            witness_size_estimators: HashMap::new(),
+            input_ordering: None,
+            change_template: None,
+            metrics_sink: None,
+            allow_partial: false,
+            freeze_registry: None,
+        }
+    }
+
+    /// Create a builder of the balancer, the config is validated when built,
+    /// see [`CapacityBalancerBuilder`].
+    pub fn builder() -> CapacityBalancerBuilder {
+        CapacityBalancerBuilder::default()
+    }
+
+    /// Create a balancer with the fee rate provider, the initial fee rate is
+    /// fetched from the provider.
+    pub fn new_with_fee_rate_provider(
+        capacity_provider: CapacityProvider,
+        fee_rate_provider: Arc<dyn FeeRateProvider>,
+    ) -> Result<Self, BalanceTxCapacityError> {
+        let mut balancer = Self::new_with_provider(0, capacity_provider);
+        balancer.set_fee_rate_provider(Some(fee_rate_provider));
+        balancer.refresh_fee_rate()?;
+        Ok(balancer)
+    }
+
+    /// Set or clear the fee rate provider
+    pub fn set_fee_rate_provider(&mut self, fee_rate_provider: Option<Arc<dyn FeeRateProvider>>) {
+        self.fee_rate_provider = fee_rate_provider;
+    }
+
+    /// Update `fee_rate` from the fee rate provider (if set)
+    pub fn refresh_fee_rate(&mut self) -> Result<(), BalanceTxCapacityError> {
+        if let Some(provider) = self.fee_rate_provider.as_ref() {
+            self.fee_rate = provider
+                .fee_rate()
+                .map_err(BalanceTxCapacityError::FeeRate)?;
+        }
+        Ok(())
+    }
+
+    /// Clone the balancer with `fee_rate` refreshed from the fee rate
+    /// provider (if set), the provider is removed from the clone.
+    fn with_refreshed_fee_rate(&self) -> Result<CapacityBalancer, BalanceTxCapacityError> {
+        let mut balancer = self.clone();
+        if balancer.fee_rate_provider.is_some() {
+            balancer.refresh_fee_rate()?;
+            balancer.fee_rate_provider = None;
+        }
+        Ok(balancer)
+    }
+
+    fn record<F: FnOnce(&mut BalanceRecord)>(&self, f: F) {
+        if let Some(recorder) = self.recorder.as_ref() {
+            recorder.record(f);
         }
     }
 
@@ -476,42 +1169,432 @@ impl CapacityBalancer {
         self.force_small_change_as_fee = max_fee;
     }
 
-    pub fn balance_tx_capacity(
-        &mut self,
+    /// Set or clear the fee guardrails, the balancer will return an error
+    /// instead of overpaying when the computed fee exceed the limits.
+    pub fn set_fee_limits(&mut self, max_absolute_fee: Option<u64>, max_fee_rate: Option<u64>) {
+        self.max_absolute_fee = max_absolute_fee;
+        self.max_fee_rate = max_fee_rate;
+    }
+
+    /// Check the fee of a transaction with `tx_size` bytes against the guardrails.
+    pub fn check_fee_limits(&self, fee: u64, tx_size: usize) -> Result<(), BalanceTxCapacityError> {
+        if let Some(max_fee) = self.max_absolute_fee {
+            if fee > max_fee {
+                return Err(BalanceTxCapacityError::FeeExceedLimit(fee, max_fee));
+            }
+        }
+        if let Some(max_fee_rate) = self.max_fee_rate {
+            let fee_rate = fee.saturating_mul(1000) / (tx_size as u64).max(1);
+            if fee_rate > max_fee_rate {
+                return Err(BalanceTxCapacityError::FeeRateExceedLimit(
+                    fee_rate,
+                    max_fee_rate,
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Set or clear the outputs data size budget
+    pub fn set_data_size_budget(&mut self, data_size_budget: Option<DataSizeBudget>) {
+        self.data_size_budget = data_size_budget;
+    }
+
+    /// Check the outputs data of the transaction against the budget
+    pub fn check_data_size(&self, tx: &TransactionView) -> Result<(), BalanceTxCapacityError> {
+        if let Some(budget) = self.data_size_budget.as_ref() {
+            budget
+                .check(tx)
+                .map_err(BalanceTxCapacityError::DataSizeExceeded)?;
+        }
+        Ok(())
+    }
+
+    /// Declare the UDT carried by the inputs
+    pub fn add_udt_change(&mut self, udt_change: UdtChange) {
+        self.udt_changes.push(udt_change);
+    }
+
+    /// Add the UDT change outputs for the declared UDT, the capacity of a
+    /// change output is its occupied capacity.
+    pub fn add_udt_change_outputs(
+        &self,
         tx: &TransactionView,
-        cell_collector: &mut dyn CellCollector,
         tx_dep_provider: &dyn TransactionDependencyProvider,
-        cell_dep_resolver: &dyn CellDepResolver,
-        header_dep_resolver: &dyn HeaderDepResolver,
     ) -> Result<TransactionView, BalanceTxCapacityError> {
-        balance_tx_capacity(
-            tx,
-            self,
-            cell_collector,
-            tx_dep_provider,
-            cell_dep_resolver,
-            header_dep_resolver,
-        )
+        if self.udt_changes.is_empty() {
+            return Ok(tx.clone());
+        }
+        let mut inputs = Vec::with_capacity(tx.inputs().len());
+        for out_point in tx.input_pts_iter() {
+            let output = tx_dep_provider.get_cell(&out_point)?;
+            let data = tx_dep_provider.get_cell_data(&out_point)?;
+            inputs.push((output, data));
+        }
+        let outputs: Vec<_> = tx.outputs_with_data_iter().collect();
+        let mut change_outputs = Vec::new();
+        for udt_change in &self.udt_changes {
+            let type_script = Some(udt_change.type_script.clone());
+            let mut input_total: u128 = 0;
+            for (index, (output, data)) in inputs.iter().enumerate() {
+                if output.type_().to_opt() == type_script {
+                    input_total += udt_amount(data, index)?;
+                }
+            }
+            let mut output_total: u128 = 0;
+            for (index, (output, data)) in outputs.iter().enumerate() {
+                if output.type_().to_opt() == type_script {
+                    output_total += udt_amount(data, index)?;
+                }
+            }
+            if input_total < output_total {
+                return Err(BalanceTxCapacityError::UdtAmountNotEnough(
+                    udt_change.type_script.clone(),
+                    input_total,
+                    output_total,
+                ));
+            }
+            if input_total == output_total {
+                continue;
+            }
+            let lock_script = match udt_change
+                .lock_script
+                .as_ref()
+                .or(self.change_lock_script.as_ref())
+            {
+                Some(lock_script) => lock_script.clone(),
+                None => self
+                    .capacity_provider
+                    .lock_scripts
+                    .first()
+                    .map(|(lock_script, _, _)| lock_script.clone())
+                    .ok_or(BalanceTxCapacityError::EmptyCapacityProvider)?,
+            };
+            let data = Bytes::from((input_total - output_total).to_le_bytes().to_vec());
+            let output = CellOutput::new_builder()
+                .lock(lock_script)
+                .type_(type_script.pack())
+                .build();
+            let capacity = output
+                .occupied_capacity(Capacity::bytes(data.len()).expect("data capacity"))
+                .expect("UDT change occupied capacity");
+            change_outputs.push((output.as_builder().capacity(capacity.pack()).build(), data));
+        }
+        Ok(tx
+            .as_advanced_builder()
+            .outputs(change_outputs.iter().map(|(output, _)| output.clone()))
+            .outputs_data(change_outputs.iter().map(|(_, data)| data.pack()))
+            .build())
     }
 
-    #[allow(clippy::too_many_arguments)]
-    pub fn rebalance_tx_capacity(
+    /// Set or clear the send max mode, see [`CapacityBalancer::send_max`]
+    pub fn set_send_max(&mut self, output_index: Option<usize>) {
+        self.send_max = output_index;
+    }
+
+    /// Set or clear the exact fee, see [`CapacityBalancer::exact_fee`]
+    pub fn set_exact_fee(&mut self, exact_fee: Option<u64>) {
+        self.exact_fee = exact_fee;
+    }
+
+    /// Check the exact fee against the fee required by the fee rate
+    fn check_exact_fee(
         &self,
-        tx: &TransactionView,
-        cell_collector: &mut dyn CellCollector,
-        tx_dep_provider: &dyn TransactionDependencyProvider,
-        cell_dep_resolver: &dyn CellDepResolver,
-        header_dep_resolver: &dyn HeaderDepResolver,
-        accepted_min_fee: u64,
-        change_index: Option<usize>,
-    ) -> Result<(TransactionView, Option<usize>), BalanceTxCapacityError> {
-        if let Some(idx) = change_index {
-            let output = tx
+        exact_fee: u64,
+        tx_size: usize,
+    ) -> Result<(), BalanceTxCapacityError> {
+        let required_fee = self.fee_rate.fee(tx_size as u64).as_u64();
+        if exact_fee < required_fee {
+            return Err(BalanceTxCapacityError::ExactFeeTooLow(
+                exact_fee,
+                required_fee,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Set or clear the dust policy
+    pub fn set_dust_policy(&mut self, dust_policy: Option<DustPolicy>) {
+        self.dust_policy = dust_policy;
+    }
+
+    /// The capacity a change cell must hold above its occupied capacity
+    fn change_threshold(&self) -> u64 {
+        self.dust_policy
+            .map(|dust_policy| dust_policy.change_threshold)
+            .unwrap_or(0)
+    }
+
+    /// Set or clear the fee payer, see [`CapacityBalancer::fee_payer`]
+    pub fn set_fee_payer(&mut self, fee_payer: Option<CapacityProvider>) {
+        self.fee_payer = fee_payer;
+    }
+
+    /// Set or clear the shuffle mode, see [`CapacityBalancer::shuffle`]
+    pub fn set_shuffle(&mut self, shuffle: Option<ShuffleMode>) {
+        self.shuffle = shuffle;
+    }
+
+    /// Set or clear the DAO capacity provider, see
+    /// [`CapacityBalancer::dao_provider`]
+    pub fn set_dao_provider(&mut self, dao_provider: Option<DaoCapacityProvider>) {
+        self.dao_provider = dao_provider;
+    }
+
+    /// Set the final witness size estimator of the lock scripts with
+    /// `script_id`, see [`CapacityBalancer::witness_size_estimators`]
+    pub fn set_witness_size_estimator(
+        &mut self,
+        script_id: ScriptId,
+        estimator: Arc<dyn WitnessSizeEstimator>,
+    ) {
+        self.witness_size_estimators.insert(script_id, estimator);
+    }
+
+    /// Set or clear the input ordering constraints, see
+    /// [`CapacityBalancer::input_ordering`]
+    pub fn set_input_ordering(&mut self, input_ordering: Option<InputOrdering>) {
+        self.input_ordering = input_ordering;
+    }
+
+    /// Set or clear the freeze registry, see
+    /// [`CapacityBalancer::freeze_registry`]
+    pub fn set_freeze_registry(&mut self, freeze_registry: Option<Arc<FreezeRegistry>>) {
+        self.freeze_registry = freeze_registry;
+    }
+
+    /// The freeze registry in use, the process wide one if not set
+    pub fn freeze_registry(&self) -> &FreezeRegistry {
+        self.freeze_registry
+            .as_deref()
+            .unwrap_or_else(FreezeRegistry::global)
+    }
+
+    /// Refuse to build the base transaction spending from a frozen lock
+    /// script
+    fn check_frozen_base_tx(
+        &self,
+        base_tx: &TransactionView,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<(), TxBuilderError> {
+        match self
+            .freeze_registry()
+            .check_frozen_inputs(base_tx, tx_dep_provider, "build")?
+        {
+            Some((lock_hash, reason)) => Err(TxBuilderError::FrozenLockScript(lock_hash, reason)),
+            None => Ok(()),
+        }
+    }
+
+    /// The balancer with the input ordering constraints of the builder added
+    fn with_input_ordering(&self, input_ordering: Option<InputOrdering>) -> CapacityBalancer {
+        let mut balancer = self.clone();
+        if let Some(input_ordering) = input_ordering.filter(|ordering| !ordering.is_empty()) {
+            balancer
+                .input_ordering
+                .get_or_insert_with(InputOrdering::default)
+                .extend(input_ordering);
+        }
+        balancer
+    }
+
+    /// The transaction size used in the fee calculation, the first witness of
+    /// every lock script group is counted by its estimated final size if it
+    /// is bigger than the current one.
+    pub fn estimated_tx_size(
+        &self,
+        tx: &TransactionView,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<usize, BalanceTxCapacityError> {
+        let tx_size = tx.data().as_reader().serialized_size_in_block();
+        if self.witness_size_estimators.is_empty() {
+            return Ok(tx_size);
+        }
+        let mut extra_size = 0;
+        #[allow(clippy::mutable_key_type)]
+        let mut lock_scripts = HashSet::new();
+        for (idx, out_point) in tx.input_pts_iter().enumerate() {
+            let lock_script = tx_dep_provider.get_cell(&out_point)?.lock();
+            if !lock_scripts.insert(lock_script.clone()) {
+                continue;
+            }
+            let estimated_size = self
+                .witness_size_estimators
+                .get(&ScriptId::from(&lock_script))
+                .and_then(|estimator| estimator.estimated_witness_size(&lock_script));
+            if let Some(estimated_size) = estimated_size {
+                let witness_size = tx
+                    .witnesses()
+                    .get(idx)
+                    .map(|witness| witness.raw_data().len())
+                    .unwrap_or_default();
+                extra_size += estimated_size.saturating_sub(witness_size);
+            }
+        }
+        Ok(tx_size + extra_size)
+    }
+
+    /// Set or clear the change output template, see
+    /// [`CapacityBalancer::change_template`]
+    pub fn set_change_template(&mut self, change_template: Option<ChangeTemplate>) {
+        self.change_template = change_template;
+    }
+
+    /// Set or clear the metrics sink, see [`CapacityBalancer::metrics_sink`]
+    pub fn set_metrics_sink(&mut self, metrics_sink: Option<Arc<dyn SelectionMetricsSink>>) {
+        self.metrics_sink = metrics_sink;
+    }
+
+    /// Enable or disable the partial balancing, see
+    /// [`CapacityBalancer::allow_partial`]
+    pub fn set_allow_partial(&mut self, allow_partial: bool) {
+        self.allow_partial = allow_partial;
+    }
+
+    /// Set or clear the change split config
+    pub fn set_change_split(&mut self, change_split: Option<ChangeSplit>) {
+        self.change_split = change_split;
+    }
+
+    /// Split the change output at `change_index` by the change split config,
+    /// return the transaction unchanged if not configured, the change is not
+    /// enough to split or the change output has a type script.
+    pub fn split_change(
+        &self,
+        tx: TransactionView,
+        change_index: usize,
+    ) -> Result<TransactionView, BalanceTxCapacityError> {
+        let change_split = match self.change_split {
+            Some(change_split) if change_split.count > 1 => change_split,
+            _ => return Ok(tx),
+        };
+        let output = tx
+            .outputs()
+            .get(change_index)
+            .ok_or(BalanceTxCapacityError::ChangeIndexNotFound(change_index))?;
+        // The type script may not allow copying the data (e.g. UDT amount)
+        if output.type_().is_some() {
+            return Ok(tx);
+        }
+        let data = tx
+            .outputs_data()
+            .get(change_index)
+            .map(|data| data.raw_data())
+            .unwrap_or_default();
+        let capacity: u64 = output.capacity().unpack();
+        let occupied_capacity = output
+            .occupied_capacity(Capacity::bytes(data.len()).expect("data capacity"))
+            .expect("change occupied capacity")
+            .as_u64();
+        let min_capacity = change_split.min_capacity.max(occupied_capacity);
+        // The output extra header size is for output offset, output data offset
+        // and output data length. NOTE: +1 is for `FeeRate::fee` round
+        let extra_fee = self
+            .fee_rate
+            .fee((output.as_slice().len() + data.len()) as u64 + 4 + 4 + 4)
+            .as_u64()
+            + 1;
+        for count in (2..=change_split.count as u64).rev() {
+            let total = match capacity.checked_sub(extra_fee * (count - 1)) {
+                Some(total) => total,
+                None => continue,
+            };
+            let part = total / count;
+            if part < min_capacity {
+                continue;
+            }
+            let mut outputs: Vec<_> = tx.outputs().into_iter().collect();
+            let mut outputs_data: Vec<_> = tx.outputs_data().into_iter().collect();
+            // The first part takes the remainder
+            outputs[change_index] = output
+                .clone()
+                .as_builder()
+                .capacity((total - part * (count - 1)).pack())
+                .build();
+            for _ in 1..count {
+                outputs.push(output.clone().as_builder().capacity(part.pack()).build());
+                outputs_data.push(data.pack());
+            }
+            let new_tx = tx
+                .as_advanced_builder()
+                .set_outputs(outputs)
+                .set_outputs_data(outputs_data)
+                .build();
+            return Ok(new_tx);
+        }
+        Ok(tx)
+    }
+
+    /// Set or clear the strict mode config
+    pub fn set_strict(&mut self, strict: Option<StrictConfig>) {
+        self.strict = strict;
+    }
+
+    fn check_strict<F>(&self, allowed: F, behavior: &str) -> Result<(), BalanceTxCapacityError>
+    where
+        F: Fn(&StrictConfig) -> bool,
+    {
+        match self.strict.as_ref() {
+            Some(strict) if !allowed(strict) => Err(BalanceTxCapacityError::StrictModeViolation(
+                behavior.to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn balance_tx_capacity(
+        &mut self,
+        tx: &TransactionView,
+        cell_collector: &mut dyn CellCollector,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+        cell_dep_resolver: &dyn CellDepResolver,
+        header_dep_resolver: &dyn HeaderDepResolver,
+    ) -> Result<TransactionView, BalanceTxCapacityError> {
+        balance_tx_capacity(
+            tx,
+            self,
+            cell_collector,
+            tx_dep_provider,
+            cell_dep_resolver,
+            header_dep_resolver,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn rebalance_tx_capacity(
+        &self,
+        tx: &TransactionView,
+        cell_collector: &mut dyn CellCollector,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+        cell_dep_resolver: &dyn CellDepResolver,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        accepted_min_fee: u64,
+        change_index: Option<usize>,
+    ) -> Result<(TransactionView, Option<usize>), BalanceTxCapacityError> {
+        if let Some(exact_fee) = self.exact_fee {
+            if exact_fee < accepted_min_fee {
+                return Err(BalanceTxCapacityError::ExactFeeTooLow(
+                    exact_fee,
+                    accepted_min_fee,
+                ));
+            }
+            return Err(BalanceTxCapacityError::AlreadyBalance(
+                exact_fee,
+                accepted_min_fee,
+            ));
+        }
+        if let Some(idx) = change_index {
+            let output = tx
                 .outputs()
                 .get(idx)
                 .ok_or(BalanceTxCapacityError::ChangeIndexNotFound(idx))?;
+            let output_data_len = tx
+                .outputs_data()
+                .get(idx)
+                .map(|data| data.raw_data().len())
+                .unwrap_or_default();
             let base_change_occupied_capacity = output
-                .occupied_capacity(Capacity::zero())
+                .occupied_capacity(Capacity::bytes(output_data_len).expect("data capacity"))
                 .expect("init change occupied capacity")
                 .as_u64();
             let output_header_extra = 4 + 4 + 4;
@@ -531,7 +1614,12 @@ impl CapacityBalancer {
             let extra_fee = accepted_min_fee - original_fee;
             // The extra capacity (delta - extra_min_fee) is enough to hold the change cell.
             let original_capacity: u64 = output.capacity().unpack();
-            if original_capacity >= base_change_occupied_capacity + extra_min_fee + extra_fee {
+            if original_capacity
+                >= base_change_occupied_capacity
+                    + self.change_threshold()
+                    + extra_min_fee
+                    + extra_fee
+            {
                 let output = output
                     .as_builder()
                     .capacity((original_capacity - extra_fee).pack())
@@ -539,11 +1627,21 @@ impl CapacityBalancer {
                 let mut outputs: Vec<_> = tx.outputs().into_iter().collect();
                 outputs[idx] = output;
                 let tx = tx.as_advanced_builder().set_outputs(outputs).build();
+                let tx_size = tx.data().as_reader().serialized_size_in_block();
+                self.check_fee_limits(accepted_min_fee, tx_size)?;
                 return Ok((tx, change_index));
             };
         }
+        if self.send_max.is_some() {
+            // All the cells are spent, only the send max output can pay the
+            // extra fee
+            return Err(BalanceTxCapacityError::CapacityNotEnough(format!(
+                "the send max output can not pay the fee, required fee: {}",
+                HumanCapacity(accepted_min_fee)
+            )));
+        }
 
-        rebalance_tx_capacity(
+        let (tx, change_index) = rebalance_tx_capacity(
             tx,
             self,
             cell_collector,
@@ -552,7 +1650,47 @@ impl CapacityBalancer {
             header_dep_resolver,
             accepted_min_fee,
             change_index,
-        )
+        )?;
+        match self.input_ordering.as_ref() {
+            Some(input_ordering) => Ok((input_ordering.apply(&tx, tx_dep_provider)?, change_index)),
+            None => Ok((tx, change_index)),
+        }
+    }
+
+    /// Check if the unlocked transaction still meets the fee rate, since the
+    /// unlocked witnesses may be bigger than the placeholders used when
+    /// balancing `placeholder_tx` (e.g. a signature bigger than expected).
+    ///
+    /// Return `None` if no witness grows or the fee is still enough. In exact
+    /// fee mode the transaction can not be rebalanced, `ExactFeeTooLow` is
+    /// returned if the exact fee is not enough for the unlocked transaction.
+    pub fn check_unlocked_fee(
+        &self,
+        placeholder_tx: &TransactionView,
+        unlocked_tx: &TransactionView,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+        header_dep_resolver: &dyn HeaderDepResolver,
+    ) -> Result<Option<WitnessSizeWarning>, BalanceTxCapacityError> {
+        let regressions = witness_size_regressions(placeholder_tx, unlocked_tx);
+        if regressions.is_empty() {
+            return Ok(None);
+        }
+        let tx_size = unlocked_tx.data().as_reader().serialized_size_in_block();
+        if let Some(exact_fee) = self.exact_fee {
+            // The fee is fixed, rebalancing would change it
+            self.check_exact_fee(exact_fee, tx_size)?;
+            return Ok(None);
+        }
+        let required_fee = self.fee_rate.fee(tx_size as u64).as_u64();
+        let fee = tx_fee(unlocked_tx.clone(), tx_dep_provider, header_dep_resolver)?;
+        if fee >= required_fee {
+            return Ok(None);
+        }
+        Ok(Some(WitnessSizeWarning {
+            regressions,
+            fee,
+            required_fee,
+        }))
     }
 
     pub fn check_cycle_fee(
@@ -591,6 +1729,207 @@ impl CapacityBalancer {
     }
 }
 
+/// The max args size of the change lock script accepted by
+/// [`CapacityBalancerBuilder`]
+pub const MAX_CHANGE_LOCK_ARGS_SIZE: usize = 1024;
+
+/// Builder of [`CapacityBalancer`], the inconsistent configs are rejected by
+/// [`CapacityBalancerBuilder::build`] instead of failing (or overpaying) at
+/// balancing time.
+///
+/// ```ignore
+/// let balancer = CapacityBalancer::builder()
+///     .fee_rate(1000)
+///     .capacity_provider(sender, placeholder_witness)
+///     .change_lock_script(change_lock)
+///     .build()?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CapacityBalancerBuilder {
+    fee_rate: u64,
+    lock_scripts: Vec<(Script, WitnessArgs, SinceSource)>,
+    change_lock_script: Option<Script>,
+    force_small_change_as_fee: Option<u64>,
+    dust_policy: Option<DustPolicy>,
+    strict: Option<StrictConfig>,
+    max_absolute_fee: Option<u64>,
+    max_fee_rate: Option<u64>,
+    send_max: Option<usize>,
+    exact_fee: Option<u64>,
+    fee_payer: Option<CapacityProvider>,
+}
+
+impl CapacityBalancerBuilder {
+    /// The fee rate (in shannons/KB)
+    pub fn fee_rate(mut self, fee_rate: u64) -> Self {
+        self.fee_rate = fee_rate;
+        self
+    }
+
+    /// Add a capacity provider lock script with the default since source
+    pub fn capacity_provider(self, lock_script: Script, placeholder_witness: WitnessArgs) -> Self {
+        self.capacity_provider_with_since(lock_script, placeholder_witness, SinceSource::default())
+    }
+
+    /// Add a capacity provider lock script
+    pub fn capacity_provider_with_since(
+        mut self,
+        lock_script: Script,
+        placeholder_witness: WitnessArgs,
+        since_source: SinceSource,
+    ) -> Self {
+        self.lock_scripts
+            .push((lock_script, placeholder_witness, since_source));
+        self
+    }
+
+    /// Set the change lock script, see [`CapacityBalancer::change_lock_script`]
+    pub fn change_lock_script(mut self, change_lock_script: Script) -> Self {
+        self.change_lock_script = Some(change_lock_script);
+        self
+    }
+
+    /// Set the max fee when the small change is forced as fee, see
+    /// [`CapacityBalancer::force_small_change_as_fee`]
+    pub fn force_small_change_as_fee(mut self, max_fee: u64) -> Self {
+        self.force_small_change_as_fee = Some(max_fee);
+        self
+    }
+
+    /// Set the dust policy, see [`CapacityBalancer::dust_policy`]
+    pub fn dust_policy(mut self, dust_policy: DustPolicy) -> Self {
+        self.dust_policy = Some(dust_policy);
+        self
+    }
+
+    /// Enable the strict mode, see [`CapacityBalancer::strict`]
+    pub fn strict(mut self, strict: StrictConfig) -> Self {
+        self.strict = Some(strict);
+        self
+    }
+
+    /// Set the fee guardrails, see [`CapacityBalancer::set_fee_limits`]
+    pub fn fee_limits(mut self, max_absolute_fee: Option<u64>, max_fee_rate: Option<u64>) -> Self {
+        self.max_absolute_fee = max_absolute_fee;
+        self.max_fee_rate = max_fee_rate;
+        self
+    }
+
+    /// Enable the send max mode, see [`CapacityBalancer::send_max`]
+    pub fn send_max(mut self, output_index: usize) -> Self {
+        self.send_max = Some(output_index);
+        self
+    }
+
+    /// Enable the exact fee mode, see [`CapacityBalancer::exact_fee`]
+    pub fn exact_fee(mut self, exact_fee: u64) -> Self {
+        self.exact_fee = Some(exact_fee);
+        self
+    }
+
+    /// Set the fee payer, see [`CapacityBalancer::fee_payer`]
+    pub fn fee_payer(mut self, fee_payer: CapacityProvider) -> Self {
+        self.fee_payer = Some(fee_payer);
+        self
+    }
+
+    /// Validate the config and build the balancer
+    pub fn build(self) -> Result<CapacityBalancer, BalanceTxCapacityError> {
+        if self.lock_scripts.is_empty() {
+            return Err(BalanceTxCapacityError::EmptyCapacityProvider);
+        }
+        if self.fee_payer.is_some() && (self.send_max.is_some() || self.exact_fee.is_some()) {
+            return Err(BalanceTxCapacityError::InvalidConfig(
+                "the fee payer is ignored in send max and exact fee mode".to_string(),
+            ));
+        }
+        for (idx, (lock_script, _, _)) in self.lock_scripts.iter().enumerate() {
+            if self.lock_scripts[..idx]
+                .iter()
+                .any(|(other, _, _)| other == lock_script)
+            {
+                return Err(BalanceTxCapacityError::InvalidConfig(format!(
+                    "duplicated capacity provider lock script: {}",
+                    lock_script
+                )));
+            }
+        }
+        if let Some(change_lock_script) = self.change_lock_script.as_ref() {
+            let args_size = change_lock_script.args().raw_data().len();
+            if args_size > MAX_CHANGE_LOCK_ARGS_SIZE {
+                return Err(BalanceTxCapacityError::InvalidConfig(format!(
+                    "change lock script args size {} exceed the limit {}",
+                    args_size, MAX_CHANGE_LOCK_ARGS_SIZE
+                )));
+            }
+        }
+        let mut balancer = CapacityBalancer::new_with_provider(
+            self.fee_rate,
+            CapacityProvider::new(self.lock_scripts),
+        );
+        balancer.change_lock_script = self.change_lock_script;
+        balancer.force_small_change_as_fee = self.force_small_change_as_fee;
+        balancer.dust_policy = self.dust_policy;
+        balancer.strict = self.strict;
+        balancer.max_absolute_fee = self.max_absolute_fee;
+        balancer.max_fee_rate = self.max_fee_rate;
+        balancer.send_max = self.send_max;
+        balancer.exact_fee = self.exact_fee;
+        balancer.fee_payer = self.fee_payer;
+        Ok(balancer)
+    }
+}
+
+/// A witness which is bigger after unlock than the placeholder used when
+/// balancing the transaction.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct WitnessSizeRegression {
+    pub witness_index: usize,
+    pub placeholder_size: usize,
+    pub unlocked_size: usize,
+}
+
+/// The unlocked transaction fee can not meet the fee rate because of the
+/// witness size regressions.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct WitnessSizeWarning {
+    pub regressions: Vec<WitnessSizeRegression>,
+    /// The actual fee of the unlocked transaction
+    pub fee: u64,
+    /// The required fee of the unlocked transaction by the fee rate
+    pub required_fee: u64,
+}
+
+/// Compare each witness of the unlocked transaction with the placeholder one,
+/// return the witnesses which become bigger.
+pub fn witness_size_regressions(
+    placeholder_tx: &TransactionView,
+    unlocked_tx: &TransactionView,
+) -> Vec<WitnessSizeRegression> {
+    let placeholder_witnesses = placeholder_tx.witnesses();
+    unlocked_tx
+        .witnesses()
+        .into_iter()
+        .enumerate()
+        .filter_map(|(witness_index, witness)| {
+            let placeholder_size = placeholder_witnesses
+                .get(witness_index)
+                .map(|placeholder| placeholder.raw_data().len())
+                .unwrap_or(0);
+            let unlocked_size = witness.raw_data().len();
+            if unlocked_size > placeholder_size {
+                Some(WitnessSizeRegression {
+                    witness_index,
+                    placeholder_size,
+                    unlocked_size,
+                })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
 const DEFAULT_BYTES_PER_CYCLE: f64 = 0.000_170_571_4;
 pub const fn bytes_per_cycle() -> f64 {
     DEFAULT_BYTES_PER_CYCLE
@@ -657,17 +1996,578 @@ pub fn balance_tx_capacity(
     cell_dep_resolver: &dyn CellDepResolver,
     header_dep_resolver: &dyn HeaderDepResolver,
 ) -> Result<TransactionView, BalanceTxCapacityError> {
-    let (tx, _change_idx) = rebalance_tx_capacity(
+    balance_tx_capacity_with_change(
         tx,
         balancer,
         cell_collector,
         tx_dep_provider,
         cell_dep_resolver,
         header_dep_resolver,
+    )
+    .map(|(tx, _)| tx)
+}
+
+/// Same as [`balance_tx_capacity`], also return a report of the balancing.
+///
+/// To inspect a failed balancing (e.g. `CapacityNotEnough`), set
+/// [`CapacityBalancer::recorder`] and call [`balance_tx_capacity`], the
+/// recorder keeps the selected cells and the number of collector queries.
+pub fn balance_tx_capacity_with_report(
+    tx: &TransactionView,
+    balancer: &CapacityBalancer,
+    cell_collector: &mut dyn CellCollector,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+    cell_dep_resolver: &dyn CellDepResolver,
+    header_dep_resolver: &dyn HeaderDepResolver,
+) -> Result<(TransactionView, BalanceReport), BalanceTxCapacityError> {
+    let recorder = BalanceRecorder::new();
+    let mut balancer = balancer.clone();
+    balancer.recorder = Some(recorder.clone());
+    let (tx, change_idx) = balance_tx_capacity_with_change(
+        tx,
+        &balancer,
+        cell_collector,
+        tx_dep_provider,
+        cell_dep_resolver,
+        header_dep_resolver,
+    )?;
+    let report = BalanceReport::new(
+        &tx,
+        change_idx,
+        recorder.take(),
+        tx_dep_provider,
+        header_dep_resolver,
+    )?;
+    Ok((tx, report))
+}
+
+/// Same as [`balance_tx_capacity`], but when the capacity providers can not
+/// cover the transaction, return the transaction with all the capacity
+/// collected and the missing capacity (in shannons) instead of
+/// `CapacityNotEnough`, see [`CapacityBalancer::allow_partial`]. The missing
+/// capacity is `0` if the transaction is balanced.
+///
+/// Another participant can supply the rest by adding inputs (and its own
+/// change output) to the returned transaction, e.g. with another balancing.
+pub fn balance_tx_capacity_partial(
+    tx: &TransactionView,
+    balancer: &CapacityBalancer,
+    cell_collector: &mut dyn CellCollector,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+    cell_dep_resolver: &dyn CellDepResolver,
+    header_dep_resolver: &dyn HeaderDepResolver,
+) -> Result<(TransactionView, u64), BalanceTxCapacityError> {
+    let recorder = BalanceRecorder::new();
+    let mut balancer = balancer.clone();
+    balancer.recorder = Some(recorder.clone());
+    balancer.allow_partial = true;
+    let (tx, _) = balance_tx_capacity_with_change(
+        tx,
+        &balancer,
+        cell_collector,
+        tx_dep_provider,
+        cell_dep_resolver,
+        header_dep_resolver,
+    )?;
+    Ok((tx, recorder.take().missing_capacity))
+}
+
+/// The capacity a transaction needs from the capacity providers, see
+/// [`estimate_required_capacity`].
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub struct CapacityEstimate {
+    /// The total capacity of the outputs of the builder
+    pub outputs_capacity: u64,
+    /// The estimated fee with one capacity provider input and no change
+    /// output
+    pub fee: u64,
+    /// The fee of every more capacity provider input
+    pub fee_per_input: u64,
+    /// The capacity the capacity providers must supply, the outputs capacity
+    /// and the fee minus the capacity of the inputs of the builder
+    pub required_capacity: u64,
+    /// The min capacity of the change output
+    pub change_capacity: u64,
+    /// The fee of the change output
+    pub change_fee: u64,
+}
+
+impl CapacityEstimate {
+    /// The capacity required to also create the change output
+    pub fn required_capacity_with_change(&self) -> u64 {
+        self.required_capacity
+            .saturating_add(self.change_capacity)
+            .saturating_add(self.change_fee)
+    }
+}
+
+/// Estimate how much capacity the transaction of the builder needs from the
+/// capacity providers of the balancer, without collecting the capacity cells
+/// or changing the state of the cell collector (the base transaction is
+/// built with a clone of the cell collector). So the "insufficient balance"
+/// can be shown before building, e.g. compare the estimate with
+/// [`CapacityProvider::report`].
+///
+/// The estimate assumes one capacity provider input is added, the
+/// placeholder witness and the cell dep of the first capacity provider are
+/// counted if they are not in the transaction.
+#[allow(clippy::too_many_arguments)]
+pub fn estimate_required_capacity(
+    builder: &dyn TxBuilder,
+    balancer: &CapacityBalancer,
+    cell_collector: &dyn CellCollector,
+    cell_dep_resolver: &dyn CellDepResolver,
+    header_dep_resolver: &dyn HeaderDepResolver,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+    unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+) -> Result<CapacityEstimate, TxBuilderError> {
+    let mut cell_collector = dyn_clone::clone_box(cell_collector);
+    let base_tx = builder.build_base(
+        cell_collector.as_mut(),
+        cell_dep_resolver,
+        header_dep_resolver,
+        tx_dep_provider,
+    )?;
+    let (base_tx, _) = fill_placeholder_witnesses(base_tx, tx_dep_provider, unlockers)?;
+    let balancer = balancer.with_refreshed_fee_rate()?;
+    let (lock_script, placeholder_witness, _) = balancer
+        .capacity_provider
+        .lock_scripts
+        .first()
+        .ok_or(BalanceTxCapacityError::EmptyCapacityProvider)?;
+
+    let mut has_provider = false;
+    for out_point in base_tx.input_pts_iter() {
+        if tx_dep_provider.get_cell(&out_point)?.lock() == *lock_script {
+            has_provider = true;
+        }
+    }
+    let input_size = CELL_INPUT_SIZE + WITNESS_HEADER_SIZE;
+    let padding = base_tx
+        .inputs()
+        .len()
+        .saturating_sub(base_tx.witnesses().len()) as u64
+        * WITNESS_HEADER_SIZE;
+    let mut tx_size =
+        balancer.estimated_tx_size(&base_tx, tx_dep_provider)? as u64 + padding + input_size;
+    if !has_provider {
+        tx_size += placeholder_witness.as_slice().len() as u64;
+    }
+    let has_cell_dep = cell_dep_resolver
+        .resolve(lock_script)
+        .map_or(true, |cell_dep| {
+            base_tx.cell_deps().into_iter().any(|item| item == cell_dep)
+        });
+    if !has_cell_dep {
+        tx_size += CELL_DEP_SIZE;
+    }
+    let fee = balancer
+        .exact_fee
+        .unwrap_or_else(|| balancer.fee_rate.fee(tx_size).as_u64());
+    let base_fee = match tx_fee(base_tx.clone(), tx_dep_provider, header_dep_resolver) {
+        Ok(fee) => fee as i128,
+        Err(TransactionFeeError::CapacityOverflow(delta)) => -(delta as i128),
+        Err(err) => return Err(BalanceTxCapacityError::from(err).into()),
+    };
+    let required_capacity = (fee as i128 - base_fee).max(0).min(u64::MAX as i128) as u64;
+
+    let change_lock_script = balancer
+        .change_lock_script
+        .clone()
+        .unwrap_or_else(|| lock_script.clone());
+    let (change_output, change_output_data) = balancer
+        .change_template
+        .clone()
+        .unwrap_or_default()
+        .build_output(change_lock_script);
+    let change_capacity = change_output
+        .occupied_capacity(Capacity::bytes(change_output_data.len()).expect("data capacity"))
+        .expect("change occupied capacity")
+        .as_u64()
+        + balancer.change_threshold();
+    // The output header, the output data header and the output data offset
+    let output_header_extra = 4 + 4 + 4;
+    let change_fee = balancer
+        .fee_rate
+        .fee(
+            (change_output.as_slice().len() + change_output_data.len()) as u64
+                + output_header_extra,
+        )
+        .as_u64();
+
+    let outputs_capacity = base_tx
+        .outputs()
+        .into_iter()
+        .map(|output| Unpack::<u64>::unpack(&output.capacity()))
+        .sum();
+    Ok(CapacityEstimate {
+        outputs_capacity,
+        fee,
+        fee_per_input: balancer.fee_rate.fee(input_size).as_u64(),
+        required_capacity,
+        change_capacity,
+        change_fee,
+    })
+}
+
+/// Same as [`balance_tx_capacity`], also return the index of the output
+/// which can be reduced to pay more fee (the change output or the send max
+/// output).
+fn balance_tx_capacity_with_change(
+    tx: &TransactionView,
+    balancer: &CapacityBalancer,
+    cell_collector: &mut dyn CellCollector,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+    cell_dep_resolver: &dyn CellDepResolver,
+    header_dep_resolver: &dyn HeaderDepResolver,
+) -> Result<(TransactionView, Option<usize>), BalanceTxCapacityError> {
+    let provider_lock_hashes = balancer
+        .capacity_provider
+        .lock_scripts
+        .iter()
+        .map(|(lock_script, _, _)| lock_script.calc_script_hash().unpack());
+    let freeze_registry = balancer.freeze_registry();
+    if let Some((lock_hash, reason)) = freeze_registry
+        .check_frozen_inputs(tx, tx_dep_provider, "balance")?
+        .or_else(|| freeze_registry.check_frozen(provider_lock_hashes, "balance"))
+    {
+        return Err(BalanceTxCapacityError::FrozenLockScript(lock_hash, reason));
+    }
+    if balancer.fee_rate_provider.is_some() {
+        return balance_tx_capacity_with_change(
+            tx,
+            &balancer.with_refreshed_fee_rate()?,
+            cell_collector,
+            tx_dep_provider,
+            cell_dep_resolver,
+            header_dep_resolver,
+        );
+    }
+    if let Some(metrics_sink) = balancer.metrics_sink.as_ref() {
+        let mut inner_balancer = balancer.clone();
+        inner_balancer.metrics_sink = None;
+        let (new_tx, change_idx) = balance_tx_capacity_with_change(
+            tx,
+            &inner_balancer,
+            cell_collector,
+            tx_dep_provider,
+            cell_dep_resolver,
+            header_dep_resolver,
+        )?;
+        let metrics = SelectionMetrics::new(
+            tx,
+            &new_tx,
+            change_idx,
+            &inner_balancer,
+            tx_dep_provider,
+            header_dep_resolver,
+        )?;
+        metrics_sink.record(&metrics);
+        return Ok((new_tx, change_idx));
+    }
+    if let Some(input_ordering) = balancer.input_ordering.as_ref() {
+        let mut inner_balancer = balancer.clone();
+        inner_balancer.input_ordering = None;
+        let (tx, change_idx) = balance_tx_capacity_with_change(
+            tx,
+            &inner_balancer,
+            cell_collector,
+            tx_dep_provider,
+            cell_dep_resolver,
+            header_dep_resolver,
+        )?;
+        // Only the inputs are reordered, the change index is kept
+        return Ok((input_ordering.apply(&tx, tx_dep_provider)?, change_idx));
+    }
+    if let Some(shuffle) = balancer.shuffle {
+        let mut inner_balancer = balancer.clone();
+        inner_balancer.shuffle = None;
+        let (tx, change_idx) = balance_tx_capacity_with_change(
+            tx,
+            &inner_balancer,
+            cell_collector,
+            tx_dep_provider,
+            cell_dep_resolver,
+            header_dep_resolver,
+        )?;
+        let (tx, output_order) =
+            shuffle::shuffle_tx_with_output_order(&tx, tx_dep_provider, shuffle.seed())?;
+        let change_idx =
+            change_idx.and_then(|idx| output_order.iter().position(|old_idx| *old_idx == idx));
+        return Ok((tx, change_idx));
+    }
+    balancer.check_data_size(tx)?;
+    if let Some(dust_policy) = balancer.dust_policy.as_ref() {
+        dust_policy.check_outputs(tx)?;
+    }
+    let tx = balancer.add_udt_change_outputs(tx, tx_dep_provider)?;
+    let tx = match balancer.dao_provider.as_ref() {
+        Some(dao_provider) => dao_provider.add_inputs(
+            &tx,
+            balancer,
+            cell_collector,
+            tx_dep_provider,
+            cell_dep_resolver,
+            header_dep_resolver,
+        )?,
+        None => tx,
+    };
+    if let Some(output_index) = balancer.send_max {
+        let tx = balance_tx_capacity_send_max(
+            &tx,
+            balancer,
+            output_index,
+            cell_collector,
+            tx_dep_provider,
+            cell_dep_resolver,
+            header_dep_resolver,
+        )?;
+        return Ok((tx, Some(output_index)));
+    }
+    if let Some(exact_fee) = balancer.exact_fee {
+        // Balance with zero fee rate and the exact fee as the min fee, the
+        // change is adjusted until the fee equals the min fee.
+        let mut exact_balancer = balancer.clone();
+        exact_balancer.fee_rate = FeeRate::from_u64(0);
+        exact_balancer.force_small_change_as_fee = None;
+        let (tx, change_idx) = rebalance_tx_capacity(
+            &tx,
+            &exact_balancer,
+            cell_collector,
+            tx_dep_provider,
+            cell_dep_resolver,
+            header_dep_resolver,
+            exact_fee,
+            None,
+        )?;
+        let tx_size = tx.data().as_reader().serialized_size_in_block();
+        balancer.check_exact_fee(exact_fee, tx_size)?;
+        return Ok((tx, change_idx));
+    }
+    if let Some(fee_payer) = balancer.fee_payer.as_ref() {
+        // Balance the outputs capacity with zero fee first, then the fee is
+        // paid by the fee payer.
+        let mut capacity_balancer = balancer.clone();
+        capacity_balancer.fee_rate = FeeRate::from_u64(0);
+        capacity_balancer.force_small_change_as_fee = None;
+        capacity_balancer.allow_partial = false;
+        let (tx, _) = rebalance_tx_capacity(
+            &tx,
+            &capacity_balancer,
+            cell_collector,
+            tx_dep_provider,
+            cell_dep_resolver,
+            header_dep_resolver,
+            0,
+            None,
+        )?;
+        let mut fee_balancer = balancer.clone();
+        fee_balancer.capacity_provider = fee_payer.clone();
+        fee_balancer.change_lock_script = None;
+        fee_balancer.change_template = None;
+        fee_balancer.allow_partial = false;
+        return rebalance_tx_capacity(
+            &tx,
+            &fee_balancer,
+            cell_collector,
+            tx_dep_provider,
+            cell_dep_resolver,
+            header_dep_resolver,
+            0,
+            None,
+        );
+    }
+    let (tx, change_idx) = rebalance_tx_capacity(
+        &tx,
+        balancer,
+        cell_collector,
+        tx_dep_provider,
+        cell_dep_resolver,
+        header_dep_resolver,
         0,
         None,
     )?;
-    Ok(tx)
+    match change_idx {
+        Some(idx) if balancer.change_split.is_some() => {
+            let tx = balancer.split_change(tx, idx)?;
+            let tx_size = tx.data().as_reader().serialized_size_in_block();
+            let fee = tx_fee(tx.clone(), tx_dep_provider, header_dep_resolver)?;
+            balancer.check_fee_limits(fee, tx_size)?;
+            Ok((tx, Some(idx)))
+        }
+        _ => Ok((tx, change_idx)),
+    }
+}
+
+/// Spend all the cells of the capacity provider, put the capacity left after
+/// the fee into the output at `output_index`.
+#[allow(clippy::too_many_arguments)]
+fn balance_tx_capacity_send_max(
+    tx: &TransactionView,
+    balancer: &CapacityBalancer,
+    output_index: usize,
+    cell_collector: &mut dyn CellCollector,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+    cell_dep_resolver: &dyn CellDepResolver,
+    header_dep_resolver: &dyn HeaderDepResolver,
+) -> Result<TransactionView, BalanceTxCapacityError> {
+    let capacity_provider = &balancer.capacity_provider;
+    if capacity_provider.lock_scripts.is_empty() {
+        return Err(BalanceTxCapacityError::EmptyCapacityProvider);
+    }
+    let output = tx
+        .outputs()
+        .get(output_index)
+        .ok_or(BalanceTxCapacityError::ChangeIndexNotFound(output_index))?;
+    if tx.witnesses().len() > tx.inputs().len() {
+        return Err(BalanceTxCapacityError::InvalidWitnessArgs(anyhow!(
+            "the witnesses count exceed the inputs count"
+        )));
+    }
+    let mut witnesses: Vec<_> = tx.witnesses().into_iter().collect();
+    witnesses.resize(tx.inputs().len(), Default::default());
+    #[allow(clippy::mutable_key_type)]
+    let mut cell_deps: HashSet<CellDep> = tx.cell_deps().into_iter().collect();
+    let mut new_cell_deps = Vec::new();
+    #[allow(clippy::mutable_key_type)]
+    let mut input_locks = HashSet::new();
+    for out_point in tx.input_pts_iter() {
+        input_locks.insert(tx_dep_provider.get_cell(&out_point)?.lock());
+    }
+    #[allow(clippy::mutable_key_type)]
+    let mut out_points: HashSet<OutPoint> = tx.input_pts_iter().collect();
+    let mut inputs = Vec::new();
+    for (lock_script, placeholder_witness, since_source) in &capacity_provider.lock_scripts {
+        let mut query = CellQueryOptions::new_lock(lock_script.clone());
+        query.secondary_script_len_range = Some(ValueRangeOption::new_exact(0));
+        query.data_len_range = Some(ValueRangeOption::new_exact(0));
+        query.min_total_capacity = u64::MAX;
+        let (cells, _) = cell_collector.collect_live_cells(&query, true)?;
+        let cells: Vec<_> = cells
+            .into_iter()
+            .filter(|cell| out_points.insert(cell.out_point.clone()))
+            .collect();
+        if cells.is_empty() {
+            continue;
+        }
+        let cell_dep = cell_dep_resolver
+            .resolve(lock_script)
+            .ok_or_else(|| BalanceTxCapacityError::ResolveCellDepFailed(lock_script.clone()))?;
+        if cell_deps.insert(cell_dep.clone()) {
+            new_cell_deps.push(cell_dep);
+        }
+        for cell in cells {
+            if input_locks.insert(lock_script.clone()) {
+                witnesses.push(placeholder_witness.as_bytes().pack());
+            } else {
+                witnesses.push(Default::default());
+            }
+            let since = since_source.input_since(&cell)?;
+            inputs.push(CellInput::new(cell.out_point, since));
+        }
+    }
+    let base_tx = tx
+        .as_advanced_builder()
+        .cell_deps(new_cell_deps)
+        .inputs(inputs)
+        .set_witnesses(witnesses)
+        .build();
+
+    // The capacity field has fixed size, the transaction size is not changed
+    // by the new capacity.
+    let tx_size = balancer.estimated_tx_size(&base_tx, tx_dep_provider)?;
+    let min_fee = match balancer.exact_fee {
+        Some(exact_fee) => {
+            balancer.check_exact_fee(exact_fee, tx_size)?;
+            exact_fee
+        }
+        None => balancer.fee_rate.fee(tx_size as u64).as_u64(),
+    };
+    let extra_capacity = match tx_fee(base_tx.clone(), tx_dep_provider, header_dep_resolver) {
+        Ok(fee) if fee >= min_fee => fee - min_fee,
+        Ok(fee) => {
+            return Err(BalanceTxCapacityError::CapacityNotEnough(format!(
+                "need more capacity, value={}",
+                HumanCapacity(min_fee - fee)
+            )));
+        }
+        Err(TransactionFeeError::CapacityOverflow(delta)) => {
+            return Err(BalanceTxCapacityError::CapacityNotEnough(format!(
+                "need more capacity, value={}",
+                HumanCapacity(delta + min_fee)
+            )));
+        }
+        Err(err) => return Err(err.into()),
+    };
+    let capacity: u64 = output.capacity().unpack();
+    let mut outputs: Vec<_> = base_tx.outputs().into_iter().collect();
+    outputs[output_index] = output
+        .as_builder()
+        .capacity((capacity + extra_capacity).pack())
+        .build();
+    balancer.check_fee_limits(min_fee, tx_size)?;
+    Ok(base_tx.as_advanced_builder().set_outputs(outputs).build())
+}
+
+/// The max total capacity of the capacity provider at `idx` (see
+/// [`CellCollector::max_total_capacity`]), it's queried at most once in a
+/// balancing and cached in `max_capacities`.
+///
+/// It's an upper bound of the spendable capacity: the cells with type script
+/// or data are filtered out like the collecting query, but the immature cells
+/// and the cells already collected in this balancing are still counted. So
+/// it's only used to fail fast, never to tell the capacity is enough.
+fn provider_max_capacity(
+    max_capacities: &mut [Option<Option<u64>>],
+    cell_collector: &mut dyn CellCollector,
+    idx: usize,
+    lock_script: &Script,
+) -> Result<Option<u64>, BalanceTxCapacityError> {
+    if let Some(capacity) = max_capacities[idx] {
+        return Ok(capacity);
+    }
+    let mut query = CellQueryOptions::new_lock(lock_script.clone());
+    query.secondary_script_len_range = Some(ValueRangeOption::new_exact(0));
+    query.data_len_range = Some(ValueRangeOption::new_exact(0));
+    let capacity = cell_collector.max_total_capacity(&query)?;
+    max_capacities[idx] = Some(capacity);
+    Ok(capacity)
+}
+
+/// Return `CapacityNotEnough` with the available capacity of every provider
+/// when the max total capacity of all the providers is known (see
+/// [`provider_max_capacity`]) and below `required_capacity`.
+fn check_providers_capacity(
+    cell_collector: &mut dyn CellCollector,
+    lock_scripts: &[(Script, WitnessArgs, SinceSource)],
+    max_capacities: &mut [Option<Option<u64>>],
+    required_capacity: u64,
+) -> Result<(), BalanceTxCapacityError> {
+    let mut total_capacity: u64 = 0;
+    let mut providers = Vec::with_capacity(lock_scripts.len());
+    for (idx, (lock_script, _, _)) in lock_scripts.iter().enumerate() {
+        match provider_max_capacity(max_capacities, cell_collector, idx, lock_script)? {
+            Some(capacity) => {
+                total_capacity = total_capacity.saturating_add(capacity);
+                providers.push(format!(
+                    "{}={}",
+                    lock_script.calc_script_hash(),
+                    HumanCapacity(capacity)
+                ));
+            }
+            // Unknown, let the balancing find out
+            None => return Ok(()),
+        }
+    }
+    if total_capacity < required_capacity {
+        return Err(BalanceTxCapacityError::CapacityNotEnough(format!(
+            "need more capacity, value={}, max available={}, providers: [{}]",
+            HumanCapacity(required_capacity),
+            HumanCapacity(total_capacity),
+            providers.join(", ")
+        )));
+    }
+    Ok(())
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -689,37 +2589,94 @@ fn rebalance_tx_capacity(
         .change_lock_script
         .clone()
         .unwrap_or_else(|| capacity_provider.lock_scripts[0].0.clone());
-    let (tx, base_change_output, base_change_occupied_capacity) = if let Some(idx) = change_index {
-        let outputs = tx.outputs();
-        let output = tx
-            .outputs()
-            .get(idx)
-            .ok_or(BalanceTxCapacityError::ChangeIndexNotFound(idx))?;
-
-        // remove change output
-        let outputs: Vec<_> = outputs
-            .into_iter()
-            .enumerate()
-            .filter_map(|(i, output)| if idx == i { None } else { Some(output) })
-            .collect();
-        let base_change_occupied_capacity = output
-            .occupied_capacity(Capacity::zero())
-            .expect("init change occupied capacity")
-            .as_u64();
-        let tx = tx.data().as_advanced_builder().set_outputs(outputs).build();
-        (tx, output, base_change_occupied_capacity)
-    } else {
-        let base_change_output = CellOutput::new_builder().lock(change_lock_script).build();
-        let base_change_occupied_capacity = base_change_output
-            .occupied_capacity(Capacity::zero())
-            .expect("init change occupied capacity")
-            .as_u64();
-        (
-            tx.clone(),
-            base_change_output,
-            base_change_occupied_capacity,
+    let (tx, base_change_output, base_change_output_data, base_change_occupied_capacity) =
+        if let Some(idx) = change_index {
+            let outputs = tx.outputs();
+            let output = tx
+                .outputs()
+                .get(idx)
+                .ok_or(BalanceTxCapacityError::ChangeIndexNotFound(idx))?;
+            let output_data = tx
+                .outputs_data()
+                .get(idx)
+                .map(|data| data.raw_data())
+                .unwrap_or_default();
+
+            // remove change output and its data, the change output may carry
+            // data (e.g. an info output), it must be counted in the occupied
+            // capacity.
+            let outputs: Vec<_> = outputs
+                .into_iter()
+                .enumerate()
+                .filter_map(|(i, output)| if idx == i { None } else { Some(output) })
+                .collect();
+            let outputs_data: Vec<_> = tx
+                .outputs_data()
+                .into_iter()
+                .enumerate()
+                .filter_map(|(i, data)| if idx == i { None } else { Some(data) })
+                .collect();
+            let base_change_occupied_capacity = output
+                .occupied_capacity(Capacity::bytes(output_data.len()).expect("data capacity"))
+                .expect("init change occupied capacity")
+                .as_u64();
+            let tx = tx
+                .data()
+                .as_advanced_builder()
+                .set_outputs(outputs)
+                .set_outputs_data(outputs_data)
+                .build();
+            (tx, output, output_data, base_change_occupied_capacity)
+        } else {
+            let (base_change_output, base_change_output_data) = balancer
+                .change_template
+                .clone()
+                .unwrap_or_default()
+                .build_output(change_lock_script);
+            let base_change_occupied_capacity = base_change_output
+                .occupied_capacity(
+                    Capacity::bytes(base_change_output_data.len()).expect("data capacity"),
+                )
+                .expect("init change occupied capacity")
+                .as_u64();
+            (
+                tx.clone(),
+                base_change_output,
+                base_change_output_data,
+                base_change_occupied_capacity,
+            )
+        };
+    // The min capacity of a new change cell
+    let min_change_capacity = base_change_occupied_capacity + balancer.change_threshold();
+    // The output extra header size is for:
+    //   * first 4 bytes is for output data header (the length)
+    //   * second 4 bytes if for output data offset
+    //   * third 4 bytes is for output offset
+    let output_header_extra = 4 + 4 + 4;
+    // NOTE: extra_min_fee +1 is for `FeeRate::fee` round
+    let extra_min_fee = balancer
+        .fee_rate
+        .fee(
+            (base_change_output.as_slice().len() + base_change_output_data.len()) as u64
+                + output_header_extra,
         )
+        .as_u64()
+        + 1;
+
+    // Resolve the inputs of the transaction only once, the fee of the new
+    // transaction is then computed from the capacity of the new inputs and
+    // the change output.
+    #[allow(clippy::mutable_key_type)]
+    let mut input_lock_scripts = HashSet::new();
+    for out_point in tx.input_pts_iter() {
+        input_lock_scripts.insert(tx_dep_provider.get_cell(&out_point)?.lock());
+    }
+    let base_fee = match tx_fee(tx.clone(), tx_dep_provider, header_dep_resolver) {
+        Ok(fee) => fee as i128,
+        Err(TransactionFeeError::CapacityOverflow(delta)) => -(delta as i128),
+        Err(err) => return Err(err.into()),
     };
+    let mut inputs_capacity: u64 = 0;
 
     let mut lock_scripts = Vec::new();
     // remove duplicated lock script
@@ -728,6 +2685,21 @@ fn rebalance_tx_capacity(
             lock_scripts.push((script.clone(), placeholder.clone(), since_source.clone()));
         }
     }
+    // Fail fast when the capacity providers together obviously can not cover
+    // the shortfall, before collecting (and locking) any cell.
+    let base_tx_size = balancer.estimated_tx_size(&tx, tx_dep_provider)?;
+    let base_min_fee = accepted_min_fee.max(balancer.fee_rate.fee(base_tx_size as u64).as_u64());
+    let required_capacity = base_min_fee as i128 - base_fee;
+    let mut max_capacities = vec![None; lock_scripts.len()];
+    if required_capacity > 0 && !balancer.allow_partial {
+        check_providers_capacity(
+            cell_collector,
+            &lock_scripts,
+            &mut max_capacities,
+            required_capacity.min(u64::MAX as i128) as u64,
+        )?;
+    }
+
     let mut lock_script_idx = 0;
     let mut cell_deps = Vec::new();
     #[allow(clippy::mutable_key_type)]
@@ -740,8 +2712,11 @@ fn rebalance_tx_capacity(
     };
     let mut changed_witnesses: HashMap<usize, WitnessArgs> = HashMap::default();
     let mut witnesses = Vec::new();
+    // The missing capacity of the partially balanced transaction
+    let mut partial_missing = None;
     loop {
         let (lock_script, placeholder_witness, since_source) = &lock_scripts[lock_script_idx];
+        let mut input_reason = InputReason::Capacity;
         let base_query = {
             let mut query = CellQueryOptions::new_lock(lock_script.clone());
             query.secondary_script_len_range = Some(ValueRangeOption::new_exact(0));
@@ -749,13 +2724,7 @@ fn rebalance_tx_capacity(
             query
         };
         // check if capacity provider lock script already in inputs
-        let mut has_provider = false;
-        for input in tx.inputs().into_iter().chain(inputs.clone().into_iter()) {
-            let cell = tx_dep_provider.get_cell(&input.previous_output())?;
-            if cell.lock() == *lock_script {
-                has_provider = true;
-            }
-        }
+        let has_provider = input_lock_scripts.contains(lock_script);
         while tx.witnesses().item_count() + witnesses.len()
             < tx.inputs().item_count() + inputs.len()
         {
@@ -777,17 +2746,34 @@ fn rebalance_tx_capacity(
                 .set_witnesses(all_witnesses);
             if let Some(output) = change_output.clone() {
                 ret_change_index = Some(output_len);
-                builder = builder.output(output).output_data(Default::default());
+                builder = builder
+                    .output(output)
+                    .output_data(base_change_output_data.pack());
             }
             builder.build()
         };
-        let tx_size = new_tx.data().as_reader().serialized_size_in_block();
+        if let Some(missing) = partial_missing {
+            balancer.record(|record| record.missing_capacity = missing);
+            return Ok((new_tx, ret_change_index));
+        }
+        let tx_size = balancer.estimated_tx_size(&new_tx, tx_dep_provider)?;
         let min_fee = accepted_min_fee.max(balancer.fee_rate.fee(tx_size as u64).as_u64());
         let mut need_more_capacity = 1;
-        let fee_result: Result<u64, TransactionFeeError> =
-            tx_fee(new_tx.clone(), tx_dep_provider, header_dep_resolver);
+        // The fee and the capacity left when they are not enough for a change cell
+        let mut small_change = None;
+        let change_capacity: u64 = change_output
+            .as_ref()
+            .map(|output| output.capacity().unpack())
+            .unwrap_or(0);
+        let fee = base_fee + inputs_capacity as i128 - change_capacity as i128;
+        let fee_result: Result<u64, TransactionFeeError> = if fee >= 0 {
+            Ok(fee as u64)
+        } else {
+            Err(TransactionFeeError::CapacityOverflow((-fee) as u64))
+        };
         match fee_result {
             Ok(fee) if fee == min_fee => {
+                balancer.check_fee_limits(fee, tx_size)?;
                 return Ok((new_tx, ret_change_index));
             }
             Ok(fee) if fee > min_fee => {
@@ -803,20 +2789,8 @@ fn rebalance_tx_capacity(
                     need_more_capacity = 0;
                 } else {
                     // If change cell not exists, add a change cell.
-
-                    // The output extra header size is for:
-                    //   * first 4 bytes is for output data header (the length)
-                    //   * second 4 bytes if for output data offset
-                    //   * third 4 bytes is for output offset
-                    let output_header_extra = 4 + 4 + 4;
-                    // NOTE: extra_min_fee +1 is for `FeeRate::fee` round
-                    let extra_min_fee = balancer
-                        .fee_rate
-                        .fee(base_change_output.as_slice().len() as u64 + output_header_extra)
-                        .as_u64()
-                        + 1;
                     // The extra capacity (delta - extra_min_fee) is enough to hold the change cell.
-                    if delta >= base_change_occupied_capacity + extra_min_fee {
+                    if delta >= min_change_capacity + extra_min_fee {
                         // next loop round must return new_tx;
                         change_output = Some(
                             base_change_output
@@ -827,37 +2801,17 @@ fn rebalance_tx_capacity(
                         );
                         need_more_capacity = 0;
                     } else {
-                        // peek if there is more live cell owned by this capacity provider
-                        let (more_cells, _more_capacity) =
-                            cell_collector.collect_live_cells(&base_query, false)?;
-                        if more_cells.is_empty() {
-                            if let Some(capacity) = balancer.force_small_change_as_fee {
-                                if fee > capacity {
-                                    return Err(
-                                        BalanceTxCapacityError::ForceSmallChangeAsFeeFailed(fee),
-                                    );
-                                } else {
-                                    return Ok((new_tx, ret_change_index));
-                                }
-                            } else if lock_script_idx + 1 == lock_scripts.len() {
-                                return Err(BalanceTxCapacityError::CapacityNotEnough(format!(
-                                    "can not create change cell, left capacity={}",
-                                    HumanCapacity(delta)
-                                )));
-                            } else {
-                                lock_script_idx += 1;
-                                continue;
-                            }
-                        } else {
-                            // need more input to balance the capacity
-                            change_output = Some(
-                                base_change_output
-                                    .clone()
-                                    .as_builder()
-                                    .capacity(base_change_occupied_capacity.pack())
-                                    .build(),
-                            );
-                        }
+                        // need more input to hold the change cell
+                        input_reason = InputReason::Change;
+                        need_more_capacity = min_change_capacity + extra_min_fee - delta;
+                        small_change = Some((fee, delta));
+                        change_output = Some(
+                            base_change_output
+                                .clone()
+                                .as_builder()
+                                .capacity(min_change_capacity.pack())
+                                .build(),
+                        );
                     }
                 }
             }
@@ -873,19 +2827,129 @@ fn rebalance_tx_capacity(
             }
         }
         if need_more_capacity > 0 {
+            // Also collect the fee of the new input (and the placeholder
+            // witness and the cell dep if they are not in the transaction
+            // yet), so the shortfall is usually covered in one query.
+            let mut input_size = CELL_INPUT_SIZE + WITNESS_HEADER_SIZE;
+            if !has_provider
+                && tx.witnesses().item_count() <= tx.inputs().item_count() + inputs.len()
+            {
+                input_size += placeholder_witness.as_slice().len() as u64;
+            }
+            if !resolved_scripts.contains(lock_script)
+                && cell_dep_resolver
+                    .resolve(lock_script)
+                    .map_or(false, |cell_dep| {
+                        tx.cell_deps().into_iter().all(|item| item != cell_dep)
+                    })
+            {
+                input_size += CELL_DEP_SIZE;
+            }
             let query = {
                 let mut query = base_query.clone();
-                query.min_total_capacity = need_more_capacity;
+                query.min_total_capacity =
+                    need_more_capacity.saturating_add(balancer.fee_rate.fee(input_size).as_u64());
                 query
             };
-            let (more_cells, _more_capacity) = cell_collector.collect_live_cells(&query, true)?;
+            // Skip paginating the cells when the provider obviously can not
+            // cover the shortfall
+            let max_capacity = if small_change.is_none() {
+                provider_max_capacity(
+                    &mut max_capacities,
+                    cell_collector,
+                    lock_script_idx,
+                    lock_script,
+                )?
+            } else {
+                None
+            };
+            if let Some(max_capacity) = max_capacity.filter(|_| !balancer.allow_partial) {
+                if max_capacity < need_more_capacity && lock_script_idx + 1 == lock_scripts.len() {
+                    return Err(BalanceTxCapacityError::CapacityNotEnough(format!(
+                        "need more capacity, value={}, max available={}",
+                        HumanCapacity(need_more_capacity),
+                        HumanCapacity(max_capacity)
+                    )));
+                }
+            }
+            let more_cells = if max_capacity == Some(0) {
+                Vec::new()
+            } else {
+                let (more_cells, _more_capacity) =
+                    cell_collector.collect_live_cells(&query, true)?;
+                balancer.record(|record| record.collector_queries += 1);
+                more_cells
+            };
             if more_cells.is_empty() {
+                if let Some((fee, delta)) = small_change {
+                    change_output = None;
+                    if let Some(capacity) = balancer.force_small_change_as_fee {
+                        if fee > capacity {
+                            return Err(BalanceTxCapacityError::ForceSmallChangeAsFeeFailed(fee));
+                        } else {
+                            balancer.check_strict(
+                                |strict| strict.allow_small_change_as_fee,
+                                "absorb small change into fee",
+                            )?;
+                            balancer.check_fee_limits(fee, tx_size)?;
+                            balancer.record(|record| record.absorbed_change = Some(delta));
+                            return Ok((new_tx, ret_change_index));
+                        }
+                    } else if lock_script_idx + 1 == lock_scripts.len() {
+                        if balancer.allow_partial {
+                            // Keep the change output, the missing capacity
+                            // is supplied by others.
+                            change_output = Some(
+                                base_change_output
+                                    .clone()
+                                    .as_builder()
+                                    .capacity(min_change_capacity.pack())
+                                    .build(),
+                            );
+                            partial_missing = Some(need_more_capacity);
+                            continue;
+                        }
+                        return Err(BalanceTxCapacityError::CapacityNotEnough(format!(
+                            "can not create change cell, left capacity={}",
+                            HumanCapacity(delta)
+                        )));
+                    } else {
+                        balancer.check_strict(
+                            |strict| strict.allow_switch_provider,
+                            "switch to next capacity provider",
+                        )?;
+                        balancer.record(|record| {
+                            record.switch_provider(
+                                lock_script,
+                                &lock_scripts[lock_script_idx + 1].0,
+                                "no more cells to create change cell",
+                            )
+                        });
+                        lock_script_idx += 1;
+                        continue;
+                    }
+                }
                 if lock_script_idx + 1 == lock_scripts.len() {
+                    if balancer.allow_partial {
+                        partial_missing = Some(need_more_capacity);
+                        continue;
+                    }
                     return Err(BalanceTxCapacityError::CapacityNotEnough(format!(
                         "need more capacity, value={}",
                         HumanCapacity(need_more_capacity)
                     )));
                 } else {
+                    balancer.check_strict(
+                        |strict| strict.allow_switch_provider,
+                        "switch to next capacity provider",
+                    )?;
+                    balancer.record(|record| {
+                        record.switch_provider(
+                            lock_script,
+                            &lock_scripts[lock_script_idx + 1].0,
+                            "not enough capacity",
+                        )
+                    });
                     lock_script_idx += 1;
                     continue;
                 }
@@ -902,6 +2966,11 @@ fn rebalance_tx_capacity(
                 {
                     cell_deps.push(provider_cell_dep);
                     resolved_scripts.insert(lock_script);
+                } else {
+                    balancer.check_strict(
+                        |strict| strict.allow_reuse_cell_deps,
+                        "reuse cell dep of capacity provider in transaction",
+                    )?;
                 }
             }
             if !has_provider {
@@ -938,26 +3007,19 @@ fn rebalance_tx_capacity(
                     witnesses.push(placeholder_witness.as_bytes().pack());
                 }
             }
-            let since = match since_source {
-                SinceSource::LockArgs(offset) => {
-                    let lock_arg = lock_script.args().raw_data();
-                    if lock_arg.len() < offset + 8 {
-                        return Err(BalanceTxCapacityError::InvalidSinceValue(
-                            *offset,
-                            lock_arg.len(),
-                        ));
-                    }
-                    let mut since_bytes = [0u8; 8];
-                    since_bytes.copy_from_slice(&lock_arg[*offset..*offset + 8]);
-                    u64::from_le_bytes(since_bytes)
-                }
-                SinceSource::Value(since_value) => *since_value,
-            };
-            inputs.extend(
-                more_cells
-                    .into_iter()
-                    .map(|cell| CellInput::new(cell.out_point, since)),
-            );
+            balancer.record(|record| {
+                record.selected.extend(
+                    more_cells
+                        .iter()
+                        .map(|cell| (cell.out_point.clone(), input_reason)),
+                )
+            });
+            for cell in more_cells {
+                inputs_capacity += Unpack::<u64>::unpack(&cell.output.capacity());
+                let since = since_source.input_since(&cell)?;
+                inputs.push(CellInput::new(cell.out_point, since));
+            }
+            input_lock_scripts.insert(lock_script.clone());
         }
     }
 }
@@ -1011,11 +3073,33 @@ pub fn fill_placeholder_witnesses(
     balanced_tx: TransactionView,
     tx_dep_provider: &dyn TransactionDependencyProvider,
     unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+) -> Result<(TransactionView, Vec<ScriptGroup>), UnlockError> {
+    fill_placeholder_witnesses_with_skip(balanced_tx, tx_dep_provider, unlockers, &HashSet::new())
+}
+
+/// Fill placeholder lock script witnesses, the lock script groups in
+/// `skip_lock_hashes` are handled externally (e.g. signed by another service),
+/// they are neither filled nor reported. The external handler should fill
+/// the placeholders of those groups before balancing, or the fee will be
+/// underestimated.
+///
+/// Return value:
+///   * The updated transaction
+///   * The script groups that not matched by given `unlockers`
+#[allow(clippy::mutable_key_type)]
+pub fn fill_placeholder_witnesses_with_skip(
+    balanced_tx: TransactionView,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+    unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+    skip_lock_hashes: &HashSet<Byte32>,
 ) -> Result<(TransactionView, Vec<ScriptGroup>), UnlockError> {
     let ScriptGroups { lock_groups, .. } = gen_script_groups(&balanced_tx, tx_dep_provider)?;
     let mut tx = balanced_tx;
     let mut not_matched = Vec::new();
-    for script_group in lock_groups.values() {
+    for (lock_hash, script_group) in lock_groups.iter() {
+        if skip_lock_hashes.contains(lock_hash) {
+            continue;
+        }
         let script_id = ScriptId::from(&script_group.script);
         let script_args = script_group.script.args().raw_data();
         if let Some(unlocker) = unlockers.get(&script_id) {
@@ -1042,11 +3126,70 @@ pub fn unlock_tx(
     balanced_tx: TransactionView,
     tx_dep_provider: &dyn TransactionDependencyProvider,
     unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+) -> Result<(TransactionView, Vec<ScriptGroup>), UnlockError> {
+    unlock_tx_with_skip(balanced_tx, tx_dep_provider, unlockers, &HashSet::new())
+}
+
+/// Same as [`unlock_tx`], refuse to sign a transaction spending from the lock
+/// scripts frozen in `freeze_registry` instead of the process wide registry.
+pub fn unlock_tx_with_freeze_registry(
+    balanced_tx: TransactionView,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+    unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+    freeze_registry: &FreezeRegistry,
+) -> Result<(TransactionView, Vec<ScriptGroup>), UnlockError> {
+    unlock_tx_inner(
+        balanced_tx,
+        tx_dep_provider,
+        unlockers,
+        &HashSet::new(),
+        freeze_registry,
+    )
+}
+
+/// Build unlocked transaction, the lock script groups in `skip_lock_hashes`
+/// are handled externally, they are neither unlocked nor reported.
+///
+/// Return value:
+///   * The built transaction
+///   * The script groups that not unlocked by given `unlockers`
+#[allow(clippy::mutable_key_type)]
+pub fn unlock_tx_with_skip(
+    balanced_tx: TransactionView,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+    unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+    skip_lock_hashes: &HashSet<Byte32>,
+) -> Result<(TransactionView, Vec<ScriptGroup>), UnlockError> {
+    unlock_tx_inner(
+        balanced_tx,
+        tx_dep_provider,
+        unlockers,
+        skip_lock_hashes,
+        FreezeRegistry::global(),
+    )
+}
+
+#[allow(clippy::mutable_key_type)]
+fn unlock_tx_inner(
+    balanced_tx: TransactionView,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+    unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+    skip_lock_hashes: &HashSet<Byte32>,
+    freeze_registry: &FreezeRegistry,
 ) -> Result<(TransactionView, Vec<ScriptGroup>), UnlockError> {
     let ScriptGroups { lock_groups, .. } = gen_script_groups(&balanced_tx, tx_dep_provider)?;
+    if let Some((lock_hash, reason)) = freeze_registry.check_frozen(
+        lock_groups.keys().map(|lock_hash| lock_hash.unpack()),
+        "sign",
+    ) {
+        return Err(UnlockError::FrozenLockScript(lock_hash, reason));
+    }
     let mut tx = balanced_tx;
     let mut not_unlocked = Vec::new();
-    for script_group in lock_groups.values() {
+    for (lock_hash, script_group) in lock_groups.iter() {
+        if skip_lock_hashes.contains(lock_hash) {
+            continue;
+        }
         let script_id = ScriptId::from(&script_group.script);
         let script_args = script_group.script.args().raw_data();
         if let Some(unlocker) = unlockers.get(&script_id) {
@@ -1091,4 +3234,90 @@ mod anyhow_tests {
         let error = anyhow!(eror);
         assert_eq!("empty capacity provider", error.to_string())
     }
+
+    #[test]
+    fn test_strict_mode_violation() {
+        use super::{CapacityBalancer, StrictConfig};
+        use ckb_types::packed::{Script, WitnessArgs};
+        let mut balancer =
+            CapacityBalancer::new_simple(Script::default(), WitnessArgs::default(), 1000);
+        assert!(balancer
+            .check_strict(|strict| strict.allow_switch_provider, "switch")
+            .is_ok());
+        balancer.set_strict(Some(StrictConfig::default()));
+        let error = balancer
+            .check_strict(|strict| strict.allow_switch_provider, "switch")
+            .unwrap_err();
+        assert_eq!("strict mode violation: `switch`", error.to_string());
+    }
+
+    #[test]
+    fn test_fee_limits() {
+        use super::{BalanceTxCapacityError, CapacityBalancer};
+        use ckb_types::packed::{Script, WitnessArgs};
+        let mut balancer =
+            CapacityBalancer::new_simple(Script::default(), WitnessArgs::default(), 1000);
+        assert!(balancer.check_fee_limits(u64::MAX, 1).is_ok());
+        balancer.set_fee_limits(Some(10_000), Some(2000));
+        assert!(balancer.check_fee_limits(1000, 1000).is_ok());
+        assert!(matches!(
+            balancer.check_fee_limits(10_001, 100_000),
+            Err(BalanceTxCapacityError::FeeExceedLimit(10_001, 10_000))
+        ));
+        assert!(matches!(
+            balancer.check_fee_limits(3000, 1000),
+            Err(BalanceTxCapacityError::FeeRateExceedLimit(3000, 2000))
+        ));
+    }
+
+    #[test]
+    fn test_witness_size_regressions() {
+        use super::{witness_size_regressions, WitnessSizeRegression};
+        use ckb_types::{bytes::Bytes, core::TransactionBuilder, prelude::*};
+        let placeholder_tx = TransactionBuilder::default()
+            .witness(Bytes::from(vec![0u8; 85]).pack())
+            .witness(Bytes::from(vec![0u8; 20]).pack())
+            .build();
+        let unlocked_tx = placeholder_tx
+            .as_advanced_builder()
+            .set_witnesses(vec![
+                Bytes::from(vec![1u8; 85]).pack(),
+                Bytes::from(vec![1u8; 21]).pack(),
+            ])
+            .build();
+        assert!(witness_size_regressions(&placeholder_tx, &placeholder_tx).is_empty());
+        assert_eq!(
+            witness_size_regressions(&placeholder_tx, &unlocked_tx),
+            vec![WitnessSizeRegression {
+                witness_index: 1,
+                placeholder_size: 20,
+                unlocked_size: 21,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_data_size_budget() {
+        use super::{DataSizeBudget, DataSizeExceeded};
+        use ckb_types::{bytes::Bytes, core::TransactionBuilder, packed::CellOutput, prelude::*};
+        let tx = TransactionBuilder::default()
+            .output(CellOutput::default())
+            .output_data(Bytes::from(vec![0u8; 100]).pack())
+            .output(CellOutput::default())
+            .output_data(Bytes::from(vec![0u8; 300]).pack())
+            .build();
+        assert!(DataSizeBudget::default().check(&tx).is_ok());
+        assert!(DataSizeBudget::new(400, 300).check(&tx).is_ok());
+        let budget = DataSizeBudget::new(1000, 200);
+        assert_eq!(
+            budget.check(&tx),
+            Err(DataSizeExceeded {
+                output_indices: vec![1],
+                total: 400,
+                budget,
+            })
+        );
+        let err = DataSizeBudget::new(399, 300).check(&tx).unwrap_err();
+        assert!(err.output_indices.is_empty());
+    }
 }
@@ -0,0 +1,621 @@
+//! Builders for the [m-NFT](https://github.com/nervina-labs/ckb-nft-scripts)
+//! standard.
+//!
+//! The m-NFT cells use a fixed big-endian layout (not molecule) for the cell
+//! data:
+//!   * issuer: `version: u8 | class_count: u32 | set_count: u32 | info_size: u16 | info`
+//!   * class: `version: u8 | total: u32 | issued: u32 | configure: u8 | name | description | renderer`,
+//!     the strings are encoded as `size: u16 | bytes`
+//!   * nft: `version: u8 | characteristic: [u8; 8] | configure: u8 | state: u8`
+//!
+//! Extra bytes after the known fields (extinfo) are preserved.
+use std::collections::HashSet;
+use std::convert::{TryFrom, TryInto};
+
+use anyhow::anyhow;
+use ckb_hash::new_blake2b;
+use ckb_types::{
+    bytes::{BufMut, Bytes, BytesMut},
+    core::{Capacity, TransactionBuilder, TransactionView},
+    packed::{CellDep, CellInput, CellOutput, Script},
+    prelude::*,
+};
+
+use super::{TxBuilder, TxBuilderError};
+use crate::traits::{
+    CellCollector, CellDepResolver, CellQueryOptions, HeaderDepResolver, LiveCell,
+    TransactionDependencyProvider, ValueRangeOption,
+};
+use crate::types::ScriptId;
+
+/// The issuer id length (the issuer type script args)
+pub const ISSUER_ID_LEN: usize = 20;
+const CLASS_ARGS_LEN: usize = ISSUER_ID_LEN + 4;
+const NFT_ARGS_LEN: usize = CLASS_ARGS_LEN + 4;
+
+/// Class configure: the NFT can not be transferred
+pub const CONFIGURE_NOT_TRANSFERABLE: u8 = 0b0000_1000;
+/// NFT state: the NFT is locked
+pub const STATE_LOCKED: u8 = 0b0000_0001;
+
+/// The m-NFT script ids, they are different in mainnet and testnet
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct NftScripts {
+    pub issuer: ScriptId,
+    pub class: ScriptId,
+    pub nft: ScriptId,
+}
+
+impl NftScripts {
+    pub fn issuer_script(&self, issuer_id: &[u8]) -> Script {
+        build_script(&self.issuer, issuer_id.to_vec())
+    }
+
+    pub fn class_script(&self, issuer_id: &[u8], class_id: u32) -> Script {
+        let mut args = issuer_id.to_vec();
+        args.extend_from_slice(&class_id.to_be_bytes());
+        build_script(&self.class, args)
+    }
+
+    pub fn nft_script(&self, issuer_id: &[u8], class_id: u32, token_id: u32) -> Script {
+        let mut args = issuer_id.to_vec();
+        args.extend_from_slice(&class_id.to_be_bytes());
+        args.extend_from_slice(&token_id.to_be_bytes());
+        build_script(&self.nft, args)
+    }
+}
+
+fn build_script(script_id: &ScriptId, args: Vec<u8>) -> Script {
+    Script::new_builder()
+        .code_hash(script_id.code_hash.pack())
+        .hash_type(script_id.hash_type.into())
+        .args(Bytes::from(args).pack())
+        .build()
+}
+
+/// Calculate the issuer id from the first input of the transaction and the
+/// index of the issuer output.
+pub fn calculate_issuer_id(first_cell_input: &CellInput, output_index: u64) -> [u8; ISSUER_ID_LEN] {
+    let mut blake2b = new_blake2b();
+    blake2b.update(first_cell_input.as_slice());
+    blake2b.update(&output_index.to_le_bytes());
+    let mut hash = [0u8; 32];
+    blake2b.finalize(&mut hash);
+    let mut issuer_id = [0u8; ISSUER_ID_LEN];
+    issuer_id.copy_from_slice(&hash[0..ISSUER_ID_LEN]);
+    issuer_id
+}
+
+struct Reader<'r> {
+    data: &'r [u8],
+    offset: usize,
+}
+
+impl<'r> Reader<'r> {
+    fn new(data: &'r [u8]) -> Reader<'r> {
+        Reader { data, offset: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'r [u8], String> {
+        if self.data.len() < self.offset + len {
+            return Err(format!(
+                "data too short, expected at least: {}, got: {}",
+                self.offset + len,
+                self.data.len()
+            ));
+        }
+        let bytes = &self.data[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn sized_bytes(&mut self) -> Result<Bytes, String> {
+        let len = self.u16()? as usize;
+        Ok(Bytes::copy_from_slice(self.take(len)?))
+    }
+
+    fn rest(&self) -> Bytes {
+        Bytes::copy_from_slice(&self.data[self.offset..])
+    }
+}
+
+fn put_sized_bytes(buf: &mut BytesMut, data: &[u8]) -> Result<(), String> {
+    let len: u16 = data
+        .len()
+        .try_into()
+        .map_err(|_| format!("field too long: {}", data.len()))?;
+    buf.put_u16(len);
+    buf.put_slice(data);
+    Ok(())
+}
+
+/// The issuer cell data
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct IssuerData {
+    pub version: u8,
+    pub class_count: u32,
+    pub set_count: u32,
+    /// The issuer information (usually json)
+    pub info: Bytes,
+    pub extinfo: Bytes,
+}
+
+impl IssuerData {
+    pub fn new(info: Bytes) -> IssuerData {
+        IssuerData {
+            info,
+            ..Default::default()
+        }
+    }
+
+    pub fn from_slice(data: &[u8]) -> Result<IssuerData, String> {
+        let mut reader = Reader::new(data);
+        Ok(IssuerData {
+            version: reader.u8()?,
+            class_count: reader.u32()?,
+            set_count: reader.u32()?,
+            info: reader.sized_bytes()?,
+            extinfo: reader.rest(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Result<Bytes, String> {
+        let mut buf = BytesMut::new();
+        buf.put_u8(self.version);
+        buf.put_u32(self.class_count);
+        buf.put_u32(self.set_count);
+        put_sized_bytes(&mut buf, &self.info)?;
+        buf.put_slice(&self.extinfo);
+        Ok(buf.freeze())
+    }
+}
+
+/// The class cell data
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct ClassData {
+    pub version: u8,
+    /// The max NFT count of the class, `0` means unlimited
+    pub total: u32,
+    /// The issued NFT count
+    pub issued: u32,
+    pub configure: u8,
+    pub name: Bytes,
+    pub description: Bytes,
+    pub renderer: Bytes,
+    pub extinfo: Bytes,
+}
+
+impl ClassData {
+    pub fn from_slice(data: &[u8]) -> Result<ClassData, String> {
+        let mut reader = Reader::new(data);
+        Ok(ClassData {
+            version: reader.u8()?,
+            total: reader.u32()?,
+            issued: reader.u32()?,
+            configure: reader.u8()?,
+            name: reader.sized_bytes()?,
+            description: reader.sized_bytes()?,
+            renderer: reader.sized_bytes()?,
+            extinfo: reader.rest(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Result<Bytes, String> {
+        let mut buf = BytesMut::new();
+        buf.put_u8(self.version);
+        buf.put_u32(self.total);
+        buf.put_u32(self.issued);
+        buf.put_u8(self.configure);
+        put_sized_bytes(&mut buf, &self.name)?;
+        put_sized_bytes(&mut buf, &self.description)?;
+        put_sized_bytes(&mut buf, &self.renderer)?;
+        buf.put_slice(&self.extinfo);
+        Ok(buf.freeze())
+    }
+}
+
+/// The NFT cell data
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct NftData {
+    pub version: u8,
+    pub characteristic: [u8; 8],
+    /// Copied from the class configure
+    pub configure: u8,
+    pub state: u8,
+    pub extinfo: Bytes,
+}
+
+impl NftData {
+    pub fn from_slice(data: &[u8]) -> Result<NftData, String> {
+        let mut reader = Reader::new(data);
+        Ok(NftData {
+            version: reader.u8()?,
+            characteristic: reader.take(8)?.try_into().unwrap(),
+            configure: reader.u8()?,
+            state: reader.u8()?,
+            extinfo: reader.rest(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        buf.put_u8(self.version);
+        buf.put_slice(&self.characteristic);
+        buf.put_u8(self.configure);
+        buf.put_u8(self.state);
+        buf.put_slice(&self.extinfo);
+        buf.freeze()
+    }
+
+    pub fn is_transferable(&self) -> bool {
+        self.configure & CONFIGURE_NOT_TRANSFERABLE == 0 && self.state & STATE_LOCKED == 0
+    }
+}
+
+fn invalid_data(err: String) -> TxBuilderError {
+    TxBuilderError::InvalidParameter(anyhow!("invalid m-NFT cell data: {}", err))
+}
+
+fn resolve_cell_dep(
+    cell_dep_resolver: &dyn CellDepResolver,
+    script: &Script,
+) -> Result<CellDep, TxBuilderError> {
+    cell_dep_resolver
+        .resolve(script)
+        .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(script.clone()))
+}
+
+/// Find the only live cell of the type script
+fn find_type_cell(
+    cell_collector: &mut dyn CellCollector,
+    type_script: &Script,
+) -> Result<LiveCell, TxBuilderError> {
+    let query = CellQueryOptions::new_type(type_script.clone());
+    let (mut cells, _) = cell_collector.collect_live_cells(&query, true)?;
+    if cells.is_empty() {
+        return Err(TxBuilderError::Other(anyhow!(
+            "m-NFT cell not found, type_script={:?}",
+            type_script
+        )));
+    }
+    Ok(cells.remove(0))
+}
+
+/// Build the output with the capacity covers the new data, keep the old
+/// capacity if it is enough.
+fn update_output(output: &CellOutput, data: &Bytes) -> CellOutput {
+    let old_capacity: u64 = output.capacity().unpack();
+    let occupied_capacity = output
+        .occupied_capacity(Capacity::bytes(data.len()).unwrap())
+        .unwrap()
+        .as_u64();
+    output
+        .clone()
+        .as_builder()
+        .capacity(std::cmp::max(old_capacity, occupied_capacity).pack())
+        .build()
+}
+
+fn exact_output(lock_script: Script, type_script: Script, data: &Bytes) -> CellOutput {
+    CellOutput::new_builder()
+        .lock(lock_script)
+        .type_(Some(type_script).pack())
+        .build_exact_capacity(Capacity::bytes(data.len()).unwrap())
+        .unwrap()
+}
+
+/// Create an issuer cell, the issuer id is calculated from the first input
+/// which is collected from `lock_script`.
+pub struct IssuerCreateBuilder {
+    pub scripts: NftScripts,
+    /// The owner of the issuer cell
+    pub lock_script: Script,
+    pub info: Bytes,
+}
+
+impl IssuerCreateBuilder {
+    pub fn new(scripts: NftScripts, lock_script: Script, info: Bytes) -> IssuerCreateBuilder {
+        IssuerCreateBuilder {
+            scripts,
+            lock_script,
+            info,
+        }
+    }
+}
+
+impl TxBuilder for IssuerCreateBuilder {
+    fn build_base(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        let query = {
+            let mut query = CellQueryOptions::new_lock(self.lock_script.clone());
+            query.secondary_script_len_range = Some(ValueRangeOption::new_exact(0));
+            query.data_len_range = Some(ValueRangeOption::new_exact(0));
+            query
+        };
+        let (cells, _) = cell_collector.collect_live_cells(&query, true)?;
+        if cells.is_empty() {
+            return Err(TxBuilderError::Other(anyhow!(
+                "first input cell not found, lock={:?}",
+                self.lock_script
+            )));
+        }
+        let first_input = CellInput::new(cells[0].out_point.clone(), 0);
+        let issuer_id = calculate_issuer_id(&first_input, 0);
+        let issuer_script = self.scripts.issuer_script(&issuer_id);
+
+        #[allow(clippy::mutable_key_type)]
+        let mut cell_deps = HashSet::new();
+        cell_deps.insert(resolve_cell_dep(cell_dep_resolver, &self.lock_script)?);
+        cell_deps.insert(resolve_cell_dep(cell_dep_resolver, &issuer_script)?);
+
+        let data = IssuerData::new(self.info.clone())
+            .to_bytes()
+            .map_err(invalid_data)?;
+        let output = exact_output(self.lock_script.clone(), issuer_script, &data);
+        Ok(TransactionBuilder::default()
+            .set_cell_deps(cell_deps.into_iter().collect())
+            .set_inputs(vec![first_input])
+            .set_outputs(vec![output])
+            .set_outputs_data(vec![data.pack()])
+            .build())
+    }
+}
+
+/// Create a class cell, the issuer cell is updated (`class_count + 1`) and the
+/// new class id is the old `class_count`.
+pub struct ClassCreateBuilder {
+    pub scripts: NftScripts,
+    pub issuer_id: [u8; ISSUER_ID_LEN],
+    /// The lock script of the new class cell
+    pub lock_script: Script,
+    /// The class data, `issued` must be `0`
+    pub class_data: ClassData,
+}
+
+impl TxBuilder for ClassCreateBuilder {
+    fn build_base(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        if self.class_data.issued != 0 {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "the issued count of a new class must be 0"
+            )));
+        }
+        let issuer_script = self.scripts.issuer_script(&self.issuer_id);
+        let issuer_cell = find_type_cell(cell_collector, &issuer_script)?;
+        let mut issuer_data =
+            IssuerData::from_slice(&issuer_cell.output_data).map_err(invalid_data)?;
+        let class_id = issuer_data.class_count;
+        issuer_data.class_count = class_id
+            .checked_add(1)
+            .ok_or_else(|| TxBuilderError::Other(anyhow!("class count overflow")))?;
+        let issuer_output_data = issuer_data.to_bytes().map_err(invalid_data)?;
+        let class_script = self.scripts.class_script(&self.issuer_id, class_id);
+        let class_output_data = self.class_data.to_bytes().map_err(invalid_data)?;
+
+        #[allow(clippy::mutable_key_type)]
+        let mut cell_deps = HashSet::new();
+        cell_deps.insert(resolve_cell_dep(
+            cell_dep_resolver,
+            &issuer_cell.output.lock(),
+        )?);
+        cell_deps.insert(resolve_cell_dep(cell_dep_resolver, &issuer_script)?);
+        cell_deps.insert(resolve_cell_dep(cell_dep_resolver, &class_script)?);
+
+        let outputs = vec![
+            update_output(&issuer_cell.output, &issuer_output_data),
+            exact_output(self.lock_script.clone(), class_script, &class_output_data),
+        ];
+        Ok(TransactionBuilder::default()
+            .set_cell_deps(cell_deps.into_iter().collect())
+            .set_inputs(vec![CellInput::new(issuer_cell.out_point, 0)])
+            .set_outputs(outputs)
+            .set_outputs_data(vec![issuer_output_data.pack(), class_output_data.pack()])
+            .build())
+    }
+}
+
+/// Mint NFT cells of a class, the class cell is updated (`issued + count`) and
+/// the token ids are assigned from the old `issued` in order.
+pub struct NftMintBuilder {
+    pub scripts: NftScripts,
+    pub issuer_id: [u8; ISSUER_ID_LEN],
+    pub class_id: u32,
+    /// The receivers and the characteristic of their NFTs
+    pub receivers: Vec<(Script, [u8; 8])>,
+}
+
+impl TxBuilder for NftMintBuilder {
+    fn build_base(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        if self.receivers.is_empty() {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "empty NFT receivers"
+            )));
+        }
+        let class_script = self.scripts.class_script(&self.issuer_id, self.class_id);
+        let class_cell = find_type_cell(cell_collector, &class_script)?;
+        let mut class_data =
+            ClassData::from_slice(&class_cell.output_data).map_err(invalid_data)?;
+        let first_token_id = class_data.issued;
+        let issued = u32::try_from(self.receivers.len())
+            .ok()
+            .and_then(|count| first_token_id.checked_add(count))
+            .ok_or_else(|| TxBuilderError::Other(anyhow!("issued count overflow")))?;
+        if class_data.total != 0 && issued > class_data.total {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "exceed the class total: {}, issued: {}",
+                class_data.total,
+                issued
+            )));
+        }
+        class_data.issued = issued;
+        let class_output_data = class_data.to_bytes().map_err(invalid_data)?;
+
+        #[allow(clippy::mutable_key_type)]
+        let mut cell_deps = HashSet::new();
+        cell_deps.insert(resolve_cell_dep(
+            cell_dep_resolver,
+            &class_cell.output.lock(),
+        )?);
+        cell_deps.insert(resolve_cell_dep(cell_dep_resolver, &class_script)?);
+
+        let mut outputs = vec![update_output(&class_cell.output, &class_output_data)];
+        let mut outputs_data = vec![class_output_data.pack()];
+        for (token_id, (lock_script, characteristic)) in
+            (first_token_id..).zip(self.receivers.iter())
+        {
+            let nft_script = self
+                .scripts
+                .nft_script(&self.issuer_id, self.class_id, token_id);
+            if token_id == first_token_id {
+                cell_deps.insert(resolve_cell_dep(cell_dep_resolver, &nft_script)?);
+            }
+            let data = NftData {
+                characteristic: *characteristic,
+                configure: class_data.configure,
+                ..Default::default()
+            }
+            .to_bytes();
+            outputs.push(exact_output(lock_script.clone(), nft_script, &data));
+            outputs_data.push(data.pack());
+        }
+        Ok(TransactionBuilder::default()
+            .set_cell_deps(cell_deps.into_iter().collect())
+            .set_inputs(vec![CellInput::new(class_cell.out_point, 0)])
+            .set_outputs(outputs)
+            .set_outputs_data(outputs_data)
+            .build())
+    }
+}
+
+/// Transfer NFT cells, the type script and data are kept, the capacity of the
+/// NFT cell is kept as well.
+pub struct NftTransferBuilder {
+    /// The NFT type scripts and their receivers
+    pub transfers: Vec<(Script, Script)>,
+}
+
+impl NftTransferBuilder {
+    pub fn new(transfers: Vec<(Script, Script)>) -> NftTransferBuilder {
+        NftTransferBuilder { transfers }
+    }
+}
+
+impl TxBuilder for NftTransferBuilder {
+    fn build_base(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        if self.transfers.is_empty() {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "empty NFT transfers"
+            )));
+        }
+        #[allow(clippy::mutable_key_type)]
+        let mut cell_deps = HashSet::new();
+        let mut inputs = Vec::new();
+        let mut outputs = Vec::new();
+        let mut outputs_data = Vec::new();
+        for (nft_script, receiver) in &self.transfers {
+            if nft_script.args().raw_data().len() != NFT_ARGS_LEN {
+                return Err(TxBuilderError::InvalidParameter(anyhow!(
+                    "invalid NFT type script args length: {}",
+                    nft_script.args().raw_data().len()
+                )));
+            }
+            let nft_cell = find_type_cell(cell_collector, nft_script)?;
+            let nft_data = NftData::from_slice(&nft_cell.output_data).map_err(invalid_data)?;
+            if !nft_data.is_transferable() {
+                return Err(TxBuilderError::InvalidParameter(anyhow!(
+                    "the NFT is not transferable, type_script={:?}",
+                    nft_script
+                )));
+            }
+            cell_deps.insert(resolve_cell_dep(
+                cell_dep_resolver,
+                &nft_cell.output.lock(),
+            )?);
+            cell_deps.insert(resolve_cell_dep(cell_dep_resolver, nft_script)?);
+            inputs.push(CellInput::new(nft_cell.out_point, 0));
+            outputs.push(nft_cell.output.as_builder().lock(receiver.clone()).build());
+            outputs_data.push(nft_cell.output_data.pack());
+        }
+        Ok(TransactionBuilder::default()
+            .set_cell_deps(cell_deps.into_iter().collect())
+            .set_inputs(inputs)
+            .set_outputs(outputs)
+            .set_outputs_data(outputs_data)
+            .build())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nft_data_encoding() {
+        let issuer = IssuerData {
+            class_count: 3,
+            info: Bytes::from(r#"{"name":"issuer"}"#),
+            ..Default::default()
+        };
+        let issuer_bytes = issuer.to_bytes().unwrap();
+        assert_eq!(&issuer_bytes[0..11], &[0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 17]);
+        assert_eq!(IssuerData::from_slice(&issuer_bytes).unwrap(), issuer);
+
+        let class = ClassData {
+            total: 100,
+            issued: 2,
+            configure: CONFIGURE_NOT_TRANSFERABLE,
+            name: Bytes::from("class"),
+            description: Bytes::from("description"),
+            renderer: Bytes::from("https://example.com/1.png"),
+            extinfo: Bytes::from(vec![9u8; 3]),
+            ..Default::default()
+        };
+        let class_bytes = class.to_bytes().unwrap();
+        assert_eq!(ClassData::from_slice(&class_bytes).unwrap(), class);
+        assert!(ClassData::from_slice(&class_bytes[0..12]).is_err());
+
+        let nft = NftData {
+            characteristic: [1u8; 8],
+            configure: CONFIGURE_NOT_TRANSFERABLE,
+            ..Default::default()
+        };
+        let nft_bytes = nft.to_bytes();
+        assert_eq!(nft_bytes.len(), 11);
+        assert_eq!(NftData::from_slice(&nft_bytes).unwrap(), nft);
+        assert!(!nft.is_transferable());
+    }
+}
@@ -1,14 +1,19 @@
 use std::collections::HashSet;
 
+use anyhow::anyhow;
 use ckb_types::{
     bytes::Bytes,
-    core::{DepType, TransactionBuilder, TransactionView},
-    packed::{CellDep, CellInput, CellOutput, OutPoint},
+    core::{DepType, ScriptHashType, TransactionBuilder, TransactionView},
+    packed::{CellDep, CellInput, CellOutput, OutPoint, Script},
     prelude::*,
+    H256,
 };
 
 use super::{TxBuilder, TxBuilderError};
-use crate::types::ScriptId;
+use crate::types::{
+    xudt_rce_mol::{RCData, RCDataUnion},
+    ScriptId,
+};
 use crate::{
     traits::{CellCollector, CellDepResolver, HeaderDepResolver, TransactionDependencyProvider},
     unlock::OmniLockConfig,
@@ -102,3 +107,129 @@ impl TxBuilder for OmniLockTransferBuilder {
             .build())
     }
 }
+
+/// The max nested level of the RC cells
+const MAX_RC_DEPTH: usize = 8;
+
+/// Resolve the RC (RCE rules) cells referenced by the admin config's
+/// `rc_type_id`, the referenced child RC cells are resolved recursively.
+///
+/// Every RC cell is located by its type script hash through `cell_dep_resolver`
+/// (as a `type` script id), the child cells are returned before the parent.
+///
+/// Return value:
+///   * The RC cells
+///   * The count of the RC rules
+pub fn resolve_rce_cells(
+    rc_type_id: &H256,
+    cell_dep_resolver: &dyn CellDepResolver,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+) -> Result<(Vec<OutPoint>, usize), TxBuilderError> {
+    let mut rce_cells = Vec::new();
+    let rules_count = resolve_rce_cell(
+        rc_type_id,
+        cell_dep_resolver,
+        tx_dep_provider,
+        &mut rce_cells,
+        0,
+    )?;
+    Ok((rce_cells, rules_count))
+}
+
+fn resolve_rce_cell(
+    type_hash: &H256,
+    cell_dep_resolver: &dyn CellDepResolver,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+    rce_cells: &mut Vec<OutPoint>,
+    depth: usize,
+) -> Result<usize, TxBuilderError> {
+    if depth >= MAX_RC_DEPTH {
+        return Err(TxBuilderError::Other(anyhow!(
+            "RC cells nested too deep, max level: {}",
+            MAX_RC_DEPTH
+        )));
+    }
+    let script = Script::new_builder()
+        .code_hash(type_hash.pack())
+        .hash_type(ScriptHashType::Type.into())
+        .build();
+    let out_point = cell_dep_resolver
+        .resolve(&script)
+        .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(script.clone()))?
+        .out_point();
+    let data = tx_dep_provider.get_cell_data(&out_point)?;
+    let rc_data = RCData::from_slice(data.as_ref()).map_err(|err| {
+        TxBuilderError::Other(anyhow!("invalid RC cell data of {:#x}: {}", type_hash, err))
+    })?;
+    let rules_count = match rc_data.to_enum() {
+        RCDataUnion::RCRule(_) => 1,
+        RCDataUnion::RCCellVec(cell_vec) => {
+            let mut count = 0;
+            for child_hash in cell_vec.into_iter() {
+                let child_hash: H256 = child_hash.unpack();
+                count += resolve_rce_cell(
+                    &child_hash,
+                    cell_dep_resolver,
+                    tx_dep_provider,
+                    rce_cells,
+                    depth + 1,
+                )?;
+            }
+            count
+        }
+    };
+    if !rce_cells.contains(&out_point) {
+        rce_cells.push(out_point);
+    }
+    Ok(rules_count)
+}
+
+/// A builder to build an omnilock transfer transaction in administrator mode.
+///
+/// The RC cells are resolved from the admin config's `rc_type_id`, they are
+/// put into cell deps or inputs (when `rce_in_input` is set). The smt proofs
+/// in the admin config must match the RC rules, the unlocker fills them into
+/// the `OmniLockWitnessLock` with the admin identity.
+pub struct OmniLockAdminTransferBuilder {
+    pub outputs: Vec<(CellOutput, Bytes)>,
+    pub cfg: OmniLockConfig,
+}
+
+impl OmniLockAdminTransferBuilder {
+    pub fn new(
+        outputs: Vec<(CellOutput, Bytes)>,
+        cfg: OmniLockConfig,
+    ) -> OmniLockAdminTransferBuilder {
+        OmniLockAdminTransferBuilder { outputs, cfg }
+    }
+}
+
+impl TxBuilder for OmniLockAdminTransferBuilder {
+    fn build_base(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        let admin_cfg = self.cfg.get_admin_config().ok_or_else(|| {
+            TxBuilderError::InvalidParameter(anyhow!("omnilock admin config not set"))
+        })?;
+        let (rce_cells, rules_count) =
+            resolve_rce_cells(admin_cfg.rc_type_id(), cell_dep_resolver, tx_dep_provider)?;
+        if admin_cfg.proofs().len() != rules_count {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "smt proofs count not match the RC rules, expected: {}, actual: {}",
+                rules_count,
+                admin_cfg.proofs().len()
+            )));
+        }
+        OmniLockTransferBuilder::new(self.outputs.clone(), self.cfg.clone(), Some(rce_cells))
+            .build_base(
+                cell_collector,
+                cell_dep_resolver,
+                header_dep_resolver,
+                tx_dep_provider,
+            )
+    }
+}
@@ -0,0 +1,200 @@
+//! Input ordering constraints.
+//!
+//! Some scripts expect the inputs of their group at certain positions, e.g. a
+//! type script which checks the first input of the transaction, or one which
+//! requires its inputs after all the other inputs. The balancer appends the
+//! capacity inputs after the inputs of the builder and may shuffle them (see
+//! [`shuffle`](super::shuffle)), an [`InputOrdering`] tells it which positions
+//! must be kept. Set it to
+//! [`CapacityBalancer::input_ordering`](super::CapacityBalancer::input_ordering),
+//! or return it from
+//! [`TxBuilder::input_ordering`](super::TxBuilder::input_ordering).
+use ckb_types::{core::TransactionView, packed::OutPoint};
+
+use super::{shuffle::reorder_inputs, BalanceTxCapacityError};
+use crate::traits::TransactionDependencyProvider;
+
+/// An ordering constraint of an input
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum InputConstraint {
+    /// The input must be at this index
+    Pinned(OutPoint, usize),
+    /// The input must be after all the inputs not trailing, the trailing
+    /// inputs are in the order of their constraints.
+    Trailing(OutPoint),
+}
+
+/// The ordering constraints of the inputs, the inputs without constraint
+/// keep their relative order.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct InputOrdering {
+    pub constraints: Vec<InputConstraint>,
+}
+
+impl InputOrdering {
+    pub fn new() -> InputOrdering {
+        InputOrdering::default()
+    }
+
+    /// Keep all the current inputs of the transaction at their indices
+    pub fn pin_all(tx: &TransactionView) -> InputOrdering {
+        InputOrdering {
+            constraints: tx
+                .input_pts_iter()
+                .enumerate()
+                .map(|(index, out_point)| InputConstraint::Pinned(out_point, index))
+                .collect(),
+        }
+    }
+
+    /// Pin the input at `index`
+    pub fn pin(mut self, out_point: OutPoint, index: usize) -> InputOrdering {
+        self.constraints
+            .push(InputConstraint::Pinned(out_point, index));
+        self
+    }
+
+    /// Put the input after the other inputs, see [`InputConstraint::Trailing`]
+    pub fn trailing(mut self, out_point: OutPoint) -> InputOrdering {
+        self.constraints.push(InputConstraint::Trailing(out_point));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.constraints.is_empty()
+    }
+
+    /// Add the constraints of `other`
+    pub fn extend(&mut self, other: InputOrdering) {
+        self.constraints.extend(other.constraints);
+    }
+
+    /// Check if the inputs of the transaction satisfy the constraints
+    pub fn is_satisfied(&self, tx: &TransactionView) -> Result<bool, BalanceTxCapacityError> {
+        Ok(self
+            .input_order(tx)?
+            .iter()
+            .enumerate()
+            .all(|(new_idx, old_idx)| new_idx == *old_idx))
+    }
+
+    /// Reorder the inputs of the transaction to satisfy the constraints, the
+    /// witnesses are moved along with their inputs (see
+    /// [`shuffle`](super::shuffle) for the limitations).
+    pub fn apply(
+        &self,
+        tx: &TransactionView,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, BalanceTxCapacityError> {
+        let input_order = self.input_order(tx)?;
+        if input_order
+            .iter()
+            .enumerate()
+            .all(|(new_idx, old_idx)| new_idx == *old_idx)
+        {
+            return Ok(tx.clone());
+        }
+        reorder_inputs(tx, tx_dep_provider, &input_order)
+    }
+
+    /// new index => old index
+    fn input_order(&self, tx: &TransactionView) -> Result<Vec<usize>, BalanceTxCapacityError> {
+        let out_points: Vec<_> = tx.input_pts_iter().collect();
+        let inputs_len = out_points.len();
+        let find_input = |out_point: &OutPoint| {
+            out_points
+                .iter()
+                .position(|item| item == out_point)
+                .ok_or_else(|| {
+                    BalanceTxCapacityError::InputOrdering(format!(
+                        "input {} not found in the transaction",
+                        out_point
+                    ))
+                })
+        };
+        let mut pinned = Vec::new();
+        let mut trailing = Vec::new();
+        for constraint in &self.constraints {
+            match constraint {
+                InputConstraint::Pinned(out_point, index) => {
+                    pinned.push((find_input(out_point)?, *index))
+                }
+                InputConstraint::Trailing(out_point) => {
+                    let old_idx = find_input(out_point)?;
+                    if !trailing.contains(&old_idx) {
+                        trailing.push(old_idx);
+                    }
+                }
+            }
+        }
+
+        let mut slots: Vec<Option<usize>> = vec![None; inputs_len];
+        let trailing_start = inputs_len - trailing.len();
+        for (offset, old_idx) in trailing.iter().enumerate() {
+            slots[trailing_start + offset] = Some(*old_idx);
+        }
+        for (old_idx, index) in pinned {
+            if trailing.contains(&old_idx) {
+                return Err(BalanceTxCapacityError::InputOrdering(format!(
+                    "input {} is both pinned and trailing",
+                    out_points[old_idx]
+                )));
+            }
+            match slots.get(index) {
+                Some(None) if !slots.contains(&Some(old_idx)) => slots[index] = Some(old_idx),
+                Some(Some(item)) if *item == old_idx => {}
+                _ => {
+                    return Err(BalanceTxCapacityError::InputOrdering(format!(
+                        "can not pin input {} at index {}",
+                        out_points[old_idx], index
+                    )))
+                }
+            }
+        }
+        let placed: Vec<_> = slots.iter().flatten().copied().collect();
+        let mut free_inputs = (0..inputs_len).filter(|old_idx| !placed.contains(old_idx));
+        Ok(slots
+            .into_iter()
+            .map(|slot| slot.unwrap_or_else(|| free_inputs.next().expect("enough free inputs")))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::{core::TransactionBuilder, packed::CellInput, prelude::*, H256};
+
+    fn out_point(n: u8) -> OutPoint {
+        OutPoint::new(H256([n; 32]).pack(), 0)
+    }
+
+    #[test]
+    fn test_input_order() {
+        let tx = TransactionBuilder::default()
+            .inputs((0..5).map(|n| CellInput::new(out_point(n), 0)))
+            .build();
+        assert!(InputOrdering::pin_all(&tx).is_satisfied(&tx).unwrap());
+
+        let ordering = InputOrdering::new()
+            .trailing(out_point(1))
+            .pin(out_point(4), 0);
+        assert!(!ordering.is_satisfied(&tx).unwrap());
+        assert_eq!(ordering.input_order(&tx).unwrap(), vec![4, 0, 2, 3, 1]);
+
+        let ordering = InputOrdering::new()
+            .trailing(out_point(0))
+            .pin(out_point(0), 0);
+        assert!(ordering.input_order(&tx).is_err());
+        let ordering = InputOrdering::new()
+            .pin(out_point(0), 1)
+            .pin(out_point(1), 1);
+        assert!(ordering.input_order(&tx).is_err());
+        let ordering = InputOrdering::new()
+            .pin(out_point(0), 1)
+            .pin(out_point(0), 2);
+        assert!(ordering.input_order(&tx).is_err());
+        let ordering = InputOrdering::new().pin(out_point(9), 0);
+        assert!(ordering.input_order(&tx).is_err());
+    }
+}
@@ -0,0 +1,240 @@
+//! Machine-readable plan of the balancing decisions.
+//!
+//! [`TxBuilder::build_balanced_with_plan`](super::TxBuilder::build_balanced_with_plan)
+//! returns a [`BalancePlan`] alongside the transaction, it tells why every
+//! input is selected, the fee, what happened to the change and the capacity
+//! provider switches. The plan serializes to JSON, so the planned behaviors
+//! can be reviewed and diffed across SDK upgrades.
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use ckb_jsonrpc_types as json_types;
+use ckb_types::{
+    core::TransactionView,
+    packed::{OutPoint, Script},
+    prelude::*,
+    H256,
+};
+
+use super::{tx_fee, BalanceTxCapacityError};
+use crate::traits::{HeaderDepResolver, TransactionDependencyProvider};
+
+/// Why an input is in the transaction
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InputReason {
+    /// Added by the transaction builder
+    Builder,
+    /// Selected by the balancer to cover the outputs and the fee
+    Capacity,
+    /// Selected by the balancer to hold the change cell
+    Change,
+}
+
+/// The balancer switched to the next capacity provider lock script
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct ProviderSwitch {
+    pub from: json_types::Script,
+    pub to: json_types::Script,
+    pub reason: String,
+}
+
+/// An input of the planned transaction
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct PlannedInput {
+    pub out_point: json_types::OutPoint,
+    pub lock: json_types::Script,
+    pub capacity: json_types::Capacity,
+    pub reason: InputReason,
+}
+
+/// What the balancer did with the capacity left
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(tag = "decision", rename_all = "snake_case")]
+pub enum ChangeDecision {
+    /// No capacity left
+    None,
+    /// The capacity left is put into the change output
+    Output {
+        index: json_types::Uint32,
+        capacity: json_types::Capacity,
+    },
+    /// The capacity left is too small for a change cell and paid as fee
+    AbsorbedAsFee { capacity: json_types::Capacity },
+}
+
+/// The plan of the balancing decisions
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct BalancePlan {
+    pub tx_hash: H256,
+    pub inputs: Vec<PlannedInput>,
+    pub outputs_capacity: json_types::Capacity,
+    pub fee: json_types::Capacity,
+    pub tx_size: json_types::Uint64,
+    pub change: ChangeDecision,
+    pub provider_switches: Vec<ProviderSwitch>,
+}
+
+impl BalancePlan {
+    /// Assemble the plan of the balanced transaction from the recorded
+    /// decisions.
+    pub(crate) fn new(
+        tx: &TransactionView,
+        change_index: Option<usize>,
+        record: BalanceRecord,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+        header_dep_resolver: &dyn HeaderDepResolver,
+    ) -> Result<BalancePlan, BalanceTxCapacityError> {
+        let mut inputs = Vec::with_capacity(tx.inputs().len());
+        for out_point in tx.input_pts_iter() {
+            let output = tx_dep_provider.get_cell(&out_point)?;
+            let reason = record
+                .selected
+                .iter()
+                .find(|(selected, _)| *selected == out_point)
+                .map(|(_, reason)| *reason)
+                .unwrap_or(InputReason::Builder);
+            inputs.push(PlannedInput {
+                out_point: out_point.into(),
+                lock: output.lock().into(),
+                capacity: Unpack::<u64>::unpack(&output.capacity()).into(),
+                reason,
+            });
+        }
+        let change = match change_index.and_then(|idx| tx.output(idx).map(|output| (idx, output))) {
+            Some((idx, output)) => ChangeDecision::Output {
+                index: (idx as u32).into(),
+                capacity: Unpack::<u64>::unpack(&output.capacity()).into(),
+            },
+            None => match record.absorbed_change {
+                Some(capacity) => ChangeDecision::AbsorbedAsFee {
+                    capacity: capacity.into(),
+                },
+                None => ChangeDecision::None,
+            },
+        };
+        Ok(BalancePlan {
+            tx_hash: tx.hash().unpack(),
+            inputs,
+            outputs_capacity: outputs_capacity(tx).into(),
+            fee: tx_fee(tx.clone(), tx_dep_provider, header_dep_resolver)?.into(),
+            tx_size: (tx.data().as_reader().serialized_size_in_block() as u64).into(),
+            change,
+            provider_switches: record.provider_switches,
+        })
+    }
+
+    /// The plan in pretty printed JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("serialize balance plan")
+    }
+}
+
+/// The summary of a balanced transaction, see
+/// [`balance_tx_capacity_with_report`](super::balance_tx_capacity_with_report).
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct BalanceReport {
+    /// The cells selected by the balancer
+    pub selected_cells: Vec<OutPoint>,
+    /// The total capacity of all inputs
+    pub inputs_capacity: u64,
+    /// The total capacity of all outputs
+    pub outputs_capacity: u64,
+    pub fee: u64,
+    /// The index of the change output (or the send max output)
+    pub change_index: Option<usize>,
+    /// The number of the cell collector queries issued
+    pub collector_queries: usize,
+}
+
+impl BalanceReport {
+    pub(crate) fn new(
+        tx: &TransactionView,
+        change_index: Option<usize>,
+        record: BalanceRecord,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+        header_dep_resolver: &dyn HeaderDepResolver,
+    ) -> Result<BalanceReport, BalanceTxCapacityError> {
+        let mut inputs_capacity = 0;
+        for out_point in tx.input_pts_iter() {
+            let output = tx_dep_provider.get_cell(&out_point)?;
+            inputs_capacity += Unpack::<u64>::unpack(&output.capacity());
+        }
+        Ok(BalanceReport {
+            selected_cells: record
+                .selected
+                .into_iter()
+                .map(|(out_point, _)| out_point)
+                .collect(),
+            inputs_capacity,
+            outputs_capacity: outputs_capacity(tx),
+            fee: tx_fee(tx.clone(), tx_dep_provider, header_dep_resolver)?,
+            change_index,
+            collector_queries: record.collector_queries,
+        })
+    }
+}
+
+fn outputs_capacity(tx: &TransactionView) -> u64 {
+    tx.outputs()
+        .into_iter()
+        .map(|output| Unpack::<u64>::unpack(&output.capacity()))
+        .sum()
+}
+
+/// The decisions recorded during balancing
+#[derive(Debug, Clone, Default)]
+pub(crate) struct BalanceRecord {
+    pub(crate) selected: Vec<(OutPoint, InputReason)>,
+    pub(crate) provider_switches: Vec<ProviderSwitch>,
+    pub(crate) collector_queries: usize,
+    pub(crate) absorbed_change: Option<u64>,
+    pub(crate) missing_capacity: u64,
+}
+
+impl BalanceRecord {
+    pub(crate) fn switch_provider(&mut self, from: &Script, to: &Script, reason: &str) {
+        self.provider_switches.push(ProviderSwitch {
+            from: from.clone().into(),
+            to: to.clone().into(),
+            reason: reason.to_string(),
+        });
+    }
+}
+
+/// Record the balancing decisions, set it to
+/// [`CapacityBalancer::recorder`](super::CapacityBalancer::recorder). The
+/// clones of a recorder share the records.
+#[derive(Debug, Clone, Default)]
+pub struct BalanceRecorder(Arc<Mutex<BalanceRecord>>);
+
+impl BalanceRecorder {
+    pub fn new() -> BalanceRecorder {
+        BalanceRecorder::default()
+    }
+
+    /// The cells selected by the balancer so far
+    pub fn selected_cells(&self) -> Vec<OutPoint> {
+        self.0
+            .lock()
+            .selected
+            .iter()
+            .map(|(out_point, _)| out_point.clone())
+            .collect()
+    }
+
+    /// The number of the cell collector queries issued so far
+    pub fn collector_queries(&self) -> usize {
+        self.0.lock().collector_queries
+    }
+
+    pub(crate) fn record<F: FnOnce(&mut BalanceRecord)>(&self, f: F) {
+        f(&mut self.0.lock());
+    }
+
+    pub(crate) fn take(&self) -> BalanceRecord {
+        std::mem::take(&mut self.0.lock())
+    }
+}
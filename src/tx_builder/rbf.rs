@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use ckb_types::core::{FeeRate, TransactionView};
+
+use super::{
+    amend::TxAmendBuilder, fill_placeholder_witnesses, tx_fee, unlock_tx, CapacityBalancer,
+    TxBuilder, TxBuilderError,
+};
+use crate::traits::{
+    CellCollector, CellDepResolver, HeaderDepResolver, TransactionDependencyProvider,
+};
+use crate::types::{ScriptGroup, ScriptId};
+use crate::unlock::ScriptUnlocker;
+
+/// The default `min_rbf_rate` of the node (shannons/KB)
+pub const DEFAULT_MIN_RBF_RATE: u64 = 1500;
+
+/// The max rounds to raise the fee, adding inputs makes the replacement bigger
+/// so the required fee may change after a round.
+const MAX_BUMP_ROUNDS: usize = 4;
+
+/// Build a replacement of a pending transaction with higher fee.
+///
+/// The inputs and outputs of the pending transaction are kept, the fee is
+/// raised by reducing the change output (`change_index`), or by adding inputs
+/// from the balancer's capacity provider (a new change output will be
+/// created). The replacement fee satisfies the node's RBF rule:
+///
+/// ```text
+/// fee >= original_fee + min_rbf_rate * replacement_size
+/// ```
+///
+/// and the fee rate of the balancer. The witness locks of the pending
+/// transaction are cleared and signed again by the unlockers.
+#[derive(Debug, Clone)]
+pub struct ReplaceByFeeBuilder {
+    /// The pending transaction to replace
+    pub tx: TransactionView,
+    /// The change output of the pending transaction
+    pub change_index: Option<usize>,
+    /// The `min_rbf_rate` of the node (shannons/KB)
+    pub min_rbf_rate: u64,
+}
+
+impl ReplaceByFeeBuilder {
+    pub fn new(tx: TransactionView, change_index: Option<usize>) -> ReplaceByFeeBuilder {
+        ReplaceByFeeBuilder {
+            tx,
+            change_index,
+            min_rbf_rate: DEFAULT_MIN_RBF_RATE,
+        }
+    }
+
+    /// The minimal fee of the replacement with `tx_size` bytes
+    pub fn min_replace_fee(&self, original_fee: u64, tx_size: usize) -> u64 {
+        original_fee
+            + FeeRate::from_u64(self.min_rbf_rate)
+                .fee(tx_size as u64)
+                .as_u64()
+    }
+
+    /// Build the replacement transaction, unlocked by `unlockers`.
+    ///
+    /// Return value:
+    ///   * The replacement transaction
+    ///   * The script groups not unlocked by given `unlockers`
+    #[allow(clippy::too_many_arguments)]
+    pub fn build_replacement(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+        balancer: &CapacityBalancer,
+        unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+    ) -> Result<(TransactionView, Vec<ScriptGroup>), TxBuilderError> {
+        let original_fee = tx_fee(self.tx.clone(), tx_dep_provider, header_dep_resolver)
+            .map_err(|err| TxBuilderError::Other(anyhow!(err)))?;
+        let base_tx = TxAmendBuilder::new(self.tx.clone()).build_base(
+            cell_collector,
+            cell_dep_resolver,
+            header_dep_resolver,
+            tx_dep_provider,
+        )?;
+        let (mut tx, _) = fill_placeholder_witnesses(base_tx, tx_dep_provider, unlockers)?;
+        let mut change_index = self.change_index;
+        for _ in 0..MAX_BUMP_ROUNDS {
+            let tx_size = tx.data().as_reader().serialized_size_in_block();
+            let min_fee = self.min_replace_fee(original_fee, tx_size);
+            let fee = tx_fee(tx.clone(), tx_dep_provider, header_dep_resolver)
+                .map_err(|err| TxBuilderError::Other(anyhow!(err)))?;
+            if fee >= min_fee {
+                return Ok(unlock_tx(tx, tx_dep_provider, unlockers)?);
+            }
+            let (new_tx, new_change_index) = balancer.rebalance_tx_capacity(
+                &tx,
+                cell_collector,
+                tx_dep_provider,
+                cell_dep_resolver,
+                header_dep_resolver,
+                min_fee,
+                change_index,
+            )?;
+            // the placeholders of the new inputs are filled by the balancer
+            tx = new_tx;
+            change_index = new_change_index;
+        }
+        Err(TxBuilderError::Other(anyhow!(
+            "can not raise the fee to meet the RBF rule in {} rounds",
+            MAX_BUMP_ROUNDS
+        )))
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+
+use anyhow::anyhow;
+use ckb_types::{
+    bytes::Bytes,
+    core::{Capacity, FeeRate, TransactionBuilder, TransactionView},
+    packed::{CellInput, CellOutput, OutPoint, Script},
+    prelude::*,
+};
+
+use super::{fill_placeholder_witnesses, unlock_tx, TxBuilderError};
+use crate::traits::{CellDepResolver, TransactionDependencyProvider};
+use crate::types::{ScriptGroup, ScriptId};
+use crate::unlock::ScriptUnlocker;
+
+/// Destroy the given cells (e.g. obsolete contract cells, empty ACP cells)
+/// and return their capacity to the receiver lock script.
+///
+/// The transaction fee is paid by the reclaimed capacity. A cell is treated
+/// as a UDT cell when it has a type script (other than type id) and the first
+/// 16 bytes of the data is a non-zero amount, such cells are rejected unless
+/// `allow_udt` is set since the amount will be burned. DAO cells are always
+/// rejected, they must be withdrawn by the DAO builders.
+#[derive(Debug, Clone)]
+pub struct ReclaimBuilder {
+    /// The cells to destroy
+    pub cells: Vec<OutPoint>,
+    /// The lock script of the reclaimed capacity
+    pub receiver: Script,
+    /// Allow destroying the cells carrying UDT amounts
+    pub allow_udt: bool,
+    /// The fee rate (shannons/KB)
+    pub fee_rate: u64,
+}
+
+impl ReclaimBuilder {
+    pub fn new(cells: Vec<OutPoint>, receiver: Script, fee_rate: u64) -> ReclaimBuilder {
+        ReclaimBuilder {
+            cells,
+            receiver,
+            allow_udt: false,
+            fee_rate,
+        }
+    }
+
+    /// The UDT amount of the cell, `None` if it is not a UDT cell
+    pub fn udt_amount(output: &CellOutput, output_data: &[u8]) -> Option<u128> {
+        let type_script = output.type_().to_opt()?;
+        if ScriptId::from(&type_script).is_type_id() || output_data.len() < 16 {
+            return None;
+        }
+        let amount = u128::from_le_bytes(output_data[0..16].try_into().unwrap());
+        if amount > 0 {
+            Some(amount)
+        } else {
+            None
+        }
+    }
+
+    /// Build the reclaim transaction, unlocked by `unlockers`.
+    ///
+    /// Return value:
+    ///   * The transaction with the script groups not unlocked by given
+    ///     `unlockers`
+    pub fn build_reclaim_tx(
+        &self,
+        cell_dep_resolver: &dyn CellDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+        unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+    ) -> Result<(TransactionView, Vec<ScriptGroup>), TxBuilderError> {
+        let base_tx = self.build_base(cell_dep_resolver, tx_dep_provider)?;
+        let (tx, _) = fill_placeholder_witnesses(base_tx, tx_dep_provider, unlockers)?;
+        let tx = self.deduct_fee(tx)?;
+        Ok(unlock_tx(tx, tx_dep_provider, unlockers)?)
+    }
+
+    fn build_base(
+        &self,
+        cell_dep_resolver: &dyn CellDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        if self.cells.is_empty() {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "empty cells to reclaim"
+            )));
+        }
+        #[allow(clippy::mutable_key_type)]
+        let mut cell_deps = HashSet::new();
+        #[allow(clippy::mutable_key_type)]
+        let mut out_points = HashSet::new();
+        let mut inputs = Vec::new();
+        let mut input_total = 0;
+        for out_point in &self.cells {
+            if !out_points.insert(out_point.clone()) {
+                return Err(TxBuilderError::InvalidParameter(anyhow!(
+                    "duplicated cell: {}",
+                    out_point
+                )));
+            }
+            let output = tx_dep_provider.get_cell(out_point)?;
+            let output_data = tx_dep_provider.get_cell_data(out_point)?;
+            let lock_script = output.lock();
+            let cell_dep = cell_dep_resolver
+                .resolve(&lock_script)
+                .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(lock_script.clone()))?;
+            cell_deps.insert(cell_dep);
+            if let Some(type_script) = output.type_().to_opt() {
+                let script_id = ScriptId::from(&type_script);
+                if script_id.is_dao() {
+                    return Err(TxBuilderError::InvalidParameter(anyhow!(
+                        "can not reclaim DAO cell: {}",
+                        out_point
+                    )));
+                }
+                if let Some(amount) = Self::udt_amount(&output, output_data.as_ref()) {
+                    if !self.allow_udt {
+                        return Err(TxBuilderError::InvalidParameter(anyhow!(
+                            "cell {} carries UDT amount: {}",
+                            out_point,
+                            amount
+                        )));
+                    }
+                }
+                if !script_id.is_type_id() {
+                    let cell_dep = cell_dep_resolver
+                        .resolve(&type_script)
+                        .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(type_script.clone()))?;
+                    cell_deps.insert(cell_dep);
+                }
+            }
+            inputs.push(CellInput::new(out_point.clone(), 0));
+            input_total += Unpack::<u64>::unpack(&output.capacity());
+        }
+        let output = CellOutput::new_builder()
+            .capacity(input_total.pack())
+            .lock(self.receiver.clone())
+            .build();
+        Ok(TransactionBuilder::default()
+            .set_cell_deps(cell_deps.into_iter().collect())
+            .set_inputs(inputs)
+            .set_outputs(vec![output])
+            .set_outputs_data(vec![Bytes::new().pack()])
+            .build())
+    }
+
+    /// Pay the transaction fee from the reclaimed output, the witnesses must
+    /// be filled with placeholders.
+    fn deduct_fee(&self, tx: TransactionView) -> Result<TransactionView, TxBuilderError> {
+        let output = tx.output(0).expect("reclaim output");
+        let capacity: u64 = output.capacity().unpack();
+        let tx_size = tx.data().as_reader().serialized_size_in_block();
+        let fee = FeeRate::from_u64(self.fee_rate)
+            .fee(tx_size as u64)
+            .as_u64();
+        let occupied_capacity = output
+            .occupied_capacity(Capacity::zero())
+            .expect("occupied capacity")
+            .as_u64();
+        if capacity < occupied_capacity + fee {
+            return Err(TxBuilderError::Other(anyhow!(
+                "reclaimed capacity not enough, required: {}, actual: {}",
+                occupied_capacity + fee,
+                capacity
+            )));
+        }
+        let output = output
+            .as_builder()
+            .capacity((capacity - fee).pack())
+            .build();
+        Ok(tx.as_advanced_builder().set_outputs(vec![output]).build())
+    }
+}
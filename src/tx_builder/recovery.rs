@@ -0,0 +1,343 @@
+//! Recover the cells under the lock scripts derivable from the known keys
+//! (or mnemonics).
+//!
+//! Funds may be sent to any lock script variant of a key: the sighash
+//! address, an anyone-can-pay address, an omnilock address (with the CKB or
+//! the Ethereum identity) or a multisig address the key is a member of.
+//! [`RecoveryScanner`] enumerates those variants, scans the chain for the
+//! live cells under each of them and builds the [`RecoveryPlan`] of the
+//! sweep transactions moving the plain cells to a receiver.
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::Path;
+
+use anyhow::anyhow;
+use ckb_crypto::secp::Pubkey;
+use ckb_types::{
+    core::TransactionView,
+    packed::{OutPoint, Script},
+    prelude::*,
+    H160,
+};
+
+use super::{sweep::SweepBuilder, TxBuilderError};
+use crate::constants::{MULTISIG_TYPE_HASH, SIGHASH_TYPE_HASH};
+use crate::traits::{
+    CellCollector, CellDepResolver, CellQueryOptions, LiveCell, SecpCkbRawKeySigner, Signer,
+    TransactionDependencyProvider,
+};
+use crate::types::bip32::{
+    account_path, Bip32Error, ExtendedPrivKey, CHANGE_CHAIN, EXTERNAL_CHAIN,
+};
+#[cfg(feature = "keystore")]
+use crate::types::mnemonic::Mnemonic;
+use crate::types::{ScriptGroup, ScriptId};
+use crate::unlock::{
+    AcpUnlocker, MultisigConfig, OmniLockConfig, OmniLockUnlocker, OmniUnlockMode, ScriptUnlocker,
+    SecpMultisigUnlocker, SecpSighashUnlocker,
+};
+use crate::util::{blake160, keccak160};
+use crate::SECP256K1;
+
+/// The lock script variants of the recovery
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum RecoveryLock {
+    Sighash(H160),
+    Acp(H160),
+    OmniLock(OmniLockConfig),
+    Multisig(MultisigConfig),
+}
+
+impl RecoveryLock {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RecoveryLock::Sighash(_) => "sighash",
+            RecoveryLock::Acp(_) => "acp",
+            RecoveryLock::OmniLock(config) if config.is_ethereum() => "omnilock-ethereum",
+            RecoveryLock::OmniLock(config) if config.is_multisig() => "omnilock-multisig",
+            RecoveryLock::OmniLock(_) => "omnilock",
+            RecoveryLock::Multisig(_) => "multisig",
+        }
+    }
+}
+
+/// A lock script variant and its live cells
+#[derive(Debug, Clone)]
+pub struct RecoveredLock {
+    pub lock: RecoveryLock,
+    pub lock_script: Script,
+    pub cells: Vec<LiveCell>,
+}
+
+impl RecoveredLock {
+    pub fn capacity(&self) -> u64 {
+        self.cells
+            .iter()
+            .map(|cell| Unpack::<u64>::unpack(&cell.output.capacity()))
+            .sum()
+    }
+
+    /// The cells with type script, they are not swept
+    pub fn type_cells(&self) -> Vec<OutPoint> {
+        self.cells
+            .iter()
+            .filter(|cell| cell.output.type_().is_some())
+            .map(|cell| cell.out_point.clone())
+            .collect()
+    }
+}
+
+/// The found cells and the sweep transactions to recover them
+#[derive(Debug, Clone, Default)]
+pub struct RecoveryPlan {
+    pub found: Vec<RecoveredLock>,
+    /// The sweep transactions with the script groups not unlocked (e.g. a
+    /// multisig lock without enough known keys), the transactions of the
+    /// same lock script must be sent in order.
+    pub txs: Vec<(TransactionView, Vec<ScriptGroup>)>,
+}
+
+impl RecoveryPlan {
+    pub fn total_capacity(&self) -> u64 {
+        self.found.iter().map(|found| found.capacity()).sum()
+    }
+}
+
+/// Load the multisig configs from a JSON file of a config array
+pub fn load_multisig_configs<P: AsRef<Path>>(
+    path: P,
+) -> Result<Vec<MultisigConfig>, TxBuilderError> {
+    let path = path.as_ref();
+    let content = std::fs::read_to_string(path).map_err(|err| {
+        TxBuilderError::InvalidParameter(anyhow!(
+            "read multisig configs from {} failed: {}",
+            path.display(),
+            err
+        ))
+    })?;
+    serde_json::from_str(&content).map_err(|err| {
+        TxBuilderError::InvalidParameter(anyhow!(
+            "parse multisig configs from {} failed: {}",
+            path.display(),
+            err
+        ))
+    })
+}
+
+/// Enumerate the lock script variants of the keys and recover their cells.
+///
+/// The anyone-can-pay and the omnilock variants are only scanned when their
+/// script ids (which differ across networks) are given.
+pub struct RecoveryScanner {
+    pub keys: Vec<secp256k1::SecretKey>,
+    /// The multisig configs, see [`load_multisig_configs`]
+    pub multisig_configs: Vec<MultisigConfig>,
+    pub acp_script_id: Option<ScriptId>,
+    pub omnilock_script_id: Option<ScriptId>,
+}
+
+impl RecoveryScanner {
+    pub fn new(keys: Vec<secp256k1::SecretKey>) -> RecoveryScanner {
+        RecoveryScanner {
+            keys,
+            multisig_configs: Vec::new(),
+            acp_script_id: None,
+            omnilock_script_id: None,
+        }
+    }
+
+    /// Add the keys of the accounts derived from the seed, the first
+    /// `gap_limit` keys of both the receiving and the change chains.
+    pub fn add_seed(
+        &mut self,
+        seed: &[u8],
+        accounts: Range<u32>,
+        gap_limit: u32,
+    ) -> Result<(), Bip32Error> {
+        let master_key = ExtendedPrivKey::new_master(seed)?;
+        for account in accounts {
+            let account_key = master_key.derive_path(&account_path(account))?;
+            for chain in [EXTERNAL_CHAIN, CHANGE_CHAIN] {
+                let chain_key = account_key.derive_child(chain)?;
+                for index in 0..gap_limit {
+                    // An invalid child key is skipped as BIP-32 suggests
+                    if let Ok(key) = chain_key.derive_child(index) {
+                        self.keys.push(key.private_key);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Same as [`RecoveryScanner::add_seed`] with the seed of the mnemonic
+    #[cfg(feature = "keystore")]
+    pub fn add_mnemonic(
+        &mut self,
+        mnemonic: &Mnemonic,
+        passphrase: &str,
+        accounts: Range<u32>,
+        gap_limit: u32,
+    ) -> Result<(), Bip32Error> {
+        self.add_seed(&mnemonic.to_seed(passphrase), accounts, gap_limit)
+    }
+
+    fn pubkeys(&self) -> Vec<secp256k1::PublicKey> {
+        self.keys
+            .iter()
+            .map(|key| secp256k1::PublicKey::from_secret_key(&SECP256K1, key))
+            .collect()
+    }
+
+    /// All the lock script variants to scan
+    pub fn locks(&self) -> Vec<RecoveryLock> {
+        let mut locks = Vec::new();
+        for pubkey in self.pubkeys() {
+            let pubkey_hash = blake160(&pubkey.serialize());
+            locks.push(RecoveryLock::Sighash(pubkey_hash.clone()));
+            if self.acp_script_id.is_some() {
+                locks.push(RecoveryLock::Acp(pubkey_hash.clone()));
+            }
+            if self.omnilock_script_id.is_some() {
+                let eth_hash = keccak160(Pubkey::from(pubkey).as_ref());
+                locks.push(RecoveryLock::OmniLock(OmniLockConfig::new_pubkey_hash(
+                    pubkey_hash,
+                )));
+                locks.push(RecoveryLock::OmniLock(OmniLockConfig::new_ethereum(
+                    eth_hash,
+                )));
+            }
+        }
+        for config in &self.multisig_configs {
+            locks.push(RecoveryLock::Multisig(config.clone()));
+            if self.omnilock_script_id.is_some() {
+                locks.push(RecoveryLock::OmniLock(OmniLockConfig::new_multisig(
+                    config.clone(),
+                )));
+            }
+        }
+        locks
+    }
+
+    /// The lock script of the variant
+    pub fn lock_script(&self, lock: &RecoveryLock) -> Result<Script, TxBuilderError> {
+        let (script_id, args) = match lock {
+            RecoveryLock::Sighash(pubkey_hash) => (
+                ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+                pubkey_hash.as_bytes().to_vec(),
+            ),
+            RecoveryLock::Multisig(config) => (
+                ScriptId::new_type(MULTISIG_TYPE_HASH.clone()),
+                config.hash160().as_bytes().to_vec(),
+            ),
+            RecoveryLock::Acp(pubkey_hash) => (
+                self.acp_script_id.clone().ok_or_else(|| {
+                    TxBuilderError::InvalidParameter(anyhow!("acp script id not set"))
+                })?,
+                pubkey_hash.as_bytes().to_vec(),
+            ),
+            RecoveryLock::OmniLock(config) => (
+                self.omnilock_script_id.clone().ok_or_else(|| {
+                    TxBuilderError::InvalidParameter(anyhow!("omnilock script id not set"))
+                })?,
+                config.build_args().to_vec(),
+            ),
+        };
+        Ok(Script::new_builder()
+            .code_hash(script_id.code_hash.pack())
+            .hash_type(script_id.hash_type.into())
+            .args(args.pack())
+            .build())
+    }
+
+    fn unlockers(
+        &self,
+        lock: &RecoveryLock,
+        lock_script: &Script,
+    ) -> HashMap<ScriptId, Box<dyn ScriptUnlocker>> {
+        let signer = || -> Box<dyn Signer> {
+            Box::new(SecpCkbRawKeySigner::new_with_secret_keys(self.keys.clone()))
+        };
+        let unlocker: Box<dyn ScriptUnlocker> = match lock {
+            RecoveryLock::Sighash(_) => Box::new(SecpSighashUnlocker::from(signer())),
+            RecoveryLock::Acp(_) => Box::new(AcpUnlocker::from(signer())),
+            RecoveryLock::Multisig(config) => {
+                Box::new(SecpMultisigUnlocker::from((signer(), config.clone())))
+            }
+            RecoveryLock::OmniLock(config) => {
+                let signer: Box<dyn Signer> = if config.is_ethereum() {
+                    Box::new(SecpCkbRawKeySigner::new_with_ethereum_secret_keys(
+                        self.keys.clone(),
+                    ))
+                } else {
+                    signer()
+                };
+                Box::new(OmniLockUnlocker::from((
+                    signer,
+                    config.clone(),
+                    OmniUnlockMode::Normal,
+                )))
+            }
+        };
+        let mut unlockers = HashMap::new();
+        unlockers.insert(ScriptId::from(lock_script), unlocker);
+        unlockers
+    }
+
+    /// Scan the live cells of every lock script variant, the variants
+    /// without cells are not returned. The cells are not marked as used in
+    /// the collector.
+    pub fn scan(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+    ) -> Result<Vec<RecoveredLock>, TxBuilderError> {
+        let mut found = Vec::new();
+        for lock in self.locks() {
+            let lock_script = self.lock_script(&lock)?;
+            let mut query = CellQueryOptions::new_lock(lock_script.clone());
+            query.min_total_capacity = u64::MAX;
+            let (cells, _) = cell_collector.collect_live_cells(&query, false)?;
+            if !cells.is_empty() {
+                found.push(RecoveredLock {
+                    lock,
+                    lock_script,
+                    cells,
+                });
+            }
+        }
+        Ok(found)
+    }
+
+    /// Scan the cells and build the sweep transactions moving the cells
+    /// without type script to `receiver`, the fee of each transaction is
+    /// paid by the swept capacity.
+    pub fn build_plan(
+        &self,
+        receiver: Script,
+        fee_rate: u64,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<RecoveryPlan, TxBuilderError> {
+        let found = self.scan(cell_collector)?;
+        let mut txs = Vec::new();
+        for recovered in &found {
+            if recovered.type_cells().len() == recovered.cells.len() {
+                continue;
+            }
+            let builder = SweepBuilder::new(
+                vec![recovered.lock_script.clone()],
+                receiver.clone(),
+                fee_rate,
+            );
+            let unlockers = self.unlockers(&recovered.lock, &recovered.lock_script);
+            txs.extend(builder.build_sweep_txs(
+                cell_collector,
+                cell_dep_resolver,
+                tx_dep_provider,
+                &unlockers,
+            )?);
+        }
+        Ok(RecoveryPlan { found, txs })
+    }
+}
@@ -0,0 +1,251 @@
+//! Salt (nonce) management for one-time lock args.
+//!
+//! Builders deriving one-time lock args from a salt (e.g. the HTLC hash lock
+//! from a salted preimage) must not reuse a salt for the same recipient,
+//! otherwise repeated payments produce identical cells which the scripts may
+//! conflate. A [`SaltStore`] issues deterministic salts per key and detects
+//! collisions of the salts chosen elsewhere.
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use ckb_hash::new_blake2b;
+use ckb_jsonrpc_types::JsonBytes;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use ckb_types::{bytes::Bytes, molecule, H256};
+
+#[derive(Error, Debug)]
+pub enum SaltStoreError {
+    #[error("state error: `{0}`")]
+    State(anyhow::Error),
+
+    #[error("salt collision, key: `{0}`, salt: `{1}`")]
+    Collision(String, String),
+}
+
+/// Issue and record the salts used by one-time lock args.
+pub trait SaltStore {
+    /// Issue a new salt for the key (e.g. the recipient lock script hash),
+    /// the salt is never issued or registered before for the key.
+    fn next_salt(&mut self, key: &[u8]) -> Result<H256, SaltStoreError>;
+
+    /// Record a salt chosen elsewhere, return an error if it is already used
+    /// for the key.
+    fn register(&mut self, key: &[u8], salt: &[u8]) -> Result<(), SaltStoreError>;
+
+    /// Check if the salt is already used for the key
+    fn is_used(&self, key: &[u8], salt: &[u8]) -> bool;
+}
+
+/// Derive the salt: `blake2b_256(seed || key || nonce)`, the nonce is encoded
+/// in little endian.
+pub fn derive_salt(seed: &H256, key: &[u8], nonce: u64) -> H256 {
+    let mut hasher = new_blake2b();
+    hasher.update(seed.as_bytes());
+    hasher.update(key);
+    hasher.update(&nonce.to_le_bytes());
+    let mut salt = [0u8; 32];
+    hasher.finalize(&mut salt);
+    H256::from(salt)
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct SaltEntry {
+    key: JsonBytes,
+    /// The next nonce to derive salt
+    nonce: u64,
+    salts: Vec<JsonBytes>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct KeySalts {
+    nonce: u64,
+    salts: HashSet<Bytes>,
+}
+
+/// An in-memory salt store, the salts are derived from the `seed` so they are
+/// reproducible by the same seed.
+#[derive(Debug, Clone)]
+pub struct MemorySaltStore {
+    seed: H256,
+    keys: HashMap<Bytes, KeySalts>,
+}
+
+impl MemorySaltStore {
+    pub fn new(seed: H256) -> MemorySaltStore {
+        MemorySaltStore {
+            seed,
+            keys: HashMap::default(),
+        }
+    }
+
+    fn entries(&self) -> Vec<SaltEntry> {
+        self.keys
+            .iter()
+            .map(|(key, key_salts)| SaltEntry {
+                key: JsonBytes::from_bytes(key.clone()),
+                nonce: key_salts.nonce,
+                salts: key_salts
+                    .salts
+                    .iter()
+                    .map(|salt| JsonBytes::from_bytes(salt.clone()))
+                    .collect(),
+            })
+            .collect()
+    }
+
+    fn load_entries(&mut self, entries: Vec<SaltEntry>) {
+        for entry in entries {
+            let key_salts = self.keys.entry(entry.key.into_bytes()).or_default();
+            key_salts.nonce = key_salts.nonce.max(entry.nonce);
+            key_salts
+                .salts
+                .extend(entry.salts.into_iter().map(JsonBytes::into_bytes));
+        }
+    }
+}
+
+impl SaltStore for MemorySaltStore {
+    fn next_salt(&mut self, key: &[u8]) -> Result<H256, SaltStoreError> {
+        let key_salts = self.keys.entry(Bytes::from(key.to_vec())).or_default();
+        loop {
+            let salt = derive_salt(&self.seed, key, key_salts.nonce);
+            key_salts.nonce += 1;
+            if key_salts
+                .salts
+                .insert(Bytes::from(salt.as_bytes().to_vec()))
+            {
+                return Ok(salt);
+            }
+        }
+    }
+
+    fn register(&mut self, key: &[u8], salt: &[u8]) -> Result<(), SaltStoreError> {
+        let key_salts = self.keys.entry(Bytes::from(key.to_vec())).or_default();
+        if !key_salts.salts.insert(Bytes::from(salt.to_vec())) {
+            return Err(SaltStoreError::Collision(hex_string(key), hex_string(salt)));
+        }
+        Ok(())
+    }
+
+    fn is_used(&self, key: &[u8], salt: &[u8]) -> bool {
+        self.keys
+            .get(key)
+            .map(|key_salts| key_salts.salts.contains(salt))
+            .unwrap_or(false)
+    }
+}
+
+fn hex_string(data: &[u8]) -> String {
+    format!("0x{}", molecule::hex_string(data))
+}
+
+/// A salt store persisted to a JSON file on every change.
+#[derive(Debug, Clone)]
+pub struct FileSaltStore {
+    path: PathBuf,
+    inner: MemorySaltStore,
+}
+
+impl FileSaltStore {
+    pub fn open<P: AsRef<Path>>(path: P, seed: H256) -> Result<FileSaltStore, SaltStoreError> {
+        let path = path.as_ref().to_path_buf();
+        let mut inner = MemorySaltStore::new(seed);
+        if path.exists() {
+            let content = fs::read(&path).map_err(|err| SaltStoreError::State(anyhow!(err)))?;
+            let entries: Vec<SaltEntry> = serde_json::from_slice(&content)
+                .map_err(|err| SaltStoreError::State(anyhow!(err)))?;
+            inner.load_entries(entries);
+        }
+        Ok(FileSaltStore { path, inner })
+    }
+
+    fn save(&self) -> Result<(), SaltStoreError> {
+        let content = serde_json::to_vec(&self.inner.entries())
+            .map_err(|err| SaltStoreError::State(anyhow!(err)))?;
+        fs::write(&self.path, content).map_err(|err| SaltStoreError::State(anyhow!(err)))
+    }
+}
+
+impl SaltStore for FileSaltStore {
+    fn next_salt(&mut self, key: &[u8]) -> Result<H256, SaltStoreError> {
+        let salt = self.inner.next_salt(key)?;
+        self.save()?;
+        Ok(salt)
+    }
+
+    fn register(&mut self, key: &[u8], salt: &[u8]) -> Result<(), SaltStoreError> {
+        self.inner.register(key, salt)?;
+        self.save()
+    }
+
+    fn is_used(&self, key: &[u8], salt: &[u8]) -> bool {
+        self.inner.is_used(key, salt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::h256;
+
+    #[test]
+    fn test_salt_store() {
+        let seed = h256!("0x1");
+        let key = [1u8; 32];
+        let path =
+            std::env::temp_dir().join(format!("ckb-sdk-salt-store-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut store = FileSaltStore::open(&path, seed.clone()).unwrap();
+        let salt0 = store.next_salt(&key).unwrap();
+        assert_eq!(salt0, derive_salt(&seed, &key, 0));
+        // the salt of nonce 1 is taken, skip it
+        store
+            .register(&key, derive_salt(&seed, &key, 1).as_bytes())
+            .unwrap();
+        let salt2 = store.next_salt(&key).unwrap();
+        assert_eq!(salt2, derive_salt(&seed, &key, 2));
+        assert!(matches!(
+            store.register(&key, salt0.as_bytes()),
+            Err(SaltStoreError::Collision(_, _))
+        ));
+        assert!(!store.is_used(&[2u8; 32], salt0.as_bytes()));
+
+        let mut store = FileSaltStore::open(&path, seed.clone()).unwrap();
+        assert!(store.is_used(&key, salt2.as_bytes()));
+        assert_eq!(store.next_salt(&key).unwrap(), derive_salt(&seed, &key, 3));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_salted_htlc() {
+        use crate::tx_builder::htlc::HtlcCreateBuilder;
+        use crate::types::ScriptId;
+        use ckb_types::h160;
+
+        let script_id = ScriptId::new_type(h256!("0x2"));
+        let receiver = h160!("0x1");
+        let refund = h160!("0x2");
+        let mut store = MemorySaltStore::new(h256!("0x3"));
+        let (builder0, preimage0) = HtlcCreateBuilder::new_salted(
+            script_id.clone(),
+            receiver.clone(),
+            refund.clone(),
+            0,
+            b"secret",
+            100,
+            &mut store,
+        )
+        .unwrap();
+        let (builder1, preimage1) = HtlcCreateBuilder::new_salted(
+            script_id, receiver, refund, 0, b"secret", 100, &mut store,
+        )
+        .unwrap();
+        assert_ne!(preimage0, preimage1);
+        assert_ne!(builder0.args.hash_lock, builder1.args.hash_lock);
+    }
+}
@@ -0,0 +1,240 @@
+//! Shuffle the inputs and outputs of a balanced transaction.
+//!
+//! The balancer always appends the capacity provider inputs and the change
+//! output last, which tells the observers which output is the change. The
+//! shuffle pass hides this by permuting the inputs and the outputs, the
+//! permutation is derived from a seed so it can be reproduced in tests.
+//!
+//! The witnesses are moved along with their inputs, then the leading witness
+//! (which carries the lock placeholder) of every lock script group is moved to
+//! the new first input of the group. So the shuffle must be done before
+//! unlocking.
+//!
+//! Some scripts depend on the positions of the cells: the type id args are
+//! computed from the first input and the output index, and the DAO withdraw
+//! requires the input and the output to have the same index. The inputs and
+//! the outputs with a type id or DAO type script are kept in place, so is the
+//! first input when a type id output is present, only the other cells are
+//! shuffled. Other position dependent scripts must not be used with the
+//! shuffle.
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+
+use ckb_hash::new_blake2b;
+use ckb_types::{
+    core::{ScriptHashType, TransactionView},
+    packed,
+    prelude::*,
+};
+
+use super::BalanceTxCapacityError;
+use crate::constants::{DAO_TYPE_HASH, TYPE_ID_CODE_HASH};
+use crate::traits::TransactionDependencyProvider;
+
+/// How to choose the shuffle seed
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum ShuffleMode {
+    /// Use a random seed
+    Random,
+    /// Use the given seed, the same transaction is always shuffled the same way
+    Seeded(u64),
+}
+
+impl ShuffleMode {
+    pub fn seed(&self) -> u64 {
+        match self {
+            ShuffleMode::Random => RandomState::new().build_hasher().finish(),
+            ShuffleMode::Seeded(seed) => *seed,
+        }
+    }
+}
+
+/// A permutation of `0..len` derived from the seed (Fisher-Yates shuffle),
+/// `salt` separates the permutations derived from the same seed.
+pub fn shuffle_indices(len: usize, seed: u64, salt: &[u8]) -> Vec<usize> {
+    let mut indices: Vec<usize> = (0..len).collect();
+    for i in (1..len).rev() {
+        let mut hasher = new_blake2b();
+        hasher.update(&seed.to_le_bytes());
+        hasher.update(salt);
+        hasher.update(&(i as u64).to_le_bytes());
+        let mut hash = [0u8; 32];
+        hasher.finalize(&mut hash);
+        let mut value = [0u8; 8];
+        value.copy_from_slice(&hash[0..8]);
+        let j = (u64::from_le_bytes(value) % (i as u64 + 1)) as usize;
+        indices.swap(i, j);
+    }
+    indices
+}
+
+/// Same as [`shuffle_indices`], but the `pinned` positions are kept in place
+/// and only the others are shuffled among themselves.
+pub fn shuffle_indices_with_pinned(pinned: &[bool], seed: u64, salt: &[u8]) -> Vec<usize> {
+    let free: Vec<usize> = (0..pinned.len()).filter(|idx| !pinned[*idx]).collect();
+    let mut indices: Vec<usize> = (0..pinned.len()).collect();
+    for (free_idx, old_idx) in shuffle_indices(free.len(), seed, salt)
+        .into_iter()
+        .enumerate()
+    {
+        indices[free[free_idx]] = free[old_idx];
+    }
+    indices
+}
+
+fn is_type_script(type_script: &Option<packed::Script>, code_hash: &[u8]) -> bool {
+    type_script.as_ref().map_or(false, |script| {
+        script.code_hash().as_slice() == code_hash
+            && script.hash_type() == ScriptHashType::Type.into()
+    })
+}
+
+/// Check if the cell must be kept in place, see the module document.
+fn is_position_dependent(type_script: &Option<packed::Script>) -> bool {
+    is_type_script(type_script, TYPE_ID_CODE_HASH.as_bytes())
+        || is_type_script(type_script, DAO_TYPE_HASH.as_bytes())
+}
+
+/// Shuffle the inputs and the outputs of the transaction, see the module
+/// document for the limitations.
+pub fn shuffle_tx(
+    tx: &TransactionView,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+    seed: u64,
+) -> Result<TransactionView, BalanceTxCapacityError> {
+    shuffle_tx_with_output_order(tx, tx_dep_provider, seed).map(|(tx, _)| tx)
+}
+
+/// Shuffle the transaction, also return the old indices of the new outputs
+pub(crate) fn shuffle_tx_with_output_order(
+    tx: &TransactionView,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+    seed: u64,
+) -> Result<(TransactionView, Vec<usize>), BalanceTxCapacityError> {
+    let outputs: Vec<_> = tx.outputs_with_data_iter().collect();
+    let pinned_outputs: Vec<bool> = outputs
+        .iter()
+        .map(|(output, _)| is_position_dependent(&output.type_().to_opt()))
+        .collect();
+    let has_type_id_output = outputs
+        .iter()
+        .any(|(output, _)| is_type_script(&output.type_().to_opt(), TYPE_ID_CODE_HASH.as_bytes()));
+    let mut pinned_inputs = Vec::with_capacity(tx.inputs().len());
+    for (idx, input) in tx.inputs().into_iter().enumerate() {
+        let cell = tx_dep_provider.get_cell(&input.previous_output())?;
+        pinned_inputs.push(
+            (idx == 0 && has_type_id_output) || is_position_dependent(&cell.type_().to_opt()),
+        );
+    }
+
+    let input_order = shuffle_indices_with_pinned(&pinned_inputs, seed, b"inputs");
+    let tx = reorder_inputs(tx, tx_dep_provider, &input_order)?;
+
+    let output_order = shuffle_indices_with_pinned(&pinned_outputs, seed, b"outputs");
+    let new_tx = tx
+        .as_advanced_builder()
+        .set_outputs(
+            output_order
+                .iter()
+                .map(|idx| outputs[*idx].0.clone())
+                .collect(),
+        )
+        .set_outputs_data(
+            output_order
+                .iter()
+                .map(|idx| outputs[*idx].1.pack())
+                .collect(),
+        )
+        .build();
+    Ok((new_tx, output_order))
+}
+
+/// Reorder the inputs by `input_order` (new index => old index), the
+/// witnesses are moved along with their inputs, then the leading witness of
+/// every lock script group is moved to the new first input of the group.
+pub(crate) fn reorder_inputs(
+    tx: &TransactionView,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+    input_order: &[usize],
+) -> Result<TransactionView, BalanceTxCapacityError> {
+    let inputs: Vec<_> = tx.inputs().into_iter().collect();
+    let mut lock_scripts = Vec::with_capacity(inputs.len());
+    for input in &inputs {
+        lock_scripts.push(tx_dep_provider.get_cell(&input.previous_output())?.lock());
+    }
+    let witness_count = tx.witnesses().len();
+    let mut witnesses: Vec<packed::Bytes> = tx.witnesses().into_iter().collect();
+    witnesses.resize(witness_count.max(inputs.len()), Default::default());
+
+    let mut new_positions = vec![0; inputs.len()];
+    for (new_idx, old_idx) in input_order.iter().enumerate() {
+        new_positions[*old_idx] = new_idx;
+    }
+    let mut new_witnesses = witnesses.clone();
+    for (new_idx, old_idx) in input_order.iter().enumerate() {
+        new_witnesses[new_idx] = witnesses[*old_idx].clone();
+    }
+    // lock script => (old first index, new first index)
+    #[allow(clippy::mutable_key_type)]
+    let mut group_firsts: HashMap<packed::Script, (usize, usize)> = HashMap::new();
+    for (old_idx, lock_script) in lock_scripts.iter().enumerate() {
+        let new_idx = new_positions[old_idx];
+        let firsts = group_firsts
+            .entry(lock_script.clone())
+            .or_insert((old_idx, new_idx));
+        firsts.1 = firsts.1.min(new_idx);
+    }
+    for (old_first, new_first) in group_firsts.values() {
+        new_witnesses.swap(new_positions[*old_first], *new_first);
+    }
+    // Do not grow the transaction with the padded empty witnesses
+    while new_witnesses.len() > witness_count
+        && new_witnesses
+            .last()
+            .map_or(false, |witness| witness.raw_data().is_empty())
+    {
+        new_witnesses.pop();
+    }
+    Ok(tx
+        .as_advanced_builder()
+        .set_inputs(input_order.iter().map(|idx| inputs[*idx].clone()).collect())
+        .set_witnesses(new_witnesses)
+        .build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shuffle_indices() {
+        let indices = shuffle_indices(10, 1, b"inputs");
+        assert_eq!(indices, shuffle_indices(10, 1, b"inputs"));
+        let mut sorted = indices.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, (0..10).collect::<Vec<_>>());
+        assert!((0..16).any(|seed| shuffle_indices(10, seed, b"inputs") != indices));
+        assert!(shuffle_indices(0, 1, b"inputs").is_empty());
+    }
+
+    #[test]
+    fn test_shuffle_indices_with_pinned() {
+        let pinned = [true, false, false, true, false, false];
+        for seed in 0..16 {
+            let indices = shuffle_indices_with_pinned(&pinned, seed, b"outputs");
+            assert_eq!(indices[0], 0);
+            assert_eq!(indices[3], 3);
+            let mut sorted = indices.clone();
+            sorted.sort_unstable();
+            assert_eq!(sorted, (0..6).collect::<Vec<_>>());
+        }
+        assert!((0..16).any(|seed| {
+            shuffle_indices_with_pinned(&pinned, seed, b"outputs") != vec![0, 1, 2, 3, 4, 5]
+        }));
+        assert_eq!(
+            shuffle_indices_with_pinned(&[true; 3], 1, b"outputs"),
+            vec![0, 1, 2]
+        );
+    }
+}
@@ -0,0 +1,672 @@
+//! Builders for the [Spore](https://github.com/sporeprotocol/spore-contract)
+//! protocol (DOB).
+//!
+//! The spore contracts require a co-build message listing the spore actions
+//! of the transaction, it is attached as a `SighashAll` witness layout after
+//! the input witnesses, use [`build_spore_unlocked`] to build, balance, attach
+//! the message and unlock in one step.
+use std::collections::{HashMap, HashSet};
+
+use anyhow::anyhow;
+use ckb_hash::blake2b_256;
+use ckb_types::{
+    bytes::Bytes,
+    core::{Capacity, DepType, TransactionBuilder, TransactionView},
+    packed::{self, CellDep, CellInput, CellOutput, Script},
+    prelude::*,
+    H256,
+};
+
+use super::{
+    balance_tx_capacity, fill_placeholder_witnesses, tx_fee, unlock_tx, BalanceTxCapacityError,
+    CapacityBalancer, TxBuilder, TxBuilderError,
+};
+use crate::traits::{
+    CellCollector, CellDepResolver, CellQueryOptions, HeaderDepResolver, LiveCell,
+    TransactionDependencyProvider, ValueRangeOption,
+};
+use crate::types::{ScriptGroup, ScriptId};
+use crate::unlock::ScriptUnlocker;
+use crate::util::calculate_type_id;
+
+/// The union id of `WitnessLayout::SighashAll` in the co-build protocol
+pub const WITNESS_LAYOUT_SIGHASH_ALL: u32 = 0xFF00_0001;
+
+/// The spore and cluster script ids, they are different in mainnet and
+/// testnet
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct SporeScripts {
+    pub spore: ScriptId,
+    pub cluster: ScriptId,
+}
+
+impl SporeScripts {
+    pub fn spore_script(&self, spore_id: &H256) -> Script {
+        build_script(&self.spore, spore_id)
+    }
+
+    pub fn cluster_script(&self, cluster_id: &H256) -> Script {
+        build_script(&self.cluster, cluster_id)
+    }
+}
+
+fn build_script(script_id: &ScriptId, args: &H256) -> Script {
+    Script::new_builder()
+        .code_hash(script_id.code_hash.pack())
+        .hash_type(script_id.hash_type.into())
+        .args(Bytes::from(args.as_bytes().to_vec()).pack())
+        .build()
+}
+
+/// Serialize a molecule table (or dynvec, they share the same layout)
+fn mol_table(fields: &[&[u8]]) -> Vec<u8> {
+    let header_size = 4 * (1 + fields.len());
+    let total_size = header_size + fields.iter().map(|field| field.len()).sum::<usize>();
+    let mut buf = Vec::with_capacity(total_size);
+    buf.extend_from_slice(&(total_size as u32).to_le_bytes());
+    let mut offset = header_size;
+    for field in fields {
+        buf.extend_from_slice(&(offset as u32).to_le_bytes());
+        offset += field.len();
+    }
+    for field in fields {
+        buf.extend_from_slice(field);
+    }
+    buf
+}
+
+fn mol_union(item_id: u32, item: &[u8]) -> Vec<u8> {
+    let mut buf = item_id.to_le_bytes().to_vec();
+    buf.extend_from_slice(item);
+    buf
+}
+
+fn mol_bytes_opt(data: Option<&Bytes>) -> Vec<u8> {
+    data.map(|data| data.pack().as_slice().to_vec())
+        .unwrap_or_default()
+}
+
+/// The spore cell data (`SporeData` in molecule)
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct SporeData {
+    /// The MIME type of the content
+    pub content_type: String,
+    pub content: Bytes,
+    pub cluster_id: Option<H256>,
+}
+
+impl SporeData {
+    pub fn to_bytes(&self) -> Bytes {
+        let cluster_id = self
+            .cluster_id
+            .as_ref()
+            .map(|id| Bytes::from(id.as_bytes().to_vec()));
+        Bytes::from(mol_table(&[
+            Bytes::from(self.content_type.clone()).pack().as_slice(),
+            self.content.pack().as_slice(),
+            &mol_bytes_opt(cluster_id.as_ref()),
+        ]))
+    }
+}
+
+/// The cluster cell data (`ClusterDataV2` in molecule)
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct ClusterData {
+    pub name: String,
+    pub description: String,
+    pub mutant_id: Option<Bytes>,
+}
+
+impl ClusterData {
+    pub fn to_bytes(&self) -> Bytes {
+        Bytes::from(mol_table(&[
+            Bytes::from(self.name.clone()).pack().as_slice(),
+            Bytes::from(self.description.clone()).pack().as_slice(),
+            &mol_bytes_opt(self.mutant_id.as_ref()),
+        ]))
+    }
+}
+
+/// The spore actions of the co-build message
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum SporeAction {
+    MintSpore {
+        spore_id: H256,
+        to: Script,
+        data_hash: H256,
+    },
+    TransferSpore {
+        spore_id: H256,
+        from: Script,
+        to: Script,
+    },
+    BurnSpore {
+        spore_id: H256,
+        from: Script,
+    },
+    MintCluster {
+        cluster_id: H256,
+        to: Script,
+        data_hash: H256,
+    },
+}
+
+impl SporeAction {
+    /// The type script hash of the spore or cluster the action applies to
+    fn script_hash(&self, scripts: &SporeScripts) -> packed::Byte32 {
+        match self {
+            SporeAction::MintSpore { spore_id, .. }
+            | SporeAction::TransferSpore { spore_id, .. }
+            | SporeAction::BurnSpore { spore_id, .. } => {
+                scripts.spore_script(spore_id).calc_script_hash()
+            }
+            SporeAction::MintCluster { cluster_id, .. } => {
+                scripts.cluster_script(cluster_id).calc_script_hash()
+            }
+        }
+    }
+
+    /// The molecule serialized `SporeAction`
+    pub fn to_bytes(&self) -> Bytes {
+        // union Address { Script }
+        let address = |script: &Script| mol_union(0, script.as_slice());
+        let (item_id, item) = match self {
+            SporeAction::MintSpore {
+                spore_id,
+                to,
+                data_hash,
+            } => (
+                0,
+                mol_table(&[spore_id.as_bytes(), &address(to), data_hash.as_bytes()]),
+            ),
+            SporeAction::TransferSpore { spore_id, from, to } => (
+                1,
+                mol_table(&[spore_id.as_bytes(), &address(from), &address(to)]),
+            ),
+            SporeAction::BurnSpore { spore_id, from } => {
+                (2, mol_table(&[spore_id.as_bytes(), &address(from)]))
+            }
+            SporeAction::MintCluster {
+                cluster_id,
+                to,
+                data_hash,
+            } => (
+                3,
+                mol_table(&[cluster_id.as_bytes(), &address(to), data_hash.as_bytes()]),
+            ),
+        };
+        Bytes::from(mol_union(item_id, &item))
+    }
+}
+
+/// Build the `WitnessLayout::SighashAll` witness with an empty seal, the
+/// `script_info_hash` of the actions are left zero.
+pub fn build_cobuild_witness(scripts: &SporeScripts, actions: &[SporeAction]) -> Bytes {
+    let actions: Vec<Vec<u8>> = actions
+        .iter()
+        .map(|action| {
+            mol_table(&[
+                &[0u8; 32],
+                action.script_hash(scripts).as_slice(),
+                action.to_bytes().pack().as_slice(),
+            ])
+        })
+        .collect();
+    let action_vec = mol_table(&actions.iter().map(Vec::as_slice).collect::<Vec<_>>());
+    let message = mol_table(&[&action_vec]);
+    let sighash_all = mol_table(&[&message, Bytes::new().pack().as_slice()]);
+    Bytes::from(mol_union(WITNESS_LAYOUT_SIGHASH_ALL, &sighash_all))
+}
+
+/// Append the co-build witness after the input witnesses
+pub fn attach_cobuild_witness(tx: &TransactionView, witness: &Bytes) -> TransactionView {
+    let mut witnesses: Vec<packed::Bytes> = tx.witnesses().into_iter().collect();
+    while witnesses.len() < tx.inputs().len() {
+        witnesses.push(Default::default());
+    }
+    witnesses.push(witness.pack());
+    tx.as_advanced_builder().set_witnesses(witnesses).build()
+}
+
+/// A spore transaction builder which knows the actions of the transaction
+pub trait SporeTxBuilder: TxBuilder {
+    fn scripts(&self) -> &SporeScripts;
+
+    /// The actions of the base transaction built by [`TxBuilder::build_base`]
+    fn actions(
+        &self,
+        base_tx: &TransactionView,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<Vec<SporeAction>, TxBuilderError>;
+}
+
+/// Build the base transaction, balance it, attach the co-build message and
+/// unlock the transaction. The fee of the co-build witness is covered by the
+/// balancer.
+///
+/// Return value:
+///   * The built transaction
+///   * The script groups that not unlocked by given `unlockers`
+#[allow(clippy::too_many_arguments)]
+pub fn build_spore_unlocked(
+    builder: &dyn SporeTxBuilder,
+    cell_collector: &mut dyn CellCollector,
+    cell_dep_resolver: &dyn CellDepResolver,
+    header_dep_resolver: &dyn HeaderDepResolver,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+    balancer: &CapacityBalancer,
+    unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+) -> Result<(TransactionView, Vec<ScriptGroup>), TxBuilderError> {
+    let base_tx = builder.build_base(
+        cell_collector,
+        cell_dep_resolver,
+        header_dep_resolver,
+        tx_dep_provider,
+    )?;
+    let actions = builder.actions(&base_tx, tx_dep_provider)?;
+    let witness = build_cobuild_witness(builder.scripts(), &actions);
+    let base_outputs_len = base_tx.outputs().len();
+    let (tx_filled_witnesses, _) = fill_placeholder_witnesses(base_tx, tx_dep_provider, unlockers)?;
+    let mut balanced_tx = balance_tx_capacity(
+        &tx_filled_witnesses,
+        balancer,
+        cell_collector,
+        tx_dep_provider,
+        cell_dep_resolver,
+        header_dep_resolver,
+    )?;
+    let tx = attach_cobuild_witness(&balanced_tx, &witness);
+    let tx_size = tx.data().as_reader().serialized_size_in_block();
+    let required_fee = balancer.fee_rate.fee(tx_size as u64).as_u64();
+    let fee =
+        tx_fee(tx, tx_dep_provider, header_dep_resolver).map_err(BalanceTxCapacityError::from)?;
+    if fee < required_fee {
+        // the balancer appends the change output after the base outputs
+        let change_idx = if balanced_tx.outputs().len() > base_outputs_len {
+            Some(base_outputs_len)
+        } else {
+            None
+        };
+        // rebalance without the co-build witness, so new input witnesses are
+        // not mixed up with it
+        let (new_tx, _) = balancer.rebalance_tx_capacity(
+            &balanced_tx,
+            cell_collector,
+            tx_dep_provider,
+            cell_dep_resolver,
+            header_dep_resolver,
+            required_fee,
+            change_idx,
+        )?;
+        balanced_tx = new_tx;
+    }
+    let tx = attach_cobuild_witness(&balanced_tx, &witness);
+    Ok(unlock_tx(tx, tx_dep_provider, unlockers)?)
+}
+
+fn resolve_cell_dep(
+    cell_dep_resolver: &dyn CellDepResolver,
+    script: &Script,
+) -> Result<CellDep, TxBuilderError> {
+    cell_dep_resolver
+        .resolve(script)
+        .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(script.clone()))
+}
+
+fn collect_first_input(
+    cell_collector: &mut dyn CellCollector,
+    lock_script: &Script,
+) -> Result<CellInput, TxBuilderError> {
+    let mut query = CellQueryOptions::new_lock(lock_script.clone());
+    query.secondary_script_len_range = Some(ValueRangeOption::new_exact(0));
+    query.data_len_range = Some(ValueRangeOption::new_exact(0));
+    let (cells, _) = cell_collector.collect_live_cells(&query, true)?;
+    let cell = cells.first().ok_or_else(|| {
+        TxBuilderError::Other(anyhow!(
+            "first input cell not found, lock={:?}",
+            lock_script
+        ))
+    })?;
+    Ok(CellInput::new(cell.out_point.clone(), 0))
+}
+
+/// Find the only live cell of the type script
+fn find_type_cell(
+    cell_collector: &mut dyn CellCollector,
+    type_script: &Script,
+) -> Result<LiveCell, TxBuilderError> {
+    let query = CellQueryOptions::new_type(type_script.clone());
+    let (mut cells, _) = cell_collector.collect_live_cells(&query, true)?;
+    if cells.len() != 1 {
+        return Err(TxBuilderError::Other(anyhow!(
+            "expected exactly one cell, found: {}, type_script={:?}",
+            cells.len(),
+            type_script
+        )));
+    }
+    Ok(cells.remove(0))
+}
+
+fn exact_output(lock_script: Script, type_script: Script, data: &Bytes) -> CellOutput {
+    CellOutput::new_builder()
+        .lock(lock_script)
+        .type_(Some(type_script).pack())
+        .build_exact_capacity(Capacity::bytes(data.len()).unwrap())
+        .unwrap()
+}
+
+/// The type id args of the output at `output_index` of the transaction
+fn output_type_id(tx: &TransactionView, output_index: u64) -> Result<H256, TxBuilderError> {
+    let first_input = tx
+        .inputs()
+        .get(0)
+        .ok_or_else(|| TxBuilderError::Other(anyhow!("transaction has no input")))?;
+    Ok(H256(calculate_type_id(&first_input, output_index)))
+}
+
+/// The lock script of the first input, the spore being transferred or melted
+fn first_input_lock(
+    tx: &TransactionView,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+) -> Result<Script, TxBuilderError> {
+    let first_input = tx
+        .inputs()
+        .get(0)
+        .ok_or_else(|| TxBuilderError::Other(anyhow!("transaction has no input")))?;
+    Ok(tx_dep_provider
+        .get_cell(&first_input.previous_output())?
+        .lock())
+}
+
+/// Create a cluster cell, the cluster id is calculated from the first input
+/// which is collected from `lock_script`. The cluster cell is the first
+/// output.
+pub struct ClusterCreateBuilder {
+    pub scripts: SporeScripts,
+    /// The owner of the cluster
+    pub lock_script: Script,
+    pub cluster_data: ClusterData,
+}
+
+impl TxBuilder for ClusterCreateBuilder {
+    fn build_base(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        let first_input = collect_first_input(cell_collector, &self.lock_script)?;
+        let cluster_id = H256(calculate_type_id(&first_input, 0));
+        let cluster_script = self.scripts.cluster_script(&cluster_id);
+
+        #[allow(clippy::mutable_key_type)]
+        let mut cell_deps = HashSet::new();
+        cell_deps.insert(resolve_cell_dep(cell_dep_resolver, &self.lock_script)?);
+        cell_deps.insert(resolve_cell_dep(cell_dep_resolver, &cluster_script)?);
+
+        let data = self.cluster_data.to_bytes();
+        let output = exact_output(self.lock_script.clone(), cluster_script, &data);
+        Ok(TransactionBuilder::default()
+            .set_cell_deps(cell_deps.into_iter().collect())
+            .set_inputs(vec![first_input])
+            .set_outputs(vec![output])
+            .set_outputs_data(vec![data.pack()])
+            .build())
+    }
+}
+
+impl SporeTxBuilder for ClusterCreateBuilder {
+    fn scripts(&self) -> &SporeScripts {
+        &self.scripts
+    }
+
+    fn actions(
+        &self,
+        base_tx: &TransactionView,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<Vec<SporeAction>, TxBuilderError> {
+        Ok(vec![SporeAction::MintCluster {
+            cluster_id: output_type_id(base_tx, 0)?,
+            to: self.lock_script.clone(),
+            data_hash: H256(blake2b_256(self.cluster_data.to_bytes())),
+        }])
+    }
+}
+
+/// Mint a spore cell, the spore cell is the first output.
+///
+/// If the spore belongs to a cluster, the cluster cell is consumed and
+/// re-created unchanged (the cluster owner must unlock it) and becomes the
+/// first input, otherwise the first input is collected from `payer_lock`.
+pub struct SporeMintBuilder {
+    pub scripts: SporeScripts,
+    /// The owner of the new spore
+    pub to: Script,
+    /// Provide the first input when the spore has no cluster
+    pub payer_lock: Script,
+    pub spore_data: SporeData,
+}
+
+impl TxBuilder for SporeMintBuilder {
+    fn build_base(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        if self.spore_data.content.is_empty() {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "empty spore content"
+            )));
+        }
+        #[allow(clippy::mutable_key_type)]
+        let mut cell_deps = HashSet::new();
+        let mut outputs = Vec::new();
+        let mut outputs_data = Vec::new();
+        let first_input = match self.spore_data.cluster_id.as_ref() {
+            Some(cluster_id) => {
+                let cluster_script = self.scripts.cluster_script(cluster_id);
+                let cluster_cell = find_type_cell(cell_collector, &cluster_script)?;
+                cell_deps.insert(resolve_cell_dep(
+                    cell_dep_resolver,
+                    &cluster_cell.output.lock(),
+                )?);
+                cell_deps.insert(resolve_cell_dep(cell_dep_resolver, &cluster_script)?);
+                // the spore contract reads the cluster from the cell deps
+                cell_deps.insert(
+                    CellDep::new_builder()
+                        .out_point(cluster_cell.out_point.clone())
+                        .dep_type(DepType::Code.into())
+                        .build(),
+                );
+                outputs.push(cluster_cell.output.clone());
+                outputs_data.push(cluster_cell.output_data.pack());
+                CellInput::new(cluster_cell.out_point, 0)
+            }
+            None => {
+                cell_deps.insert(resolve_cell_dep(cell_dep_resolver, &self.payer_lock)?);
+                collect_first_input(cell_collector, &self.payer_lock)?
+            }
+        };
+        let spore_id = H256(calculate_type_id(&first_input, 0));
+        let spore_script = self.scripts.spore_script(&spore_id);
+        cell_deps.insert(resolve_cell_dep(cell_dep_resolver, &spore_script)?);
+
+        let data = self.spore_data.to_bytes();
+        outputs.insert(0, exact_output(self.to.clone(), spore_script, &data));
+        outputs_data.insert(0, data.pack());
+        Ok(TransactionBuilder::default()
+            .set_cell_deps(cell_deps.into_iter().collect())
+            .set_inputs(vec![first_input])
+            .set_outputs(outputs)
+            .set_outputs_data(outputs_data)
+            .build())
+    }
+}
+
+impl SporeTxBuilder for SporeMintBuilder {
+    fn scripts(&self) -> &SporeScripts {
+        &self.scripts
+    }
+
+    fn actions(
+        &self,
+        base_tx: &TransactionView,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<Vec<SporeAction>, TxBuilderError> {
+        Ok(vec![SporeAction::MintSpore {
+            spore_id: output_type_id(base_tx, 0)?,
+            to: self.to.clone(),
+            data_hash: H256(blake2b_256(self.spore_data.to_bytes())),
+        }])
+    }
+}
+
+/// Transfer a spore cell, the data and capacity are kept.
+pub struct SporeTransferBuilder {
+    pub scripts: SporeScripts,
+    pub spore_id: H256,
+    pub to: Script,
+}
+
+impl TxBuilder for SporeTransferBuilder {
+    fn build_base(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        let spore_script = self.scripts.spore_script(&self.spore_id);
+        let spore_cell = find_type_cell(cell_collector, &spore_script)?;
+
+        #[allow(clippy::mutable_key_type)]
+        let mut cell_deps = HashSet::new();
+        cell_deps.insert(resolve_cell_dep(
+            cell_dep_resolver,
+            &spore_cell.output.lock(),
+        )?);
+        cell_deps.insert(resolve_cell_dep(cell_dep_resolver, &spore_script)?);
+
+        let output = spore_cell.output.as_builder().lock(self.to.clone()).build();
+        Ok(TransactionBuilder::default()
+            .set_cell_deps(cell_deps.into_iter().collect())
+            .set_inputs(vec![CellInput::new(spore_cell.out_point, 0)])
+            .set_outputs(vec![output])
+            .set_outputs_data(vec![spore_cell.output_data.pack()])
+            .build())
+    }
+}
+
+impl SporeTxBuilder for SporeTransferBuilder {
+    fn scripts(&self) -> &SporeScripts {
+        &self.scripts
+    }
+
+    fn actions(
+        &self,
+        base_tx: &TransactionView,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<Vec<SporeAction>, TxBuilderError> {
+        let from = first_input_lock(base_tx, tx_dep_provider)?;
+        Ok(vec![SporeAction::TransferSpore {
+            spore_id: self.spore_id.clone(),
+            from,
+            to: self.to.clone(),
+        }])
+    }
+}
+
+/// Melt (destroy) a spore cell, the capacity is returned to the change cell
+/// of the balancer.
+pub struct SporeMeltBuilder {
+    pub scripts: SporeScripts,
+    pub spore_id: H256,
+}
+
+impl TxBuilder for SporeMeltBuilder {
+    fn build_base(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        let spore_script = self.scripts.spore_script(&self.spore_id);
+        let spore_cell = find_type_cell(cell_collector, &spore_script)?;
+
+        #[allow(clippy::mutable_key_type)]
+        let mut cell_deps = HashSet::new();
+        cell_deps.insert(resolve_cell_dep(
+            cell_dep_resolver,
+            &spore_cell.output.lock(),
+        )?);
+        cell_deps.insert(resolve_cell_dep(cell_dep_resolver, &spore_script)?);
+        Ok(TransactionBuilder::default()
+            .set_cell_deps(cell_deps.into_iter().collect())
+            .set_inputs(vec![CellInput::new(spore_cell.out_point, 0)])
+            .build())
+    }
+}
+
+impl SporeTxBuilder for SporeMeltBuilder {
+    fn scripts(&self) -> &SporeScripts {
+        &self.scripts
+    }
+
+    fn actions(
+        &self,
+        base_tx: &TransactionView,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<Vec<SporeAction>, TxBuilderError> {
+        Ok(vec![SporeAction::BurnSpore {
+            spore_id: self.spore_id.clone(),
+            from: first_input_lock(base_tx, tx_dep_provider)?,
+        }])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spore_molecule_encoding() {
+        let data = SporeData {
+            content_type: "text/plain".to_string(),
+            content: Bytes::from("hello"),
+            cluster_id: None,
+        };
+        let bytes = data.to_bytes();
+        // header (4 * 4) + content_type (4 + 10) + content (4 + 5) + cluster_id (0)
+        assert_eq!(bytes.len(), 16 + 14 + 9);
+        assert_eq!(&bytes[0..4], &(39u32).to_le_bytes());
+        assert_eq!(&bytes[12..16], &(39u32).to_le_bytes());
+
+        let with_cluster = SporeData {
+            cluster_id: Some(H256([1u8; 32])),
+            ..data
+        };
+        assert_eq!(with_cluster.to_bytes().len(), 39 + 4 + 32);
+
+        let action = SporeAction::BurnSpore {
+            spore_id: H256([2u8; 32]),
+            from: Script::default(),
+        };
+        let action_bytes = action.to_bytes();
+        assert_eq!(&action_bytes[0..4], &2u32.to_le_bytes());
+        assert_eq!(
+            action_bytes.len(),
+            4 + 12 + 32 + 4 + Script::default().as_slice().len()
+        );
+
+        let scripts = SporeScripts {
+            spore: ScriptId::default(),
+            cluster: ScriptId::default(),
+        };
+        let witness = build_cobuild_witness(&scripts, &[action]);
+        assert_eq!(&witness[0..4], &WITNESS_LAYOUT_SIGHASH_ALL.to_le_bytes());
+    }
+}
@@ -0,0 +1,436 @@
+//! State cells, the most common dApp data pattern.
+//!
+//! A state cell is guarded by a type id script, so it has a stable identity
+//! across updates, and its data holds a versioned state:
+//!
+//! ```text
+//! schema_version (u32, little endian) | revision (u64, little endian) | state
+//! ```
+//!
+//! `revision` is increased by every update, `schema_version` is changed by a
+//! migration which re-encodes the state with a new schema. The update
+//! builders record the out point of the cell they were derived from, and
+//! refuse to build when the live cell changed since it was read (optimistic
+//! concurrency), the caller should load the cell again and retry.
+use std::collections::HashSet;
+use std::convert::TryInto;
+
+use anyhow::anyhow;
+use bytes::{BufMut, BytesMut};
+use ckb_types::{
+    bytes::Bytes,
+    core::{Capacity, TransactionBuilder, TransactionView},
+    packed::{CellInput, CellOutput, OutPoint, Script},
+    prelude::*,
+};
+
+use super::{type_id::build_type_id_script, TxBuilder, TxBuilderError};
+use crate::traits::{
+    CellCollector, CellDepResolver, CellQueryOptions, HeaderDepResolver, LiveCell,
+    TransactionDependencyProvider, ValueRangeOption,
+};
+use crate::types::ScriptId;
+
+/// The size of the version header of the state cell data
+pub const STATE_HEADER_SIZE: usize = 4 + 8;
+
+/// Encode and decode the state stored in a state cell, implemented for all
+/// molecule entities.
+pub trait MoleculeCodec: Sized {
+    fn encode(&self) -> Bytes;
+    fn decode(data: &[u8]) -> Result<Self, String>;
+}
+
+impl<T: Entity> MoleculeCodec for T {
+    fn encode(&self) -> Bytes {
+        self.as_bytes()
+    }
+    fn decode(data: &[u8]) -> Result<Self, String> {
+        T::from_slice(data).map_err(|err| err.to_string())
+    }
+}
+
+/// Encode the state cell data
+pub fn encode_state_data<T: MoleculeCodec>(schema_version: u32, revision: u64, state: &T) -> Bytes {
+    let state = state.encode();
+    let mut data = BytesMut::with_capacity(STATE_HEADER_SIZE + state.len());
+    data.put_u32_le(schema_version);
+    data.put_u64_le(revision);
+    data.put(state.as_ref());
+    data.freeze()
+}
+
+/// Decode the state cell data, return `(schema_version, revision, state)`
+pub fn decode_state_data<T: MoleculeCodec>(data: &[u8]) -> Result<(u32, u64, T), String> {
+    if data.len() < STATE_HEADER_SIZE {
+        return Err(format!(
+            "state cell data too short, expected at least {} bytes, got {}",
+            STATE_HEADER_SIZE,
+            data.len()
+        ));
+    }
+    let schema_version = u32::from_le_bytes(data[0..4].try_into().unwrap());
+    let revision = u64::from_le_bytes(data[4..STATE_HEADER_SIZE].try_into().unwrap());
+    let state = T::decode(&data[STATE_HEADER_SIZE..])?;
+    Ok((schema_version, revision, state))
+}
+
+/// A state cell read from the chain (or from a built transaction)
+#[derive(Debug, Clone)]
+pub struct StateCell<T> {
+    /// The type id script of the state cell
+    pub type_script: Script,
+    /// The out point of the cell when it was read
+    pub out_point: OutPoint,
+    pub output: CellOutput,
+    pub schema_version: u32,
+    pub revision: u64,
+    pub state: T,
+}
+
+impl<T: MoleculeCodec> StateCell<T> {
+    /// Decode the state cell from a live cell
+    pub fn from_live_cell(cell: &LiveCell) -> Result<StateCell<T>, TxBuilderError> {
+        let type_script = cell
+            .output
+            .type_()
+            .to_opt()
+            .filter(|script| ScriptId::from(script).is_type_id())
+            .ok_or_else(|| {
+                TxBuilderError::InvalidParameter(anyhow!(
+                    "cell {} has no type id script",
+                    cell.out_point
+                ))
+            })?;
+        let (schema_version, revision, state) =
+            decode_state_data(&cell.output_data).map_err(|err| {
+                TxBuilderError::InvalidParameter(anyhow!(
+                    "invalid state cell data of {}: {}",
+                    cell.out_point,
+                    err
+                ))
+            })?;
+        Ok(StateCell {
+            type_script,
+            out_point: cell.out_point.clone(),
+            output: cell.output.clone(),
+            schema_version,
+            revision,
+            state,
+        })
+    }
+
+    /// Decode the state cell from the output of a transaction, e.g. the
+    /// transaction built by the create or update builders (output index `0`).
+    pub fn from_tx(
+        tx: &TransactionView,
+        output_index: u32,
+    ) -> Result<StateCell<T>, TxBuilderError> {
+        let (output, output_data) =
+            tx.output_with_data(output_index as usize).ok_or_else(|| {
+                TxBuilderError::InvalidParameter(anyhow!(
+                    "output index out of bound: {}",
+                    output_index
+                ))
+            })?;
+        StateCell::from_live_cell(&LiveCell {
+            output,
+            output_data,
+            out_point: OutPoint::new(tx.hash(), output_index),
+            block_number: 0,
+            tx_index: 0,
+        })
+    }
+
+    /// Load the live state cell by its type id script
+    pub fn load(
+        cell_collector: &mut dyn CellCollector,
+        type_script: &Script,
+    ) -> Result<StateCell<T>, TxBuilderError> {
+        let cell = find_live_state_cell(cell_collector, type_script, false)?;
+        StateCell::from_live_cell(&cell)
+    }
+
+    /// Update the state, the revision is increased
+    pub fn update(&self, state: T) -> StateCellUpdateBuilder<T> {
+        StateCellUpdateBuilder {
+            type_script: self.type_script.clone(),
+            expected_out_point: self.out_point.clone(),
+            schema_version: self.schema_version,
+            revision: self.revision + 1,
+            state,
+            lock_script: None,
+            capacity: None,
+        }
+    }
+
+    /// Migrate the state to a new schema, the revision is increased
+    pub fn migrate<U: MoleculeCodec>(
+        &self,
+        schema_version: u32,
+        state: U,
+    ) -> StateCellUpdateBuilder<U> {
+        StateCellUpdateBuilder {
+            type_script: self.type_script.clone(),
+            expected_out_point: self.out_point.clone(),
+            schema_version,
+            revision: self.revision + 1,
+            state,
+            lock_script: None,
+            capacity: None,
+        }
+    }
+
+    /// Consume the state cell, the capacity goes to the change output
+    pub fn consume(&self) -> StateCellConsumeBuilder {
+        StateCellConsumeBuilder {
+            type_script: self.type_script.clone(),
+            expected_out_point: self.out_point.clone(),
+        }
+    }
+}
+
+fn find_live_state_cell(
+    cell_collector: &mut dyn CellCollector,
+    type_script: &Script,
+    apply_changes: bool,
+) -> Result<LiveCell, TxBuilderError> {
+    if !ScriptId::from(type_script).is_type_id() {
+        return Err(TxBuilderError::InvalidParameter(anyhow!(
+            "not a type id script: {:?}",
+            type_script
+        )));
+    }
+    let query = CellQueryOptions::new_type(type_script.clone());
+    let (mut cells, _) = cell_collector.collect_live_cells(&query, apply_changes)?;
+    if cells.len() != 1 {
+        return Err(TxBuilderError::Other(anyhow!(
+            "expected exactly one state cell, found: {}, type_script={:?}",
+            cells.len(),
+            type_script
+        )));
+    }
+    Ok(cells.remove(0))
+}
+
+/// Check the live state cell is still the one the transition was derived
+/// from.
+fn check_not_changed(
+    cell_collector: &mut dyn CellCollector,
+    type_script: &Script,
+    expected_out_point: &OutPoint,
+) -> Result<LiveCell, TxBuilderError> {
+    let cell = find_live_state_cell(cell_collector, type_script, true)?;
+    if &cell.out_point != expected_out_point {
+        return Err(TxBuilderError::Other(anyhow!(
+            "state cell changed since read, expected: {}, current: {}",
+            expected_out_point,
+            cell.out_point
+        )));
+    }
+    Ok(cell)
+}
+
+fn state_cell_capacity(
+    output: &CellOutput,
+    data_len: usize,
+    capacity: Option<u64>,
+    min_capacity: u64,
+) -> Result<u64, TxBuilderError> {
+    let occupied_capacity = output
+        .occupied_capacity(Capacity::bytes(data_len).unwrap())
+        .unwrap()
+        .as_u64();
+    match capacity {
+        Some(capacity) if capacity < occupied_capacity => {
+            Err(TxBuilderError::InvalidParameter(anyhow!(
+                "state cell capacity not enough, occupied: {}, actual: {}",
+                occupied_capacity,
+                capacity
+            )))
+        }
+        Some(capacity) => Ok(capacity),
+        None => Ok(std::cmp::max(min_capacity, occupied_capacity)),
+    }
+}
+
+/// Create a state cell guarded by a new type id script.
+///
+/// Like [`TypeIdDeployBuilder`](super::type_id::TypeIdDeployBuilder), the
+/// first input is collected from `lock_script` to compute the type id args,
+/// the state cell is always the first output. The schema version and the
+/// revision start from `0`.
+#[derive(Debug, Clone)]
+pub struct StateCellCreateBuilder<T> {
+    /// The lock script of the state cell, also provide the first input
+    pub lock_script: Script,
+    pub state: T,
+    /// The capacity of the state cell, use the occupied capacity if not given
+    pub capacity: Option<u64>,
+}
+
+impl<T: MoleculeCodec> StateCellCreateBuilder<T> {
+    pub fn new(lock_script: Script, state: T) -> StateCellCreateBuilder<T> {
+        StateCellCreateBuilder {
+            lock_script,
+            state,
+            capacity: None,
+        }
+    }
+}
+
+impl<T: MoleculeCodec> TxBuilder for StateCellCreateBuilder<T> {
+    fn build_base(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        let query = {
+            let mut query = CellQueryOptions::new_lock(self.lock_script.clone());
+            query.secondary_script_len_range = Some(ValueRangeOption::new_exact(0));
+            query.data_len_range = Some(ValueRangeOption::new_exact(0));
+            query
+        };
+        let (cells, _) = cell_collector.collect_live_cells(&query, true)?;
+        if cells.is_empty() {
+            return Err(TxBuilderError::Other(anyhow!(
+                "first input cell not found, lock={:?}",
+                self.lock_script
+            )));
+        }
+        let first_input = CellInput::new(cells[0].out_point.clone(), 0);
+        let lock_cell_dep = cell_dep_resolver
+            .resolve(&self.lock_script)
+            .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(self.lock_script.clone()))?;
+        #[allow(clippy::mutable_key_type)]
+        let mut cell_deps = HashSet::new();
+        cell_deps.insert(lock_cell_dep);
+
+        let data = encode_state_data(0, 0, &self.state);
+        let output = CellOutput::new_builder()
+            .lock(self.lock_script.clone())
+            .type_(Some(build_type_id_script(&first_input, 0)).pack())
+            .build();
+        let capacity = state_cell_capacity(&output, data.len(), self.capacity, 0)?;
+        let output = output.as_builder().capacity(capacity.pack()).build();
+        Ok(TransactionBuilder::default()
+            .set_cell_deps(cell_deps.into_iter().collect())
+            .set_inputs(vec![first_input])
+            .set_outputs(vec![output])
+            .set_outputs_data(vec![data.pack()])
+            .build())
+    }
+}
+
+/// Update (or migrate) a state cell, build with [`StateCell::update`] or
+/// [`StateCell::migrate`].
+///
+/// The state cell is consumed and re-created as the first output with the
+/// same type id script. Building fails if the live state cell is not
+/// `expected_out_point` any more.
+#[derive(Debug, Clone)]
+pub struct StateCellUpdateBuilder<T> {
+    /// The type id script of the state cell
+    pub type_script: Script,
+    /// The out point of the state cell this update is derived from
+    pub expected_out_point: OutPoint,
+    pub schema_version: u32,
+    pub revision: u64,
+    pub state: T,
+    /// The lock script of the new state cell, keep the old one if not given
+    pub lock_script: Option<Script>,
+    /// The capacity of the new state cell, if not given, keep the old capacity
+    /// unless the new occupied capacity is larger.
+    pub capacity: Option<u64>,
+}
+
+impl<T: MoleculeCodec> TxBuilder for StateCellUpdateBuilder<T> {
+    fn build_base(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        let old_cell =
+            check_not_changed(cell_collector, &self.type_script, &self.expected_out_point)?;
+        let old_lock = old_cell.output.lock();
+        let lock_script = self.lock_script.clone().unwrap_or_else(|| old_lock.clone());
+        let lock_cell_dep = cell_dep_resolver
+            .resolve(&old_lock)
+            .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(old_lock.clone()))?;
+        #[allow(clippy::mutable_key_type)]
+        let mut cell_deps = HashSet::new();
+        cell_deps.insert(lock_cell_dep);
+
+        let data = encode_state_data(self.schema_version, self.revision, &self.state);
+        let output = CellOutput::new_builder()
+            .lock(lock_script)
+            .type_(Some(self.type_script.clone()).pack())
+            .build();
+        let old_capacity: u64 = old_cell.output.capacity().unpack();
+        let capacity = state_cell_capacity(&output, data.len(), self.capacity, old_capacity)?;
+        let output = output.as_builder().capacity(capacity.pack()).build();
+        Ok(TransactionBuilder::default()
+            .set_cell_deps(cell_deps.into_iter().collect())
+            .set_inputs(vec![CellInput::new(old_cell.out_point, 0)])
+            .set_outputs(vec![output])
+            .set_outputs_data(vec![data.pack()])
+            .build())
+    }
+}
+
+/// Consume a state cell, build with [`StateCell::consume`].
+///
+/// The transaction has no outputs, the capacity of the state cell goes to
+/// the change output created by the balancer. Building fails if the live
+/// state cell is not `expected_out_point` any more.
+#[derive(Debug, Clone)]
+pub struct StateCellConsumeBuilder {
+    /// The type id script of the state cell
+    pub type_script: Script,
+    /// The out point of the state cell when it was read
+    pub expected_out_point: OutPoint,
+}
+
+impl TxBuilder for StateCellConsumeBuilder {
+    fn build_base(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        let old_cell =
+            check_not_changed(cell_collector, &self.type_script, &self.expected_out_point)?;
+        let old_lock = old_cell.output.lock();
+        let lock_cell_dep = cell_dep_resolver
+            .resolve(&old_lock)
+            .ok_or(TxBuilderError::ResolveCellDepFailed(old_lock))?;
+        Ok(TransactionBuilder::default()
+            .cell_dep(lock_cell_dep)
+            .input(CellInput::new(old_cell.out_point, 0))
+            .build())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::packed::Uint64;
+
+    #[test]
+    fn test_state_data_codec() {
+        let state: Uint64 = 42u64.pack();
+        let data = encode_state_data(1, 7, &state);
+        assert_eq!(data.len(), STATE_HEADER_SIZE + 8);
+        let (schema_version, revision, decoded) = decode_state_data::<Uint64>(&data).unwrap();
+        assert_eq!(schema_version, 1);
+        assert_eq!(revision, 7);
+        assert_eq!(decoded, state);
+        assert!(decode_state_data::<Uint64>(&data[0..STATE_HEADER_SIZE - 1]).is_err());
+        assert!(decode_state_data::<Uint64>(&data[0..STATE_HEADER_SIZE + 4]).is_err());
+    }
+}
@@ -0,0 +1,253 @@
+//! Submit a chain of dependent transactions in order.
+//!
+//! The transactions built by [`TxChainBuilder`](super::chain::TxChainBuilder)
+//! spend the outputs of the earlier ones, the node rejects a transaction if
+//! the transactions it depends on are not in the pool yet. [`ChainSubmitter`]
+//! sends them one by one and waits for each to reach
+//! [`ChainSubmitter::wait_for`] before sending the next. On failure the
+//! [`SubmitChainError`] tells where the chain stops, fix the cause (e.g.
+//! rebuild the failed transaction) and continue with
+//! [`ChainSubmitter::resume`].
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ckb_jsonrpc_types::{OutputsValidator, Status};
+use ckb_types::{core::TransactionView, packed::Byte32, prelude::*, H256};
+use thiserror::Error;
+
+use crate::rpc::{CkbRpcClient, RpcError};
+
+#[derive(Error, Debug)]
+pub enum SubmitError {
+    #[error("rpc error: `{0}`")]
+    Rpc(#[from] RpcError),
+
+    #[error("transaction rejected: `{0}`")]
+    Rejected(String),
+
+    #[error("transaction not accepted after {0:?}")]
+    Timeout(Duration),
+
+    #[error("transaction depends on the later transaction {0:#x} of the chain")]
+    OutOfOrder(H256),
+}
+
+/// The transactions before `index` are accepted, the chain can be resumed
+/// from `index`.
+#[derive(Error, Debug)]
+#[error("submit transaction #{index} {tx_hash:#x} failed: {source}")]
+pub struct SubmitChainError {
+    pub index: usize,
+    pub tx_hash: H256,
+    pub source: SubmitError,
+}
+
+/// The status a transaction must reach before the next one is sent
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum SubmitWaitFor {
+    /// Accepted into the pool
+    Pending,
+    Proposed,
+    Committed,
+}
+
+impl SubmitWaitFor {
+    fn is_reached(self, status: &Status) -> bool {
+        match status {
+            Status::Pending => self == SubmitWaitFor::Pending,
+            Status::Proposed => self != SubmitWaitFor::Committed,
+            Status::Committed => true,
+            _ => false,
+        }
+    }
+}
+
+/// Send the transactions of a chain in order
+#[derive(Clone)]
+pub struct ChainSubmitter {
+    pub ckb_client: CkbRpcClient,
+    pub wait_for: SubmitWaitFor,
+    /// The interval to poll the transaction status
+    pub poll_interval: Duration,
+    /// The max time to wait for each transaction
+    pub timeout: Duration,
+    pub outputs_validator: Option<OutputsValidator>,
+}
+
+impl ChainSubmitter {
+    pub fn new(ckb_client: &str) -> ChainSubmitter {
+        ChainSubmitter {
+            ckb_client: CkbRpcClient::new(ckb_client),
+            wait_for: SubmitWaitFor::Pending,
+            poll_interval: Duration::from_millis(500),
+            timeout: Duration::from_secs(60),
+            outputs_validator: None,
+        }
+    }
+
+    /// Send the transactions in order, return the transaction hashes.
+    pub fn submit_chain(&self, txs: Vec<TransactionView>) -> Result<Vec<H256>, SubmitChainError> {
+        self.resume(txs, 0)
+    }
+
+    /// Send the transactions from `index` in order, the transactions before
+    /// `index` must have been accepted. A transaction already known by the
+    /// node (e.g. sent before a timeout) is not sent again.
+    pub fn resume(
+        &self,
+        txs: Vec<TransactionView>,
+        index: usize,
+    ) -> Result<Vec<H256>, SubmitChainError> {
+        check_chain_order(&txs)?;
+        for (idx, tx) in txs.iter().enumerate().skip(index) {
+            self.submit_one(tx).map_err(|source| SubmitChainError {
+                index: idx,
+                tx_hash: tx.hash().unpack(),
+                source,
+            })?;
+        }
+        Ok(txs.iter().map(|tx| tx.hash().unpack()).collect())
+    }
+
+    fn submit_one(&self, tx: &TransactionView) -> Result<(), SubmitError> {
+        let tx_hash: H256 = tx.hash().unpack();
+        let sent = matches!(
+            self.status(&tx_hash)?,
+            Some((Status::Pending, _)) | Some((Status::Proposed, _)) | Some((Status::Committed, _))
+        );
+        if !sent {
+            self.ckb_client
+                .send_transaction(tx.data().into(), self.outputs_validator)?;
+        }
+        let start = Instant::now();
+        loop {
+            match self.status(&tx_hash)? {
+                Some((status, _)) if self.wait_for.is_reached(&status) => return Ok(()),
+                Some((Status::Rejected, reason)) => {
+                    return Err(SubmitError::Rejected(reason.unwrap_or_default()))
+                }
+                _ => {}
+            }
+            if start.elapsed() >= self.timeout {
+                return Err(SubmitError::Timeout(self.timeout));
+            }
+            thread::sleep(self.poll_interval);
+        }
+    }
+
+    fn status(&self, tx_hash: &H256) -> Result<Option<(Status, Option<String>)>, SubmitError> {
+        Ok(self
+            .ckb_client
+            .get_transaction(tx_hash.clone())?
+            .map(|tx_with_status| {
+                (
+                    tx_with_status.tx_status.status,
+                    tx_with_status.tx_status.reason,
+                )
+            }))
+    }
+}
+
+/// Check every transaction only spends (or references as cell deps) the
+/// outputs of the earlier transactions of the chain.
+pub fn check_chain_order(txs: &[TransactionView]) -> Result<(), SubmitChainError> {
+    let tx_hashes: Vec<Byte32> = txs.iter().map(|tx| tx.hash()).collect();
+    for (index, tx) in txs.iter().enumerate() {
+        let dep_tx_hashes = tx
+            .input_pts_iter()
+            .chain(tx.cell_deps_iter().map(|cell_dep| cell_dep.out_point()))
+            .map(|out_point| out_point.tx_hash());
+        for dep_tx_hash in dep_tx_hashes {
+            if tx_hashes[index..].contains(&dep_tx_hash) {
+                return Err(SubmitChainError {
+                    index,
+                    tx_hash: tx.hash().unpack(),
+                    source: SubmitError::OutOfOrder(dep_tx_hash.unpack()),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::MockRpcResult;
+    use ckb_types::{
+        bytes::Bytes,
+        core::TransactionBuilder,
+        packed::{CellInput, CellOutput, OutPoint},
+    };
+    use httpmock::prelude::*;
+
+    fn build_tx(input: OutPoint) -> TransactionView {
+        TransactionBuilder::default()
+            .input(CellInput::new(input, 0))
+            .output(CellOutput::new_builder().capacity(100u64.pack()).build())
+            .output_data(Bytes::default().pack())
+            .build()
+    }
+
+    #[test]
+    fn test_submit_chain() {
+        let tx0 = build_tx(OutPoint::new(H256([1; 32]).pack(), 0));
+        let tx1 = build_tx(OutPoint::new(tx0.hash(), 0));
+        let tx0_hash: H256 = tx0.hash().unpack();
+        let tx1_hash: H256 = tx1.hash().unpack();
+
+        let server = MockServer::start();
+        let submitter = ChainSubmitter::new(server.base_url().as_str());
+        let err = submitter
+            .submit_chain(vec![tx1.clone(), tx0.clone()])
+            .unwrap_err();
+        assert_eq!(err.index, 0);
+        assert!(matches!(err.source, SubmitError::OutOfOrder(ref hash) if hash == &tx0_hash));
+
+        // tx0 is already in the pool, tx1 is rejected after sent
+        server.mock(|when, then| {
+            when.method(POST)
+                .path("/")
+                .body_contains("get_transaction")
+                .body_contains(format!("{:#x}", tx0_hash));
+            then.status(200).body(
+                MockRpcResult::new(serde_json::json!({
+                    "tx_status": {"status": "pending"}
+                }))
+                .to_json(),
+            );
+        });
+        server.mock(|when, then| {
+            when.method(POST)
+                .path("/")
+                .body_contains("get_transaction")
+                .body_contains(format!("{:#x}", tx1_hash));
+            then.status(200).body(
+                MockRpcResult::new(serde_json::json!({
+                    "tx_status": {"status": "rejected", "reason": "Resolve failed"}
+                }))
+                .to_json(),
+            );
+        });
+        let send_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/")
+                .body_contains("send_transaction");
+            then.status(200)
+                .body(MockRpcResult::new(tx1_hash.clone()).to_json());
+        });
+        let err = submitter
+            .submit_chain(vec![tx0.clone(), tx1.clone()])
+            .unwrap_err();
+        assert_eq!(err.index, 1);
+        assert_eq!(err.tx_hash, tx1_hash);
+        assert!(
+            matches!(err.source, SubmitError::Rejected(ref reason) if reason == "Resolve failed")
+        );
+        send_mock.assert_hits(1);
+
+        let err = submitter.resume(vec![tx0, tx1], 1).unwrap_err();
+        assert_eq!(err.index, 1);
+        send_mock.assert_hits(2);
+    }
+}
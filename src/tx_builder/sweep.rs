@@ -0,0 +1,208 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::anyhow;
+use ckb_types::{
+    bytes::Bytes,
+    core::{Capacity, FeeRate, TransactionBuilder, TransactionView},
+    packed::{CellInput, CellOutput, OutPoint, Script},
+    prelude::*,
+};
+
+use super::{
+    channel::PendingTxDependencyProvider, fill_placeholder_witnesses, unlock_tx, TxBuilderError,
+};
+use crate::traits::{
+    CellCollector, CellDepResolver, CellQueryOptions, LiveCell, TransactionDependencyProvider,
+    ValueRangeOption,
+};
+use crate::types::{ScriptGroup, ScriptId};
+use crate::unlock::ScriptUnlocker;
+
+/// The default max inputs count of a sweep transaction
+pub const DEFAULT_SWEEP_MAX_INPUTS: usize = 1000;
+
+/// Consolidate all live cells under the lock scripts into a single output.
+///
+/// The transaction fee is paid by the swept capacity, so no extra capacity
+/// provider is required. When the cells count exceeds `max_inputs`, multiple
+/// chained transactions are built: the consolidated output of each
+/// transaction is the first input of the next one, so the last transaction
+/// holds the single final output. The chained transactions must be sent in
+/// order, and `receiver` must be unlockable by the given unlockers.
+#[derive(Debug, Clone)]
+pub struct SweepBuilder {
+    /// Sweep the live cells of those lock scripts
+    pub lock_scripts: Vec<Script>,
+    /// The lock script of the consolidated output
+    pub receiver: Script,
+    /// Also sweep the cells with type script. The type script is dropped from
+    /// the consolidated output, so it is up to the type script to allow it.
+    pub include_type_cells: bool,
+    /// The max inputs count of each transaction
+    pub max_inputs: usize,
+    /// The fee rate (shannons/KB)
+    pub fee_rate: u64,
+}
+
+impl SweepBuilder {
+    pub fn new(lock_scripts: Vec<Script>, receiver: Script, fee_rate: u64) -> SweepBuilder {
+        SweepBuilder {
+            lock_scripts,
+            receiver,
+            include_type_cells: false,
+            max_inputs: DEFAULT_SWEEP_MAX_INPUTS,
+            fee_rate,
+        }
+    }
+
+    fn collect_cells(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+    ) -> Result<Vec<LiveCell>, TxBuilderError> {
+        let mut cells = Vec::new();
+        for lock_script in &self.lock_scripts {
+            let mut query = CellQueryOptions::new_lock(lock_script.clone());
+            if !self.include_type_cells {
+                query.secondary_script_len_range = Some(ValueRangeOption::new_exact(0));
+            }
+            query.min_total_capacity = u64::MAX;
+            let (lock_cells, _) = cell_collector.collect_live_cells(&query, true)?;
+            cells.extend(lock_cells);
+        }
+        Ok(cells)
+    }
+
+    /// Build the sweep transactions, each one is unlocked by `unlockers`.
+    ///
+    /// Return value:
+    ///   * The transactions in sending order, with the script groups not
+    ///     unlocked by given `unlockers`
+    pub fn build_sweep_txs(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+        unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+    ) -> Result<Vec<(TransactionView, Vec<ScriptGroup>)>, TxBuilderError> {
+        if self.lock_scripts.is_empty() {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "empty sweep lock scripts"
+            )));
+        }
+        if self.max_inputs < 2 {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "sweep max inputs must be at least 2, got: {}",
+                self.max_inputs
+            )));
+        }
+        let cells = self.collect_cells(cell_collector)?;
+        if cells.is_empty() {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "no live cell to sweep"
+            )));
+        }
+
+        let mut txs: Vec<(TransactionView, Vec<ScriptGroup>)> = Vec::new();
+        let mut rest_cells = &cells[..];
+        while !rest_cells.is_empty() {
+            let prev_tx = txs.last().map(|(tx, _)| tx.clone());
+            let batch_size = if prev_tx.is_some() {
+                self.max_inputs - 1
+            } else {
+                self.max_inputs
+            };
+            let (batch, rest) = rest_cells.split_at(std::cmp::min(batch_size, rest_cells.len()));
+            rest_cells = rest;
+
+            let pending_provider;
+            let provider: &dyn TransactionDependencyProvider = match prev_tx.as_ref() {
+                Some(tx) => {
+                    pending_provider = PendingTxDependencyProvider {
+                        inner: tx_dep_provider,
+                        tx,
+                    };
+                    &pending_provider
+                }
+                None => tx_dep_provider,
+            };
+            let base_tx = self.build_batch(prev_tx.as_ref(), batch, cell_dep_resolver)?;
+            let (tx, _) = fill_placeholder_witnesses(base_tx, provider, unlockers)?;
+            let tx = self.deduct_fee(tx)?;
+            txs.push(unlock_tx(tx, provider, unlockers)?);
+        }
+        Ok(txs)
+    }
+
+    fn build_batch(
+        &self,
+        prev_tx: Option<&TransactionView>,
+        cells: &[LiveCell],
+        cell_dep_resolver: &dyn CellDepResolver,
+    ) -> Result<TransactionView, TxBuilderError> {
+        #[allow(clippy::mutable_key_type)]
+        let mut cell_deps = HashSet::new();
+        let mut inputs = Vec::new();
+        let mut input_total = 0;
+        if let Some(prev_tx) = prev_tx {
+            let prev_output = prev_tx.output(0).expect("sweep output");
+            let cell_dep = cell_dep_resolver
+                .resolve(&self.receiver)
+                .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(self.receiver.clone()))?;
+            cell_deps.insert(cell_dep);
+            inputs.push(CellInput::new(OutPoint::new(prev_tx.hash(), 0), 0));
+            input_total += Unpack::<u64>::unpack(&prev_output.capacity());
+        }
+        for cell in cells {
+            let lock_script = cell.output.lock();
+            let cell_dep = cell_dep_resolver
+                .resolve(&lock_script)
+                .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(lock_script.clone()))?;
+            cell_deps.insert(cell_dep);
+            if let Some(type_script) = cell.output.type_().to_opt() {
+                let cell_dep = cell_dep_resolver
+                    .resolve(&type_script)
+                    .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(type_script.clone()))?;
+                cell_deps.insert(cell_dep);
+            }
+            inputs.push(CellInput::new(cell.out_point.clone(), 0));
+            input_total += Unpack::<u64>::unpack(&cell.output.capacity());
+        }
+        let output = CellOutput::new_builder()
+            .capacity(input_total.pack())
+            .lock(self.receiver.clone())
+            .build();
+        Ok(TransactionBuilder::default()
+            .set_cell_deps(cell_deps.into_iter().collect())
+            .set_inputs(inputs)
+            .set_outputs(vec![output])
+            .set_outputs_data(vec![Bytes::new().pack()])
+            .build())
+    }
+
+    /// Pay the transaction fee from the consolidated output, the witnesses
+    /// must be filled with placeholders.
+    fn deduct_fee(&self, tx: TransactionView) -> Result<TransactionView, TxBuilderError> {
+        let output = tx.output(0).expect("sweep output");
+        let capacity: u64 = output.capacity().unpack();
+        let tx_size = tx.data().as_reader().serialized_size_in_block();
+        let fee = FeeRate::from_u64(self.fee_rate)
+            .fee(tx_size as u64)
+            .as_u64();
+        let occupied_capacity = output
+            .occupied_capacity(Capacity::zero())
+            .expect("occupied capacity")
+            .as_u64();
+        if capacity < occupied_capacity + fee {
+            return Err(TxBuilderError::Other(anyhow!(
+                "swept capacity not enough, required: {}, actual: {}",
+                occupied_capacity + fee,
+                capacity
+            )));
+        }
+        let output = output
+            .as_builder()
+            .capacity((capacity - fee).pack())
+            .build();
+        Ok(tx.as_advanced_builder().set_outputs(vec![output]).build())
+    }
+}
@@ -1,13 +1,14 @@
 use std::collections::HashSet;
 
+use anyhow::anyhow;
 use ckb_types::{
     bytes::Bytes,
-    core::{TransactionBuilder, TransactionView},
-    packed::CellOutput,
+    core::{Capacity, TransactionBuilder, TransactionView},
+    packed::{CellOutput, Script},
     prelude::*,
 };
 
-use super::{TxBuilder, TxBuilderError};
+use super::{is_info_output, TxBuilder, TxBuilderError};
 use crate::traits::{
     CellCollector, CellDepResolver, HeaderDepResolver, TransactionDependencyProvider,
 };
@@ -15,13 +16,73 @@ use crate::types::ScriptId;
 
 /// A builder to build a transaction simply transfer capcity to an address. It
 /// will resolve the type script's cell_dep if given.
+///
+/// The outputs can carry type script and output data, the capacity of every
+/// output must cover its occupied capacity (include the output data). The
+/// info outputs (see [`is_info_output`]) are allowed by default.
 pub struct CapacityTransferBuilder {
     pub outputs: Vec<(CellOutput, Bytes)>,
+    /// Allow the outputs holding exactly the occupied capacity with data
+    pub allow_info_outputs: bool,
 }
 
 impl CapacityTransferBuilder {
     pub fn new(outputs: Vec<(CellOutput, Bytes)>) -> CapacityTransferBuilder {
-        CapacityTransferBuilder { outputs }
+        CapacityTransferBuilder {
+            outputs,
+            allow_info_outputs: true,
+        }
+    }
+
+    /// Send all the capacity of the capacity provider to the receiver, the
+    /// balancer must be in send max mode for the output:
+    /// `balancer.set_send_max(Some(0))`.
+    pub fn new_send_max(receiver: Script) -> CapacityTransferBuilder {
+        let output = CellOutput::new_builder().lock(receiver).build();
+        let capacity = output
+            .occupied_capacity(Capacity::zero())
+            .expect("occupied capacity");
+        let output = output.as_builder().capacity(capacity.pack()).build();
+        CapacityTransferBuilder::new(vec![(output, Bytes::default())])
+    }
+
+    /// Allow or deny the info outputs
+    pub fn set_allow_info_outputs(&mut self, allow: bool) {
+        self.allow_info_outputs = allow;
+    }
+
+    /// Append an output with its output data
+    pub fn add_output(&mut self, output: CellOutput, output_data: Bytes) {
+        self.outputs.push((output, output_data));
+    }
+
+    /// Check every output capacity covers its occupied capacity
+    pub fn check_outputs(&self) -> Result<(), TxBuilderError> {
+        for (index, (output, output_data)) in self.outputs.iter().enumerate() {
+            let data_capacity = Capacity::bytes(output_data.len())
+                .map_err(|err| TxBuilderError::InvalidParameter(anyhow!(err)))?;
+            let occupied_capacity = output
+                .occupied_capacity(data_capacity)
+                .map_err(|err| TxBuilderError::InvalidParameter(anyhow!(err)))?
+                .as_u64();
+            let capacity: u64 = output.capacity().unpack();
+            if capacity < occupied_capacity {
+                return Err(TxBuilderError::InvalidParameter(anyhow!(
+                    "output[{}] capacity not enough, occupied: {}, actual: {}",
+                    index,
+                    occupied_capacity,
+                    capacity
+                )));
+            }
+            if !self.allow_info_outputs && is_info_output(output, output_data) {
+                return Err(TxBuilderError::InvalidParameter(anyhow!(
+                    "output[{}] is an info output (capacity equals occupied capacity: {})",
+                    index,
+                    capacity
+                )));
+            }
+        }
+        Ok(())
     }
 }
 
@@ -33,6 +94,7 @@ impl TxBuilder for CapacityTransferBuilder {
         _header_dep_resolver: &dyn HeaderDepResolver,
         _tx_dep_provider: &dyn TransactionDependencyProvider,
     ) -> Result<TransactionView, TxBuilderError> {
+        self.check_outputs()?;
         #[allow(clippy::mutable_key_type)]
         let mut cell_deps = HashSet::new();
         let mut outputs = Vec::new();
@@ -0,0 +1,279 @@
+use std::collections::HashSet;
+
+use anyhow::anyhow;
+use ckb_types::{
+    bytes::Bytes,
+    core::{Capacity, DepType, ScriptHashType, TransactionBuilder, TransactionView},
+    packed::{CellDep, CellInput, CellOutput, OutPoint, Script},
+    prelude::*,
+    H256,
+};
+
+use super::{TxBuilder, TxBuilderError};
+use crate::constants::TYPE_ID_CODE_HASH;
+use crate::traits::{
+    CellCollector, CellDepResolver, CellQueryOptions, HeaderDepResolver,
+    TransactionDependencyProvider, ValueRangeOption,
+};
+use crate::types::ScriptId;
+use crate::util::calculate_type_id;
+
+/// The deployed code cell guarded by a type id script
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct TypeIdDeployment {
+    /// The type id script of the code cell
+    pub type_id_script: Script,
+    /// The out point of the code cell
+    pub out_point: OutPoint,
+    /// The data hash of the deployed binary
+    pub data_hash: H256,
+}
+
+impl TypeIdDeployment {
+    /// Extract the deployment from the output of a transaction
+    pub fn from_tx(
+        tx: &TransactionView,
+        output_index: u32,
+    ) -> Result<TypeIdDeployment, TxBuilderError> {
+        let (output, data) = tx.output_with_data(output_index as usize).ok_or_else(|| {
+            TxBuilderError::InvalidParameter(anyhow!("output index out of bound: {}", output_index))
+        })?;
+        let type_id_script = output
+            .type_()
+            .to_opt()
+            .filter(|script| ScriptId::from(script).is_type_id())
+            .ok_or_else(|| {
+                TxBuilderError::InvalidParameter(anyhow!(
+                    "output[{}] has no type id script",
+                    output_index
+                ))
+            })?;
+        Ok(TypeIdDeployment {
+            type_id_script,
+            out_point: OutPoint::new(tx.hash(), output_index),
+            data_hash: CellOutput::calc_data_hash(&data).unpack(),
+        })
+    }
+
+    /// The code hash to reference the deployed code (with `type` hash type),
+    /// it stays the same after the code cell is upgraded.
+    pub fn code_hash(&self) -> H256 {
+        self.type_id_script.calc_script_hash().unpack()
+    }
+
+    /// The script id to reference the deployed code
+    pub fn script_id(&self) -> ScriptId {
+        ScriptId::new_type(self.code_hash())
+    }
+
+    /// The cell dep of the code cell, for registering into a cell dep resolver
+    pub fn cell_dep(&self) -> CellDep {
+        CellDep::new_builder()
+            .out_point(self.out_point.clone())
+            .dep_type(DepType::Code.into())
+            .build()
+    }
+}
+
+/// Deploy binary code into a cell guarded by the type id script.
+///
+/// The first input is collected from `lock_script` so the type id args can be
+/// computed, the balancer must not reorder the inputs. The code cell is always
+/// the first output, use [`TypeIdDeployment::from_tx`] with output index `0`
+/// to get the deployed code hash and out point.
+#[derive(Debug, Clone)]
+pub struct TypeIdDeployBuilder {
+    /// The lock script of the code cell, also provide the first input
+    pub lock_script: Script,
+    /// The binary code
+    pub data: Bytes,
+    /// The capacity of the code cell, use the occupied capacity if not given
+    pub capacity: Option<u64>,
+}
+
+impl TypeIdDeployBuilder {
+    pub fn new(lock_script: Script, data: Bytes) -> TypeIdDeployBuilder {
+        TypeIdDeployBuilder {
+            lock_script,
+            data,
+            capacity: None,
+        }
+    }
+}
+
+/// Build the type id script of the output at `output_index`
+pub fn build_type_id_script(first_cell_input: &CellInput, output_index: u64) -> Script {
+    let args = calculate_type_id(first_cell_input, output_index);
+    Script::new_builder()
+        .code_hash(TYPE_ID_CODE_HASH.pack())
+        .hash_type(ScriptHashType::Type.into())
+        .args(Bytes::from(args.to_vec()).pack())
+        .build()
+}
+
+impl TxBuilder for TypeIdDeployBuilder {
+    fn build_base(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        if self.data.is_empty() {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "empty type id deploy data"
+            )));
+        }
+        let query = {
+            let mut query = CellQueryOptions::new_lock(self.lock_script.clone());
+            query.secondary_script_len_range = Some(ValueRangeOption::new_exact(0));
+            query.data_len_range = Some(ValueRangeOption::new_exact(0));
+            query
+        };
+        let (cells, _) = cell_collector.collect_live_cells(&query, true)?;
+        if cells.is_empty() {
+            return Err(TxBuilderError::Other(anyhow!(
+                "first input cell not found, lock={:?}",
+                self.lock_script
+            )));
+        }
+        let first_input = CellInput::new(cells[0].out_point.clone(), 0);
+        let lock_cell_dep = cell_dep_resolver
+            .resolve(&self.lock_script)
+            .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(self.lock_script.clone()))?;
+        #[allow(clippy::mutable_key_type)]
+        let mut cell_deps = HashSet::new();
+        cell_deps.insert(lock_cell_dep);
+
+        let type_script = build_type_id_script(&first_input, 0);
+        let output = CellOutput::new_builder()
+            .lock(self.lock_script.clone())
+            .type_(Some(type_script).pack())
+            .build();
+        let occupied_capacity = output
+            .occupied_capacity(Capacity::bytes(self.data.len()).unwrap())
+            .unwrap()
+            .as_u64();
+        let capacity = match self.capacity {
+            Some(capacity) if capacity < occupied_capacity => {
+                return Err(TxBuilderError::InvalidParameter(anyhow!(
+                    "code cell capacity not enough, occupied: {}, actual: {}",
+                    occupied_capacity,
+                    capacity
+                )));
+            }
+            Some(capacity) => capacity,
+            None => occupied_capacity,
+        };
+        let output = output.as_builder().capacity(capacity.pack()).build();
+        Ok(TransactionBuilder::default()
+            .set_cell_deps(cell_deps.into_iter().collect())
+            .set_inputs(vec![first_input])
+            .set_outputs(vec![output])
+            .set_outputs_data(vec![self.data.pack()])
+            .build())
+    }
+}
+
+/// Upgrade the code cell guarded by a type id script.
+///
+/// The existing code cell is located by the type id script and consumed, the
+/// new code cell keeps the type id script so the code hash (type script hash)
+/// stays the same. When the new binary is larger, the extra capacity is
+/// provided by the balancer.
+#[derive(Debug, Clone)]
+pub struct TypeIdUpgradeBuilder {
+    /// The type id script of the code cell
+    pub type_id_script: Script,
+    /// The new binary code
+    pub data: Bytes,
+    /// The lock script of the new code cell, keep the old one if not given
+    pub lock_script: Option<Script>,
+    /// The capacity of the new code cell, if not given, keep the old capacity
+    /// unless the new occupied capacity is larger.
+    pub capacity: Option<u64>,
+}
+
+impl TypeIdUpgradeBuilder {
+    pub fn new(type_id_script: Script, data: Bytes) -> TypeIdUpgradeBuilder {
+        TypeIdUpgradeBuilder {
+            type_id_script,
+            data,
+            lock_script: None,
+            capacity: None,
+        }
+    }
+
+    /// The code hash of the deployed code, not changed by the upgrade
+    pub fn code_hash(&self) -> H256 {
+        self.type_id_script.calc_script_hash().unpack()
+    }
+}
+
+impl TxBuilder for TypeIdUpgradeBuilder {
+    fn build_base(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        if !ScriptId::from(&self.type_id_script).is_type_id() {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "not a type id script: {:?}",
+                self.type_id_script
+            )));
+        }
+        if self.data.is_empty() {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "empty type id upgrade data"
+            )));
+        }
+        let query = CellQueryOptions::new_type(self.type_id_script.clone());
+        let (cells, _) = cell_collector.collect_live_cells(&query, true)?;
+        if cells.len() != 1 {
+            return Err(TxBuilderError::Other(anyhow!(
+                "expected exactly one type id cell, found: {}, code_hash={:#x}",
+                cells.len(),
+                self.code_hash()
+            )));
+        }
+        let old_cell = &cells[0];
+        let old_lock = old_cell.output.lock();
+        let lock_script = self.lock_script.clone().unwrap_or_else(|| old_lock.clone());
+        let lock_cell_dep = cell_dep_resolver
+            .resolve(&old_lock)
+            .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(old_lock.clone()))?;
+        #[allow(clippy::mutable_key_type)]
+        let mut cell_deps = HashSet::new();
+        cell_deps.insert(lock_cell_dep);
+
+        let output = CellOutput::new_builder()
+            .lock(lock_script)
+            .type_(Some(self.type_id_script.clone()).pack())
+            .build();
+        let occupied_capacity = output
+            .occupied_capacity(Capacity::bytes(self.data.len()).unwrap())
+            .unwrap()
+            .as_u64();
+        let old_capacity: u64 = old_cell.output.capacity().unpack();
+        let capacity = match self.capacity {
+            Some(capacity) if capacity < occupied_capacity => {
+                return Err(TxBuilderError::InvalidParameter(anyhow!(
+                    "code cell capacity not enough, occupied: {}, actual: {}",
+                    occupied_capacity,
+                    capacity
+                )));
+            }
+            Some(capacity) => capacity,
+            None => std::cmp::max(old_capacity, occupied_capacity),
+        };
+        let output = output.as_builder().capacity(capacity.pack()).build();
+        Ok(TransactionBuilder::default()
+            .set_cell_deps(cell_deps.into_iter().collect())
+            .set_inputs(vec![CellInput::new(old_cell.out_point.clone(), 0)])
+            .set_outputs(vec![output])
+            .set_outputs_data(vec![self.data.pack()])
+            .build())
+    }
+}
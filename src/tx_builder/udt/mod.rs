@@ -1,4 +1,10 @@
 mod sudt;
+mod xudt;
+
+pub use xudt::{
+    XudtArgs, XudtExtension, XudtTransferBuilder, XUDT_FLAGS_OWNER_MODE_DISABLED,
+    XUDT_FLAGS_OWNER_MODE_INPUT_TYPE, XUDT_FLAGS_OWNER_MODE_OUTPUT_TYPE,
+};
 
 use anyhow::anyhow;
 use ckb_types::{
@@ -15,12 +21,14 @@ use crate::traits::{
     TransactionDependencyProvider, ValueRangeOption,
 };
 use crate::types::ScriptId;
+use crate::Address;
 
 /// The udt type
 #[derive(Debug, Eq, PartialEq, Hash, Clone)]
 pub enum UdtType {
     Sudt,
-    /// The parameter is <xudt args> (NOTE: xudt is current not supported, this variant is for future support)
+    /// The parameter is <xudt args> after the owner lock hash (flags and
+    /// extension data), see [`XudtArgs`]
     Xudt(Bytes),
 }
 
@@ -173,7 +181,9 @@ impl UdtTargetReceiver {
     }
 }
 
-/// The udt issue transaction builder
+/// The udt issue transaction builder, the owner cell is the first input so
+/// that the udt type script runs in owner mode. Multiple receivers can be
+/// minted in one transaction.
 pub struct UdtIssueBuilder {
     /// The udt type (sudt/xudt)
     pub udt_type: UdtType,
@@ -191,6 +201,28 @@ pub struct UdtIssueBuilder {
     pub receivers: Vec<UdtTargetReceiver>,
 }
 
+impl UdtIssueBuilder {
+    pub fn new(
+        udt_type: UdtType,
+        script_id: ScriptId,
+        owner: Script,
+        receivers: Vec<UdtTargetReceiver>,
+    ) -> UdtIssueBuilder {
+        UdtIssueBuilder {
+            udt_type,
+            script_id,
+            owner,
+            receivers,
+        }
+    }
+
+    /// The udt type script, the args is built from the owner lock hash
+    pub fn type_script(&self) -> Script {
+        self.udt_type
+            .build_script(&self.script_id, &self.owner.calc_script_hash())
+    }
+}
+
 impl TxBuilder for UdtIssueBuilder {
     fn build_base(
         &self,
@@ -199,6 +231,16 @@ impl TxBuilder for UdtIssueBuilder {
         _header_dep_resolver: &dyn HeaderDepResolver,
         _tx_dep_provider: &dyn TransactionDependencyProvider,
     ) -> Result<TransactionView, TxBuilderError> {
+        if self.receivers.is_empty() {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "udt issue receivers can not be empty"
+            )));
+        }
+        if self.receivers.iter().any(|receiver| receiver.amount == 0) {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "udt issue amount can not be 0"
+            )));
+        }
         // Build inputs
         let owner_query = {
             let mut query = CellQueryOptions::new_lock(self.owner.clone());
@@ -214,10 +256,7 @@ impl TxBuilder for UdtIssueBuilder {
         let mut inputs = vec![CellInput::new(owner_cells[0].out_point.clone(), 0)];
 
         // Build output type script
-        let owner_lock_hash = self.owner.calc_script_hash();
-        let type_script = self
-            .udt_type
-            .build_script(&self.script_id, &owner_lock_hash);
+        let type_script = self.type_script();
 
         let owner_cell_dep = cell_dep_resolver
             .resolve(&self.owner)
@@ -275,6 +314,248 @@ impl TxBuilder for UdtTransferBuilder {
         _header_dep_resolver: &dyn HeaderDepResolver,
         _tx_dep_provider: &dyn TransactionDependencyProvider,
     ) -> Result<TransactionView, TxBuilderError> {
+        build_transfer_base(
+            &self.type_script,
+            &self.sender,
+            &self.receivers,
+            false,
+            cell_collector,
+            cell_dep_resolver,
+        )
+    }
+}
+
+/// How the udt is delivered to a receiver
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum UdtReceiverMode {
+    /// Add the amount to the receiver's existing anyone-can-pay udt cell
+    Acp,
+    /// Create a cheque cell, the receiver claims it later
+    Cheque,
+    /// Create a new udt cell with the receiver's lock script
+    Plain,
+}
+
+/// Detect the receiver mode from the receiver lock script:
+///
+///   * The lock is an anyone-can-pay lock with an existing udt cell: `Acp`
+///   * Otherwise, if `cheque_script_id` is set: `Cheque`
+///   * Otherwise: `Plain`
+#[derive(Debug, Clone, Default)]
+pub struct UdtReceiverDetector {
+    /// The anyone-can-pay lock script ids (e.g. mainnet and testnet)
+    pub acp_script_ids: Vec<ScriptId>,
+    /// The cheque lock script id, create cheque cells for the receivers
+    /// without an anyone-can-pay udt cell if set
+    pub cheque_script_id: Option<ScriptId>,
+}
+
+impl UdtReceiverDetector {
+    pub fn new(
+        acp_script_ids: Vec<ScriptId>,
+        cheque_script_id: Option<ScriptId>,
+    ) -> UdtReceiverDetector {
+        UdtReceiverDetector {
+            acp_script_ids,
+            cheque_script_id,
+        }
+    }
+
+    pub fn detect_mode(
+        &self,
+        lock_script: &Script,
+        type_script: &Script,
+        cell_collector: &mut dyn CellCollector,
+    ) -> Result<UdtReceiverMode, TxBuilderError> {
+        if self.acp_script_ids.contains(&ScriptId::from(lock_script)) {
+            let mut query = CellQueryOptions::new_lock(lock_script.clone());
+            query.secondary_script = Some(type_script.clone());
+            query.data_len_range = Some(ValueRangeOption::new_min(16));
+            let (cells, _) = cell_collector.collect_live_cells(&query, false)?;
+            if !cells.is_empty() {
+                return Ok(UdtReceiverMode::Acp);
+            }
+        }
+        if self.cheque_script_id.is_some() {
+            Ok(UdtReceiverMode::Cheque)
+        } else {
+            Ok(UdtReceiverMode::Plain)
+        }
+    }
+
+    /// Build the transfer receiver of `lock_script` by the detected mode
+    pub fn build_receiver(
+        &self,
+        lock_script: &Script,
+        amount: u128,
+        type_script: &Script,
+        sender: &Script,
+        cell_collector: &mut dyn CellCollector,
+    ) -> Result<(UdtReceiverMode, UdtTargetReceiver), TxBuilderError> {
+        let mode = self.detect_mode(lock_script, type_script, cell_collector)?;
+        let receiver = match (mode, self.cheque_script_id.as_ref()) {
+            (UdtReceiverMode::Acp, _) => {
+                UdtTargetReceiver::new(TransferAction::Update, lock_script.clone(), amount)
+            }
+            (UdtReceiverMode::Cheque, Some(cheque_script_id)) => {
+                let mut args = lock_script.calc_script_hash().as_slice()[0..20].to_vec();
+                args.extend_from_slice(&sender.calc_script_hash().as_slice()[0..20]);
+                let cheque_lock = Script::new_builder()
+                    .code_hash(cheque_script_id.code_hash.pack())
+                    .hash_type(cheque_script_id.hash_type.into())
+                    .args(Bytes::from(args).pack())
+                    .build();
+                UdtTargetReceiver::new(TransferAction::Create, cheque_lock, amount)
+            }
+            _ => UdtTargetReceiver::new(TransferAction::Create, lock_script.clone(), amount),
+        };
+        Ok((mode, receiver))
+    }
+}
+
+/// The udt transfer builder, the `TransferAction` of each receiver is
+/// detected by `detector` (see [`UdtReceiverDetector`]).
+pub struct UdtAutoTransferBuilder {
+    /// The udt type script
+    pub type_script: Script,
+
+    /// Sender's lock script (we will asume there is only one udt cell identify
+    /// by `type_script` and `sender`)
+    pub sender: Script,
+
+    /// The receiver lock scripts (from the receiver addresses) and amounts
+    pub receivers: Vec<(Script, u128)>,
+
+    pub detector: UdtReceiverDetector,
+}
+
+impl UdtAutoTransferBuilder {
+    pub fn new(
+        type_script: Script,
+        sender: Script,
+        receivers: Vec<(Address, u128)>,
+        detector: UdtReceiverDetector,
+    ) -> UdtAutoTransferBuilder {
+        UdtAutoTransferBuilder {
+            type_script,
+            sender,
+            receivers: receivers
+                .iter()
+                .map(|(address, amount)| (Script::from(address), *amount))
+                .collect(),
+            detector,
+        }
+    }
+
+    /// Detect the receiver modes, in the same order of `receivers`
+    pub fn receiver_modes(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+    ) -> Result<Vec<UdtReceiverMode>, TxBuilderError> {
+        self.receivers
+            .iter()
+            .map(|(lock_script, _)| {
+                self.detector
+                    .detect_mode(lock_script, &self.type_script, cell_collector)
+            })
+            .collect()
+    }
+}
+
+impl TxBuilder for UdtAutoTransferBuilder {
+    fn build_base(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        let mut receivers = Vec::with_capacity(self.receivers.len());
+        for (lock_script, amount) in &self.receivers {
+            let (_, receiver) = self.detector.build_receiver(
+                lock_script,
+                *amount,
+                &self.type_script,
+                &self.sender,
+                cell_collector,
+            )?;
+            receivers.push(receiver);
+        }
+        build_transfer_base(
+            &self.type_script,
+            &self.sender,
+            &receivers,
+            false,
+            cell_collector,
+            cell_dep_resolver,
+        )
+    }
+}
+
+/// The udt burn transaction builder, burn part or all of the udt in sender's
+/// udt cell (the first input) and reclaim the capacity to `reclaim_lock`.
+///
+///   * Holder mode: the udt type script only allows output amount less or
+///     equal to input amount, xUDT with extension scripts is not supported
+///     since the extension scripts may reject burning.
+///   * Owner mode (sender is the udt owner): the udt type script skips amount
+///     checking (and xUDT extension scripts), this builder still refuses to
+///     burn more than the sender holds.
+///
+/// When burning partially, the change udt cell keeps the sender lock and the
+/// extra data, with minimal occupied capacity.
+pub struct UdtBurnBuilder {
+    /// The udt type script
+    pub type_script: Script,
+
+    /// Sender's lock script (we will asume there is only one udt cell identify
+    /// by `type_script` and `sender`)
+    pub sender: Script,
+
+    /// The amount to burn, `None` for burning all
+    pub amount: Option<u128>,
+
+    /// The lock script to receive the reclaimed capacity, `None` for sender
+    pub reclaim_lock: Option<Script>,
+}
+
+impl UdtBurnBuilder {
+    pub fn new(type_script: Script, sender: Script, amount: Option<u128>) -> UdtBurnBuilder {
+        UdtBurnBuilder {
+            type_script,
+            sender,
+            amount,
+            reclaim_lock: None,
+        }
+    }
+
+    /// Check if the sender is the udt owner
+    pub fn is_owner_mode(&self) -> Result<bool, TxBuilderError> {
+        let args = XudtArgs::from_slice(self.type_script.args().raw_data().as_ref())?;
+        Ok(args.is_owner_lock(&self.sender))
+    }
+}
+
+impl TxBuilder for UdtBurnBuilder {
+    fn build_base(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        if self.amount == Some(0) {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "udt burn amount can not be 0"
+            )));
+        }
+        let args = XudtArgs::from_slice(self.type_script.args().raw_data().as_ref())?;
+        if !args.is_owner_lock(&self.sender) && args.extension != XudtExtension::None {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "burn xudt with extension scripts in holder mode is not supported"
+            )));
+        }
+
         let sender_query = {
             let mut query = CellQueryOptions::new_lock(self.sender.clone());
             query.secondary_script = Some(self.type_script.clone());
@@ -301,45 +582,147 @@ impl TxBuilder for UdtTransferBuilder {
         let mut amount_bytes = [0u8; 16];
         amount_bytes.copy_from_slice(&sender_cell.output_data.as_ref()[0..16]);
         let input_total = u128::from_le_bytes(amount_bytes);
-        let output_total: u128 = self.receivers.iter().map(|receiver| receiver.amount).sum();
-        if input_total < output_total {
+        let burn_amount = self.amount.unwrap_or(input_total);
+        if input_total < burn_amount {
             return Err(TxBuilderError::Other(anyhow!(
                 "sender udt amount not enough, expected at least: {}, actual: {}",
-                output_total,
+                burn_amount,
                 input_total
             )));
         }
 
-        let sender_output_data = {
-            let new_amount = input_total - output_total;
+        let input_capacity: u64 = sender_cell.output.capacity().unpack();
+        let reclaim_lock = self.reclaim_lock.as_ref().unwrap_or(&self.sender);
+        let mut outputs = Vec::new();
+        let mut outputs_data = Vec::new();
+        let mut reclaim_capacity = input_capacity;
+        let rest_amount = input_total - burn_amount;
+        if rest_amount > 0 {
             let mut new_data = sender_cell.output_data.as_ref().to_vec();
-            new_data[0..16].copy_from_slice(&new_amount.to_le_bytes()[..]);
-            Bytes::from(new_data)
-        };
-
-        let mut inputs = vec![CellInput::new(sender_cell.out_point.clone(), 0)];
-        let mut outputs = vec![sender_cell.output.clone()];
-        let mut outputs_data = vec![sender_output_data.pack()];
-
-        for receiver in &self.receivers {
-            let ReceiverBuildOutput {
-                input,
-                output,
-                output_data,
-            } = receiver.build(&self.type_script, cell_collector, cell_dep_resolver)?;
-            if let Some((input, input_lock_cell_dep)) = input {
-                inputs.push(input);
-                cell_deps.insert(input_lock_cell_dep);
-            }
-            outputs.push(output);
-            outputs_data.push(output_data.pack());
+            new_data[0..16].copy_from_slice(&rest_amount.to_le_bytes()[..]);
+            let change_capacity = sender_cell
+                .output
+                .occupied_capacity(Capacity::bytes(new_data.len()).unwrap())
+                .unwrap()
+                .as_u64();
+            outputs.push(
+                sender_cell
+                    .output
+                    .clone()
+                    .as_builder()
+                    .capacity(change_capacity.pack())
+                    .build(),
+            );
+            outputs_data.push(Bytes::from(new_data).pack());
+            reclaim_capacity -= change_capacity;
+        }
+        let reclaim_output = CellOutput::new_builder().lock(reclaim_lock.clone()).build();
+        let reclaim_occupied_capacity = reclaim_output
+            .occupied_capacity(Capacity::zero())
+            .unwrap()
+            .as_u64();
+        if reclaim_capacity >= reclaim_occupied_capacity {
+            outputs.push(
+                reclaim_output
+                    .as_builder()
+                    .capacity(reclaim_capacity.pack())
+                    .build(),
+            );
+            outputs_data.push(Bytes::new().pack());
+        } else if let Some(change_output) = outputs.pop() {
+            // Not enough to hold a reclaim cell, keep the capacity in change udt cell
+            outputs.push(
+                change_output
+                    .as_builder()
+                    .capacity(input_capacity.pack())
+                    .build(),
+            );
         }
 
         Ok(TransactionBuilder::default()
             .set_cell_deps(cell_deps.into_iter().collect())
-            .set_inputs(inputs)
+            .set_inputs(vec![CellInput::new(sender_cell.out_point.clone(), 0)])
             .set_outputs(outputs)
             .set_outputs_data(outputs_data)
             .build())
     }
 }
+
+/// Build the udt transfer transaction, the sender udt cell is the first input
+/// and the first output. When `owner_mode` is true the sender can transfer
+/// more than it holds (issue more udt).
+fn build_transfer_base(
+    type_script: &Script,
+    sender: &Script,
+    receivers: &[UdtTargetReceiver],
+    owner_mode: bool,
+    cell_collector: &mut dyn CellCollector,
+    cell_dep_resolver: &dyn CellDepResolver,
+) -> Result<TransactionView, TxBuilderError> {
+    let sender_query = {
+        let mut query = CellQueryOptions::new_lock(sender.clone());
+        query.secondary_script = Some(type_script.clone());
+        query.data_len_range = Some(ValueRangeOption::new_min(16));
+        query
+    };
+    let (sender_cells, _) = cell_collector.collect_live_cells(&sender_query, true)?;
+    if sender_cells.is_empty() {
+        return Err(TxBuilderError::Other(anyhow!("sender cell not found")));
+    }
+    let sender_cell = &sender_cells[0];
+
+    let sender_cell_dep = cell_dep_resolver
+        .resolve(sender)
+        .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(sender.clone()))?;
+    let udt_cell_dep = cell_dep_resolver
+        .resolve(type_script)
+        .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(type_script.clone()))?;
+    #[allow(clippy::mutable_key_type)]
+    let mut cell_deps = HashSet::new();
+    cell_deps.insert(sender_cell_dep);
+    cell_deps.insert(udt_cell_dep);
+
+    let mut amount_bytes = [0u8; 16];
+    amount_bytes.copy_from_slice(&sender_cell.output_data.as_ref()[0..16]);
+    let input_total = u128::from_le_bytes(amount_bytes);
+    let output_total: u128 = receivers.iter().map(|receiver| receiver.amount).sum();
+    if input_total < output_total && !owner_mode {
+        return Err(TxBuilderError::Other(anyhow!(
+            "sender udt amount not enough, expected at least: {}, actual: {}",
+            output_total,
+            input_total
+        )));
+    }
+
+    let sender_output_data = {
+        let new_amount = input_total.saturating_sub(output_total);
+        let mut new_data = sender_cell.output_data.as_ref().to_vec();
+        new_data[0..16].copy_from_slice(&new_amount.to_le_bytes()[..]);
+        Bytes::from(new_data)
+    };
+
+    let mut inputs = vec![CellInput::new(sender_cell.out_point.clone(), 0)];
+    let mut outputs = vec![sender_cell.output.clone()];
+    let mut outputs_data = vec![sender_output_data.pack()];
+
+    for receiver in receivers {
+        let ReceiverBuildOutput {
+            input,
+            output,
+            output_data,
+        } = receiver.build(type_script, cell_collector, cell_dep_resolver)?;
+        if let Some((input, input_lock_cell_dep)) = input {
+            inputs.push(input);
+            cell_deps.insert(input_lock_cell_dep);
+        }
+        outputs.push(output);
+        outputs_data.push(output_data.pack());
+    }
+
+    Ok(TransactionBuilder::default()
+        .set_cell_deps(cell_deps.into_iter().collect())
+        .set_inputs(inputs)
+        .set_outputs(outputs)
+        .set_outputs_data(outputs_data)
+        .build())
+}
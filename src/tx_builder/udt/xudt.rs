@@ -0,0 +1,315 @@
+use anyhow::anyhow;
+use ckb_types::{
+    bytes::{BufMut, Bytes, BytesMut},
+    core::TransactionView,
+    packed::{Byte32, BytesVec, Script, WitnessArgs},
+    prelude::*,
+    H160,
+};
+
+use super::{build_transfer_base, UdtTargetReceiver};
+use crate::traits::{
+    CellCollector, CellDepResolver, HeaderDepResolver, TransactionDependencyProvider,
+};
+use crate::tx_builder::{TxBuilder, TxBuilderError};
+use crate::types::xudt_rce_mol::{ScriptVec, ScriptVecOpt, XudtWitnessInput};
+use crate::util::blake160;
+
+/// Owner mode is checked by input type script hash instead of input lock script hash
+pub const XUDT_FLAGS_OWNER_MODE_INPUT_TYPE: u32 = 0x8000_0000;
+/// Owner mode is disabled
+pub const XUDT_FLAGS_OWNER_MODE_DISABLED: u32 = 0x4000_0000;
+/// Owner mode is checked by output type script hash instead of input lock script hash
+pub const XUDT_FLAGS_OWNER_MODE_OUTPUT_TYPE: u32 = 0x2000_0000;
+const XUDT_FLAGS_OWNER_MODE_MASK: u32 = 0xE000_0000;
+const XUDT_FLAGS_EXTENSION_MASK: u32 = 0x1FFF_FFFF;
+
+/// The extension scripts of xUDT
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum XudtExtension {
+    /// No extension script
+    None,
+    /// The extension scripts are stored in args directly
+    Inline(Vec<Script>),
+    /// The blake160 hash of the extension scripts (molecule `ScriptVec`), the
+    /// scripts must be provided in witness
+    Hash(H160),
+}
+
+/// The xUDT type script args:
+///
+/// ```text
+/// <owner lock script hash (32 bytes)> <xUDT flags (4 bytes)> <extension data>
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct XudtArgs {
+    pub owner_lock_hash: Byte32,
+    /// The owner mode flags (the highest 3 bits of xUDT flags)
+    pub owner_mode_flags: u32,
+    pub extension: XudtExtension,
+}
+
+impl XudtArgs {
+    pub fn new(owner_lock_hash: Byte32, extension: XudtExtension) -> XudtArgs {
+        XudtArgs {
+            owner_lock_hash,
+            owner_mode_flags: 0,
+            extension,
+        }
+    }
+
+    pub fn from_slice(args: &[u8]) -> Result<XudtArgs, TxBuilderError> {
+        if args.len() < 32 {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "invalid xudt args length, expected at least: 32, got: {}",
+                args.len()
+            )));
+        }
+        let owner_lock_hash = Byte32::from_slice(&args[0..32]).unwrap();
+        if args.len() == 32 {
+            return Ok(XudtArgs::new(owner_lock_hash, XudtExtension::None));
+        }
+        if args.len() < 36 {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "invalid xudt args length, expected at least: 36, got: {}",
+                args.len()
+            )));
+        }
+        let mut flags_bytes = [0u8; 4];
+        flags_bytes.copy_from_slice(&args[32..36]);
+        let flags = u32::from_le_bytes(flags_bytes);
+        let extension_data = &args[36..];
+        let extension = match flags & XUDT_FLAGS_EXTENSION_MASK {
+            0 => XudtExtension::None,
+            1 => {
+                let scripts = ScriptVec::from_slice(extension_data).map_err(|err| {
+                    TxBuilderError::InvalidParameter(anyhow!(
+                        "invalid xudt extension scripts: {}",
+                        err
+                    ))
+                })?;
+                XudtExtension::Inline(scripts.into_iter().collect())
+            }
+            2 => {
+                if extension_data.len() != 20 {
+                    return Err(TxBuilderError::InvalidParameter(anyhow!(
+                        "invalid xudt extension scripts hash length, expected: 20, got: {}",
+                        extension_data.len()
+                    )));
+                }
+                XudtExtension::Hash(H160::from_slice(extension_data).unwrap())
+            }
+            flags => {
+                return Err(TxBuilderError::InvalidParameter(anyhow!(
+                    "unsupported xudt extension flags: {:#x}",
+                    flags
+                )))
+            }
+        };
+        Ok(XudtArgs {
+            owner_lock_hash,
+            owner_mode_flags: flags & XUDT_FLAGS_OWNER_MODE_MASK,
+            extension,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let (extension_flags, extension_data) = match &self.extension {
+            XudtExtension::None => (0u32, Bytes::new()),
+            XudtExtension::Inline(scripts) => (1, build_script_vec(scripts).as_bytes()),
+            XudtExtension::Hash(hash) => (2, Bytes::from(hash.as_bytes().to_vec())),
+        };
+        let flags = (self.owner_mode_flags & XUDT_FLAGS_OWNER_MODE_MASK) | extension_flags;
+        let mut args = BytesMut::with_capacity(36 + extension_data.len());
+        args.put(self.owner_lock_hash.as_slice());
+        if flags != 0 {
+            args.put(&flags.to_le_bytes()[..]);
+            args.put(extension_data.as_ref());
+        }
+        args.freeze()
+    }
+
+    /// Check if the owner mode is satisfied by a input lock script
+    pub fn is_owner_lock(&self, lock_script: &Script) -> bool {
+        self.owner_mode_flags & XUDT_FLAGS_OWNER_MODE_MASK == 0
+            && lock_script.calc_script_hash() == self.owner_lock_hash
+    }
+}
+
+fn build_script_vec(scripts: &[Script]) -> ScriptVec {
+    ScriptVec::new_builder().set(scripts.to_vec()).build()
+}
+
+/// The xUDT transfer transaction builder, the sender xUDT cell is the first
+/// input.
+///
+/// If the xUDT has extension scripts, their cell deps are added and the
+/// `XudtWitnessInput` is filled into the `input_type` field of the first
+/// witness.
+pub struct XudtTransferBuilder {
+    /// The xudt type script
+    pub type_script: Script,
+
+    /// Sender's lock script (we will asume there is only one udt cell identify
+    /// by `type_script` and `sender`), if the sender is the xUDT owner more
+    /// xUDT can be issued.
+    pub sender: Script,
+
+    /// The transfer receivers
+    pub receivers: Vec<UdtTargetReceiver>,
+
+    /// The extension scripts, required when extension scripts is stored as
+    /// hash in type script args
+    pub extension_scripts: Option<Vec<Script>>,
+
+    /// The extension data of each extension script
+    pub extension_data: Vec<Bytes>,
+}
+
+impl XudtTransferBuilder {
+    pub fn new(
+        type_script: Script,
+        sender: Script,
+        receivers: Vec<UdtTargetReceiver>,
+    ) -> XudtTransferBuilder {
+        XudtTransferBuilder {
+            type_script,
+            sender,
+            receivers,
+            extension_scripts: None,
+            extension_data: Vec::new(),
+        }
+    }
+
+    fn resolve_extension_scripts(
+        &self,
+        args: &XudtArgs,
+    ) -> Result<Option<Vec<Script>>, TxBuilderError> {
+        let scripts = match &args.extension {
+            XudtExtension::None => return Ok(None),
+            XudtExtension::Inline(scripts) => scripts.clone(),
+            XudtExtension::Hash(hash) => {
+                let scripts = self.extension_scripts.clone().ok_or_else(|| {
+                    TxBuilderError::InvalidParameter(anyhow!("xudt extension scripts required"))
+                })?;
+                if &blake160(build_script_vec(&scripts).as_slice()) != hash {
+                    return Err(TxBuilderError::InvalidParameter(anyhow!(
+                        "xudt extension scripts not match with the hash in args"
+                    )));
+                }
+                scripts
+            }
+        };
+        if self.extension_data.len() != scripts.len() {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "xudt extension data count not match with extension scripts, expected: {}, got: {}",
+                scripts.len(),
+                self.extension_data.len()
+            )));
+        }
+        Ok(Some(scripts))
+    }
+}
+
+impl TxBuilder for XudtTransferBuilder {
+    fn build_base(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        let args = XudtArgs::from_slice(self.type_script.args().raw_data().as_ref())?;
+        let extension_scripts = self.resolve_extension_scripts(&args)?;
+        let tx = build_transfer_base(
+            &self.type_script,
+            &self.sender,
+            &self.receivers,
+            args.is_owner_lock(&self.sender),
+            cell_collector,
+            cell_dep_resolver,
+        )?;
+        let scripts = match extension_scripts {
+            Some(scripts) => scripts,
+            None => return Ok(tx),
+        };
+
+        let mut cell_deps: Vec<_> = tx.cell_deps().into_iter().collect();
+        for script in &scripts {
+            let cell_dep = cell_dep_resolver
+                .resolve(script)
+                .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(script.clone()))?;
+            if !cell_deps.contains(&cell_dep) {
+                cell_deps.push(cell_dep);
+            }
+        }
+        let raw_extension_data = match &args.extension {
+            XudtExtension::Hash(_) => Some(build_script_vec(&scripts)),
+            _ => None,
+        };
+        let witness_input = XudtWitnessInput::new_builder()
+            .raw_extension_data(ScriptVecOpt::new_builder().set(raw_extension_data).build())
+            .extension_data(
+                BytesVec::new_builder()
+                    .set(self.extension_data.iter().map(|data| data.pack()).collect())
+                    .build(),
+            )
+            .build();
+        let witness = WitnessArgs::new_builder()
+            .input_type(Some(witness_input.as_bytes()).pack())
+            .build();
+        let mut witnesses: Vec<_> = tx.witnesses().into_iter().collect();
+        if witnesses.is_empty() {
+            witnesses.push(witness.as_bytes().pack());
+        } else {
+            witnesses[0] = witness.as_bytes().pack();
+        }
+        Ok(tx
+            .as_advanced_builder()
+            .set_cell_deps(cell_deps)
+            .set_witnesses(witnesses)
+            .build())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::{core::ScriptHashType, h256};
+
+    #[test]
+    fn test_xudt_args_roundtrip() {
+        let owner_lock_hash =
+            h256!("0x0c9cc8a8a7c9e5ea9b7e06bf5d6fde3e7c2b5a0f2f3e2e4d1a8b7c6d5e4f3a2b").pack();
+        let extension_script = Script::new_builder()
+            .code_hash(
+                h256!("0x9bd7e06f3ecf4be0f2fcd2188b23f1b9fcc88e5d4b65a8637b17723bbda3cce8").pack(),
+            )
+            .hash_type(ScriptHashType::Type.into())
+            .build();
+        let script_vec = build_script_vec(&[extension_script.clone()]);
+        let cases = vec![
+            XudtArgs::new(owner_lock_hash.clone(), XudtExtension::None),
+            XudtArgs::new(
+                owner_lock_hash.clone(),
+                XudtExtension::Inline(vec![extension_script]),
+            ),
+            XudtArgs {
+                owner_lock_hash: owner_lock_hash.clone(),
+                owner_mode_flags: XUDT_FLAGS_OWNER_MODE_INPUT_TYPE,
+                extension: XudtExtension::Hash(blake160(script_vec.as_slice())),
+            },
+        ];
+        for args in cases {
+            let bytes = args.to_bytes();
+            assert_eq!(XudtArgs::from_slice(bytes.as_ref()).unwrap(), args);
+        }
+        assert_eq!(
+            XudtArgs::new(owner_lock_hash, XudtExtension::None)
+                .to_bytes()
+                .len(),
+            32
+        );
+        assert!(XudtArgs::from_slice(&[0u8; 34]).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+//! Capacity utilization report of built transactions.
+//!
+//! Report the occupied capacity vs total capacity of every output, and flag
+//! the outputs carrying large unnecessary free capacity, for tuning payout
+//! sizes and detecting capacity leaking into cells controlled by others.
+use ckb_types::{
+    core::{Capacity, TransactionView},
+    prelude::*,
+};
+
+use crate::types::HumanCapacity;
+
+/// The default max free capacity of an output before it is flagged (100 CKB)
+pub const DEFAULT_MAX_FREE_CAPACITY: u64 = 100 * 100_000_000;
+
+/// Options for building [`CapacityUtilizationReport`]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct UtilizationOptions {
+    /// Flag the output when its free capacity is larger than this value
+    pub max_free_capacity: u64,
+    /// Also flag plain cells (no type script and empty data), the capacity of
+    /// a plain cell is usually the payment itself so they are skipped by default.
+    pub include_plain_cells: bool,
+}
+
+impl Default for UtilizationOptions {
+    fn default() -> UtilizationOptions {
+        UtilizationOptions {
+            max_free_capacity: DEFAULT_MAX_FREE_CAPACITY,
+            include_plain_cells: false,
+        }
+    }
+}
+
+/// The capacity usage of an output
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct OutputCapacityUsage {
+    /// The output index
+    pub index: usize,
+    /// The capacity of the output
+    pub capacity: u64,
+    /// The occupied capacity of the output (include output data)
+    pub occupied_capacity: u64,
+    /// If the output carries large unnecessary free capacity
+    pub flagged: bool,
+}
+
+impl OutputCapacityUsage {
+    pub fn free_capacity(&self) -> u64 {
+        self.capacity.saturating_sub(self.occupied_capacity)
+    }
+
+    /// The ratio of occupied capacity to total capacity
+    pub fn utilization(&self) -> f64 {
+        if self.capacity == 0 {
+            return 0.0;
+        }
+        self.occupied_capacity as f64 / self.capacity as f64
+    }
+}
+
+/// The capacity utilization report of a transaction
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct CapacityUtilizationReport {
+    pub outputs: Vec<OutputCapacityUsage>,
+}
+
+impl CapacityUtilizationReport {
+    pub fn new(tx: &TransactionView, options: &UtilizationOptions) -> CapacityUtilizationReport {
+        let outputs = tx
+            .outputs_with_data_iter()
+            .enumerate()
+            .map(|(index, (output, data))| {
+                let capacity: u64 = output.capacity().unpack();
+                let occupied_capacity = output
+                    .occupied_capacity(Capacity::bytes(data.len()).expect("data capacity"))
+                    .expect("occupied capacity")
+                    .as_u64();
+                let is_plain = output.type_().is_none() && data.is_empty();
+                let flagged = (options.include_plain_cells || !is_plain)
+                    && capacity.saturating_sub(occupied_capacity) > options.max_free_capacity;
+                OutputCapacityUsage {
+                    index,
+                    capacity,
+                    occupied_capacity,
+                    flagged,
+                }
+            })
+            .collect();
+        CapacityUtilizationReport { outputs }
+    }
+
+    /// The flagged outputs
+    pub fn flagged(&self) -> impl Iterator<Item = &OutputCapacityUsage> {
+        self.outputs.iter().filter(|usage| usage.flagged)
+    }
+
+    pub fn total_capacity(&self) -> u64 {
+        self.outputs.iter().map(|usage| usage.capacity).sum()
+    }
+
+    pub fn total_occupied_capacity(&self) -> u64 {
+        self.outputs
+            .iter()
+            .map(|usage| usage.occupied_capacity)
+            .sum()
+    }
+
+    /// The ratio of occupied capacity to total capacity of all outputs
+    pub fn utilization(&self) -> f64 {
+        let total_capacity = self.total_capacity();
+        if total_capacity == 0 {
+            return 0.0;
+        }
+        self.total_occupied_capacity() as f64 / total_capacity as f64
+    }
+
+    /// Human readable summary, one line per output
+    pub fn summary(&self) -> String {
+        self.outputs
+            .iter()
+            .map(|usage| {
+                format!(
+                    "output[{}]: capacity: {}, occupied: {}, utilization: {:.2}%{}",
+                    usage.index,
+                    HumanCapacity(usage.capacity),
+                    HumanCapacity(usage.occupied_capacity),
+                    usage.utilization() * 100.0,
+                    if usage.flagged {
+                        ", large free capacity"
+                    } else {
+                        ""
+                    }
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::{
+        bytes::Bytes,
+        core::TransactionBuilder,
+        packed::{CellOutput, Script},
+    };
+
+    #[test]
+    fn test_capacity_utilization_report() {
+        let lock = Script::default();
+        let type_script = Script::new_builder()
+            .args(Bytes::from(vec![1u8; 32]).pack())
+            .build();
+        let output = |capacity: u64, type_script: Option<Script>| {
+            CellOutput::new_builder()
+                .capacity(capacity.pack())
+                .lock(lock.clone())
+                .type_(type_script.pack())
+                .build()
+        };
+        let tx = TransactionBuilder::default()
+            .output(output(1000 * 100_000_000, None))
+            .output(output(1000 * 100_000_000, Some(type_script.clone())))
+            .output(output(150 * 100_000_000, Some(type_script)))
+            .output_data(Bytes::new().pack())
+            .output_data(Bytes::from(vec![0u8; 16]).pack())
+            .output_data(Bytes::from(vec![0u8; 16]).pack())
+            .build();
+
+        let report = CapacityUtilizationReport::new(&tx, &UtilizationOptions::default());
+        assert_eq!(report.outputs.len(), 3);
+        assert_eq!(
+            report
+                .flagged()
+                .map(|usage| usage.index)
+                .collect::<Vec<_>>(),
+            vec![1]
+        );
+        assert_eq!(report.outputs[0].occupied_capacity, 41 * 100_000_000);
+        assert!(report.outputs[2].utilization() > 0.5);
+        assert_eq!(report.summary().lines().count(), 3);
+
+        let options = UtilizationOptions {
+            include_plain_cells: true,
+            ..Default::default()
+        };
+        let report = CapacityUtilizationReport::new(&tx, &options);
+        assert_eq!(report.flagged().count(), 2);
+    }
+}
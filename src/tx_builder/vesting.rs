@@ -0,0 +1,257 @@
+use std::collections::HashSet;
+
+use anyhow::anyhow;
+use ckb_types::{
+    bytes::Bytes,
+    core::{EpochNumberWithFraction, TransactionBuilder, TransactionView},
+    packed::{CellInput, CellOutput, Script},
+    prelude::*,
+};
+
+use super::{TxBuilder, TxBuilderError};
+use crate::traits::{
+    CellCollector, CellDepResolver, CellQueryOptions, HeaderDepResolver,
+    TransactionDependencyProvider, ValueRangeOption,
+};
+use crate::types::{ScriptId, Since};
+use crate::unlock::{MultisigConfig, OmniLockConfig};
+
+/// The lock used to hold vesting tranches, the unlock epoch of each tranche
+/// is encoded into the lock script args.
+#[derive(Debug, Clone)]
+pub enum VestingLock {
+    /// Multisig lock with the absolute epoch since appended to the args.
+    Multisig(MultisigConfig),
+    /// Omni lock with the time lock flag set.
+    OmniLock {
+        cfg: OmniLockConfig,
+        /// The omni lock script id (code hash and hash type)
+        script_id: ScriptId,
+    },
+}
+
+impl VestingLock {
+    /// Build the lock script of the tranche unlocked at `epoch_number`.
+    pub fn lock_script(&self, epoch_number: u64) -> Script {
+        match self {
+            VestingLock::Multisig(cfg) => Script::from(&cfg.to_address_payload(Some(epoch_number))),
+            VestingLock::OmniLock { cfg, script_id } => {
+                let mut cfg = cfg.clone();
+                cfg.set_time_lock_config(Since::new_absolute_epoch(epoch_number).value());
+                Script::new_builder()
+                    .code_hash(script_id.code_hash.pack())
+                    .hash_type(script_id.hash_type.into())
+                    .args(cfg.build_args().pack())
+                    .build()
+            }
+        }
+    }
+}
+
+/// A vesting tranche, `capacity` will be unlocked at the start of epoch
+/// `epoch_number`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct VestingTranche {
+    pub epoch_number: u64,
+    pub capacity: u64,
+}
+
+impl VestingTranche {
+    pub fn new(epoch_number: u64, capacity: u64) -> VestingTranche {
+        VestingTranche {
+            epoch_number,
+            capacity,
+        }
+    }
+}
+
+/// Build a transaction lock capacity into several timelocked tranches, each
+/// tranche is a cell that can only be spent after its epoch.
+#[derive(Debug, Clone)]
+pub struct VestingBuilder {
+    pub lock: VestingLock,
+    pub tranches: Vec<VestingTranche>,
+}
+
+impl VestingBuilder {
+    pub fn new(lock: VestingLock, tranches: Vec<VestingTranche>) -> VestingBuilder {
+        VestingBuilder { lock, tranches }
+    }
+
+    /// Split `total_capacity` evenly into `count` tranches, one tranche every
+    /// `interval` epochs starting from `start_epoch`. The remainder is added
+    /// to the last tranche.
+    pub fn new_linear(
+        lock: VestingLock,
+        total_capacity: u64,
+        start_epoch: u64,
+        interval: u64,
+        count: u64,
+    ) -> Result<VestingBuilder, TxBuilderError> {
+        if count == 0 {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "vesting tranche count can not be 0"
+            )));
+        }
+        let per_tranche = total_capacity / count;
+        let tranches = (0..count)
+            .map(|idx| {
+                let capacity = if idx + 1 == count {
+                    total_capacity - per_tranche * (count - 1)
+                } else {
+                    per_tranche
+                };
+                let epoch_number = interval
+                    .checked_mul(idx)
+                    .and_then(|offset| start_epoch.checked_add(offset))
+                    .ok_or_else(|| {
+                        TxBuilderError::InvalidParameter(anyhow!(
+                            "vesting tranche {} epoch overflow, start: {}, interval: {}",
+                            idx,
+                            start_epoch,
+                            interval
+                        ))
+                    })?;
+                Ok(VestingTranche::new(epoch_number, capacity))
+            })
+            .collect::<Result<Vec<_>, TxBuilderError>>()?;
+        Ok(VestingBuilder { lock, tranches })
+    }
+}
+
+impl TxBuilder for VestingBuilder {
+    fn build_base(
+        &self,
+        _cell_collector: &mut dyn CellCollector,
+        _cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        if self.tranches.is_empty() {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "empty vesting tranches"
+            )));
+        }
+        let mut outputs = Vec::new();
+        let mut outputs_data = Vec::new();
+        for tranche in &self.tranches {
+            let lock_script = self.lock.lock_script(tranche.epoch_number);
+            let output = CellOutput::new_builder()
+                .capacity(tranche.capacity.pack())
+                .lock(lock_script)
+                .build();
+            outputs.push(output);
+            outputs_data.push(Bytes::new().pack());
+        }
+        Ok(TransactionBuilder::default()
+            .set_outputs(outputs)
+            .set_outputs_data(outputs_data)
+            .build())
+    }
+}
+
+/// Build a transaction sweep all matured tranches to the receiver.
+#[derive(Debug, Clone)]
+pub struct VestingClaimBuilder {
+    pub lock: VestingLock,
+    /// The unlock epoch numbers of the tranches to claim
+    pub epoch_numbers: Vec<u64>,
+    /// Current epoch, tranches not matured at this epoch will be ignored
+    pub current_epoch: EpochNumberWithFraction,
+    /// The receiver of the claimed capacity
+    pub receiver: Script,
+}
+
+impl VestingClaimBuilder {
+    pub fn new(
+        lock: VestingLock,
+        epoch_numbers: Vec<u64>,
+        current_epoch: EpochNumberWithFraction,
+        receiver: Script,
+    ) -> VestingClaimBuilder {
+        VestingClaimBuilder {
+            lock,
+            epoch_numbers,
+            current_epoch,
+            receiver,
+        }
+    }
+
+    /// The epoch numbers of matured tranches
+    pub fn matured_epochs(&self) -> Vec<u64> {
+        let mut epochs: Vec<_> = self
+            .epoch_numbers
+            .iter()
+            .copied()
+            .filter(|epoch_number| *epoch_number <= self.current_epoch.number())
+            .collect();
+        epochs.sort_unstable();
+        epochs.dedup();
+        epochs
+    }
+}
+
+impl TxBuilder for VestingClaimBuilder {
+    fn build_base(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        let matured_epochs = self.matured_epochs();
+        if matured_epochs.is_empty() {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "no matured vesting tranche at epoch {}",
+                self.current_epoch
+            )));
+        }
+        #[allow(clippy::mutable_key_type)]
+        let mut cell_deps = HashSet::new();
+        let mut inputs = Vec::new();
+        let mut input_total = 0;
+        for epoch_number in matured_epochs {
+            let lock_script = self.lock.lock_script(epoch_number);
+            let mut query = CellQueryOptions::new_lock(lock_script.clone());
+            query.secondary_script_len_range = Some(ValueRangeOption::new_exact(0));
+            query.data_len_range = Some(ValueRangeOption::new_exact(0));
+            query.min_total_capacity = u64::MAX;
+            let (cells, capacity) = cell_collector.collect_live_cells(&query, true)?;
+            if cells.is_empty() {
+                continue;
+            }
+            let cell_dep = cell_dep_resolver
+                .resolve(&lock_script)
+                .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(lock_script.clone()))?;
+            cell_deps.insert(cell_dep);
+            let since = Since::new_absolute_epoch(epoch_number).value();
+            inputs.extend(
+                cells
+                    .into_iter()
+                    .map(|cell| CellInput::new(cell.out_point, since)),
+            );
+            input_total += capacity;
+        }
+        if inputs.is_empty() {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "no live cell found in matured vesting tranches"
+            )));
+        }
+        let output = CellOutput::new_builder()
+            .capacity(input_total.pack())
+            .lock(self.receiver.clone())
+            .build();
+        Ok(TransactionBuilder::default()
+            .set_cell_deps(cell_deps.into_iter().collect())
+            .set_inputs(inputs)
+            .set_outputs(vec![output])
+            .set_outputs_data(vec![Bytes::new().pack()])
+            .build())
+    }
+}
+
+impl From<MultisigConfig> for VestingLock {
+    fn from(cfg: MultisigConfig) -> VestingLock {
+        VestingLock::Multisig(cfg)
+    }
+}
@@ -20,6 +20,7 @@ use crate::constants::{
 pub use old_addr::{Address as OldAddress, AddressFormat as OldAddressFormat};
 
 #[derive(Hash, Eq, PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[repr(u8)]
 pub enum AddressType {
     // full version identifies the hash_type and vm_version
@@ -45,6 +46,7 @@ impl AddressType {
 }
 
 #[derive(Hash, Eq, PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[repr(u8)]
 pub enum CodeHashIndex {
     /// SECP256K1 + blake160, args: `blake160(PK)`
@@ -0,0 +1,319 @@
+//! BIP-32 key derivation for per-invoice addresses.
+//!
+//! The merchant server keeps only the account extended public key and
+//! derives a fresh sighash address for every invoice by non-hardened
+//! derivation, while the signer derives the same child keys from the account
+//! extended private key, so all the invoice cells can be swept with one key
+//! tree.
+use std::ops::Range;
+
+use hmac::{Hmac, Mac};
+use secp256k1::{PublicKey, Scalar, SecretKey};
+use sha2::Sha512;
+use thiserror::Error;
+
+use ckb_types::H160;
+
+use crate::util::blake160;
+use crate::SECP256K1;
+
+/// The index bit of hardened derivation
+pub const HARDENED_BIT: u32 = 0x8000_0000;
+/// The coin type of CKB registered in SLIP-44
+pub const CKB_COIN_TYPE: u32 = 309;
+/// The external (receiving) chain of an account
+pub const EXTERNAL_CHAIN: u32 = 0;
+/// The internal (change) chain of an account
+pub const CHANGE_CHAIN: u32 = 1;
+
+type HmacSha512 = Hmac<Sha512>;
+
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+pub enum Bip32Error {
+    #[error("invalid seed length: `{0}`")]
+    InvalidSeed(usize),
+
+    #[error("hardened derivation from public key, index: `{0}`")]
+    HardenedFromPublic(u32),
+
+    #[error("invalid child key at index `{0}`, try the next index")]
+    InvalidChildKey(u32),
+
+    #[error("invalid derivation path: `{0}`")]
+    InvalidPath(String),
+
+    #[error("invalid extended key: `{0}`")]
+    InvalidExtendedKey(String),
+}
+
+/// Parse a derivation path like `m/44'/309'/0'/0/1`, both `'` and `h` mark
+/// a hardened index.
+pub fn parse_derivation_path(path: &str) -> Result<Vec<u32>, Bip32Error> {
+    let mut parts = path.split('/');
+    if parts.next() != Some("m") {
+        return Err(Bip32Error::InvalidPath(path.to_string()));
+    }
+    parts
+        .map(|part| {
+            let (number, hardened) = match part.strip_suffix('\'').or(part.strip_suffix('h')) {
+                Some(number) => (number, true),
+                None => (part, false),
+            };
+            let index: u32 = number
+                .parse()
+                .map_err(|_| Bip32Error::InvalidPath(path.to_string()))?;
+            if index & HARDENED_BIT != 0 {
+                return Err(Bip32Error::InvalidPath(path.to_string()));
+            }
+            Ok(if hardened {
+                index | HARDENED_BIT
+            } else {
+                index
+            })
+        })
+        .collect()
+}
+
+/// The account path `m/44'/309'/{account}'`
+pub fn account_path(account: u32) -> Vec<u32> {
+    vec![
+        44 | HARDENED_BIT,
+        CKB_COIN_TYPE | HARDENED_BIT,
+        account | HARDENED_BIT,
+    ]
+}
+
+fn hmac_sha512(key: &[u8], data: &[u8]) -> ([u8; 32], [u8; 32]) {
+    let mut mac = HmacSha512::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    let result = mac.finalize().into_bytes();
+    let mut left = [0u8; 32];
+    let mut right = [0u8; 32];
+    left.copy_from_slice(&result[0..32]);
+    right.copy_from_slice(&result[32..64]);
+    (left, right)
+}
+
+/// An extended private key
+#[derive(Clone, Eq, PartialEq)]
+pub struct ExtendedPrivKey {
+    pub private_key: SecretKey,
+    pub chain_code: [u8; 32],
+}
+
+impl std::fmt::Debug for ExtendedPrivKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ExtendedPrivKey")
+            .field("private_key", &"<hidden>")
+            .finish()
+    }
+}
+
+impl ExtendedPrivKey {
+    /// Create the master key from a seed (16 to 64 bytes)
+    pub fn new_master(seed: &[u8]) -> Result<ExtendedPrivKey, Bip32Error> {
+        if seed.len() < 16 || seed.len() > 64 {
+            return Err(Bip32Error::InvalidSeed(seed.len()));
+        }
+        let (key, chain_code) = hmac_sha512(b"Bitcoin seed", seed);
+        let private_key =
+            SecretKey::from_slice(&key).map_err(|_| Bip32Error::InvalidSeed(seed.len()))?;
+        Ok(ExtendedPrivKey {
+            private_key,
+            chain_code,
+        })
+    }
+
+    pub fn derive_child(&self, index: u32) -> Result<ExtendedPrivKey, Bip32Error> {
+        let mut data = Vec::with_capacity(37);
+        if index & HARDENED_BIT != 0 {
+            data.push(0);
+            data.extend_from_slice(&self.private_key.secret_bytes());
+        } else {
+            data.extend_from_slice(&self.public_key().serialize());
+        }
+        data.extend_from_slice(&index.to_be_bytes());
+        let (tweak, chain_code) = hmac_sha512(&self.chain_code, &data);
+        let tweak = Scalar::from_be_bytes(tweak).map_err(|_| Bip32Error::InvalidChildKey(index))?;
+        let private_key = self
+            .private_key
+            .add_tweak(&tweak)
+            .map_err(|_| Bip32Error::InvalidChildKey(index))?;
+        Ok(ExtendedPrivKey {
+            private_key,
+            chain_code,
+        })
+    }
+
+    pub fn derive_path(&self, path: &[u32]) -> Result<ExtendedPrivKey, Bip32Error> {
+        path.iter()
+            .try_fold(self.clone(), |key, index| key.derive_child(*index))
+    }
+
+    /// Derive the private key of the invoice: `{account}/0/{index}`
+    pub fn derive_invoice_key(&self, index: u32) -> Result<SecretKey, Bip32Error> {
+        Ok(self.derive_path(&[EXTERNAL_CHAIN, index])?.private_key)
+    }
+
+    /// Derive the private keys of the invoices in the range, e.g. to build
+    /// a [`SecpCkbRawKeySigner`](crate::traits::SecpCkbRawKeySigner) to sweep
+    /// the invoice cells.
+    pub fn derive_invoice_keys(&self, indices: Range<u32>) -> Result<Vec<SecretKey>, Bip32Error> {
+        let chain = self.derive_child(EXTERNAL_CHAIN)?;
+        indices
+            .map(|index| Ok(chain.derive_child(index)?.private_key))
+            .collect()
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey::from_secret_key(&SECP256K1, &self.private_key)
+    }
+
+    pub fn to_extended_pubkey(&self) -> ExtendedPubKey {
+        ExtendedPubKey {
+            public_key: self.public_key(),
+            chain_code: self.chain_code,
+        }
+    }
+}
+
+/// An extended public key, only non-hardened children can be derived
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct ExtendedPubKey {
+    pub public_key: PublicKey,
+    pub chain_code: [u8; 32],
+}
+
+impl ExtendedPubKey {
+    pub fn derive_child(&self, index: u32) -> Result<ExtendedPubKey, Bip32Error> {
+        if index & HARDENED_BIT != 0 {
+            return Err(Bip32Error::HardenedFromPublic(index));
+        }
+        let mut data = Vec::with_capacity(37);
+        data.extend_from_slice(&self.public_key.serialize());
+        data.extend_from_slice(&index.to_be_bytes());
+        let (tweak, chain_code) = hmac_sha512(&self.chain_code, &data);
+        let tweak = Scalar::from_be_bytes(tweak).map_err(|_| Bip32Error::InvalidChildKey(index))?;
+        let public_key = self
+            .public_key
+            .add_exp_tweak(&SECP256K1, &tweak)
+            .map_err(|_| Bip32Error::InvalidChildKey(index))?;
+        Ok(ExtendedPubKey {
+            public_key,
+            chain_code,
+        })
+    }
+
+    pub fn derive_path(&self, path: &[u32]) -> Result<ExtendedPubKey, Bip32Error> {
+        path.iter()
+            .try_fold(*self, |key, index| key.derive_child(*index))
+    }
+
+    /// Derive the public key of the invoice: `{account}/0/{index}`
+    pub fn derive_invoice_pubkey(&self, index: u32) -> Result<PublicKey, Bip32Error> {
+        Ok(self.derive_path(&[EXTERNAL_CHAIN, index])?.public_key)
+    }
+
+    /// Derive the sighash lock args of the invoice: `{account}/0/{index}`
+    pub fn derive_invoice_lock_arg(&self, index: u32) -> Result<H160, Bip32Error> {
+        let public_key = self.derive_invoice_pubkey(index)?;
+        Ok(blake160(&public_key.serialize()))
+    }
+
+    /// Serialize to `chain_code (32 bytes) || compressed public key (33 bytes)`
+    pub fn to_bytes(&self) -> [u8; 65] {
+        let mut bytes = [0u8; 65];
+        bytes[0..32].copy_from_slice(&self.chain_code);
+        bytes[32..65].copy_from_slice(&self.public_key.serialize());
+        bytes
+    }
+
+    pub fn from_slice(data: &[u8]) -> Result<ExtendedPubKey, Bip32Error> {
+        if data.len() != 65 {
+            return Err(Bip32Error::InvalidExtendedKey(format!(
+                "invalid length: {}",
+                data.len()
+            )));
+        }
+        let public_key = PublicKey::from_slice(&data[32..65])
+            .map_err(|err| Bip32Error::InvalidExtendedKey(err.to_string()))?;
+        let mut chain_code = [0u8; 32];
+        chain_code.copy_from_slice(&data[0..32]);
+        Ok(ExtendedPubKey {
+            public_key,
+            chain_code,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::h256;
+
+    #[test]
+    fn test_bip32_vector1() {
+        // BIP-32 test vector 1
+        let seed = [
+            0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d,
+            0x0e, 0x0f,
+        ];
+        let master = ExtendedPrivKey::new_master(&seed).unwrap();
+        assert_eq!(
+            master.private_key.secret_bytes(),
+            h256!("0xe8f32e723decf4051aefac8e2c93c9c5b214313817cdb01a1494b917c8436b35").0
+        );
+        assert_eq!(
+            master.chain_code,
+            h256!("0x873dff81c02f525623fd1fe5167eac3a55a049de3d314bb42ee227ffed37d508").0
+        );
+        let path = parse_derivation_path("m/0'/1").unwrap();
+        assert_eq!(path, vec![HARDENED_BIT, 1]);
+        let child = master.derive_path(&path).unwrap();
+        assert_eq!(
+            child.private_key.secret_bytes(),
+            h256!("0x3c6cb8d0f6a264c91ea8b5030fadaa8e538b020f0a387421a12de9319dc93368").0
+        );
+        assert_eq!(
+            child.chain_code,
+            h256!("0x2a7857631386ba23dacac34180dd1983734e444fdbf774041578e9b6adb37c19").0
+        );
+
+        // the non-hardened child can be derived from the public key
+        let account = master.derive_child(HARDENED_BIT).unwrap();
+        let xpub = ExtendedPubKey::from_slice(&account.to_extended_pubkey().to_bytes()).unwrap();
+        assert_eq!(xpub.derive_child(1).unwrap(), child.to_extended_pubkey());
+        assert_eq!(
+            xpub.derive_child(HARDENED_BIT),
+            Err(Bip32Error::HardenedFromPublic(HARDENED_BIT))
+        );
+    }
+
+    #[test]
+    fn test_invoice_keys() {
+        let master = ExtendedPrivKey::new_master(&[7u8; 32]).unwrap();
+        let account = master.derive_path(&account_path(0)).unwrap();
+        let xpub = account.to_extended_pubkey();
+        for index in 0..3 {
+            let key = account.derive_invoice_key(index).unwrap();
+            let pubkey = PublicKey::from_secret_key(&SECP256K1, &key);
+            assert_eq!(
+                xpub.derive_invoice_lock_arg(index).unwrap(),
+                blake160(&pubkey.serialize())
+            );
+        }
+        assert_eq!(
+            account.derive_invoice_keys(1..3).unwrap(),
+            vec![
+                account.derive_invoice_key(1).unwrap(),
+                account.derive_invoice_key(2).unwrap()
+            ]
+        );
+        assert_ne!(
+            xpub.derive_invoice_lock_arg(0).unwrap(),
+            xpub.derive_invoice_lock_arg(1).unwrap()
+        );
+        assert!(parse_derivation_path("44'/309'").is_err());
+        assert!(parse_derivation_path("m/2147483648").is_err());
+    }
+}
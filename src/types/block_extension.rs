@@ -0,0 +1,102 @@
+use std::convert::TryFrom;
+
+use ckb_types::{bytes::Bytes, core::BlockView, packed, prelude::*};
+
+/// The max size of the block extension field.
+pub const MAX_BLOCK_EXTENSION_SIZE: usize = 96;
+const CHAIN_ROOT_SIZE: usize = 32;
+
+/// The block extension field introduced by ckb2021 hardfork.
+///
+/// Known contents:
+///   * `extension[0..32]`: the chain root, the root hash of the header MMR of
+///     all ancestor blocks (see RFC0044 CKB light client).
+///   * `extension[32..]`: reserved.
+#[derive(Clone, Hash, Eq, PartialEq, Debug)]
+pub struct BlockExtension {
+    raw: Bytes,
+}
+
+impl BlockExtension {
+    pub fn new(raw: Bytes) -> Result<BlockExtension, String> {
+        if raw.is_empty() || raw.len() > MAX_BLOCK_EXTENSION_SIZE {
+            return Err(format!(
+                "invalid block extension length, expected: 1..={}, got: {}",
+                MAX_BLOCK_EXTENSION_SIZE,
+                raw.len()
+            ));
+        }
+        Ok(BlockExtension { raw })
+    }
+
+    /// Extract the extension from a block, return `None` if the block has no
+    /// extension.
+    pub fn from_block(block: &BlockView) -> Result<Option<BlockExtension>, String> {
+        block.extension().map(BlockExtension::try_from).transpose()
+    }
+
+    /// The raw extension data
+    pub fn raw(&self) -> &Bytes {
+        &self.raw
+    }
+
+    /// The chain root (MMR root of all ancestor block headers), return `None`
+    /// if the extension is too short to contain one.
+    pub fn chain_root(&self) -> Option<packed::Byte32> {
+        if self.raw.len() < CHAIN_ROOT_SIZE {
+            return None;
+        }
+        Some(packed::Byte32::from_slice(&self.raw[0..CHAIN_ROOT_SIZE]).expect("chain root"))
+    }
+
+    /// The bytes after the chain root
+    pub fn reserved(&self) -> Bytes {
+        if self.raw.len() <= CHAIN_ROOT_SIZE {
+            Bytes::new()
+        } else {
+            self.raw.slice(CHAIN_ROOT_SIZE..)
+        }
+    }
+}
+
+impl TryFrom<packed::Bytes> for BlockExtension {
+    type Error = String;
+    fn try_from(extension: packed::Bytes) -> Result<Self, Self::Error> {
+        BlockExtension::new(extension.raw_data())
+    }
+}
+
+impl From<BlockExtension> for packed::Bytes {
+    fn from(extension: BlockExtension) -> packed::Bytes {
+        extension.raw.pack()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::core::BlockBuilder;
+
+    #[test]
+    fn test_block_extension() {
+        let mut data = vec![7u8; CHAIN_ROOT_SIZE];
+        data.extend_from_slice(&[1, 2, 3]);
+        let block = BlockBuilder::default()
+            .extension(Some(Bytes::from(data.clone()).pack()))
+            .build();
+        let extension = BlockExtension::from_block(&block).unwrap().unwrap();
+        assert_eq!(extension.raw().as_ref(), &data[..]);
+        assert_eq!(extension.chain_root().unwrap().as_slice(), &[7u8; 32][..]);
+        assert_eq!(extension.reserved(), Bytes::from(vec![1, 2, 3]));
+
+        let short = BlockExtension::new(Bytes::from(vec![1u8; 8])).unwrap();
+        assert!(short.chain_root().is_none());
+        assert!(short.reserved().is_empty());
+
+        assert!(BlockExtension::new(Bytes::new()).is_err());
+        assert!(BlockExtension::new(Bytes::from(vec![0u8; MAX_BLOCK_EXTENSION_SIZE + 1])).is_err());
+        assert!(BlockExtension::from_block(&BlockBuilder::default().build())
+            .unwrap()
+            .is_none());
+    }
+}
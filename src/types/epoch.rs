@@ -0,0 +1,307 @@
+use ckb_jsonrpc_types as json_types;
+use ckb_types::{
+    core::{BlockNumber, EpochNumber, EpochNumberWithFraction},
+    utilities, U256,
+};
+
+use crate::rpc::{CkbRpcClient, RpcError};
+
+/// The target duration of an epoch (4 hours, in milliseconds)
+pub const EPOCH_DURATION_TARGET: u64 = 4 * 60 * 60 * 1000;
+
+/// The primary issuance halves every 4 years (8760 epochs)
+pub const HALVING_INTERVAL: EpochNumber = 4 * 365 * 24 * 60 * 60 * 1000 / EPOCH_DURATION_TARGET;
+
+/// The primary issuance of an epoch before the first halving (in shannons)
+pub const INITIAL_PRIMARY_EPOCH_REWARD: u64 = 191_780_821_917_808;
+
+/// Convert the compact target to difficulty
+pub fn compact_to_difficulty(compact_target: u32) -> U256 {
+    utilities::compact_to_difficulty(compact_target)
+}
+
+/// Convert the difficulty to compact target
+pub fn difficulty_to_compact(difficulty: U256) -> u32 {
+    utilities::difficulty_to_compact(difficulty)
+}
+
+/// Convert the compact target to target, return `None` if overflow
+pub fn compact_to_target(compact_target: u32) -> Option<U256> {
+    let (target, overflow) = utilities::compact_to_target(compact_target);
+    if overflow {
+        None
+    } else {
+        Some(target)
+    }
+}
+
+/// Convert the target to compact target
+pub fn target_to_compact(target: U256) -> u32 {
+    utilities::target_to_compact(target)
+}
+
+/// Estimate the network hash rate (hashes per second) from the difficulty and
+/// the average block interval (in milliseconds).
+///
+/// The difficulty is the expected number of hashes to find a block.
+pub fn estimate_hash_rate(difficulty: &U256, block_interval_ms: u64) -> U256 {
+    if block_interval_ms == 0 {
+        return U256::zero();
+    }
+    difficulty * U256::from(1000u64) / U256::from(block_interval_ms)
+}
+
+/// The epoch information, can be converted from the `get_epoch_by_number`
+/// and `get_current_epoch` rpc result.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct EpochInfo {
+    pub number: EpochNumber,
+    pub start_number: BlockNumber,
+    pub length: BlockNumber,
+    pub compact_target: u32,
+}
+
+impl EpochInfo {
+    /// The difficulty of every block in the epoch
+    pub fn difficulty(&self) -> U256 {
+        compact_to_difficulty(self.compact_target)
+    }
+
+    /// The total difficulty of the epoch
+    pub fn total_difficulty(&self) -> U256 {
+        self.difficulty() * U256::from(self.length)
+    }
+
+    /// The first block number of next epoch
+    pub fn end_number(&self) -> BlockNumber {
+        self.start_number + self.length
+    }
+
+    pub fn contains(&self, block_number: BlockNumber) -> bool {
+        block_number >= self.start_number && block_number < self.end_number()
+    }
+
+    /// The epoch of the block, return `None` if the block is not in this epoch
+    pub fn epoch_of(&self, block_number: BlockNumber) -> Option<EpochNumberWithFraction> {
+        if !self.contains(block_number) {
+            return None;
+        }
+        Some(EpochNumberWithFraction::new(
+            self.number,
+            block_number - self.start_number,
+            self.length,
+        ))
+    }
+
+    /// The block number at the epoch fraction, return `None` if it is not in
+    /// this epoch
+    pub fn block_number_of(&self, epoch: EpochNumberWithFraction) -> Option<BlockNumber> {
+        if epoch.number() != self.number || epoch.index() >= self.length {
+            return None;
+        }
+        Some(self.start_number + epoch.index())
+    }
+
+    /// Estimate the hash rate of the epoch from its actual duration (in
+    /// milliseconds), use [`EPOCH_DURATION_TARGET`] for an expected value.
+    pub fn estimate_hash_rate(&self, duration_ms: u64) -> U256 {
+        if duration_ms == 0 {
+            return U256::zero();
+        }
+        self.total_difficulty() * U256::from(1000u64) / U256::from(duration_ms)
+    }
+}
+
+/// The number of halvings happened at the epoch
+pub fn halving_count(epoch: EpochNumber) -> u64 {
+    epoch / HALVING_INTERVAL
+}
+
+/// The first epoch of the next halving after the epoch
+pub fn next_halving_epoch(epoch: EpochNumber) -> EpochNumber {
+    (halving_count(epoch) + 1) * HALVING_INTERVAL
+}
+
+/// The primary issuance of the epoch (in shannons)
+pub fn primary_epoch_reward(epoch: EpochNumber) -> u64 {
+    INITIAL_PRIMARY_EPOCH_REWARD
+        .checked_shr(halving_count(epoch) as u32)
+        .unwrap_or(0)
+}
+
+/// Estimate the timestamp (in milliseconds) of the `target` epoch from a known
+/// epoch and its timestamp (e.g. the tip header), every epoch is assumed to
+/// last [`EPOCH_DURATION_TARGET`]. Useful to show the DAO unlock time.
+pub fn estimate_epoch_timestamp(
+    target: EpochNumberWithFraction,
+    known: EpochNumberWithFraction,
+    known_timestamp: u64,
+) -> u64 {
+    let offset = |epoch: EpochNumberWithFraction| -> i128 {
+        let duration = i128::from(EPOCH_DURATION_TARGET);
+        let mut offset = i128::from(epoch.number()) * duration;
+        if epoch.length() > 0 {
+            offset += i128::from(epoch.index()) * duration / i128::from(epoch.length());
+        }
+        offset
+    };
+    let timestamp = i128::from(known_timestamp) + offset(target) - offset(known);
+    timestamp.clamp(0, i128::from(u64::MAX)) as u64
+}
+
+/// Iterate the epochs from `start`, the epochs are fetched one by one by the
+/// fetcher, the iteration stops at the first epoch not found (e.g. beyond the
+/// current epoch) or after an error.
+pub struct EpochIter<'a, E> {
+    fetch: Box<dyn FnMut(EpochNumber) -> Result<Option<EpochInfo>, E> + 'a>,
+    next: EpochNumber,
+    end: EpochNumber,
+    done: bool,
+}
+
+impl<'a, E> EpochIter<'a, E> {
+    pub fn new<F>(start: EpochNumber, fetch: F) -> EpochIter<'a, E>
+    where
+        F: FnMut(EpochNumber) -> Result<Option<EpochInfo>, E> + 'a,
+    {
+        EpochIter {
+            fetch: Box::new(fetch),
+            next: start,
+            end: EpochNumber::MAX,
+            done: false,
+        }
+    }
+
+    /// Stop before the `end` epoch
+    pub fn until(mut self, end: EpochNumber) -> EpochIter<'a, E> {
+        self.end = end;
+        self
+    }
+}
+
+impl<'a> EpochIter<'a, RpcError> {
+    /// Fetch the epochs by `get_epoch_by_number` rpc
+    pub fn from_rpc(client: &'a CkbRpcClient, start: EpochNumber) -> EpochIter<'a, RpcError> {
+        EpochIter::new(start, move |number| {
+            Ok(client
+                .get_epoch_by_number(number.into())?
+                .map(EpochInfo::from))
+        })
+    }
+}
+
+impl<'a, E> Iterator for EpochIter<'a, E> {
+    type Item = Result<EpochInfo, E>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.next >= self.end {
+            return None;
+        }
+        match (self.fetch)(self.next) {
+            Ok(Some(epoch)) => {
+                self.next += 1;
+                Some(Ok(epoch))
+            }
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+impl From<json_types::EpochView> for EpochInfo {
+    fn from(epoch: json_types::EpochView) -> EpochInfo {
+        EpochInfo {
+            number: epoch.number.value(),
+            start_number: epoch.start_number.value(),
+            length: epoch.length.value(),
+            compact_target: epoch.compact_target.value(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_difficulty_conversion() {
+        let compact_target = 0x1d09_0fbc;
+        let difficulty = compact_to_difficulty(compact_target);
+        // a larger target is easier
+        assert!(compact_to_difficulty(0x1d0a_0fbc) < difficulty);
+        let target = compact_to_target(compact_target).unwrap();
+        assert_eq!(target_to_compact(target), compact_target);
+        assert_eq!(
+            estimate_hash_rate(&difficulty, 8000),
+            difficulty.clone() / U256::from(8u64)
+        );
+        assert_eq!(estimate_hash_rate(&difficulty, 0), U256::zero());
+    }
+
+    #[test]
+    fn test_epoch_info() {
+        let epoch = EpochInfo {
+            number: 10,
+            start_number: 10000,
+            length: 1800,
+            compact_target: 0x1d09_0fbc,
+        };
+        assert_eq!(epoch.end_number(), 11800);
+        assert!(epoch.contains(10000));
+        assert!(!epoch.contains(11800));
+        let fraction = epoch.epoch_of(10900).unwrap();
+        assert_eq!(fraction, EpochNumberWithFraction::new(10, 900, 1800));
+        assert_eq!(epoch.block_number_of(fraction), Some(10900));
+        assert!(epoch.epoch_of(9999).is_none());
+        assert_eq!(
+            epoch.estimate_hash_rate(EPOCH_DURATION_TARGET),
+            epoch.total_difficulty() / U256::from(EPOCH_DURATION_TARGET / 1000)
+        );
+    }
+
+    #[test]
+    fn test_halving() {
+        assert_eq!(HALVING_INTERVAL, 8760);
+        assert_eq!(next_halving_epoch(0), 8760);
+        assert_eq!(next_halving_epoch(8760), 17520);
+        assert_eq!(primary_epoch_reward(8759), INITIAL_PRIMARY_EPOCH_REWARD);
+        assert_eq!(primary_epoch_reward(8760), INITIAL_PRIMARY_EPOCH_REWARD / 2);
+        assert_eq!(primary_epoch_reward(8760 * 64), 0);
+    }
+
+    #[test]
+    fn test_epoch_iter_and_timestamp() {
+        let epochs: Vec<_> = EpochIter::new(2, |number| {
+            Ok::<_, ()>(if number < 5 {
+                Some(EpochInfo {
+                    number,
+                    start_number: number * 1000,
+                    length: 1000,
+                    compact_target: 0x1d09_0fbc,
+                })
+            } else {
+                None
+            })
+        })
+        .map(|epoch| epoch.unwrap().start_number)
+        .collect();
+        assert_eq!(epochs, vec![2000, 3000, 4000]);
+        let mut iter = EpochIter::new(0, |_| Err::<Option<EpochInfo>, _>("rpc error"));
+        assert_eq!(iter.next().map(|ret| ret.is_err()), Some(true));
+        assert!(iter.next().is_none());
+
+        let known = EpochNumberWithFraction::new(100, 0, 1800);
+        let target = EpochNumberWithFraction::new(280, 900, 1800);
+        assert_eq!(
+            estimate_epoch_timestamp(target, known, 1_000_000),
+            1_000_000 + 180 * EPOCH_DURATION_TARGET + EPOCH_DURATION_TARGET / 2
+        );
+        assert_eq!(estimate_epoch_timestamp(known, target, 0), 0);
+    }
+}
@@ -0,0 +1,221 @@
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+use ckb_types::core::FeeRate;
+
+use crate::constants::ONE_CKB;
+
+/// The units of the fee rates.
+///
+/// The balancer (and [`FeeRate`]) counts in shannons per 1000 weight, the
+/// weight of a transaction is its serialized size unless its cycles are too
+/// many, so shannons/kW and shannons/kB (e.g. `min_fee_rate` in the node
+/// config) are the same number. Explorers and wallets may show shannons per
+/// byte or CKB per kB instead, which are 1000x and 1/10^8x of it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum FeeRateUnit {
+    ShannonsPerKW,
+    ShannonsPerKB,
+    ShannonsPerByte,
+    CkbPerKB,
+}
+
+impl FeeRateUnit {
+    /// The shannons/kW of one unit
+    pub fn factor(self) -> u64 {
+        match self {
+            FeeRateUnit::ShannonsPerKW | FeeRateUnit::ShannonsPerKB => 1,
+            FeeRateUnit::ShannonsPerByte => 1000,
+            FeeRateUnit::CkbPerKB => ONE_CKB,
+        }
+    }
+
+    fn decimals(self) -> usize {
+        self.factor().to_string().len() - 1
+    }
+}
+
+impl FromStr for FeeRateUnit {
+    type Err = String;
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.trim().to_lowercase().as_str() {
+            "shannons/kw" | "shannon/kw" => Ok(FeeRateUnit::ShannonsPerKW),
+            "shannons/kb" | "shannon/kb" => Ok(FeeRateUnit::ShannonsPerKB),
+            "shannons/byte" | "shannon/byte" | "shannons/b" | "shannon/b" => {
+                Ok(FeeRateUnit::ShannonsPerByte)
+            }
+            "ckb/kb" => Ok(FeeRateUnit::CkbPerKB),
+            unit => Err(format!(
+                "unknown fee rate unit: {}, expected: shannons/kW, shannons/kB, shannons/byte or CKB/kB",
+                unit
+            )),
+        }
+    }
+}
+
+impl fmt::Display for FeeRateUnit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let unit = match self {
+            FeeRateUnit::ShannonsPerKW => "shannons/kW",
+            FeeRateUnit::ShannonsPerKB => "shannons/kB",
+            FeeRateUnit::ShannonsPerByte => "shannons/byte",
+            FeeRateUnit::CkbPerKB => "CKB/kB",
+        };
+        write!(f, "{}", unit)
+    }
+}
+
+/// A fee rate in shannons/kW (the unit of the balancer), parsed from and
+/// displayed with an explicit unit, see [`FeeRateUnit`].
+///
+/// The unit is required when parsing (e.g. `1000 shannons/kB`,
+/// `1.5 shannons/byte`), a bare number is rejected since its unit is
+/// ambiguous.
+#[derive(Default, Clone, Copy, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct HumanFeeRate(pub u64);
+
+impl HumanFeeRate {
+    /// Convert `value` in `unit`, return `None` on overflow
+    pub fn from_unit(value: u64, unit: FeeRateUnit) -> Option<HumanFeeRate> {
+        value.checked_mul(unit.factor()).map(HumanFeeRate)
+    }
+
+    /// The fee rate in `unit`, the decimals are kept
+    pub fn format_in(self, unit: FeeRateUnit) -> String {
+        let factor = unit.factor();
+        let integer_part = self.0 / factor;
+        let fraction_part = self.0 % factor;
+        if fraction_part == 0 {
+            format!("{} {}", integer_part, unit)
+        } else {
+            let fraction = format!("{:0>width$}", fraction_part, width = unit.decimals());
+            format!(
+                "{}.{} {}",
+                integer_part,
+                fraction.trim_end_matches('0'),
+                unit
+            )
+        }
+    }
+
+    pub fn fee_rate(self) -> FeeRate {
+        FeeRate::from_u64(self.0)
+    }
+}
+
+impl From<FeeRate> for HumanFeeRate {
+    fn from(value: FeeRate) -> HumanFeeRate {
+        HumanFeeRate(value.as_u64())
+    }
+}
+
+impl From<HumanFeeRate> for FeeRate {
+    fn from(value: HumanFeeRate) -> FeeRate {
+        value.fee_rate()
+    }
+}
+
+impl From<HumanFeeRate> for u64 {
+    fn from(value: HumanFeeRate) -> u64 {
+        value.0
+    }
+}
+
+impl Deref for HumanFeeRate {
+    type Target = u64;
+    fn deref(&self) -> &u64 {
+        &self.0
+    }
+}
+
+impl FromStr for HumanFeeRate {
+    type Err = String;
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let input = input.trim();
+        let split_at = input
+            .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '_'))
+            .ok_or_else(|| format!("missing fee rate unit: {}", input))?;
+        let (number, unit) = input.split_at(split_at);
+        let unit = FeeRateUnit::from_str(unit)?;
+        let number = number.replace('_', "");
+        let parts = number.split('.').collect::<Vec<_>>();
+        if parts.len() > 2 {
+            return Err(format!("invalid fee rate: {}", input));
+        }
+        let overflow = || format!("fee rate overflow: {}", input);
+        let mut fee_rate = parts[0]
+            .parse::<u64>()
+            .map_err(|err| err.to_string())?
+            .checked_mul(unit.factor())
+            .ok_or_else(overflow)?;
+        if let Some(fraction_str) = parts.get(1) {
+            if fraction_str.len() > unit.decimals() {
+                return Err(format!(
+                    "fee rate precision is 1 shannons/kW, got: {}",
+                    input
+                ));
+            }
+            let mut fraction = fraction_str.parse::<u64>().map_err(|err| err.to_string())?;
+            for _ in 0..(unit.decimals() - fraction_str.len()) {
+                fraction *= 10;
+            }
+            fee_rate = fee_rate.checked_add(fraction).ok_or_else(overflow)?;
+        }
+        Ok(HumanFeeRate(fee_rate))
+    }
+}
+
+impl fmt::Display for HumanFeeRate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}", self.format_in(FeeRateUnit::ShannonsPerKW))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_human_fee_rate() {
+        for (input, fee_rate) in &[
+            ("1000 shannons/kW", 1000),
+            ("1000 shannons/kB", 1000),
+            ("1_000 shannons/kB", 1000),
+            ("1 shannons/byte", 1000),
+            ("1.5 shannon/byte", 1500),
+            ("0.00001 CKB/kB", 1000),
+            ("2500shannons/KB", 2500),
+        ] {
+            assert_eq!(
+                HumanFeeRate::from_str(input).unwrap(),
+                HumanFeeRate(*fee_rate),
+                "{}",
+                input
+            );
+        }
+        assert!(HumanFeeRate::from_str("1000").is_err());
+        assert!(HumanFeeRate::from_str("1000 shannons").is_err());
+        assert!(HumanFeeRate::from_str("1.0001 shannons/byte").is_err());
+        assert!(HumanFeeRate::from_str("0.5 shannons/kB").is_err());
+        assert!(HumanFeeRate::from_str("1.2.3 shannons/byte").is_err());
+        assert!(HumanFeeRate::from_str("99999999999999999 CKB/kB").is_err());
+
+        let fee_rate = HumanFeeRate(1500);
+        assert_eq!(fee_rate.to_string(), "1500 shannons/kW");
+        assert_eq!(
+            fee_rate.format_in(FeeRateUnit::ShannonsPerByte),
+            "1.5 shannons/byte"
+        );
+        assert_eq!(fee_rate.format_in(FeeRateUnit::CkbPerKB), "0.000015 CKB/kB");
+        assert_eq!(
+            HumanFeeRate::from_str(&fee_rate.format_in(FeeRateUnit::CkbPerKB)).unwrap(),
+            fee_rate
+        );
+        assert_eq!(
+            HumanFeeRate::from_unit(2, FeeRateUnit::ShannonsPerByte),
+            Some(HumanFeeRate(2000))
+        );
+        assert_eq!(fee_rate.fee_rate(), FeeRate::from_u64(1500));
+    }
+}
@@ -0,0 +1,148 @@
+//! BIP-39 mnemonic backup of an HD wallet.
+//!
+//! A mnemonic of 12, 15, 18, 21 or 24 words encodes the entropy of the
+//! wallet, the seed is derived from the mnemonic and an optional passphrase,
+//! and the master key of [`bip32`](super::bip32) is derived from the seed.
+//! The word lists of all the BIP-39 languages are supported.
+use std::fmt;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+pub use bip39::Language;
+
+use super::bip32::{Bip32Error, ExtendedPrivKey};
+
+/// The valid word counts
+pub const MNEMONIC_WORD_COUNTS: [usize; 5] = [12, 15, 18, 21, 24];
+
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+pub enum MnemonicError {
+    #[error("invalid word count: `{0}`, expected: 12, 15, 18, 21 or 24")]
+    InvalidWordCount(usize),
+
+    #[error("invalid mnemonic: `{0}`")]
+    Invalid(#[from] bip39::Error),
+
+    #[error(transparent)]
+    Bip32(#[from] Bip32Error),
+}
+
+/// A BIP-39 mnemonic, the words are hidden in the `Debug` output
+#[derive(Clone, Eq, PartialEq)]
+pub struct Mnemonic(bip39::Mnemonic);
+
+impl fmt::Debug for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Mnemonic")
+            .field("language", &self.language())
+            .field("word_count", &self.word_count())
+            .finish()
+    }
+}
+
+impl Mnemonic {
+    /// Generate a new mnemonic from random entropy
+    pub fn generate(language: Language, word_count: usize) -> Result<Mnemonic, MnemonicError> {
+        if !MNEMONIC_WORD_COUNTS.contains(&word_count) {
+            return Err(MnemonicError::InvalidWordCount(word_count));
+        }
+        Ok(Mnemonic(bip39::Mnemonic::generate_in(
+            language, word_count,
+        )?))
+    }
+
+    /// Create the mnemonic of the entropy (16 to 32 bytes, a multiple of 4)
+    pub fn from_entropy(language: Language, entropy: &[u8]) -> Result<Mnemonic, MnemonicError> {
+        Ok(Mnemonic(bip39::Mnemonic::from_entropy_in(
+            language, entropy,
+        )?))
+    }
+
+    /// Import the mnemonic in the language, the checksum is verified
+    pub fn parse_in(language: Language, phrase: &str) -> Result<Mnemonic, MnemonicError> {
+        Ok(Mnemonic(bip39::Mnemonic::parse_in(language, phrase)?))
+    }
+
+    /// Import the mnemonic, the language is detected from the words
+    pub fn parse(phrase: &str) -> Result<Mnemonic, MnemonicError> {
+        Ok(Mnemonic(bip39::Mnemonic::parse(phrase)?))
+    }
+
+    pub fn language(&self) -> Language {
+        self.0.language()
+    }
+
+    pub fn word_count(&self) -> usize {
+        self.0.word_count()
+    }
+
+    /// The words separated by spaces, keep it secret
+    pub fn phrase(&self) -> String {
+        self.0.to_string()
+    }
+
+    /// Derive the 64 bytes seed with the passphrase (empty if not set)
+    pub fn to_seed(&self, passphrase: &str) -> [u8; 64] {
+        self.0.to_seed(passphrase)
+    }
+
+    /// Derive the BIP-32 master key with the passphrase
+    pub fn to_master_key(&self, passphrase: &str) -> Result<ExtendedPrivKey, MnemonicError> {
+        Ok(ExtendedPrivKey::new_master(&self.to_seed(passphrase))?)
+    }
+}
+
+impl FromStr for Mnemonic {
+    type Err = MnemonicError;
+    fn from_str(phrase: &str) -> Result<Self, Self::Err> {
+        Mnemonic::parse(phrase)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::bip32::account_path;
+
+    #[test]
+    fn test_mnemonic() {
+        // The test vector of BIP-39
+        let mnemonic = Mnemonic::from_entropy(Language::English, &[0u8; 16]).unwrap();
+        assert_eq!(
+            mnemonic.phrase(),
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about"
+        );
+        assert_eq!(
+            hex_string(&mnemonic.to_seed("TREZOR")),
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04"
+        );
+        assert_eq!(Mnemonic::from_str(&mnemonic.phrase()).unwrap(), mnemonic);
+        assert!(!format!("{:?}", mnemonic).contains("abandon"));
+
+        for word_count in [12, 15, 24] {
+            for language in [Language::English, Language::SimplifiedChinese] {
+                let mnemonic = Mnemonic::generate(language, word_count).unwrap();
+                assert_eq!(mnemonic.word_count(), word_count);
+                let imported = Mnemonic::parse_in(language, &mnemonic.phrase()).unwrap();
+                assert_eq!(imported, mnemonic);
+                assert_eq!(imported.language(), language);
+            }
+        }
+        assert_eq!(
+            Mnemonic::generate(Language::English, 13),
+            Err(MnemonicError::InvalidWordCount(13))
+        );
+        let phrase = mnemonic.phrase().replace("about", "abandon");
+        assert!(Mnemonic::parse(&phrase).is_err());
+
+        // The passphrase changes the keys
+        let master_key = mnemonic.to_master_key("").unwrap();
+        assert_ne!(master_key, mnemonic.to_master_key("TREZOR").unwrap());
+        assert!(master_key.derive_path(&account_path(0)).is_ok());
+    }
+
+    fn hex_string(data: &[u8]) -> String {
+        data.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
@@ -1,12 +1,20 @@
 //! Basic ckb sdk types
 mod address;
+pub mod bip32;
+mod block_extension;
+mod epoch;
 mod human_capacity;
+mod human_fee_rate;
+#[cfg(feature = "keystore")]
+pub mod mnemonic;
 mod network_type;
 #[allow(clippy::all)]
 pub mod omni_lock;
+mod rent;
 mod script_group;
 mod script_id;
 mod since;
+mod stable;
 pub mod transaction_with_groups;
 #[allow(clippy::all)]
 pub mod xudt_rce_mol;
@@ -14,9 +22,20 @@ pub mod xudt_rce_mol;
 pub use address::{
     Address, AddressPayload, AddressType, CodeHashIndex, OldAddress, OldAddressFormat,
 };
+pub use block_extension::{BlockExtension, MAX_BLOCK_EXTENSION_SIZE};
+pub use epoch::{
+    compact_to_difficulty, compact_to_target, difficulty_to_compact, estimate_epoch_timestamp,
+    estimate_hash_rate, halving_count, next_halving_epoch, primary_epoch_reward, target_to_compact,
+    EpochInfo, EpochIter, EPOCH_DURATION_TARGET, HALVING_INTERVAL, INITIAL_PRIMARY_EPOCH_REWARD,
+};
 pub use human_capacity::HumanCapacity;
+pub use human_fee_rate::{FeeRateUnit, HumanFeeRate};
 pub use network_type::{NetworkInfo, NetworkType};
+pub use rent::{CellSchema, RentForecast, RentPlanItem};
 pub use script_group::{ScriptGroup, ScriptGroupType};
 pub use script_id::ScriptId;
 pub use since::{Since, SinceType};
+pub use stable::{
+    StableConversionError, StableHashType, StableOutPoint, StableScript, StableTransaction,
+};
 pub use transaction_with_groups::TransactionWithScriptGroups;
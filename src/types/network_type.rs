@@ -8,6 +8,7 @@ use crate::constants::{
 };
 
 #[derive(Hash, Eq, PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum NetworkType {
     Mainnet,
     Testnet,
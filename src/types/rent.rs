@@ -0,0 +1,209 @@
+//! Forecast the CKB locked by planned cells.
+//!
+//! Every cell must hold at least its occupied capacity (1 CKB per byte of
+//! capacity field, lock script, type script and data), this is the "rent" a
+//! dApp pays for its on-chain state. The forecast reports the total CKB to lock
+//! for a planned data layout, and how it changes when the layout is tweaked.
+use std::convert::TryFrom;
+use std::fmt;
+
+use ckb_types::{bytes::Bytes, packed::CellOutput, prelude::*};
+
+use crate::constants::ONE_CKB;
+use crate::types::HumanCapacity;
+
+/// The size of the capacity field
+const CAPACITY_FIELD_SIZE: u64 = 8;
+/// The size of code_hash + hash_type of a script
+const SCRIPT_BASE_SIZE: u64 = 32 + 1;
+
+/// The layout of a planned cell
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct CellSchema {
+    /// The lock script args length
+    pub lock_args_len: usize,
+    /// The type script args length, `None` if no type script
+    pub type_args_len: Option<usize>,
+    /// The cell data length
+    pub data_len: usize,
+}
+
+impl CellSchema {
+    pub fn new(lock_args_len: usize, type_args_len: Option<usize>, data_len: usize) -> CellSchema {
+        CellSchema {
+            lock_args_len,
+            type_args_len,
+            data_len,
+        }
+    }
+
+    /// The layout of an existing cell
+    pub fn from_output(output: &CellOutput, output_data: &Bytes) -> CellSchema {
+        CellSchema {
+            lock_args_len: output.lock().args().raw_data().len(),
+            type_args_len: output
+                .type_()
+                .to_opt()
+                .map(|type_script| type_script.args().raw_data().len()),
+            data_len: output_data.len(),
+        }
+    }
+
+    pub fn with_lock_args_len(mut self, lock_args_len: usize) -> CellSchema {
+        self.lock_args_len = lock_args_len;
+        self
+    }
+
+    pub fn with_type_args_len(mut self, type_args_len: Option<usize>) -> CellSchema {
+        self.type_args_len = type_args_len;
+        self
+    }
+
+    pub fn with_data_len(mut self, data_len: usize) -> CellSchema {
+        self.data_len = data_len;
+        self
+    }
+
+    /// The occupied bytes of the cell
+    pub fn occupied_bytes(&self) -> u64 {
+        CAPACITY_FIELD_SIZE
+            + SCRIPT_BASE_SIZE
+            + self.lock_args_len as u64
+            + self
+                .type_args_len
+                .map(|len| SCRIPT_BASE_SIZE + len as u64)
+                .unwrap_or_default()
+            + self.data_len as u64
+    }
+
+    /// The minimal capacity (in shannons) of the cell
+    pub fn occupied_capacity(&self) -> u64 {
+        self.occupied_bytes() * ONE_CKB
+    }
+}
+
+/// A group of planned cells with the same layout
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct RentPlanItem {
+    pub name: String,
+    pub schema: CellSchema,
+    /// The expected cells count
+    pub count: u64,
+}
+
+impl RentPlanItem {
+    pub fn new(name: String, schema: CellSchema, count: u64) -> RentPlanItem {
+        RentPlanItem {
+            name,
+            schema,
+            count,
+        }
+    }
+
+    /// The total capacity (in shannons) locked by the cells
+    pub fn total_capacity(&self) -> u128 {
+        u128::from(self.schema.occupied_capacity()) * u128::from(self.count)
+    }
+}
+
+/// The planned cells of a dApp
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct RentForecast {
+    pub items: Vec<RentPlanItem>,
+}
+
+impl RentForecast {
+    pub fn new(items: Vec<RentPlanItem>) -> RentForecast {
+        RentForecast { items }
+    }
+
+    pub fn add_item(&mut self, name: String, schema: CellSchema, count: u64) {
+        self.items.push(RentPlanItem::new(name, schema, count));
+    }
+
+    /// The total capacity (in shannons) locked by all planned cells
+    pub fn total_capacity(&self) -> u128 {
+        self.items.iter().map(RentPlanItem::total_capacity).sum()
+    }
+
+    /// Replace the schema of the named item, return the updated forecast
+    pub fn with_schema(&self, name: &str, schema: CellSchema) -> RentForecast {
+        let mut forecast = self.clone();
+        for item in &mut forecast.items {
+            if item.name == name {
+                item.schema = schema;
+            }
+        }
+        forecast
+    }
+
+    /// The change of total capacity (in shannons) from `self` to `other`,
+    /// negative if `other` locks less CKB.
+    pub fn diff(&self, other: &RentForecast) -> i128 {
+        other.total_capacity() as i128 - self.total_capacity() as i128
+    }
+}
+
+fn fmt_capacity(capacity: u128) -> String {
+    match u64::try_from(capacity) {
+        Ok(capacity) => HumanCapacity(capacity).to_string(),
+        Err(_) => format!("{} shannons", capacity),
+    }
+}
+
+impl fmt::Display for RentForecast {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for item in &self.items {
+            writeln!(
+                f,
+                "{}: {} cells x {} bytes = {} CKB",
+                item.name,
+                item.count,
+                item.schema.occupied_bytes(),
+                fmt_capacity(item.total_capacity())
+            )?;
+        }
+        write!(f, "total: {} CKB", fmt_capacity(self.total_capacity()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::{core::Capacity, packed::Script};
+
+    #[test]
+    fn test_rent_forecast() {
+        let lock = Script::new_builder()
+            .args(Bytes::from(vec![0u8; 20]).pack())
+            .build();
+        let output = CellOutput::new_builder()
+            .lock(lock.clone())
+            .type_(Some(lock).pack())
+            .build();
+        let data = Bytes::from(vec![0u8; 16]);
+        let schema = CellSchema::from_output(&output, &data);
+        assert_eq!(
+            schema.occupied_capacity(),
+            output
+                .occupied_capacity(Capacity::bytes(data.len()).unwrap())
+                .unwrap()
+                .as_u64()
+        );
+        assert_eq!(
+            CellSchema::new(20, None, 0).occupied_capacity(),
+            61 * ONE_CKB
+        );
+
+        let mut forecast = RentForecast::default();
+        forecast.add_item("account".to_string(), schema, 1000);
+        forecast.add_item("config".to_string(), CellSchema::new(20, None, 100), 1);
+        assert_eq!(
+            forecast.total_capacity(),
+            u128::from(1000 * 130 * ONE_CKB + 161 * ONE_CKB)
+        );
+        let tweaked = forecast.with_schema("account", schema.with_data_len(8));
+        assert_eq!(forecast.diff(&tweaked), -(1000 * 8 * ONE_CKB as i128));
+        assert!(forecast.to_string().ends_with("total: 130161.0 CKB"));
+    }
+}
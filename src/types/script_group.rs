@@ -49,6 +49,7 @@ impl ScriptGroup {
 /// A cell can have a lock script and an optional type script. Even they reference the same script,
 /// lock script and type script will not be grouped together.
 #[derive(Copy, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 pub enum ScriptGroupType {
     /// Lock script group.
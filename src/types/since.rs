@@ -69,4 +69,114 @@ impl Since {
         };
         ty_opt.map(|ty| (ty, value))
     }
+
+    /// Check if the since value of an input satisfies the `required` since,
+    /// they must be both absolute or both relative in the same metric.
+    pub fn satisfies(self, required: Since) -> bool {
+        if required.value() == 0 {
+            return true;
+        }
+        if self.is_relative() != required.is_relative()
+            || !self.flags_is_valid()
+            || !required.flags_is_valid()
+        {
+            return false;
+        }
+        match (self.extract_metric(), required.extract_metric()) {
+            (
+                Some((SinceType::EpochNumberWithFraction, value)),
+                Some((SinceType::EpochNumberWithFraction, required_value)),
+            ) => {
+                EpochNumberWithFraction::from_full_value(value).to_rational()
+                    >= EpochNumberWithFraction::from_full_value(required_value).to_rational()
+            }
+            (Some((ty, value)), Some((required_ty, required_value))) => {
+                ty == required_ty && value >= required_value
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_satisfies_block_number_and_timestamp() {
+        for ty in [SinceType::BlockNumber, SinceType::Timestamp] {
+            for is_relative in [false, true] {
+                let required = Since::new(ty, 100, is_relative);
+                assert!(Since::new(ty, 100, is_relative).satisfies(required));
+                assert!(Since::new(ty, 101, is_relative).satisfies(required));
+                assert!(!Since::new(ty, 99, is_relative).satisfies(required));
+                // Absolute and relative are not comparable
+                assert!(!Since::new(ty, 200, !is_relative).satisfies(required));
+            }
+        }
+    }
+
+    #[test]
+    fn test_satisfies_epoch() {
+        let epoch = |number, index, length, is_relative| {
+            Since::new(
+                SinceType::EpochNumberWithFraction,
+                EpochNumberWithFraction::new(number, index, length).full_value(),
+                is_relative,
+            )
+        };
+        for is_relative in [false, true] {
+            let required = epoch(10, 1, 2, is_relative);
+            assert!(epoch(10, 1, 2, is_relative).satisfies(required));
+            // Compared by the rational value instead of the raw value
+            assert!(epoch(10, 2, 4, is_relative).satisfies(required));
+            assert!(epoch(10, 3, 4, is_relative).satisfies(required));
+            assert!(epoch(11, 0, 1, is_relative).satisfies(required));
+            assert!(!epoch(10, 1, 4, is_relative).satisfies(required));
+            assert!(!epoch(9, 99, 100, is_relative).satisfies(required));
+            assert!(!epoch(11, 0, 1, !is_relative).satisfies(required));
+        }
+        assert!(Since::new_absolute_epoch(20).satisfies(Since::new_absolute_epoch(20)));
+        assert!(!Since::new_absolute_epoch(19).satisfies(Since::new_absolute_epoch(20)));
+    }
+
+    #[test]
+    fn test_satisfies_metric_mismatch() {
+        let metrics = [
+            SinceType::BlockNumber,
+            SinceType::EpochNumberWithFraction,
+            SinceType::Timestamp,
+        ];
+        for ty in metrics {
+            for required_ty in metrics {
+                if ty == required_ty {
+                    continue;
+                }
+                for is_relative in [false, true] {
+                    let required = Since::new(required_ty, 1, is_relative);
+                    assert!(!Since::new(ty, u32::MAX as u64, is_relative).satisfies(required));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_satisfies_zero_and_invalid_flags() {
+        let required = Since::new(SinceType::BlockNumber, 100, false);
+        // No requirement
+        assert!(Since::from_raw_value(0).satisfies(Since::from_raw_value(0)));
+        assert!(Since::new(SinceType::Timestamp, 1, true).satisfies(Since::from_raw_value(0)));
+        assert!(!Since::from_raw_value(0).satisfies(required));
+
+        // The remaining flag bits are set
+        let invalid = Since::from_raw_value(0x0100_0000_0000_0000 | 200);
+        assert!(!invalid.flags_is_valid());
+        assert!(!invalid.satisfies(required));
+        assert!(!Since::new(SinceType::BlockNumber, 200, false).satisfies(invalid));
+        // The metric flag is reserved
+        let invalid = Since::from_raw_value(0x6000_0000_0000_0000 | 200);
+        assert!(!invalid.flags_is_valid());
+        assert!(!invalid.satisfies(required));
+        assert!(!Since::new(SinceType::BlockNumber, 200, false).satisfies(invalid));
+    }
 }
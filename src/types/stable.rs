@@ -0,0 +1,250 @@
+//! Wrapper types which do not depend on the `ckb-types` version.
+//!
+//! Downstream crates can keep those types in their own APIs and convert them
+//! to/from the `ckb-types` types used by the SDK, so a `ckb-types` major
+//! version bump of the SDK does not force them to upgrade in lockstep. The
+//! layouts follow the consensus molecule format, which does not change with
+//! the `ckb-types` version.
+use std::convert::TryFrom;
+use std::fmt;
+
+use ckb_types::{
+    bytes::Bytes,
+    core::TransactionView,
+    packed::{self, Byte},
+    prelude::*,
+};
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+pub enum StableConversionError {
+    #[error("unknown script hash type: `{0}`")]
+    UnknownHashType(u8),
+
+    #[error("invalid transaction data: `{0}`")]
+    InvalidTransaction(String),
+}
+
+/// The script hash type
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum StableHashType {
+    Data,
+    Type,
+    Data1,
+    Data2,
+}
+
+impl StableHashType {
+    pub fn to_u8(self) -> u8 {
+        match self {
+            StableHashType::Data => 0,
+            StableHashType::Type => 1,
+            StableHashType::Data1 => 2,
+            StableHashType::Data2 => 4,
+        }
+    }
+}
+
+impl TryFrom<u8> for StableHashType {
+    type Error = StableConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(StableHashType::Data),
+            1 => Ok(StableHashType::Type),
+            2 => Ok(StableHashType::Data1),
+            4 => Ok(StableHashType::Data2),
+            _ => Err(StableConversionError::UnknownHashType(value)),
+        }
+    }
+}
+
+/// The script
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct StableScript {
+    pub code_hash: [u8; 32],
+    pub hash_type: StableHashType,
+    pub args: Vec<u8>,
+}
+
+impl TryFrom<&packed::Script> for StableScript {
+    type Error = StableConversionError;
+
+    fn try_from(script: &packed::Script) -> Result<Self, Self::Error> {
+        let mut code_hash = [0u8; 32];
+        code_hash.copy_from_slice(script.code_hash().as_slice());
+        Ok(StableScript {
+            code_hash,
+            hash_type: StableHashType::try_from(script.hash_type().as_slice()[0])?,
+            args: script.args().raw_data().to_vec(),
+        })
+    }
+}
+
+impl TryFrom<packed::Script> for StableScript {
+    type Error = StableConversionError;
+
+    fn try_from(script: packed::Script) -> Result<Self, Self::Error> {
+        StableScript::try_from(&script)
+    }
+}
+
+impl From<&StableScript> for packed::Script {
+    fn from(script: &StableScript) -> packed::Script {
+        packed::Script::new_builder()
+            .code_hash(script.code_hash.pack())
+            .hash_type(Byte::new(script.hash_type.to_u8()))
+            .args(Bytes::from(script.args.clone()).pack())
+            .build()
+    }
+}
+
+impl From<StableScript> for packed::Script {
+    fn from(script: StableScript) -> packed::Script {
+        packed::Script::from(&script)
+    }
+}
+
+/// The out point of a cell
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct StableOutPoint {
+    pub tx_hash: [u8; 32],
+    pub index: u32,
+}
+
+impl From<&packed::OutPoint> for StableOutPoint {
+    fn from(out_point: &packed::OutPoint) -> StableOutPoint {
+        let mut tx_hash = [0u8; 32];
+        tx_hash.copy_from_slice(out_point.tx_hash().as_slice());
+        StableOutPoint {
+            tx_hash,
+            index: out_point.index().unpack(),
+        }
+    }
+}
+
+impl From<packed::OutPoint> for StableOutPoint {
+    fn from(out_point: packed::OutPoint) -> StableOutPoint {
+        StableOutPoint::from(&out_point)
+    }
+}
+
+impl From<&StableOutPoint> for packed::OutPoint {
+    fn from(out_point: &StableOutPoint) -> packed::OutPoint {
+        packed::OutPoint::new(out_point.tx_hash.pack(), out_point.index)
+    }
+}
+
+impl From<StableOutPoint> for packed::OutPoint {
+    fn from(out_point: StableOutPoint) -> packed::OutPoint {
+        packed::OutPoint::from(&out_point)
+    }
+}
+
+impl fmt::Display for StableOutPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x")?;
+        for byte in &self.tx_hash {
+            write!(f, "{:02x}", byte)?;
+        }
+        write!(f, "-{}", self.index)
+    }
+}
+
+/// The transaction in the molecule serialized format
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct StableTransaction {
+    data: Vec<u8>,
+    hash: [u8; 32],
+}
+
+impl StableTransaction {
+    /// Create from the molecule serialized transaction
+    pub fn from_slice(data: &[u8]) -> Result<StableTransaction, StableConversionError> {
+        let tx = packed::Transaction::from_slice(data)
+            .map_err(|err| StableConversionError::InvalidTransaction(err.to_string()))?;
+        Ok(StableTransaction::from(&tx.into_view()))
+    }
+
+    /// The molecule serialized transaction
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn hash(&self) -> [u8; 32] {
+        self.hash
+    }
+}
+
+impl From<&TransactionView> for StableTransaction {
+    fn from(tx: &TransactionView) -> StableTransaction {
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(tx.hash().as_slice());
+        StableTransaction {
+            data: tx.data().as_slice().to_vec(),
+            hash,
+        }
+    }
+}
+
+impl From<TransactionView> for StableTransaction {
+    fn from(tx: TransactionView) -> StableTransaction {
+        StableTransaction::from(&tx)
+    }
+}
+
+impl From<&StableTransaction> for TransactionView {
+    fn from(tx: &StableTransaction) -> TransactionView {
+        // the data is always checked when the StableTransaction is created
+        packed::Transaction::new_unchecked(Bytes::from(tx.data.clone())).into_view()
+    }
+}
+
+impl From<StableTransaction> for TransactionView {
+    fn from(tx: StableTransaction) -> TransactionView {
+        TransactionView::from(&tx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::{core::ScriptHashType, h256, packed::CellOutput, H256};
+
+    #[test]
+    fn test_stable_conversion() {
+        let script = packed::Script::new_builder()
+            .code_hash(h256!("0x1234").pack())
+            .hash_type(ScriptHashType::Data2.into())
+            .args(Bytes::from(vec![1u8; 20]).pack())
+            .build();
+        let stable_script = StableScript::try_from(&script).unwrap();
+        assert_eq!(stable_script.hash_type, StableHashType::Data2);
+        assert_eq!(packed::Script::from(&stable_script), script);
+        let invalid = script.clone().as_builder().hash_type(Byte::new(3)).build();
+        assert_eq!(
+            StableScript::try_from(&invalid),
+            Err(StableConversionError::UnknownHashType(3))
+        );
+
+        let out_point = packed::OutPoint::new(h256!("0xabcd").pack(), 3);
+        let stable_out_point = StableOutPoint::from(&out_point);
+        assert_eq!(stable_out_point.index, 3);
+        assert_eq!(packed::OutPoint::from(stable_out_point), out_point);
+
+        let tx = TransactionView::new_advanced_builder()
+            .input(packed::CellInput::new(out_point, 0))
+            .output(CellOutput::new_builder().lock(script).build())
+            .output_data(Bytes::new().pack())
+            .build();
+        let stable_tx = StableTransaction::from(&tx);
+        let tx_hash: H256 = tx.hash().unpack();
+        assert_eq!(stable_tx.hash(), tx_hash.0);
+        assert_eq!(
+            StableTransaction::from_slice(stable_tx.as_slice()).unwrap(),
+            stable_tx
+        );
+        assert_eq!(TransactionView::from(stable_tx).hash(), tx.hash());
+        assert!(StableTransaction::from_slice(&[1u8; 3]).is_err());
+    }
+}
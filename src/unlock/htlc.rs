@@ -0,0 +1,240 @@
+use anyhow::anyhow;
+use ckb_hash::blake2b_256;
+use ckb_types::{
+    bytes::{Bytes, BytesMut},
+    core::TransactionView,
+    packed::WitnessArgs,
+    prelude::*,
+    H160, H256,
+};
+
+use super::unlocker::{fill_witness_lock, reset_witness_lock, ScriptUnlocker, UnlockError};
+use crate::traits::TransactionDependencyProvider;
+use crate::types::ScriptGroup;
+
+/// The length of the HTLC lock script args.
+pub const HTLC_ARGS_LEN: usize = 20 + 20 + 32 + 8;
+
+/// The HTLC (hashed timelock contract) lock script args:
+///
+/// ```text
+/// receiver_lock_hash[0..20] | refund_lock_hash[20..40] | hash_lock[40..72] | refund_since[72..80]
+/// ```
+///
+///   * Claim: an input locked by receiver lock is presented in the transaction
+///     and the preimage of `hash_lock` is placed in the `WitnessArgs.lock`
+///     field of the first input of the script group.
+///   * Refund: an input locked by refund lock is presented in the transaction,
+///     and all HTLC inputs use `refund_since` as since value.
+///
+/// NOTE: There is no HTLC lock script deployed as a system script on the
+/// mainnet or the testnet, the layout above is the one defined by this SDK.
+/// The caller deploys a lock script implementing exactly these rules and
+/// passes its [`ScriptId`](crate::ScriptId) to the builders, the SDK does not
+/// validate the script binary.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct HtlcArgs {
+    /// The first 20 bytes of the receiver lock script hash
+    pub receiver_lock_hash: H160,
+    /// The first 20 bytes of the refund lock script hash
+    pub refund_lock_hash: H160,
+    /// blake2b_256 hash of the preimage
+    pub hash_lock: H256,
+    /// The since value required by refund path
+    pub refund_since: u64,
+}
+
+impl HtlcArgs {
+    pub fn new(
+        receiver_lock_hash: H160,
+        refund_lock_hash: H160,
+        hash_lock: H256,
+        refund_since: u64,
+    ) -> HtlcArgs {
+        HtlcArgs {
+            receiver_lock_hash,
+            refund_lock_hash,
+            hash_lock,
+            refund_since,
+        }
+    }
+
+    /// Calculate the hash lock from preimage
+    pub fn hash_preimage(preimage: &[u8]) -> H256 {
+        H256::from(blake2b_256(preimage))
+    }
+
+    pub fn from_slice(args: &[u8]) -> Result<HtlcArgs, UnlockError> {
+        if args.len() != HTLC_ARGS_LEN {
+            return Err(UnlockError::Other(anyhow!(
+                "invalid htlc args length, expected: {}, got: {}",
+                HTLC_ARGS_LEN,
+                args.len()
+            )));
+        }
+        let mut since_bytes = [0u8; 8];
+        since_bytes.copy_from_slice(&args[72..80]);
+        Ok(HtlcArgs {
+            receiver_lock_hash: H160::from_slice(&args[0..20]).unwrap(),
+            refund_lock_hash: H160::from_slice(&args[20..40]).unwrap(),
+            hash_lock: H256::from_slice(&args[40..72]).unwrap(),
+            refund_since: u64::from_le_bytes(since_bytes),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut args = BytesMut::with_capacity(HTLC_ARGS_LEN);
+        args.extend_from_slice(self.receiver_lock_hash.as_bytes());
+        args.extend_from_slice(self.refund_lock_hash.as_bytes());
+        args.extend_from_slice(self.hash_lock.as_bytes());
+        args.extend_from_slice(&self.refund_since.to_le_bytes()[..]);
+        args.freeze()
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum HtlcAction {
+    /// Claim by receiver with the preimage
+    Claim(Bytes),
+    /// Refund to the refund lock after timeout
+    Refund,
+}
+
+/// Unlocker for the HTLC lock script. The HTLC lock itself does not require
+/// signature, the owner lock (receiver or refund lock) input must be unlocked
+/// by its own unlocker.
+pub struct HtlcUnlocker {
+    action: HtlcAction,
+}
+
+impl HtlcUnlocker {
+    pub fn new(action: HtlcAction) -> HtlcUnlocker {
+        HtlcUnlocker { action }
+    }
+
+    pub fn action(&self) -> &HtlcAction {
+        &self.action
+    }
+}
+
+impl ScriptUnlocker for HtlcUnlocker {
+    fn match_args(&self, args: &[u8]) -> bool {
+        match HtlcArgs::from_slice(args) {
+            Ok(htlc_args) => match &self.action {
+                HtlcAction::Claim(preimage) => {
+                    HtlcArgs::hash_preimage(preimage.as_ref()) == htlc_args.hash_lock
+                }
+                HtlcAction::Refund => true,
+            },
+            Err(_) => false,
+        }
+    }
+
+    fn is_unlocked(
+        &self,
+        tx: &TransactionView,
+        script_group: &ScriptGroup,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<bool, UnlockError> {
+        let args = HtlcArgs::from_slice(script_group.script.args().raw_data().as_ref())?;
+        let inputs: Vec<_> = tx.inputs().into_iter().collect();
+        let (owner_lock_hash, expected_since) = match &self.action {
+            HtlcAction::Claim(_) => (&args.receiver_lock_hash, 0),
+            HtlcAction::Refund => (&args.refund_lock_hash, args.refund_since),
+        };
+        for idx in &script_group.input_indices {
+            let since: u64 = inputs[*idx].since().unpack();
+            if since != expected_since {
+                return Err(UnlockError::Other(anyhow!(
+                    "invalid since value in htlc input {}, expected: {:#x}, got: {:#x}",
+                    idx,
+                    expected_since,
+                    since
+                )));
+            }
+        }
+        let mut has_owner_input = false;
+        for input in &inputs {
+            let output = tx_dep_provider.get_cell(&input.previous_output())?;
+            if &output.lock().calc_script_hash().as_slice()[0..20] == owner_lock_hash.as_bytes() {
+                has_owner_input = true;
+                break;
+            }
+        }
+        if !has_owner_input {
+            return Ok(false);
+        }
+        if let HtlcAction::Claim(preimage) = &self.action {
+            let witness_idx = script_group.input_indices[0];
+            let witness = tx
+                .witnesses()
+                .get(witness_idx)
+                .map(|witness| witness.raw_data())
+                .unwrap_or_default();
+            let witness_args = match WitnessArgs::from_slice(witness.as_ref()) {
+                Ok(args) => args,
+                Err(_) => return Ok(false),
+            };
+            return Ok(witness_args
+                .lock()
+                .to_opt()
+                .map(|lock| lock.raw_data() == *preimage)
+                .unwrap_or(false));
+        }
+        Ok(true)
+    }
+
+    fn unlock(
+        &self,
+        tx: &TransactionView,
+        script_group: &ScriptGroup,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, UnlockError> {
+        match &self.action {
+            HtlcAction::Claim(preimage) => {
+                let tx = reset_witness_lock(tx.clone(), script_group.input_indices[0])
+                    .map_err(UnlockError::InvalidWitnessArgs)?;
+                fill_witness_lock(&tx, script_group, preimage.clone())
+            }
+            HtlcAction::Refund => Ok(tx.clone()),
+        }
+    }
+
+    fn fill_placeholder_witness(
+        &self,
+        tx: &TransactionView,
+        script_group: &ScriptGroup,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, UnlockError> {
+        match &self.action {
+            HtlcAction::Claim(preimage) => fill_witness_lock(tx, script_group, preimage.clone()),
+            HtlcAction::Refund => Ok(tx.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::h160;
+
+    #[test]
+    fn test_htlc_args_roundtrip() {
+        let hash_lock = HtlcArgs::hash_preimage(b"preimage");
+        let args = HtlcArgs::new(
+            h160!("0x7d33bdd64eb80f8ca4d186d161f7f0cc65c627b0"),
+            h160!("0x9943f8613bd23d45631265ccef19a6edff7dac4d"),
+            hash_lock,
+            0x2000_0000_0000_0064,
+        );
+        let bytes = args.to_bytes();
+        assert_eq!(bytes.len(), HTLC_ARGS_LEN);
+        assert_eq!(HtlcArgs::from_slice(bytes.as_ref()).unwrap(), args);
+        assert!(HtlcArgs::from_slice(&bytes[0..40]).is_err());
+
+        let unlocker = HtlcUnlocker::new(HtlcAction::Claim(Bytes::from_static(b"preimage")));
+        assert!(unlocker.match_args(bytes.as_ref()));
+        let unlocker = HtlcUnlocker::new(HtlcAction::Claim(Bytes::from_static(b"wrong")));
+        assert!(!unlocker.match_args(bytes.as_ref()));
+    }
+}
@@ -1,16 +1,30 @@
+mod htlc;
+mod omni_identity;
 pub(crate) mod omni_lock;
+mod proposal;
+mod pw_lock;
 pub mod rc_data;
 mod signer;
 mod unlocker;
+mod verifier;
 
 pub use signer::{
-    generate_message, AcpScriptSigner, ChequeAction, ChequeScriptSigner, MultisigConfig,
-    OmniLockScriptSigner, OmniUnlockMode, ScriptSignError, ScriptSigner, SecpMultisigScriptSigner,
-    SecpSighashScriptSigner,
+    generate_keccak256_message, generate_message, AcpScriptSigner, ChequeAction,
+    ChequeScriptSigner, MultisigConfig, OmniLockScriptSigner, OmniUnlockMode, ScriptSignError,
+    ScriptSigner, SecpMultisigScriptSigner, SecpSighashScriptSigner,
 };
 pub use unlocker::{
     fill_witness_lock, reset_witness_lock, AcpUnlocker, ChequeUnlocker, OmniLockUnlocker,
     ScriptUnlocker, SecpMultisigUnlocker, SecpSighashUnlocker, UnlockError,
 };
 
+pub use verifier::{
+    dedup_multisig_witnesses, find_duplicated_multisig_configs, verify_signatures,
+    DuplicatedMultisigConfig, GroupSignatureReport, SignatureStatus,
+};
+
+pub use htlc::{HtlcAction, HtlcArgs, HtlcUnlocker, HTLC_ARGS_LEN};
+pub use omni_identity::OmniIdentity;
 pub use omni_lock::{IdentityFlag, InfoCellData, OmniLockAcpConfig, OmniLockConfig};
+pub use proposal::{Approval, MultisigProposal, ProposalError};
+pub use pw_lock::{generate_pw_lock_message, PwLockScriptSigner, PwLockUnlocker};
@@ -0,0 +1,254 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use ckb_types::{bytes::Bytes, packed::Script, prelude::*, H160};
+
+use super::omni_lock::{ConfigError, Identity, IdentityFlag};
+use super::MultisigConfig;
+
+/// The auth of an omnilock cell, tells who can unlock the cell.
+///
+/// The string format is `<flavor>:0x<auth content>`, for example
+/// `ethereum:0x1234...`, the flavors are `pubkey-hash`, `ethereum`, `eos`,
+/// `tron`, `bitcoin`, `dogecoin`, `multisig`, `owner-lock`, `exec` and `dl`.
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub enum OmniIdentity {
+    /// blake160 hash of a secp256k1 public key
+    PubkeyHash(H160),
+    /// The ethereum address (keccak160 of the public key)
+    Ethereum(H160),
+    Eos(H160),
+    Tron(H160),
+    Bitcoin(H160),
+    Dogecoin(H160),
+    /// blake160 hash of the multisig config
+    Multisig(H160),
+    /// The owner lock script hash (first 20 bytes)
+    OwnerLock(H160),
+    /// blake160 hash of the exec preimage
+    Exec(H160),
+    /// blake160 hash of the dynamic linking preimage
+    Dl(H160),
+}
+
+impl OmniIdentity {
+    pub fn new(flag: IdentityFlag, auth_content: H160) -> OmniIdentity {
+        match flag {
+            IdentityFlag::PubkeyHash => OmniIdentity::PubkeyHash(auth_content),
+            IdentityFlag::Ethereum => OmniIdentity::Ethereum(auth_content),
+            IdentityFlag::Eos => OmniIdentity::Eos(auth_content),
+            IdentityFlag::Tron => OmniIdentity::Tron(auth_content),
+            IdentityFlag::Bitcoin => OmniIdentity::Bitcoin(auth_content),
+            IdentityFlag::Dogecoin => OmniIdentity::Dogecoin(auth_content),
+            IdentityFlag::Multisig => OmniIdentity::Multisig(auth_content),
+            IdentityFlag::OwnerLock => OmniIdentity::OwnerLock(auth_content),
+            IdentityFlag::Exec => OmniIdentity::Exec(auth_content),
+            IdentityFlag::Dl => OmniIdentity::Dl(auth_content),
+        }
+    }
+
+    pub fn from_multisig_config(multisig_config: &MultisigConfig) -> OmniIdentity {
+        OmniIdentity::Multisig(multisig_config.hash160())
+    }
+
+    /// The owner lock identity, the auth content is the first 20 bytes of the
+    /// script hash.
+    pub fn from_owner_lock(owner_lock: &Script) -> OmniIdentity {
+        let script_hash = owner_lock.calc_script_hash();
+        OmniIdentity::OwnerLock(H160::from_slice(&script_hash.as_slice()[0..20]).unwrap())
+    }
+
+    /// Parse the identity from the omnilock args (the first 21 bytes)
+    pub fn from_args(args: &[u8]) -> Result<OmniIdentity, ConfigError> {
+        Identity::from_slice(args)
+            .map(OmniIdentity::from)
+            .map_err(|err| ConfigError::Other(anyhow::anyhow!(err)))
+    }
+
+    /// Parse the identity from an omnilock script
+    pub fn from_lock_script(lock_script: &Script) -> Result<OmniIdentity, ConfigError> {
+        Self::from_args(&lock_script.args().raw_data())
+    }
+
+    pub fn flag(&self) -> IdentityFlag {
+        match self {
+            OmniIdentity::PubkeyHash(_) => IdentityFlag::PubkeyHash,
+            OmniIdentity::Ethereum(_) => IdentityFlag::Ethereum,
+            OmniIdentity::Eos(_) => IdentityFlag::Eos,
+            OmniIdentity::Tron(_) => IdentityFlag::Tron,
+            OmniIdentity::Bitcoin(_) => IdentityFlag::Bitcoin,
+            OmniIdentity::Dogecoin(_) => IdentityFlag::Dogecoin,
+            OmniIdentity::Multisig(_) => IdentityFlag::Multisig,
+            OmniIdentity::OwnerLock(_) => IdentityFlag::OwnerLock,
+            OmniIdentity::Exec(_) => IdentityFlag::Exec,
+            OmniIdentity::Dl(_) => IdentityFlag::Dl,
+        }
+    }
+
+    pub fn auth_content(&self) -> &H160 {
+        match self {
+            OmniIdentity::PubkeyHash(content)
+            | OmniIdentity::Ethereum(content)
+            | OmniIdentity::Eos(content)
+            | OmniIdentity::Tron(content)
+            | OmniIdentity::Bitcoin(content)
+            | OmniIdentity::Dogecoin(content)
+            | OmniIdentity::Multisig(content)
+            | OmniIdentity::OwnerLock(content)
+            | OmniIdentity::Exec(content)
+            | OmniIdentity::Dl(content) => content,
+        }
+    }
+
+    /// The flavor name used in the string format
+    pub fn flavor(&self) -> &'static str {
+        flavor_name(self.flag())
+    }
+
+    /// Check the auth content is set, an all zero auth content can not be
+    /// unlocked by anyone.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.auth_content() == &H160::default() {
+            return Err(ConfigError::Other(anyhow::anyhow!(
+                "empty auth content of {} identity",
+                self.flavor()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Check the multisig config matches the multisig identity
+    pub fn validate_multisig_config(
+        &self,
+        multisig_config: &MultisigConfig,
+    ) -> Result<(), ConfigError> {
+        match self {
+            OmniIdentity::Multisig(content) if content == &multisig_config.hash160() => Ok(()),
+            OmniIdentity::Multisig(_) => Err(ConfigError::Other(anyhow::anyhow!(
+                "multisig config not match the identity: {}",
+                self
+            ))),
+            _ => Err(ConfigError::NoMultiSigConfig),
+        }
+    }
+
+    /// The omnilock args without any omnilock flags
+    pub fn to_args(&self) -> Bytes {
+        let mut args: Vec<u8> = Identity::from(self.clone()).into();
+        args.push(0);
+        Bytes::from(args)
+    }
+
+    /// A human readable description of who can unlock the cell
+    pub fn describe(&self) -> String {
+        let content = self.auth_content();
+        match self {
+            OmniIdentity::PubkeyHash(_) => format!("secp256k1 key (blake160: {:#x})", content),
+            OmniIdentity::Ethereum(_) => format!("ethereum address {:#x}", content),
+            OmniIdentity::Eos(_) => format!("EOS key (hash: {:#x})", content),
+            OmniIdentity::Tron(_) => format!("Tron address (hash: {:#x})", content),
+            OmniIdentity::Bitcoin(_) => format!("bitcoin key (hash160: {:#x})", content),
+            OmniIdentity::Dogecoin(_) => format!("dogecoin key (hash160: {:#x})", content),
+            OmniIdentity::Multisig(_) => format!("multisig (config hash: {:#x})", content),
+            OmniIdentity::OwnerLock(_) => {
+                format!("owner of lock script (hash: {:#x})", content)
+            }
+            OmniIdentity::Exec(_) => format!("exec delegate (preimage hash: {:#x})", content),
+            OmniIdentity::Dl(_) => {
+                format!("dynamic linking delegate (preimage hash: {:#x})", content)
+            }
+        }
+    }
+}
+
+fn flavor_name(flag: IdentityFlag) -> &'static str {
+    match flag {
+        IdentityFlag::PubkeyHash => "pubkey-hash",
+        IdentityFlag::Ethereum => "ethereum",
+        IdentityFlag::Eos => "eos",
+        IdentityFlag::Tron => "tron",
+        IdentityFlag::Bitcoin => "bitcoin",
+        IdentityFlag::Dogecoin => "dogecoin",
+        IdentityFlag::Multisig => "multisig",
+        IdentityFlag::OwnerLock => "owner-lock",
+        IdentityFlag::Exec => "exec",
+        IdentityFlag::Dl => "dl",
+    }
+}
+
+impl From<Identity> for OmniIdentity {
+    fn from(id: Identity) -> OmniIdentity {
+        OmniIdentity::new(id.flag(), id.auth_content().clone())
+    }
+}
+
+impl From<OmniIdentity> for Identity {
+    fn from(id: OmniIdentity) -> Identity {
+        Identity::new(id.flag(), id.auth_content().clone())
+    }
+}
+
+impl fmt::Display for OmniIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{:#x}", self.flavor(), self.auth_content())
+    }
+}
+
+impl FromStr for OmniIdentity {
+    type Err = ConfigError;
+
+    fn from_str(value: &str) -> Result<OmniIdentity, ConfigError> {
+        let (flavor, content) = value.split_once(':').ok_or_else(|| {
+            ConfigError::Other(anyhow::anyhow!("invalid omnilock identity: {}", value))
+        })?;
+        let flag = (0..=u8::MAX)
+            .filter_map(|value| IdentityFlag::try_from(value).ok())
+            .find(|flag| flavor_name(*flag) == flavor)
+            .ok_or_else(|| {
+                ConfigError::Other(anyhow::anyhow!("unknown identity flavor: {}", flavor))
+            })?;
+        let content = content.strip_prefix("0x").unwrap_or(content);
+        let auth_content = H160::from_str(content).map_err(|err| {
+            ConfigError::Other(anyhow::anyhow!("invalid auth content {}: {}", content, err))
+        })?;
+        Ok(OmniIdentity::new(flag, auth_content))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::h160;
+
+    #[test]
+    fn test_omni_identity_format() {
+        let id = OmniIdentity::Ethereum(h160!("0x1234567890abcdef1234567890abcdef12345678"));
+        let text = id.to_string();
+        assert_eq!(text, "ethereum:0x1234567890abcdef1234567890abcdef12345678");
+        assert_eq!(text.parse::<OmniIdentity>().unwrap(), id);
+        assert!("unknown:0x1234567890abcdef1234567890abcdef12345678"
+            .parse::<OmniIdentity>()
+            .is_err());
+        assert!("dl:0x1234".parse::<OmniIdentity>().is_err());
+
+        let args = id.to_args();
+        assert_eq!(args.len(), 22);
+        assert_eq!(args[0], IdentityFlag::Ethereum as u8);
+        assert_eq!(OmniIdentity::from_args(&args).unwrap(), id);
+
+        assert!(id.validate().is_ok());
+        assert!(OmniIdentity::Exec(H160::default()).validate().is_err());
+        let multisig_config = MultisigConfig::new_with(
+            vec![h160!("0x1234567890abcdef1234567890abcdef12345678")],
+            0,
+            1,
+        )
+        .unwrap();
+        assert!(id.validate_multisig_config(&multisig_config).is_err());
+        let multisig_id = OmniIdentity::from_multisig_config(&multisig_config);
+        assert!(multisig_id
+            .validate_multisig_config(&multisig_config)
+            .is_ok());
+    }
+}
@@ -20,9 +20,11 @@ use enum_repr_derive::{FromEnumToRepr, TryFromReprToEnum};
 use serde::{de::Unexpected, Deserialize, Serialize};
 use std::convert::TryFrom;
 
+#[cfg(feature = "bitcoin")]
+use bech32::FromBase32;
 use bitflags::bitflags;
 
-use super::{MultisigConfig, OmniUnlockMode};
+use super::{MultisigConfig, OmniIdentity, OmniUnlockMode};
 use thiserror::Error;
 
 #[derive(
@@ -39,6 +41,7 @@ use thiserror::Error;
 )]
 #[repr(u8)]
 #[derive(Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum IdentityFlag {
     /// The auth content represents the blake160 hash of a secp256k1 public key.
     /// The lock script will perform secp256k1 signature verification, the same as the SECP256K1/blake160 lock.
@@ -71,10 +74,12 @@ pub enum IdentityFlag {
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, Hash, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Identity {
     /// Indicate what's auth content of auth_content will be.
     flag: IdentityFlag,
     /// The auth content of the identity.
+    #[cfg_attr(feature = "schema", schemars(with = "String"))]
     auth_content: H160,
 }
 
@@ -183,6 +188,7 @@ impl Display for Identity {
 }
 bitflags! {
     #[derive(Serialize, Deserialize)]
+    #[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
     pub struct OmniLockFlags: u8 {
         /// administrator mode, flag is 1, affected args:  RC cell type ID, affected field:omni_identity/signature in OmniLockWitnessLock
         const ADMIN = 1;
@@ -293,10 +299,13 @@ impl InfoCellData {
 
 /// The administrator mode configuration.
 #[derive(Clone, Serialize, Deserialize, Debug, Hash, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct AdminConfig {
     /// The rc cell's type script hash, the type script should be a type id script.
+    #[cfg_attr(feature = "schema", schemars(with = "String"))]
     rc_type_id: H256,
     /// The smt proofs
+    #[cfg_attr(feature = "schema", schemars(with = "Vec<u8>"))]
     proofs: SmtProofEntryVec,
     /// The alternative auth content to the args part.
     auth: Identity,
@@ -370,11 +379,15 @@ pub enum ConfigError {
     #[error("there is no multisig config in the OmniLockConfig")]
     NoMultiSigConfig,
 
+    #[error("invalid bitcoin address: `{0}`")]
+    InvalidBitcoinAddress(String),
+
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, Hash, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct OmniLockAcpConfig {
     /// Tthe minimal transfer amount will be 10^ckb_minimum, if ckb_minimum is 0, means no minimum is enforced on the transfer operation.
     pub ckb_minimum: u8,
@@ -400,6 +413,7 @@ impl OmniLockAcpConfig {
 /// 5. 8 bytes since for time lock, optional
 /// 6. 32 bytes type script hash for supply, optional
 #[derive(Clone, Serialize, Deserialize, Debug, Hash, Eq, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct OmniLockConfig {
     /// The auth id of the OmniLock
     id: Identity,
@@ -414,6 +428,7 @@ pub struct OmniLockConfig {
     /// 8 bytes since for time lock
     time_lock_config: Option<u64>,
     // 32 bytes type script hash
+    #[cfg_attr(feature = "schema", schemars(with = "Option<String>"))]
     info_cell: Option<H256>,
 }
 
@@ -460,6 +475,36 @@ impl OmniLockConfig {
         Self::new(IdentityFlag::Ethereum, pubkey_hash)
     }
 
+    /// Create a tron identity omnilock
+    /// # Arguments
+    /// * `pubkey_hash` keccak160 hash of public key (the tron address
+    ///   without the `0x41` prefix)
+    pub fn new_tron(pubkey_hash: H160) -> Self {
+        Self::new(IdentityFlag::Tron, pubkey_hash)
+    }
+
+    /// Create an EOS identity omnilock
+    /// # Arguments
+    /// * `pubkey_hash` blake160 hash of the compressed public key
+    pub fn new_eos(pubkey_hash: H160) -> Self {
+        Self::new(IdentityFlag::Eos, pubkey_hash)
+    }
+
+    /// Create a bitcoin identity omnilock
+    /// # Arguments
+    /// * `pubkey_hash` hash160 (`ripemd160(sha256(pubkey))`) of the compressed
+    ///   or the uncompressed public key
+    pub fn new_bitcoin(pubkey_hash: H160) -> Self {
+        Self::new(IdentityFlag::Bitcoin, pubkey_hash)
+    }
+
+    /// Create a bitcoin identity omnilock from a P2PKH (`1...`, `m...` or
+    /// `n...`) or a P2WPKH (`bc1q...`, `tb1q...` or `bcrt1q...`) address.
+    #[cfg(feature = "bitcoin")]
+    pub fn new_bitcoin_address(address: &str) -> Result<Self, ConfigError> {
+        Ok(Self::new_bitcoin(parse_bitcoin_address(address)?))
+    }
+
     /// Create an ownerlock omnilock with according script hash.
     /// # Arguments
     /// * `script_hash` the proper blake160 hash of according ownerlock script.
@@ -487,6 +532,21 @@ impl OmniLockConfig {
         }
     }
 
+    /// Create a new OmniLockConfig from the identity, the auth content is kept
+    /// for all flavors. A multisig identity still needs the multisig config
+    /// to unlock, use [`OmniLockConfig::new_multisig`] instead.
+    pub fn new_with_identity(identity: OmniIdentity) -> Self {
+        OmniLockConfig {
+            id: identity.into(),
+            multisig_config: None,
+            omni_lock_flags: OmniLockFlags::empty(),
+            admin_config: None,
+            acp_config: None,
+            time_lock_config: None,
+            info_cell: None,
+        }
+    }
+
     /// Set the admin cofiguration, and set the OmniLockFlags::ADMIN flag.
     /// # Arguments
     /// * `admin_config` The new admin config.
@@ -588,6 +648,16 @@ impl OmniLockConfig {
         self.info_cell.as_ref()
     }
 
+    /// The raw since value of the time lock
+    pub fn get_time_lock_config(&self) -> Option<u64> {
+        self.time_lock_config
+    }
+
+    /// The identity of who can unlock the cell
+    pub fn omni_identity(&self) -> OmniIdentity {
+        self.id.clone().into()
+    }
+
     /// Calculate script args length
     pub fn get_args_len(&self) -> usize {
         let mut len = 22;
@@ -632,6 +702,21 @@ impl OmniLockConfig {
         self.id.flag == IdentityFlag::Ethereum
     }
 
+    /// Indicate whether is a tron type.
+    pub fn is_tron(&self) -> bool {
+        self.id.flag == IdentityFlag::Tron
+    }
+
+    /// Indicate whether is an EOS type.
+    pub fn is_eos(&self) -> bool {
+        self.id.flag == IdentityFlag::Eos
+    }
+
+    /// Indicate whether is a bitcoin type.
+    pub fn is_bitcoin(&self) -> bool {
+        self.id.flag == IdentityFlag::Bitcoin
+    }
+
     /// Check if it is a mutlisig flag.
     pub fn is_multisig(&self) -> bool {
         self.id.flag == IdentityFlag::Multisig
@@ -647,7 +732,11 @@ impl OmniLockConfig {
         unlock_mode: OmniUnlockMode,
     ) -> Result<Bytes, ConfigError> {
         let mut builder = match self.id.flag {
-            IdentityFlag::PubkeyHash | IdentityFlag::Ethereum => OmniLockWitnessLock::new_builder()
+            IdentityFlag::PubkeyHash
+            | IdentityFlag::Ethereum
+            | IdentityFlag::Tron
+            | IdentityFlag::Eos
+            | IdentityFlag::Bitcoin => OmniLockWitnessLock::new_builder()
                 .signature(Some(Bytes::from(vec![0u8; 65])).pack()),
             IdentityFlag::Multisig => {
                 let multisig_config = match unlock_mode {
@@ -705,7 +794,12 @@ impl OmniLockConfig {
         unlock_mode: OmniUnlockMode,
     ) -> Result<WitnessArgs, ConfigError> {
         match self.id.flag {
-            IdentityFlag::PubkeyHash | IdentityFlag::Ethereum | IdentityFlag::Multisig => {
+            IdentityFlag::PubkeyHash
+            | IdentityFlag::Ethereum
+            | IdentityFlag::Tron
+            | IdentityFlag::Eos
+            | IdentityFlag::Bitcoin
+            | IdentityFlag::Multisig => {
                 let lock = self.placeholder_witness_lock(unlock_mode)?;
                 Ok(WitnessArgs::new_builder().lock(Some(lock).pack()).build())
             }
@@ -722,6 +816,43 @@ impl OmniLockConfig {
     }
 }
 
+/// The pubkey hash of a P2PKH or a P2WPKH bitcoin address
+#[cfg(feature = "bitcoin")]
+fn parse_bitcoin_address(address: &str) -> Result<H160, ConfigError> {
+    let invalid = |reason: &str| {
+        ConfigError::InvalidBitcoinAddress(format!("{}, address: {}", reason, address))
+    };
+    let lower = address.to_lowercase();
+    if ["bc1", "tb1", "bcrt1"]
+        .iter()
+        .any(|prefix| lower.starts_with(prefix))
+    {
+        let (_hrp, data, variant) =
+            bech32::decode(address).map_err(|err| invalid(&err.to_string()))?;
+        let (version, program) = data
+            .split_first()
+            .ok_or_else(|| invalid("empty witness program"))?;
+        if version.to_u8() != 0 || variant != bech32::Variant::Bech32 {
+            return Err(invalid("only the witness version 0 is supported"));
+        }
+        let program = Vec::<u8>::from_base32(program).map_err(|err| invalid(&err.to_string()))?;
+        if program.len() != 20 {
+            return Err(invalid("not a P2WPKH address"));
+        }
+        Ok(H160::from_slice(&program).unwrap())
+    } else {
+        let data = bs58::decode(address)
+            .with_check(None)
+            .into_vec()
+            .map_err(|err| invalid(&err.to_string()))?;
+        // 0x00: mainnet P2PKH, 0x6f: testnet P2PKH
+        if data.len() != 21 || (data[0] != 0x00 && data[0] != 0x6f) {
+            return Err(invalid("not a P2PKH address"));
+        }
+        Ok(H160::from_slice(&data[1..]).unwrap())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use ckb_types::packed::Byte;
@@ -755,6 +886,37 @@ mod tests {
         let cfg2: AdminConfig = serde_json::from_str(&x).unwrap();
         assert_eq!(cfg, cfg2);
     }
+
+    #[cfg(feature = "bitcoin")]
+    #[test]
+    fn test_bitcoin_address() {
+        use super::{IdentityFlag, OmniLockConfig};
+        use ckb_types::h160;
+
+        // The addresses of the secret key 1
+        let compressed = h160!("0x751e76e8199196d454941c45d1b3a323f1433bd6");
+        let uncompressed = h160!("0x91b24bf9f5288532960ac687abb035127b1d28a5");
+        for (address, pubkey_hash) in [
+            ("1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH", &compressed),
+            ("1EHNa6Q4Jz2uvNExL497mE43ikXhwF6kZm", &uncompressed),
+            ("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4", &compressed),
+            ("BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4", &compressed),
+        ] {
+            let cfg = OmniLockConfig::new_bitcoin_address(address).unwrap();
+            assert_eq!(cfg.id().flag(), IdentityFlag::Bitcoin);
+            assert_eq!(cfg.id().auth_content(), pubkey_hash);
+        }
+        for address in [
+            // P2SH
+            "3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy",
+            // P2WSH
+            "bc1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qccfmv3",
+            // invalid checksum
+            "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMJ",
+        ] {
+            assert!(OmniLockConfig::new_bitcoin_address(address).is_err());
+        }
+    }
 }
 #[cfg(test)]
 mod anyhow_tests {
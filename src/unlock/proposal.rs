@@ -0,0 +1,310 @@
+//! Multisig proposals.
+//!
+//! A proposal wraps an unsigned transaction spending the cells of a multisig
+//! lock, collects the approvals (signatures) of the members one by one, and
+//! assembles the final transaction once the threshold is met. The proposal is
+//! serializable, so it can be stored or passed around between the approvals.
+use std::fs;
+use std::path::Path;
+
+use anyhow::anyhow;
+use ckb_jsonrpc_types::{self as json_types, JsonBytes};
+use ckb_types::{
+    bytes::Bytes,
+    core::{ScriptHashType, TransactionView},
+    packed::{self, Script, WitnessArgs},
+    prelude::*,
+    H160, H256,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::verifier::{recover_multisig_signers, witness_lock};
+use super::{MultisigConfig, UnlockError};
+use crate::constants::MULTISIG_TYPE_HASH;
+use crate::traits::TransactionDependencyProvider;
+use crate::tx_builder::{gen_script_groups, ScriptGroups};
+use crate::types::ScriptGroup;
+
+#[derive(Error, Debug)]
+pub enum ProposalError {
+    #[error("state error: `{0}`")]
+    State(anyhow::Error),
+
+    #[error("no input is locked by the multisig config")]
+    NoMultisigInput,
+
+    #[error("invalid approval: `{0}`")]
+    InvalidApproval(String),
+
+    #[error("missing the approval of required signer: `{0:#x}`")]
+    MissingRequiredApproval(H160),
+
+    #[error("not enough approvals, approved: `{approved}`, threshold: `{threshold}`")]
+    NotEnoughApprovals { approved: usize, threshold: u8 },
+
+    #[error(transparent)]
+    Unlock(#[from] UnlockError),
+}
+
+/// The approval of a multisig member
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Approval {
+    pub signer: H160,
+    /// The signatures of the multisig lock groups, in the order of the groups
+    /// (by the first input index)
+    pub signatures: Vec<JsonBytes>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ProposalGroup {
+    script: json_types::Script,
+    input_indices: Vec<u32>,
+}
+
+impl ProposalGroup {
+    fn to_script_group(&self) -> ScriptGroup {
+        let mut script_group = ScriptGroup::from_lock_script(&Script::from(self.script.clone()));
+        script_group.input_indices = self.input_indices.iter().map(|idx| *idx as usize).collect();
+        script_group
+    }
+}
+
+/// A transaction proposal of a multisig lock.
+///
+/// The lifecycle:
+///   * [`MultisigProposal::new`]: create the proposal from an unsigned (but
+///     balanced) transaction, the witness locks of the multisig groups are set
+///     to the placeholder.
+///   * [`MultisigProposal::approve`]: every member signs [`MultisigProposal::tx`]
+///     (e.g. by [`SecpMultisigUnlocker`](super::SecpMultisigUnlocker)) and
+///     submits the signed transaction, only the signatures of the member are
+///     taken.
+///   * [`MultisigProposal::execute`]: assemble the final transaction when the
+///     threshold and the `require_first_n` rule are met.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultisigProposal {
+    id: H256,
+    summary: String,
+    config: MultisigConfig,
+    tx: json_types::Transaction,
+    groups: Vec<ProposalGroup>,
+    approvals: Vec<Approval>,
+}
+
+impl MultisigProposal {
+    pub fn new(
+        tx: TransactionView,
+        config: MultisigConfig,
+        summary: String,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<MultisigProposal, ProposalError> {
+        let ScriptGroups { lock_groups, .. } =
+            gen_script_groups(&tx, tx_dep_provider).map_err(UnlockError::from)?;
+        let config_hash = config.hash160();
+        let mut script_groups: Vec<_> = lock_groups
+            .into_values()
+            .filter(|group| {
+                let script = &group.script;
+                let args = script.args().raw_data();
+                script.code_hash() == MULTISIG_TYPE_HASH.pack()
+                    && script.hash_type() == ScriptHashType::Type.into()
+                    && args.len() >= 20
+                    && &args[0..20] == config_hash.as_bytes()
+            })
+            .collect();
+        if script_groups.is_empty() {
+            return Err(ProposalError::NoMultisigInput);
+        }
+        script_groups.sort_by_key(|group| group.input_indices[0]);
+
+        let placeholder_lock = config.placeholder_witness().lock();
+        let mut tx = tx;
+        for script_group in &script_groups {
+            tx = set_witness_lock(&tx, script_group.input_indices[0], placeholder_lock.clone())?;
+        }
+        let groups = script_groups
+            .iter()
+            .map(|group| ProposalGroup {
+                script: group.script.clone().into(),
+                input_indices: group.input_indices.iter().map(|idx| *idx as u32).collect(),
+            })
+            .collect();
+        Ok(MultisigProposal {
+            id: tx.hash().unpack(),
+            summary,
+            config,
+            tx: tx.data().into(),
+            groups,
+            approvals: Vec::new(),
+        })
+    }
+
+    /// The proposal id (the transaction hash)
+    pub fn id(&self) -> &H256 {
+        &self.id
+    }
+    pub fn summary(&self) -> &str {
+        &self.summary
+    }
+    pub fn config(&self) -> &MultisigConfig {
+        &self.config
+    }
+    pub fn approvals(&self) -> &[Approval] {
+        &self.approvals
+    }
+
+    /// The transaction to sign, the witness locks of the multisig groups are
+    /// placeholders.
+    pub fn tx(&self) -> TransactionView {
+        packed::Transaction::from(self.tx.clone()).into_view()
+    }
+
+    pub fn is_approved_by(&self, signer: &H160) -> bool {
+        self.approvals
+            .iter()
+            .any(|approval| &approval.signer == signer)
+    }
+
+    /// Take the signatures of `signer` from the signed transaction, a
+    /// previous approval of the same signer is replaced.
+    pub fn approve(
+        &mut self,
+        signer: &H160,
+        signed_tx: &TransactionView,
+    ) -> Result<(), ProposalError> {
+        if !self.config.contains_address(signer) {
+            return Err(ProposalError::InvalidApproval(format!(
+                "signer not in multisig config: {:#x}",
+                signer
+            )));
+        }
+        let tx_hash: H256 = signed_tx.hash().unpack();
+        if tx_hash != self.id {
+            return Err(ProposalError::InvalidApproval(
+                "transaction not match the proposal".to_string(),
+            ));
+        }
+        let tx = self.tx();
+        let config_hash = self.config.hash160();
+        let mut signatures = Vec::with_capacity(self.groups.len());
+        for group in &self.groups {
+            let script_group = group.to_script_group();
+            let lock =
+                witness_lock(signed_tx, &script_group).map_err(ProposalError::InvalidApproval)?;
+            let (_, signers) =
+                recover_multisig_signers(&tx, &script_group, lock.as_ref(), config_hash.as_bytes())
+                    .map_err(ProposalError::InvalidApproval)?;
+            let signature = signers
+                .into_iter()
+                .find(|(hash, _)| hash == signer)
+                .map(|(_, signature)| signature)
+                .ok_or_else(|| {
+                    ProposalError::InvalidApproval(format!(
+                        "no signature of {:#x} for input {}",
+                        signer, script_group.input_indices[0]
+                    ))
+                })?;
+            signatures.push(JsonBytes::from_bytes(signature));
+        }
+        let approval = Approval {
+            signer: signer.clone(),
+            signatures,
+        };
+        if let Some(existing) = self
+            .approvals
+            .iter_mut()
+            .find(|approval| &approval.signer == signer)
+        {
+            *existing = approval;
+        } else {
+            self.approvals.push(approval);
+        }
+        Ok(())
+    }
+
+    /// Select the approvals to execute: the first `require_first_n` members,
+    /// then the others in the approval order until the threshold.
+    fn select_approvals(&self) -> Result<Vec<&Approval>, ProposalError> {
+        let require_first_n = self.config.require_first_n() as usize;
+        let threshold = self.config.threshold();
+        let mut selected = Vec::with_capacity(threshold as usize);
+        for signer in &self.config.sighash_addresses()[0..require_first_n] {
+            let approval = self
+                .approvals
+                .iter()
+                .find(|approval| &approval.signer == signer)
+                .ok_or_else(|| ProposalError::MissingRequiredApproval(signer.clone()))?;
+            selected.push(approval);
+        }
+        for approval in &self.approvals {
+            if selected.len() >= threshold as usize {
+                break;
+            }
+            if !selected.iter().any(|item| item.signer == approval.signer) {
+                selected.push(approval);
+            }
+        }
+        if selected.len() < threshold as usize {
+            return Err(ProposalError::NotEnoughApprovals {
+                approved: selected.len(),
+                threshold,
+            });
+        }
+        Ok(selected)
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.select_approvals().is_ok()
+    }
+
+    /// Assemble the fully signed transaction
+    pub fn execute(&self) -> Result<TransactionView, ProposalError> {
+        let selected = self.select_approvals()?;
+        let config_data = self.config.to_witness_data();
+        let mut tx = self.tx();
+        for (idx, group) in self.groups.iter().enumerate() {
+            let mut lock = config_data.clone();
+            for approval in &selected {
+                lock.extend_from_slice(approval.signatures[idx].as_bytes());
+            }
+            tx = set_witness_lock(
+                &tx,
+                group.input_indices[0] as usize,
+                Some(Bytes::from(lock)).pack(),
+            )?;
+        }
+        Ok(tx)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<MultisigProposal, ProposalError> {
+        let content = fs::read(path).map_err(|err| ProposalError::State(anyhow!(err)))?;
+        serde_json::from_slice(&content).map_err(|err| ProposalError::State(anyhow!(err)))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ProposalError> {
+        let content =
+            serde_json::to_vec_pretty(self).map_err(|err| ProposalError::State(anyhow!(err)))?;
+        fs::write(path, content).map_err(|err| ProposalError::State(anyhow!(err)))
+    }
+}
+
+fn set_witness_lock(
+    tx: &TransactionView,
+    witness_idx: usize,
+    lock: packed::BytesOpt,
+) -> Result<TransactionView, UnlockError> {
+    let mut witnesses: Vec<packed::Bytes> = tx.witnesses().into_iter().collect();
+    while witnesses.len() <= witness_idx {
+        witnesses.push(Default::default());
+    }
+    let witness_data = witnesses[witness_idx].raw_data();
+    let witness = if witness_data.is_empty() {
+        WitnessArgs::default()
+    } else {
+        WitnessArgs::from_slice(witness_data.as_ref())
+            .map_err(|_| UnlockError::InvalidWitnessArgs(witness_idx))?
+    };
+    witnesses[witness_idx] = witness.as_builder().lock(lock).build().as_bytes().pack();
+    Ok(tx.as_advanced_builder().set_witnesses(witnesses).build())
+}
@@ -0,0 +1,214 @@
+use anyhow::anyhow;
+use ckb_types::{
+    bytes::Bytes,
+    core::TransactionView,
+    packed::{self, WitnessArgs},
+    prelude::*,
+    H256,
+};
+
+use super::signer::{generate_keccak256_message, ScriptSignError, ScriptSigner};
+use super::unlocker::{fill_witness_lock, ScriptUnlocker, UnlockError};
+use crate::traits::{Signer, SignerError, TransactionDependencyProvider};
+use crate::types::ScriptGroup;
+use crate::util::convert_keccak256_hash;
+
+/// The message of the PW-lock script group, it is the message to sign by
+/// the `personal_sign` of an Ethereum wallet (e.g. MetaMask).
+pub fn generate_pw_lock_message(
+    tx: &TransactionView,
+    script_group: &ScriptGroup,
+) -> Result<Bytes, ScriptSignError> {
+    generate_keccak256_message(tx, script_group, Bytes::from(vec![0u8; 65]))
+}
+
+/// Signer for the PW-lock script with Ethereum identity, the lock args is
+/// the Ethereum address (`keccak160(pubkey)`).
+///
+/// The message is hashed by keccak256 then signed as an Ethereum personal
+/// message: the signer is given
+/// `keccak256("\x19Ethereum Signed Message:\n32" | message)`.
+pub struct PwLockScriptSigner {
+    // Can be: SecpCkbRawKeySigner with ethereum secret keys
+    signer: Box<dyn Signer>,
+}
+
+impl PwLockScriptSigner {
+    pub fn new(signer: Box<dyn Signer>) -> PwLockScriptSigner {
+        PwLockScriptSigner { signer }
+    }
+
+    pub fn signer(&self) -> &dyn Signer {
+        self.signer.as_ref()
+    }
+}
+
+impl ScriptSigner for PwLockScriptSigner {
+    fn match_args(&self, args: &[u8]) -> bool {
+        args.len() == 20 && self.signer.match_id(args)
+    }
+
+    fn sign_tx(
+        &self,
+        tx: &TransactionView,
+        script_group: &ScriptGroup,
+    ) -> Result<TransactionView, ScriptSignError> {
+        let args = script_group.script.args().raw_data();
+        let witness_idx = script_group.input_indices[0];
+        let mut witnesses: Vec<packed::Bytes> = tx.witnesses().into_iter().collect();
+        while witnesses.len() <= witness_idx {
+            witnesses.push(Default::default());
+        }
+        let tx_new = tx
+            .as_advanced_builder()
+            .set_witnesses(witnesses.clone())
+            .build();
+
+        let message = generate_pw_lock_message(&tx_new, script_group)?;
+        let digest: H256 = convert_keccak256_hash(message.as_ref());
+        let signature = self
+            .signer
+            .sign(args.as_ref(), digest.as_bytes(), true, tx)?;
+        if signature.len() != 65 {
+            return Err(ScriptSignError::Signer(SignerError::Other(anyhow!(
+                "invalid signature length, expected: 65, got: {}",
+                signature.len()
+            ))));
+        }
+        // The wallets return the Ethereum `v` (27 or 28), PW-lock expects the
+        // recovery id.
+        let mut signature = signature.to_vec();
+        if signature[64] >= 27 {
+            signature[64] -= 27;
+        }
+
+        // Put signature into witness
+        let witness_data = witnesses[witness_idx].raw_data();
+        let mut current_witness: WitnessArgs = if witness_data.is_empty() {
+            WitnessArgs::default()
+        } else {
+            WitnessArgs::from_slice(witness_data.as_ref())?
+        };
+        current_witness = current_witness
+            .as_builder()
+            .lock(Some(Bytes::from(signature)).pack())
+            .build();
+        witnesses[witness_idx] = current_witness.as_bytes().pack();
+        Ok(tx.as_advanced_builder().set_witnesses(witnesses).build())
+    }
+}
+
+/// Unlocker for the PW-lock script, see [`PwLockScriptSigner`]. Register it
+/// with the PW-lock script id (e.g. the
+/// [`PW_LOCK_TYPE_HASH_LINA`](crate::constants::PW_LOCK_TYPE_HASH_LINA) type
+/// script) to unlock the PW-lock cells.
+pub struct PwLockUnlocker {
+    signer: PwLockScriptSigner,
+}
+impl PwLockUnlocker {
+    pub fn new(signer: PwLockScriptSigner) -> PwLockUnlocker {
+        PwLockUnlocker { signer }
+    }
+}
+impl From<Box<dyn Signer>> for PwLockUnlocker {
+    fn from(signer: Box<dyn Signer>) -> PwLockUnlocker {
+        PwLockUnlocker::new(PwLockScriptSigner::new(signer))
+    }
+}
+impl ScriptUnlocker for PwLockUnlocker {
+    fn match_args(&self, args: &[u8]) -> bool {
+        self.signer.match_args(args)
+    }
+
+    fn unlock(
+        &self,
+        tx: &TransactionView,
+        script_group: &ScriptGroup,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, UnlockError> {
+        Ok(self.signer.sign_tx(tx, script_group)?)
+    }
+
+    fn fill_placeholder_witness(
+        &self,
+        tx: &TransactionView,
+        script_group: &ScriptGroup,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, UnlockError> {
+        fill_witness_lock(tx, script_group, Bytes::from(vec![0u8; 65]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_crypto::secp::Pubkey;
+    use ckb_types::{
+        core::{ScriptHashType, TransactionBuilder},
+        packed::{CellInput, CellOutput, OutPoint, Script},
+    };
+
+    use crate::constants::PW_LOCK_TYPE_HASH_AGGRON;
+    use crate::traits::SecpCkbRawKeySigner;
+    use crate::util::keccak160;
+    use crate::SECP256K1;
+
+    #[test]
+    fn test_pw_lock_unlocker() {
+        let key = secp256k1::SecretKey::from_slice(&[7u8; 32]).unwrap();
+        let pubkey = secp256k1::PublicKey::from_secret_key(&SECP256K1, &key);
+        let address = keccak160(Pubkey::from(pubkey).as_ref());
+        let lock = Script::new_builder()
+            .code_hash(PW_LOCK_TYPE_HASH_AGGRON.pack())
+            .hash_type(ScriptHashType::Type.into())
+            .args(Bytes::from(address.as_bytes().to_vec()).pack())
+            .build();
+        let tx = TransactionBuilder::default()
+            .input(CellInput::new(OutPoint::new(H256([1u8; 32]).pack(), 0), 0))
+            .input(CellInput::new(OutPoint::new(H256([1u8; 32]).pack(), 1), 0))
+            .output(CellOutput::new_builder().lock(lock.clone()).build())
+            .output_data(Bytes::new().pack())
+            .build();
+        let mut script_group = ScriptGroup::from_lock_script(&lock);
+        script_group.input_indices = vec![0, 1];
+
+        let signer = SecpCkbRawKeySigner::new_with_ethereum_secret_keys(vec![key]);
+        let unlocker = PwLockUnlocker::from(Box::new(signer) as Box<dyn Signer>);
+        assert!(unlocker.match_args(address.as_bytes()));
+        assert!(!unlocker.match_args(&[0u8; 20]));
+        // The sighash id of the same key does not match
+        let sighash_signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![key]);
+        assert!(
+            !PwLockUnlocker::from(Box::new(sighash_signer) as Box<dyn Signer>)
+                .match_args(address.as_bytes())
+        );
+
+        let signed_tx = unlocker.signer.sign_tx(&tx, &script_group).unwrap();
+        let witness =
+            WitnessArgs::from_slice(&signed_tx.witnesses().get(0).unwrap().raw_data()).unwrap();
+        let signature = witness.lock().to_opt().unwrap().raw_data();
+        assert_eq!(signature.len(), 65);
+        assert!(signature[64] < 2);
+
+        // Recover the Ethereum address from the personal message digest
+        let message = generate_pw_lock_message(&signed_tx, &script_group).unwrap();
+        let digest = convert_keccak256_hash(message.as_ref());
+        let recoverable = secp256k1::ecdsa::RecoverableSignature::from_compact(
+            &signature[0..64],
+            secp256k1::ecdsa::RecoveryId::from_i32(signature[64] as i32).unwrap(),
+        )
+        .unwrap();
+        let recovered = SECP256K1
+            .recover_ecdsa(
+                &secp256k1::Message::from_digest_slice(digest.as_bytes()).unwrap(),
+                &recoverable,
+            )
+            .unwrap();
+        assert_eq!(keccak160(Pubkey::from(recovered).as_ref()), address);
+        // Not the blake2b message of the sighash lock
+        let blake2b_message =
+            crate::unlock::generate_message(&signed_tx, &script_group, Bytes::from(vec![0u8; 65]))
+                .unwrap();
+        assert_ne!(blake2b_message, message);
+    }
+}
@@ -8,19 +8,20 @@ use ckb_types::{
     error::VerificationError,
     packed::{self, BytesOpt, Script, WitnessArgs},
     prelude::*,
-    H160,
+    H160, H256,
 };
 use serde::{Deserialize, Serialize};
+use sha3::{Digest, Keccak256};
 use thiserror::Error;
 
 use crate::{constants::MULTISIG_TYPE_HASH, types::omni_lock::OmniLockWitnessLock};
 use crate::{
-    traits::{Signer, SignerError},
-    util::convert_keccak256_hash,
+    traits::{Signer, SignerError, WitnessSizeEstimator},
+    util::{convert_btc_hash, convert_keccak256_hash, convert_tron_hash},
 };
 use crate::{
     types::{AddressPayload, CodeHashIndex, ScriptGroup, Since},
-    Address, NetworkType,
+    Address, NetworkType, SECP256K1,
 };
 
 use super::{
@@ -138,7 +139,9 @@ impl ScriptSigner for SecpSighashScriptSigner {
 }
 
 #[derive(Eq, PartialEq, Clone, Hash, Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct MultisigConfig {
+    #[cfg_attr(feature = "schema", schemars(with = "Vec<String>"))]
     sighash_addresses: Vec<H160>,
     require_first_n: u8,
     threshold: u8,
@@ -244,6 +247,14 @@ impl MultisigConfig {
     }
 }
 
+/// The final witness holds `threshold` signatures, the lock args (the
+/// multisig config hash) are not checked.
+impl WitnessSizeEstimator for MultisigConfig {
+    fn estimated_witness_size(&self, _lock_script: &Script) -> Option<usize> {
+        Some(self.placeholder_witness().as_slice().len())
+    }
+}
+
 impl From<&MultisigConfig> for Script {
     fn from(value: &MultisigConfig) -> Self {
         Script::new_builder()
@@ -441,6 +452,69 @@ impl ScriptSigner for ChequeScriptSigner {
     }
 }
 
+/// Convert the `r | s | recovery id` signature to the `header | r | s`
+/// format of the Bitcoin style message signing (EOS, Bitcoin), the header is
+/// `27 + recovery id`, plus 4 if the public key is compressed.
+fn to_header_signature(signature: &[u8], compressed: bool) -> Result<Bytes, ScriptSignError> {
+    if signature.len() != 65 || signature[64] > 3 {
+        return Err(ScriptSignError::Other(anyhow!(
+            "invalid recoverable signature, length: {}",
+            signature.len()
+        )));
+    }
+    let mut data = BytesMut::with_capacity(65);
+    data.extend_from_slice(&[27 + signature[64] + if compressed { 4 } else { 0 }]);
+    data.extend_from_slice(&signature[0..64]);
+    Ok(data.freeze())
+}
+
+/// Whether the bitcoin pubkey hash is the hash160 of the compressed public
+/// key recovered from the signature (otherwise of the uncompressed one).
+#[cfg(feature = "bitcoin")]
+fn is_compressed_pubkey_hash(
+    message: &H256,
+    signature: &[u8],
+    pubkey_hash: &H160,
+) -> Result<bool, ScriptSignError> {
+    if signature.len() != 65 {
+        return Err(ScriptSignError::Other(anyhow!(
+            "invalid recoverable signature, length: {}",
+            signature.len()
+        )));
+    }
+    let recid = secp256k1::ecdsa::RecoveryId::from_i32(i32::from(signature[64]))
+        .map_err(|err| anyhow!("invalid recovery id: {}", err))?;
+    let recoverable =
+        secp256k1::ecdsa::RecoverableSignature::from_compact(&signature[0..64], recid)
+            .map_err(|err| anyhow!("invalid signature: {}", err))?;
+    let message = secp256k1::Message::from_digest_slice(message.as_bytes())
+        .map_err(|err| anyhow!("invalid message: {}", err))?;
+    let pubkey = SECP256K1
+        .recover_ecdsa(&message, &recoverable)
+        .map_err(|err| anyhow!("recover public key failed: {}", err))?;
+    if &crate::util::hash160(&pubkey.serialize()) == pubkey_hash {
+        Ok(true)
+    } else if &crate::util::hash160(&pubkey.serialize_uncompressed()) == pubkey_hash {
+        Ok(false)
+    } else {
+        Err(ScriptSignError::Other(anyhow!(
+            "the signature does not match the bitcoin pubkey hash: {:#x}",
+            pubkey_hash
+        )))
+    }
+}
+
+#[cfg(not(feature = "bitcoin"))]
+fn is_compressed_pubkey_hash(
+    _message: &H256,
+    _signature: &[u8],
+    _pubkey_hash: &H160,
+) -> Result<bool, ScriptSignError> {
+    Err(ScriptSignError::Other(anyhow!(
+        "the bitcoin identity requires the `bitcoin` feature"
+    )))
+}
+
 /// Common logic of generate message for certain script group. Overwrite
 /// this method to support special use case.
 pub fn generate_message(
@@ -448,6 +522,35 @@ pub fn generate_message(
     script_group: &ScriptGroup,
     zero_lock: Bytes,
 ) -> Result<Bytes, ScriptSignError> {
+    let mut blake2b = new_blake2b();
+    update_message_hasher(tx, script_group, zero_lock, &mut |data: &[u8]| {
+        blake2b.update(data)
+    })?;
+    let mut message = vec![0u8; 32];
+    blake2b.finalize(&mut message);
+    Ok(Bytes::from(message))
+}
+
+/// Same as [`generate_message`] but hashed by keccak256, the message of
+/// the Ethereum style lock scripts (e.g. PW-lock).
+pub fn generate_keccak256_message(
+    tx: &TransactionView,
+    script_group: &ScriptGroup,
+    zero_lock: Bytes,
+) -> Result<Bytes, ScriptSignError> {
+    let mut hasher = Keccak256::new();
+    update_message_hasher(tx, script_group, zero_lock, &mut |data: &[u8]| {
+        hasher.update(data)
+    })?;
+    Ok(Bytes::from(hasher.finalize().to_vec()))
+}
+
+fn update_message_hasher(
+    tx: &TransactionView,
+    script_group: &ScriptGroup,
+    zero_lock: Bytes,
+    update: &mut dyn FnMut(&[u8]),
+) -> Result<(), ScriptSignError> {
     if tx.witnesses().item_count() <= script_group.input_indices[0] {
         return Err(ScriptSignError::WitnessNotEnough);
     }
@@ -491,21 +594,18 @@ pub fn generate_message(
         Default::default()
     };
 
-    let mut blake2b = new_blake2b();
-    blake2b.update(tx.hash().as_slice());
-    blake2b.update(&(init_witness.as_bytes().len() as u64).to_le_bytes());
-    blake2b.update(&init_witness.as_bytes());
+    update(tx.hash().as_slice());
+    update(&(init_witness.as_bytes().len() as u64).to_le_bytes());
+    update(&init_witness.as_bytes());
     for (len_le, data) in other_witnesses {
-        blake2b.update(&len_le);
-        blake2b.update(&data);
+        update(&len_le);
+        update(&data);
     }
     for (len_le, data) in outter_witnesses {
-        blake2b.update(&len_le);
-        blake2b.update(&data);
+        update(&len_le);
+        update(&data);
     }
-    let mut message = vec![0u8; 32];
-    blake2b.finalize(&mut message);
-    Ok(Bytes::from(message))
+    Ok(())
 }
 
 /// specify the unlock mode for a omnilock transaction.
@@ -640,7 +740,10 @@ impl OmniLockScriptSigner {
         Ok(tx.as_advanced_builder().set_witnesses(witnesses).build())
     }
 
-    fn sign_ethereum_tx(
+    /// Sign the digest converted from the message in the convention of the
+    /// identity (ethereum, tron, eos or bitcoin), then convert the signature to the
+    /// format of the identity.
+    fn sign_converted_tx(
         &self,
         tx: &TransactionView,
         script_group: &ScriptGroup,
@@ -658,11 +761,32 @@ impl OmniLockScriptSigner {
 
         let zero_lock = self.config.zero_lock(self.unlock_mode())?;
         let message = generate_message(&tx_new, script_group, zero_lock)?;
-        let message = convert_keccak256_hash(message.as_ref());
+        let message = match id.flag() {
+            IdentityFlag::Ethereum => convert_keccak256_hash(message.as_ref()),
+            IdentityFlag::Tron => convert_tron_hash(message.as_ref()),
+            IdentityFlag::Bitcoin => convert_btc_hash(message.as_ref()),
+            // EOS signs the message as is
+            IdentityFlag::Eos => H256::from_slice(message.as_ref()).unwrap(),
+            flag => {
+                return Err(ScriptSignError::Other(anyhow!(
+                    "identity flag {:?} has no message convention",
+                    flag
+                )))
+            }
+        };
 
         let signature = self
             .signer
             .sign(id.auth_content().as_ref(), message.as_ref(), true, tx)?;
+        let signature = match id.flag() {
+            IdentityFlag::Eos => to_header_signature(&signature, true)?,
+            IdentityFlag::Bitcoin => {
+                let compressed =
+                    is_compressed_pubkey_hash(&message, &signature, id.auth_content())?;
+                to_header_signature(&signature, compressed)?
+            }
+            _ => signature,
+        };
 
         // Put signature into witness
         let witness_data = witnesses[witness_idx].raw_data();
@@ -730,7 +854,11 @@ impl ScriptSigner for OmniLockScriptSigner {
             return false;
         }
         match self.config.id().flag() {
-            IdentityFlag::PubkeyHash | IdentityFlag::Ethereum => self
+            IdentityFlag::PubkeyHash
+            | IdentityFlag::Ethereum
+            | IdentityFlag::Tron
+            | IdentityFlag::Eos
+            | IdentityFlag::Bitcoin => self
                 .signer
                 .match_id(self.config.id().auth_content().as_ref()),
             IdentityFlag::Multisig => {
@@ -799,7 +927,10 @@ impl ScriptSigner for OmniLockScriptSigner {
                 witnesses[witness_idx] = current_witness.as_bytes().pack();
                 Ok(tx.as_advanced_builder().set_witnesses(witnesses).build())
             }
-            IdentityFlag::Ethereum => self.sign_ethereum_tx(tx, script_group, &id),
+            IdentityFlag::Ethereum
+            | IdentityFlag::Tron
+            | IdentityFlag::Eos
+            | IdentityFlag::Bitcoin => self.sign_converted_tx(tx, script_group, &id),
             IdentityFlag::Multisig => self.sign_multisig_tx(tx, script_group),
             IdentityFlag::OwnerLock => {
                 // should not reach here, just return a clone for compatible reason.
@@ -4,6 +4,7 @@ use ckb_types::{
     core::TransactionView,
     packed::{self, Byte32, BytesOpt, WitnessArgs},
     prelude::*,
+    H256,
 };
 use thiserror::Error;
 
@@ -13,10 +14,11 @@ use super::{
         AcpScriptSigner, ChequeAction, ChequeScriptSigner, MultisigConfig, ScriptSignError,
         ScriptSigner, SecpMultisigScriptSigner, SecpSighashScriptSigner,
     },
+    verifier::dedup_group_multisig_witnesses,
     OmniLockConfig, OmniLockScriptSigner, OmniUnlockMode,
 };
 use crate::traits::{Signer, TransactionDependencyError, TransactionDependencyProvider};
-use crate::types::ScriptGroup;
+use crate::types::{ScriptGroup, Since};
 
 const CHEQUE_CLAIM_SINCE: u64 = 0;
 const CHEQUE_WITHDRAW_SINCE: u64 = 0xA000000000000006;
@@ -38,6 +40,9 @@ pub enum UnlockError {
     #[error("sign context is incorrect")]
     SignContextTypeIncorrect,
 
+    #[error("lock script `{0:#x}` is frozen: `{1}`")]
+    FrozenLockScript(H256, String),
+
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
@@ -212,7 +217,9 @@ impl ScriptUnlocker for SecpMultisigUnlocker {
         let config_data = config.to_witness_data();
         let mut zero_lock = vec![0u8; config_data.len() + 65 * (config.threshold() as usize)];
         zero_lock[0..config_data.len()].copy_from_slice(&config_data);
-        fill_witness_lock(tx, script_group, Bytes::from(zero_lock))
+        // The config is only needed in the first witness of the group
+        let tx = dedup_group_multisig_witnesses(tx, script_group)?;
+        fill_witness_lock(&tx, script_group, Bytes::from(zero_lock))
     }
 }
 
@@ -615,6 +622,66 @@ impl OmniLockUnlocker {
     pub fn new(signer: OmniLockScriptSigner, config: OmniLockConfig) -> OmniLockUnlocker {
         OmniLockUnlocker { signer, config }
     }
+
+    /// Check the constraints of the time lock and supply modes, the omni lock
+    /// script rejects the transaction otherwise:
+    ///   * TIME_LOCK: every input of the script group must set a since value
+    ///     satisfying the since value in the args.
+    ///   * SUPPLY: the info cell in the inputs must be kept in the outputs.
+    fn check_flags(
+        &self,
+        tx: &TransactionView,
+        script_group: &ScriptGroup,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<(), UnlockError> {
+        if let Some(since) = self.config.get_time_lock_config() {
+            let required = Since::from_raw_value(since);
+            for idx in &script_group.input_indices {
+                let input = tx
+                    .inputs()
+                    .get(*idx)
+                    .ok_or_else(|| UnlockError::Other(anyhow!("input {} not found", idx)))?;
+                let input_since = Since::from_raw_value(input.since().unpack());
+                if !input_since.satisfies(required) {
+                    return Err(UnlockError::Other(anyhow!(
+                        "since of input {} ({:#x}) does not satisfy the time lock ({:#x})",
+                        idx,
+                        input_since.value(),
+                        since
+                    )));
+                }
+            }
+        }
+        if let Some(info_cell) = self.config.get_info_cell() {
+            let info_cell_hash: Byte32 = info_cell.pack();
+            let has_info_cell = |output: &packed::CellOutput| {
+                output
+                    .type_()
+                    .to_opt()
+                    .map(|script| script.calc_script_hash() == info_cell_hash)
+                    .unwrap_or(false)
+            };
+            let mut in_inputs = false;
+            for out_point in tx.input_pts_iter() {
+                if has_info_cell(&tx_dep_provider.get_cell(&out_point)?) {
+                    in_inputs = true;
+                    break;
+                }
+            }
+            if in_inputs
+                && !tx
+                    .outputs()
+                    .into_iter()
+                    .any(|output| has_info_cell(&output))
+            {
+                return Err(UnlockError::Other(anyhow!(
+                    "info cell {:#x} is consumed but not found in the outputs",
+                    info_cell
+                )));
+            }
+        }
+        Ok(())
+    }
 }
 impl From<(Box<dyn Signer>, OmniLockConfig, OmniUnlockMode)> for OmniLockUnlocker {
     fn from(
@@ -709,8 +776,9 @@ impl ScriptUnlocker for OmniLockUnlocker {
         &self,
         tx: &TransactionView,
         script_group: &ScriptGroup,
-        _tx_dep_provider: &dyn TransactionDependencyProvider,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
     ) -> Result<TransactionView, UnlockError> {
+        self.check_flags(tx, script_group, tx_dep_provider)?;
         Ok(self.signer.sign_tx(tx, script_group)?)
     }
 
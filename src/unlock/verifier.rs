@@ -0,0 +1,348 @@
+use std::collections::HashSet;
+
+use ckb_types::{
+    bytes::Bytes,
+    core::{ScriptHashType, TransactionView},
+    packed::{BytesOpt, Script, WitnessArgs},
+    prelude::*,
+    H160, H256,
+};
+use secp256k1::ecdsa::{RecoverableSignature, RecoveryId};
+
+use super::{generate_message, UnlockError};
+use crate::constants::{
+    ACP_TYPE_HASH_AGGRON, ACP_TYPE_HASH_LINA, MULTISIG_TYPE_HASH, SECP_SIGNATURE_SIZE,
+    SIGHASH_TYPE_HASH,
+};
+use crate::traits::TransactionDependencyProvider;
+use crate::tx_builder::{gen_script_groups, ScriptGroups};
+use crate::types::ScriptGroup;
+use crate::util::blake160;
+use crate::SECP256K1;
+
+/// The signature status of a lock script group
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum SignatureStatus {
+    /// All required signatures are present and valid
+    Valid,
+    /// Some of the multisig signatures are present and all of them are valid
+    Partial { signed: u8, threshold: u8 },
+    /// No signature in the witness (empty or placeholder)
+    Missing,
+    /// The witness contains an invalid signature
+    Invalid(String),
+    /// The lock script is not supported by the verifier
+    Unsupported,
+}
+
+impl SignatureStatus {
+    pub fn is_valid(&self) -> bool {
+        *self == SignatureStatus::Valid
+    }
+}
+
+/// The signature verification result of a lock script group
+#[derive(Clone, Debug)]
+pub struct GroupSignatureReport {
+    pub script_group: ScriptGroup,
+    pub status: SignatureStatus,
+}
+
+/// Check the signatures already in the witnesses for all lock script groups,
+/// without executing the scripts.
+///
+/// The secp256k1 sighash, the legacy secp256k1 multisig and the
+/// anyone-can-pay (signature path) locks are supported, the other lock
+/// groups are reported as [`SignatureStatus::Unsupported`]. This is useful to
+/// validate the partial signatures from counterparties before merging them.
+/// The result is ordered by the first input index of the groups.
+pub fn verify_signatures(
+    tx: &TransactionView,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+) -> Result<Vec<GroupSignatureReport>, UnlockError> {
+    let ScriptGroups { lock_groups, .. } = gen_script_groups(tx, tx_dep_provider)?;
+    let mut script_groups: Vec<_> = lock_groups.into_values().collect();
+    script_groups.sort_by_key(|group| group.input_indices[0]);
+    Ok(script_groups
+        .into_iter()
+        .map(|script_group| {
+            let status = verify_group_signature(tx, &script_group);
+            GroupSignatureReport {
+                script_group,
+                status,
+            }
+        })
+        .collect())
+}
+
+fn verify_group_signature(tx: &TransactionView, script_group: &ScriptGroup) -> SignatureStatus {
+    let script = &script_group.script;
+    let args = script.args().raw_data();
+    if is_type_script(script, &SIGHASH_TYPE_HASH) && args.len() == 20 {
+        verify_sighash(tx, script_group, &args)
+    } else if (is_type_script(script, &ACP_TYPE_HASH_LINA)
+        || is_type_script(script, &ACP_TYPE_HASH_AGGRON))
+        && (20..=22).contains(&args.len())
+    {
+        verify_sighash(tx, script_group, &args[0..20])
+    } else if is_type_script(script, &MULTISIG_TYPE_HASH) && (args.len() == 20 || args.len() == 28)
+    {
+        verify_multisig(tx, script_group, &args[0..20])
+    } else {
+        SignatureStatus::Unsupported
+    }
+}
+
+fn is_type_script(script: &Script, code_hash: &H256) -> bool {
+    script.hash_type() == ScriptHashType::Type.into() && script.code_hash() == code_hash.pack()
+}
+
+pub(crate) fn witness_lock(
+    tx: &TransactionView,
+    script_group: &ScriptGroup,
+) -> Result<Bytes, String> {
+    let witness_data = tx
+        .witnesses()
+        .get(script_group.input_indices[0])
+        .map(|witness| witness.raw_data())
+        .unwrap_or_default();
+    if witness_data.is_empty() {
+        return Ok(Bytes::new());
+    }
+    let witness = WitnessArgs::from_slice(witness_data.as_ref())
+        .map_err(|err| format!("invalid witness args: {}", err))?;
+    Ok(witness
+        .lock()
+        .to_opt()
+        .map(|lock| lock.raw_data())
+        .unwrap_or_default())
+}
+
+fn recover_pubkey_hash(message: &[u8], signature: &[u8]) -> Result<H160, String> {
+    let recid = RecoveryId::from_i32(i32::from(signature[64]))
+        .map_err(|err| format!("invalid recovery id: {}", err))?;
+    let signature = RecoverableSignature::from_compact(&signature[0..64], recid)
+        .map_err(|err| format!("invalid signature: {}", err))?;
+    let message = secp256k1::Message::from_digest_slice(message)
+        .map_err(|err| format!("invalid message: {}", err))?;
+    let pubkey = SECP256K1
+        .recover_ecdsa(&message, &signature)
+        .map_err(|err| format!("recover public key failed: {}", err))?;
+    Ok(blake160(&pubkey.serialize()))
+}
+
+fn verify_sighash(
+    tx: &TransactionView,
+    script_group: &ScriptGroup,
+    pubkey_hash: &[u8],
+) -> SignatureStatus {
+    let lock = match witness_lock(tx, script_group) {
+        Ok(lock) => lock,
+        Err(err) => return SignatureStatus::Invalid(err),
+    };
+    if lock.is_empty() || lock.iter().all(|byte| *byte == 0) {
+        return SignatureStatus::Missing;
+    }
+    if lock.len() != SECP_SIGNATURE_SIZE {
+        return SignatureStatus::Invalid(format!("invalid signature length: {}", lock.len()));
+    }
+    let zero_lock = Bytes::from(vec![0u8; SECP_SIGNATURE_SIZE]);
+    let message = match generate_message(tx, script_group, zero_lock) {
+        Ok(message) => message,
+        Err(err) => return SignatureStatus::Invalid(err.to_string()),
+    };
+    match recover_pubkey_hash(message.as_ref(), lock.as_ref()) {
+        Ok(hash) if hash.as_bytes() == pubkey_hash => SignatureStatus::Valid,
+        Ok(hash) => SignatureStatus::Invalid(format!("signed by other key: {:#x}", hash)),
+        Err(err) => SignatureStatus::Invalid(err),
+    }
+}
+
+fn verify_multisig(
+    tx: &TransactionView,
+    script_group: &ScriptGroup,
+    config_hash: &[u8],
+) -> SignatureStatus {
+    let lock = match witness_lock(tx, script_group) {
+        Ok(lock) => lock,
+        Err(err) => return SignatureStatus::Invalid(err),
+    };
+    if lock.is_empty() {
+        return SignatureStatus::Missing;
+    }
+    let (threshold, signatures) =
+        match recover_multisig_signers(tx, script_group, lock.as_ref(), config_hash) {
+            Ok(result) => result,
+            Err(err) => return SignatureStatus::Invalid(err),
+        };
+    match signatures.len() {
+        0 => SignatureStatus::Missing,
+        count if count == threshold as usize => SignatureStatus::Valid,
+        count => SignatureStatus::Partial {
+            signed: count as u8,
+            threshold,
+        },
+    }
+}
+
+/// Recover the signers of the signatures in a multisig witness lock, the
+/// message is generated from `tx` (the lock field of the group's witness is
+/// ignored).
+///
+/// Return the threshold of the multisig config and the (signer, signature)
+/// pairs in the lock order, the empty signature slots are skipped.
+pub(crate) fn recover_multisig_signers(
+    tx: &TransactionView,
+    script_group: &ScriptGroup,
+    lock: &[u8],
+    config_hash: &[u8],
+) -> Result<(u8, Vec<(H160, Bytes)>), String> {
+    // reserved(1) + require_first_n(1) + threshold(1) + pubkeys_count(1)
+    if lock.len() < 4 || lock[0] != 0 {
+        return Err("invalid multisig config".to_string());
+    }
+    let threshold = lock[2];
+    let config_len = 4 + lock[3] as usize * 20;
+    if threshold == 0
+        || lock.len() != config_len + threshold as usize * SECP_SIGNATURE_SIZE
+        || blake160(&lock[0..config_len]).as_bytes() != config_hash
+    {
+        return Err("multisig config not match the lock args".to_string());
+    }
+    let pubkey_hashes: Vec<&[u8]> = lock[4..config_len].chunks(20).collect();
+    let mut zero_lock = lock[0..config_len].to_vec();
+    zero_lock.resize(lock.len(), 0);
+    let message = generate_message(tx, script_group, Bytes::from(zero_lock))
+        .map_err(|err| err.to_string())?;
+    let mut signed = HashSet::new();
+    let mut signatures = Vec::new();
+    for signature in lock[config_len..].chunks(SECP_SIGNATURE_SIZE) {
+        if signature.iter().all(|byte| *byte == 0) {
+            continue;
+        }
+        let hash = recover_pubkey_hash(message.as_ref(), signature)?;
+        if !pubkey_hashes.contains(&hash.as_bytes()) {
+            return Err(format!("signer not in multisig config: {:#x}", hash));
+        }
+        if !signed.insert(hash.clone()) {
+            return Err(format!("duplicated signature of {:#x}", hash));
+        }
+        signatures.push((hash, Bytes::from(signature.to_vec())));
+    }
+    Ok((threshold, signatures))
+}
+
+/// A witness which repeats the multisig config of its lock script group.
+///
+/// The multisig lock only reads the lock field of the first witness of a
+/// group, the config in the other witnesses of the group is never used and
+/// just makes the transaction (and the fee) bigger.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct DuplicatedMultisigConfig {
+    /// The multisig lock script
+    pub script: Script,
+    /// The input (witness) index of the duplicated config
+    pub input_index: usize,
+    /// The size of the lock field carrying the duplicated config
+    pub wasted_size: usize,
+}
+
+/// Find the witnesses which repeat the multisig config of their lock script
+/// groups (not the first witness of the group), see
+/// [`DuplicatedMultisigConfig`].
+pub fn find_duplicated_multisig_configs(
+    tx: &TransactionView,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+) -> Result<Vec<DuplicatedMultisigConfig>, UnlockError> {
+    let ScriptGroups { lock_groups, .. } = gen_script_groups(tx, tx_dep_provider)?;
+    let mut duplicated: Vec<_> = lock_groups
+        .values()
+        .flat_map(|script_group| group_duplicated_multisig_configs(tx, script_group))
+        .collect();
+    duplicated.sort_by_key(|item| item.input_index);
+    Ok(duplicated)
+}
+
+/// Remove the duplicated multisig configs found by
+/// [`find_duplicated_multisig_configs`], the other fields of the witnesses
+/// are kept. The signatures cover all the witnesses of the group, so this
+/// must be done before signing.
+pub fn dedup_multisig_witnesses(
+    tx: &TransactionView,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+) -> Result<TransactionView, UnlockError> {
+    let duplicated = find_duplicated_multisig_configs(tx, tx_dep_provider)?;
+    remove_witness_locks(tx, &duplicated)
+}
+
+/// Same as [`dedup_multisig_witnesses`], only for one lock script group
+pub(crate) fn dedup_group_multisig_witnesses(
+    tx: &TransactionView,
+    script_group: &ScriptGroup,
+) -> Result<TransactionView, UnlockError> {
+    remove_witness_locks(tx, &group_duplicated_multisig_configs(tx, script_group))
+}
+
+fn group_duplicated_multisig_configs(
+    tx: &TransactionView,
+    script_group: &ScriptGroup,
+) -> Vec<DuplicatedMultisigConfig> {
+    let script = &script_group.script;
+    let args = script.args().raw_data();
+    if !is_type_script(script, &MULTISIG_TYPE_HASH) || !(args.len() == 20 || args.len() == 28) {
+        return Vec::new();
+    }
+    let mut duplicated = Vec::new();
+    for input_index in script_group.input_indices.iter().skip(1) {
+        let lock = match other_witness_lock(tx, *input_index) {
+            Some(lock) => lock,
+            None => continue,
+        };
+        if lock.len() < 4 || lock[0] != 0 {
+            continue;
+        }
+        let config_len = 4 + lock[3] as usize * 20;
+        if lock.len() >= config_len && blake160(&lock[0..config_len]).as_bytes() == &args[0..20] {
+            duplicated.push(DuplicatedMultisigConfig {
+                script: script.clone(),
+                input_index: *input_index,
+                wasted_size: lock.len(),
+            });
+        }
+    }
+    duplicated
+}
+
+fn remove_witness_locks(
+    tx: &TransactionView,
+    duplicated: &[DuplicatedMultisigConfig],
+) -> Result<TransactionView, UnlockError> {
+    if duplicated.is_empty() {
+        return Ok(tx.clone());
+    }
+    let mut witnesses: Vec<_> = tx.witnesses().into_iter().collect();
+    for item in duplicated {
+        let witness = WitnessArgs::from_slice(&witnesses[item.input_index].raw_data())
+            .map_err(|_| UnlockError::InvalidWitnessArgs(item.input_index))?
+            .as_builder()
+            .lock(BytesOpt::default())
+            .build();
+        witnesses[item.input_index] = if witness == WitnessArgs::default() {
+            Default::default()
+        } else {
+            witness.as_bytes().pack()
+        };
+    }
+    Ok(tx.as_advanced_builder().set_witnesses(witnesses).build())
+}
+
+/// The lock field of the witness at `index`, `None` if not a `WitnessArgs`
+/// or without lock.
+fn other_witness_lock(tx: &TransactionView, index: usize) -> Option<Bytes> {
+    let witness_data = tx.witnesses().get(index)?.raw_data();
+    WitnessArgs::from_slice(witness_data.as_ref())
+        .ok()?
+        .lock()
+        .to_opt()
+        .map(|lock| lock.raw_data())
+}
@@ -2,14 +2,17 @@ use std::{convert::TryInto, ptr, sync::atomic};
 
 use ckb_dao_utils::extract_dao_data;
 use ckb_types::{
-    core::{Capacity, EpochNumber, EpochNumberWithFraction, HeaderView},
-    packed::CellOutput,
+    core::{Capacity, EpochNumber, EpochNumberWithFraction, HeaderView, RationalU256},
+    packed::{CellInput, CellOutput},
     prelude::*,
     H160, H256, U256,
 };
+#[cfg(feature = "bitcoin")]
+use ripemd::Ripemd160;
+use sha2::Sha256;
 use sha3::{Digest, Keccak256};
 
-use crate::rpc::CkbRpcClient;
+use crate::rpc::{AsyncCkbRpcClient, CkbRpcClient};
 use crate::traits::LiveCell;
 
 use secp256k1::ffi::CPtr;
@@ -41,40 +44,96 @@ pub fn get_max_mature_number(rpc_client: &CkbRpcClient) -> Result<u64, String> {
         .get_tip_header()
         .map(|header| EpochNumberWithFraction::from_full_value(header.inner.epoch.value()))
         .map_err(|err| err.to_string())?;
+    get_max_mature_number_at(rpc_client, tip_epoch, cellbase_maturity)
+}
+
+/// Same as [`get_max_mature_number`] with known tip epoch and cellbase
+/// maturity
+pub fn get_max_mature_number_at(
+    rpc_client: &CkbRpcClient,
+    tip_epoch: EpochNumberWithFraction,
+    cellbase_maturity: EpochNumberWithFraction,
+) -> Result<u64, String> {
+    match max_mature_epoch(tip_epoch, cellbase_maturity) {
+        // No cellbase live cell is mature
+        None => Ok(0),
+        Some((epoch_number, fraction)) => {
+            let max_mature_epoch = rpc_client
+                .get_epoch_by_number(epoch_number.into())
+                .map_err(|err| err.to_string())?
+                .ok_or_else(|| "Can not get epoch less than current epoch number".to_string())?;
+            Ok(block_number_in_epoch(
+                fraction,
+                max_mature_epoch.start_number.value(),
+                max_mature_epoch.length.value(),
+            ))
+        }
+    }
+}
+
+/// Async version of [`get_max_mature_number`]
+pub async fn get_max_mature_number_async(rpc_client: &AsyncCkbRpcClient) -> Result<u64, String> {
+    let cellbase_maturity = EpochNumberWithFraction::from_full_value(
+        rpc_client
+            .get_consensus()
+            .await
+            .map_err(|err| err.to_string())?
+            .cellbase_maturity
+            .value(),
+    );
+    let tip_epoch = rpc_client
+        .get_tip_header()
+        .await
+        .map(|header| EpochNumberWithFraction::from_full_value(header.inner.epoch.value()))
+        .map_err(|err| err.to_string())?;
+    match max_mature_epoch(tip_epoch, cellbase_maturity) {
+        // No cellbase live cell is mature
+        None => Ok(0),
+        Some((epoch_number, fraction)) => {
+            let max_mature_epoch = rpc_client
+                .get_epoch_by_number(epoch_number.into())
+                .await
+                .map_err(|err| err.to_string())?
+                .ok_or_else(|| "Can not get epoch less than current epoch number".to_string())?;
+            Ok(block_number_in_epoch(
+                fraction,
+                max_mature_epoch.start_number.value(),
+                max_mature_epoch.length.value(),
+            ))
+        }
+    }
+}
 
+/// The epoch number of the max mature block and its fraction within the
+/// epoch, `None` if no cellbase cell is mature yet.
+fn max_mature_epoch(
+    tip_epoch: EpochNumberWithFraction,
+    cellbase_maturity: EpochNumberWithFraction,
+) -> Option<(EpochNumber, RationalU256)> {
     let tip_epoch_rational = tip_epoch.to_rational();
     let cellbase_maturity_rational = cellbase_maturity.to_rational();
-
     if tip_epoch_rational < cellbase_maturity_rational {
-        // No cellbase live cell is mature
-        Ok(0)
-    } else {
-        let difference = tip_epoch_rational - cellbase_maturity_rational;
-        let rounds_down_difference = difference.clone().into_u256();
-        let difference_delta = difference - rounds_down_difference.clone();
-
-        let epoch_number = u64::from_le_bytes(
-            rounds_down_difference.to_le_bytes()[..8]
-                .try_into()
-                .expect("should be u64"),
-        )
-        .into();
-        let max_mature_epoch = rpc_client
-            .get_epoch_by_number(epoch_number)
-            .map_err(|err| err.to_string())?
-            .ok_or_else(|| "Can not get epoch less than current epoch number".to_string())?;
-
-        let max_mature_block_number = (difference_delta
-            * U256::from(max_mature_epoch.length.value())
-            + U256::from(max_mature_epoch.start_number.value()))
-        .into_u256();
-
-        Ok(u64::from_le_bytes(
-            max_mature_block_number.to_le_bytes()[..8]
-                .try_into()
-                .expect("should be u64"),
-        ))
+        return None;
     }
+    let difference = tip_epoch_rational - cellbase_maturity_rational;
+    let rounds_down_difference = difference.clone().into_u256();
+    let difference_delta = difference - rounds_down_difference.clone();
+    let epoch_number = u64::from_le_bytes(
+        rounds_down_difference.to_le_bytes()[..8]
+            .try_into()
+            .expect("should be u64"),
+    );
+    Some((epoch_number, difference_delta))
+}
+
+/// The block number at `fraction` of the epoch
+fn block_number_in_epoch(fraction: RationalU256, start_number: u64, length: u64) -> u64 {
+    let block_number = (fraction * U256::from(length) + U256::from(start_number)).into_u256();
+    u64::from_le_bytes(
+        block_number.to_le_bytes()[..8]
+            .try_into()
+            .expect("should be u64"),
+    )
 }
 
 pub fn is_mature(info: &LiveCell, max_mature_number: u64) -> bool {
@@ -156,6 +215,46 @@ pub fn convert_keccak256_hash(message: &[u8]) -> H256 {
     H256::from_slice(r.as_slice()).expect("convert_keccak256_hash")
 }
 
+/// Do a tron style message convert before do a signature.
+pub fn convert_tron_hash(message: &[u8]) -> H256 {
+    let tron_prefix: &[u8; 24] = b"\x19TRON Signed Message:\n32";
+    let mut hasher = Keccak256::new();
+    hasher.update(tron_prefix);
+    hasher.update(message);
+    let r = hasher.finalize();
+    H256::from_slice(r.as_slice()).expect("convert_tron_hash")
+}
+
+/// Do a bitcoin style public key hash: `ripemd160(sha256(pubkey))`.
+#[cfg(feature = "bitcoin")]
+pub fn hash160(message: &[u8]) -> H160 {
+    let r = Ripemd160::digest(Sha256::digest(message));
+    H160::from_slice(r.as_slice()).expect("hash160")
+}
+
+/// Do a bitcoin style message convert before do a signature, the double
+/// sha256 of the message prefixed by "Bitcoin Signed Message".
+pub fn convert_btc_hash(message: &[u8]) -> H256 {
+    let btc_prefix: &[u8; 25] = b"\x18Bitcoin Signed Message:\n";
+    let mut hasher = Sha256::new();
+    hasher.update(btc_prefix);
+    hasher.update([message.len() as u8]);
+    hasher.update(message);
+    let r = Sha256::digest(hasher.finalize());
+    H256::from_slice(r.as_slice()).expect("convert_btc_hash")
+}
+
+/// Calculate the type id args from the first input of the transaction and
+/// the index of the output with the type id script.
+pub fn calculate_type_id(first_cell_input: &CellInput, output_index: u64) -> [u8; 32] {
+    let mut blake2b = ckb_hash::new_blake2b();
+    blake2b.update(first_cell_input.as_slice());
+    blake2b.update(&output_index.to_le_bytes());
+    let mut ret = [0u8; 32];
+    blake2b.finalize(&mut ret);
+    ret
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -381,4 +480,38 @@ mod tests {
             assert_eq!(151500, get_max_mature_number(&rpc_client).unwrap());
         }
     }
+
+    #[test]
+    fn test_max_mature_epoch() {
+        assert!(max_mature_epoch(
+            EpochNumberWithFraction::new(3, 200, 400),
+            EpochNumberWithFraction::new(4, 0, 1)
+        )
+        .is_none());
+        // 105(300/600) - 3(2/3) = 101(5/6)
+        let (epoch_number, fraction) = max_mature_epoch(
+            EpochNumberWithFraction::new(105, 300, 600),
+            EpochNumberWithFraction::new(3, 2, 3),
+        )
+        .unwrap();
+        assert_eq!(epoch_number, 101);
+        assert_eq!(block_number_in_epoch(fraction, 150000, 1800), 151500);
+    }
+
+    #[cfg(feature = "bitcoin")]
+    #[test]
+    fn test_hash160() {
+        let mut secret = [0u8; 32];
+        secret[31] = 1;
+        let key = secp256k1::SecretKey::from_slice(&secret).unwrap();
+        let pubkey = secp256k1::PublicKey::from_secret_key(&crate::SECP256K1, &key);
+        assert_eq!(
+            hash160(&pubkey.serialize()),
+            ckb_types::h160!("0x751e76e8199196d454941c45d1b3a323f1433bd6")
+        );
+        assert_eq!(
+            hash160(&pubkey.serialize_uncompressed()),
+            ckb_types::h160!("0x91b24bf9f5288532960ac687abb035127b1d28a5")
+        );
+    }
 }